@@ -0,0 +1,266 @@
+use crate::compare::{self, METRICS};
+use crate::erode::{DropZone, Progress};
+use crate::heightmap::analysis::{self, SeamMetrics, TerrainMetrics};
+use crate::heightmap::{self, HeightmapType};
+use crate::partitioning::{MarginMode, Method};
+use crate::visualize::app_state::AppParameters;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Instant;
+
+/*
+`--benchmark [config json]` erodes the default heightmap preset with every partitioning method at
+every grid size and resolution of the config and writes how long it took and how the results look
+to `benchmark.csv` and `benchmark.json`, without a window or any interaction. Every resolution is
+first eroded without partitioning, the reference the partitioned results are compared against with
+the metrics of `--compare`, so the seams of a method show up as a larger difference, and the seam
+energy of every partitioned result is measured along its own boundaries.
+
+The droplet budget grows with the area so every resolution is eroded about as much. The partitions
+of a method run in parallel, so their summed time is more than the wall time on a machine with
+several cores, and the slowest partition is the one the others wait for.
+ */
+
+pub const CSV_FILE: &str = "benchmark.csv";
+pub const JSON_FILE: &str = "benchmark.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkConfig {
+    pub resolutions: Vec<usize>,
+    pub grid_sizes: Vec<usize>,
+    pub droplets_per_cell: f32, // 2
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        BenchmarkConfig {
+            resolutions: vec![128, 256, 512],
+            grid_sizes: vec![2, 4, 8],
+            droplets_per_cell: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkResult {
+    pub method: String,
+    pub grid_size: usize,
+    pub resolution: usize,
+    pub iterations: usize,
+    pub seconds: f32,
+    pub partition_seconds: Vec<f32>,
+    pub terrain: TerrainMetrics,
+    /// `None` without partitioning.
+    pub seams: Option<SeamMetrics>,
+    /// Against the heightmap eroded without partitioning, empty for that one itself.
+    pub difference: BTreeMap<String, f64>,
+}
+
+impl BenchmarkResult {
+    pub fn slowest_partition(&self) -> f32 {
+        self.partition_seconds.iter().copied().fold(0.0, f32::max)
+    }
+
+    pub fn mean_partition(&self) -> f32 {
+        let count = self.partition_seconds.len().max(1) as f32;
+        self.partition_seconds.iter().sum::<f32>() / count
+    }
+}
+
+/// Runs every method of the matrix, the reference without partitioning first per resolution.
+pub fn run_matrix(config: &BenchmarkConfig) -> Vec<BenchmarkResult> {
+    let model = AppParameters::default().model();
+    let mut results = Vec::new();
+    for &resolution in config.resolutions.iter() {
+        let mut heightmap_type = HeightmapType::default();
+        heightmap_type.params_mut().size = resolution;
+        let heightmap = heightmap::create_heightmap_from_preset(&heightmap_type);
+        let drop_zone = DropZone::default(&heightmap);
+        let iterations = (config.droplets_per_cell * (resolution * resolution) as f32) as usize;
+        let model = model.with_iterations(iterations);
+
+        let mut methods = vec![(Method::Default, 1)];
+        for &grid_size in config.grid_sizes.iter() {
+            for method in Method::list(grid_size).into_iter().skip(1) {
+                methods.push((method, grid_size));
+            }
+        }
+
+        let mut reference = None;
+        for (method, grid_size) in methods {
+            let mut eroded = heightmap.clone();
+            let time = Instant::now();
+            let outputs =
+                method.erode(&mut eroded, &model, false, &drop_zone, &Progress::default());
+            let seconds = time.elapsed().as_secs_f32();
+            let difference = match &reference {
+                Some(reference) => compare::metrics(reference, &eroded)
+                    .map(|metrics| {
+                        METRICS
+                            .iter()
+                            .map(|name| name.to_string())
+                            .zip(metrics)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                None => BTreeMap::new(),
+            };
+            let result = BenchmarkResult {
+                method: method.to_string(),
+                grid_size,
+                resolution,
+                iterations,
+                seconds,
+                partition_seconds: outputs
+                    .partitions
+                    .iter()
+                    .map(|partition| partition.seconds)
+                    .collect(),
+                terrain: analysis::measure(&eroded),
+                seams: method
+                    .seams(eroded.width, eroded.height, MarginMode::None)
+                    .and_then(|seams| analysis::seam_metrics(&eroded, &seams)),
+                difference,
+            };
+            println!(
+                "{}x{}, {} ({}x{}): {:.2} s, slowest partition {:.2} s",
+                resolution,
+                resolution,
+                result.method,
+                grid_size,
+                grid_size,
+                result.seconds,
+                result.slowest_partition()
+            );
+            results.push(result);
+            reference.get_or_insert(eroded);
+        }
+    }
+    results
+}
+
+pub fn to_csv(results: &[BenchmarkResult]) -> String {
+    let mut csv = format!(
+        "method,grid_size,resolution,iterations,seconds,partitions,mean_partition_seconds,\
+        slowest_partition_seconds,mean_slope,mean_abs_profile_curvature,mean_abs_plan_curvature,\
+        mean_roughness,seam_energy,seam_ratio,{}\n",
+        METRICS.join(",")
+    );
+    for result in results {
+        let terrain = &result.terrain;
+        let (seam_energy, seam_ratio) = match &result.seams {
+            Some(seams) => (seams.seam_energy.to_string(), seams.ratio().to_string()),
+            None => (String::new(), String::new()),
+        };
+        let difference = METRICS
+            .iter()
+            .map(|name| match result.difference.get(*name) {
+                Some(value) => value.to_string(),
+                None => String::new(),
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            result.method,
+            result.grid_size,
+            result.resolution,
+            result.iterations,
+            result.seconds,
+            result.partition_seconds.len(),
+            result.mean_partition(),
+            result.slowest_partition(),
+            terrain.mean_slope,
+            terrain.mean_abs_profile_curvature,
+            terrain.mean_abs_plan_curvature,
+            terrain.mean_roughness,
+            seam_energy,
+            seam_ratio,
+            difference
+        ));
+    }
+    csv
+}
+
+/// Runs `--benchmark` with the arguments following it, false when it failed.
+pub fn run(args: &[String]) -> bool {
+    let config = match args.first().filter(|arg| !arg.starts_with("--")) {
+        Some(path) => match fs::read_to_string(path).map(|json| serde_json::from_str(&json)) {
+            Ok(Ok(config)) => config,
+            Ok(Err(err)) => {
+                println!("Failed to read the benchmark config {}: {:?}", path, err);
+                return false;
+            }
+            Err(err) => {
+                println!("Failed to open the benchmark config {}: {:?}", path, err);
+                return false;
+            }
+        },
+        None => BenchmarkConfig::default(),
+    };
+    let results = run_matrix(&config);
+    match serde_json::to_string_pretty(&results) {
+        Ok(json) => {
+            let mut written = true;
+            for (file, contents) in [(CSV_FILE, to_csv(&results)), (JSON_FILE, json)] {
+                match fs::write(file, contents) {
+                    Ok(()) => println!("Wrote {}", file),
+                    Err(err) => {
+                        println!("Failed to write {}: {:?}", file, err);
+                        written = false;
+                    }
+                }
+            }
+            written
+        }
+        Err(err) => {
+            println!("Failed to serialize the benchmark: {:?}", err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Check, GRID_SIZE, SIZE};
+
+    #[test]
+    fn benchmark() -> Check {
+        use crate::bench::{run_matrix, to_csv, BenchmarkConfig};
+        let config = BenchmarkConfig {
+            resolutions: vec![SIZE],
+            grid_sizes: vec![GRID_SIZE],
+            droplets_per_cell: 0.25,
+        };
+        let results = run_matrix(&config);
+        let methods = Method::list(GRID_SIZE).len();
+        if results.len() != methods {
+            return Err(format!("ran {} of {} methods", results.len(), methods));
+        }
+        for (i, result) in results.iter().enumerate() {
+            if result.partition_seconds.is_empty() {
+                return Err(format!("{} did not time its partitions", result.method));
+            }
+            // Only the reference without partitioning has nothing to be compared against
+            if result.difference.is_empty() != (i == 0) {
+                return Err(format!("{} was not compared as expected", result.method));
+            }
+            if result.seams.is_some() == (i == 0) {
+                return Err(format!(
+                    "{} was not measured along its seams",
+                    result.method
+                ));
+            }
+        }
+        if results[1].partition_seconds.len() != GRID_SIZE * GRID_SIZE {
+            return Err("not every partition was timed".to_string());
+        }
+        if to_csv(&results).lines().count() != results.len() + 1 {
+            return Err("csv does not have a row per result".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use crate::erode::{DropZone, Model, Parameters, Progress};
+use crate::heightmap::io::{self, DataFormat};
+use crate::heightmap::Heightmap;
+use crate::partitioning::{MarginMode, Method};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/*
+`erode --input <heightmap> --output <heightmap>` loads a heightmap, erodes it with droplets and
+saves it, without opening a window or writing a script. The input is read like an imported image
+or a json export and the output format follows its extension, a png is written with 16 bits so
+the heights survive the round trip. Flags take their value after a space or an `=`, the number of
+droplets may end in k, M or G and `--seed` drops the droplets at the same cells on every run.
+ */
+
+pub const USAGE: &str = "Usage: erode --input <png|tif|exr|json> --output \
+<png|tif|exr|npy|csv|csv.gz|r32|json> [--iterations 1M] [--method grid-overlap:8] [--seed 42] \
+[--margin none|auto|<cells>|<right,top,left,bottom>]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErodeArgs {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub iterations: usize,
+    pub method: Method,
+    pub seed: Option<u64>,
+    pub margin: MarginMode,
+}
+
+impl ErodeArgs {
+    /// Reads the flags following `erode` on the command line.
+    pub fn parse(args: &[String]) -> Result<ErodeArgs, String> {
+        let mut input = None;
+        let mut output = None;
+        let mut iterations = Parameters::default().num_iterations;
+        let mut method = Method::Default;
+        let mut seed = None;
+        let mut margin = MarginMode::None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next().cloned())
+                    .ok_or_else(|| format!("{} needs a value", flag))
+            };
+            match flag {
+                "--input" | "-i" => input = Some(PathBuf::from(value()?)),
+                "--output" | "-o" => output = Some(PathBuf::from(value()?)),
+                "--iterations" | "-n" => iterations = parse_count(&value()?)?,
+                "--method" | "-m" => method = Method::parse(&value()?)?,
+                "--seed" | "-s" => {
+                    let value = value()?;
+                    let parsed = value
+                        .parse()
+                        .map_err(|_| format!("invalid seed {}", value))?;
+                    seed = Some(parsed);
+                }
+                "--margin" => margin = MarginMode::parse(&value()?)?,
+                _ => return Err(format!("unknown flag {}", flag)),
+            }
+        }
+
+        Ok(ErodeArgs {
+            input: input.ok_or("--input is missing")?,
+            output: output.ok_or("--output is missing")?,
+            iterations,
+            method,
+            seed,
+            margin,
+        })
+    }
+
+    pub fn model(&self) -> Model {
+        Model::Lague(Parameters {
+            num_iterations: self.iterations,
+            seed: self.seed,
+            record_flow: false,
+            ..Default::default()
+        })
+    }
+}
+
+/// A count such as `2000000`, `2_000_000`, `2M` or `1.5k`.
+pub fn parse_count(text: &str) -> Result<usize, String> {
+    let digits = text.replace('_', "");
+    let (number, scale) = match digits.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&digits[..i], 1e3),
+        Some((i, 'm' | 'M')) => (&digits[..i], 1e6),
+        Some((i, 'g' | 'G')) => (&digits[..i], 1e9),
+        _ => (digits.as_str(), 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(count) if count >= 0.0 && count.is_finite() => Ok((count * scale).round() as usize),
+        _ => Err(format!("invalid count {}", text)),
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.eq_ignore_ascii_case("json"))
+        .unwrap_or(false)
+}
+
+pub fn load(path: &Path) -> Result<Heightmap, String> {
+    let heightmap = if is_json(path) {
+        io::import(&path.to_string_lossy())
+    } else {
+        io::import_image(path)
+    };
+    heightmap.map_err(|err| format!("Failed to read {}: {:?}", path.display(), err))
+}
+
+pub fn save(heightmap: &Heightmap, path: &Path) -> Result<(), String> {
+    let result = if is_json(path) {
+        let folder = path.parent().unwrap_or(Path::new(""));
+        let filename = path.with_extension("");
+        io::export(
+            heightmap,
+            &folder.to_string_lossy(),
+            &filename.to_string_lossy(),
+        )
+    } else {
+        let format = DataFormat::from_path(path)
+            .ok_or_else(|| format!("Unknown format of {}", path.display()))?;
+        io::export_data_to(heightmap, path, format)
+    };
+    result.map_err(|err| format!("Failed to write {}: {:?}", path.display(), err))
+}
+
+/// Loads, erodes and saves the heightmap of `args`, returning the eroded heightmap.
+pub fn erode(args: &ErodeArgs) -> Result<Heightmap, String> {
+    let heightmap = load(&args.input)?;
+    let (eroded, _) = args.method.erode_with_margin(
+        args.margin,
+        false,
+        &heightmap,
+        &args.model(),
+        &DropZone::default(&heightmap),
+        &Progress::default(),
+    );
+    save(&eroded, &args.output)?;
+    Ok(eroded)
+}
+
+/// Runs `erode` with the flags following it, false when it failed.
+pub fn run(args: &[String]) -> bool {
+    let args = match ErodeArgs::parse(args) {
+        Ok(args) => args,
+        Err(err) => {
+            println!("{}\n{}", err, USAGE);
+            return false;
+        }
+    };
+    let time = Instant::now();
+    match erode(&args) {
+        Ok(eroded) => {
+            println!(
+                "Eroded {} with {} droplets using the {} method in {:.2} s, wrote {}x{} to {}",
+                args.input.display(),
+                args.iterations,
+                args.method.to_string(),
+                time.elapsed().as_secs_f32(),
+                eroded.width,
+                eroded.height,
+                args.output.display()
+            );
+            true
+        }
+        Err(err) => {
+            println!("{}", err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        check_heightmap, temp_path, tiny_heightmap, Check, GRID_SIZE, ITERATIONS, SIZE,
+    };
+    use std::fs;
+
+    #[test]
+    fn erode_command() -> Check {
+        use crate::cli::{self, ErodeArgs};
+        let input = temp_path("cli-input.json");
+        let output = temp_path("cli-output.png");
+        let args: Vec<String> = [
+            "--input",
+            &input.to_string_lossy(),
+            "--output",
+            &output.to_string_lossy(),
+            "--iterations",
+            "0.2k",
+            "--method=grid-overlap:2",
+            "--seed",
+            "42",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let args = ErodeArgs::parse(&args)?;
+        if args.iterations != ITERATIONS || args.method.get_grid_size() != GRID_SIZE {
+            return Err(format!("parsed {:?}", args));
+        }
+        if ErodeArgs::parse(&["--input".to_string()]).is_ok() || Method::parse("hexagons").is_ok() {
+            return Err("accepted invalid flags".to_string());
+        }
+        cli::save(&tiny_heightmap(), &input)?;
+        let first = cli::erode(&args);
+        let second = cli::erode(&args);
+        let read = cli::load(&output);
+        let _ = fs::remove_file(&input);
+        let _ = fs::remove_file(&output);
+        let (first, second, read) = (first?, second?, read?);
+        check_heightmap(&read, SIZE, SIZE)?;
+        // The same seed drops the droplets at the same cells
+        if first.content_hash() != second.content_hash() {
+            return Err("seeded runs differ".to_string());
+        }
+        Ok(())
+    }
+}
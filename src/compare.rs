@@ -0,0 +1,375 @@
+use crate::heightmap::io::{import, import_image, IMAGE_EXTENSIONS};
+use crate::heightmap::Heightmap;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+`--compare <references> <outputs> [summary]` compares every heightmap in the folder of outputs
+against the reference heightmap of the same name in the other folder, so results of the simulation
+can be checked against ground truth produced elsewhere. Files are matched by name without the
+extension, so a reference png can be compared against an exported exr, and may be any of the
+images `io::import_image` reads or the json written by `io::export`.
+
+Heights are divided by the depth of their heightmap first, so maps are compared in [0, 1] whatever
+range they were stored in. Every pair gets the full metric suite, see `METRICS`, and the summary csv
+has a row per pair followed by the mean, median, min and max of every metric over all pairs.
+Identical maps have an infinite psnr, written as `inf`. The correlation of a flat map and the
+volume against a reference of zeros are undefined, written as `n/a` and left out of the aggregates.
+ */
+
+pub const SUMMARY_FILE: &str = "comparison.csv";
+
+/// Output minus reference unless noted, on heights divided by the depth.
+pub const METRICS: [&str; 8] = [
+    "mean_abs",    // mean absolute difference
+    "rms",         // root mean square difference
+    "max_abs",     // largest absolute difference
+    "bias",        // mean signed difference, negative where the output is lower
+    "psnr",        // peak signal to noise ratio in dB, with a peak of 1, infinite when identical
+    "correlation", // Pearson correlation of the heights, NaN when either map is flat
+    "slope_rms",   // root mean square difference of the slopes
+    "volume",      // difference of the total heights relative to the reference, NaN for zeros
+];
+
+pub const AGGREGATES: [&str; 4] = ["mean", "median", "min", "max"];
+
+#[derive(Debug)]
+pub enum CompareError {
+    Io(std::io::Error),
+    NoMatches,
+}
+
+impl From<std::io::Error> for CompareError {
+    fn from(err: std::io::Error) -> Self {
+        CompareError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub metrics: [f64; METRICS.len()],
+}
+
+fn slope(heightmap: &Heightmap, x: usize, y: usize) -> f64 {
+    let (x, y) = (x as i32, y as i32);
+    let dx = heightmap.get_clamped(x + 1, y) - heightmap.get_clamped(x - 1, y);
+    let dy = heightmap.get_clamped(x, y + 1) - heightmap.get_clamped(x, y - 1);
+    ((dx * dx + dy * dy) as f64).sqrt() / 2.0 / heightmap.depth as f64
+}
+
+/// The metrics of `output` against `reference`, `None` when they differ in size.
+pub fn metrics(reference: &Heightmap, output: &Heightmap) -> Option<[f64; METRICS.len()]> {
+    if reference.width != output.width || reference.height != output.height {
+        return None;
+    }
+    let cells = (reference.width * reference.height) as f64;
+    let (reference_depth, output_depth) = (reference.depth as f64, output.depth as f64);
+    let (mut sum_abs, mut sum_squared, mut max_abs, mut sum) = (0.0, 0.0, 0.0f64, 0.0);
+    let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    let mut slope_squared = 0.0;
+    for x in 0..reference.width {
+        for y in 0..reference.height {
+            let a = reference.data[x][y] as f64 / reference_depth;
+            let b = output.data[x][y] as f64 / output_depth;
+            let diff = b - a;
+            sum += diff;
+            sum_abs += diff.abs();
+            sum_squared += diff * diff;
+            max_abs = max_abs.max(diff.abs());
+            sum_a += a;
+            sum_b += b;
+            sum_aa += a * a;
+            sum_bb += b * b;
+            sum_ab += a * b;
+            let slope_diff = slope(output, x, y) - slope(reference, x, y);
+            slope_squared += slope_diff * slope_diff;
+        }
+    }
+    let rms = (sum_squared / cells).sqrt();
+    let covariance = sum_ab / cells - (sum_a / cells) * (sum_b / cells);
+    let variance_a = sum_aa / cells - (sum_a / cells).powi(2);
+    let variance_b = sum_bb / cells - (sum_b / cells).powi(2);
+    let correlation = if variance_a > 0.0 && variance_b > 0.0 {
+        covariance / (variance_a * variance_b).sqrt()
+    } else {
+        f64::NAN
+    };
+    let psnr = if rms > 0.0 {
+        -20.0 * rms.log10()
+    } else {
+        f64::INFINITY
+    };
+    let volume = if sum_a != 0.0 {
+        (sum_b - sum_a) / sum_a
+    } else {
+        f64::NAN
+    };
+    Some([
+        sum_abs / cells,
+        rms,
+        max_abs,
+        sum / cells,
+        psnr,
+        correlation,
+        (slope_squared / cells).sqrt(),
+        volume,
+    ])
+}
+
+fn is_heightmap(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_lowercase();
+    name.ends_with(".json")
+        || IMAGE_EXTENSIONS
+            .iter()
+            .any(|extension| name.ends_with(extension))
+}
+
+fn read(path: &Path) -> Option<Heightmap> {
+    let json = path
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    let heightmap = if json {
+        import(&path.to_string_lossy()).ok()
+    } else {
+        import_image(path).ok()
+    };
+    if heightmap.is_none() {
+        println!("Failed to read {}", path.display());
+    }
+    heightmap
+}
+
+/// Heightmaps in `folder` by name without the extension, the first one for names used twice.
+fn heightmaps(folder: &str) -> std::io::Result<BTreeMap<String, PathBuf>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(folder)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && is_heightmap(path))
+        .collect();
+    paths.sort();
+    let mut heightmaps = BTreeMap::new();
+    for path in paths {
+        if let Some(stem) = path.file_stem() {
+            heightmaps
+                .entry(stem.to_string_lossy().to_string())
+                .or_insert(path);
+        }
+    }
+    Ok(heightmaps)
+}
+
+/// Compares the outputs against the references with the same names, sorted by name.
+pub fn compare_folders(references: &str, outputs: &str) -> Result<Vec<Comparison>, CompareError> {
+    let references = heightmaps(references)?;
+    let outputs = heightmaps(outputs)?;
+    let mut comparisons = Vec::new();
+    for (name, output_path) in outputs.iter() {
+        let reference_path = match references.get(name) {
+            Some(path) => path,
+            None => {
+                println!("No reference for {}", output_path.display());
+                continue;
+            }
+        };
+        let (reference, output) = match (read(reference_path), read(output_path)) {
+            (Some(reference), Some(output)) => (reference, output),
+            _ => continue,
+        };
+        match metrics(&reference, &output) {
+            Some(metrics) => comparisons.push(Comparison {
+                name: name.clone(),
+                width: output.width,
+                height: output.height,
+                metrics,
+            }),
+            None => println!(
+                "{} is {}x{} but its reference is {}x{}",
+                output_path.display(),
+                output.width,
+                output.height,
+                reference.width,
+                reference.height
+            ),
+        }
+    }
+    for name in references
+        .keys()
+        .filter(|name| !outputs.contains_key(*name))
+    {
+        println!("No output for reference {}", name);
+    }
+    if comparisons.is_empty() {
+        return Err(CompareError::NoMatches);
+    }
+    Ok(comparisons)
+}
+
+/// The mean, median, min and max of every metric, in the order of `AGGREGATES`, over the pairs
+/// where the metric is defined.
+pub fn aggregate(comparisons: &[Comparison]) -> [[f64; METRICS.len()]; AGGREGATES.len()] {
+    let mut aggregates = [[f64::NAN; METRICS.len()]; AGGREGATES.len()];
+    for (metric, _) in METRICS.iter().enumerate() {
+        let mut values: Vec<f64> = comparisons
+            .iter()
+            .map(|c| c.metrics[metric])
+            .filter(|value| !value.is_nan())
+            .collect();
+        if values.is_empty() {
+            continue;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        let middle = values.len() / 2;
+        let median = if values.len() & 1 == 0 {
+            (values[middle - 1] + values[middle]) / 2.0
+        } else {
+            values[middle]
+        };
+        aggregates[0][metric] = values.iter().sum::<f64>() / values.len() as f64;
+        aggregates[1][metric] = median;
+        aggregates[2][metric] = values[0];
+        aggregates[3][metric] = values[values.len() - 1];
+    }
+    aggregates
+}
+
+pub fn to_csv(comparisons: &[Comparison]) -> String {
+    let mut csv = format!("name,width,height,{}\n", METRICS.join(","));
+    let row = |values: &[f64]| {
+        values
+            .iter()
+            .map(|value| {
+                if value.is_nan() {
+                    "n/a".to_string()
+                } else {
+                    value.to_string()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    for comparison in comparisons {
+        csv.push_str(&format!(
+            "\"{}\",{},{},{}\n",
+            comparison.name.replace('"', "\"\""),
+            comparison.width,
+            comparison.height,
+            row(&comparison.metrics)
+        ));
+    }
+    for (name, values) in AGGREGATES.iter().zip(aggregate(comparisons).iter()) {
+        csv.push_str(&format!("{},,,{}\n", name, row(values)));
+    }
+    csv
+}
+
+/// Runs `--compare` with the arguments following it, false when it failed.
+pub fn run(args: &[String]) -> bool {
+    let (references, outputs) = match args {
+        [references, outputs, ..] => (references, outputs),
+        _ => {
+            println!("Usage: --compare <references> <outputs> [summary csv]");
+            return false;
+        }
+    };
+    let summary = args.get(2).map(String::as_str).unwrap_or(SUMMARY_FILE);
+    match compare_folders(references, outputs) {
+        Ok(comparisons) => match fs::write(summary, to_csv(&comparisons)) {
+            Ok(()) => {
+                println!(
+                    "Compared {} heightmaps, wrote {}",
+                    comparisons.len(),
+                    summary
+                );
+                true
+            }
+            Err(err) => {
+                println!("Failed to write {}: {:?}", summary, err);
+                false
+            }
+        },
+        Err(err) => {
+            println!(
+                "Failed to compare {} with {}: {:?}",
+                outputs, references, err
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_path, tiny_heightmap, Check};
+    use crate::heightmap;
+
+    #[test]
+    fn compare_folders() -> Check {
+        use crate::compare::{self, METRICS};
+        let reference = tiny_heightmap();
+        let mut lowered = reference.clone();
+        lowered.map_inplace(|_, _, value| value * 0.5);
+        let same = compare::metrics(&reference, &reference).ok_or("sizes differ")?;
+        let lower = compare::metrics(&reference, &lowered).ok_or("sizes differ")?;
+        let metric = |values: &[f64; METRICS.len()], name: &str| {
+            values[METRICS.iter().position(|m| *m == name).unwrap()]
+        };
+        if metric(&same, "rms") != 0.0
+            || metric(&same, "psnr") != f64::INFINITY
+            || (metric(&same, "correlation") - 1.0).abs() > 1e-9
+        {
+            return Err(format!("identical maps compare as {:?}", same));
+        }
+        let flat = Heightmap::new_empty(reference.width, reference.height, 1.0, 1.0);
+        let against_flat = compare::metrics(&flat, &reference).ok_or("sizes differ")?;
+        if !metric(&against_flat, "correlation").is_nan()
+            || !metric(&against_flat, "volume").is_nan()
+        {
+            return Err(format!("flat reference compares as {:?}", against_flat));
+        }
+        if metric(&lower, "bias") >= 0.0 || (metric(&lower, "volume") + 0.5).abs() > 1e-6 {
+            return Err(format!("lowered map compares as {:?}", lower));
+        }
+
+        let (references, outputs) = (temp_path("references"), temp_path("outputs"));
+        let write = |folder: &PathBuf, name: &str, heightmap: &Heightmap| {
+            let folder = folder.to_string_lossy();
+            let filename = format!("{}/{}", folder, name);
+            heightmap::io::export(heightmap, &folder, &filename).map_err(|err| format!("{:?}", err))
+        };
+        let result = write(&references, "a", &reference)
+            .and_then(|_| write(&references, "b", &reference))
+            .and_then(|_| write(&outputs, "a", &reference))
+            .and_then(|_| write(&outputs, "b", &lowered))
+            .and_then(|_| write(&outputs, "unmatched", &lowered))
+            .and_then(|_| {
+                compare::compare_folders(&references.to_string_lossy(), &outputs.to_string_lossy())
+                    .map_err(|err| format!("{:?}", err))
+            });
+        let _ = fs::remove_dir_all(&references);
+        let _ = fs::remove_dir_all(&outputs);
+        let comparisons = result?;
+        let names: Vec<&str> = comparisons.iter().map(|c| c.name.as_str()).collect();
+        if names != ["a", "b"] {
+            return Err(format!("compared {:?}", names));
+        }
+        let csv = compare::to_csv(&comparisons);
+        if csv.lines().count() != 1 + 2 + compare::AGGREGATES.len() || !csv.contains("\n\"a\",") {
+            return Err(format!("summary is\n{}", csv));
+        }
+        let mut quoted = comparisons[0].clone();
+        quoted.name = "a,\"b\"".to_string();
+        quoted.metrics[METRICS.iter().position(|m| *m == "correlation").unwrap()] = f64::NAN;
+        let csv = compare::to_csv(&[quoted]);
+        let row = csv.lines().nth(1).unwrap_or_default();
+        if !row.starts_with("\"a,\"\"b\"\"\",") || !row.contains(",n/a,") {
+            return Err(format!("summary is\n{}", csv));
+        }
+        Ok(())
+    }
+}
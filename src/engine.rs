@@ -1,13 +1,32 @@
+pub mod archive;
+pub mod checkpoint;
+pub mod docs;
+pub mod format;
+pub mod progress;
+pub mod record;
 pub mod scripts;
+#[cfg(feature = "gui")]
+pub mod watch;
+#[cfg(feature = "gui")]
+pub mod window;
 
-use crate::engine::scripts::{tick, Function, Instruction, Script};
-use crate::erode::Parameters;
-use crate::heightmap::HeightmapType;
+use crate::engine::checkpoint::Checkpoints;
+use crate::engine::format::ScriptError;
+use crate::engine::progress::{Log, Progress};
+use crate::engine::scripts::{tick, Function, FunctionName, Instruction, Metric, Script, StateRef};
+use crate::erode::{Model, Parameters};
+use crate::heightmap::{Heightmap, HeightmapError, HeightmapHash, HeightmapType};
 use crate::partitioning::Method;
 use crate::State;
-use macroquad::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::collections::HashMap;
+use std::future::Future;
+use std::mem;
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
 
 #[derive(Debug)]
 pub enum EngineError {
@@ -15,42 +34,171 @@ pub enum EngineError {
     HasNoInstruction,
     MissingSnapshotData,
     JsonError(serde_json::Error),
+    ScriptError(ScriptError),
+    BinaryError(bincode::Error),
+    InvalidSnapshotArchive,
     MissingMainFunction,
     MissingFunction(String),
+    UnknownPreset(String),
+    RecursiveCall(String),
     RWError(std::io::Error),
+    UnknownState(StateRef),
+    MismatchingStates,
+    UnknownVariable(String),
+    /// The metric has no value yet, e.g. without a snapshot or an eroded state.
+    MissingMetric(Metric),
+    AssertionFailed(String),
+    WorkerPanicked(FunctionName),
 }
 
+/// Calls nested deeper than this are taken to be recursive.
+pub const MAX_CALL_DEPTH: usize = 64;
+
 pub type Stack = Vec<State>;
+/// States stored by name with `StoreState`, kept until the script ends or they are overwritten.
+pub type Registry = HashMap<String, State>;
+/// Values set with `Set` or by `ForEach`, substituted for `${name}` in the instructions that follow.
+pub type Variables = HashMap<String, String>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tuning {
     pub method: Option<Method>,
+    pub model: Model,
     pub parameters: Parameters,
     pub map_type: HeightmapType,
     pub flatness: f32,
     pub isoline_value: f32,
     pub isoline_error: f32,
+    pub session_notes: String,
+    pub notes: String,
 }
 
 type Flooded = usize;
 type Unflooded = usize;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Measurement {
     Time(f32), // Seconds
     LowAreas(Flooded, Unflooded),
     HighAreas(Flooded, Unflooded),
     IsoError(Flooded),
+    // Of the absolute difference between two states, see `Instruction::CompareStates`
+    MeanDifference(f32),
+    RmsDifference(f32),
+    MaxDifference(f32),
+    // See `analysis::SeamMetrics`
+    SeamEnergy(f32),
+    SeamRatio(f32),
+}
+
+pub type Snapshot = (Tuning, Vec<Measurement>, HeightmapHash);
+
+/// The absolute difference between two heightmaps of the same size and measurements of it.
+pub fn difference(
+    a: &Heightmap,
+    b: &Heightmap,
+) -> Result<(Heightmap, Vec<Measurement>), HeightmapError> {
+    let mut diff = a.subtract(b)?;
+    let cells = (diff.width * diff.height) as f32;
+    let values = diff.data.iter().flatten();
+    let mean = values.clone().sum::<f32>() / cells;
+    let rms = (values.clone().map(|d| d * d).sum::<f32>() / cells).sqrt();
+    let max = values.fold(0.0, |max: f32, &d| max.max(d));
+    diff.metadata_add("DIFFERENCE_MEAN", mean.to_string());
+    diff.metadata_add("DIFFERENCE_RMS", rms.to_string());
+    diff.metadata_add("DIFFERENCE_MAX", max.to_string());
+    Ok((
+        diff,
+        vec![
+            Measurement::MeanDifference(mean),
+            Measurement::RmsDifference(rms),
+            Measurement::MaxDifference(max),
+        ],
+    ))
+}
+
+/// Snapshots refer to their heightmap by content hash so that identical heightmaps,
+/// e.g. from an isoline sweep over a single eroded map, are only stored once. Heightmaps that
+/// differ but hash the same, e.g. in metadata alone, are stored under the next free key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshots {
+    pub heightmaps: HashMap<HeightmapHash, Rc<Heightmap>>,
+    pub entries: Vec<Snapshot>,
+}
+
+impl Snapshots {
+    pub fn push(
+        &mut self,
+        tuning: Tuning,
+        measurements: Vec<Measurement>,
+        heightmap: Rc<Heightmap>,
+    ) {
+        let hash = self.insert(heightmap);
+        self.entries.push((tuning, measurements, hash));
+    }
+
+    /// Stores the heightmap once and returns its key, the content hash unless a heightmap with
+    /// other heights or metadata already has it, then the next free key after it.
+    fn insert(&mut self, heightmap: Rc<Heightmap>) -> HeightmapHash {
+        let mut hash = heightmap.content_hash();
+        loop {
+            match self.heightmaps.get(&hash) {
+                None => {
+                    self.heightmaps.insert(hash, heightmap);
+                    return hash;
+                }
+                Some(stored)
+                    if Rc::ptr_eq(stored, &heightmap) || stored.same_content(&heightmap) =>
+                {
+                    return hash;
+                }
+                Some(_) => hash = hash.wrapping_add(1),
+            }
+        }
+    }
+
+    pub fn heightmap(&self, snapshot: &Snapshot) -> Option<&Rc<Heightmap>> {
+        self.heightmaps.get(&snapshot.2)
+    }
+
+    pub fn clear(&mut self) {
+        self.heightmaps.clear();
+        self.entries.clear();
+    }
+
+    /// Adds the snapshots of `other` after these.
+    pub fn append(&mut self, other: Snapshots) {
+        for (tuning, measurements, hash) in other.entries {
+            if let Some(heightmap) = other.heightmaps.get(&hash) {
+                self.push(tuning, measurements, Rc::clone(heightmap));
+            }
+        }
+    }
 }
 
-pub type Snapshot = (Tuning, Vec<Measurement>);
+/// A spawned function and the thread running it, which returns its snapshots in bincode since
+/// they hold `Rc`s that cannot cross threads.
+pub type Worker = (FunctionName, JoinHandle<Result<Vec<u8>, EngineError>>);
 
 pub struct Engine {
     pub state: State,
     pub main: Function,
     pub script: Script,
     pub stack: Stack,
-    pub snapshots: Vec<Snapshot>,
+    pub registry: Registry,
+    pub snapshots: Snapshots,
+    pub variables: Variables,
+    /// Instructions run so far, including those of called functions.
+    pub completed: usize,
+    pub checkpoints: Option<Checkpoints>,
+    /// Called functions and the length of `main` before their instructions, see `function`.
+    pub calls: Vec<(FunctionName, usize)>,
+    pub progress: Option<Progress>,
+    /// Where the text printed by the script and the progress go, see `progress::Output`.
+    pub log: Log,
+    /// Frames, window sizes and handovers are skipped, see `run_headless`.
+    pub headless: bool,
+    pub workers: Vec<Worker>,
 }
 
 impl Engine {
@@ -58,25 +206,39 @@ impl Engine {
         !self.main.is_empty()
     }
 
-    pub fn snapshot(&mut self) -> Option<()> {
-        let tuning = Tuning {
-            method: self
-                .state
+    /// The function the next instruction belongs to, calls whose instructions all ran are done.
+    pub fn function(&self) -> &str {
+        self.calls
+            .iter()
+            .rev()
+            .find(|(_, base)| *base < self.main.len())
+            .map_or("main", |(name, _)| name)
+    }
+
+    fn tuning(state: &State) -> Option<Tuning> {
+        Some(Tuning {
+            method: state
                 .app_state
                 .simulation_state()
                 .eroded()
                 .and_then(|e| Some(*e.erosion_method.clone())),
-            parameters: self.state.app_state.parameters.erosion_params,
-            map_type: self.state.app_state.parameters.heightmap_type,
-            flatness: self
-                .state
+            model: state.app_state.parameters.model(),
+            parameters: state.app_state.parameters.erosion_params,
+            map_type: state.app_state.parameters.heightmap_type.clone(),
+            flatness: state
                 .app_state
                 .simulation_state()
                 .get_heightmap()
                 .get_average_height()?,
-            isoline_value: self.state.ui_state.isoline.height,
-            isoline_error: self.state.ui_state.isoline.error,
-        };
+            isoline_value: state.ui_state.isoline.height,
+            isoline_error: state.ui_state.isoline.error,
+            session_notes: state.app_state.notes.clone(),
+            notes: state.app_state.simulation_state().notes().to_string(),
+        })
+    }
+
+    pub fn snapshot(&mut self) -> Option<()> {
+        let tuning = Engine::tuning(&self.state)?;
         let (l_flooded, l_unflooded) = self.state.ui_state.isoline.flooded_areas_lower?;
         let (h_flooded, h_unflooded) = self.state.ui_state.isoline.flooded_areas_higher?;
         let mut measurements = vec![
@@ -86,29 +248,174 @@ impl Engine {
         ];
         if let Some(eroded) = self.state.app_state.simulation_state().eroded() {
             measurements.push(Measurement::Time(eroded.simulation_time.as_secs_f32()));
+            if let Some(seams) = eroded.seams {
+                measurements.push(Measurement::SeamEnergy(seams.seam_energy));
+                measurements.push(Measurement::SeamRatio(seams.ratio()));
+            }
         }
-        let snapshot: Snapshot = (tuning, measurements);
-        self.snapshots.push(snapshot);
+        let heightmap = self.state.app_state.simulation_state().get_heightmap();
+        self.snapshots.push(tuning, measurements, heightmap);
         Some(())
     }
 
+    /// Runs the function `name` on a thread of its own, see `Instruction::Spawn`.
+    pub fn spawn(&mut self, name: &FunctionName) -> Result<(), EngineError> {
+        // Functions are kept reversed, so the function is run as it is
+        let main = self
+            .script
+            .get(name)
+            .ok_or_else(|| EngineError::MissingFunction(name.to_string()))?
+            .clone();
+        let script = self.script.clone();
+        let variables = self.variables.clone();
+        let log = Arc::clone(&self.log);
+        let states = bincode::serialize(&(&self.state, &self.stack, &self.registry))?;
+        let worker = thread::spawn(move || {
+            let (state, stack, registry) = bincode::deserialize(&states)?;
+            let engine = Engine {
+                state,
+                main,
+                script,
+                stack,
+                registry,
+                snapshots: Snapshots::default(),
+                variables,
+                completed: 0,
+                checkpoints: None,
+                calls: Vec::new(),
+                progress: None,
+                log,
+                headless: true,
+                workers: Vec::new(),
+            };
+            let engine = run_headless(engine)?;
+            Ok(bincode::serialize(&engine.snapshots)?)
+        });
+        self.workers.push((name.clone(), worker));
+        Ok(())
+    }
+
+    /// Waits for every spawned function and adds their snapshots, see `Instruction::Join`.
+    pub fn join(&mut self) -> Result<(), EngineError> {
+        for (name, worker) in mem::take(&mut self.workers) {
+            let snapshots = worker
+                .join()
+                .map_err(|_| EngineError::WorkerPanicked(name))??;
+            self.snapshots.append(bincode::deserialize(&snapshots)?);
+        }
+        Ok(())
+    }
+
+    /// The value of `metric` now, see `Instruction::Assert`.
+    pub fn metric(&self, metric: Metric) -> Result<f32, EngineError> {
+        let simulation = self.state.app_state.simulation_state();
+        let value = match metric {
+            Metric::AverageHeight => simulation.get_heightmap().get_average_height(),
+            Metric::AverageHeightDelta => simulation.eroded().and_then(|_| {
+                let base = simulation
+                    .base()
+                    .heightmap_base
+                    .heightmap
+                    .get_average_height()?;
+                Some(simulation.get_heightmap().get_average_height()? - base)
+            }),
+            Metric::Snapshots => Some(self.snapshots.entries.len() as f32),
+            metric => self
+                .snapshots
+                .entries
+                .last()
+                .and_then(|(_, measurements, _)| {
+                    measurements
+                        .iter()
+                        .find_map(|measurement| match (metric, measurement) {
+                            (Metric::SimulationTime, Measurement::Time(seconds)) => Some(*seconds),
+                            (Metric::LowFlooded, Measurement::LowAreas(flooded, _))
+                            | (Metric::HighFlooded, Measurement::HighAreas(flooded, _))
+                            | (Metric::IsoError, Measurement::IsoError(flooded)) => {
+                                Some(*flooded as f32)
+                            }
+                            (Metric::LowUnflooded, Measurement::LowAreas(_, unflooded))
+                            | (Metric::HighUnflooded, Measurement::HighAreas(_, unflooded)) => {
+                                Some(*unflooded as f32)
+                            }
+                            (Metric::MeanDifference, Measurement::MeanDifference(value))
+                            | (Metric::RmsDifference, Measurement::RmsDifference(value))
+                            | (Metric::MaxDifference, Measurement::MaxDifference(value))
+                            | (Metric::SeamEnergy, Measurement::SeamEnergy(value))
+                            | (Metric::SeamRatio, Measurement::SeamRatio(value)) => Some(*value),
+                            _ => None,
+                        })
+                }),
+        };
+        value.ok_or(EngineError::MissingMetric(metric))
+    }
+
+    /// The state `state` refers to, names are looked up in the registry before the stack.
+    pub fn resolve(&self, state: &StateRef) -> Option<&State> {
+        match state {
+            StateRef::Current => Some(&self.state),
+            StateRef::Stack(depth) => self.stack.iter().rev().nth(*depth),
+            StateRef::Named(name) => self.registry.get(name).or_else(|| {
+                self.stack
+                    .iter()
+                    .rev()
+                    .chain(std::iter::once(&self.state))
+                    .find(|s| s.state_name.as_ref() == Some(name))
+            }),
+        }
+    }
+
+    /// Takes a snapshot of the difference between the selected heightmaps of `a` and `b`, tuned
+    /// like `b` so a sweep comparing against one reference lists what changed in each entry.
+    pub fn compare(&mut self, a: &StateRef, b: &StateRef) -> Result<(), EngineError> {
+        let state_a = self
+            .resolve(a)
+            .ok_or_else(|| EngineError::UnknownState(a.clone()))?;
+        let state_b = self
+            .resolve(b)
+            .ok_or_else(|| EngineError::UnknownState(b.clone()))?;
+        let (diff, measurements) = difference(
+            &state_a.app_state.simulation_state().get_heightmap(),
+            &state_b.app_state.simulation_state().get_heightmap(),
+        )
+        .map_err(|_| EngineError::MismatchingStates)?;
+        let mut tuning = Engine::tuning(state_b).ok_or(EngineError::MissingSnapshotData)?;
+        tuning.notes = format!("Difference between {} and {}", a, b);
+        self.snapshots.push(tuning, measurements, Rc::new(diff));
+        Ok(())
+    }
+
+    /// The snapshots as a csv table, see `record`.
     pub fn snapshots_to_string(&self) -> Result<String, EngineError> {
-        Ok(serde_json::to_string(&self.snapshots)?)
+        Ok(record::to_csv(&record::records(&self.snapshots)?))
+    }
+
+    /// Writes the records of the snapshots as csv, or json if `filename` ends in `.json`.
+    pub fn export_records(&self, filename: &str) -> Result<(), EngineError> {
+        record::write(&record::records(&self.snapshots)?, filename)
     }
 
     pub fn export_snapshots(&self, filename: &str) -> Result<(), EngineError> {
-        fs::write(filename, self.snapshots_to_string()?)?;
-        Ok(())
+        archive::write(&self.snapshots, filename)
     }
 }
 
-pub async fn launch(mut script: Script) -> Result<Engine, EngineError> {
-    prevent_quit();
+#[cfg(feature = "gui")]
+pub async fn launch(script: Script) -> Result<Engine, EngineError> {
+    window::prevent_quit();
+    let mut engine = start(script)?;
+    engine = turn(engine).await?;
+    Ok(engine)
+}
+
+/// An engine ready to run `script`, in the state of its first instruction.
+pub fn start(mut script: Script) -> Result<Engine, EngineError> {
     for (_, fun) in script.iter_mut() {
         fun.reverse()
     }
     let stack: Stack = Vec::new();
-    let snapshots: Vec<Snapshot> = Vec::new();
+    let registry = Registry::new();
+    let snapshots = Snapshots::default();
     let mut main = script
         .remove("main")
         .ok_or(EngineError::MissingMainFunction)?;
@@ -121,21 +428,202 @@ pub async fn launch(mut script: Script) -> Result<Engine, EngineError> {
         return Err(EngineError::HasNoState);
     };
 
-    let mut engine = Engine {
+    Ok(Engine {
         state,
         main,
         script,
         stack,
+        registry,
         snapshots,
-    };
+        variables: Variables::new(),
+        completed: 1,
+        checkpoints: None,
+        calls: Vec::new(),
+        progress: None,
+        log: progress::terminal(),
+        headless: false,
+        workers: Vec::new(),
+    })
+}
 
-    engine = turn(engine).await?;
+/// Expands every call into the instructions of the called function.
+pub fn flatten(
+    script: &Script,
+    name: &FunctionName,
+    depth: usize,
+    instructions: &mut Function,
+) -> Result<(), EngineError> {
+    if depth > MAX_CALL_DEPTH {
+        return Err(EngineError::RecursiveCall(name.to_string()));
+    }
+    let function = script
+        .get(name)
+        .ok_or_else(|| EngineError::MissingFunction(name.to_string()))?;
+    flatten_function(script, function, depth, instructions)
+}
+
+/// Expands calls and loops, a loop becomes its body once per run with the variable set before it.
+fn flatten_function(
+    script: &Script,
+    function: &Function,
+    depth: usize,
+    instructions: &mut Function,
+) -> Result<(), EngineError> {
+    for instruction in function {
+        match instruction {
+            Instruction::Call(callee) => flatten(script, callee, depth + 1, instructions)?,
+            Instruction::Repeat(times, body) => {
+                for _ in 0..*times {
+                    flatten_function(script, body, depth, instructions)?;
+                }
+            }
+            Instruction::ForEach(values, variable, body) => {
+                for value in values {
+                    instructions.push(Instruction::Set(variable.clone(), value.clone()));
+                    flatten_function(script, body, depth, instructions)?;
+                }
+            }
+            instruction => instructions.push(instruction.clone()),
+        }
+    }
+    Ok(())
+}
+
+/// `text` with every `${name}` replaced by the value of the variable.
+pub fn substitute_text(text: &str, variables: &Variables) -> Result<String, EngineError> {
+    let mut substituted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(length) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + length];
+        let value = variables
+            .get(name)
+            .ok_or_else(|| EngineError::UnknownVariable(name.to_string()))?;
+        substituted.push_str(&rest[..start]);
+        substituted.push_str(value);
+        rest = &rest[start + length + 1..];
+    }
+    substituted.push_str(rest);
+    Ok(substituted)
+}
+
+/// The instruction with the variables substituted into its strings. The bodies of loops are left
+/// as they are, their variables are substituted when the instructions in them run.
+pub fn substitute(
+    instruction: Instruction,
+    variables: &Variables,
+) -> Result<Instruction, EngineError> {
+    fn strings(value: &mut serde_json::Value, variables: &Variables) -> Result<(), EngineError> {
+        match value {
+            serde_json::Value::String(text) => *text = substitute_text(text, variables)?,
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    strings(value, variables)?;
+                }
+            }
+            serde_json::Value::Object(fields) => {
+                for value in fields.values_mut() {
+                    strings(value, variables)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    if let Instruction::Repeat(..) | Instruction::ForEach(..) = instruction {
+        return Ok(instruction);
+    }
+    let mut value = serde_json::to_value(&instruction)?;
+    if !value.to_string().contains("${") {
+        return Ok(instruction);
+    }
+    strings(&mut value, variables)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// The instructions `main` runs with every call expanded, which must start with a new state.
+pub fn expand(script: &Script) -> Result<Function, EngineError> {
+    if !script.contains_key("main") {
+        return Err(EngineError::MissingMainFunction);
+    }
+    let mut instructions = Vec::new();
+    flatten(script, &"main".to_string(), 0, &mut instructions)?;
+    match instructions.first() {
+        Some(Instruction::NewState(_)) => Ok(instructions),
+        _ => Err(EngineError::HasNoState),
+    }
+}
+
+/// Checks a script without running it, as far as it can be checked without a state, returning
+/// the instructions it would run. Variables are substituted in the order they are set and
+/// templates are read with the values they will have.
+pub fn check(script: &Script) -> Result<Function, EngineError> {
+    let instructions = expand(script)?;
+    let mut variables = Variables::new();
+    for instruction in &instructions {
+        let mut lines = match substitute(instruction.clone(), &variables)? {
+            Instruction::Template(line) => scripts::text::line(&line)?,
+            instruction => vec![instruction],
+        };
+        for instruction in lines.drain(..) {
+            match instruction {
+                Instruction::SetErosionPreset(name) => {
+                    Parameters::preset(&name).ok_or(EngineError::UnknownPreset(name))?;
+                }
+                Instruction::Set(variable, value) => {
+                    variables.insert(variable, value);
+                }
+                Instruction::Spawn(name) => {
+                    let mut branch = Vec::new();
+                    flatten(script, &name, 1, &mut branch)?;
+                }
+                Instruction::If(_, then, otherwise) => {
+                    for name in std::iter::once(then).chain(otherwise) {
+                        let mut branch = Vec::new();
+                        flatten(script, &name, 1, &mut branch)?;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(instructions)
+}
+
+/// Runs the engine to the end on this thread without a window.
+pub fn run_headless(mut engine: Engine) -> Result<Engine, EngineError> {
+    engine.headless = true;
+    // Headless instructions never wait for a frame, so every tick is ready when first polled
+    let mut context = Context::from_waker(Waker::noop());
+    while engine.ready() {
+        let mut next = pin!(tick(engine));
+        engine = loop {
+            if let Poll::Ready(result) = next.as_mut().poll(&mut context) {
+                break result?;
+            }
+        };
+    }
+    engine.join()?;
     Ok(engine)
 }
 
 pub async fn turn(mut engine: Engine) -> Result<Engine, EngineError> {
     while engine.ready() {
         engine = tick(engine).await?;
+        checkpoint::update(&mut engine)?;
+        progress::update(&mut engine)?;
+    }
+    engine.join()?;
+    progress::finish(&mut engine)?;
+    // A finished script has nothing to resume
+    if let Some(checkpoints) = &engine.checkpoints {
+        match std::fs::remove_file(&checkpoints.path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
     }
     Ok(engine)
 }
@@ -146,8 +634,234 @@ impl From<serde_json::Error> for EngineError {
     }
 }
 
+impl From<ScriptError> for EngineError {
+    fn from(err: ScriptError) -> Self {
+        EngineError::ScriptError(err)
+    }
+}
+
+impl From<bincode::Error> for EngineError {
+    fn from(err: bincode::Error) -> Self {
+        EngineError::BinaryError(err)
+    }
+}
+
 impl From<std::io::Error> for EngineError {
     fn from(err: std::io::Error) -> Self {
         EngineError::RWError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        check_heightmap, tiny_heightmap, tiny_rectangular_heightmap, Check, ITERATIONS, SIZE,
+    };
+    use crate::heightmap;
+
+    #[test]
+    fn state_difference() -> Check {
+        use crate::engine::{difference, Measurement};
+        let heightmap = tiny_heightmap();
+        let mut raised = heightmap.clone();
+        raised.data[3][5] += 0.5;
+        let (diff, measurements) =
+            difference(&heightmap, &raised).map_err(|err| format!("{:?}", err))?;
+        check_heightmap(&diff, SIZE, SIZE)?;
+        let cells = (SIZE * SIZE) as f32;
+        for measurement in measurements {
+            let (value, expected) = match measurement {
+                Measurement::MeanDifference(mean) => (mean, 0.5 / cells),
+                Measurement::RmsDifference(rms) => (rms, (0.25 / cells).sqrt()),
+                Measurement::MaxDifference(max) => (max, 0.5),
+                _ => continue,
+            };
+            if (value - expected).abs() > 1e-4 {
+                return Err(format!("{:?}, expected {}", measurement, expected));
+            }
+        }
+        if difference(&heightmap, &tiny_rectangular_heightmap()).is_ok() {
+            return Err("compared heightmaps of different sizes".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_dedup() -> Check {
+        use format::ScriptFormat;
+        // The hash is stored in archives and records, so it must not change between releases
+        let fixed = Heightmap::new(vec![vec![0.0, 0.25], vec![0.5, 1.0]], 2, 2, 1.0, 1.0, None);
+        if fixed.content_hash() != 0x3935_e331_03a5_df51 {
+            return Err(format!(
+                "content hash changed to {:016x}",
+                fixed.content_hash()
+            ));
+        }
+        let script = ScriptFormat::Text
+            .parse("new procedural size=32\nisoline\nflush\nsnapshot\nsnapshot\n")
+            .map_err(|err| err.to_string())?;
+        let engine = start(script).map_err(|err| format!("{:?}", err))?;
+        let mut snapshots = run_headless(engine)
+            .map_err(|err| format!("{:?}", err))?
+            .snapshots;
+        if snapshots.entries.len() != 2 || snapshots.heightmaps.len() != 1 {
+            return Err("snapshots of the same heightmap are not stored once".to_string());
+        }
+        let (tuning, _, hash) = snapshots.entries[0].clone();
+        let mut noted = (*snapshots.heightmaps[&hash]).clone();
+        noted.metadata_add("NOTE", "other".to_string());
+        // Equal heights with other metadata hash the same but are stored apart
+        snapshots.push(tuning.clone(), vec![], Rc::new(noted.clone()));
+        snapshots.push(tuning, vec![], Rc::new(noted.clone()));
+        let noted_hash = snapshots.entries[2].2;
+        if snapshots.heightmaps.len() != 2 || snapshots.entries[3].2 != noted_hash {
+            return Err(format!("{} heightmaps stored", snapshots.heightmaps.len()));
+        }
+        match snapshots.heightmap(&snapshots.entries[2]) {
+            Some(stored) if noted_hash != hash && stored.metadata == noted.metadata => Ok(()),
+            _ => Err("the snapshot refers to the metadata of another heightmap".to_string()),
+        }
+    }
+
+    #[test]
+    fn script_conditions() -> Check {
+        use crate::engine::{self, Engine, EngineError};
+        use format::ScriptFormat;
+        use scripts::{Comparison, Instruction, Metric};
+        let text = "new procedural size=32\n\
+                    assert average-height >= 0\n\
+                    if snapshots == 0 then fresh else taken\n\
+                    \n\
+                    fn fresh\n    \
+                        print nothing taken yet\n\
+                    \n\
+                    fn taken\n    \
+                        save taken\n";
+        let script = ScriptFormat::Text
+            .parse(text)
+            .map_err(|err| err.to_string())?;
+        match &script["main"][1..] {
+            [Instruction::Assert(assert), Instruction::If(condition, then, Some(otherwise))]
+                if assert.metric == Metric::AverageHeight
+                    && assert.comparison == Comparison::GreaterOrEqual
+                    && condition.metric == Metric::Snapshots
+                    && then == "fresh"
+                    && otherwise == "taken" => {}
+            main => return Err(format!("conditions were read as {:?}", main)),
+        }
+        engine::check(&script).map_err(|err| format!("{:?}", err))?;
+        let written = ScriptFormat::Text
+            .write(&script)
+            .map_err(|err| err.to_string())?;
+        if !written.contains("if snapshots == 0 then fresh else taken") {
+            return Err(format!("conditions were written as\n{}", written));
+        }
+        let mut missing = script.clone();
+        missing.remove("taken");
+        match engine::check(&missing) {
+            Err(EngineError::MissingFunction(name)) if name == "taken" => {}
+            result => return Err(format!("a missing branch gave {:?}", result)),
+        }
+
+        let state = crate::State::new(&HeightmapType::Procedural(
+            heightmap::HeightmapParameters {
+                size: SIZE,
+                height: None,
+            },
+            Default::default(),
+        ));
+        let average = state
+            .app_state
+            .simulation_state()
+            .get_heightmap()
+            .get_average_height();
+        let engine = Engine {
+            state,
+            main: Vec::new(),
+            script: Default::default(),
+            stack: Vec::new(),
+            registry: Default::default(),
+            snapshots: Default::default(),
+            variables: Default::default(),
+            completed: 0,
+            checkpoints: None,
+            calls: Vec::new(),
+            progress: None,
+            log: progress::terminal(),
+            headless: true,
+            workers: Vec::new(),
+        };
+        if engine.metric(Metric::AverageHeight).ok() != average {
+            return Err("average height differs from the heightmap".to_string());
+        }
+        if engine.metric(Metric::Snapshots).ok() != Some(0.0) {
+            return Err("snapshots counted without any taken".to_string());
+        }
+        for metric in [Metric::AverageHeightDelta, Metric::SeamRatio] {
+            match engine.metric(metric) {
+                Err(EngineError::MissingMetric(missing)) if missing == metric => {}
+                result => return Err(format!("{:?} of a base state is {:?}", metric, result)),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn script_workers() -> Check {
+        use crate::engine;
+        use format::ScriptFormat;
+        use scripts::{Instruction, Metric};
+        let text = "new procedural size=32\n\
+                    push\n\
+                    spawn branch\n\
+                    spawn branch\n\
+                    join\n\
+                    assert snapshots == 2\n\
+                    \n\
+                    fn branch\n    \
+                        render\n    \
+                        erode\n    \
+                        isoline\n    \
+                        flush\n    \
+                        snapshot\n";
+        let mut script = ScriptFormat::Text
+            .parse(text)
+            .map_err(|err| err.to_string())?;
+        // Scripts in text can not set the iterations, a full erosion in each worker takes long
+        let params = Parameters {
+            num_iterations: ITERATIONS,
+            ..Default::default()
+        };
+        script
+            .get_mut("branch")
+            .ok_or("branch was not read")?
+            .insert(0, Instruction::SetErosionParameters(params));
+        match &script["main"][2..5] {
+            [Instruction::Spawn(a), Instruction::Spawn(b), Instruction::Join]
+                if a == "branch" && b == "branch" => {}
+            main => return Err(format!("workers were read as {:?}", main)),
+        }
+        engine::check(&script).map_err(|err| format!("{:?}", err))?;
+        let engine = engine::start(script).map_err(|err| format!("{:?}", err))?;
+        let engine = engine::run_headless(engine).map_err(|err| format!("{:?}", err))?;
+        if !engine.workers.is_empty() || engine.snapshots.entries.len() != 2 {
+            return Err(format!(
+                "{} workers left with {} snapshots joined",
+                engine.workers.len(),
+                engine.snapshots.entries.len()
+            ));
+        }
+        if engine.metric(Metric::Snapshots).ok() != Some(2.0) {
+            return Err("joined snapshots are not counted".to_string());
+        }
+        let (_, _, a) = &engine.snapshots.entries[0];
+        let (_, _, b) = &engine.snapshots.entries[1];
+        if !engine.snapshots.heightmaps.contains_key(a)
+            || !engine.snapshots.heightmaps.contains_key(b)
+        {
+            return Err("joined snapshots lost their heightmaps".to_string());
+        }
+        Ok(())
+    }
+}
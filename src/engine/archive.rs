@@ -0,0 +1,189 @@
+use crate::engine::{EngineError, Snapshot, Snapshots};
+use crate::heightmap::{Heightmap, HeightmapHash};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/*
+Snapshot archive layout:
+- [4 bytes]  magic "ERSS"
+- [4 bytes]  format version (u32, little endian)
+- [8 bytes]  offset of the index (u64, little endian)
+- [n bytes]  blobs, one bincode encoded heightmap per unique hash followed by one per snapshot
+- [n bytes]  bincode encoded ArchiveIndex
+ */
+
+pub const SNAPSHOT_FILE_EXT: &str = "ersnap";
+const MAGIC: &[u8; 4] = b"ERSS";
+const VERSION: u32 = 2;
+const HEADER_SIZE: u64 = 16;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct BlobRef {
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ArchiveIndex {
+    pub snapshots: Vec<BlobRef>,
+    pub heightmaps: HashMap<HeightmapHash, BlobRef>,
+}
+
+fn write_blob<W: Write, T: Serialize>(
+    writer: &mut W,
+    offset: &mut u64,
+    value: &T,
+) -> Result<BlobRef, EngineError> {
+    let data = bincode::serialize(value)?;
+    writer.write_all(&data)?;
+    let blob = BlobRef {
+        offset: *offset,
+        length: data.len() as u64,
+    };
+    *offset += blob.length;
+    Ok(blob)
+}
+
+pub fn list_archives(path: &str) -> std::io::Result<Vec<String>> {
+    let extension = format!(".{}", SNAPSHOT_FILE_EXT);
+    let mut archives: Vec<String> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.ends_with(&extension))
+        .collect();
+    archives.sort();
+    Ok(archives)
+}
+
+pub fn write(snapshots: &Snapshots, filename: &str) -> Result<(), EngineError> {
+    if let Some(parent) = Path::new(filename).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(File::create(filename)?);
+    let mut offset = HEADER_SIZE;
+    let mut index = ArchiveIndex::default();
+
+    // The index offset is patched in once all blobs are written
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&0u64.to_le_bytes())?;
+
+    for (hash, heightmap) in snapshots.heightmaps.iter() {
+        let blob = write_blob(&mut writer, &mut offset, heightmap.as_ref())?;
+        index.heightmaps.insert(*hash, blob);
+    }
+    for snapshot in snapshots.entries.iter() {
+        let blob = write_blob(&mut writer, &mut offset, snapshot)?;
+        index.snapshots.push(blob);
+    }
+    writer.write_all(&bincode::serialize(&index)?)?;
+
+    writer.seek(SeekFrom::Start(8))?;
+    writer.write_all(&offset.to_le_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the index of a snapshot archive up front and decodes snapshots and heightmaps on demand.
+#[derive(Debug)]
+pub struct SnapshotArchive {
+    file: File,
+    pub index: ArchiveIndex,
+}
+
+impl SnapshotArchive {
+    pub fn open(filename: &str) -> Result<Self, EngineError> {
+        let mut file = File::open(filename)?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(EngineError::InvalidSnapshotArchive);
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(EngineError::InvalidSnapshotArchive);
+        }
+        let index_offset = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+        file.seek(SeekFrom::Start(index_offset))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let index = bincode::deserialize(&data)?;
+        Ok(SnapshotArchive { file, index })
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.snapshots.is_empty()
+    }
+
+    fn read_blob<T: for<'de> Deserialize<'de>>(&mut self, blob: BlobRef) -> Result<T, EngineError> {
+        self.file.seek(SeekFrom::Start(blob.offset))?;
+        let mut data = vec![0u8; blob.length as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(bincode::deserialize(&data)?)
+    }
+
+    pub fn read_snapshot(&mut self, index: usize) -> Result<Snapshot, EngineError> {
+        let blob = *self
+            .index
+            .snapshots
+            .get(index)
+            .ok_or(EngineError::MissingSnapshotData)?;
+        self.read_blob(blob)
+    }
+
+    pub fn read_heightmap(&mut self, hash: HeightmapHash) -> Result<Heightmap, EngineError> {
+        let blob = *self
+            .index
+            .heightmaps
+            .get(&hash)
+            .ok_or(EngineError::MissingSnapshotData)?;
+        self.read_blob(blob)
+    }
+
+    pub fn read_all(&mut self) -> Result<Snapshots, EngineError> {
+        let mut snapshots = Snapshots::default();
+        let hashes: Vec<HeightmapHash> = self.index.heightmaps.keys().copied().collect();
+        for hash in hashes {
+            let heightmap = self.read_heightmap(hash)?;
+            snapshots.heightmaps.insert(hash, Rc::new(heightmap));
+        }
+        for i in 0..self.len() {
+            snapshots.entries.push(self.read_snapshot(i)?);
+        }
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive;
+    use crate::fixtures::{check_round_trip, temp_path, tiny_heightmap, Check};
+
+    #[test]
+    fn archive_round_trip() -> Check {
+        let heightmap = tiny_heightmap();
+        let hash = heightmap.content_hash();
+        let mut snapshots = Snapshots::default();
+        snapshots
+            .heightmaps
+            .insert(hash, Rc::new(heightmap.clone()));
+        let path = temp_path(&format!("archive.{}", archive::SNAPSHOT_FILE_EXT));
+        let path = path.to_string_lossy();
+        let result = archive::write(&snapshots, &path)
+            .and_then(|_| archive::SnapshotArchive::open(&path)?.read_heightmap(hash))
+            .map_err(|err| format!("{:?}", err));
+        let _ = fs::remove_file(path.as_ref());
+        check_round_trip(&heightmap, &result?)
+    }
+}
@@ -0,0 +1,167 @@
+use crate::engine::progress;
+use crate::engine::scripts::{Function, FunctionName, Script};
+use crate::engine::{Engine, EngineError, Registry, Snapshots, Stack, Variables};
+use crate::State;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/*
+A long script is checkpointed while it runs so that it can be resumed with `--resume` after it
+died, instead of starting over. A checkpoint holds everything the engine runs on, the instructions
+left to run, the functions they may call, the current state, the stack, the named states, the
+snapshots not saved yet, the variables and the functions being called, and is written with
+bincode every `interval` after an instruction finished. It is written next to the checkpoint file
+and renamed over it, so a crash while writing leaves the previous checkpoint, and removed once the
+script finished.
+ */
+
+pub const CHECKPOINT_FILE: &str = "engine.checkpoint";
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where and how often a running engine writes its checkpoints.
+pub struct Checkpoints {
+    pub path: PathBuf,
+    pub interval: Duration,
+    last: Instant,
+}
+
+impl Checkpoints {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Checkpoints {
+            path: path.into(),
+            interval,
+            last: Instant::now(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Instructions run before the checkpoint was written.
+    pub completed: usize,
+    /// Instructions left to run, reversed like `Engine::main`.
+    pub main: Function,
+    pub script: Script,
+    pub state: State,
+    pub stack: Stack,
+    pub registry: Registry,
+    pub snapshots: Snapshots,
+    pub variables: Variables,
+    pub calls: Vec<(FunctionName, usize)>,
+}
+
+impl Checkpoint {
+    pub fn of(engine: &Engine) -> Self {
+        Checkpoint {
+            completed: engine.completed,
+            main: engine.main.clone(),
+            script: engine.script.clone(),
+            state: engine.state.clone(),
+            stack: engine.stack.clone(),
+            registry: engine.registry.clone(),
+            snapshots: engine.snapshots.clone(),
+            variables: engine.variables.clone(),
+            calls: engine.calls.clone(),
+        }
+    }
+
+    /// An engine continuing where the checkpoint was written, without checkpoints of its own.
+    pub fn resume(self) -> Engine {
+        Engine {
+            state: self.state,
+            main: self.main,
+            script: self.script,
+            stack: self.stack,
+            registry: self.registry,
+            snapshots: self.snapshots,
+            variables: self.variables,
+            completed: self.completed,
+            checkpoints: None,
+            calls: self.calls,
+            progress: None,
+            log: progress::terminal(),
+            headless: false,
+            workers: Vec::new(),
+        }
+    }
+}
+
+pub fn write(checkpoint: &Checkpoint, path: &Path) -> Result<(), EngineError> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, bincode::serialize(checkpoint)?)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Checkpoint, EngineError> {
+    Ok(bincode::deserialize(&fs::read(path)?)?)
+}
+
+/// Writes a checkpoint of the engine if its interval has passed since the last one. Spawned
+/// functions would be lost with their threads, so none is written until they are joined.
+pub fn update(engine: &mut Engine) -> Result<(), EngineError> {
+    if !engine.workers.is_empty() {
+        return Ok(());
+    }
+    let due = match &engine.checkpoints {
+        Some(checkpoints) => checkpoints.last.elapsed() >= checkpoints.interval,
+        None => false,
+    };
+    if due {
+        let checkpoint = Checkpoint::of(engine);
+        if let Some(checkpoints) = &mut engine.checkpoints {
+            write(&checkpoint, &checkpoints.path)?;
+            checkpoints.last = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{temp_path, Check};
+
+    #[test]
+    fn engine_checkpoint() -> Check {
+        use crate::engine::checkpoint::{self, Checkpoint};
+        use crate::engine::format::ScriptFormat;
+        let script = ScriptFormat::Text
+            .parse("new procedural size=32\nset run 3\npush\nerode\nsave run-${run}\n")
+            .map_err(|err| err.to_string())?;
+        let mut engine = crate::engine::start(script).map_err(|err| format!("{:?}", err))?;
+        engine.variables.insert("run".to_string(), "3".to_string());
+        engine.stack.push(engine.state.clone());
+        let path = temp_path("engine.checkpoint");
+        checkpoint::write(&Checkpoint::of(&engine), &path).map_err(|err| format!("{:?}", err))?;
+        let resumed = checkpoint::read(&path)
+            .map_err(|err| format!("{:?}", err))?
+            .resume();
+        let _ = std::fs::remove_file(&path);
+        if resumed.completed != engine.completed || resumed.main.len() != engine.main.len() {
+            return Err(format!(
+                "resumed after {} with {} left, not after {} with {} left",
+                resumed.completed,
+                resumed.main.len(),
+                engine.completed,
+                engine.main.len()
+            ));
+        }
+        if resumed.variables != engine.variables || resumed.stack.len() != 1 {
+            return Err("variables or the stack were lost".to_string());
+        }
+        let heightmap = |engine: &crate::engine::Engine| {
+            engine
+                .state
+                .app_state
+                .simulation_state()
+                .get_heightmap()
+                .content_hash()
+        };
+        if heightmap(&resumed) != heightmap(&engine) {
+            return Err("the resumed state has another heightmap".to_string());
+        }
+        Ok(())
+    }
+}
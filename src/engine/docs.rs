@@ -0,0 +1,226 @@
+use crate::engine::scripts::Script;
+use crate::erode::{beyer, fluvial, glacial, pipes, wind, Parameters};
+use crate::heightmap::{HeightmapParameters, ProceduralHeightmapSettings};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/*
+A json schema of engine scripts, written by `--script-docs` for tools that generate scripts to
+validate them before they are run. Every `Instruction` is described down to the `UiEvent`s,
+partitioning `Method`s and parameter structs it takes, with the field names and types derived from
+the same definitions the scripts are read with. Parameter fields also get the default of the
+struct, and the range and note from the comment after the field, e.g. `// [0, 1], 0.3 (note)`,
+which the source is read for so the comments stay the only place ranges are written down.
+ */
+
+pub const SCHEMA_FILE: &str = "script.schema.json";
+
+/// A parameter struct with range comments, by the name of its definition in the schema.
+struct Documented {
+    definition: &'static str,
+    name: &'static str,
+    source: &'static str,
+    defaults: Value,
+}
+
+impl Documented {
+    fn new<T: Default + Serialize>(
+        definition: &'static str,
+        name: &'static str,
+        source: &'static str,
+    ) -> Self {
+        Documented {
+            definition,
+            name,
+            source,
+            defaults: serde_json::to_value(T::default())
+                .map(shortest_floats)
+                .unwrap_or(Value::Null),
+        }
+    }
+}
+
+fn documented() -> Vec<Documented> {
+    let heightmap = include_str!("../heightmap.rs");
+    vec![
+        Documented::new::<Parameters>(
+            "LagueParameters",
+            "Parameters",
+            include_str!("../erode/lague.rs"),
+        ),
+        Documented::new::<beyer::Parameters>(
+            "BeyerParameters",
+            "Parameters",
+            include_str!("../erode/beyer.rs"),
+        ),
+        Documented::new::<pipes::Parameters>(
+            "PipesParameters",
+            "Parameters",
+            include_str!("../erode/pipes.rs"),
+        ),
+        Documented::new::<fluvial::Parameters>(
+            "FluvialParameters",
+            "Parameters",
+            include_str!("../erode/fluvial.rs"),
+        ),
+        Documented::new::<wind::Parameters>(
+            "WindParameters",
+            "Parameters",
+            include_str!("../erode/wind.rs"),
+        ),
+        Documented::new::<glacial::Parameters>(
+            "GlacialParameters",
+            "Parameters",
+            include_str!("../erode/glacial.rs"),
+        ),
+        Documented::new::<HeightmapParameters>(
+            "HeightmapParameters",
+            "HeightmapParameters",
+            heightmap,
+        ),
+        Documented::new::<ProceduralHeightmapSettings>(
+            "ProceduralHeightmapSettings",
+            "ProceduralHeightmapSettings",
+            heightmap,
+        ),
+    ]
+}
+
+/// Writes the f32 fields as short as they are in the source instead of as the nearest f64,
+/// 0.3 instead of 0.30000001192092896.
+fn shortest_floats(value: Value) -> Value {
+    match value {
+        Value::Number(number) if number.is_f64() => number
+            .as_f64()
+            .and_then(|float| (float as f32).to_string().parse::<f64>().ok())
+            .map(|float| json!(float))
+            .unwrap_or(Value::Number(number)),
+        Value::Array(values) => Value::Array(values.into_iter().map(shortest_floats).collect()),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, shortest_floats(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// A field of a struct with the range and note of its comment.
+#[derive(Debug, PartialEq)]
+pub struct FieldComment {
+    pub field: String,
+    pub range: Option<(f64, f64)>,
+    pub note: Option<String>,
+}
+
+/// Comments after the fields of the struct `name` in `source`.
+pub fn field_comments(source: &str, name: &str) -> Vec<FieldComment> {
+    let start = match source.find(&format!("pub struct {} {{", name)) {
+        Some(start) => start,
+        None => return Vec::new(),
+    };
+    source[start..]
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.starts_with('}'))
+        .filter_map(|line| {
+            let (field, comment) = line.trim().strip_prefix("pub ")?.split_once("//")?;
+            let field = field.split(':').next()?.trim().to_string();
+            let comment = comment.trim();
+            let (range, rest) = match comment.strip_prefix('[').and_then(|c| c.split_once(']')) {
+                Some((range, rest)) => {
+                    let (min, max) = range.split_once(',')?;
+                    let range = (min.trim().parse().ok()?, max.trim().parse().ok()?);
+                    (Some(range), rest.trim_start_matches(',').trim())
+                }
+                None => (None, comment),
+            };
+            let note = rest
+                .split_once('(')
+                .map(|(_, note)| note.trim_end_matches(')').to_string());
+            Some(FieldComment { field, range, note })
+        })
+        .collect()
+}
+
+pub fn schema() -> Value {
+    let mut schema = match serde_json::to_value(schemars::schema_for!(Script)) {
+        Ok(schema) => schema,
+        Err(err) => panic!("Script schema can't be converted to json: {:?}", err),
+    };
+    schema["title"] = json!("erosion-rs engine script");
+    schema["description"] = json!("Functions by name, the engine starts by calling `main`.");
+    for documented in documented() {
+        let pointer = format!("/definitions/{}/properties", documented.definition);
+        let properties = match schema.pointer_mut(&pointer).and_then(Value::as_object_mut) {
+            Some(properties) => properties,
+            None => continue,
+        };
+        for (field, property) in properties.iter_mut() {
+            if let (Some(property), Some(default)) =
+                (property.as_object_mut(), documented.defaults.get(field))
+            {
+                property.entry("default").or_insert_with(|| default.clone());
+            }
+        }
+        for comment in field_comments(documented.source, documented.name) {
+            let property = match properties.get_mut(&comment.field) {
+                Some(Value::Object(property)) => property,
+                _ => continue,
+            };
+            if let Some((min, max)) = comment.range {
+                property.insert("minimum".to_string(), json!(min));
+                property.insert("maximum".to_string(), json!(max));
+            }
+            if let Some(note) = comment.note {
+                property.entry("description").or_insert_with(|| json!(note));
+            }
+        }
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::Check;
+
+    #[test]
+    fn script_docs() -> Check {
+        use crate::engine::docs;
+        let schema = docs::schema();
+        for definition in [
+            "Instruction",
+            "UiEvent",
+            "Method",
+            "LagueParameters",
+            "BeyerParameters",
+        ] {
+            if schema
+                .pointer(&format!("/definitions/{}", definition))
+                .is_none()
+            {
+                return Err(format!("{} is not documented", definition));
+            }
+        }
+        let radius = schema
+            .pointer("/definitions/LagueParameters/properties/erosion_radius")
+            .ok_or("erosion radius is not documented")?;
+        if radius["minimum"] != 2.0 || radius["maximum"] != 8.0 || radius["default"] != 3 {
+            return Err(format!("erosion radius documented as {}", radius));
+        }
+        let comments = docs::field_comments(
+            "pub struct A {\n    pub a: f32, // [0, 0.5], 0.1 (note)\n    pub b: bool, // true\n}",
+            "A",
+        );
+        match comments.as_slice() {
+            [a, b]
+                if a.range == Some((0.0, 0.5))
+                    && a.note.as_deref() == Some("note")
+                    && b.range.is_none() =>
+            {
+                Ok(())
+            }
+            _ => Err(format!("comments read as {:?}", comments)),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::engine::scripts::{text, Script};
+use crate::engine::EngineError;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+/*
+Scripts can be written in JSON, RON, YAML or the text format of `scripts::text`, picked by the
+extension of the file, `.ron` for RON, `.yaml` or `.yml` for YAML, `.erst` for text and anything
+else, such as `.erss`, for JSON. RON writes enums the way they are written in Rust, so
+`Method::SubdivisionBlurBoundary((usize, (f32, u16)))` becomes
+`SubdivisionBlurBoundary((4, (0.5, 3)))`, which is the easiest to edit by hand. Errors name the
+key path to the value that failed to parse, e.g. `main[3].RunSimulation.method`, next to the
+message of the parser, errors in the text format name the line instead.
+ */
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScriptFormat {
+    Json,
+    Ron,
+    Yaml,
+    Text,
+}
+
+impl Display for ScriptFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptFormat::Json => f.write_str("JSON"),
+            ScriptFormat::Ron => f.write_str("RON"),
+            ScriptFormat::Yaml => f.write_str("YAML"),
+            ScriptFormat::Text => f.write_str("text"),
+        }
+    }
+}
+
+/// A script that failed to parse, with the key path to the value the parser stopped at.
+#[derive(Debug)]
+pub struct ScriptError {
+    pub format: ScriptFormat,
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid {} script", self.format)?;
+        if !self.path.is_empty() && self.path != "." {
+            write!(f, " at {}", self.path)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl ScriptFormat {
+    pub fn list() -> [ScriptFormat; 4] {
+        [
+            ScriptFormat::Json,
+            ScriptFormat::Ron,
+            ScriptFormat::Yaml,
+            ScriptFormat::Text,
+        ]
+    }
+
+    pub fn from_path(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("ron") => ScriptFormat::Ron,
+            Some("yaml") | Some("yml") => ScriptFormat::Yaml,
+            Some("erst") => ScriptFormat::Text,
+            _ => ScriptFormat::Json,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ScriptFormat::Json => "erss",
+            ScriptFormat::Ron => "ron",
+            ScriptFormat::Yaml => "yaml",
+            ScriptFormat::Text => "erst",
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> Result<Script, ScriptError> {
+        let error = |path: String, message: String| ScriptError {
+            format: *self,
+            path,
+            message,
+        };
+        match self {
+            ScriptFormat::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(text);
+                let value = serde_path_to_error::deserialize(&mut deserializer)
+                    .map_err(|err| error(err.path().to_string(), err.into_inner().to_string()))?;
+                deserializer
+                    .end()
+                    .map_err(|err| error(String::new(), err.to_string()))?;
+                Ok(value)
+            }
+            ScriptFormat::Ron => {
+                let mut deserializer = ron::Deserializer::from_str(text)
+                    .map_err(|err| error(String::new(), err.to_string()))?;
+                // Positions are only known to the deserializer, not to the errors it returns
+                let value = match serde_path_to_error::deserialize(&mut deserializer) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        let path = err.path().to_string();
+                        let message = deserializer.span_error(err.into_inner()).to_string();
+                        return Err(error(path, message));
+                    }
+                };
+                if let Err(err) = deserializer.end() {
+                    return Err(error(
+                        String::new(),
+                        deserializer.span_error(err).to_string(),
+                    ));
+                }
+                Ok(value)
+            }
+            ScriptFormat::Yaml => {
+                let deserializer = serde_yaml::Deserializer::from_str(text);
+                serde_path_to_error::deserialize(deserializer)
+                    .map_err(|err| error(err.path().to_string(), err.into_inner().to_string()))
+            }
+            ScriptFormat::Text => text::parse(text),
+        }
+    }
+
+    pub fn write(&self, script: &Script) -> Result<String, ScriptError> {
+        let error = |message: String| ScriptError {
+            format: *self,
+            path: String::new(),
+            message,
+        };
+        match self {
+            ScriptFormat::Json => {
+                serde_json::to_string(script).map_err(|err| error(err.to_string()))
+            }
+            ScriptFormat::Ron => {
+                ron::ser::to_string_pretty(script, ron::ser::PrettyConfig::default())
+                    .map_err(|err| error(err.to_string()))
+            }
+            ScriptFormat::Yaml => {
+                serde_yaml::to_string(script).map_err(|err| error(err.to_string()))
+            }
+            ScriptFormat::Text => text::write(script),
+        }
+    }
+}
+
+/// Reads a script in the format of its extension.
+pub fn read(path: &str) -> Result<Script, EngineError> {
+    let text = fs::read_to_string(path)?;
+    Ok(ScriptFormat::from_path(path).parse(&text)?)
+}
+
+/// Writes a script in the format of the extension of `path`.
+pub fn write(path: &str, script: &Script) -> Result<(), EngineError> {
+    let text = ScriptFormat::from_path(path).write(script)?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Check;
+
+    #[test]
+    fn script_formats() -> Check {
+        use crate::engine::scripts::{self, Script};
+        let script = scripts::default();
+        let expected = serde_json::to_value(&script).map_err(|err| format!("{:?}", err))?;
+        for format in ScriptFormat::list() {
+            let path = format!("script.{}", format.extension());
+            if ScriptFormat::from_path(&path) != format {
+                return Err(format!("{} is not read as {}", path, format));
+            }
+            let text = format.write(&script).map_err(|err| err.to_string())?;
+            let read: Script = format.parse(&text).map_err(|err| err.to_string())?;
+            if serde_json::to_value(&read).map_err(|err| format!("{:?}", err))? != expected {
+                return Err(format!("{} script changed when read back", format));
+            }
+        }
+        let broken = "{ \"main\": [ PushState, Render(maybe) ] }";
+        match ScriptFormat::Ron.parse(broken) {
+            Err(err) if err.path == "main[1].Render" => Ok(()),
+            Err(err) => Err(format!("error at {}: {}", err.path, err.message)),
+            Ok(_) => Err("broken script was read".to_string()),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::engine::scripts::{Function, FunctionName, Instruction, Script};
+use crate::engine::{Engine, EngineError, MAX_CALL_DEPTH};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/*
+A running script reports how far it got on a single line that is rewritten as it goes, such as
+
+    1520 / 48000 (3.2%) in erode-sweep, 0:04:12 elapsed, 1:55:40 left
+
+and, when given a file, writes the same report there as json for other programs to follow. The
+total counts the instructions the engine will tick, the calls and loops included, as if every `if`
+called its `then` function and every template read as a single instruction, so it is an estimate
+that is raised whenever the script outruns it. The time left assumes the instructions still to run
+take as long as those run so far, counting from when the report started so a resumed script does
+not take the instructions run before the checkpoint for free.
+
+The report and the text printed by the script go to the log of the engine, see `Output`, which
+prints them on the terminal unless the engine was given another one.
+ */
+
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What a running engine writes to its log.
+pub enum Output<'a> {
+    /// Text printed by the script, ended by a new line.
+    Line(&'a str),
+    /// How far the script got, see `update`.
+    Progress(&'a Report),
+    /// The script finished, after its last report.
+    Finished,
+}
+
+/// Receives the output of an engine, shared with the engines of its spawned functions.
+pub type Log = Arc<dyn Fn(Output) + Send + Sync>;
+
+/// Prints the output on the terminal, the report on a single line that is rewritten as it goes
+/// and cleared for the lines printed in between.
+pub fn terminal() -> Log {
+    let shown = AtomicBool::new(false);
+    Arc::new(move |output| match output {
+        Output::Line(text) => {
+            if shown.swap(false, Ordering::Relaxed) {
+                clear();
+            }
+            println!("{}", text);
+        }
+        Output::Progress(report) => {
+            clear();
+            print!("{}", report);
+            let _ = std::io::stdout().flush();
+            shown.store(true, Ordering::Relaxed);
+        }
+        Output::Finished => {
+            if shown.swap(false, Ordering::Relaxed) {
+                println!();
+            }
+        }
+    })
+}
+
+/// How a running engine reports its progress, see `update`.
+pub struct Progress {
+    pub file: Option<PathBuf>,
+    pub interval: Duration,
+    total: usize,
+    /// Instructions run before the report started.
+    first: usize,
+    started: Instant,
+    last: Option<Instant>,
+}
+
+impl Progress {
+    /// Progress of the engine from where it is now, written to `file` as well if there is one. A
+    /// missing or recursive function fails the script once it is called, until then the total
+    /// counts the instructions in `main` alone.
+    pub fn new(engine: &Engine, file: Option<PathBuf>) -> Self {
+        let mut counts = HashMap::new();
+        let left = count_function(&engine.script, &engine.main, 0, &mut counts)
+            .unwrap_or(engine.main.len());
+        Progress {
+            file,
+            interval: PROGRESS_INTERVAL,
+            total: engine.completed + left,
+            first: engine.completed,
+            started: Instant::now(),
+            last: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    pub completed: usize,
+    pub total: usize,
+    /// The function the next instruction belongs to.
+    pub function: FunctionName,
+    /// Seconds since the report started.
+    pub elapsed: f64,
+    /// Seconds left, unknown until an instruction finished.
+    pub eta: Option<f64>,
+}
+
+impl Report {
+    pub fn of(engine: &Engine, progress: &Progress) -> Self {
+        let completed = engine.completed;
+        // Instructions left keep the estimate from falling behind, e.g. after an `else`
+        let total = progress.total.max(completed + engine.main.len());
+        let elapsed = progress.started.elapsed().as_secs_f64();
+        let done = completed - progress.first.min(completed);
+        let eta = (done > 0).then(|| elapsed / done as f64 * (total - completed) as f64);
+        Report {
+            completed,
+            total,
+            function: engine.function().to_string(),
+            elapsed,
+            eta,
+        }
+    }
+
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.completed as f64 / self.total as f64
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} / {} ({:.1}%) in {}, {} elapsed",
+            self.completed,
+            self.total,
+            100.0 * self.fraction(),
+            self.function,
+            clock(self.elapsed)
+        )?;
+        match self.eta {
+            Some(eta) => write!(f, ", {} left", clock(eta)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Seconds as `h:mm:ss`.
+fn clock(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The instructions the engine ticks to run `name`, the call included.
+pub fn count(
+    script: &Script,
+    name: &FunctionName,
+    depth: usize,
+    counts: &mut HashMap<FunctionName, usize>,
+) -> Result<usize, EngineError> {
+    if let Some(count) = counts.get(name) {
+        return Ok(*count);
+    }
+    if depth > MAX_CALL_DEPTH {
+        return Err(EngineError::RecursiveCall(name.to_string()));
+    }
+    let function = script
+        .get(name)
+        .ok_or_else(|| EngineError::MissingFunction(name.to_string()))?;
+    let count = count_function(script, function, depth, counts)?;
+    counts.insert(name.clone(), count);
+    Ok(count)
+}
+
+fn count_function(
+    script: &Script,
+    function: &Function,
+    depth: usize,
+    counts: &mut HashMap<FunctionName, usize>,
+) -> Result<usize, EngineError> {
+    let mut total = 0;
+    for instruction in function {
+        total += 1 + match instruction {
+            Instruction::Call(callee) | Instruction::If(_, callee, _) => {
+                count(script, callee, depth + 1, counts)?
+            }
+            Instruction::Repeat(times, body) => {
+                times * count_function(script, body, depth, counts)?
+            }
+            Instruction::ForEach(values, _, body) => {
+                values.len() * (1 + count_function(script, body, depth, counts)?)
+            }
+            Instruction::Template(_) => 1,
+            _ => 0,
+        };
+    }
+    Ok(total)
+}
+
+pub fn write(report: &Report, path: &Path) -> Result<(), EngineError> {
+    fs::write(path, serde_json::to_string_pretty(report)?)?;
+    Ok(())
+}
+
+/// Rewrites the progress line and file if the interval has passed since they were last written.
+pub fn update(engine: &mut Engine) -> Result<(), EngineError> {
+    let due = match &engine.progress {
+        Some(progress) => progress
+            .last
+            .is_none_or(|last| last.elapsed() >= progress.interval),
+        None => false,
+    };
+    if due {
+        report(engine)?;
+    }
+    Ok(())
+}
+
+/// Rewrites the progress line and file and ends the line, once the script finished.
+pub fn finish(engine: &mut Engine) -> Result<(), EngineError> {
+    if engine.progress.is_some() {
+        report(engine)?;
+        (engine.log)(Output::Finished);
+    }
+    Ok(())
+}
+
+/// Clears the progress line so that text printed by the script gets a line of its own.
+fn clear() {
+    print!("\r\x1b[K");
+}
+
+fn report(engine: &mut Engine) -> Result<(), EngineError> {
+    if let Some(progress) = &engine.progress {
+        let report = Report::of(engine, progress);
+        (engine.log)(Output::Progress(&report));
+        if let Some(path) = &progress.file {
+            write(&report, path)?;
+        }
+    }
+    if let Some(progress) = &mut engine.progress {
+        progress.last = Some(Instant::now());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{temp_path, Check};
+
+    #[test]
+    fn script_progress() -> Check {
+        use crate::engine::format::ScriptFormat;
+        use crate::engine::progress::{self, Progress, Report};
+        use crate::engine::{self, scripts};
+        let text = "new procedural size=32\n\
+                    repeat 2\n    \
+                        call inner\n\
+                    end\n\
+                    for n in 1 2 3\n    \
+                        nop\n\
+                    end\n\
+                    \n\
+                    fn inner\n    \
+                        push\n    \
+                        pop\n";
+        let script = ScriptFormat::Text
+            .parse(text)
+            .map_err(|err| err.to_string())?;
+        let engine = engine::start(script.clone()).map_err(|err| format!("{:?}", err))?;
+        let progress = Progress::new(&engine, None);
+        let report = Report::of(&engine, &progress);
+        if report.total != 15 || report.function != "main" || report.eta.is_some() {
+            return Err(format!("progress at the start is {:?}", report));
+        }
+        let engine = engine::run_headless(engine).map_err(|err| format!("{:?}", err))?;
+        if engine.completed != report.total {
+            return Err(format!(
+                "{} instructions ran, {} were counted",
+                engine.completed, report.total
+            ));
+        }
+
+        let engine = engine::start(script).map_err(|err| format!("{:?}", err))?;
+        let engine =
+            scripts::call(engine, &"inner".to_string()).map_err(|err| format!("{:?}", err))?;
+        if engine.function() != "inner" {
+            return Err(format!("a called function ran in {}", engine.function()));
+        }
+        let mut engine = engine::run_headless(engine).map_err(|err| format!("{:?}", err))?;
+        if engine.function() != "main" {
+            return Err(format!("a finished script ran in {}", engine.function()));
+        }
+        let path = temp_path("progress.json");
+        engine.progress = Some(Progress::new(&engine, Some(path.clone())));
+        progress::finish(&mut engine).map_err(|err| format!("{:?}", err))?;
+        let written = std::fs::read_to_string(&path).map_err(|err| err.to_string())?;
+        let _ = std::fs::remove_file(&path);
+        let written: Report = serde_json::from_str(&written).map_err(|err| err.to_string())?;
+        if written.completed != engine.completed || written.fraction() != 1.0 {
+            return Err(format!("the progress file has {:?}", written));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn script_log() -> Check {
+        use crate::engine;
+        use crate::engine::format::ScriptFormat;
+        use crate::engine::progress::{self, Output, Progress};
+        use std::sync::{Arc, Mutex};
+        let text = "new procedural size=32\n\
+                    print hello\n\
+                    spawn worker\n\
+                    join\n\
+                    \n\
+                    fn worker\n    \
+                        print from a worker\n";
+        let script = ScriptFormat::Text
+            .parse(text)
+            .map_err(|err| err.to_string())?;
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = engine::start(script).map_err(|err| format!("{:?}", err))?;
+        let logged = Arc::clone(&lines);
+        engine.log = Arc::new(move |output| {
+            let line = match output {
+                Output::Line(text) => text.to_string(),
+                Output::Progress(report) => format!("progress {}", report.completed),
+                Output::Finished => "finished".to_string(),
+            };
+            logged.lock().unwrap().push(line);
+        });
+        let mut engine = engine::run_headless(engine).map_err(|err| format!("{:?}", err))?;
+        engine.progress = Some(Progress::new(&engine, None));
+        progress::finish(&mut engine).map_err(|err| format!("{:?}", err))?;
+        let lines = lines.lock().unwrap().clone();
+        let expected = ["hello", "from a worker", "progress 4", "finished"];
+        if lines != expected {
+            return Err(format!("logged {:?}", lines));
+        }
+        Ok(())
+    }
+}
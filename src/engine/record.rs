@@ -0,0 +1,374 @@
+use crate::engine::{EngineError, Measurement, Snapshot, Snapshots};
+use crate::erode::Model;
+use crate::heightmap::Heightmap;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/*
+Snapshots are exported for analysis in pandas or R as a table with one typed record per snapshot,
+the heightmap statistics, the erosion model and its parameters, the partitioning method, the time
+the erosion took, the flood counts around the isoline and the comparison and seam metrics. The
+columns are named as in `COLUMNS`, which are also the keys of the json records, and keep their
+names and order, new columns are only ever appended. Values a snapshot has none of, such as the
+time of a map that was never eroded or the droplet parameters of another backend, are left empty
+in csv and null in json. The parameters of every backend are in `backend_parameters` as json.
+ */
+
+pub const COLUMNS: [&str; 37] = [
+    "hash",
+    "notes",
+    "session_notes",
+    "width",
+    "height",
+    "min_height",
+    "max_height",
+    "mean_height",
+    "std_height",
+    "flatness",
+    "backend",
+    "method",
+    "num_iterations",
+    "erosion_radius",
+    "inertia",
+    "sediment_capacity_factor",
+    "min_sediment_capacity",
+    "erode_speed",
+    "deposit_speed",
+    "evaporate_speed",
+    "gravity",
+    "max_droplet_lifetime",
+    "seed",
+    "simulation_time",
+    "isoline_value",
+    "isoline_error",
+    "low_flooded",
+    "low_unflooded",
+    "high_flooded",
+    "high_unflooded",
+    "iso_error",
+    "mean_difference",
+    "rms_difference",
+    "max_difference",
+    "seam_energy",
+    "seam_ratio",
+    "backend_parameters",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Key of the heightmap in hex, equal for snapshots of the same heightmap, see `Snapshots`.
+    pub hash: String,
+    pub notes: String,
+    pub session_notes: String,
+    pub width: usize,
+    pub height: usize,
+    pub min_height: f64,
+    pub max_height: f64,
+    pub mean_height: f64,
+    pub std_height: f64,
+    pub flatness: f32,
+    /// See `Backend::name`.
+    pub backend: String,
+    /// Spec of the partitioning method, see `Method::spec`, none before the map was eroded.
+    pub method: Option<String>,
+    pub num_iterations: usize,
+    /// Droplet parameters, none unless the backend is Lague.
+    pub erosion_radius: Option<usize>,
+    pub inertia: Option<f32>,
+    pub sediment_capacity_factor: Option<f32>,
+    pub min_sediment_capacity: Option<f32>,
+    pub erode_speed: Option<f32>,
+    pub deposit_speed: Option<f32>,
+    pub evaporate_speed: Option<f32>,
+    pub gravity: Option<f32>,
+    pub max_droplet_lifetime: Option<usize>,
+    pub seed: Option<u64>,
+    /// Seconds.
+    pub simulation_time: Option<f32>,
+    pub isoline_value: f32,
+    pub isoline_error: f32,
+    pub low_flooded: Option<usize>,
+    pub low_unflooded: Option<usize>,
+    pub high_flooded: Option<usize>,
+    pub high_unflooded: Option<usize>,
+    pub iso_error: Option<usize>,
+    pub mean_difference: Option<f32>,
+    pub rms_difference: Option<f32>,
+    pub max_difference: Option<f32>,
+    pub seam_energy: Option<f32>,
+    pub seam_ratio: Option<f32>,
+    /// The parameters of the backend as json, see `Model::parameters_json`.
+    pub backend_parameters: String,
+}
+
+impl Record {
+    pub fn of(snapshot: &Snapshot, heightmap: &Heightmap) -> Result<Self, EngineError> {
+        let (tuning, measurements, hash) = snapshot;
+        let cells = heightmap.data.iter().flatten().map(|&h| h as f64);
+        let count = (heightmap.width * heightmap.height).max(1) as f64;
+        let mean = cells.clone().sum::<f64>() / count;
+        let variance = cells.clone().map(|h| (h - mean) * (h - mean)).sum::<f64>() / count;
+        let droplets = match tuning.model {
+            Model::Lague(params) => Some(params),
+            _ => None,
+        };
+        let mut record = Record {
+            hash: format!("{:016x}", hash),
+            notes: tuning.notes.clone(),
+            session_notes: tuning.session_notes.clone(),
+            width: heightmap.width,
+            height: heightmap.height,
+            min_height: cells.clone().fold(f64::INFINITY, f64::min),
+            max_height: cells.fold(f64::NEG_INFINITY, f64::max),
+            mean_height: mean,
+            std_height: variance.sqrt(),
+            flatness: tuning.flatness,
+            backend: tuning.model.backend().name().to_string(),
+            method: tuning.method.map(|method| method.spec()),
+            num_iterations: tuning.model.num_iterations(),
+            erosion_radius: droplets.map(|params| params.erosion_radius),
+            inertia: droplets.map(|params| params.inertia),
+            sediment_capacity_factor: droplets.map(|params| params.sediment_capacity_factor),
+            min_sediment_capacity: droplets.map(|params| params.min_sediment_capacity),
+            erode_speed: droplets.map(|params| params.erode_speed),
+            deposit_speed: droplets.map(|params| params.deposit_speed),
+            evaporate_speed: droplets.map(|params| params.evaporate_speed),
+            gravity: droplets.map(|params| params.gravity),
+            max_droplet_lifetime: droplets.map(|params| params.max_droplet_lifetime),
+            seed: tuning.model.seed(),
+            simulation_time: None,
+            isoline_value: tuning.isoline_value,
+            isoline_error: tuning.isoline_error,
+            low_flooded: None,
+            low_unflooded: None,
+            high_flooded: None,
+            high_unflooded: None,
+            iso_error: None,
+            mean_difference: None,
+            rms_difference: None,
+            max_difference: None,
+            seam_energy: None,
+            seam_ratio: None,
+            backend_parameters: tuning.model.parameters_json()?,
+        };
+        for measurement in measurements {
+            match *measurement {
+                Measurement::Time(seconds) => record.simulation_time = Some(seconds),
+                Measurement::LowAreas(flooded, unflooded) => {
+                    record.low_flooded = Some(flooded);
+                    record.low_unflooded = Some(unflooded);
+                }
+                Measurement::HighAreas(flooded, unflooded) => {
+                    record.high_flooded = Some(flooded);
+                    record.high_unflooded = Some(unflooded);
+                }
+                Measurement::IsoError(flooded) => record.iso_error = Some(flooded),
+                Measurement::MeanDifference(mean) => record.mean_difference = Some(mean),
+                Measurement::RmsDifference(rms) => record.rms_difference = Some(rms),
+                Measurement::MaxDifference(max) => record.max_difference = Some(max),
+                Measurement::SeamEnergy(energy) => record.seam_energy = Some(energy),
+                Measurement::SeamRatio(ratio) => record.seam_ratio = Some(ratio),
+            }
+        }
+        Ok(record)
+    }
+
+    /// The values in the order of `COLUMNS`, empty when missing.
+    pub fn values(&self) -> Vec<String> {
+        fn optional<T: ToString>(value: Option<T>) -> String {
+            value.map(|value| value.to_string()).unwrap_or_default()
+        }
+        vec![
+            self.hash.clone(),
+            self.notes.clone(),
+            self.session_notes.clone(),
+            self.width.to_string(),
+            self.height.to_string(),
+            self.min_height.to_string(),
+            self.max_height.to_string(),
+            self.mean_height.to_string(),
+            self.std_height.to_string(),
+            self.flatness.to_string(),
+            self.backend.clone(),
+            optional(self.method.as_ref()),
+            self.num_iterations.to_string(),
+            optional(self.erosion_radius),
+            optional(self.inertia),
+            optional(self.sediment_capacity_factor),
+            optional(self.min_sediment_capacity),
+            optional(self.erode_speed),
+            optional(self.deposit_speed),
+            optional(self.evaporate_speed),
+            optional(self.gravity),
+            optional(self.max_droplet_lifetime),
+            optional(self.seed),
+            optional(self.simulation_time),
+            self.isoline_value.to_string(),
+            self.isoline_error.to_string(),
+            optional(self.low_flooded),
+            optional(self.low_unflooded),
+            optional(self.high_flooded),
+            optional(self.high_unflooded),
+            optional(self.iso_error),
+            optional(self.mean_difference),
+            optional(self.rms_difference),
+            optional(self.max_difference),
+            optional(self.seam_energy),
+            optional(self.seam_ratio),
+            self.backend_parameters.clone(),
+        ]
+    }
+}
+
+/// Records of the snapshots in the order they were taken.
+pub fn records(snapshots: &Snapshots) -> Result<Vec<Record>, EngineError> {
+    snapshots
+        .entries
+        .iter()
+        .map(|snapshot| {
+            let heightmap = snapshots
+                .heightmap(snapshot)
+                .ok_or(EngineError::MissingSnapshotData)?;
+            Record::of(snapshot, heightmap)
+        })
+        .collect()
+}
+
+/// A csv field, quoted when it holds a separator, a quote or a line break.
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(records: &[Record]) -> String {
+    let mut csv = COLUMNS.join(",");
+    csv.push('\n');
+    for record in records {
+        let values: Vec<String> = record.values().iter().map(|v| field(v)).collect();
+        csv.push_str(&values.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+pub fn to_json(records: &[Record]) -> Result<String, EngineError> {
+    Ok(serde_json::to_string_pretty(records)?)
+}
+
+/// Writes the records as json if the file ends in `.json`, as csv otherwise.
+pub fn write(records: &[Record], filename: &str) -> Result<(), EngineError> {
+    let path = Path::new(filename);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let text = if json {
+        to_json(records)?
+    } else {
+        to_csv(records)
+    };
+    fs::write(path, text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_path, Check, SIZE};
+
+    #[test]
+    fn snapshot_records() -> Check {
+        use crate::engine::format::ScriptFormat;
+        use crate::engine::record::{self, Record, COLUMNS};
+        let (csv, json) = (temp_path("records.csv"), temp_path("records.json"));
+        let text = format!(
+            "new procedural size=32\n\
+             isoline\n\
+             flush\n\
+             snapshot\n\
+             snapshot export {}\n\
+             snapshot export {}\n",
+            csv.display(),
+            json.display()
+        );
+        let script = ScriptFormat::Text
+            .parse(&text)
+            .map_err(|err| err.to_string())?;
+        let engine = crate::engine::start(script).map_err(|err| format!("{:?}", err))?;
+        let engine = crate::engine::run_headless(engine).map_err(|err| format!("{:?}", err))?;
+        let records = record::records(&engine.snapshots).map_err(|err| format!("{:?}", err))?;
+        let [taken] = records.as_slice() else {
+            return Err(format!("{} records of one snapshot", records.len()));
+        };
+        if taken.values().len() != COLUMNS.len() {
+            return Err(format!(
+                "{} values for {} columns",
+                taken.values().len(),
+                COLUMNS.len()
+            ));
+        }
+        let keys = match serde_json::to_value(taken).map_err(|err| err.to_string())? {
+            serde_json::Value::Object(map) => map.keys().cloned().collect::<Vec<String>>(),
+            value => return Err(format!("a record is written as {}", value)),
+        };
+        if keys.len() != COLUMNS.len()
+            || COLUMNS
+                .iter()
+                .any(|column| !keys.contains(&column.to_string()))
+        {
+            return Err(format!("json keys {:?} differ from the columns", keys));
+        }
+        if taken.width != SIZE || taken.method.is_some() || taken.low_flooded.is_none() {
+            return Err(format!("the base snapshot was recorded as {:?}", taken));
+        }
+        if taken.min_height > taken.mean_height || taken.mean_height > taken.max_height {
+            return Err(format!("heights out of order in {:?}", taken));
+        }
+        if taken.backend != "lague" || taken.erosion_radius.is_none() {
+            return Err(format!(
+                "the droplet parameters were recorded as {:?}",
+                taken
+            ));
+        }
+        // Another backend leaves the droplet columns empty and records its own parameters
+        let mut snapshot = engine.snapshots.entries[0].clone();
+        let wind = crate::erode::wind::Parameters::default();
+        snapshot.0.model = Model::Wind(wind);
+        let heightmap = engine
+            .snapshots
+            .heightmap(&snapshot)
+            .ok_or("no heightmap")?;
+        let blown = Record::of(&snapshot, heightmap).map_err(|err| format!("{:?}", err))?;
+        let parameters: serde_json::Value =
+            serde_json::from_str(&blown.backend_parameters).map_err(|err| err.to_string())?;
+        if blown.backend != "wind"
+            || blown.erosion_radius.is_some()
+            || blown.seed != Some(wind.seed)
+            || blown.num_iterations != wind.num_iterations
+            || parameters["seed"] != wind.seed
+        {
+            return Err(format!("the wind snapshot was recorded as {:?}", blown));
+        }
+
+        let written_csv = std::fs::read_to_string(&csv).map_err(|err| err.to_string())?;
+        let written_json = std::fs::read_to_string(&json).map_err(|err| err.to_string())?;
+        let _ = std::fs::remove_file(&csv);
+        let _ = std::fs::remove_file(&json);
+        let lines: Vec<&str> = written_csv.lines().collect();
+        if lines.len() != 2 || lines[0] != COLUMNS.join(",") {
+            return Err(format!("the csv was written as\n{}", written_csv));
+        }
+        let read: Vec<Record> =
+            serde_json::from_str(&written_json).map_err(|err| err.to_string())?;
+        if read != records {
+            return Err(format!("the json was read back as {:?}", read));
+        }
+        Ok(())
+    }
+}
@@ -1,38 +1,159 @@
-use crate::engine::{Engine, EngineError};
-use crate::erode::Parameters;
-use crate::heightmap::{HeightmapParameters, HeightmapType};
-use crate::partitioning::Method;
+pub mod text;
+
+use crate::engine::progress::Output;
+#[cfg(feature = "gui")]
+use crate::engine::window;
+use crate::engine::{substitute, Engine, EngineError};
+use crate::erode::{beyer, fluvial, glacial, pipes, wind, Backend, ErosionPipeline, Parameters};
+use crate::heightmap::io::{DataFormat, ExportCrop};
+use crate::heightmap::{
+    BlendCurve, ExtendFill, HeightmapParameters, HeightmapType, ResampleFilter,
+};
+use crate::math::Margins;
+use crate::partitioning::{MarginMode, Method};
 use crate::visualize::events::{poll_ui_events, UiEvent};
+use crate::visualize::ui::Resample;
 use crate::State;
-use egui::{Pos2, Rect};
-use macroquad::prelude::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::mem;
 
 pub type Function = Vec<Instruction>;
 pub type FunctionName = String;
 pub type Script = HashMap<FunctionName, Function>;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub enum SnapshotAction {
     Take,
+    /// Prints the records of the snapshots as a csv table.
     PrintAll,
     SaveAndClear(String),
+    /// Writes the records of the snapshots as csv, or json for a `.json` file, and keeps them.
+    Export(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub enum IsolineAction {
     Queue,
     SetValue(f32),
     SetError(f32),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// A state to read from in a script, see `Engine::resolve`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub enum StateRef {
+    Current,
+    Stack(usize),  // 0 is the most recently pushed state
+    Named(String), // Stored with `StoreState`, or set with `SetName` before pushing
+}
+
+impl Display for StateRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateRef::Current => f.write_str("the current state"),
+            StateRef::Stack(depth) => write!(f, "stack entry {}", depth),
+            StateRef::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A value conditions compare, of the current state or the last snapshot taken.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Copy, Clone, PartialEq, Eq)]
+pub enum Metric {
+    /// Average height of the current heightmap.
+    AverageHeight,
+    /// Average height of the eroded heightmap minus that of the base it was eroded from.
+    AverageHeightDelta,
+    /// Number of snapshots taken since they were last saved.
+    Snapshots,
+    // Of the last snapshot, see `Measurement`
+    SimulationTime,
+    LowFlooded,
+    LowUnflooded,
+    HighFlooded,
+    HighUnflooded,
+    IsoError,
+    MeanDifference,
+    RmsDifference,
+    MaxDifference,
+    SeamEnergy,
+    SeamRatio,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    pub fn list() -> [Comparison; 6] {
+        [
+            Comparison::Less,
+            Comparison::LessOrEqual,
+            Comparison::Greater,
+            Comparison::GreaterOrEqual,
+            Comparison::Equal,
+            Comparison::NotEqual,
+        ]
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        }
+    }
+
+    pub fn holds(&self, a: f32, b: f32) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+        }
+    }
+}
+
+/// A metric compared to a value, e.g. that the average height dropped by more than 0.01.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Copy, Clone, PartialEq)]
+pub struct Condition {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub value: f32,
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} {} {}",
+            self.metric,
+            self.comparison.symbol(),
+            self.value
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub enum Instruction {
     NewState(HeightmapType),
     PushState,
     PopSetState,
+    StoreState(String),
+    LoadStateNamed(String),
     Poll,
     Flush,
     Render(bool),
@@ -49,7 +170,59 @@ pub enum Instruction {
     GridSize(usize),
     SetName(String),
     SetErosionParameters(Parameters),
+    SetErosionPreset(String),
+    SetErosionBackend(Backend),
+    SetBeyerParameters(beyer::Parameters),
+    SetPipesParameters(pipes::Parameters),
+    SetFluvialParameters(fluvial::Parameters),
+    SetWindParameters(wind::Parameters),
+    SetGlacialParameters(glacial::Parameters),
     SetAdvancedView(bool),
+    SetExportFormats(Vec<DataFormat>),
+    SetExportCrop(ExportCrop),
+    /// Cells cut off the sides of the map when eroding, `Custom` margins per side.
+    SetMargins(MarginMode),
+    RunPipeline(ErosionPipeline),
+    CompareStates {
+        a: StateRef,
+        b: StateRef,
+    },
+    /// Adds the current state scaled to `width` x `height` cells as a new base state.
+    Resample {
+        width: usize,
+        height: usize,
+        filter: ResampleFilter,
+    },
+    /// Adds the `width` x `height` cells from (`x`, `y`) of the current state as a new base state.
+    Crop {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+    /// Adds the current state with `margins` cells added to its sides as a new base state.
+    Extend {
+        margins: Margins,
+        fill: ExtendFill,
+    },
+    /// Runs the instructions `n` times.
+    Repeat(usize, Function),
+    /// Runs the instructions once per value with the variable set to it.
+    ForEach(Vec<String>, String, Function),
+    /// Sets a variable, substituted for `${name}` in the strings of the instructions that follow.
+    Set(String, String),
+    /// A line of the text format with variables in it, read when it runs so variables can stand
+    /// in for numbers too, e.g. `isoline-value ${value}`.
+    Template(String),
+    /// Stops the script with an error unless the condition holds.
+    Assert(Condition),
+    /// Calls the first function if the condition holds and the second, if any, otherwise.
+    If(Condition, FunctionName, Option<FunctionName>),
+    /// Runs the function on a thread of its own, from a copy of the current state, the stack and
+    /// the named states, without a window.
+    Spawn(FunctionName),
+    /// Waits for the spawned functions and adds their snapshots in the order they were spawned.
+    Join,
 }
 
 pub fn default() -> Script {
@@ -68,7 +241,10 @@ pub fn default() -> Script {
             Snapshot(SnapshotAction::Take),
             Nop,
             Render(false),
-            Queue(UiEvent::SelectMethod(Method::GridOverlapBlend(8))),
+            Queue(UiEvent::SelectMethod(Method::GridOverlapBlend((
+                8,
+                BlendCurve::default(),
+            )))),
             Queue(UiEvent::RunSimulation),
             Render(false),
             Flush,
@@ -81,7 +257,7 @@ pub fn default() -> Script {
     script
 }
 
-fn poll(state: &mut State) {
+pub(crate) fn poll(state: &mut State) {
     poll_ui_events(
         #[cfg(feature = "export")]
         &mut state.state_name,
@@ -90,42 +266,15 @@ fn poll(state: &mut State) {
     );
 }
 
-fn draw(state: &mut State, ui: bool) {
-    clear_background(BLACK);
-    let canvas_rect = if ui {
-        state
-            .ui_state
-            .frame_slots
-            .as_ref()
-            .and_then(|slots| slots.canvas)
-    } else {
-        None
-    }
-    .unwrap_or(Rect {
-        min: Pos2 { x: 0.0, y: 0.0 },
-        max: Pos2 {
-            x: screen_width(),
-            y: screen_height(),
-        },
-    });
-    crate::visualize::draw_frame(
-        &canvas_rect,
-        &state.app_state.simulation_state().get_active_texture(),
-    );
-
-    state.ui_state.frame_slots = if ui {
-        crate::visualize::ui::ui_draw(state)
-    } else {
-        None
-    };
-}
-
 pub fn call(mut engine: Engine, function_name: &FunctionName) -> Result<Engine, EngineError> {
     let mut function = if let Some(function) = engine.script.get(function_name) {
         function.clone()
     } else {
         return Err(EngineError::MissingFunction(function_name.to_string()));
     };
+    let base = engine.main.len();
+    engine.calls.retain(|(_, call)| *call < base);
+    engine.calls.push((function_name.clone(), base));
     engine.main.append(&mut function);
     Ok(engine)
 }
@@ -134,7 +283,22 @@ pub async fn tick(mut engine: Engine) -> Result<Engine, EngineError> {
     let state = &mut engine.state;
     let stack = &mut engine.stack;
     let result = if let Some(instruction) = engine.main.pop() {
-        match instruction {
+        match substitute(instruction, &engine.variables)? {
+            // Without a window there are no frames to draw or to hand over
+            Instruction::Render(_)
+            | Instruction::Handover
+            | Instruction::WindowSize(_)
+            | Instruction::WindowAutoSize(_)
+                if engine.headless =>
+            {
+                Ok(())
+            }
+            // Builds without the gui have no window, so every engine runs headless
+            #[cfg(not(feature = "gui"))]
+            Instruction::Render(_)
+            | Instruction::Handover
+            | Instruction::WindowSize(_)
+            | Instruction::WindowAutoSize(_) => Ok(()),
             Instruction::NewState(map_type) => {
                 let mut s = State::new(&map_type);
                 mem::swap(&mut s, state);
@@ -152,6 +316,18 @@ pub async fn tick(mut engine: Engine) -> Result<Engine, EngineError> {
                     Err(EngineError::HasNoState)
                 }
             }
+            Instruction::StoreState(name) => {
+                engine.registry.insert(name, state.clone());
+                Ok(())
+            }
+            Instruction::LoadStateNamed(name) => {
+                if let Some(s) = engine.registry.get(&name) {
+                    *state = s.clone();
+                    Ok(())
+                } else {
+                    Err(EngineError::UnknownState(StateRef::Named(name)))
+                }
+            }
             Instruction::Poll => {
                 poll(state);
                 Ok(())
@@ -162,51 +338,32 @@ pub async fn tick(mut engine: Engine) -> Result<Engine, EngineError> {
                 }
                 Ok(())
             }
+            #[cfg(feature = "gui")]
             Instruction::Render(ui) => {
-                draw(state, ui);
-                next_frame().await;
+                window::render(state, ui).await;
                 Ok(())
             }
             Instruction::Queue(event) => {
                 state.ui_state.ui_events.push(event);
                 Ok(())
             }
-            Instruction::WindowSize((w, h)) => {
-                request_new_screen_size(w, h);
+            #[cfg(feature = "gui")]
+            Instruction::WindowSize(size) => {
+                window::resize(size);
                 Ok(())
             }
-            Instruction::WindowAutoSize((w, h)) => {
-                let canvas_rect = state
-                    .ui_state
-                    .frame_slots
-                    .as_ref()
-                    .and_then(|slots| slots.canvas)
-                    .unwrap_or(Rect {
-                        min: Pos2 { x: 0.0, y: 0.0 },
-                        max: Pos2 {
-                            x: screen_width(),
-                            y: screen_height(),
-                        },
-                    });
-
-                let fit = canvas_rect.width().min(canvas_rect.height());
-                request_new_screen_size(
-                    w + canvas_rect.height() - fit,
-                    h + canvas_rect.width() - fit,
-                );
+            #[cfg(feature = "gui")]
+            Instruction::WindowAutoSize(size) => {
+                window::auto_size(state, size);
                 Ok(())
             }
+            #[cfg(feature = "gui")]
             Instruction::Handover => {
-                while !state.ui_state.application_quit && !is_quit_requested() {
-                    draw(state, true);
-                    poll(state);
-                    crate::visualize::keybinds::poll_ui_keybinds(&mut state.ui_state);
-                    next_frame().await;
-                }
+                window::handover(state).await;
                 Ok(())
             }
             Instruction::Print(s) => {
-                println!("{}", s);
+                (engine.log)(Output::Line(&s));
                 Ok(())
             }
             Instruction::Snapshot(action) => match action {
@@ -218,9 +375,11 @@ pub async fn tick(mut engine: Engine) -> Result<Engine, EngineError> {
                     }
                 }
                 SnapshotAction::PrintAll => {
-                    println!("{:?}", engine.snapshots_to_string()?);
+                    let csv = engine.snapshots_to_string()?;
+                    (engine.log)(Output::Line(csv.trim_end_matches('\n')));
                     Ok(())
                 }
+                SnapshotAction::Export(filename) => engine.export_records(&filename),
                 SnapshotAction::SaveAndClear(filename) => {
                     engine.export_snapshots(&filename)?;
                     engine.snapshots.clear();
@@ -267,10 +426,135 @@ pub async fn tick(mut engine: Engine) -> Result<Engine, EngineError> {
                 state.app_state.parameters.erosion_params = params;
                 Ok(())
             }
+            Instruction::SetErosionPreset(name) => {
+                state.app_state.parameters.erosion_params =
+                    Parameters::preset(&name).ok_or(EngineError::UnknownPreset(name))?;
+                Ok(())
+            }
+            Instruction::SetErosionBackend(backend) => {
+                state.app_state.parameters.backend = backend;
+                Ok(())
+            }
+            Instruction::SetBeyerParameters(params) => {
+                state.app_state.parameters.beyer_params = params;
+                Ok(())
+            }
+            Instruction::SetPipesParameters(params) => {
+                state.app_state.parameters.pipes_params = params;
+                Ok(())
+            }
+            Instruction::SetFluvialParameters(params) => {
+                state.app_state.parameters.fluvial_params = params;
+                Ok(())
+            }
+            Instruction::SetWindParameters(params) => {
+                state.app_state.parameters.wind_params = params;
+                Ok(())
+            }
+            Instruction::SetGlacialParameters(params) => {
+                state.app_state.parameters.glacial_params = params;
+                Ok(())
+            }
             Instruction::SetAdvancedView(mode) => {
                 state.ui_state.isoline.advanced_texture = mode;
                 Ok(())
             }
+            Instruction::SetExportFormats(formats) => {
+                state.ui_state.export_formats = formats;
+                Ok(())
+            }
+            Instruction::SetExportCrop(crop) => {
+                state.ui_state.export_crop = crop;
+                Ok(())
+            }
+            Instruction::SetMargins(margin) => {
+                state.app_state.parameters.set_margin_mode(margin);
+                Ok(())
+            }
+            Instruction::RunPipeline(pipeline) => {
+                state.app_state.parameters.pipeline = pipeline;
+                state.ui_state.ui_events.push(UiEvent::RunPipeline);
+                Ok(())
+            }
+            Instruction::CompareStates { a, b } => engine.compare(&a, &b),
+            Instruction::Resample {
+                width,
+                height,
+                filter,
+            } => {
+                state.ui_state.resample = Resample {
+                    width,
+                    height,
+                    filter,
+                };
+                state.ui_state.ui_events.push(UiEvent::Resample);
+                Ok(())
+            }
+            Instruction::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                state.ui_state.ui_events.push(UiEvent::Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                });
+                Ok(())
+            }
+            Instruction::Extend { margins, fill } => {
+                state
+                    .ui_state
+                    .ui_events
+                    .push(UiEvent::Extend(margins, fill));
+                Ok(())
+            }
+            Instruction::Repeat(times, body) => {
+                for _ in 0..times {
+                    engine.main.extend(body.iter().rev().cloned());
+                }
+                Ok(())
+            }
+            Instruction::ForEach(values, variable, body) => {
+                for value in values.into_iter().rev() {
+                    engine.main.extend(body.iter().rev().cloned());
+                    engine.main.push(Instruction::Set(variable.clone(), value));
+                }
+                Ok(())
+            }
+            Instruction::Set(variable, value) => {
+                engine.variables.insert(variable, value);
+                Ok(())
+            }
+            Instruction::Template(line) => {
+                let instructions = text::line(&line)?;
+                engine.main.extend(instructions.into_iter().rev());
+                Ok(())
+            }
+            Instruction::Assert(condition) => {
+                let value = engine.metric(condition.metric)?;
+                if condition.comparison.holds(value, condition.value) {
+                    Ok(())
+                } else {
+                    Err(EngineError::AssertionFailed(format!(
+                        "{} does not hold, it is {}",
+                        condition, value
+                    )))
+                }
+            }
+            Instruction::Spawn(name) => engine.spawn(&name),
+            Instruction::Join => engine.join(),
+            Instruction::If(condition, then, otherwise) => {
+                let value = engine.metric(condition.metric)?;
+                if condition.comparison.holds(value, condition.value) {
+                    engine = call(engine, &then)?;
+                } else if let Some(otherwise) = otherwise {
+                    engine = call(engine, &otherwise)?;
+                }
+                Ok(())
+            }
         }
     } else {
         return Err(EngineError::HasNoInstruction);
@@ -279,6 +563,7 @@ pub async fn tick(mut engine: Engine) -> Result<Engine, EngineError> {
     if let Some(err) = result.err() {
         Err(err)
     } else {
+        engine.completed += 1;
         Ok(engine)
     }
 }
@@ -0,0 +1,975 @@
+use crate::engine::format::{ScriptError, ScriptFormat};
+use crate::engine::scripts::{
+    Comparison, Condition, Function, FunctionName, Instruction, IsolineAction, Metric, Script,
+    SnapshotAction, StateRef,
+};
+use crate::heightmap::io::DataFormat;
+use crate::heightmap::{HeightmapParameters, HeightmapType, ProceduralHeightmapSettings};
+use crate::partitioning::{MarginMode, Method};
+use crate::visualize::events::UiEvent;
+use serde::de::DeserializeOwned;
+use std::iter::Peekable;
+use std::mem;
+use std::path::PathBuf;
+use std::str::{Chars, FromStr};
+
+/*
+The text format writes a script one instruction per line, such as
+
+    new procedural size=512 seed=7
+    erode method=subdivision grid=8
+    save foo
+
+and loops as blocks closed by `end`, `repeat 3` runs its lines three times and
+`for seed in 1 2 3` once per value with `${seed}` in the lines replaced by the value. Lines with
+variables in them are kept as templates and read when they run, so variables can stand in for
+numbers as well as names, `set seed 7` sets one outside of a loop. `assert` stops the script
+unless a condition on the state or the last snapshot holds, e.g. `assert average-height-delta < 0`,
+and `if seam-ratio > 1.5 then smooth else keep` calls one of two functions. `spawn branch` runs a
+function on a thread of its own from a copy of the current state, and `join` waits for the spawned
+functions and adds their snapshots.
+
+Lines before the first `fn <name>` line belong to `main`, the lines after it to that function,
+and lines starting with `#` are comments. Words are split on whitespace, words with spaces or
+quotes in them are written in double quotes with `\"`, `\\` and `\n` escapes, and `key=value`
+words set the optional settings of a command. `print` and `queue` take the rest of the line, the
+text to print and the event in RON. Instructions without a command of their own, such as the
+parameters of the erosion models, are written in RON on a line of their own, which is told apart
+from the commands by its capital first letter. Errors name the line that failed to parse.
+ */
+
+const COMMANDS: &[&str] = &[
+    "new",
+    "push",
+    "pop",
+    "store",
+    "load",
+    "poll",
+    "flush",
+    "render",
+    "queue",
+    "window",
+    "window-auto",
+    "handover",
+    "print",
+    "snapshot",
+    "save",
+    "nop",
+    "call",
+    "isoline",
+    "isoline-value",
+    "isoline-error",
+    "size",
+    "grid",
+    "name",
+    "preset",
+    "backend",
+    "advanced",
+    "formats",
+    "margin",
+    "method",
+    "erode",
+    "compare",
+    "set",
+    "repeat",
+    "for",
+    "end",
+    "assert",
+    "if",
+    "spawn",
+    "join",
+];
+
+const METRICS: &[(&str, Metric)] = &[
+    ("average-height", Metric::AverageHeight),
+    ("average-height-delta", Metric::AverageHeightDelta),
+    ("snapshots", Metric::Snapshots),
+    ("simulation-time", Metric::SimulationTime),
+    ("low-flooded", Metric::LowFlooded),
+    ("low-unflooded", Metric::LowUnflooded),
+    ("high-flooded", Metric::HighFlooded),
+    ("high-unflooded", Metric::HighUnflooded),
+    ("iso-error", Metric::IsoError),
+    ("mean-difference", Metric::MeanDifference),
+    ("rms-difference", Metric::RmsDifference),
+    ("max-difference", Metric::MaxDifference),
+    ("seam-energy", Metric::SeamEnergy),
+    ("seam-ratio", Metric::SeamRatio),
+];
+
+/// A word of a line, `key=value` words carry their key.
+struct Word {
+    key: Option<String>,
+    text: String,
+}
+
+/// The words of a command after its name, taken one by one while the command is read.
+struct Args<'a> {
+    command: &'a str,
+    positional: Vec<String>,
+    keyed: Vec<(String, String)>,
+}
+
+impl<'a> Args<'a> {
+    fn new(command: &'a str, words: Vec<Word>) -> Self {
+        let mut positional = Vec::new();
+        let mut keyed = Vec::new();
+        for word in words {
+            match word.key {
+                Some(key) => keyed.push((key, word.text)),
+                None => positional.push(word.text),
+            }
+        }
+        positional.reverse();
+        Args {
+            command,
+            positional,
+            keyed,
+        }
+    }
+
+    fn optional(&mut self) -> Option<String> {
+        self.positional.pop()
+    }
+
+    fn next(&mut self, what: &str) -> Result<String, String> {
+        self.optional()
+            .ok_or_else(|| format!("{} needs {}", self.command, what))
+    }
+
+    fn number<T: FromStr>(&mut self, what: &str) -> Result<T, String> {
+        let text = self.next(what)?;
+        number(what, &text)
+    }
+
+    fn key<T: FromStr>(&mut self, key: &str) -> Result<Option<T>, String> {
+        self.key_with(key, |text| number(key, text))
+    }
+
+    fn key_with<T>(
+        &mut self,
+        key: &str,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, String> {
+        match self.keyed.iter().position(|(name, _)| name == key) {
+            Some(i) => parse(&self.keyed.remove(i).1).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Fails on the words no part of the command took.
+    fn finish(self) -> Result<(), String> {
+        if let Some(word) = self.positional.last() {
+            return Err(format!("unexpected {} after {}", word, self.command));
+        }
+        if let Some((key, _)) = self.keyed.first() {
+            return Err(format!("{} has no setting {}", self.command, key));
+        }
+        Ok(())
+    }
+}
+
+fn number<T: FromStr>(what: &str, text: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("invalid {} {}", what, text))
+}
+
+fn ron_value<T: DeserializeOwned>(what: &str, text: &str) -> Result<T, String> {
+    ron::from_str(text).map_err(|err| format!("invalid {} {}: {}", what, text, err.code))
+}
+
+/// Reads a quoted string after its opening quote.
+fn unquote(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(text),
+            Some('\\') => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some(c @ ('"' | '\\')) => text.push(c),
+                Some(c) => return Err(format!("unknown escape \\{}", c)),
+                None => return Err("unterminated quote".to_string()),
+            },
+            Some(c) => text.push(c),
+            None => return Err("unterminated quote".to_string()),
+        }
+    }
+}
+
+fn split(line: &str) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(words);
+        }
+        let mut word = Word {
+            key: None,
+            text: String::new(),
+        };
+        let mut quoted = false;
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '"' => {
+                    quoted = true;
+                    word.text.push_str(&unquote(&mut chars)?);
+                }
+                '=' if word.key.is_none() && !quoted && is_key(&word.text) => {
+                    word.key = Some(mem::take(&mut word.text));
+                }
+                c => word.text.push(c),
+            }
+        }
+        words.push(word);
+    }
+}
+
+fn is_key(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The word as it is written, quoted when it would not be read back as it is.
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\\' | '='));
+    if plain {
+        return word.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in word.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn state_ref(text: &str) -> Result<StateRef, String> {
+    if text == "current" {
+        return Ok(StateRef::Current);
+    }
+    match text.strip_prefix("stack:") {
+        Some(depth) => number("stack depth", depth).map(StateRef::Stack),
+        None => Ok(StateRef::Named(text.to_string())),
+    }
+}
+
+/// A condition written as `<metric> <comparison> <value>`, such as `average-height-delta < 0`.
+fn condition(args: &mut Args) -> Result<Condition, String> {
+    let name = args.next("a metric")?;
+    let metric = METRICS
+        .iter()
+        .find(|(metric, _)| *metric == name)
+        .map(|(_, metric)| *metric)
+        .ok_or_else(|| {
+            let names: Vec<&str> = METRICS.iter().map(|(name, _)| *name).collect();
+            format!(
+                "unknown metric {}, expected one of {}",
+                name,
+                names.join(", ")
+            )
+        })?;
+    let symbol = args.next("a comparison")?;
+    let comparison = Comparison::list()
+        .into_iter()
+        .find(|comparison| comparison.symbol() == symbol)
+        .ok_or_else(|| {
+            format!(
+                "unknown comparison {}, expected <, <=, >, >=, == or !=",
+                symbol
+            )
+        })?;
+    Ok(Condition {
+        metric,
+        comparison,
+        value: args.number("a value")?,
+    })
+}
+
+fn condition_words(condition: &Condition) -> String {
+    let name = METRICS
+        .iter()
+        .find(|(_, metric)| *metric == condition.metric)
+        .map(|(name, _)| *name)
+        .unwrap_or_default();
+    format!(
+        "{} {} {}",
+        name,
+        condition.comparison.symbol(),
+        condition.value
+    )
+}
+
+fn heightmap_parameters(args: &mut Args) -> Result<HeightmapParameters, String> {
+    let mut params = HeightmapParameters::default();
+    if let Some(size) = args.key("size")? {
+        params.size = size;
+    }
+    params.height = args.key("height")?;
+    Ok(params)
+}
+
+fn new_state(args: &mut Args) -> Result<HeightmapType, String> {
+    match args.next("procedural or file")?.as_str() {
+        "procedural" => {
+            let params = heightmap_parameters(args)?;
+            let mut settings = ProceduralHeightmapSettings::default();
+            if let Some(seed) = args.key("seed")? {
+                settings.seed = seed;
+            }
+            if let Some(noise) = args.key_with("noise", |text| ron_value("noise", text))? {
+                settings.noise_type = noise;
+            }
+            if let Some(fractal) = args.key_with("fractal", |text| ron_value("fractal", text))? {
+                settings.fractal_type = fractal;
+            }
+            if let Some(octaves) = args.key("octaves")? {
+                settings.fractal_octaves = octaves;
+            }
+            if let Some(gain) = args.key("gain")? {
+                settings.fractal_gain = gain;
+            }
+            if let Some(lacunarity) = args.key("lacunarity")? {
+                settings.fractal_lacunarity = lacunarity;
+            }
+            if let Some(frequency) = args.key("frequency")? {
+                settings.frequency = frequency;
+            }
+            Ok(HeightmapType::Procedural(params, settings))
+        }
+        "file" => {
+            let path = PathBuf::from(args.next("a path")?);
+            Ok(HeightmapType::FromFile(heightmap_parameters(args)?, path))
+        }
+        kind => Err(format!(
+            "new takes procedural or file, not {}, other heightmaps are written as NewState(...)",
+            kind
+        )),
+    }
+}
+
+/// The text after the command of `print`, which is either the text itself or one quoted string.
+fn text(rest: &str) -> Result<String, String> {
+    if !rest.starts_with('"') {
+        return Ok(rest.to_string());
+    }
+    match &split(rest)?[..] {
+        [Word { key: None, text }] => Ok(text.clone()),
+        _ => Err("print takes plain text or a single quoted string".to_string()),
+    }
+}
+
+/// The instructions of one line, `erode` becomes a method and a simulation.
+fn instructions(line: &str) -> Result<Vec<Instruction>, String> {
+    use Instruction::*;
+    if line.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return ron::from_str(line)
+            .map(|instruction| vec![instruction])
+            .map_err(|err| err.to_string());
+    }
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim_start();
+    match command {
+        "print" => return Ok(vec![Print(text(rest)?)]),
+        "queue" => return Ok(vec![Queue(ron_value("event", rest)?)]),
+        _ => (),
+    }
+
+    let mut args = Args::new(command, split(rest)?);
+    let instructions = match command {
+        "new" => vec![NewState(new_state(&mut args)?)],
+        "push" => vec![PushState],
+        "pop" => vec![PopSetState],
+        "store" => vec![StoreState(args.next("a name")?)],
+        "load" => vec![LoadStateNamed(args.next("a name")?)],
+        "poll" => vec![Poll],
+        "flush" => vec![Flush],
+        "render" => match args.optional().as_deref() {
+            None => vec![Render(false)],
+            Some("ui") => vec![Render(true)],
+            Some(word) => return Err(format!("render takes ui or nothing, not {}", word)),
+        },
+        "window" | "window-auto" => {
+            let size = (args.number("a width")?, args.number("a height")?);
+            match command {
+                "window" => vec![WindowSize(size)],
+                _ => vec![WindowAutoSize(size)],
+            }
+        }
+        "handover" => vec![Handover],
+        "snapshot" => match args.optional().as_deref() {
+            None => vec![Snapshot(SnapshotAction::Take)],
+            Some("print") => vec![Snapshot(SnapshotAction::PrintAll)],
+            Some("export") => vec![Snapshot(SnapshotAction::Export(args.next("a file name")?))],
+            Some(word) => {
+                return Err(format!(
+                    "snapshot takes print, export or nothing, not {}",
+                    word
+                ))
+            }
+        },
+        "save" => vec![Snapshot(SnapshotAction::SaveAndClear(
+            args.next("a file name")?,
+        ))],
+        "nop" => vec![Nop],
+        "call" => vec![Call(args.next("a function")?)],
+        "spawn" => vec![Spawn(args.next("a function")?)],
+        "join" => vec![Join],
+        "isoline" => vec![Isoline(IsolineAction::Queue)],
+        "isoline-value" => vec![Isoline(IsolineAction::SetValue(args.number("a height")?))],
+        "isoline-error" => vec![Isoline(IsolineAction::SetError(args.number("an error")?))],
+        "size" => vec![Size(args.number("a size")?)],
+        "grid" => vec![GridSize(args.number("a grid size")?)],
+        "name" => vec![SetName(args.next("a name")?)],
+        "preset" => vec![SetErosionPreset(args.next("a preset")?)],
+        "backend" => vec![SetErosionBackend(ron_value(
+            "backend",
+            &args.next("a backend")?,
+        )?)],
+        "advanced" => match args.next("on or off")?.as_str() {
+            "on" | "true" => vec![SetAdvancedView(true)],
+            "off" | "false" => vec![SetAdvancedView(false)],
+            word => return Err(format!("advanced takes on or off, not {}", word)),
+        },
+        "formats" => {
+            let mut formats = Vec::new();
+            while let Some(format) = args.optional() {
+                formats.push(ron_value::<DataFormat>("format", &format)?);
+            }
+            vec![SetExportFormats(formats)]
+        }
+        "margin" => vec![SetMargins(MarginMode::parse(&args.next("a margin")?)?)],
+        "method" => vec![Queue(UiEvent::SelectMethod(Method::parse(
+            &args.next("a method")?,
+        )?))],
+        "erode" => {
+            let method = args.key_with("method", Method::parse)?;
+            let grid = args.key("grid")?;
+            let mut instructions = match (method, grid) {
+                (Some(mut method), Some(grid)) => {
+                    method.set_grid_size_unchecked(grid);
+                    vec![Queue(UiEvent::SelectMethod(method))]
+                }
+                (Some(method), None) => vec![Queue(UiEvent::SelectMethod(method))],
+                (None, Some(grid)) => vec![GridSize(grid)],
+                (None, None) => Vec::new(),
+            };
+            instructions.push(Queue(UiEvent::RunSimulation));
+            instructions
+        }
+        "set" => vec![Set(args.next("a variable")?, args.next("a value")?)],
+        "assert" => vec![Assert(condition(&mut args)?)],
+        "if" => {
+            let condition = condition(&mut args)?;
+            if args.next("then")? != "then" {
+                return Err("if is written as if <condition> then <fn> [else <fn>]".to_string());
+            }
+            let then = args.next("a function")?;
+            let otherwise = match args.optional().as_deref() {
+                None => None,
+                Some("else") => Some(args.next("a function")?),
+                Some(word) => return Err(format!("expected else, not {}", word)),
+            };
+            vec![If(condition, then, otherwise)]
+        }
+        "compare" => vec![CompareStates {
+            a: state_ref(&args.next("two states")?)?,
+            b: state_ref(&args.next("two states")?)?,
+        }],
+        _ => {
+            return Err(format!(
+                "unknown command {}, expected one of {} or an instruction in RON",
+                command,
+                COMMANDS.join(", ")
+            ))
+        }
+    };
+    args.finish()?;
+    Ok(instructions)
+}
+
+/// The instructions of a line run on its own, such as a template once its variables are filled in.
+pub fn line(line: &str) -> Result<Vec<Instruction>, ScriptError> {
+    instructions(line.trim()).map_err(|message| ScriptError {
+        format: ScriptFormat::Text,
+        path: String::new(),
+        message,
+    })
+}
+
+/// A `repeat` or `for` waiting for its `end`.
+struct Block {
+    line: usize,
+    variable: Option<String>,
+    values: Vec<String>,
+    times: usize,
+    body: Function,
+}
+
+impl Block {
+    fn start(line: usize, command: &str, rest: &str) -> Result<Block, String> {
+        let mut args = Args::new(command, split(rest)?);
+        let mut block = Block {
+            line,
+            variable: None,
+            values: Vec::new(),
+            times: 0,
+            body: Function::new(),
+        };
+        if command == "repeat" {
+            block.times = args.number("a count")?;
+        } else {
+            block.variable = Some(args.next("a variable")?);
+            if args.next("in")? != "in" {
+                return Err("for is written as for <variable> in <values>".to_string());
+            }
+            while let Some(value) = args.optional() {
+                block.values.push(value);
+            }
+        }
+        args.finish()?;
+        Ok(block)
+    }
+
+    fn end(self) -> Instruction {
+        match self.variable {
+            Some(variable) => Instruction::ForEach(self.values, variable, self.body),
+            None => Instruction::Repeat(self.times, self.body),
+        }
+    }
+}
+
+/// Reads a script written in the text format.
+pub fn parse(text: &str) -> Result<Script, ScriptError> {
+    let error = |line: usize, message: String| ScriptError {
+        format: ScriptFormat::Text,
+        path: format!("line {}", line + 1),
+        message,
+    };
+    let mut script = Script::new();
+    let mut name: FunctionName = "main".to_string();
+    let mut function = Function::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    // Set once `fn` names the function, `main` is only kept without it when it has instructions
+    let mut declared = false;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match command {
+            "repeat" | "for" => {
+                blocks.push(Block::start(i, command, rest).map_err(|message| error(i, message))?);
+                continue;
+            }
+            "end" => {
+                let block = blocks
+                    .pop()
+                    .ok_or_else(|| error(i, "end without a repeat or for".to_string()))?;
+                match blocks.last_mut() {
+                    Some(outer) => outer.body.push(block.end()),
+                    None => function.push(block.end()),
+                }
+                continue;
+            }
+            "fn" => (),
+            _ => {
+                // Variables may stand in for any word, so the line is read once they are known
+                let instructions = if line.contains("${") {
+                    vec![Instruction::Template(line.to_string())]
+                } else {
+                    instructions(line).map_err(|message| error(i, message))?
+                };
+                match blocks.last_mut() {
+                    Some(block) => block.body.extend(instructions),
+                    None => function.extend(instructions),
+                }
+                continue;
+            }
+        }
+        if let Some(block) = blocks.last() {
+            return Err(error(i, format!("line {} has no end", block.line + 1)));
+        }
+        let next = match &split(rest).map_err(|message| error(i, message))?[..] {
+            [Word { key: None, text }] => text.clone(),
+            _ => return Err(error(i, "fn takes the name of the function".to_string())),
+        };
+        let kept = declared || !function.is_empty();
+        if script.contains_key(&next) || (kept && next == name) {
+            return Err(error(i, format!("{} is defined twice", next)));
+        }
+        if kept {
+            script.insert(mem::replace(&mut name, next), mem::take(&mut function));
+        } else {
+            name = next;
+        }
+        declared = true;
+    }
+    if let Some(block) = blocks.last() {
+        return Err(error(block.line, "missing its end".to_string()));
+    }
+    if declared || !function.is_empty() {
+        script.insert(name, function);
+    }
+    Ok(script)
+}
+
+/// The line of an instruction with a command of its own.
+fn command(instruction: &Instruction) -> Option<String> {
+    use Instruction::*;
+    let line = match instruction {
+        NewState(HeightmapType::Procedural(params, settings)) => {
+            let default = ProceduralHeightmapSettings::default();
+            let mut line = format!("new procedural{}", size_keys(params));
+            let keys = [
+                (
+                    "seed",
+                    settings.seed != default.seed,
+                    settings.seed.to_string(),
+                ),
+                (
+                    "noise",
+                    settings.noise_type != default.noise_type,
+                    ron::to_string(&settings.noise_type).ok()?,
+                ),
+                (
+                    "fractal",
+                    settings.fractal_type != default.fractal_type,
+                    ron::to_string(&settings.fractal_type).ok()?,
+                ),
+                (
+                    "octaves",
+                    settings.fractal_octaves != default.fractal_octaves,
+                    settings.fractal_octaves.to_string(),
+                ),
+                (
+                    "gain",
+                    settings.fractal_gain != default.fractal_gain,
+                    settings.fractal_gain.to_string(),
+                ),
+                (
+                    "lacunarity",
+                    settings.fractal_lacunarity != default.fractal_lacunarity,
+                    settings.fractal_lacunarity.to_string(),
+                ),
+                (
+                    "frequency",
+                    settings.frequency != default.frequency,
+                    settings.frequency.to_string(),
+                ),
+            ];
+            for (key, _, value) in keys.iter().filter(|(_, changed, _)| *changed) {
+                line.push_str(&format!(" {}={}", key, value));
+            }
+            line
+        }
+        NewState(HeightmapType::FromFile(params, path)) => {
+            format!("new file {}{}", quote(path.to_str()?), size_keys(params))
+        }
+        PushState => "push".to_string(),
+        PopSetState => "pop".to_string(),
+        StoreState(name) => format!("store {}", quote(name)),
+        LoadStateNamed(name) => format!("load {}", quote(name)),
+        Poll => "poll".to_string(),
+        Flush => "flush".to_string(),
+        Render(false) => "render".to_string(),
+        Render(true) => "render ui".to_string(),
+        Queue(UiEvent::SelectMethod(method)) => format!("method {}", spec(method)?),
+        Queue(UiEvent::RunSimulation) => "erode".to_string(),
+        Queue(event) => format!("queue {}", ron::to_string(event).ok()?),
+        WindowSize((width, height)) => format!("window {} {}", width, height),
+        WindowAutoSize((width, height)) => format!("window-auto {} {}", width, height),
+        Handover => "handover".to_string(),
+        Print(text) => {
+            let plain = !text.is_empty()
+                && text.trim() == text
+                && !text.starts_with('"')
+                && !text.contains('\n');
+            if plain {
+                format!("print {}", text)
+            } else {
+                format!("print {}", quote(text))
+            }
+        }
+        Snapshot(SnapshotAction::Take) => "snapshot".to_string(),
+        Snapshot(SnapshotAction::PrintAll) => "snapshot print".to_string(),
+        Snapshot(SnapshotAction::Export(file)) => format!("snapshot export {}", quote(file)),
+        Snapshot(SnapshotAction::SaveAndClear(file)) => format!("save {}", quote(file)),
+        Nop => "nop".to_string(),
+        Call(name) => format!("call {}", quote(name)),
+        Spawn(name) => format!("spawn {}", quote(name)),
+        Join => "join".to_string(),
+        Isoline(IsolineAction::Queue) => "isoline".to_string(),
+        Isoline(IsolineAction::SetValue(value)) => format!("isoline-value {}", value),
+        Isoline(IsolineAction::SetError(error)) => format!("isoline-error {}", error),
+        Size(size) => format!("size {}", size),
+        GridSize(size) => format!("grid {}", size),
+        SetName(name) => format!("name {}", quote(name)),
+        SetErosionPreset(name) => format!("preset {}", quote(name)),
+        SetErosionBackend(backend) => format!("backend {}", ron::to_string(backend).ok()?),
+        SetAdvancedView(true) => "advanced on".to_string(),
+        SetAdvancedView(false) => "advanced off".to_string(),
+        SetExportFormats(formats) => {
+            let mut line = "formats".to_string();
+            for format in formats {
+                line.push(' ');
+                line.push_str(&ron::to_string(format).ok()?);
+            }
+            line
+        }
+        SetMargins(margin) if MarginMode::parse(&margin.spec()) == Ok(*margin) => {
+            format!("margin {}", margin.spec())
+        }
+        CompareStates { a, b } => format!("compare {} {}", state_word(a)?, state_word(b)?),
+        Set(variable, value) => format!("set {} {}", quote(variable), quote(value)),
+        Template(line) if template(line) => line.clone(),
+        Assert(condition) => format!("assert {}", condition_words(condition)),
+        If(condition, then, otherwise) => {
+            let mut line = format!("if {} then {}", condition_words(condition), quote(then));
+            if let Some(otherwise) = otherwise {
+                line.push_str(&format!(" else {}", quote(otherwise)));
+            }
+            line
+        }
+        _ => return None,
+    };
+    Some(line)
+}
+
+fn size_keys(params: &HeightmapParameters) -> String {
+    let mut keys = String::new();
+    if params.size != HeightmapParameters::default().size {
+        keys.push_str(&format!(" size={}", params.size));
+    }
+    if let Some(height) = params.height {
+        keys.push_str(&format!(" height={}", height));
+    }
+    keys
+}
+
+/// The spec of a method that reads back as the same method, see `Method::spec`.
+fn spec(method: &Method) -> Option<String> {
+    let spec = method.spec();
+    (Method::parse(&spec) == Ok(*method)).then_some(spec)
+}
+
+fn state_word(state: &StateRef) -> Option<String> {
+    match state {
+        StateRef::Current => Some("current".to_string()),
+        StateRef::Stack(depth) => Some(format!("stack:{}", depth)),
+        StateRef::Named(name) if name == "current" || name.starts_with("stack:") => None,
+        StateRef::Named(name) => Some(quote(name)),
+    }
+}
+
+/// Whether the line is read back as a template of itself.
+fn template(line: &str) -> bool {
+    let command = line.split_whitespace().next().unwrap_or_default();
+    line.contains("${")
+        && line.trim() == line
+        && !line.contains('\n')
+        && !line.starts_with('#')
+        && !matches!(command, "fn" | "end" | "repeat" | "for")
+}
+
+fn write_function(function: &Function, indent: &str, text: &mut String) -> Result<(), String> {
+    let mut instructions = function.iter().peekable();
+    while let Some(instruction) = instructions.next() {
+        let erode = match (instruction, instructions.peek()) {
+            (
+                Instruction::Queue(UiEvent::SelectMethod(method)),
+                Some(Instruction::Queue(UiEvent::RunSimulation)),
+            ) => spec(method),
+            _ => None,
+        };
+        let line = match (erode, instruction) {
+            (Some(spec), _) => {
+                instructions.next();
+                format!("erode method={}", spec)
+            }
+            (None, Instruction::Repeat(times, body)) => {
+                text.push_str(&format!("{}repeat {}\n", indent, times));
+                write_function(body, &format!("{}    ", indent), text)?;
+                "end".to_string()
+            }
+            (None, Instruction::ForEach(values, variable, body)) => {
+                text.push_str(&format!("{}for {} in", indent, quote(variable)));
+                for value in values {
+                    text.push(' ');
+                    text.push_str(&quote(value));
+                }
+                text.push('\n');
+                write_function(body, &format!("{}    ", indent), text)?;
+                "end".to_string()
+            }
+            (None, instruction) => match command(instruction) {
+                Some(line) => line,
+                None => ron::to_string(instruction).map_err(|err| err.to_string())?,
+            },
+        };
+        text.push_str(indent);
+        text.push_str(&line);
+        text.push('\n');
+    }
+    Ok(())
+}
+
+/// Writes a script in the text format, `main` first and the other functions by name.
+pub fn write(script: &Script) -> Result<String, ScriptError> {
+    let error = |message: String| ScriptError {
+        format: ScriptFormat::Text,
+        path: String::new(),
+        message,
+    };
+    let mut text = String::new();
+    if let Some(main) = script.get("main") {
+        if main.is_empty() {
+            text.push_str("fn main\n");
+        }
+        write_function(main, "", &mut text).map_err(error)?;
+    }
+    let mut names: Vec<&FunctionName> = script.keys().filter(|name| *name != "main").collect();
+    names.sort();
+    for name in names {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("fn {}\n", quote(name)));
+        write_function(&script[name], "    ", &mut text).map_err(error)?;
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Check;
+
+    #[test]
+    fn script_text() -> Check {
+        use crate::engine::format::ScriptFormat;
+        use crate::engine::scripts::{Instruction, SnapshotAction};
+        use crate::engine::{self, EngineError};
+        use crate::heightmap::HeightmapType;
+        use crate::visualize::events::UiEvent;
+        let text = "# Erode a small map twice\n\
+                    new procedural size=64 seed=7\n\
+                    print \"two  spaces\"\n\
+                    call twice\n\
+                    save foo\n\
+                    \n\
+                    fn twice\n    \
+                        erode method=subdivision grid=8\n    \
+                        erode\n";
+        let script = ScriptFormat::Text
+            .parse(text)
+            .map_err(|err| err.to_string())?;
+        match &script["main"][..] {
+            [Instruction::NewState(HeightmapType::Procedural(params, settings)), Instruction::Print(print), Instruction::Call(_), Instruction::Snapshot(SnapshotAction::SaveAndClear(file))]
+                if params.size == 64
+                    && settings.seed == 7
+                    && print == "two  spaces"
+                    && file == "foo" => {}
+            main => return Err(format!("main was read as {:?}", main)),
+        }
+        match &script["twice"][..] {
+            [Instruction::Queue(UiEvent::SelectMethod(Method::Subdivision(8))), Instruction::Queue(UiEvent::RunSimulation), Instruction::Queue(UiEvent::RunSimulation)] =>
+                {}
+            twice => return Err(format!("twice was read as {:?}", twice)),
+        }
+        let instructions = engine::check(&script).map_err(|err| format!("{:?}", err))?;
+        if instructions.len() != 6 {
+            return Err(format!(
+                "{} instructions after expanding calls",
+                instructions.len()
+            ));
+        }
+
+        match ScriptFormat::Text.parse("new procedural\nerode grid=many\n") {
+            Err(err) if err.to_string() == "invalid text script at line 2: invalid grid many" => {}
+            Err(err) => return Err(format!("unexpected error {}", err)),
+            Ok(_) => return Err("an invalid grid size was read".to_string()),
+        }
+        let missing = ScriptFormat::Text.parse("new procedural\ncall nowhere\n");
+        match missing.map(|script| engine::check(&script)) {
+            Ok(Err(EngineError::MissingFunction(name))) if name == "nowhere" => Ok(()),
+            result => Err(format!("a call to a missing function gave {:?}", result)),
+        }
+    }
+
+    #[test]
+    fn script_loops() -> Check {
+        use crate::engine::format::ScriptFormat;
+        use crate::engine::scripts::{Instruction, IsolineAction};
+        use crate::engine::{self, EngineError, Variables};
+        let text = "new procedural size=64\n\
+                    repeat 2\n    \
+                        erode\n\
+                    end\n\
+                    for value in 0.25 0.5\n    \
+                        isoline-value ${value}\n    \
+                        name iso-${value}\n\
+                    end\n";
+        let script = ScriptFormat::Text
+            .parse(text)
+            .map_err(|err| err.to_string())?;
+        let written = ScriptFormat::Text
+            .write(&script)
+            .map_err(|err| err.to_string())?;
+        let read = ScriptFormat::Text
+            .parse(&written)
+            .map_err(|err| err.to_string())?;
+        if serde_json::to_value(&read).ok() != serde_json::to_value(&script).ok() {
+            return Err(format!("loops changed when written as\n{}", written));
+        }
+        let instructions = engine::check(&script).map_err(|err| format!("{:?}", err))?;
+        if instructions.len() != 9 {
+            return Err(format!(
+                "{} instructions after expanding loops",
+                instructions.len()
+            ));
+        }
+
+        let mut variables = Variables::new();
+        variables.insert("value".to_string(), "0.5".to_string());
+        let template = Instruction::Template("isoline-value ${value}".to_string());
+        let line = match engine::substitute(template, &variables) {
+            Ok(Instruction::Template(line)) => line,
+            result => return Err(format!("template substituted as {:?}", result)),
+        };
+        match &super::line(&line).map_err(|err| err.to_string())?[..] {
+            [Instruction::Isoline(IsolineAction::SetValue(value))] if *value == 0.5 => {}
+            read => return Err(format!("template read as {:?}", read)),
+        }
+        match engine::substitute(Instruction::SetName("iso-${value}".to_string()), &variables) {
+            Ok(Instruction::SetName(name)) if name == "iso-0.5" => {}
+            result => return Err(format!("name substituted as {:?}", result)),
+        }
+
+        let unknown = ScriptFormat::Text
+            .parse("new procedural\nname iso-${missing}\n")
+            .map_err(|err| err.to_string())?;
+        match engine::check(&unknown) {
+            Err(EngineError::UnknownVariable(name)) if name == "missing" => {}
+            result => return Err(format!("an unknown variable gave {:?}", result)),
+        }
+        match ScriptFormat::Text.parse("new procedural\nrepeat 2\nerode\n") {
+            Err(err) if err.path == "line 2" => Ok(()),
+            result => Err(format!(
+                "a repeat without end gave {:?}",
+                result.map(|_| ())
+            )),
+        }
+    }
+}
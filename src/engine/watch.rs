@@ -0,0 +1,211 @@
+use crate::engine::format;
+use crate::engine::progress::{Log, Output};
+use crate::engine::scripts::{tick, Function, Instruction, Script};
+use crate::engine::window::{self, next_frame};
+use crate::engine::{self, Engine, EngineError, Registry, Snapshots, Stack, Variables};
+use crate::State;
+use std::fs;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/*
+Watch mode runs a script like `--engine` does and runs it again whenever the file changes. The
+script may be JSON, RON, YAML or text, see `format`. Calls are expanded up front so that a run is a flat
+list of instructions, the engine state is checkpointed after every instruction and a new run
+resumes from the last checkpoint within the prefix it shares with the previous run. Skipped instructions are not executed again, so side
+effects such as prints or saved snapshot archives only happen when the instruction is new.
+ */
+
+/// Engine state after an instruction, to resume from when the script changes after it.
+struct Checkpoint {
+    state: State,
+    stack: Stack,
+    registry: Registry,
+    snapshots: Snapshots,
+    variables: Variables,
+}
+
+fn read_script(path: &str) -> Result<(Script, Function), EngineError> {
+    let script = format::read(path)?;
+    let instructions = engine::expand(&script)?;
+    Ok((script, instructions))
+}
+
+/// The serialized instruction, with the functions an `If` or `Spawn` may run as part of it.
+fn key(script: &Script, instruction: &Instruction) -> Result<String, EngineError> {
+    let mut key = serde_json::to_string(instruction)?;
+    let names = match instruction {
+        Instruction::If(_, then, otherwise) => std::iter::once(then).chain(otherwise).collect(),
+        Instruction::Spawn(name) => vec![name],
+        _ => Vec::new(),
+    };
+    for name in names {
+        let mut branch = Vec::new();
+        engine::flatten(script, name, 1, &mut branch)?;
+        key.push_str(&serde_json::to_string(&branch)?);
+    }
+    Ok(key)
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Instructions are compared by their serialized form since not all of them implement `PartialEq`.
+fn common_prefix(previous: &[String], instructions: &[String]) -> usize {
+    previous
+        .iter()
+        .zip(instructions.iter())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// Runs `instructions` from the last checkpoint on, recording a checkpoint per instruction.
+/// Returns the final state, or `None` when the file changed mid run or the run failed.
+async fn run(
+    path: &str,
+    version: Option<SystemTime>,
+    script: &Script,
+    instructions: &[Instruction],
+    checkpoints: &mut Vec<Checkpoint>,
+    log: &Log,
+) -> Option<State> {
+    let resumed = checkpoints.last()?;
+    let mut remaining: Function = instructions[checkpoints.len()..].to_vec();
+    remaining.reverse();
+    // Functions are kept reversed like `main`, for the calls of `If`
+    let mut functions = script.clone();
+    for function in functions.values_mut() {
+        function.reverse();
+    }
+    let mut flat = remaining.len();
+    let mut engine = Engine {
+        state: resumed.state.clone(),
+        main: remaining,
+        script: functions,
+        stack: resumed.stack.clone(),
+        registry: resumed.registry.clone(),
+        snapshots: resumed.snapshots.clone(),
+        variables: resumed.variables.clone(),
+        completed: checkpoints.len(),
+        checkpoints: None,
+        calls: Vec::new(),
+        progress: None,
+        log: Arc::clone(log),
+        headless: false,
+        workers: Vec::new(),
+    };
+
+    while engine.ready() {
+        if modified(path) != version {
+            return None;
+        }
+        engine = match tick(engine).await {
+            Ok(engine) => engine,
+            Err(err) => {
+                log(Output::Line(&format!(
+                    "Script failed at instruction {}. Reason: {:?}",
+                    checkpoints.len(),
+                    err
+                )));
+                return None;
+            }
+        };
+        // Calls and templates add instructions, the checkpoint follows once they have all run
+        if engine.main.len() >= flat {
+            continue;
+        }
+        flat = engine.main.len();
+        checkpoints.push(Checkpoint {
+            state: engine.state.clone(),
+            stack: engine.stack.clone(),
+            registry: engine.registry.clone(),
+            snapshots: engine.snapshots.clone(),
+            variables: engine.variables.clone(),
+        });
+    }
+    Some(engine.state)
+}
+
+/// Runs the script at `path` and runs it again on every change until the window is closed.
+pub async fn watch(path: &str, log: Log) -> Result<(), EngineError> {
+    window::prevent_quit();
+    let mut checkpoints: Vec<Checkpoint> = Vec::new();
+    let mut previous: Vec<String> = Vec::new();
+    let mut version = None;
+    let mut display: Option<State> = None;
+
+    loop {
+        let current = modified(path);
+        if current != version {
+            version = current;
+            match read_script(path) {
+                Ok((script, instructions)) => {
+                    let serialized = instructions
+                        .iter()
+                        .map(|instruction| key(&script, instruction))
+                        .collect::<Result<Vec<String>, _>>()?;
+                    let shared = common_prefix(&previous, &serialized);
+                    checkpoints.truncate(shared);
+                    if checkpoints.is_empty() {
+                        if let Some(Instruction::NewState(map_type)) = instructions.first() {
+                            checkpoints.push(Checkpoint {
+                                state: State::new(map_type),
+                                stack: Vec::new(),
+                                registry: Registry::new(),
+                                snapshots: Snapshots::default(),
+                                variables: Variables::new(),
+                            });
+                        }
+                    }
+                    previous = serialized;
+                    log(Output::Line(&format!(
+                        "Running {} ({} of {} instructions cached)",
+                        path,
+                        checkpoints.len(),
+                        instructions.len()
+                    )));
+                    if let Some(state) = run(
+                        path,
+                        version,
+                        &script,
+                        &instructions,
+                        &mut checkpoints,
+                        &log,
+                    )
+                    .await
+                    {
+                        log(Output::Line(&format!(
+                            "Done, watching {} for changes",
+                            path
+                        )));
+                        display = Some(state);
+                    } else {
+                        // Keep the cache consistent with what actually ran
+                        previous.truncate(checkpoints.len());
+                        display = checkpoints
+                            .last()
+                            .map(|checkpoint| checkpoint.state.clone());
+                    }
+                }
+                Err(EngineError::ScriptError(err)) => {
+                    log(Output::Line(&format!("Failed to load {}, {}", path, err)))
+                }
+                Err(err) => log(Output::Line(&format!(
+                    "Failed to load {}. Reason: {:?}",
+                    path, err
+                ))),
+            }
+        }
+
+        if let Some(state) = &mut display {
+            if window::quit_requested(state) {
+                return Ok(());
+            }
+            window::interact(state);
+        } else if window::closed() {
+            return Ok(());
+        }
+        next_frame().await;
+    }
+}
@@ -0,0 +1,99 @@
+use crate::engine::scripts::poll;
+use crate::State;
+use egui::{Pos2, Rect};
+use macroquad::prelude::*;
+
+pub use macroquad::window::next_frame;
+
+/*
+Everything the engine does with the window is here, drawing the state, handing it over to the user
+and sizing the window. The rest of the engine only changes the state and its queued ui events, so
+a headless engine, see `Engine::headless`, skips these instructions and never touches the window.
+ */
+
+/// Keeps the window open when closed until the engine decides to quit, see `quit_requested`.
+pub fn prevent_quit() {
+    macroquad::prelude::prevent_quit();
+}
+
+/// Whether the user closed the window.
+pub fn closed() -> bool {
+    is_quit_requested()
+}
+
+/// Whether the user closed the window or quit from the ui.
+pub fn quit_requested(state: &State) -> bool {
+    state.ui_state.application_quit || closed()
+}
+
+/// The part of the window the heightmap is drawn in, all of it before the ui laid out a canvas.
+fn canvas(state: &State, ui: bool) -> Rect {
+    if ui {
+        state
+            .ui_state
+            .frame_slots
+            .as_ref()
+            .and_then(|slots| slots.canvas)
+    } else {
+        None
+    }
+    .unwrap_or(Rect {
+        min: Pos2 { x: 0.0, y: 0.0 },
+        max: Pos2 {
+            x: screen_width(),
+            y: screen_height(),
+        },
+    })
+}
+
+pub(crate) fn draw(state: &mut State, ui: bool) {
+    clear_background(BLACK);
+    let canvas_rect = canvas(state, ui);
+    let texture = state.ui_state.textures.get(
+        &state
+            .app_state
+            .simulation_state()
+            .get_active_heightmap_texture(),
+    );
+    crate::visualize::draw_frame(&canvas_rect, &texture, &state.ui_state.view);
+
+    state.ui_state.frame_slots = if ui {
+        crate::visualize::ui::ui_draw(state)
+    } else {
+        None
+    };
+}
+
+/// Draws the state with the ui and handles the events and keys of the frame.
+pub(crate) fn interact(state: &mut State) {
+    draw(state, true);
+    poll(state);
+    crate::visualize::keybinds::poll_ui_keybinds(&mut state.ui_state);
+}
+
+pub(crate) async fn render(state: &mut State, ui: bool) {
+    draw(state, ui);
+    next_frame().await;
+}
+
+/// Lets the user interact with the state until the window is closed.
+pub(crate) async fn handover(state: &mut State) {
+    while !quit_requested(state) {
+        interact(state);
+        next_frame().await;
+    }
+}
+
+pub(crate) fn resize((width, height): (f32, f32)) {
+    request_new_screen_size(width, height);
+}
+
+/// Resizes the window so that the canvas of the last frame becomes `width` by `height`.
+pub(crate) fn auto_size(state: &State, (width, height): (f32, f32)) {
+    let canvas_rect = canvas(state, true);
+    let fit = canvas_rect.width().min(canvas_rect.height());
+    request_new_screen_size(
+        width + canvas_rect.height() - fit,
+        height + canvas_rect.width() - fit,
+    );
+}
@@ -1,59 +1,568 @@
+pub mod beyer;
+pub mod coastal;
+pub mod fluvial;
+pub mod glacial;
+pub mod lague;
+pub mod pipeline;
+pub mod pipes;
+pub mod space;
+pub mod wind;
+
 use crate::heightmap::*;
-use crate::math::Vector2;
-use rand::{thread_rng, Rng};
+use crate::math::{Extent, Margins, UVector2, Vector2};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub use lague::Parameters;
+pub use pipeline::ErosionPipeline;
+pub use space::ParameterSpace;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Backend {
+    Lague,
+    Beyer,
+    Pipes,
+    Fluvial,
+    Wind,
+    Glacial,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Lague => f.write_str("Hydraulic (Droplets)"),
+            Backend::Beyer => f.write_str("Hydraulic (Beyer)"),
+            Backend::Pipes => f.write_str("Hydraulic (Pipes)"),
+            Backend::Fluvial => f.write_str("Fluvial (Stream Power)"),
+            Backend::Wind => f.write_str("Aeolian (Wind)"),
+            Backend::Glacial => f.write_str("Glacial (Ice Flow)"),
+        }
+    }
+}
+
+impl Backend {
+    /// Name that stays the same across releases, unlike the label, for exports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Lague => "lague",
+            Backend::Beyer => "beyer",
+            Backend::Pipes => "pipes",
+            Backend::Fluvial => "fluvial",
+            Backend::Wind => "wind",
+            Backend::Glacial => "glacial",
+        }
+    }
+
+    pub fn list() -> [Backend; 6] {
+        [
+            Backend::Lague,
+            Backend::Beyer,
+            Backend::Pipes,
+            Backend::Fluvial,
+            Backend::Wind,
+            Backend::Glacial,
+        ]
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Model {
+    Lague(Parameters),
+    Beyer(beyer::Parameters),
+    Pipes(pipes::Parameters),
+    Fluvial(fluvial::Parameters),
+    Wind(wind::Parameters),
+    Glacial(glacial::Parameters),
+}
+
+impl Model {
+    pub fn backend(&self) -> Backend {
+        match self {
+            Model::Lague(_) => Backend::Lague,
+            Model::Beyer(_) => Backend::Beyer,
+            Model::Pipes(_) => Backend::Pipes,
+            Model::Fluvial(_) => Backend::Fluvial,
+            Model::Wind(_) => Backend::Wind,
+            Model::Glacial(_) => Backend::Glacial,
+        }
+    }
+
+    pub fn num_iterations(&self) -> usize {
+        match self {
+            Model::Lague(params) => params.num_iterations,
+            Model::Beyer(params) => params.num_iterations,
+            Model::Pipes(params) => params.num_iterations,
+            Model::Fluvial(params) => params.num_iterations,
+            Model::Wind(params) => params.num_iterations,
+            Model::Glacial(params) => params.num_iterations,
+        }
+    }
+
+    /// The seed of backends that take one.
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            Model::Lague(params) => params.seed,
+            Model::Wind(params) => Some(params.seed),
+            _ => None,
+        }
+    }
+
+    /// The parameters of the backend alone, as in scripts.
+    pub fn parameters_json(&self) -> serde_json::Result<String> {
+        match self {
+            Model::Lague(params) => serde_json::to_string(params),
+            Model::Beyer(params) => serde_json::to_string(params),
+            Model::Pipes(params) => serde_json::to_string(params),
+            Model::Fluvial(params) => serde_json::to_string(params),
+            Model::Wind(params) => serde_json::to_string(params),
+            Model::Glacial(params) => serde_json::to_string(params),
+        }
+    }
+
+    /// Records the backend and its parameters in the heightmap metadata.
+    pub fn add_metadata(&self, heightmap: &mut Heightmap) {
+        heightmap.metadata_add("BACKEND", self.backend().name().to_string());
+        match self {
+            Model::Lague(params) => lague::add_metadata(params, heightmap),
+            Model::Beyer(params) => beyer::add_metadata(params, heightmap),
+            Model::Pipes(params) => pipes::add_metadata(params, heightmap),
+            Model::Fluvial(params) => fluvial::add_metadata(params, heightmap),
+            Model::Wind(params) => wind::add_metadata(params, heightmap),
+            Model::Glacial(params) => glacial::add_metadata(params, heightmap),
+        }
+    }
+
+    /// Returns the model with its iteration budget replaced by `iterations`.
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        match self {
+            Model::Lague(ref mut params) => params.num_iterations = iterations,
+            Model::Beyer(ref mut params) => params.num_iterations = iterations,
+            Model::Pipes(ref mut params) => params.num_iterations = iterations,
+            Model::Fluvial(ref mut params) => params.num_iterations = iterations,
+            Model::Wind(ref mut params) => params.num_iterations = iterations,
+            Model::Glacial(ref mut params) => params.num_iterations = iterations,
+        }
+        self
+    }
+
+    /// Whether droplets leaving an edge enter on the opposite side.
+    pub fn wraps(&self) -> bool {
+        match self {
+            Model::Lague(params) => params.wrap,
+            _ => false,
+        }
+    }
+
+    /// Whether iterations are independent samples that partitions can share out between them.
+    pub fn splits_iterations(&self) -> bool {
+        // Time steps are not independent samples, every partition has to run all of them
+        !matches!(
+            self,
+            Model::Pipes(_) | Model::Fluvial(_) | Model::Glacial(_)
+        )
+    }
+
+    /// Returns the model with its iteration budget split evenly across `parts` partitions.
+    pub fn divide_iterations(self, parts: usize) -> Self {
+        if self.splits_iterations() {
+            let iterations = self.num_iterations() / parts;
+            self.with_iterations(iterations)
+        } else {
+            self
+        }
+    }
+
+    pub fn erode(
+        &self,
+        heightmap: &mut Heightmap,
+        drop_zone: &DropZone,
+        progress: &Progress,
+    ) -> ErosionOutputs {
+        if drop_zone.is_empty() {
+            return ErosionOutputs::empty(heightmap);
+        }
+        match self {
+            // Only the pass itself runs in 64 bits, partitions are rounded back to 32 bits after it
+            Model::Lague(params) if params.double_precision => {
+                let mut precise = heightmap.to_precision::<f64>();
+                let outputs = lague::erode(&mut precise, params, drop_zone, progress);
+                *heightmap = precise.to_precision();
+                outputs
+            }
+            Model::Lague(params) => lague::erode(heightmap, params, drop_zone, progress),
+            Model::Beyer(params) => beyer::erode(heightmap, params, drop_zone, progress),
+            Model::Pipes(params) => pipes::erode(heightmap, params, drop_zone, progress),
+            Model::Fluvial(params) => fluvial::erode(heightmap, params, drop_zone, progress),
+            Model::Wind(params) => wind::erode(heightmap, params, drop_zone, progress),
+            Model::Glacial(params) => glacial::erode(heightmap, params, drop_zone, progress),
+        }
+    }
+}
+
+/// Shared between the simulation and the UI thread to report progress and request cancellation.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    done: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+    pub fn add_total(&self, iterations: usize) {
+        self.total.fetch_add(iterations, Ordering::Relaxed);
+    }
+
+    /// Counts one finished iteration, returns false once the simulation should stop.
+    pub fn tick(&self) -> bool {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        !self.is_cancelled()
+    }
+
+    /// Shares the iteration counter and cancellation but not the total, for callers that have
+    /// already accounted for all iterations up front.
+    pub fn with_own_total(&self) -> Progress {
+        Progress {
+            done: Arc::clone(&self.done),
+            total: Arc::default(),
+            cancelled: Arc::clone(&self.cancelled),
+        }
+    }
+
+    /// Iterations finished so far.
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            0.0
+        } else {
+            (self.done.load(Ordering::Relaxed) as f32 / total as f32).min(1.0)
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub struct Parameters {
-    pub erosion_radius: usize,         // [2, 8], 3
-    pub inertia: f32,                  // [0, 1], 0.05
-    pub sediment_capacity_factor: f32, // 4
-    pub min_sediment_capacity: f32,    // 0.01
-    pub erode_speed: f32,              // [0, 1], 0.3
-    pub deposit_speed: f32,            // [0, 1], 0.3
-    pub evaporate_speed: f32,          // [0, 1], 0.1
-    pub gravity: f32,                  // 4
-    pub max_droplet_lifetime: usize,   // 30
-    pub initial_water_volume: f32,     // 1
-    pub initial_speed: f32,            // 1
-    pub num_iterations: usize,         // 1
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Data gathered while eroding that is not part of the resulting heightmap.
+#[derive(Debug, Clone)]
+pub struct ErosionOutputs {
+    pub width: usize,
+    pub height: usize,
+    pub flow: Option<HeightmapData>,
+    pub eroded: Option<HeightmapData>,
+    pub deposited: Option<HeightmapData>,
+    /// Droplets spawned and died in each cell, for the droplet based backends.
+    pub spawns: Option<HeightmapData>,
+    pub deaths: Option<HeightmapData>,
+    /// Every partition in the order they were merged.
+    pub partitions: Vec<PartitionReport>,
+}
+
+/// Where a partition was eroded, with how many iterations and how long it took.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionReport {
+    pub anchor: UVector2,
+    pub size: UVector2,
+    pub iterations: usize,
+    /// Wall time.
+    pub seconds: f32,
 }
 
-impl Default for Parameters {
-    fn default() -> Self {
-        Parameters {
-            erosion_radius: 3,
-            inertia: 0.05,
-            sediment_capacity_factor: 4.0,
-            min_sediment_capacity: 0.01,
-            erode_speed: 0.3,
-            deposit_speed: 0.3,
-            evaporate_speed: 0.1,
-            gravity: 4.0,
-            max_droplet_lifetime: 30,
-            initial_water_volume: 1.0,
-            initial_speed: 1.0,
-            num_iterations: 1_000_000,
+impl PartitionReport {
+    /// Partitions eroded more than once in the same place, such as over the steps of an
+    /// incremental simulation, added up, in the order they were first eroded.
+    pub fn combine(reports: &[PartitionReport]) -> Vec<PartitionReport> {
+        let mut combined: Vec<PartitionReport> = Vec::new();
+        for report in reports {
+            match combined
+                .iter_mut()
+                .find(|c| c.anchor == report.anchor && c.size == report.size)
+            {
+                Some(c) => {
+                    c.iterations += report.iterations;
+                    c.seconds += report.seconds;
+                }
+                None => combined.push(*report),
+            }
+        }
+        combined
+    }
+
+    /// The part of the partition inside the `size` area at `anchor`, relative to it.
+    fn crop(self, anchor: (usize, usize), size: (usize, usize)) -> Self {
+        let start = |a: usize, offset: usize, size: usize| a.saturating_sub(offset).min(size);
+        let min = (
+            start(self.anchor.x, anchor.0, size.0),
+            start(self.anchor.y, anchor.1, size.1),
+        );
+        let max = (
+            start(self.anchor.x + self.size.x, anchor.0, size.0),
+            start(self.anchor.y + self.size.y, anchor.1, size.1),
+        );
+        PartitionReport {
+            anchor: UVector2::new(min.0, min.1),
+            size: UVector2::new(max.0 - min.0, max.1 - min.1),
+            ..self
         }
     }
 }
 
+fn merge_data(
+    target: &mut Option<HeightmapData>,
+    source: &Option<HeightmapData>,
+    size: (usize, usize),
+    anchor: &UVector2,
+) {
+    if let Some(source) = source {
+        let target = target.get_or_insert_with(|| vec![vec![0.0; size.1]; size.0]);
+        for (x, col) in source.iter().enumerate() {
+            for (y, value) in col.iter().enumerate() {
+                target[x + anchor.x][y + anchor.y] += value;
+            }
+        }
+    }
+}
+
+fn cell(coordinate: f32, size: usize) -> usize {
+    (coordinate.max(0.0) as usize).min(size - 1)
+}
+
+/// The splitmix64 mixing function, a fixed scramble of every bit of `x`.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn crop_data(
+    data: Option<HeightmapData>,
+    anchor: (usize, usize),
+    size: (usize, usize),
+) -> Option<HeightmapData> {
+    data.map(|data| {
+        data[anchor.0..anchor.0 + size.0]
+            .iter()
+            .map(|col| col[anchor.1..anchor.1 + size.1].to_vec())
+            .collect()
+    })
+}
+
+impl ErosionOutputs {
+    pub fn new<P: Precision>(heightmap: &Heightmap<P>, record_flow: bool) -> Self {
+        let empty = || Some(vec![vec![0.0; heightmap.height]; heightmap.width]);
+        ErosionOutputs {
+            width: heightmap.width,
+            height: heightmap.height,
+            flow: if record_flow { empty() } else { None },
+            eroded: empty(),
+            deposited: empty(),
+            spawns: None,
+            deaths: None,
+            partitions: Vec::new(),
+        }
+    }
+
+    /// Also counts where droplets spawn and die.
+    pub fn with_droplets(mut self) -> Self {
+        let empty = || Some(vec![vec![0.0; self.height]; self.width]);
+        self.spawns = empty();
+        self.deaths = empty();
+        self
+    }
+
+    /// Outputs without any recorded data, partitions are merged into these.
+    pub fn empty(heightmap: &Heightmap) -> Self {
+        ErosionOutputs {
+            width: heightmap.width,
+            height: heightmap.height,
+            flow: None,
+            eroded: None,
+            deposited: None,
+            spawns: None,
+            deaths: None,
+            partitions: Vec::new(),
+        }
+    }
+
+    pub fn record_flow(&mut self, x: usize, y: usize, water: HeightmapPrecision) {
+        if let Some(flow) = &mut self.flow {
+            flow[x][y] += water;
+        }
+    }
+
+    pub fn record_erosion(&mut self, x: usize, y: usize, amount: HeightmapPrecision) {
+        if let Some(eroded) = &mut self.eroded {
+            eroded[x][y] += amount;
+        }
+    }
+
+    pub fn record_deposition(&mut self, x: usize, y: usize, amount: HeightmapPrecision) {
+        if let Some(deposited) = &mut self.deposited {
+            deposited[x][y] += amount;
+        }
+    }
+
+    /// Counts a droplet spawned at (`x`, `y`) in cells.
+    pub fn record_spawn(&mut self, x: f32, y: f32) {
+        let (width, height) = (self.width, self.height);
+        if let Some(spawns) = &mut self.spawns {
+            spawns[cell(x, width)][cell(y, height)] += 1.0;
+        }
+    }
+
+    /// Counts a droplet that died at (`x`, `y`) in cells, positions off the map count at the edge.
+    pub fn record_death(&mut self, x: f32, y: f32) {
+        let (width, height) = (self.width, self.height);
+        if let Some(deaths) = &mut self.deaths {
+            deaths[cell(x, width)][cell(y, height)] += 1.0;
+        }
+    }
+
+    /// Adds the outputs of a partition anchored at `anchor` into these outputs.
+    pub fn merge(&mut self, other: &ErosionOutputs, anchor: &UVector2) {
+        let size = (self.width, self.height);
+        merge_data(&mut self.flow, &other.flow, size, anchor);
+        merge_data(&mut self.eroded, &other.eroded, size, anchor);
+        merge_data(&mut self.deposited, &other.deposited, size, anchor);
+        merge_data(&mut self.spawns, &other.spawns, size, anchor);
+        merge_data(&mut self.deaths, &other.deaths, size, anchor);
+        self.partitions
+            .extend(other.partitions.iter().map(|partition| PartitionReport {
+                anchor: partition.anchor + *anchor,
+                ..*partition
+            }));
+    }
+
+    pub fn with_margin(self, margin: Margins) -> Self {
+        let width = self.width - margin.horizontal();
+        let height = self.height - margin.vertical();
+        let anchor = margin.anchor().to_tuple();
+        ErosionOutputs {
+            width,
+            height,
+            flow: crop_data(self.flow, anchor, (width, height)),
+            eroded: crop_data(self.eroded, anchor, (width, height)),
+            deposited: crop_data(self.deposited, anchor, (width, height)),
+            spawns: crop_data(self.spawns, anchor, (width, height)),
+            deaths: crop_data(self.deaths, anchor, (width, height)),
+            partitions: self
+                .partitions
+                .into_iter()
+                .map(|partition| partition.crop(anchor, (width, height)))
+                .collect(),
+        }
+    }
+
+    /// Flow is heavily skewed towards rivers, so it is log scaled into [0, 1] for display.
+    pub fn flow_map(&self) -> Option<Heightmap> {
+        let flow = self.flow.as_ref()?;
+        let data: HeightmapData = flow
+            .iter()
+            .map(|col| col.iter().map(|water| water.ln_1p()).collect())
+            .collect();
+        let max = data
+            .iter()
+            .flat_map(|col| col.iter())
+            .fold(0.0f32, |max, &v| max.max(v));
+        let data = if max > 0.0 {
+            data.iter()
+                .map(|col| col.iter().map(|v| v / max).collect())
+                .collect()
+        } else {
+            data
+        };
+        Some(self.to_heightmap(data))
+    }
+
+    /// Total material removed from each cell, in heightmap units.
+    pub fn erosion_map(&self) -> Option<Heightmap> {
+        Some(self.to_heightmap(self.eroded.clone()?))
+    }
+
+    /// Total material deposited on each cell, in heightmap units.
+    pub fn deposition_map(&self) -> Option<Heightmap> {
+        Some(self.to_heightmap(self.deposited.clone()?))
+    }
+
+    /// Droplets spawned in each cell.
+    pub fn spawn_map(&self) -> Option<Heightmap> {
+        Some(self.to_heightmap(self.spawns.clone()?))
+    }
+
+    /// Droplets that died in each cell.
+    pub fn death_map(&self) -> Option<Heightmap> {
+        Some(self.to_heightmap(self.deaths.clone()?))
+    }
+
+    fn to_heightmap(&self, data: HeightmapData) -> Heightmap {
+        Heightmap::new(data, self.width, self.height, 1.0, 1.0, None)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DropZoneValidator {
     None,
     Circle(f32),
+    Disc(Vector2, f32),   // center and radius in cells, for eroding around a point
+    Mask(Box<Heightmap>), // [0, 1] per cell, the chance that a drop there is accepted
 }
 
 impl DropZoneValidator {
-    pub fn validate(&self, heightmap: &Heightmap, drop: &Vector2) -> bool {
+    /// Whether a drop at `drop` is accepted, masks roll `rng` so seeded erosion stays repeatable.
+    pub fn validate<P: Precision, R: rand::Rng>(
+        &self,
+        heightmap: &Heightmap<P>,
+        drop: &Vector2,
+        rng: &mut R,
+    ) -> bool {
+        match self {
+            DropZoneValidator::Mask(_) => rng.gen::<f32>() < self.weight(heightmap, drop),
+            _ => self.weight(heightmap, drop) > 0.0,
+        }
+    }
+
+    /// How much of the drops or rain at `drop` is let through, in [0, 1].
+    pub fn weight<P: Precision>(&self, heightmap: &Heightmap<P>, drop: &Vector2) -> f32 {
         match self {
-            DropZoneValidator::None => true,
+            DropZoneValidator::None => 1.0,
             DropZoneValidator::Circle(radius) => {
                 let width = heightmap.width as f32;
                 let height = heightmap.height as f32;
-                ((drop.x - width / 2.0).powf(2.0) + (drop.y - height / 2.0).powf(2.0)).sqrt()
+                let inside = ((drop.x - width / 2.0).powf(2.0) + (drop.y - height / 2.0).powf(2.0))
+                    .sqrt()
                     / (width / 2.0)
-                    <= *radius
+                    <= *radius;
+                if inside {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            DropZoneValidator::Disc(center, radius) => {
+                let inside =
+                    ((drop.x - center.x).powi(2) + (drop.y - center.y).powi(2)).sqrt() <= *radius;
+                if inside {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            DropZoneValidator::Mask(mask) => {
+                let x = (drop.x / heightmap.width as f32 * mask.width as f32) as usize;
+                let y = (drop.y / heightmap.height as f32 * mask.height as f32) as usize;
+                mask.data[x.min(mask.width - 1)][y.min(mask.height - 1)].clamp(0.0, 1.0)
             }
         }
     }
@@ -64,6 +573,60 @@ pub struct DropZone {
     _min: Vector2,
     _max: Vector2,
     validator: DropZoneValidator,
+    #[serde(default)]
+    precipitation: Option<Box<Heightmap>>, // [0, 1] per cell, relative rainfall
+    // Set for the duration of a simulation from the app parameters
+    #[serde(skip)]
+    sea: Option<coastal::Parameters>,
+    // Set for the duration of a simulation from the base state, see `with_halo`
+    #[serde(skip)]
+    halo: usize,
+    // Cells along the edges where drops never spawn, the halo of the partition this is cropped to
+    #[serde(skip)]
+    border: Margins,
+    // Where the zone lies on the whole map and the size of it, set once the zone is cropped
+    #[serde(skip)]
+    origin: UVector2,
+    #[serde(skip)]
+    map_size: Option<Extent>,
+}
+
+fn crop_heightmap(
+    heightmap: &Heightmap,
+    anchor: &UVector2,
+    width: usize,
+    height: usize,
+) -> Heightmap {
+    let data = heightmap.data[anchor.x..anchor.x + width]
+        .iter()
+        .map(|column| column[anchor.y..anchor.y + height].to_vec())
+        .collect();
+    Heightmap::new(data, width, height, 1.0, 1.0, None)
+}
+
+/// Picks drop positions in proportion to the rainfall of each cell.
+pub struct RainSampler {
+    cumulative: Vec<f32>,
+    width: usize,
+    height: usize,
+}
+
+impl RainSampler {
+    /// Returns a position within `[0, width - 1)` x `[0, height - 1)` like uniform spawning does.
+    pub fn sample<R: rand::Rng>(&self, rng: &mut R) -> Vector2 {
+        let total = *self.cumulative.last().unwrap();
+        let target = rng.gen::<f32>() * total;
+        let index = self
+            .cumulative
+            .partition_point(|&sum| sum <= target)
+            .min(self.cumulative.len() - 1);
+        let x = (index / self.height).min(self.width.saturating_sub(2));
+        let y = (index % self.height).min(self.height.saturating_sub(2));
+        Vector2 {
+            x: x as f32 + rng.gen::<f32>(),
+            y: y as f32 + rng.gen::<f32>(),
+        }
+    }
 }
 
 impl DropZone {
@@ -75,6 +638,12 @@ impl DropZone {
                 y: heightmap.height as f32 - 1.0,
             },
             validator,
+            precipitation: None,
+            sea: None,
+            halo: 0,
+            border: Margins::default(),
+            origin: UVector2::new(0, 0),
+            map_size: None,
         }
     }
 
@@ -90,298 +659,421 @@ impl DropZone {
                 y: heightmap.height as f32 - 1.0,
             },
             validator: DropZoneValidator::None,
+            precipitation: None,
+            sea: None,
+            halo: 0,
+            border: Margins::default(),
+            origin: UVector2::new(0, 0),
+            map_size: None,
         }
     }
 
-    pub fn circle(heightmap: &Heightmap, radius: f32) -> Self {
-        DropZone {
-            _min: Vector2 { x: 0.0, y: 0.0 },
-            _max: Vector2 {
-                x: heightmap.width as f32,
-                y: heightmap.height as f32,
-            },
-            validator: DropZoneValidator::Circle(radius),
+    /// Drops only spawn where `mask` is painted, the mask should match the heightmap size.
+    pub fn mask(heightmap: &Heightmap, mask: Heightmap) -> Self {
+        DropZone::new(heightmap, DropZoneValidator::Mask(Box::new(mask)))
+    }
+
+    /// Drops only spawn within `radius` cells of `center`.
+    pub fn local(heightmap: &Heightmap, center: Vector2, radius: f32) -> Self {
+        DropZone::new(heightmap, DropZoneValidator::Disc(center, radius))
+    }
+
+    pub fn get_mask(&self) -> Option<&Heightmap> {
+        match &self.validator {
+            DropZoneValidator::Mask(mask) => Some(mask),
+            _ => None,
         }
     }
-}
 
-pub struct State {
-    params: Parameters,
-    current_map_size: usize,
-    current_erosion_radius: usize,
-    erosion_brush_indices: Vec<Vec<i32>>,
-    erosion_brush_weights: Vec<Vec<f32>>,
-    rng: rand::rngs::ThreadRng,
-}
+    /// Biases where drops spawn by `precipitation`, which should match the heightmap size.
+    pub fn with_precipitation(mut self, precipitation: Option<Heightmap>) -> Self {
+        self.precipitation = precipitation.map(Box::new);
+        self
+    }
 
-impl State {
-    fn random_in_range(&mut self, min: f32, max: f32) -> f32 {
-        self.rng.gen::<f32>() * (max - min) + min
+    pub fn get_precipitation(&self) -> Option<&Heightmap> {
+        self.precipitation.as_deref()
     }
-}
 
-fn index_to_position(index: usize, width: usize) -> (usize, usize) {
-    (index % width, index / width)
-}
+    pub fn with_sea(mut self, sea: Option<coastal::Parameters>) -> Self {
+        self.sea = sea;
+        self
+    }
 
-pub fn erode(heightmap: &mut Heightmap, params: &Parameters, drop_zone: &DropZone) {
-    let mut state = State {
-        params: *params,
-        current_map_size: 0,
-        current_erosion_radius: 0,
-        erosion_brush_indices: vec![],
-        erosion_brush_weights: vec![],
-        rng: thread_rng(),
-    };
+    pub fn get_sea(&self) -> Option<&coastal::Parameters> {
+        self.sea.as_ref()
+    }
 
-    initialize(&mut state, heightmap.width);
-    add_metadata(&mut state, heightmap);
+    /// Partitions read `halo` cells of their neighbours around them, see
+    /// `PartialHeightmap::with_halo`.
+    pub fn with_halo(mut self, halo: usize) -> Self {
+        self.halo = halo;
+        self
+    }
 
-    for _iteration in 0..params.num_iterations {
-        let mut pos_x = state.random_in_range(0.0, heightmap.width as f32 - 1.0);
-        let mut pos_y = state.random_in_range(0.0, heightmap.height as f32 - 1.0);
-        while !drop_zone
-            .validator
-            .validate(&heightmap, &Vector2 { x: pos_x, y: pos_y })
-        {
-            pos_x = state.random_in_range(0.0, heightmap.width as f32 - 1.0);
-            pos_y = state.random_in_range(0.0, heightmap.height as f32 - 1.0);
-        }
-        let mut dir_x = 0.0;
-        let mut dir_y = 0.0;
-        let mut speed = state.params.initial_speed;
-        let mut water = state.params.initial_water_volume;
-        let mut sediment = 0.0;
-
-        for _lifetime in 0..params.max_droplet_lifetime {
-            let node_x = pos_x.floor() as usize;
-            let node_y = pos_y.floor() as usize;
-            let droplet_index = node_y * heightmap.width + node_x;
-
-            let cell_offset_x = pos_x - node_x as f32;
-            let cell_offset_y = pos_y - node_y as f32;
-
-            let height_and_gradient = calculate_height_and_gradient(heightmap, pos_x, pos_y);
-
-            dir_x = dir_x * state.params.inertia
-                - height_and_gradient.gradient_x * (1.0 - state.params.inertia);
-            dir_y = dir_y * state.params.inertia
-                - height_and_gradient.gradient_y * (1.0 - state.params.inertia);
-
-            let len = (dir_x * dir_x + dir_y * dir_y).sqrt();
-            if len != 0.0 {
-                dir_x /= len;
-                dir_y /= len;
-            }
-            pos_x += dir_x;
-            pos_y += dir_y;
-
-            if (dir_x == 0.0 && dir_y == 0.0)
-                || pos_x < 0.0
-                || pos_x >= heightmap.width as f32 - 1.0
-                || pos_y < 0.0
-                || pos_y >= heightmap.height as f32 - 1.0
-            {
-                break;
-            }
+    pub fn get_halo(&self) -> usize {
+        self.halo
+    }
+
+    /// Drops never spawn within `border` of the edges, for the halo of a partition.
+    pub fn with_border(mut self, border: Margins) -> Self {
+        self.border = border;
+        self
+    }
 
-            let new_height = calculate_height_and_gradient(heightmap, pos_x, pos_y).height;
-            let delta_height = new_height - height_and_gradient.height;
+    /// Whether a drop may spawn at `drop`, inside the border and accepted by the validator.
+    pub fn validate<P: Precision, R: rand::Rng>(
+        &self,
+        heightmap: &Heightmap<P>,
+        drop: &Vector2,
+        rng: &mut R,
+    ) -> bool {
+        self.inside_border(heightmap, drop) && self.validator.validate(heightmap, drop, rng)
+    }
 
-            let sediment_capacity =
-                (-delta_height * speed * water * state.params.sediment_capacity_factor)
-                    .max(state.params.min_sediment_capacity);
+    fn inside_border<P: Precision>(&self, heightmap: &Heightmap<P>, drop: &Vector2) -> bool {
+        let border = &self.border;
+        drop.x >= border.left as f32
+            && drop.x < heightmap.width.saturating_sub(border.right) as f32
+            && drop.y >= border.top as f32
+            && drop.y < heightmap.height.saturating_sub(border.bottom) as f32
+    }
 
-            if sediment > sediment_capacity || delta_height > 0.0 {
-                let amount_to_deposit = if delta_height > 0.0 {
-                    delta_height.min(sediment)
-                } else {
-                    (sediment - sediment_capacity) * state.params.deposit_speed
+    /// Whether a droplet at `height` has reached the sea and dissolves.
+    pub fn is_submerged(&self, height: HeightmapPrecision) -> bool {
+        self.sea.is_some_and(|sea| height < sea.sea_level)
+    }
+
+    /// Weighted sampling of spawn positions by precipitation and the validator, `None` without a
+    /// precipitation map or when it has no rain where drops are allowed.
+    pub fn rain_sampler<P: Precision>(&self, heightmap: &Heightmap<P>) -> Option<RainSampler> {
+        let precipitation = self.precipitation.as_ref()?;
+        let mut total = 0.0;
+        let mut cumulative = Vec::with_capacity(heightmap.width * heightmap.height);
+        for x in 0..heightmap.width {
+            for y in 0..heightmap.height {
+                let drop = Vector2 {
+                    x: x as f32,
+                    y: y as f32,
                 };
-                sediment -= amount_to_deposit;
-
-                heightmap.data[node_x][node_y] +=
-                    amount_to_deposit * (1.0 - cell_offset_x) * (1.0 - cell_offset_y);
-                heightmap.data[node_x + 1][node_y] +=
-                    amount_to_deposit * cell_offset_x * (1.0 - cell_offset_y);
-                heightmap.data[node_x][node_y + 1] +=
-                    amount_to_deposit * (1.0 - cell_offset_x) * cell_offset_y;
-                heightmap.data[node_x + 1][node_y + 1] +=
-                    amount_to_deposit * cell_offset_x * cell_offset_y;
-            } else {
-                let amount_to_erode =
-                    ((sediment_capacity - sediment) * state.params.erode_speed).min(-delta_height);
-
-                for brush_point_index in 0..state.erosion_brush_indices[droplet_index].len() {
-                    let node_index = state.erosion_brush_indices[droplet_index][brush_point_index];
-                    let (node_x, node_y) = index_to_position(node_index as usize, heightmap.width);
-                    let weighted_erode_amount = amount_to_erode
-                        * state.erosion_brush_weights[droplet_index][brush_point_index];
-                    let delta_sediment = heightmap.data[node_x][node_y].min(weighted_erode_amount);
-                    heightmap.data[node_x][node_y] -= delta_sediment;
-                    sediment += delta_sediment;
+                if self.inside_border(heightmap, &drop) {
+                    total +=
+                        precipitation.data[x][y].max(0.0) * self.validator.weight(heightmap, &drop);
                 }
+                cumulative.push(total);
             }
-
-            speed = (speed * speed + delta_height * state.params.gravity).sqrt();
-            water *= 1.0 - state.params.evaporate_speed;
         }
+        if total <= 0.0 {
+            return None;
+        }
+        Some(RainSampler {
+            cumulative,
+            width: heightmap.width,
+            height: heightmap.height,
+        })
     }
-}
 
-fn initialize(state: &mut State, map_size: usize) {
-    state.current_map_size = map_size;
+    /// True if no drop could ever be accepted, eroding would then never find a starting point.
+    pub fn is_empty(&self) -> bool {
+        match &self.validator {
+            DropZoneValidator::Mask(mask) => {
+                let border = &self.border;
+                let columns = border.left.min(mask.width)..mask.width.saturating_sub(border.right);
+                let rows = border.top.min(mask.height)..mask.height.saturating_sub(border.bottom);
+                mask.data
+                    .get(columns)
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(|column| column.get(rows.clone()).unwrap_or_default())
+                    .all(|&v| v <= 0.0)
+            }
+            DropZoneValidator::Circle(radius) => *radius <= 0.0,
+            DropZoneValidator::Disc(center, radius) => {
+                // Partitions away from the brush have no cell within reach of it
+                let (min_x, max_x) = (
+                    self._min.x + self.border.left as f32,
+                    self._max.x - self.border.right as f32,
+                );
+                let (min_y, max_y) = (
+                    self._min.y + self.border.top as f32,
+                    self._max.y - self.border.bottom as f32,
+                );
+                let nearest_x = center.x.clamp(min_x, max_x.max(min_x));
+                let nearest_y = center.y.clamp(min_y, max_y.max(min_y));
+                ((nearest_x - center.x).powi(2) + (nearest_y - center.y).powi(2)).sqrt() >= *radius
+            }
+            DropZoneValidator::None => false,
+        }
+    }
 
-    if state.erosion_brush_indices.is_empty()
-        || state.current_erosion_radius != state.params.erosion_radius
-        || state.current_map_size != map_size
-    {
-        initialize_brush_indices(state, map_size, state.params.erosion_radius);
-        state.current_erosion_radius = state.params.erosion_radius;
-        state.current_map_size = map_size;
+    /// Top left cell of the zone on the whole map, not at the origin once cropped to a partition.
+    pub fn get_origin(&self) -> UVector2 {
+        self.origin
     }
-}
 
-fn calculate_height_and_gradient(
-    heightmap: &Heightmap,
-    pos_x: f32,
-    pos_y: f32,
-) -> HeightAndGradient {
-    let coord_x = pos_x as usize;
-    let coord_y = pos_y as usize;
+    /// Size of the whole map, which partitions are only a part of.
+    pub fn get_map_size(&self) -> Extent {
+        self.map_size.unwrap_or(Extent::new(
+            self._max.x as usize + 1,
+            self._max.y as usize + 1,
+        ))
+    }
 
-    let x = pos_x - coord_x as f32;
-    let y = pos_y - coord_y as f32;
+    /// Seed of a run seeded with `seed` on the partition at this drop zone, mixing in where the
+    /// partition lies and its heights so that partitions and passes do not all drop at the same
+    /// cells, while the same map and seed still erode the same way with any Rust release.
+    pub fn partition_seed<P: Precision>(&self, seed: u64, heightmap: &Heightmap<P>) -> u64 {
+        let words = [
+            self.origin.x as u64,
+            self.origin.y as u64,
+            heightmap.width as u64,
+            heightmap.height as u64,
+        ];
+        let heights = heightmap
+            .data
+            .iter()
+            .flatten()
+            .map(|h| h.to_f64().to_bits());
+        words
+            .into_iter()
+            .chain(heights)
+            .fold(splitmix64(seed), |hash, word| splitmix64(hash ^ word))
+    }
 
-    let height_nw = heightmap.data[coord_x + 0][coord_y + 0];
-    let height_ne = heightmap.data[coord_x + 1][coord_y + 0];
-    let height_sw = heightmap.data[coord_x + 0][coord_y + 1];
-    let height_se = heightmap.data[coord_x + 1][coord_y + 1];
+    /// The part of the drop zone covering the `width` x `height` area at `anchor`.
+    pub fn crop(&self, anchor: &UVector2, width: usize, height: usize) -> Self {
+        let validator = match &self.validator {
+            DropZoneValidator::Mask(mask) => {
+                DropZoneValidator::Mask(Box::new(crop_heightmap(mask, anchor, width, height)))
+            }
+            DropZoneValidator::Disc(center, radius) => DropZoneValidator::Disc(
+                Vector2::new(center.x - anchor.x as f32, center.y - anchor.y as f32),
+                *radius,
+            ),
+            validator => validator.clone(),
+        };
+        DropZone {
+            _min: Vector2 { x: 0.0, y: 0.0 },
+            _max: Vector2 {
+                x: width as f32 - 1.0,
+                y: height as f32 - 1.0,
+            },
+            validator,
+            precipitation: self.precipitation.as_ref().map(|precipitation| {
+                Box::new(crop_heightmap(precipitation, anchor, width, height))
+            }),
+            sea: self.sea,
+            halo: self.halo,
+            border: Margins::default(),
+            origin: self.origin + *anchor,
+            map_size: Some(self.get_map_size()),
+        }
+    }
 
-    let gradient_x = (height_ne - height_nw) * (1.0 - y) + (height_se - height_sw) * y;
-    let gradient_y = (height_sw - height_nw) * (1.0 - x) + (height_se - height_ne) * x;
+    pub fn circle(heightmap: &Heightmap, radius: f32) -> Self {
+        DropZone {
+            _min: Vector2 { x: 0.0, y: 0.0 },
+            _max: Vector2 {
+                x: heightmap.width as f32 - 1.0,
+                y: heightmap.height as f32 - 1.0,
+            },
+            validator: DropZoneValidator::Circle(radius),
+            precipitation: None,
+            sea: None,
+            halo: 0,
+            border: Margins::default(),
+            origin: UVector2::new(0, 0),
+            map_size: None,
+        }
+    }
+}
 
-    let height = height_nw * (1.0 - x) * (1.0 - y)
-        + height_ne * x * (1.0 - y)
-        + height_sw * (1.0 - x) * y
-        + height_se * x * y;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        check_heightmap, tiny_heightmap, tiny_model, tiny_rectangular_heightmap, Check, GRID_SIZE,
+        ITERATIONS, SIZE,
+    };
+    use crate::heightmap;
+    use crate::partitioning::{MarginMode, Method};
 
-    HeightAndGradient {
-        height,
-        gradient_x,
-        gradient_y,
+    #[test]
+    fn erode_wrapping() -> Check {
+        let heightmap = tiny_rectangular_heightmap();
+        let model = Model::Lague(Parameters {
+            wrap: true,
+            num_iterations: ITERATIONS,
+            ..Default::default()
+        });
+        let (eroded, _) = Method::Default.erode_with_margin(
+            MarginMode::None,
+            false,
+            &heightmap,
+            &model,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE, SIZE * 2)?;
+        if !eroded.wrap {
+            return Err("terrain was not marked as tileable".to_string());
+        }
+        Ok(())
     }
-}
 
-fn initialize_brush_indices(state: &mut State, map_size: usize, radius: usize) {
-    let radius: i32 = radius.try_into().unwrap();
-
-    let erosion_brush_indices_size = map_size * map_size;
-    let mut x_offsets: Vec<i32> = vec![];
-    let mut y_offsets: Vec<i32> = vec![];
-    let mut weights: Vec<f32> = vec![];
-    state
-        .erosion_brush_indices
-        .resize(erosion_brush_indices_size, vec![]);
-    state
-        .erosion_brush_weights
-        .resize(erosion_brush_indices_size, vec![]);
-    x_offsets.resize((radius as usize).pow(2) * 4, 0);
-    y_offsets.resize((radius as usize).pow(2) * 4, 0);
-    weights.resize((radius as usize).pow(2) * 4, 0.0);
-    let mut weight_sum = 0.0f32;
-    let mut add_index = 0;
-
-    for i in 0..erosion_brush_indices_size {
-        let centre_x = i % map_size;
-        let centre_y = i / map_size;
-
-        if centre_y as i32 <= radius
-            || centre_y as i32 >= map_size as i32 - radius
-            || centre_x as i32 <= radius + 1
-            || centre_x as i32 >= map_size as i32 - radius
+    #[test]
+    fn erode_with_sea() -> Check {
+        let heightmap = tiny_rectangular_heightmap();
+        let sea = coastal::Parameters {
+            enabled: true,
+            sea_level: 0.3,
+            wave_interval: 1,
+            ..Default::default()
+        };
+        let model = Model::Lague(Parameters {
+            num_iterations: ITERATIONS,
+            ..Default::default()
+        });
+        let drop_zone = DropZone::default(&heightmap).with_sea(sea.sea());
+        let (eroded, _) = Method::Default.erode_with_margin(
+            MarginMode::None,
+            false,
+            &heightmap,
+            &model,
+            &drop_zone,
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE, SIZE * 2)?;
+        if !eroded
+            .metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.contains_key("SEA_LEVEL"))
         {
-            weight_sum = 0.0;
-            add_index = 0;
-            for y in -radius..=radius {
-                for x in -radius..=radius {
-                    let sqr_dst: f32 = (x as f32).powi(2) + (y as f32).powi(2);
-                    if sqr_dst < (radius * radius) as f32 {
-                        let coord_x = centre_x as i32 + x;
-                        let coord_y = centre_y as i32 + y;
-
-                        if coord_x >= 0
-                            && coord_x < map_size as i32
-                            && coord_y >= 0
-                            && coord_y < map_size as i32
-                        {
-                            let weight = 1.0 - sqr_dst.sqrt() / radius as f32;
-                            weight_sum += weight;
-                            weights[add_index] = weight;
-                            x_offsets[add_index] = x;
-                            y_offsets[add_index] = y;
-                            add_index += 1;
-                        }
-                    }
-                }
-            }
+            return Err("coastal erosion did not run".to_string());
         }
+        Ok(())
+    }
 
-        let num_entries = add_index;
-        state.erosion_brush_indices[i] = vec![];
-        state.erosion_brush_weights[i] = vec![];
-        state.erosion_brush_indices[i].resize(num_entries, 0);
-        state.erosion_brush_weights[i].resize(num_entries, 0.0);
+    #[test]
+    fn erode_with_vegetation() -> Check {
+        let heightmap = tiny_rectangular_heightmap();
+        let vegetation = heightmap::create_vegetation(
+            &heightmap,
+            None,
+            &heightmap::VegetationParameters::default(),
+        );
+        check_heightmap(&vegetation, SIZE, SIZE * 2)?;
+        let heightmap = heightmap.with_vegetation(Some(&vegetation));
+        let model = Model::Lague(Parameters {
+            num_iterations: ITERATIONS,
+            ..Default::default()
+        });
+        let (eroded, _) = Method::Default.erode_with_margin(
+            MarginMode::None,
+            false,
+            &heightmap,
+            &model,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE, SIZE * 2)?;
+        if eroded.vegetation_layer().is_none() {
+            return Err("vegetation was lost while eroding".to_string());
+        }
+        Ok(())
+    }
 
-        for j in 0..num_entries {
-            state.erosion_brush_indices[i][j] =
-                (y_offsets[j] + centre_y as i32) * map_size as i32 + x_offsets[j] + centre_x as i32;
-            state.erosion_brush_weights[i][j] = weights[j] / weight_sum;
+    #[test]
+    fn erosion_brush() -> Check {
+        let heightmap = tiny_heightmap();
+        let model = tiny_model();
+        // In a corner so most partitions have nowhere to spawn drops
+        let center = crate::math::Vector2::new(4.0, 4.0);
+        let drop_zone = DropZone::local(&heightmap, center, 3.0);
+        let mut eroded = heightmap.clone();
+        Method::Subdivision(GRID_SIZE).erode(
+            &mut eroded,
+            &model,
+            false,
+            &drop_zone,
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE, SIZE)?;
+        if eroded.content_hash() == heightmap.content_hash() {
+            return Err("nothing was eroded under the brush".to_string());
         }
+        Ok(())
     }
 
-    assert_eq!(
-        state.erosion_brush_indices.len(),
-        erosion_brush_indices_size
-    );
-    assert_eq!(
-        state.erosion_brush_weights.len(),
-        erosion_brush_indices_size
-    );
-    assert_eq!(x_offsets.len(), radius as usize * radius as usize * 4);
-    assert_eq!(y_offsets.len(), radius as usize * radius as usize * 4);
-    assert_eq!(weights.len(), radius as usize * radius as usize * 4);
-}
+    #[test]
+    fn droplet_ends() -> Check {
+        let heightmap = tiny_heightmap();
+        let model = tiny_model();
+        let mut eroded = heightmap.clone();
+        let outputs = Method::Subdivision(GRID_SIZE).erode(
+            &mut eroded,
+            &model,
+            false,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        let total = |map: Option<Heightmap>| -> f32 {
+            map.map(|map| map.data.iter().flatten().sum())
+                .unwrap_or(0.0)
+        };
+        let (spawns, deaths) = (total(outputs.spawn_map()), total(outputs.death_map()));
+        if spawns <= 0.0 {
+            return Err("no droplet spawns were recorded".to_string());
+        }
+        if spawns != deaths {
+            return Err(format!("{} droplets spawned but {} died", spawns, deaths));
+        }
+        Ok(())
+    }
 
-struct HeightAndGradient {
-    height: f32,
-    gradient_x: f32,
-    gradient_y: f32,
-}
+    #[test]
+    fn backend_metadata() -> Check {
+        let heightmap = tiny_heightmap();
+        let model = tiny_model();
+        let (eroded, _) = Method::Default.erode_with_margin(
+            MarginMode::None,
+            false,
+            &heightmap,
+            &model,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        let backend = eroded
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("BACKEND").cloned());
+        if backend.as_deref() != Some(model.backend().name()) {
+            return Err(format!("recorded backend {:?}", backend));
+        }
+        Ok(())
+    }
 
-pub fn add_metadata(state: &State, heightmap: &mut Heightmap) {
-    heightmap.metadata_add("EROSION_RADIUS", state.params.erosion_radius.to_string());
-    heightmap.metadata_add("INERTIA", state.params.inertia.to_string());
-    heightmap.metadata_add(
-        "SEDIMENT_CAPACITY_FACTOR",
-        state.params.sediment_capacity_factor.to_string(),
-    );
-    heightmap.metadata_add(
-        "MIN_SEDIMENT_CAPACITY",
-        state.params.min_sediment_capacity.to_string(),
-    );
-    heightmap.metadata_add("ERODE_SPEED", state.params.erode_speed.to_string());
-    heightmap.metadata_add("DEPOSIT_SPEED", state.params.deposit_speed.to_string());
-    heightmap.metadata_add("EVAPORATE_SPEED", state.params.evaporate_speed.to_string());
-    heightmap.metadata_add("GRAVITY", state.params.gravity.to_string());
-    heightmap.metadata_add(
-        "MAX_DROPLET_LIFETIME",
-        state.params.max_droplet_lifetime.to_string(),
-    );
-    heightmap.metadata_add(
-        "INITIAL_WATER_VOLUME",
-        state.params.initial_water_volume.to_string(),
-    );
-    heightmap.metadata_add("INITIAL_SPEED", state.params.initial_speed.to_string());
-    heightmap.metadata_add("NUM_ITERATIONS", state.params.num_iterations.to_string());
+    #[test]
+    fn seeded_mask() -> Check {
+        let heightmap = tiny_heightmap();
+        let mut mask = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        for column in mask.data.iter_mut().take(SIZE / 2) {
+            column.fill(0.5);
+        }
+        let model = Model::Lague(Parameters {
+            seed: Some(7),
+            ..Default::default()
+        })
+        .with_iterations(ITERATIONS);
+        let erode = || {
+            let mut eroded = heightmap.clone();
+            let drop_zone = DropZone::mask(&heightmap, mask.clone());
+            Method::Default.erode(&mut eroded, &model, false, &drop_zone, &Progress::default());
+            eroded.content_hash()
+        };
+        if erode() != erode() {
+            return Err("the same seed dropped differently through a mask".to_string());
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,286 @@
+use crate::erode::{DropZone, ErosionOutputs, Progress};
+use crate::heightmap::*;
+use crate::math::Vector2;
+use rand::{thread_rng, Rng};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/*
+Particle based hydraulic erosion after Hans Theobald Beyer,
+"Implementation of a method for hydraulic erosion" (2015).
+Compared to the lague backend the carry capacity is bounded by a minimum slope instead of a
+minimum capacity, erosion weights are computed per droplet from the radius and droplets that
+come to rest pick a new random direction instead of dying.
+ */
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "BeyerParameters")]
+pub struct Parameters {
+    pub inertia: f32,          // [0, 1], 0.3
+    pub capacity: f32,         // [0, 32], 8
+    pub deposition: f32,       // [0, 1], 0.2
+    pub erosion: f32,          // [0, 1], 0.7
+    pub evaporation: f32,      // [0, 1], 0.02
+    pub radius: f32,           // [1, 8], 4
+    pub min_slope: f32,        // [0, 0.1], 0.01
+    pub gravity: f32,          // [0, 20], 4
+    pub max_path: usize,       // [1, 256], 64
+    pub initial_water: f32,    // 1
+    pub initial_speed: f32,    // 1
+    pub num_iterations: usize, // 1
+    #[serde(default)]
+    pub min_water: f32, // [0, 1], 0 (drops with less water die)
+    #[serde(default)]
+    pub min_speed: f32, // [0, 1], 0 (drops slower than this die)
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            inertia: 0.3,
+            capacity: 8.0,
+            deposition: 0.2,
+            erosion: 0.7,
+            evaporation: 0.02,
+            radius: 4.0,
+            min_slope: 0.01,
+            gravity: 4.0,
+            max_path: 64,
+            initial_water: 1.0,
+            initial_speed: 1.0,
+            num_iterations: 1_000_000,
+            min_water: 0.0,
+            min_speed: 0.0,
+        }
+    }
+}
+
+struct Drop {
+    position: Vector2,
+    direction: Vector2,
+    speed: f32,
+    water: f32,
+    sediment: f32,
+}
+
+impl Drop {
+    /// Bilinearly interpolated gradient and height of the cell the drop is in.
+    fn gradient(&self, heightmap: &Heightmap) -> (Vector2, HeightmapPrecision) {
+        let node_x = self.position.x as usize;
+        let node_y = self.position.y as usize;
+        let u = self.position.x - node_x as f32;
+        let v = self.position.y - node_y as f32;
+
+        let height_nw = heightmap.data[node_x][node_y];
+        let height_ne = heightmap.data[node_x + 1][node_y];
+        let height_sw = heightmap.data[node_x][node_y + 1];
+        let height_se = heightmap.data[node_x + 1][node_y + 1];
+
+        let gradient = Vector2::new(
+            (height_ne - height_nw) * (1.0 - v) + (height_se - height_sw) * v,
+            (height_sw - height_nw) * (1.0 - u) + (height_se - height_ne) * u,
+        );
+        let height = height_nw * (1.0 - u) * (1.0 - v)
+            + height_ne * u * (1.0 - v)
+            + height_sw * (1.0 - u) * v
+            + height_se * u * v;
+        (gradient, height)
+    }
+}
+
+fn in_bounds(heightmap: &Heightmap, position: &Vector2) -> bool {
+    position.x >= 0.0
+        && position.x < heightmap.width as f32 - 1.0
+        && position.y >= 0.0
+        && position.y < heightmap.height as f32 - 1.0
+}
+
+pub fn erode(
+    heightmap: &mut Heightmap,
+    params: &Parameters,
+    drop_zone: &DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let mut outputs = ErosionOutputs::new(heightmap, false).with_droplets();
+    let mut rng = thread_rng();
+    add_metadata(params, heightmap);
+
+    if heightmap.width < 2 || heightmap.height < 2 {
+        return outputs;
+    }
+
+    let max_x = heightmap.width as f32 - 1.0;
+    let max_y = heightmap.height as f32 - 1.0;
+    for _iteration in 0..params.num_iterations {
+        if !progress.tick() {
+            break;
+        }
+        let mut position = Vector2::new(rng.gen::<f32>() * max_x, rng.gen::<f32>() * max_y);
+        while !drop_zone.validate(heightmap, &position, &mut rng) {
+            position = Vector2::new(rng.gen::<f32>() * max_x, rng.gen::<f32>() * max_y);
+        }
+        outputs.record_spawn(position.x, position.y);
+        let mut drop = Drop {
+            position,
+            direction: Vector2::new(0.0, 0.0),
+            speed: params.initial_speed,
+            water: params.initial_water,
+            sediment: 0.0,
+        };
+
+        for _step in 0..params.max_path {
+            let (gradient, height_old) = drop.gradient(heightmap);
+            let mut direction = drop.direction * params.inertia - gradient * (1.0 - params.inertia);
+            if direction.magnitude() <= f32::EPSILON {
+                let angle = rng.gen::<f32>() * std::f32::consts::TAU;
+                direction = Vector2::new(angle.cos(), angle.sin());
+            }
+            direction.normalize();
+            drop.direction = direction;
+
+            let position_new = drop.position + direction;
+            if !in_bounds(heightmap, &position_new) {
+                break;
+            }
+            let height_new = Drop {
+                position: position_new,
+                ..drop
+            }
+            .gradient(heightmap)
+            .1;
+            let height_difference = height_new - height_old;
+
+            if drop_zone.is_submerged(height_new) {
+                // Reaching the sea drops everything at the shore
+                deposit(heightmap, &mut outputs, &drop.position, drop.sediment);
+                break;
+            }
+            if height_difference > 0.0 {
+                // Uphill, fill the pit behind the drop
+                let amount = height_difference.min(drop.sediment);
+                deposit(heightmap, &mut outputs, &drop.position, amount);
+                drop.sediment -= amount;
+            } else {
+                // Roots hold the soil, plants slow erosion and lower what the drop can carry
+                let cover =
+                    heightmap.vegetation_at(drop.position.x as usize, drop.position.y as usize);
+                let capacity = (-height_difference).max(params.min_slope)
+                    * drop.speed
+                    * drop.water
+                    * params.capacity
+                    * (1.0 - cover);
+                if drop.sediment > capacity {
+                    let amount = (drop.sediment - capacity) * params.deposition;
+                    deposit(heightmap, &mut outputs, &drop.position, amount);
+                    drop.sediment -= amount;
+                } else {
+                    let amount = ((capacity - drop.sediment) * params.erosion * (1.0 - cover))
+                        .min(-height_difference);
+                    drop.sediment += erode_radius(heightmap, &mut outputs, params, &drop, amount);
+                }
+            }
+
+            // Going downhill speeds the drop up
+            drop.speed = (drop.speed * drop.speed - height_difference * params.gravity)
+                .max(0.0)
+                .sqrt();
+            drop.water *= 1.0 - params.evaporation;
+            drop.position = position_new;
+            if drop.water < params.min_water || drop.speed < params.min_speed {
+                break;
+            }
+        }
+        outputs.record_death(drop.position.x, drop.position.y);
+    }
+
+    outputs
+}
+
+fn deposit(
+    heightmap: &mut Heightmap,
+    outputs: &mut ErosionOutputs,
+    position: &Vector2,
+    amount: HeightmapPrecision,
+) {
+    let node_x = position.x as usize;
+    let node_y = position.y as usize;
+    let u = position.x - node_x as f32;
+    let v = position.y - node_y as f32;
+    for (x, y, weight) in [
+        (node_x, node_y, (1.0 - u) * (1.0 - v)),
+        (node_x + 1, node_y, u * (1.0 - v)),
+        (node_x, node_y + 1, (1.0 - u) * v),
+        (node_x + 1, node_y + 1, u * v),
+    ] {
+        heightmap.add_sediment(x, y, amount * weight);
+        outputs.record_deposition(x, y, amount * weight);
+    }
+}
+
+/// Removes `amount` spread over the cells within the radius, returning what was picked up.
+fn erode_radius(
+    heightmap: &mut Heightmap,
+    outputs: &mut ErosionOutputs,
+    params: &Parameters,
+    drop: &Drop,
+    amount: HeightmapPrecision,
+) -> HeightmapPrecision {
+    let reach = params.radius.ceil() as i32;
+    let centre_x = drop.position.x as i32;
+    let centre_y = drop.position.y as i32;
+
+    let mut cells = Vec::new();
+    let mut weight_sum = 0.0;
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let x = centre_x + dx;
+            let y = centre_y + dy;
+            if x < 0 || y < 0 || x >= heightmap.width as i32 || y >= heightmap.height as i32 {
+                continue;
+            }
+            let distance =
+                Vector2::new(x as f32 - drop.position.x, y as f32 - drop.position.y).magnitude();
+            let weight = (params.radius - distance).max(0.0);
+            if weight > 0.0 {
+                cells.push((x as usize, y as usize, weight));
+                weight_sum += weight;
+            }
+        }
+    }
+
+    if weight_sum <= 0.0 {
+        return 0.0;
+    }
+
+    let mut eroded = 0.0;
+    for (x, y, weight) in cells {
+        let weighted_amount = amount * weight / weight_sum
+            * (1.0 - heightmap.hardness_at(x, y))
+            * heightmap.erodibility_at(x, y);
+        let removed = heightmap.data[x][y].min(weighted_amount);
+        heightmap.remove_material(x, y, removed);
+        outputs.record_erosion(x, y, removed);
+        eroded += removed;
+    }
+    eroded
+}
+
+pub fn add_metadata(params: &Parameters, heightmap: &mut Heightmap) {
+    heightmap.metadata_add("BEYER_INERTIA", params.inertia.to_string());
+    heightmap.metadata_add("BEYER_CAPACITY", params.capacity.to_string());
+    heightmap.metadata_add("BEYER_DEPOSITION", params.deposition.to_string());
+    heightmap.metadata_add("BEYER_EROSION", params.erosion.to_string());
+    heightmap.metadata_add("BEYER_EVAPORATION", params.evaporation.to_string());
+    heightmap.metadata_add("BEYER_RADIUS", params.radius.to_string());
+    heightmap.metadata_add("BEYER_MIN_SLOPE", params.min_slope.to_string());
+    heightmap.metadata_add("BEYER_GRAVITY", params.gravity.to_string());
+    heightmap.metadata_add("BEYER_MAX_PATH", params.max_path.to_string());
+    heightmap.metadata_add("BEYER_INITIAL_WATER", params.initial_water.to_string());
+    heightmap.metadata_add("BEYER_INITIAL_SPEED", params.initial_speed.to_string());
+    heightmap.metadata_add("BEYER_MIN_WATER", params.min_water.to_string());
+    heightmap.metadata_add("BEYER_MIN_SPEED", params.min_speed.to_string());
+    heightmap.metadata_add("NUM_ITERATIONS", params.num_iterations.to_string());
+    heightmap.metadata_add("HARDNESS_MAP", heightmap.hardness.is_some().to_string());
+    heightmap.metadata_add("VEGETATION_MAP", heightmap.vegetation.is_some().to_string());
+    heightmap.metadata_add("MATERIAL_LAYERS", heightmap.layers.is_some().to_string());
+}
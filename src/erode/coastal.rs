@@ -0,0 +1,146 @@
+use crate::erode::{ErosionOutputs, Progress};
+use crate::heightmap::*;
+use serde::{Deserialize, Serialize};
+
+/*
+Coastal erosion around a global sea level. Everything below the sea level is submerged, droplets
+dissolve and drop their sediment as soon as they reach it. Waves attack the land in a thin band
+above the waterline, cells facing more open water erode faster, so the coast is undercut into
+cliffs while headlands retreat and bays fill up. Cut material is carried offshore onto the
+neighbouring sea floor, but never above the water, which leaves a shallow wave-cut platform.
+The wave pass runs alongside any backend, one wave step every `wave_interval` iterations.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Parameters {
+    pub enabled: bool,        // false
+    pub sea_level: f32,       // [0, 1], 0.2
+    pub wave_strength: f32,   // [0, 0.01], 0.002 (height cut per wave step at the waterline)
+    pub wave_reach: f32,      // [0, 0.1], 0.02 (height above the sea the waves reach)
+    pub wave_interval: usize, // [1, 100000], 10000 (model iterations per wave step)
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            enabled: false,
+            sea_level: 0.2,
+            wave_strength: 0.002,
+            wave_reach: 0.02,
+            wave_interval: 10_000,
+        }
+    }
+}
+
+impl Parameters {
+    /// These parameters if the sea is enabled at all.
+    pub fn sea(&self) -> Option<Parameters> {
+        if self.enabled {
+            Some(*self)
+        } else {
+            None
+        }
+    }
+
+    /// Wave steps matching `iterations` of the erosion model, at least one.
+    pub fn wave_steps(&self, iterations: usize) -> usize {
+        (iterations / self.wave_interval.max(1)).max(1)
+    }
+}
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// 1 where the terrain is below `sea_level` and 0 elsewhere.
+pub fn submerged(heightmap: &Heightmap, sea_level: HeightmapPrecision) -> Heightmap {
+    create_heightmap_from_closure(heightmap.width, heightmap.height, 1.0, &|x, y| {
+        if heightmap.data[x][y] < sea_level {
+            1.0
+        } else {
+            0.0
+        }
+    })
+}
+
+/// Runs `steps` wave steps along the coast of `heightmap`.
+pub fn erode(
+    heightmap: &mut Heightmap,
+    params: &Parameters,
+    steps: usize,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let mut outputs = ErosionOutputs::new(heightmap, false);
+    add_metadata(params, heightmap);
+    if params.wave_reach <= 0.0 {
+        return outputs;
+    }
+
+    let width = heightmap.width as i32;
+    let height = heightmap.height as i32;
+    let sea_level = params.sea_level;
+    for _step in 0..steps {
+        if progress.is_cancelled() {
+            break;
+        }
+        // Waves of one step all see the coast as it was before the step
+        let heights = heightmap.data.clone();
+        for x in 0..heightmap.width {
+            for y in 0..heightmap.height {
+                let above = heights[x][y] - sea_level;
+                if above < 0.0 || above >= params.wave_reach {
+                    continue;
+                }
+                let sea: Vec<(usize, usize)> = NEIGHBOURS
+                    .iter()
+                    .map(|(dx, dy)| (x as i32 + dx, y as i32 + dy))
+                    .filter(|&(nx, ny)| nx >= 0 && ny >= 0 && nx < width && ny < height)
+                    .map(|(nx, ny)| (nx as usize, ny as usize))
+                    .filter(|&(nx, ny)| heights[nx][ny] < sea_level)
+                    .collect();
+                if sea.is_empty() {
+                    continue;
+                }
+
+                let exposure = sea.len() as f32 / NEIGHBOURS.len() as f32;
+                // Strongest at the waterline, cut cells sink below it and join the sea
+                let cut = (params.wave_strength
+                    * exposure
+                    * (1.0 - above / params.wave_reach)
+                    * (1.0 - heightmap.hardness_at(x, y))
+                    * heightmap.erodibility_at(x, y))
+                .min(heightmap.data[x][y])
+                .max(0.0);
+                if cut <= 0.0 {
+                    continue;
+                }
+                heightmap.remove_material(x, y, cut);
+                outputs.record_erosion(x, y, cut);
+
+                // Whatever does not fit under the water is lost to the open sea
+                let share = cut / sea.len() as f32;
+                for (nx, ny) in sea {
+                    let deposit = share.min(sea_level - heightmap.data[nx][ny]).max(0.0);
+                    heightmap.add_sediment(nx, ny, deposit);
+                    outputs.record_deposition(nx, ny, deposit);
+                }
+            }
+        }
+    }
+
+    outputs
+}
+
+pub fn add_metadata(params: &Parameters, heightmap: &mut Heightmap) {
+    heightmap.metadata_add("SEA_LEVEL", params.sea_level.to_string());
+    heightmap.metadata_add("WAVE_STRENGTH", params.wave_strength.to_string());
+    heightmap.metadata_add("WAVE_REACH", params.wave_reach.to_string());
+    heightmap.metadata_add("WAVE_INTERVAL", params.wave_interval.to_string());
+}
@@ -0,0 +1,158 @@
+use crate::erode::{DropZone, ErosionOutputs, Progress};
+use crate::heightmap::*;
+use crate::math::Vector2;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/*
+Detachment limited fluvial erosion following the stream power law E = K·A^m·S^n,
+where A is the upstream drainage area and S the slope towards the steepest downhill neighbour.
+Water is routed with the D8 method, every cell passes its drainage area on to its lowest
+neighbour. Like the pipes backend, heights are measured in cell lengths through `height_scale`.
+ */
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "FluvialParameters")]
+pub struct Parameters {
+    pub erodibility: f32,      // [0, 0.01], 0.0005 (K)
+    pub area_exponent: f32,    // [0, 1], 0.5 (m)
+    pub slope_exponent: f32,   // [0.5, 2], 1 (n)
+    pub time_step: f32,        // [0.1, 10], 1
+    pub height_scale: f32,     // [1, 512], 64
+    pub record_flow: bool,     // true
+    pub num_iterations: usize, // [1, 1000], 50
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            erodibility: 0.0005,
+            area_exponent: 0.5,
+            slope_exponent: 1.0,
+            time_step: 1.0,
+            height_scale: 64.0,
+            record_flow: true,
+            num_iterations: 50,
+        }
+    }
+}
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// The steepest downhill neighbour of every cell and the slope towards it, `None` for pits.
+fn receivers(heightmap: &Heightmap, scale: f32) -> Vec<Option<((usize, usize), f32)>> {
+    let mut receivers = Vec::with_capacity(heightmap.width * heightmap.height);
+    for x in 0..heightmap.width {
+        for y in 0..heightmap.height {
+            let height = heightmap.data[x][y];
+            let mut steepest = None;
+            let mut steepest_slope = 0.0;
+            for (dx, dy) in NEIGHBOURS {
+                let nx = x as i32 + dx;
+                let ny = y as i32 + dy;
+                if nx < 0 || ny < 0 || nx >= heightmap.width as i32 || ny >= heightmap.height as i32
+                {
+                    continue;
+                }
+                let (nx, ny) = (nx as usize, ny as usize);
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                let slope = (height - heightmap.data[nx][ny]) * scale / distance;
+                if slope > steepest_slope {
+                    steepest_slope = slope;
+                    steepest = Some((nx, ny));
+                }
+            }
+            receivers.push(steepest.map(|receiver| (receiver, steepest_slope)));
+        }
+    }
+    receivers
+}
+
+pub fn erode(
+    heightmap: &mut Heightmap,
+    params: &Parameters,
+    drop_zone: &DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let mut outputs = ErosionOutputs::new(heightmap, params.record_flow);
+    add_metadata(params, heightmap);
+
+    let width = heightmap.width;
+    let height = heightmap.height;
+    let index = |x: usize, y: usize| x * height + y;
+
+    // Cells receive rain and contribute drainage area by their drop zone weight
+    let mut rainfall = vec![0.0; width * height];
+    for x in 0..width {
+        for y in 0..height {
+            rainfall[index(x, y)] = drop_zone
+                .validator
+                .weight(heightmap, &Vector2::new(x as f32, y as f32));
+        }
+    }
+
+    let scale = params.height_scale;
+    for _iteration in 0..params.num_iterations {
+        if !progress.tick() {
+            break;
+        }
+        let receivers = receivers(heightmap, scale);
+        let mut order: Vec<(usize, usize)> = (0..width)
+            .flat_map(|x| (0..height).map(move |y| (x, y)))
+            .collect();
+        order.sort_by(|a, b| heightmap.data[b.0][b.1].total_cmp(&heightmap.data[a.0][a.1]));
+
+        // Accumulate drainage area from the highest cell downwards
+        let mut area = rainfall.clone();
+        for &(x, y) in order.iter() {
+            if let Some(((rx, ry), _)) = receivers[index(x, y)] {
+                area[index(rx, ry)] += area[index(x, y)];
+            }
+        }
+
+        // Incise from the outlets upwards so every cell sees its receiver's new height
+        for &(x, y) in order.iter().rev() {
+            let i = index(x, y);
+            let ((rx, ry), slope) = match receivers[i] {
+                Some(receiver) => receiver,
+                None => continue,
+            };
+            outputs.record_flow(x, y, area[i]);
+            let incision = params.erodibility
+                * area[i].powf(params.area_exponent)
+                * slope.powf(params.slope_exponent)
+                * params.time_step
+                / scale
+                * (1.0 - heightmap.hardness_at(x, y))
+                * heightmap.erodibility_at(x, y);
+            // Never cut below the receiver, that would turn the channel into a pit
+            let removed = incision
+                .min(heightmap.data[x][y] - heightmap.data[rx][ry])
+                .max(0.0);
+            heightmap.remove_material(x, y, removed);
+            outputs.record_erosion(x, y, removed);
+        }
+    }
+
+    outputs
+}
+
+pub fn add_metadata(params: &Parameters, heightmap: &mut Heightmap) {
+    heightmap.metadata_add("FLUVIAL_ERODIBILITY", params.erodibility.to_string());
+    heightmap.metadata_add("FLUVIAL_AREA_EXPONENT", params.area_exponent.to_string());
+    heightmap.metadata_add("FLUVIAL_SLOPE_EXPONENT", params.slope_exponent.to_string());
+    heightmap.metadata_add("FLUVIAL_TIME_STEP", params.time_step.to_string());
+    heightmap.metadata_add("FLUVIAL_HEIGHT_SCALE", params.height_scale.to_string());
+    heightmap.metadata_add("NUM_ITERATIONS", params.num_iterations.to_string());
+    heightmap.metadata_add("HARDNESS_MAP", heightmap.hardness.is_some().to_string());
+    heightmap.metadata_add("MATERIAL_LAYERS", heightmap.layers.is_some().to_string());
+}
@@ -0,0 +1,194 @@
+use crate::erode::{DropZone, ErosionOutputs, Progress};
+use crate::heightmap::*;
+use crate::math::Vector2;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/*
+Glacial erosion by a simple ice flow and abrasion model. Snow accumulates into ice above the
+snowline and melts below it, the mass balance grows linearly with the distance to the snowline.
+Ice flows down the slope of its own surface to its lower neighbours and abrades the bed in
+proportion to the ice passing over it. Since the ice fills the whole valley and not only its
+lowest line the floor is widened as well as deepened, carving the U-shaped valleys hydraulic
+erosion can not produce. Abraded rock is carried along with the ice and dropped where it melts.
+Heights are measured in cell lengths through `height_scale` like the fluvial backend.
+ */
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "GlacialParameters")]
+pub struct Parameters {
+    pub snowline: f32,         // [0, 1], 0.5 (of the height range)
+    pub mass_balance: f32,     // [0, 0.01], 0.001 (ice per step at the top and bottom)
+    pub flow_rate: f32,        // [0, 1], 0.1
+    pub abrasion: f32,         // [0, 1], 0.05
+    pub height_scale: f32,     // [1, 512], 64
+    pub record_flow: bool,     // true
+    pub num_iterations: usize, // [1, 2000], 200
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            snowline: 0.5,
+            mass_balance: 0.001,
+            flow_rate: 0.1,
+            abrasion: 0.05,
+            height_scale: 64.0,
+            record_flow: true,
+            num_iterations: 200,
+        }
+    }
+}
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+pub fn erode(
+    heightmap: &mut Heightmap,
+    params: &Parameters,
+    drop_zone: &DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let mut outputs = ErosionOutputs::new(heightmap, params.record_flow);
+    add_metadata(params, heightmap);
+
+    let width = heightmap.width;
+    let height = heightmap.height;
+    let index = |x: usize, y: usize| x * height + y;
+
+    let (min, max) = heightmap.get_range();
+    let range = (max - min).max(HeightmapPrecision::EPSILON);
+    let snowline = min + params.snowline * range;
+
+    // Snow only falls where drops would be allowed to spawn
+    let mut snowfall = vec![0.0; width * height];
+    for x in 0..width {
+        for y in 0..height {
+            snowfall[index(x, y)] = drop_zone
+                .validator
+                .weight(heightmap, &Vector2::new(x as f32, y as f32));
+        }
+    }
+
+    let scale = params.height_scale;
+    let mut ice = vec![0.0; width * height];
+    let mut debris = vec![0.0; width * height];
+    for _iteration in 0..params.num_iterations {
+        if !progress.tick() {
+            break;
+        }
+
+        // Accumulate above the snowline and melt below it, dropping the carried debris
+        for x in 0..width {
+            for y in 0..height {
+                let i = index(x, y);
+                let balance = params.mass_balance * (heightmap.data[x][y] - snowline) / range;
+                if balance >= 0.0 {
+                    ice[i] += balance * snowfall[i];
+                } else if ice[i] > 0.0 {
+                    let melted = (-balance).min(ice[i]);
+                    let dropped = debris[i] * melted / ice[i];
+                    heightmap.add_sediment(x, y, dropped);
+                    outputs.record_deposition(x, y, dropped);
+                    debris[i] -= dropped;
+                    ice[i] -= melted;
+                }
+            }
+        }
+
+        // Move ice and debris down the ice surface, abrading the bed on the way
+        let mut next_ice = ice.clone();
+        let mut next_debris = debris.clone();
+        for x in 0..width {
+            for y in 0..height {
+                let i = index(x, y);
+                if ice[i] <= 0.0 {
+                    continue;
+                }
+                let surface = heightmap.data[x][y] + ice[i];
+                let mut slopes = [0.0; 8];
+                let mut total_slope = 0.0;
+                let mut steepest_slope: f32 = 0.0;
+                for (n, (dx, dy)) in NEIGHBOURS.iter().enumerate() {
+                    let nx = x as i32 + dx;
+                    let ny = y as i32 + dy;
+                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                        continue;
+                    }
+                    let (nx, ny) = (nx as usize, ny as usize);
+                    let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                    let slope =
+                        (surface - heightmap.data[nx][ny] - ice[index(nx, ny)]) * scale / distance;
+                    if slope > 0.0 {
+                        slopes[n] = slope;
+                        total_slope += slope;
+                        steepest_slope = steepest_slope.max(slope);
+                    }
+                }
+                if total_slope <= 0.0 {
+                    continue;
+                }
+
+                // At most half of the ice leaves per step so neighbours do not swap places
+                let fraction = (params.flow_rate * steepest_slope).min(0.5);
+                let moved_ice = ice[i] * fraction;
+                let moved_debris = debris[i] * fraction;
+                next_ice[i] -= moved_ice;
+                next_debris[i] -= moved_debris;
+                for (n, (dx, dy)) in NEIGHBOURS.iter().enumerate() {
+                    if slopes[n] > 0.0 {
+                        let neighbour = index((x as i32 + dx) as usize, (y as i32 + dy) as usize);
+                        let share = slopes[n] / total_slope;
+                        next_ice[neighbour] += moved_ice * share;
+                        next_debris[neighbour] += moved_debris * share;
+                    }
+                }
+                outputs.record_flow(x, y, moved_ice);
+
+                let abraded = (params.abrasion
+                    * moved_ice
+                    * (1.0 - heightmap.hardness_at(x, y))
+                    * heightmap.erodibility_at(x, y))
+                .min(heightmap.data[x][y])
+                .max(0.0);
+                heightmap.remove_material(x, y, abraded);
+                outputs.record_erosion(x, y, abraded);
+                next_debris[i] += abraded;
+            }
+        }
+        ice = next_ice;
+        debris = next_debris;
+    }
+
+    // Whatever is still frozen in is left behind as moraine
+    for x in 0..width {
+        for y in 0..height {
+            let dropped = debris[index(x, y)];
+            if dropped > 0.0 {
+                heightmap.add_sediment(x, y, dropped);
+                outputs.record_deposition(x, y, dropped);
+            }
+        }
+    }
+
+    outputs
+}
+
+pub fn add_metadata(params: &Parameters, heightmap: &mut Heightmap) {
+    heightmap.metadata_add("GLACIAL_SNOWLINE", params.snowline.to_string());
+    heightmap.metadata_add("GLACIAL_MASS_BALANCE", params.mass_balance.to_string());
+    heightmap.metadata_add("GLACIAL_FLOW_RATE", params.flow_rate.to_string());
+    heightmap.metadata_add("GLACIAL_ABRASION", params.abrasion.to_string());
+    heightmap.metadata_add("GLACIAL_HEIGHT_SCALE", params.height_scale.to_string());
+    heightmap.metadata_add("NUM_ITERATIONS", params.num_iterations.to_string());
+    heightmap.metadata_add("HARDNESS_MAP", heightmap.hardness.is_some().to_string());
+    heightmap.metadata_add("MATERIAL_LAYERS", heightmap.layers.is_some().to_string());
+}
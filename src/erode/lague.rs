@@ -0,0 +1,1077 @@
+use crate::erode::{DropZone, ErosionOutputs, Progress};
+use crate::heightmap::*;
+use crate::math::Vector2;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Falloff of the erosion brush from its centre to `erosion_radius`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum BrushKernel {
+    #[default]
+    Linear,
+    Gaussian,
+    Cosine,
+    Constant,
+}
+
+impl Display for BrushKernel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BrushKernel::Linear => f.write_str("Linear"),
+            BrushKernel::Gaussian => f.write_str("Gaussian"),
+            BrushKernel::Cosine => f.write_str("Cosine"),
+            BrushKernel::Constant => f.write_str("Constant"),
+        }
+    }
+}
+
+impl BrushKernel {
+    pub fn list() -> [BrushKernel; 4] {
+        [
+            BrushKernel::Linear,
+            BrushKernel::Gaussian,
+            BrushKernel::Cosine,
+            BrushKernel::Constant,
+        ]
+    }
+
+    /// Unnormalized weight of a cell `distance` away from the centre, `distance < radius`.
+    pub fn weight(self, distance: f32, radius: f32) -> f32 {
+        let t = distance / radius;
+        match self {
+            BrushKernel::Linear => 1.0 - t,
+            // Sigma of a third of the radius so the tail is close to zero at the edge
+            BrushKernel::Gaussian => (-4.5 * t * t).exp(),
+            BrushKernel::Cosine => ((t * PI).cos() + 1.0) / 2.0,
+            BrushKernel::Constant => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "LagueParameters")]
+pub struct Parameters {
+    pub erosion_radius: usize,         // [2, 8], 3
+    pub inertia: f32,                  // [0, 1], 0.05
+    pub sediment_capacity_factor: f32, // 4
+    pub min_sediment_capacity: f32,    // 0.01
+    pub erode_speed: f32,              // [0, 1], 0.3
+    pub deposit_speed: f32,            // [0, 1], 0.3
+    pub evaporate_speed: f32,          // [0, 1], 0.1
+    pub gravity: f32,                  // 4
+    pub max_droplet_lifetime: usize,   // 30
+    pub initial_water_volume: f32,     // 1
+    pub initial_speed: f32,            // 1
+    pub num_iterations: usize,         // 1
+    #[serde(default)]
+    pub record_flow: bool, // true
+    #[serde(default)]
+    pub min_water: f32, // [0, 1], 0 (droplets with less water die)
+    #[serde(default)]
+    pub min_speed: f32, // [0, 1], 0 (droplets slower than this die)
+    #[serde(default)]
+    pub brush_kernel: BrushKernel, // Linear
+    #[serde(default)]
+    pub wrap: bool, // false (droplets leaving an edge enter on the opposite side)
+    /// Droplets simulated side by side, each step of a batch moves all of them on the terrain as
+    /// it was before the step, so batches give other results than droplets run one by one.
+    #[serde(default)]
+    pub batch_size: usize, // [0, 1024], 0 (0 and 1 run the droplets one by one, or 256 at a time when parallel)
+    #[serde(default)]
+    pub double_precision: bool, // false (heights are eroded as 64 bit floats per pass, for large subtle maps)
+    #[serde(default)]
+    pub parallel: bool, // false (droplets of a batch step on all cores, best with large batches)
+    #[serde(default)]
+    pub seed: Option<u64>, // None (droplets fall at random, a seed drops them at the same cells again)
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            erosion_radius: 3,
+            inertia: 0.05,
+            sediment_capacity_factor: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.1,
+            gravity: 4.0,
+            max_droplet_lifetime: 30,
+            initial_water_volume: 1.0,
+            initial_speed: 1.0,
+            num_iterations: 1_000_000,
+            record_flow: true,
+            min_water: 0.0,
+            min_speed: 0.0,
+            brush_kernel: BrushKernel::Linear,
+            wrap: false,
+            batch_size: 0,
+            double_precision: false,
+            parallel: false,
+            seed: None,
+        }
+    }
+}
+
+/// Offsets and weights of the erosion brush around its centre. One radial kernel is built per
+/// radius and kernel, shared by all cells and runs and clipped to the map where it is used, so it
+/// only takes `O(radius²)` memory and cells at the border get the same falloff as those inside.
+pub struct Brush {
+    offsets: Vec<(i32, i32)>,
+    weights: Vec<f32>,
+    weight_sum: f32,
+    radius: i32,
+}
+
+/// Brushes built so far, shared by the partitions of a run and every run after it, see
+/// `Brush::cached`. There are only a few radii and kernels, so brushes are never dropped.
+static BRUSHES: OnceLock<Mutex<BrushCache>> = OnceLock::new();
+
+type BrushCache = HashMap<(usize, BrushKernel), Arc<Brush>>;
+
+impl Brush {
+    /// The brush of `radius` and `kernel`, only built the first time it is asked for.
+    pub fn cached(radius: usize, kernel: BrushKernel) -> Arc<Brush> {
+        let mut brushes = BRUSHES.get_or_init(Default::default).lock().unwrap();
+        let brush = brushes
+            .entry((radius, kernel))
+            .or_insert_with(|| Arc::new(Brush::new(radius, kernel)));
+        Arc::clone(brush)
+    }
+
+    pub fn new(radius: usize, kernel: BrushKernel) -> Self {
+        let radius: i32 = radius.try_into().unwrap();
+        let mut offsets = vec![];
+        let mut weights = vec![];
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                let sqr_dst: f32 = (x as f32).powi(2) + (y as f32).powi(2);
+                if sqr_dst < (radius * radius) as f32 {
+                    offsets.push((x, y));
+                    weights.push(kernel.weight(sqr_dst.sqrt(), radius as f32));
+                }
+            }
+        }
+        Brush {
+            weight_sum: weights.iter().sum(),
+            offsets,
+            weights,
+            radius,
+        }
+    }
+
+    /// Calls `apply` with every cell of the brush centred on `(centre_x, centre_y)` and its
+    /// normalized weight. Near the edges of a map that does not wrap the brush is cut off and the
+    /// remaining weights are normalized again.
+    pub fn for_each(
+        &self,
+        centre: (usize, usize),
+        map_size: (usize, usize),
+        wrap: bool,
+        mut apply: impl FnMut(usize, usize, f32),
+    ) {
+        let (centre_x, centre_y) = (centre.0 as i32, centre.1 as i32);
+        let (width, height) = (map_size.0 as i32, map_size.1 as i32);
+        let inside = |(x, y): &(i32, i32)| {
+            let (x, y) = (centre_x + x, centre_y + y);
+            x >= 0 && x < width && y >= 0 && y < height
+        };
+        let clipped = !wrap
+            && (centre_x < self.radius
+                || centre_y < self.radius
+                || centre_x + self.radius >= width
+                || centre_y + self.radius >= height);
+        let weight_sum = if clipped {
+            self.offsets
+                .iter()
+                .zip(&self.weights)
+                .filter(|(offset, _)| inside(offset))
+                .map(|(_, weight)| weight)
+                .sum()
+        } else {
+            self.weight_sum
+        };
+
+        for (offset, weight) in self.offsets.iter().zip(&self.weights) {
+            if clipped && !inside(offset) {
+                continue;
+            }
+            let x = (centre_x + offset.0).rem_euclid(width) as usize;
+            let y = (centre_y + offset.1).rem_euclid(height) as usize;
+            apply(x, y, weight / weight_sum);
+        }
+    }
+}
+
+const PRESETS: &str = include_str!("../../assets/erosion_presets.json");
+/// Batch size of parallel droplets when none is set, large enough to keep every core busy.
+pub const PARALLEL_BATCH_SIZE: usize = 256;
+
+/// Named parameters for a kind of terrain, the built-in ones live in `assets/erosion_presets.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub parameters: Parameters,
+}
+
+impl Parameters {
+    pub fn presets() -> Vec<Preset> {
+        serde_json::from_str(PRESETS).expect("Built-in erosion presets are malformed.")
+    }
+
+    pub fn preset(name: &str) -> Option<Parameters> {
+        Parameters::presets()
+            .into_iter()
+            .find(|preset| preset.name == name)
+            .map(|preset| preset.parameters)
+    }
+
+    /// Droplets simulated side by side, `PARALLEL_BATCH_SIZE` when parallel without a batch size,
+    /// as droplets run one by one have nothing to share between the cores.
+    pub fn effective_batch_size(&self) -> usize {
+        match self.batch_size {
+            0 | 1 if self.parallel => PARALLEL_BATCH_SIZE,
+            batch_size => batch_size.max(1),
+        }
+    }
+}
+
+pub struct State {
+    params: Parameters,
+    wrap: bool,
+    brush: Arc<Brush>,
+    rng: StdRng,
+}
+
+impl State {
+    fn random_in_range(&mut self, min: f32, max: f32) -> f32 {
+        self.rng.gen::<f32>() * (max - min) + min
+    }
+}
+
+/// Droplets simulated side by side. Every property lives in its own array, so each phase of a
+/// step walks contiguous memory instead of jumping between droplet structs.
+#[derive(Default)]
+struct Droplets {
+    pos_x: Vec<f32>,
+    pos_y: Vec<f32>,
+    dir_x: Vec<f32>,
+    dir_y: Vec<f32>,
+    speed: Vec<f32>,
+    water: Vec<f32>,
+    sediment: Vec<f32>,
+    // Position at the start of the current step and the height change of the move
+    from_x: Vec<f32>,
+    from_y: Vec<f32>,
+    delta_height: Vec<f32>,
+    new_height: Vec<f32>,
+    alive: Vec<bool>,
+}
+
+impl Droplets {
+    fn len(&self) -> usize {
+        self.pos_x.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos_x.is_empty()
+    }
+
+    fn spawn(&mut self, x: f32, y: f32, speed: f32, water: f32) {
+        self.pos_x.push(x);
+        self.pos_y.push(y);
+        self.dir_x.push(0.0);
+        self.dir_y.push(0.0);
+        self.speed.push(speed);
+        self.water.push(water);
+        self.sediment.push(0.0);
+        self.from_x.push(x);
+        self.from_y.push(y);
+        self.delta_height.push(0.0);
+        self.new_height.push(0.0);
+        self.alive.push(true);
+    }
+
+    /// Drops every droplet that died during the last step.
+    fn remove_dead(&mut self) {
+        fn retain<T: Copy>(values: &mut Vec<T>, alive: &[bool]) {
+            let mut kept = 0;
+            for i in 0..values.len() {
+                if alive[i] {
+                    values[kept] = values[i];
+                    kept += 1;
+                }
+            }
+            values.truncate(kept);
+        }
+        let alive = std::mem::take(&mut self.alive);
+        retain(&mut self.pos_x, &alive);
+        retain(&mut self.pos_y, &alive);
+        retain(&mut self.dir_x, &alive);
+        retain(&mut self.dir_y, &alive);
+        retain(&mut self.speed, &alive);
+        retain(&mut self.water, &alive);
+        retain(&mut self.sediment, &alive);
+        retain(&mut self.from_x, &alive);
+        retain(&mut self.from_y, &alive);
+        retain(&mut self.delta_height, &alive);
+        retain(&mut self.new_height, &alive);
+        self.alive = alive;
+        self.alive.retain(|&alive| alive);
+    }
+
+    fn clear(&mut self) {
+        self.alive.fill(false);
+        self.remove_dead();
+    }
+}
+
+pub fn erode<P: Precision>(
+    heightmap: &mut Heightmap<P>,
+    params: &Parameters,
+    drop_zone: &DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let mut outputs = ErosionOutputs::new(heightmap, params.record_flow).with_droplets();
+    let mut state = State {
+        params: *params,
+        // Partitions cut out of a wrapping map are not tileable on their own
+        wrap: params.wrap && heightmap.wrap,
+        brush: Brush::cached(params.erosion_radius, params.brush_kernel),
+        rng: match params.seed {
+            Some(seed) => StdRng::seed_from_u64(drop_zone.partition_seed(seed, heightmap)),
+            None => StdRng::from_entropy(),
+        },
+    };
+
+    add_metadata(params, heightmap);
+    let rain = drop_zone.rain_sampler(heightmap);
+    let batch_size = params.effective_batch_size();
+    let mut droplets = Droplets::default();
+
+    let mut spawned = 0;
+    while spawned < params.num_iterations {
+        let batch = batch_size.min(params.num_iterations - spawned);
+        for _ in 0..batch {
+            if !progress.tick() {
+                return outputs;
+            }
+            let (pos_x, pos_y) = if let Some(rain) = &rain {
+                // The sampler already accounts for the drop zone
+                let drop = rain.sample(&mut state.rng);
+                (drop.x, drop.y)
+            } else {
+                let mut pos_x = state.random_in_range(0.0, heightmap.width as f32 - 1.0);
+                let mut pos_y = state.random_in_range(0.0, heightmap.height as f32 - 1.0);
+                while !drop_zone.validate(
+                    &heightmap,
+                    &Vector2 { x: pos_x, y: pos_y },
+                    &mut state.rng,
+                ) {
+                    pos_x = state.random_in_range(0.0, heightmap.width as f32 - 1.0);
+                    pos_y = state.random_in_range(0.0, heightmap.height as f32 - 1.0);
+                }
+                (pos_x, pos_y)
+            };
+            outputs.record_spawn(pos_x, pos_y);
+            droplets.spawn(
+                pos_x,
+                pos_y,
+                state.params.initial_speed,
+                state.params.initial_water_volume,
+            );
+        }
+        spawned += batch;
+
+        for _lifetime in 0..params.max_droplet_lifetime {
+            if droplets.is_empty() {
+                break;
+            }
+            move_droplets(&mut droplets, &state, heightmap, &mut outputs);
+            erode_droplets(&mut droplets, &state, heightmap, drop_zone, &mut outputs);
+            droplets.remove_dead();
+        }
+        // Droplets still alive have used up their lifetime
+        for i in 0..droplets.len() {
+            outputs.record_death(droplets.pos_x[i], droplets.pos_y[i]);
+        }
+        droplets.clear();
+    }
+
+    outputs
+}
+
+/// Where a droplet ends up after one step, worked out from the heightmap alone.
+struct Movement {
+    dir_x: f32,
+    dir_y: f32,
+    pos_x: f32,
+    pos_y: f32,
+    new_height: f32,
+    delta_height: f32,
+    alive: bool,
+}
+
+/// Moves droplet `i` one step downhill without touching the droplets or the heightmap.
+fn movement<P: Precision>(
+    droplets: &Droplets,
+    i: usize,
+    inertia: f32,
+    wrap: bool,
+    heightmap: &Heightmap<P>,
+) -> Movement {
+    let pos_x = droplets.pos_x[i];
+    let pos_y = droplets.pos_y[i];
+    let height_and_gradient = calculate_height_and_gradient(heightmap, pos_x, pos_y, wrap);
+
+    let mut dir_x = droplets.dir_x[i] * inertia - height_and_gradient.gradient_x * (1.0 - inertia);
+    let mut dir_y = droplets.dir_y[i] * inertia - height_and_gradient.gradient_y * (1.0 - inertia);
+    let len = (dir_x * dir_x + dir_y * dir_y).sqrt();
+    if len != 0.0 {
+        dir_x /= len;
+        dir_y /= len;
+    }
+    let mut pos_x = pos_x + dir_x;
+    let mut pos_y = pos_y + dir_y;
+    if wrap {
+        pos_x = wrap_coordinate(pos_x, heightmap.width);
+        pos_y = wrap_coordinate(pos_y, heightmap.height);
+    }
+    let mut movement = Movement {
+        dir_x,
+        dir_y,
+        pos_x,
+        pos_y,
+        new_height: 0.0,
+        delta_height: 0.0,
+        alive: false,
+    };
+
+    if (dir_x == 0.0 && dir_y == 0.0)
+        || (!wrap
+            && (pos_x < 0.0
+                || pos_x >= heightmap.width as f32 - 1.0
+                || pos_y < 0.0
+                || pos_y >= heightmap.height as f32 - 1.0))
+    {
+        return movement;
+    }
+
+    let new_height = calculate_height_and_gradient(heightmap, pos_x, pos_y, wrap).height;
+    movement.new_height = new_height.to_f32();
+    // Taken before rounding so the slopes of heights far from 0 keep their precision
+    movement.delta_height = (new_height - height_and_gradient.height).to_f32();
+    movement.alive = true;
+    movement
+}
+
+/// Moves every droplet of the batch one step downhill. Only reads the heightmap, all droplets
+/// see the terrain as it was before the step.
+fn move_droplets<P: Precision>(
+    droplets: &mut Droplets,
+    state: &State,
+    heightmap: &Heightmap<P>,
+    outputs: &mut ErosionOutputs,
+) {
+    let (inertia, wrap) = (state.params.inertia, state.wrap);
+    let movements: Vec<Movement> = if state.params.parallel {
+        let droplets = &*droplets;
+        (0..droplets.len())
+            .into_par_iter()
+            .map(|i| movement(droplets, i, inertia, wrap, heightmap))
+            .collect()
+    } else {
+        (0..droplets.len())
+            .map(|i| movement(droplets, i, inertia, wrap, heightmap))
+            .collect()
+    };
+
+    for (i, movement) in movements.into_iter().enumerate() {
+        droplets.from_x[i] = droplets.pos_x[i];
+        droplets.from_y[i] = droplets.pos_y[i];
+        outputs.record_flow(
+            droplets.from_x[i].floor() as usize,
+            droplets.from_y[i].floor() as usize,
+            droplets.water[i],
+        );
+        droplets.dir_x[i] = movement.dir_x;
+        droplets.dir_y[i] = movement.dir_y;
+        droplets.pos_x[i] = movement.pos_x;
+        droplets.pos_y[i] = movement.pos_y;
+        droplets.new_height[i] = movement.new_height;
+        droplets.delta_height[i] = movement.delta_height;
+        if !movement.alive {
+            droplets.alive[i] = false;
+            outputs.record_death(droplets.from_x[i], droplets.from_y[i]);
+        }
+    }
+}
+
+/// What the erosion of a step reads from the state, which can be shared between threads unlike
+/// the random generator.
+struct Step<'a> {
+    params: &'a Parameters,
+    brush: &'a Brush,
+    wrap: bool,
+}
+
+/// Sediment laid down on (positive) or taken from (negative) cells by one droplet.
+type Changes = Vec<(usize, usize, f32)>;
+
+/// Works out what droplet `i` deposits or erodes at the cell it came from, against the heightmap
+/// as it is, into `changes`. Returns the sediment the droplet carries afterwards.
+fn erosion<P: Precision>(
+    droplets: &Droplets,
+    i: usize,
+    step: &Step,
+    heightmap: &Heightmap<P>,
+    dissolves: bool,
+    changes: &mut Changes,
+) -> f32 {
+    let params = step.params;
+    let node_x = droplets.from_x[i].floor() as usize;
+    let node_y = droplets.from_y[i].floor() as usize;
+    let cell_offset_x = droplets.from_x[i] - node_x as f32;
+    let cell_offset_y = droplets.from_y[i] - node_y as f32;
+    let delta_height = droplets.delta_height[i];
+    let speed = droplets.speed[i];
+    let water = droplets.water[i];
+    let mut sediment = droplets.sediment[i];
+
+    // Roots hold the soil, plants slow erosion and lower what the droplet can carry
+    let cover = heightmap.vegetation_at(node_x, node_y);
+    let sediment_capacity =
+        (-delta_height * speed * water * params.sediment_capacity_factor * (1.0 - cover))
+            .max(params.min_sediment_capacity);
+
+    if dissolves || sediment > sediment_capacity || delta_height > 0.0 {
+        // Reaching the sea drops everything at the shore
+        let amount_to_deposit = if dissolves {
+            sediment
+        } else if delta_height > 0.0 {
+            delta_height.min(sediment)
+        } else {
+            (sediment - sediment_capacity) * params.deposit_speed
+        };
+        sediment -= amount_to_deposit;
+
+        let next_x = next_cell(node_x, heightmap.width, step.wrap);
+        let next_y = next_cell(node_y, heightmap.height, step.wrap);
+        for (corner_x, corner_y, weight) in [
+            (
+                node_x,
+                node_y,
+                (1.0 - cell_offset_x) * (1.0 - cell_offset_y),
+            ),
+            (next_x, node_y, cell_offset_x * (1.0 - cell_offset_y)),
+            (node_x, next_y, (1.0 - cell_offset_x) * cell_offset_y),
+            (next_x, next_y, cell_offset_x * cell_offset_y),
+        ] {
+            changes.push((corner_x, corner_y, amount_to_deposit * weight));
+        }
+    } else {
+        let amount_to_erode = ((sediment_capacity - sediment) * params.erode_speed * (1.0 - cover))
+            .min(-delta_height);
+
+        let map_size = (heightmap.width, heightmap.height);
+        step.brush
+            .for_each((node_x, node_y), map_size, step.wrap, |x, y, weight| {
+                let weighted_erode_amount = amount_to_erode
+                    * weight
+                    * (1.0 - heightmap.hardness_at(x, y))
+                    * heightmap.erodibility_at(x, y);
+                let delta_sediment = heightmap.data[x][y].to_f32().min(weighted_erode_amount);
+                changes.push((x, y, -delta_sediment));
+                sediment += delta_sediment;
+            });
+    }
+    sediment
+}
+
+/// Applies the changes of one droplet and returns the sediment it planned to take but could not,
+/// since droplets of a parallel step plan against the same terrain and may take from one cell.
+fn apply_changes<P: Precision>(
+    heightmap: &mut Heightmap<P>,
+    changes: &Changes,
+    outputs: &mut ErosionOutputs,
+) -> f32 {
+    let mut missing = 0.0;
+    for &(x, y, amount) in changes {
+        if amount >= 0.0 {
+            heightmap.add_sediment(x, y, P::from_f32(amount));
+            outputs.record_deposition(x, y, amount);
+        } else {
+            let taken = heightmap.data[x][y].to_f32().min(-amount);
+            heightmap.remove_material(x, y, P::from_f32(taken));
+            outputs.record_erosion(x, y, taken);
+            missing += -amount - taken;
+        }
+    }
+    missing
+}
+
+/// Lets every droplet that moved erode or deposit at the cell it came from. One after another,
+/// or with `parallel` all planned on all cores against the terrain before the step and then
+/// applied in order, so no droplet waits on another and the result does not depend on threads.
+fn erode_droplets<P: Precision>(
+    droplets: &mut Droplets,
+    state: &State,
+    heightmap: &mut Heightmap<P>,
+    drop_zone: &DropZone,
+    outputs: &mut ErosionOutputs,
+) {
+    let step = Step {
+        params: &state.params,
+        brush: &state.brush,
+        wrap: state.wrap,
+    };
+    if state.params.parallel {
+        let planned: Vec<(usize, bool, f32, Changes)> = {
+            let (droplets, heightmap) = (&*droplets, &*heightmap);
+            (0..droplets.len())
+                .into_par_iter()
+                .filter(|&i| droplets.alive[i])
+                .map(|i| {
+                    let dissolves = drop_zone.is_submerged(droplets.new_height[i]);
+                    let mut changes = Changes::new();
+                    let sediment = erosion(droplets, i, &step, heightmap, dissolves, &mut changes);
+                    (i, dissolves, sediment, changes)
+                })
+                .collect()
+        };
+        for (i, dissolves, sediment, changes) in planned {
+            let missing = apply_changes(heightmap, &changes, outputs);
+            finish_step(droplets, i, state, dissolves, sediment - missing, outputs);
+        }
+    } else {
+        let mut changes = Changes::new();
+        for i in 0..droplets.len() {
+            if !droplets.alive[i] {
+                continue;
+            }
+            let dissolves = drop_zone.is_submerged(droplets.new_height[i]);
+            changes.clear();
+            let sediment = erosion(droplets, i, &step, heightmap, dissolves, &mut changes);
+            apply_changes(heightmap, &changes, outputs);
+            finish_step(droplets, i, state, dissolves, sediment, outputs);
+        }
+    }
+}
+
+/// Stores the sediment of droplet `i` and slows and evaporates it, or lets it die.
+fn finish_step(
+    droplets: &mut Droplets,
+    i: usize,
+    state: &State,
+    dissolves: bool,
+    sediment: f32,
+    outputs: &mut ErosionOutputs,
+) {
+    let params = &state.params;
+    droplets.sediment[i] = sediment;
+    if dissolves {
+        droplets.alive[i] = false;
+        outputs.record_death(droplets.pos_x[i], droplets.pos_y[i]);
+        return;
+    }
+
+    let speed = droplets.speed[i];
+    let speed = (speed * speed + droplets.delta_height[i] * params.gravity).sqrt();
+    let water = droplets.water[i] * (1.0 - params.evaporate_speed);
+    droplets.speed[i] = speed;
+    droplets.water[i] = water;
+    if water < params.min_water || speed < params.min_speed {
+        droplets.alive[i] = false;
+        outputs.record_death(droplets.pos_x[i], droplets.pos_y[i]);
+    }
+}
+
+/// `coordinate` moved back inside `[0, size)` from the opposite side.
+fn wrap_coordinate(coordinate: f32, size: usize) -> f32 {
+    let wrapped = coordinate.rem_euclid(size as f32);
+    // Tiny negative values round up to `size` itself
+    if wrapped >= size as f32 {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+fn next_cell(coordinate: usize, size: usize, wrap: bool) -> usize {
+    if wrap {
+        (coordinate + 1) % size
+    } else {
+        coordinate + 1
+    }
+}
+
+fn calculate_height_and_gradient<P: Precision>(
+    heightmap: &Heightmap<P>,
+    pos_x: f32,
+    pos_y: f32,
+    wrap: bool,
+) -> HeightAndGradient<P> {
+    let coord_x = pos_x as usize;
+    let coord_y = pos_y as usize;
+    let next_x = next_cell(coord_x, heightmap.width, wrap);
+    let next_y = next_cell(coord_y, heightmap.height, wrap);
+
+    let x = P::from_f32(pos_x - coord_x as f32);
+    let y = P::from_f32(pos_y - coord_y as f32);
+    let one = P::from_f32(1.0);
+
+    let height_nw = heightmap.data[coord_x + 0][coord_y + 0];
+    let height_ne = heightmap.data[next_x][coord_y + 0];
+    let height_sw = heightmap.data[coord_x + 0][next_y];
+    let height_se = heightmap.data[next_x][next_y];
+
+    let gradient_x = (height_ne - height_nw) * (one - y) + (height_se - height_sw) * y;
+    let gradient_y = (height_sw - height_nw) * (one - x) + (height_se - height_ne) * x;
+
+    let height = height_nw * (one - x) * (one - y)
+        + height_ne * x * (one - y)
+        + height_sw * (one - x) * y
+        + height_se * x * y;
+
+    HeightAndGradient {
+        height,
+        gradient_x: gradient_x.to_f32(),
+        gradient_y: gradient_y.to_f32(),
+    }
+}
+
+struct HeightAndGradient<P: Precision> {
+    height: P,
+    gradient_x: f32,
+    gradient_y: f32,
+}
+
+pub fn add_metadata<P: Precision>(params: &Parameters, heightmap: &mut Heightmap<P>) {
+    heightmap.metadata_add("EROSION_RADIUS", params.erosion_radius.to_string());
+    heightmap.metadata_add("INERTIA", params.inertia.to_string());
+    heightmap.metadata_add(
+        "SEDIMENT_CAPACITY_FACTOR",
+        params.sediment_capacity_factor.to_string(),
+    );
+    heightmap.metadata_add(
+        "MIN_SEDIMENT_CAPACITY",
+        params.min_sediment_capacity.to_string(),
+    );
+    heightmap.metadata_add("ERODE_SPEED", params.erode_speed.to_string());
+    heightmap.metadata_add("DEPOSIT_SPEED", params.deposit_speed.to_string());
+    heightmap.metadata_add("EVAPORATE_SPEED", params.evaporate_speed.to_string());
+    heightmap.metadata_add("GRAVITY", params.gravity.to_string());
+    heightmap.metadata_add(
+        "MAX_DROPLET_LIFETIME",
+        params.max_droplet_lifetime.to_string(),
+    );
+    heightmap.metadata_add(
+        "INITIAL_WATER_VOLUME",
+        params.initial_water_volume.to_string(),
+    );
+    heightmap.metadata_add("INITIAL_SPEED", params.initial_speed.to_string());
+    heightmap.metadata_add("MIN_WATER", params.min_water.to_string());
+    heightmap.metadata_add("MIN_SPEED", params.min_speed.to_string());
+    heightmap.metadata_add("BRUSH_KERNEL", params.brush_kernel.to_string());
+    heightmap.metadata_add("WRAP", params.wrap.to_string());
+    heightmap.metadata_add("BATCH_SIZE", params.effective_batch_size().to_string());
+    heightmap.metadata_add("DOUBLE_PRECISION", params.double_precision.to_string());
+    heightmap.metadata_add("PARALLEL", params.parallel.to_string());
+    if let Some(seed) = params.seed {
+        heightmap.metadata_add("SEED", seed.to_string());
+    }
+    heightmap.metadata_add("NUM_ITERATIONS", params.num_iterations.to_string());
+    heightmap.metadata_add("HARDNESS_MAP", heightmap.hardness.is_some().to_string());
+    heightmap.metadata_add("VEGETATION_MAP", heightmap.vegetation.is_some().to_string());
+    heightmap.metadata_add("MATERIAL_LAYERS", heightmap.layers.is_some().to_string());
+}
+
+/// One configuration timed by `benchmark`, `speedup` is relative to the first one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkRun {
+    pub size: usize,
+    pub droplets: usize,
+    pub batch_size: usize,
+    pub parallel: bool,
+    pub seconds: f32,
+    pub speedup: f32,
+}
+
+impl std::fmt::Display for BenchmarkRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}x{}, {} droplets, batch size {}{}: {:.2} s ({:.2}x)",
+            self.size,
+            self.size,
+            self.droplets,
+            self.batch_size,
+            if self.parallel { " in parallel" } else { "" },
+            self.seconds,
+            self.speedup
+        )
+    }
+}
+
+/// Times the droplet loop on a `size`² map for a few batch sizes, one after another and in
+/// parallel, run with `--bench-droplets`.
+pub fn benchmark(size: usize) -> Vec<BenchmarkRun> {
+    const ITERATIONS: usize = 200_000;
+    let mut heightmap_type = HeightmapType::default();
+    heightmap_type.params_mut().size = size;
+    let heightmap = create_heightmap_from_preset(&heightmap_type);
+    let drop_zone = DropZone::default(&heightmap);
+
+    let mut baseline = None;
+    let mut runs = Vec::new();
+    for (batch_size, parallel) in [
+        (0, false),
+        (16, false),
+        (64, false),
+        (256, false),
+        (256, true),
+        (1024, true),
+    ] {
+        let params = Parameters {
+            num_iterations: ITERATIONS,
+            record_flow: false,
+            batch_size,
+            parallel,
+            ..Default::default()
+        };
+        let mut eroded = heightmap.clone();
+        let time = std::time::Instant::now();
+        erode(&mut eroded, &params, &drop_zone, &Progress::default());
+        let seconds = time.elapsed().as_secs_f32();
+        let baseline = *baseline.get_or_insert(seconds);
+        runs.push(BenchmarkRun {
+            size,
+            droplets: ITERATIONS,
+            batch_size,
+            parallel,
+            seconds,
+            speedup: baseline / seconds,
+        });
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{check_heightmap, tiny_heightmap, Check, ITERATIONS, SIZE};
+    use std::panic;
+
+    #[test]
+    fn erosion_presets() -> Check {
+        let presets = panic::catch_unwind(crate::erode::Parameters::presets)
+            .map_err(|_| "built-in presets do not parse".to_string())?;
+        if presets.is_empty() {
+            return Err("no built-in presets".to_string());
+        }
+        if crate::erode::Parameters::preset("default") != Some(Default::default()) {
+            return Err("default preset does not match the default parameters".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "export")]
+    fn double_precision() -> Check {
+        use crate::fixtures::temp_path;
+        use crate::heightmap::io::{export_data, DataFormat};
+        use std::fs;
+        // A slope far too gentle for 32 bit floats this high up, where it rounds to a flat plane
+        let data = (0..SIZE)
+            .map(|x| vec![1000.0 - x as f64 * 1e-7; SIZE])
+            .collect();
+        let precise = Heightmap::<f64>::with_precision(data, SIZE, SIZE, 2000.0, 2000.0, None);
+        let single: Heightmap = precise.to_precision();
+        if single.data.iter().flatten().any(|&h| h != 1000.0) {
+            return Err("the slope survived rounding to 32 bits".to_string());
+        }
+        if single.to_precision::<f64>().to_precision::<f32>().data != single.data {
+            return Err("converting back and forth changed the heights".to_string());
+        }
+
+        let params = crate::erode::Parameters {
+            num_iterations: ITERATIONS,
+            ..Default::default()
+        };
+        let drop_zone = DropZone::default(&single);
+        let mut eroded_single = single.clone();
+        erode(
+            &mut eroded_single,
+            &params,
+            &drop_zone,
+            &Progress::default(),
+        );
+        if eroded_single.data != single.data {
+            return Err("droplets flowed on flat 32 bit terrain".to_string());
+        }
+        let mut eroded_precise = precise.clone();
+        erode(
+            &mut eroded_precise,
+            &params,
+            &drop_zone,
+            &Progress::default(),
+        );
+        if eroded_precise.data == precise.data {
+            return Err("droplets did not follow the 64 bit slope".to_string());
+        }
+
+        let path = temp_path("precise");
+        let path = path.to_string_lossy();
+        let file = format!("{}.{}", path, DataFormat::Npy.extension());
+        let npy = export_data(&precise, &path, DataFormat::Npy)
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|_| fs::read(&file).map_err(|err| format!("{:?}", err)));
+        let _ = fs::remove_file(&file);
+        let npy = npy?;
+        let header = 10 + u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        if !String::from_utf8_lossy(&npy[..header]).contains("'<f8'")
+            || npy.len() - header != SIZE * SIZE * 8
+        {
+            return Err("npy export did not keep 64 bit heights".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_droplets() -> Check {
+        let heightmap = tiny_heightmap();
+        // Many droplets on a tiny map, so those of a step keep eroding the same cells
+        let params = crate::erode::Parameters {
+            num_iterations: ITERATIONS * 10,
+            batch_size: 256,
+            parallel: true,
+            ..Default::default()
+        };
+        let mut eroded = heightmap.clone();
+        let outputs = erode(
+            &mut eroded,
+            &params,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE, SIZE)?;
+        if eroded.data == heightmap.data {
+            return Err("terrain was not changed".to_string());
+        }
+        let (taken, laid) = match (&outputs.eroded, &outputs.deposited) {
+            (Some(taken), Some(laid)) => (taken, laid),
+            _ => return Err("erosion and deposition were not recorded".to_string()),
+        };
+        for (x, y, h) in eroded.iter_cells() {
+            let expected = heightmap.data[x][y] - taken[x][y] + laid[x][y];
+            if (h - expected).abs() > 1e-4 {
+                return Err(format!(
+                    "cell ({}, {}) is {} but the recorded changes give {}",
+                    x, y, h, expected
+                ));
+            }
+            if h < 0.0 && heightmap.data[x][y] >= 0.0 {
+                return Err(format!("cell ({}, {}) was eroded below 0", x, y));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn brush_kernels() -> Check {
+        use crate::erode::lague::{Brush, BrushKernel};
+        use std::sync::Arc;
+        // Narrower than the largest brushes, so they wrap onto themselves
+        let (width, height) = (20, 13);
+        for kernel in BrushKernel::list() {
+            for radius in 2..=8 {
+                let brush = Brush::new(radius, kernel);
+                for wrap in [false, true] {
+                    for centre in [(0, 0), (10, 6), (19, 12), (3, 11), (17, 2)] {
+                        let mut applied = vec![vec![0.0; height]; width];
+                        brush.for_each(centre, (width, height), wrap, |x, y, weight| {
+                            applied[x][y] += weight
+                        });
+
+                        // Every cell within the radius weighted on its own, then normalized
+                        let mut reference = vec![vec![0.0; height]; width];
+                        let r = radius as i32;
+                        for dx in -r..=r {
+                            for dy in -r..=r {
+                                let (x, y) = (centre.0 as i32 + dx, centre.1 as i32 + dy);
+                                let outside =
+                                    x < 0 || y < 0 || x >= width as i32 || y >= height as i32;
+                                if dx * dx + dy * dy >= r * r || (outside && !wrap) {
+                                    continue;
+                                }
+                                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                                let (x, y) = (
+                                    x.rem_euclid(width as i32) as usize,
+                                    y.rem_euclid(height as i32) as usize,
+                                );
+                                reference[x][y] += kernel.weight(distance, radius as f32);
+                            }
+                        }
+                        let sum: f32 = reference.iter().flatten().sum();
+
+                        for x in 0..width {
+                            for y in 0..height {
+                                if (applied[x][y] - reference[x][y] / sum).abs() > 1e-5 {
+                                    return Err(format!(
+                                        "{} brush of radius {} at {:?} (wrap {}) weighs ({}, {}) {} instead of {}",
+                                        kernel,
+                                        radius,
+                                        centre,
+                                        wrap,
+                                        x,
+                                        y,
+                                        applied[x][y],
+                                        reference[x][y] / sum
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // Runs share the brush of the same radius and kernel instead of building it again
+        let brush = Brush::cached(4, BrushKernel::Gaussian);
+        if !Arc::ptr_eq(&brush, &Brush::cached(4, BrushKernel::Gaussian))
+            || Arc::ptr_eq(&brush, &Brush::cached(4, BrushKernel::Cosine))
+            || Arc::ptr_eq(&brush, &Brush::cached(5, BrushKernel::Gaussian))
+        {
+            return Err("brushes are not cached by radius and kernel".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn automatic_batch_size() -> Check {
+        let heightmap = tiny_heightmap();
+        let erode_with = |batch_size| {
+            let params = crate::erode::Parameters {
+                num_iterations: ITERATIONS,
+                batch_size,
+                parallel: true,
+                seed: Some(7),
+                ..Default::default()
+            };
+            let mut eroded = heightmap.clone();
+            let drop_zone = DropZone::default(&heightmap);
+            erode(&mut eroded, &params, &drop_zone, &Progress::default());
+            eroded.content_hash()
+        };
+        if erode_with(0) != erode_with(PARALLEL_BATCH_SIZE) {
+            return Err("parallel droplets without a batch size are not batched".to_string());
+        }
+        Ok(())
+    }
+}
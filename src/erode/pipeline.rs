@@ -0,0 +1,105 @@
+use crate::erode::Model;
+use crate::heightmap::Heightmap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/*
+Several erosion passes run back to back on the same heightmap, each with its own backend and
+parameters, e.g. wind to soften the relief before the droplets carve it and glacial flow to finish
+the valleys. The passes share the partitioning and margins of a single simulation so the result is
+one eroded state, see `Method::erode_pipeline`.
+ */
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ErosionPipeline {
+    pub passes: Vec<Model>,
+}
+
+impl ErosionPipeline {
+    pub fn new(passes: Vec<Model>) -> Self {
+        ErosionPipeline { passes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    pub fn num_iterations(&self) -> usize {
+        self.passes.iter().map(|model| model.num_iterations()).sum()
+    }
+
+    /// The backends of the passes in the order they run.
+    pub fn describe(&self) -> String {
+        self.passes
+            .iter()
+            .map(|model| format!("{} x{}", model.backend(), model.num_iterations()))
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    /// Records the pipeline, the parameters of the last pass are recorded like a single simulation.
+    pub fn add_metadata(&self, heightmap: &mut Heightmap) {
+        if let Some(last) = self.passes.last() {
+            last.add_metadata(heightmap);
+        }
+        heightmap.metadata_add("PIPELINE", self.describe());
+    }
+
+    pub fn move_up(&mut self, index: usize) {
+        if index > 0 && index < self.passes.len() {
+            self.passes.swap(index - 1, index);
+        }
+    }
+
+    pub fn move_down(&mut self, index: usize) {
+        if index + 1 < self.passes.len() {
+            self.passes.swap(index, index + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::erode::{DropZone, Model, Progress};
+    use crate::fixtures::{
+        check_heightmap, tiny_heightmap, tiny_model, Check, GRID_SIZE, ITERATIONS,
+    };
+    use crate::partitioning::{MarginMode, Method};
+
+    #[test]
+    fn erosion_pipeline() -> Check {
+        let heightmap = tiny_heightmap();
+        let wind = Model::Wind(Default::default()).with_iterations(ITERATIONS);
+        let droplets = tiny_model();
+        let pipeline = ErosionPipeline::new(vec![wind, droplets, wind]);
+        let method = Method::Subdivision(GRID_SIZE);
+        let drop_zone = DropZone::default(&heightmap);
+        let (single, _) = method.erode_with_margin(
+            MarginMode::Automatic,
+            false,
+            &heightmap,
+            &droplets,
+            &drop_zone,
+            &Progress::default(),
+        );
+        let (eroded, _) = method.erode_pipeline(
+            MarginMode::Automatic,
+            false,
+            &heightmap,
+            &pipeline,
+            &drop_zone,
+            &Progress::default(),
+        );
+        // The margins are cropped once for the whole pipeline, not once per pass
+        check_heightmap(&eroded, single.width, single.height)?;
+        let recorded = eroded
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("PIPELINE").cloned());
+        if recorded != Some(pipeline.describe()) {
+            return Err(format!("recorded pipeline {:?}", recorded));
+        }
+        Ok(())
+    }
+}
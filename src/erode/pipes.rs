@@ -0,0 +1,331 @@
+use crate::erode::{DropZone, ErosionOutputs, Progress};
+use crate::heightmap::*;
+use crate::math::Vector2;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/*
+Grid based hydraulic erosion using the virtual pipes shallow water model from
+Mei, Decaudin & Hu, "Fast Hydraulic Erosion Simulation and Visualization on GPU" (2007).
+Every cell holds a water column connected to its four neighbours by pipes, so water can pool
+into lakes instead of disappearing like droplets do. Internally heights are measured in cell
+lengths, `height_scale` converts heightmap units into cell lengths.
+ */
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "PipesParameters")]
+pub struct Parameters {
+    pub time_step: f32,         // [0.001, 0.2], 0.05
+    pub rain_rate: f32,         // [0, 0.1], 0.01
+    pub pipe_area: f32,         // [0.1, 20], 1
+    pub gravity: f32,           // [0, 20], 9.81
+    pub height_scale: f32,      // [1, 512], 64
+    pub sediment_capacity: f32, // [0, 4], 1
+    pub dissolving: f32,        // [0, 2], 0.5
+    pub deposition: f32,        // [0, 2], 1
+    pub evaporation: f32,       // [0, 1], 0.015
+    pub min_tilt: f32,          // [0, 1], 0.05
+    pub record_flow: bool,      // true
+    pub num_iterations: usize,  // [1, 10000], 500
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            time_step: 0.05,
+            rain_rate: 0.01,
+            pipe_area: 1.0,
+            gravity: 9.81,
+            height_scale: 64.0,
+            sediment_capacity: 1.0,
+            dissolving: 0.5,
+            deposition: 1.0,
+            evaporation: 0.015,
+            min_tilt: 0.05,
+            record_flow: true,
+            num_iterations: 500,
+        }
+    }
+}
+
+/// Outflow through the left, right, top and bottom pipes of a cell.
+#[derive(Clone, Copy, Default)]
+struct Flux {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+impl Flux {
+    fn total(&self) -> f32 {
+        self.left + self.right + self.top + self.bottom
+    }
+}
+
+struct Grid {
+    width: usize,
+    height: usize,
+    terrain: Vec<f32>,
+    water: Vec<f32>,
+    sediment: Vec<f32>,
+    flux: Vec<Flux>,
+    velocity: Vec<Vector2>,
+}
+
+impl Grid {
+    fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    /// Bilinear sample of the sediment field, clamped to the grid.
+    fn sample_sediment(&self, x: f32, y: f32) -> f32 {
+        let x = x.clamp(0.0, (self.width - 1) as f32);
+        let y = y.clamp(0.0, (self.height - 1) as f32);
+        let x0 = (x as usize).min(self.width - 2);
+        let y0 = (y as usize).min(self.height - 2);
+        let u = x - x0 as f32;
+        let v = y - y0 as f32;
+        self.sediment[self.index(x0, y0)] * (1.0 - u) * (1.0 - v)
+            + self.sediment[self.index(x0 + 1, y0)] * u * (1.0 - v)
+            + self.sediment[self.index(x0, y0 + 1)] * (1.0 - u) * v
+            + self.sediment[self.index(x0 + 1, y0 + 1)] * u * v
+    }
+}
+
+pub fn erode(
+    heightmap: &mut Heightmap,
+    params: &Parameters,
+    drop_zone: &DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let mut outputs = ErosionOutputs::new(heightmap, params.record_flow);
+    add_metadata(params, heightmap);
+
+    let width = heightmap.width;
+    let height = heightmap.height;
+    if width < 3 || height < 3 {
+        return outputs;
+    }
+
+    let scale = params.height_scale;
+    let mut terrain = vec![0.0; width * height];
+    let mut rain = vec![0.0; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            terrain[y * width + x] = heightmap.data[x][y] * scale;
+            rain[y * width + x] = drop_zone
+                .validator
+                .weight(heightmap, &Vector2::new(x as f32, y as f32))
+                * params.rain_rate;
+        }
+    }
+    let mut grid = Grid {
+        width,
+        height,
+        terrain,
+        water: vec![0.0; width * height],
+        sediment: vec![0.0; width * height],
+        flux: vec![Flux::default(); width * height],
+        velocity: vec![Vector2::new(0.0, 0.0); width * height],
+    };
+
+    let dt = params.time_step;
+    for _iteration in 0..params.num_iterations {
+        if !progress.tick() {
+            break;
+        }
+        // Rain
+        for (water, rain) in grid.water.iter_mut().zip(rain.iter()) {
+            *water += dt * rain;
+        }
+
+        update_flux(&mut grid, params);
+        update_water(&mut grid, &mut outputs, dt);
+        erode_and_deposit(&mut grid, heightmap, &mut outputs, params);
+        transport_sediment(&mut grid, dt);
+
+        for water in grid.water.iter_mut() {
+            *water *= 1.0 - params.evaporation * dt;
+        }
+    }
+
+    // Whatever is still suspended settles where the water is
+    for y in 0..height {
+        for x in 0..width {
+            let i = grid.index(x, y);
+            let amount = grid.sediment[i] / scale;
+            if amount > 0.0 {
+                heightmap.add_sediment(x, y, amount);
+                outputs.record_deposition(x, y, amount);
+            }
+        }
+    }
+
+    outputs
+}
+
+fn update_flux(grid: &mut Grid, params: &Parameters) {
+    let dt = params.time_step;
+    let k = dt * params.pipe_area * params.gravity;
+    let surface = |grid: &Grid, x: usize, y: usize| {
+        let i = grid.index(x, y);
+        grid.terrain[i] + grid.water[i]
+    };
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            let i = grid.index(x, y);
+            let h = surface(grid, x, y);
+            let mut flux = grid.flux[i];
+            // Border pipes are closed, water leaves the map only by evaporating
+            flux.left = if x > 0 {
+                (flux.left + k * (h - surface(grid, x - 1, y))).max(0.0)
+            } else {
+                0.0
+            };
+            flux.right = if x + 1 < grid.width {
+                (flux.right + k * (h - surface(grid, x + 1, y))).max(0.0)
+            } else {
+                0.0
+            };
+            flux.top = if y > 0 {
+                (flux.top + k * (h - surface(grid, x, y - 1))).max(0.0)
+            } else {
+                0.0
+            };
+            flux.bottom = if y + 1 < grid.height {
+                (flux.bottom + k * (h - surface(grid, x, y + 1))).max(0.0)
+            } else {
+                0.0
+            };
+
+            // Never let more water out than the cell holds
+            let total = flux.total();
+            if total > 0.0 {
+                let scaling = (grid.water[i] / (total * dt)).min(1.0);
+                flux.left *= scaling;
+                flux.right *= scaling;
+                flux.top *= scaling;
+                flux.bottom *= scaling;
+            }
+            grid.flux[i] = flux;
+        }
+    }
+}
+
+fn update_water(grid: &mut Grid, outputs: &mut ErosionOutputs, dt: f32) {
+    let dt_volume: Vec<f32> = (0..grid.width * grid.height)
+        .map(|i| {
+            let x = i % grid.width;
+            let y = i / grid.width;
+            let inflow_left = if x > 0 { grid.flux[i - 1].right } else { 0.0 };
+            let inflow_right = if x + 1 < grid.width {
+                grid.flux[i + 1].left
+            } else {
+                0.0
+            };
+            let inflow_top = if y > 0 {
+                grid.flux[i - grid.width].bottom
+            } else {
+                0.0
+            };
+            let inflow_bottom = if y + 1 < grid.height {
+                grid.flux[i + grid.width].top
+            } else {
+                0.0
+            };
+            let flux = grid.flux[i];
+
+            // Average flow through the cell in each direction gives the velocity field
+            let water_x = (inflow_left - flux.left + flux.right - inflow_right) / 2.0;
+            let water_y = (inflow_top - flux.top + flux.bottom - inflow_bottom) / 2.0;
+            grid.velocity[i] = Vector2::new(water_x, water_y);
+
+            (inflow_left + inflow_right + inflow_top + inflow_bottom - flux.total()) * dt
+        })
+        .collect();
+
+    for (i, volume) in dt_volume.iter().enumerate() {
+        let previous = grid.water[i];
+        grid.water[i] = (previous + volume).max(0.0);
+        let mean_depth = (previous + grid.water[i]) / 2.0;
+        let through = grid.velocity[i];
+        grid.velocity[i] = if mean_depth > 1e-4 {
+            through * (1.0 / mean_depth)
+        } else {
+            Vector2::new(0.0, 0.0)
+        };
+        outputs.record_flow(i % grid.width, i / grid.width, through.magnitude());
+    }
+}
+
+fn erode_and_deposit(
+    grid: &mut Grid,
+    heightmap: &mut Heightmap,
+    outputs: &mut ErosionOutputs,
+    params: &Parameters,
+) {
+    let scale = params.height_scale;
+    let dt = params.time_step;
+    for y in 1..grid.height - 1 {
+        for x in 1..grid.width - 1 {
+            let i = grid.index(x, y);
+            let dx = (grid.terrain[i + 1] - grid.terrain[i - 1]) / 2.0;
+            let dy = (grid.terrain[i + grid.width] - grid.terrain[i - grid.width]) / 2.0;
+            let slope = (dx * dx + dy * dy).sqrt();
+            let sin_tilt = (slope / (1.0 + slope * slope).sqrt()).max(params.min_tilt);
+
+            let capacity = params.sediment_capacity * sin_tilt * grid.velocity[i].magnitude();
+            if capacity > grid.sediment[i] {
+                let wanted = params.dissolving * (capacity - grid.sediment[i]) * dt;
+                let wanted = wanted / scale
+                    * (1.0 - heightmap.hardness_at(x, y))
+                    * heightmap.erodibility_at(x, y);
+                let removed = heightmap.data[x][y].min(wanted).max(0.0);
+                heightmap.remove_material(x, y, removed);
+                outputs.record_erosion(x, y, removed);
+                grid.terrain[i] -= removed * scale;
+                grid.sediment[i] += removed * scale;
+            } else {
+                let amount = params.deposition * (grid.sediment[i] - capacity) * dt;
+                let amount = amount.min(grid.sediment[i]);
+                heightmap.add_sediment(x, y, amount / scale);
+                outputs.record_deposition(x, y, amount / scale);
+                grid.terrain[i] += amount;
+                grid.sediment[i] -= amount;
+            }
+        }
+    }
+}
+
+/// Semi-Lagrangian advection, every cell takes the sediment from where its water came from.
+fn transport_sediment(grid: &mut Grid, dt: f32) {
+    let sediment: Vec<f32> = (0..grid.width * grid.height)
+        .map(|i| {
+            let x = (i % grid.width) as f32;
+            let y = (i / grid.width) as f32;
+            let velocity = grid.velocity[i];
+            grid.sample_sediment(x - velocity.x * dt, y - velocity.y * dt)
+        })
+        .collect();
+    grid.sediment = sediment;
+}
+
+pub fn add_metadata(params: &Parameters, heightmap: &mut Heightmap) {
+    heightmap.metadata_add("PIPES_TIME_STEP", params.time_step.to_string());
+    heightmap.metadata_add("PIPES_RAIN_RATE", params.rain_rate.to_string());
+    heightmap.metadata_add("PIPES_PIPE_AREA", params.pipe_area.to_string());
+    heightmap.metadata_add("PIPES_GRAVITY", params.gravity.to_string());
+    heightmap.metadata_add("PIPES_HEIGHT_SCALE", params.height_scale.to_string());
+    heightmap.metadata_add(
+        "PIPES_SEDIMENT_CAPACITY",
+        params.sediment_capacity.to_string(),
+    );
+    heightmap.metadata_add("PIPES_DISSOLVING", params.dissolving.to_string());
+    heightmap.metadata_add("PIPES_DEPOSITION", params.deposition.to_string());
+    heightmap.metadata_add("PIPES_EVAPORATION", params.evaporation.to_string());
+    heightmap.metadata_add("PIPES_MIN_TILT", params.min_tilt.to_string());
+    heightmap.metadata_add("NUM_ITERATIONS", params.num_iterations.to_string());
+    heightmap.metadata_add("HARDNESS_MAP", heightmap.hardness.is_some().to_string());
+    heightmap.metadata_add("MATERIAL_LAYERS", heightmap.layers.is_some().to_string());
+}
@@ -0,0 +1,146 @@
+use crate::erode::Parameters;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/*
+Ranges for every numeric field of the droplet `Parameters`, for batch experiments that sweep or
+jitter the erosion settings. A point in the unit hypercube maps to one set of parameters, which
+can be drawn uniformly at random or as a Latin hypercube where every range is split into as many
+strata as there are samples and each stratum is used exactly once. Fields without a range, like
+the brush kernel or wrapping, are copied from `base`.
+ */
+
+/// Inclusive range of a parameter, a single value when `min == max`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Range {
+    pub fn new(min: f32, max: f32) -> Self {
+        Range { min, max }
+    }
+
+    pub fn fixed(value: f32) -> Self {
+        Range::new(value, value)
+    }
+
+    /// `value` give or take `fraction` of itself.
+    pub fn jitter(value: f32, fraction: f32) -> Self {
+        let spread = (value * fraction).abs();
+        Range::new(value - spread, value + spread)
+    }
+
+    /// The value `t` of the way from `min` to `max`, `t` in [0, 1].
+    pub fn at(&self, t: f32) -> f32 {
+        self.min + (self.max - self.min) * t
+    }
+}
+
+pub const DIMENSIONS: usize = 14;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParameterSpace {
+    pub base: Parameters,
+    pub erosion_radius: Range,
+    pub inertia: Range,
+    pub sediment_capacity_factor: Range,
+    pub min_sediment_capacity: Range,
+    pub erode_speed: Range,
+    pub deposit_speed: Range,
+    pub evaporate_speed: Range,
+    pub gravity: Range,
+    pub max_droplet_lifetime: Range,
+    pub initial_water_volume: Range,
+    pub initial_speed: Range,
+    pub num_iterations: Range,
+    pub min_water: Range,
+    pub min_speed: Range,
+}
+
+impl Default for ParameterSpace {
+    fn default() -> Self {
+        ParameterSpace::fixed(Parameters::default())
+    }
+}
+
+impl ParameterSpace {
+    /// The space holding only `params`, widen single ranges from here.
+    pub fn fixed(params: Parameters) -> Self {
+        ParameterSpace::jitter(params, 0.0)
+    }
+
+    /// Every numeric parameter varies by up to `fraction` of its value in `params`.
+    pub fn jitter(params: Parameters, fraction: f32) -> Self {
+        ParameterSpace {
+            base: params,
+            erosion_radius: Range::jitter(params.erosion_radius as f32, fraction),
+            inertia: Range::jitter(params.inertia, fraction),
+            sediment_capacity_factor: Range::jitter(params.sediment_capacity_factor, fraction),
+            min_sediment_capacity: Range::jitter(params.min_sediment_capacity, fraction),
+            erode_speed: Range::jitter(params.erode_speed, fraction),
+            deposit_speed: Range::jitter(params.deposit_speed, fraction),
+            evaporate_speed: Range::jitter(params.evaporate_speed, fraction),
+            gravity: Range::jitter(params.gravity, fraction),
+            max_droplet_lifetime: Range::jitter(params.max_droplet_lifetime as f32, fraction),
+            initial_water_volume: Range::jitter(params.initial_water_volume, fraction),
+            initial_speed: Range::jitter(params.initial_speed, fraction),
+            num_iterations: Range::jitter(params.num_iterations as f32, fraction),
+            min_water: Range::jitter(params.min_water, fraction),
+            min_speed: Range::jitter(params.min_speed, fraction),
+        }
+    }
+
+    /// The parameters at `point` in the unit hypercube, counts are rounded and kept positive.
+    pub fn at(&self, point: [f32; DIMENSIONS]) -> Parameters {
+        let count = |range: &Range, t: f32| range.at(t).round().max(1.0) as usize;
+        Parameters {
+            erosion_radius: count(&self.erosion_radius, point[0]),
+            inertia: self.inertia.at(point[1]),
+            sediment_capacity_factor: self.sediment_capacity_factor.at(point[2]),
+            min_sediment_capacity: self.min_sediment_capacity.at(point[3]),
+            erode_speed: self.erode_speed.at(point[4]),
+            deposit_speed: self.deposit_speed.at(point[5]),
+            evaporate_speed: self.evaporate_speed.at(point[6]),
+            gravity: self.gravity.at(point[7]),
+            max_droplet_lifetime: count(&self.max_droplet_lifetime, point[8]),
+            initial_water_volume: self.initial_water_volume.at(point[9]),
+            initial_speed: self.initial_speed.at(point[10]),
+            num_iterations: count(&self.num_iterations, point[11]),
+            min_water: self.min_water.at(point[12]),
+            min_speed: self.min_speed.at(point[13]),
+            ..self.base
+        }
+    }
+
+    /// One point drawn uniformly from the whole space.
+    pub fn sample(&self, rng: &mut impl Rng) -> Parameters {
+        let mut point = [0.0; DIMENSIONS];
+        for t in point.iter_mut() {
+            *t = rng.gen();
+        }
+        self.at(point)
+    }
+
+    /// `n` points where every range is cut into `n` strata and each stratum is hit once.
+    pub fn latin_hypercube(&self, n: usize, rng: &mut impl Rng) -> Vec<Parameters> {
+        let strata: Vec<Vec<usize>> = (0..DIMENSIONS)
+            .map(|_| {
+                let mut stratum: Vec<usize> = (0..n).collect();
+                stratum.shuffle(rng);
+                stratum
+            })
+            .collect();
+        (0..n)
+            .map(|i| {
+                let mut point = [0.0; DIMENSIONS];
+                for (t, stratum) in point.iter_mut().zip(strata.iter()) {
+                    *t = (stratum[i] as f32 + rng.gen::<f32>()) / n as f32;
+                }
+                self.at(point)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,356 @@
+use crate::erode::{DropZone, ErosionOutputs, Progress};
+use crate::heightmap::*;
+use crate::math::{Extent, Vector2};
+use bracket_noise::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "WindParameters")]
+pub struct Parameters {
+    pub direction: f32,         // [0, 360], 0 (degrees, 0 blows towards +x)
+    pub strength: f32,          // [0, 4], 1
+    pub turbulence: f32,        // [0, 1], 0.2
+    pub turbulence_scale: f32,  // [0, 8], 2
+    pub slab_height: f32,       // 0.002
+    pub hop_length: f32,        // [1, 16], 3
+    pub deposit_chance: f32,    // [0, 1], 0.6
+    pub suspension: f32,        // [0, 1], 0.1
+    pub suspension_length: f32, // [1, 64], 24
+    pub shadow_angle: f32,      // [0, 45], 15
+    pub repose_angle: f32,      // [0, 90], 33
+    pub max_hops: usize,        // 16
+    pub seed: u64,              // 1337
+    pub num_iterations: usize,  // 1
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            direction: 0.0,
+            strength: 1.0,
+            turbulence: 0.2,
+            turbulence_scale: 2.0,
+            slab_height: 0.002,
+            hop_length: 3.0,
+            deposit_chance: 0.6,
+            suspension: 0.1,
+            suspension_length: 24.0,
+            shadow_angle: 15.0,
+            repose_angle: 33.0,
+            max_hops: 16,
+            seed: 1337,
+            num_iterations: 1_000_000,
+        }
+    }
+}
+
+struct State {
+    params: Parameters,
+    noise: FastNoise,
+    // Partitions sample the wind and scale slopes as the whole map does, see `DropZone::crop`
+    origin: Vector2,
+    map_size: Extent,
+    tan_shadow: f32,
+    tan_repose: f32,
+    rng: StdRng,
+}
+
+impl State {
+    fn random_in_range(&mut self, min: f32, max: f32) -> f32 {
+        self.rng.gen::<f32>() * (max - min) + min
+    }
+
+    /// Samples the wind field at a position, returning the unit direction and local strength.
+    fn wind_at(&self, x: f32, y: f32) -> (Vector2, f32) {
+        let u = (self.origin.x + x) / self.map_size.width as f32 * self.params.turbulence_scale;
+        let v = (self.origin.y + y) / self.map_size.height as f32 * self.params.turbulence_scale;
+        let angle_noise = self.noise.get_noise(u, v);
+        let strength_noise = self.noise.get_noise(u + 31.7, v - 17.3);
+        let angle = self.params.direction.to_radians()
+            + angle_noise * self.params.turbulence * std::f32::consts::PI;
+        let strength = self.params.strength * (1.0 + strength_noise * self.params.turbulence);
+        (Vector2::new(angle.cos(), angle.sin()), strength.max(0.0))
+    }
+
+    /// Width of a cell, the whole map is one unit wide.
+    fn cell_size(&self) -> f32 {
+        1.0 / self.map_size.width as f32
+    }
+}
+
+pub fn erode(
+    heightmap: &mut Heightmap,
+    params: &Parameters,
+    drop_zone: &DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let mut outputs = ErosionOutputs::new(heightmap, false);
+    let mut noise = FastNoise::seeded(params.seed);
+    noise.set_noise_type(NoiseType::Perlin);
+    noise.set_frequency(1.0);
+
+    let mut state = State {
+        params: *params,
+        noise,
+        origin: drop_zone.get_origin().into(),
+        map_size: drop_zone.get_map_size(),
+        tan_shadow: params.shadow_angle.to_radians().tan(),
+        tan_repose: params.repose_angle.to_radians().tan(),
+        // The seed also places the grains, so a seeded run blows the same sand again
+        rng: StdRng::seed_from_u64(drop_zone.partition_seed(params.seed, heightmap)),
+    };
+
+    add_metadata(params, heightmap);
+
+    if heightmap.width < 3 || heightmap.height < 3 {
+        return outputs;
+    }
+
+    let rain = drop_zone.rain_sampler(heightmap);
+    for _iteration in 0..params.num_iterations {
+        if !progress.tick() {
+            break;
+        }
+        let (pos_x, pos_y) = if let Some(rain) = &rain {
+            // The sampler already accounts for the drop zone
+            let grain = rain.sample(&mut state.rng);
+            (grain.x, grain.y)
+        } else {
+            let mut pos_x = state.random_in_range(0.0, heightmap.width as f32 - 1.0);
+            let mut pos_y = state.random_in_range(0.0, heightmap.height as f32 - 1.0);
+            while !drop_zone.validate(heightmap, &Vector2 { x: pos_x, y: pos_y }, &mut state.rng) {
+                pos_x = state.random_in_range(0.0, heightmap.width as f32 - 1.0);
+                pos_y = state.random_in_range(0.0, heightmap.height as f32 - 1.0);
+            }
+            (pos_x, pos_y)
+        };
+        let node_x = pos_x as usize;
+        let node_y = pos_y as usize;
+
+        let (wind, strength) = state.wind_at(pos_x, pos_y);
+        if strength <= 0.0 || in_shadow(&state, heightmap, node_x, node_y, &wind) {
+            continue;
+        }
+
+        let lifted = heightmap.data[node_x][node_y].min(params.slab_height * strength);
+        if lifted <= 0.0 {
+            continue;
+        }
+        heightmap.data[node_x][node_y] -= lifted;
+        outputs.record_erosion(node_x, node_y, lifted);
+        avalanche(&state, heightmap, &mut outputs, node_x, node_y);
+
+        let suspended = lifted * params.suspension;
+        let saltating = lifted - suspended;
+
+        // Suspended material travels far and settles thinly along the wind path
+        if suspended > 0.0 {
+            let distance = (params.suspension_length * strength).max(1.0);
+            let steps = distance.ceil() as usize;
+            let share = suspended / steps as f32;
+            for step in 1..=steps {
+                let x = pos_x + wind.x * step as f32;
+                let y = pos_y + wind.y * step as f32;
+                if !deposit(heightmap, &mut outputs, x, y, share) {
+                    break;
+                }
+            }
+        }
+
+        // Saltating material hops along the wind until it comes to rest
+        let mut x = pos_x;
+        let mut y = pos_y;
+        for _hop in 0..params.max_hops.max(1) {
+            let origin_height = height_at(heightmap, x, y);
+            let hop = params.hop_length * strength;
+            x += wind.x * hop;
+            y += wind.y * hop;
+            if !in_bounds(heightmap, x, y) {
+                break;
+            }
+            let landing_x = x as usize;
+            let landing_y = y as usize;
+            let landing_height = height_at(heightmap, x, y);
+
+            // Windward slopes accelerate grains, leeward slopes and shadows trap them
+            let sheltered = in_shadow(&state, heightmap, landing_x, landing_y, &wind)
+                || landing_height > origin_height;
+            if sheltered || state.rng.gen::<f32>() < params.deposit_chance {
+                deposit(heightmap, &mut outputs, x, y, saltating);
+                avalanche(&state, heightmap, &mut outputs, landing_x, landing_y);
+                break;
+            }
+        }
+    }
+
+    outputs
+}
+
+fn in_bounds(heightmap: &Heightmap, x: f32, y: f32) -> bool {
+    x >= 0.0 && x < heightmap.width as f32 - 1.0 && y >= 0.0 && y < heightmap.height as f32 - 1.0
+}
+
+fn height_at(heightmap: &Heightmap, x: f32, y: f32) -> HeightmapPrecision {
+    heightmap
+        .interpolated_height(&Vector2::new(x, y))
+        .unwrap_or(0.0)
+}
+
+fn deposit(
+    heightmap: &mut Heightmap,
+    outputs: &mut ErosionOutputs,
+    x: f32,
+    y: f32,
+    amount: HeightmapPrecision,
+) -> bool {
+    if !in_bounds(heightmap, x, y) {
+        return false;
+    }
+    let node_x = x as usize;
+    let node_y = y as usize;
+    let offset_x = x - node_x as f32;
+    let offset_y = y - node_y as f32;
+
+    for (corner_x, corner_y, weight) in [
+        (node_x, node_y, (1.0 - offset_x) * (1.0 - offset_y)),
+        (node_x + 1, node_y, offset_x * (1.0 - offset_y)),
+        (node_x, node_y + 1, (1.0 - offset_x) * offset_y),
+        (node_x + 1, node_y + 1, offset_x * offset_y),
+    ] {
+        heightmap.data[corner_x][corner_y] += amount * weight;
+        outputs.record_deposition(corner_x, corner_y, amount * weight);
+    }
+    true
+}
+
+/// A cell is sheltered when terrain upwind rises above it steeper than the shadow angle.
+fn in_shadow(state: &State, heightmap: &Heightmap, x: usize, y: usize, wind: &Vector2) -> bool {
+    let height = heightmap.data[x][y];
+    let cell_size = state.cell_size();
+    let reach = (state.params.hop_length * state.params.strength * 4.0).ceil() as usize;
+    for step in 1..=reach.max(1) {
+        let upwind_x = x as f32 - wind.x * step as f32;
+        let upwind_y = y as f32 - wind.y * step as f32;
+        if !in_bounds(heightmap, upwind_x, upwind_y) {
+            return false;
+        }
+        let rise = height_at(heightmap, upwind_x, upwind_y) - height;
+        if rise / (step as f32 * cell_size) > state.tan_shadow {
+            return true;
+        }
+    }
+    false
+}
+
+/// Relaxes slopes steeper than the angle of repose by sliding material to the lowest neighbour.
+fn avalanche(
+    state: &State,
+    heightmap: &mut Heightmap,
+    outputs: &mut ErosionOutputs,
+    x: usize,
+    y: usize,
+) {
+    let max_difference = state.tan_repose * state.cell_size();
+    let mut x = x;
+    let mut y = y;
+    for _step in 0..heightmap.width.max(heightmap.height) {
+        let height = heightmap.data[x][y];
+        let mut lowest = (x, y, height);
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            if let Some(neighbour) = heightmap.get_with_int(x as i32 + dx, y as i32 + dy) {
+                if neighbour < lowest.2 {
+                    lowest = (
+                        (x as i32 + dx) as usize,
+                        (y as i32 + dy) as usize,
+                        neighbour,
+                    );
+                }
+            }
+        }
+        let difference = height - lowest.2;
+        if difference <= max_difference {
+            break;
+        }
+        let moved = (difference - max_difference) / 2.0;
+        heightmap.data[x][y] -= moved;
+        heightmap.data[lowest.0][lowest.1] += moved;
+        outputs.record_erosion(x, y, moved);
+        outputs.record_deposition(lowest.0, lowest.1, moved);
+        x = lowest.0;
+        y = lowest.1;
+    }
+}
+
+pub fn add_metadata(params: &Parameters, heightmap: &mut Heightmap) {
+    heightmap.metadata_add("WIND_DIRECTION", params.direction.to_string());
+    heightmap.metadata_add("WIND_STRENGTH", params.strength.to_string());
+    heightmap.metadata_add("WIND_TURBULENCE", params.turbulence.to_string());
+    heightmap.metadata_add("WIND_TURBULENCE_SCALE", params.turbulence_scale.to_string());
+    heightmap.metadata_add("WIND_SLAB_HEIGHT", params.slab_height.to_string());
+    heightmap.metadata_add("WIND_HOP_LENGTH", params.hop_length.to_string());
+    heightmap.metadata_add("WIND_DEPOSIT_CHANCE", params.deposit_chance.to_string());
+    heightmap.metadata_add("WIND_SUSPENSION", params.suspension.to_string());
+    heightmap.metadata_add(
+        "WIND_SUSPENSION_LENGTH",
+        params.suspension_length.to_string(),
+    );
+    heightmap.metadata_add("WIND_SHADOW_ANGLE", params.shadow_angle.to_string());
+    heightmap.metadata_add("WIND_REPOSE_ANGLE", params.repose_angle.to_string());
+    heightmap.metadata_add("WIND_MAX_HOPS", params.max_hops.to_string());
+    heightmap.metadata_add("WIND_SEED", params.seed.to_string());
+    heightmap.metadata_add("NUM_ITERATIONS", params.num_iterations.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::erode::Model;
+    use crate::fixtures::{tiny_heightmap, Check, GRID_SIZE};
+    use crate::partitioning::Method;
+
+    #[test]
+    fn partitioned_wind() -> Check {
+        let heightmap = tiny_heightmap();
+        let model = Model::Wind(Parameters::default()).with_iterations(20_000);
+        let slope = |method: Method| {
+            let mut eroded = heightmap.clone();
+            let drop_zone = DropZone::default(&heightmap);
+            method.erode(&mut eroded, &model, false, &drop_zone, &Progress::default());
+            eroded.roughness()
+        };
+        // Partitions blow and slide sand as the whole map does, only sand crossing their edges is
+        // lost, so the slopes flatten about as much
+        let before = heightmap.roughness();
+        let (whole, partitioned) = (
+            slope(Method::Default),
+            slope(Method::Subdivision(GRID_SIZE)),
+        );
+        if (partitioned - whole).abs() > 0.4 * (before - whole) {
+            return Err(format!(
+                "mean slope {} on the whole map but {} in partitions, {} before",
+                whole, partitioned, before
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_wind() -> Check {
+        let heightmap = tiny_heightmap();
+        let model = Model::Wind(Parameters::default()).with_iterations(2_000);
+        let blow = || {
+            let mut eroded = heightmap.clone();
+            let drop_zone = DropZone::default(&heightmap);
+            let method = Method::Subdivision(GRID_SIZE);
+            method.erode(&mut eroded, &model, false, &drop_zone, &Progress::default());
+            eroded.content_hash()
+        };
+        if blow() != blow() {
+            return Err("the same seed blew different sand".to_string());
+        }
+        Ok(())
+    }
+}
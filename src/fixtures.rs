@@ -0,0 +1,62 @@
+use crate::heightmap::{self, Heightmap, HeightmapType};
+use std::env;
+use std::path::PathBuf;
+
+/*
+Tiny heightmaps and checks shared by the unit tests and `--self-test`, small enough that eroding
+one with every backend and partitioning method takes a moment.
+ */
+
+pub const SIZE: usize = 32;
+pub const GRID_SIZE: usize = 2;
+pub const ITERATIONS: usize = 200;
+pub const NAME: &str = "self-test";
+
+pub type Check = Result<(), String>;
+
+pub fn check_heightmap(heightmap: &Heightmap, width: usize, height: usize) -> Check {
+    if heightmap.width != width || heightmap.height != height {
+        return Err(format!(
+            "expected {}x{}, got {}x{}",
+            width, height, heightmap.width, heightmap.height
+        ));
+    }
+    if heightmap.data.len() != width || heightmap.data.iter().any(|col| col.len() != height) {
+        return Err("data does not match the dimensions".to_string());
+    }
+    if heightmap.data.iter().flatten().any(|h| !h.is_finite()) {
+        return Err("contains heights that are not finite".to_string());
+    }
+    Ok(())
+}
+
+pub fn check_round_trip(original: &Heightmap, read: &Heightmap) -> Check {
+    if original.content_hash() == read.content_hash() {
+        Ok(())
+    } else {
+        Err("heightmap changed in the round trip".to_string())
+    }
+}
+
+pub fn tiny_heightmap() -> Heightmap {
+    let mut heightmap_type = HeightmapType::default();
+    heightmap_type.params_mut().size = SIZE;
+    heightmap::create_heightmap_from_preset(&heightmap_type)
+}
+
+pub fn tiny_rectangular_heightmap() -> Heightmap {
+    let mut heightmap_type = HeightmapType::default();
+    heightmap_type.params_mut().size = SIZE;
+    heightmap_type.params_mut().height = Some(SIZE * 2);
+    heightmap::create_heightmap_from_preset(&heightmap_type)
+}
+
+/// Droplet erosion with the default parameters, cut down to a few iterations.
+#[cfg(test)]
+pub fn tiny_model() -> crate::erode::Model {
+    crate::erode::Model::Lague(Default::default()).with_iterations(ITERATIONS)
+}
+
+pub fn temp_path(file: &str) -> PathBuf {
+    env::temp_dir().join(format!("erosion-{}-{}", NAME, file))
+}
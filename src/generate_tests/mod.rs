@@ -1,10 +1,16 @@
+use crate::engine::archive;
 use crate::engine::scripts::Instruction;
 use crate::engine::scripts::{Function, FunctionName, IsolineAction, Script, SnapshotAction};
-use crate::erode::Parameters;
-use crate::heightmap::{HeightmapParameters, HeightmapType, ProceduralHeightmapSettings};
+use crate::erode::space::Range;
+use crate::erode::{ParameterSpace, Parameters};
+use crate::heightmap::{
+    BlendCurve, HeightmapParameters, HeightmapType, ProceduralHeightmapSettings,
+};
+use crate::heightmap::{FractalTypeWrapper, NoiseTypeWrapper};
 use crate::partitioning::{Method, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS, GAUSSIAN_DEFAULT_SIGMA};
 use crate::visualize::events::UiEvent;
-use crate::visualize::wrappers::{FractalTypeWrapper, NoiseTypeWrapper};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::default::Default;
 
 pub struct Test {
@@ -20,7 +26,7 @@ fn methods(grid_sizes: &Vec<usize>) -> Vec<Method> {
             *size,
             (GAUSSIAN_DEFAULT_SIGMA, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS),
         )));
-        methods.push(Method::GridOverlapBlend(*size));
+        methods.push(Method::GridOverlapBlend((*size, BlendCurve::default())));
     }
     methods.push(Method::Default);
     methods
@@ -41,7 +47,10 @@ fn generate_heightmap_types(resolutions: &Vec<usize>) -> Vec<HeightmapType> {
                 for fractal_lacunarity in (2..3i8).map(|n| f32::from(n)) {
                     for frequency in (2..30i8).step_by(5).map(|n| f32::from(n) / 10.0).rev() {
                         for res in resolutions.iter() {
-                            let params = HeightmapParameters { size: *res };
+                            let params = HeightmapParameters {
+                                size: *res,
+                                height: None,
+                            };
                             types.push(HeightmapType::Procedural(
                                 params,
                                 ProceduralHeightmapSettings {
@@ -63,25 +72,7 @@ fn generate_heightmap_types(resolutions: &Vec<usize>) -> Vec<HeightmapType> {
     types
 }
 
-pub fn find_last_iteration() -> usize {
-    let mut paths = std::fs::read_dir("./")
-        .unwrap()
-        .filter_map(|path| {
-            let path = path.unwrap();
-            if path.path().is_dir() {
-                None
-            } else {
-                let p = path.path().display().to_string();
-                Some(p.split("-").nth(2)?.strip_prefix("i")?.parse().ok()?)
-            }
-        })
-        .collect::<Vec<usize>>();
-    paths.sort();
-    *paths.last().unwrap_or(&0)
-}
-
 pub fn generate_all_permutations() -> Script {
-    let skip = find_last_iteration();
     let mut test = Test::new(HeightmapType::default());
 
     let grid_sizes = vec![32, 16, 8, 4];
@@ -90,11 +81,9 @@ pub fn generate_all_permutations() -> Script {
     let resolutions: Vec<usize> = vec![128, 256, 512, 1024];
     let map_types = generate_heightmap_types(&resolutions);
 
-    let total_iterations = map_types.len() * methods.len() * 100;
-
-    for (i, map) in map_types.into_iter().enumerate().skip(skip) {
+    for (i, map) in map_types.into_iter().enumerate() {
         test = test
-            .run(Instruction::NewState(map))
+            .run(Instruction::NewState(map.clone()))
             .run(Instruction::SetAdvancedView(false));
         for (j, method) in methods.iter().enumerate() {
             let iterations = (i * methods.len() + j) * 100;
@@ -112,38 +101,35 @@ pub fn generate_all_permutations() -> Script {
                 // .run(Instruction::Handover) // works with this line wtf
                 .run(Instruction::Render(true)) // works with this line wtf
                 // .run(Instruction::Render(false)) // but not with this
-                .run(Instruction::Flush)
-                // .run(Instruction::Queue(UiEvent::ExportActiveHeightmap))
-                .run(Instruction::Print(format!(
-                    "{} / {}  --> {}%",
-                    iterations,
-                    total_iterations,
-                    100.0 * iterations as f32 / total_iterations as f32
-                )));
-            println!(
-                "{} / {}  --> {}% {}",
-                iterations,
-                total_iterations,
-                100.0 * iterations as f32 / total_iterations as f32,
-                map.params().size
-            );
-
-            for value in (0..10).map(|n: i8| f32::from(n) / 10.0) {
-                test = test.run(Instruction::Isoline(IsolineAction::SetValue(value)));
-                for error in (0..40).step_by(4).map(|n: i8| f32::from(n) / 1000.0) {
-                    test = test
-                        .run(Instruction::Isoline(IsolineAction::SetError(error)))
-                        .run(Instruction::Isoline(IsolineAction::Queue))
-                        // .run(Instruction::Queue(UiEvent::ExportActiveHeightmap))
-                        .run(Instruction::SetName(format!(
-                            "iteration-{iterations}-i-{i}-value-{value}-error-{error}"
-                        )))
-                        .run(Instruction::Flush)
-                        .run(Instruction::Snapshot(SnapshotAction::Take))
-                        // .run(Instruction::Handover)
-                        .run(Instruction::Render(true));
-                }
-            }
+                .run(Instruction::Flush);
+
+            let values = (0..10).map(|n: i8| (f32::from(n) / 10.0).to_string());
+            let errors = (0..40)
+                .step_by(4)
+                .map(|n: i8| (f32::from(n) / 1000.0).to_string());
+            test = test.run(Instruction::ForEach(
+                values.collect(),
+                "value".to_string(),
+                vec![
+                    Instruction::Template("isoline-value ${value}".to_string()),
+                    Instruction::ForEach(
+                        errors.collect(),
+                        "error".to_string(),
+                        vec![
+                            Instruction::Template("isoline-error ${error}".to_string()),
+                            Instruction::Isoline(IsolineAction::Queue),
+                            // .run(Instruction::Queue(UiEvent::ExportActiveHeightmap))
+                            Instruction::SetName(format!(
+                                "iteration-{iterations}-i-{i}-value-${{value}}-error-${{error}}"
+                            )),
+                            Instruction::Flush,
+                            Instruction::Snapshot(SnapshotAction::Take),
+                            // .run(Instruction::Handover)
+                            Instruction::Render(true),
+                        ],
+                    ),
+                ],
+            ));
 
             test = test
                 .run(Instruction::Print(format!(
@@ -160,6 +146,32 @@ pub fn generate_all_permutations() -> Script {
     test.run(Instruction::Handover).script
 }
 
+/// Erodes the default heightmap with `samples` parameter sets spread over the space around the
+/// default parameters by a Latin hypercube, one snapshot per set.
+pub fn generate_parameter_sweep(samples: usize, seed: u64) -> Script {
+    let mut space = ParameterSpace::jitter(Parameters::default(), 0.5);
+    space.erosion_radius = Range::new(2.0, 6.0);
+    space.inertia = Range::new(0.0, 0.5);
+    space.num_iterations = Range::fixed(200_000.0);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut test = Test::new(HeightmapType::default()).run(Instruction::SetAdvancedView(false));
+    for (i, params) in space
+        .latin_hypercube(samples, &mut rng)
+        .into_iter()
+        .enumerate()
+    {
+        test = test
+            .run(Instruction::Queue(UiEvent::ReplaceHeightmap))
+            .run(Instruction::Flush)
+            .name(&format!("sweep-{seed}-sample-{i}"))
+            .run(Instruction::SetErosionParameters(params))
+            .append(Test::function_erode(Method::Default))
+            .append(Test::function_isoline());
+    }
+    test.save(&format!("sweep-{seed}")).script
+}
+
 pub fn generate_test() -> Script {
     let min_size = 256;
     let max_size = 1024;
@@ -238,7 +250,7 @@ impl Test {
             .name(&format!("grid_overlap_{}", uid))
             .generate_resolutions(min_size, max_size, step_by, |_size| {
                 vec![
-                    Test::function_erode(Method::GridOverlapBlend(6)),
+                    Test::function_erode(Method::GridOverlapBlend((6, BlendCurve::default()))),
                     Test::function_collect_data(),
                 ]
             })
@@ -316,6 +328,14 @@ impl Test {
         ]
     }
 
+    fn function_isoline() -> Function {
+        vec![
+            Instruction::Isoline(IsolineAction::Queue),
+            Instruction::Flush,
+            Instruction::Snapshot(SnapshotAction::Take),
+        ]
+    }
+
     fn heightmap(self, heightmap: HeightmapType) -> Self {
         self.run(Instruction::NewState(heightmap))
     }
@@ -326,7 +346,7 @@ impl Test {
 
     fn save(self, filename: &str) -> Self {
         self.run(Instruction::Snapshot(SnapshotAction::SaveAndClear(
-            format!("{}.json", filename),
+            format!("{}.{}", filename, archive::SNAPSHOT_FILE_EXT),
         )))
     }
 
@@ -335,6 +355,11 @@ impl Test {
         self
     }
 
+    fn append(mut self, mut function: Function) -> Self {
+        self.script.get_mut("main").unwrap().append(&mut function);
+        self
+    }
+
     fn inject(mut self, name: FunctionName, function: Function) -> Self {
         let injection_name = self.get_injection_name(name);
         self.script
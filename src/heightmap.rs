@@ -1,34 +1,442 @@
+pub mod analysis;
+pub mod contours;
+pub mod hydrology;
+pub mod indexed;
+pub mod mesh;
+pub mod schema;
+pub mod segments;
+
 use bracket_noise::prelude::*;
 use rayon::iter::IntoParallelRefMutIterator;
 use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize, Serializer};
 use std::collections::{HashMap, VecDeque};
 use std::f32::consts::PI;
-use std::fmt::{Display, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::io::Write;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use std::path::PathBuf;
 
-use crate::math::{UVector2, Vector2};
+use crate::math::{Extent, Margins, UVector2, Vector2};
 
-use crate::visualize::wrappers::{FractalTypeWrapper, NoiseTypeWrapper};
 use image::*;
 
 pub type HeightmapPrecision = f32;
+pub type HeightmapHash = u64;
 pub type HeightmapData = Vec<Vec<HeightmapPrecision>>;
 
+/// Seed and multiplier of `Heightmap::content_hash`, which follows FxHash word by word.
+const CONTENT_HASH_SEED: u64 = 0x6572_6f73_696f_6e00;
+const CONTENT_HASH_MULTIPLIER: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// Floating point type of the heights. `f32` is the default for speed and memory, `f64` keeps the
+/// subtle gradients of very large maps from drifting as millions of droplets add up tiny changes.
+/// Hardness, vegetation and material layers stay `f32` whatever the precision of the heights.
+/// Partitioning, the app and saved states hold `f32` heights, so `lague::Parameters` erodes in
+/// `f64` within a single pass and rounds the result back, exports keep `f64` heights as they are.
+pub trait Precision:
+    Copy
+    + Debug
+    + Default
+    + PartialOrd
+    + Display
+    + Send
+    + Sync
+    + Serialize
+    + DeserializeOwned
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + Sum
+    + 'static
+{
+    /// The `descr` of the type in a NumPy header.
+    const NPY_DESCR: &'static str;
+
+    fn from_f32(value: f32) -> Self;
+    fn to_f32(self) -> f32;
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn write_le<W: Write>(self, writer: &mut W) -> std::io::Result<()>;
+}
+
+impl Precision for f32 {
+    const NPY_DESCR: &'static str = "<f4";
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn min(self, other: Self) -> Self {
+        f32::min(self, other)
+    }
+
+    fn max(self, other: Self) -> Self {
+        f32::max(self, other)
+    }
+
+    fn write_le<W: Write>(self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Precision for f64 {
+    const NPY_DESCR: &'static str = "<f8";
+
+    fn from_f32(value: f32) -> Self {
+        value as f64
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn min(self, other: Self) -> Self {
+        f64::min(self, other)
+    }
+
+    fn max(self, other: Self) -> Self {
+        f64::max(self, other)
+    }
+
+    fn write_le<W: Write>(self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Heightmap {
-    pub data: HeightmapData,
+#[serde(bound = "")]
+pub struct Heightmap<P: Precision = HeightmapPrecision> {
+    pub data: Vec<Vec<P>>,
     pub width: usize,
     pub height: usize,
-    pub depth: HeightmapPrecision,
-    pub original_depth: HeightmapPrecision,
+    pub depth: P,
+    pub original_depth: P,
     pub metadata: Option<HashMap<String, String>>,
-    pub total_height: Option<HeightmapPrecision>,
+    pub total_height: Option<P>,
+    #[serde(skip)]
+    pub hardness: Option<HeightmapData>,
+    #[serde(skip)]
+    pub vegetation: Option<HeightmapData>,
+    #[serde(skip)]
+    pub layers: Option<MaterialLayers>,
+    // Opposite edges are neighbours, the heightmap tiles seamlessly
+    #[serde(default)]
+    pub wrap: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum MaterialLayer {
+    Bedrock,
+    Regolith,
+    Sediment,
+}
+
+impl Display for MaterialLayer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaterialLayer::Bedrock => f.write_str("Bedrock"),
+            MaterialLayer::Regolith => f.write_str("Regolith"),
+            MaterialLayer::Sediment => f.write_str("Sediment"),
+        }
+    }
+}
+
+impl MaterialLayer {
+    pub fn list() -> [MaterialLayer; 3] {
+        [
+            MaterialLayer::Bedrock,
+            MaterialLayer::Regolith,
+            MaterialLayer::Sediment,
+        ]
+    }
+}
+
+/// Element-wise operations combining two heightmaps, see the methods of the same names.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HeightmapOperation {
+    Add,
+    Multiply,
+    #[default]
+    Lerp,
+    Min,
+    Max,
+}
+
+impl Display for HeightmapOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeightmapOperation::Add => f.write_str("Add"),
+            HeightmapOperation::Multiply => f.write_str("Multiply"),
+            HeightmapOperation::Lerp => f.write_str("Blend"),
+            HeightmapOperation::Min => f.write_str("Minimum"),
+            HeightmapOperation::Max => f.write_str("Maximum"),
+        }
+    }
+}
+
+impl HeightmapOperation {
+    pub fn list() -> [HeightmapOperation; 5] {
+        [
+            HeightmapOperation::Add,
+            HeightmapOperation::Multiply,
+            HeightmapOperation::Lerp,
+            HeightmapOperation::Min,
+            HeightmapOperation::Max,
+        ]
+    }
+
+    /// `a` combined with `b`, `t` is only used by `Lerp`.
+    pub fn apply(
+        &self,
+        a: &Heightmap,
+        b: &Heightmap,
+        t: HeightmapPrecision,
+    ) -> Result<Heightmap, HeightmapError> {
+        match self {
+            HeightmapOperation::Add => a.add(b),
+            HeightmapOperation::Multiply => a.multiply(b),
+            HeightmapOperation::Lerp => a.lerp(b, t),
+            HeightmapOperation::Min => a.min(b),
+            HeightmapOperation::Max => a.max(b),
+        }
+    }
+}
+
+/// Interpolation between the cells when a heightmap is resampled to another size.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum ResampleFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    Bicubic,
+}
+
+impl Display for ResampleFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResampleFilter::Nearest => f.write_str("Nearest"),
+            ResampleFilter::Bilinear => f.write_str("Bilinear"),
+            ResampleFilter::Bicubic => f.write_str("Bicubic"),
+        }
+    }
+}
+
+impl ResampleFilter {
+    pub fn list() -> [ResampleFilter; 3] {
+        [
+            ResampleFilter::Nearest,
+            ResampleFilter::Bilinear,
+            ResampleFilter::Bicubic,
+        ]
+    }
+}
+
+/// What fills the cells added around a heightmap when it is extended.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum ExtendFill {
+    Height(HeightmapPrecision),
+    /// The nearest edge cell, continuing the edges outwards.
+    #[default]
+    Edge,
+    /// The heightmap mirrored at its edges.
+    Mirror,
+    /// The opposite side of the heightmap, for tileable heightmaps.
+    Wrap,
+}
+
+impl Display for ExtendFill {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtendFill::Height(_) => f.write_str("Constant Height"),
+            ExtendFill::Edge => f.write_str("Edge"),
+            ExtendFill::Mirror => f.write_str("Mirror"),
+            ExtendFill::Wrap => f.write_str("Wrap Around"),
+        }
+    }
+}
+
+impl ExtendFill {
+    pub fn list() -> [ExtendFill; 4] {
+        [
+            ExtendFill::Height(0.0),
+            ExtendFill::Edge,
+            ExtendFill::Mirror,
+            ExtendFill::Wrap,
+        ]
+    }
+}
+
+/// Catmull-Rom spline through `p[1]` at `t` = 0 and `p[2]` at `t` = 1.
+fn catmull_rom(p: [HeightmapPrecision; 4], t: HeightmapPrecision) -> HeightmapPrecision {
+    p[1] + 0.5
+        * t
+        * (p[2] - p[0]
+            + t * (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]
+                + t * (3.0 * (p[1] - p[2]) + p[3] - p[0])))
+}
+
+/// Layers computed from the heights of a state when they are shown or exported.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum DerivedLayer {
+    Slope,
+    Aspect,
+    ProfileCurvature,
+    PlanCurvature,
+    Roughness,
+}
+
+impl Display for DerivedLayer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DerivedLayer::Slope => f.write_str("Slope"),
+            DerivedLayer::Aspect => f.write_str("Aspect"),
+            DerivedLayer::ProfileCurvature => f.write_str("Profile Curvature"),
+            DerivedLayer::PlanCurvature => f.write_str("Plan Curvature"),
+            DerivedLayer::Roughness => f.write_str("Roughness"),
+        }
+    }
+}
+
+impl DerivedLayer {
+    pub fn list() -> [DerivedLayer; 5] {
+        [
+            DerivedLayer::Slope,
+            DerivedLayer::Aspect,
+            DerivedLayer::ProfileCurvature,
+            DerivedLayer::PlanCurvature,
+            DerivedLayer::Roughness,
+        ]
+    }
+
+    /// Whether the layer has negative values, which are shown with a diverging palette.
+    pub fn is_signed(&self) -> bool {
+        matches!(
+            self,
+            DerivedLayer::ProfileCurvature | DerivedLayer::PlanCurvature
+        )
+    }
+
+    /// Name of the exported files, e.g. `profile_curvature`.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            DerivedLayer::Slope => "slope",
+            DerivedLayer::Aspect => "aspect",
+            DerivedLayer::ProfileCurvature => "profile_curvature",
+            DerivedLayer::PlanCurvature => "plan_curvature",
+            DerivedLayer::Roughness => "roughness",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerParameters {
+    pub enabled: bool,
+    pub regolith_depth: f32,       // [0, 0.2], 0.02
+    pub bedrock_erodibility: f32,  // [0, 1], 0.25
+    pub regolith_erodibility: f32, // [0, 1], 0.6
+    pub sediment_erodibility: f32, // [0, 1], 1
+}
+
+impl Default for LayerParameters {
+    fn default() -> Self {
+        LayerParameters {
+            enabled: false,
+            regolith_depth: 0.02,
+            bedrock_erodibility: 0.25,
+            regolith_erodibility: 0.6,
+            sediment_erodibility: 1.0,
+        }
+    }
+}
+
+/// Thickness of each material at every cell, stacked bottom to top. The layers always
+/// sum up to the surface height of the heightmap they belong to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaterialLayers {
+    pub bedrock: HeightmapData,
+    pub regolith: HeightmapData,
+    pub sediment: HeightmapData,
+    pub erodibility: [f32; 3],
+}
+
+impl MaterialLayers {
+    fn slice(&self, anchor: &UVector2, size: &UVector2) -> Self {
+        let slice = |data: &HeightmapData| -> HeightmapData {
+            (0..size.x)
+                .map(|x| data[x + anchor.x][anchor.y..anchor.y + size.y].to_vec())
+                .collect()
+        };
+        MaterialLayers {
+            bedrock: slice(&self.bedrock),
+            regolith: slice(&self.regolith),
+            sediment: slice(&self.sediment),
+            erodibility: self.erodibility,
+        }
+    }
+
+    fn copy_to(&self, other: &mut MaterialLayers, anchor: &UVector2) {
+        for x in 0..self.bedrock.len() {
+            for y in 0..self.bedrock[x].len() {
+                other.bedrock[x + anchor.x][y + anchor.y] = self.bedrock[x][y];
+                other.regolith[x + anchor.x][y + anchor.y] = self.regolith[x][y];
+                other.sediment[x + anchor.x][y + anchor.y] = self.sediment[x][y];
+            }
+        }
+    }
+
+    fn remove(&mut self, x: usize, y: usize, amount: HeightmapPrecision) {
+        let mut remaining = amount;
+        for layer in [&mut self.sediment, &mut self.regolith, &mut self.bedrock] {
+            let removed = layer[x][y].min(remaining).max(0.0);
+            layer[x][y] -= removed;
+            remaining -= removed;
+            if remaining <= 0.0 {
+                return;
+            }
+        }
+        self.bedrock[x][y] -= remaining;
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PartialHeightmap {
+#[serde(bound = "")]
+pub struct PartialHeightmap<P: Precision = HeightmapPrecision> {
     pub anchor: UVector2,
-    pub heightmap: Heightmap,
+    pub heightmap: Heightmap<P>,
+    /// Cells of the neighbouring partitions around this one, eroded over but never written back,
+    /// see `PartialHeightmap::with_halo`.
+    #[serde(default)]
+    pub halo: Margins,
 }
 
 #[derive(Debug)]
@@ -37,15 +445,16 @@ pub enum HeightmapError {
     OutOfBounds,
 }
 
-impl Heightmap {
-    pub fn new(
-        data: HeightmapData,
+impl<P: Precision> Heightmap<P> {
+    /// A heightmap of any precision, `Heightmap::new` makes one of the default precision.
+    pub fn with_precision(
+        data: Vec<Vec<P>>,
         width: usize,
         height: usize,
-        depth: HeightmapPrecision,
-        original_depth: HeightmapPrecision,
+        depth: P,
+        original_depth: P,
         metadata: Option<HashMap<String, String>>,
-    ) -> Heightmap {
+    ) -> Self {
         Heightmap {
             data,
             width,
@@ -54,9 +463,216 @@ impl Heightmap {
             original_depth,
             metadata,
             total_height: None,
+            hardness: None,
+            vegetation: None,
+            layers: None,
+            wrap: false,
+        }
+    }
+
+    /// The heightmap with its heights converted to another precision, along with everything else
+    /// it carries.
+    pub fn to_precision<Q: Precision>(&self) -> Heightmap<Q> {
+        let convert = |value: P| Q::from_f64(value.to_f64());
+        Heightmap {
+            data: self
+                .data
+                .iter()
+                .map(|column| column.iter().map(|&h| convert(h)).collect())
+                .collect(),
+            width: self.width,
+            height: self.height,
+            depth: convert(self.depth),
+            original_depth: convert(self.original_depth),
+            metadata: self.metadata.clone(),
+            total_height: self.total_height.map(convert),
+            hardness: self.hardness.clone(),
+            vegetation: self.vegetation.clone(),
+            layers: self.layers.clone(),
+            wrap: self.wrap,
+        }
+    }
+
+    pub fn extent(&self) -> Extent {
+        Extent::new(self.width, self.height)
+    }
+
+    /// Every cell as `(x, y, height)`, column by column.
+    pub fn iter_cells(&self) -> impl Iterator<Item = (usize, usize, P)> + '_ {
+        self.data
+            .iter()
+            .enumerate()
+            .flat_map(|(x, col)| col.iter().enumerate().map(move |(y, &v)| (x, y, v)))
+    }
+
+    pub fn metadata_add(&mut self, key: &str, value: String) {
+        if let Some(hashmap) = &mut self.metadata {
+            hashmap.insert(key.to_string(), value);
+        } else {
+            let mut hashmap = HashMap::new();
+            hashmap.insert(key.to_string(), value);
+            self.metadata = Some(hashmap);
+        }
+    }
+
+    /// How easily the topmost exposed material at a cell erodes, 1 when there are no layers.
+    pub fn erodibility_at(&self, x: usize, y: usize) -> f32 {
+        if let Some(layers) = &self.layers {
+            if layers.sediment[x][y] > 0.0 {
+                layers.erodibility[2]
+            } else if layers.regolith[x][y] > 0.0 {
+                layers.erodibility[1]
+            } else {
+                layers.erodibility[0]
+            }
+        } else {
+            1.0
+        }
+    }
+
+    pub fn remove_material(&mut self, x: usize, y: usize, amount: P) {
+        self.data[x][y] -= amount;
+        if let Some(layers) = &mut self.layers {
+            layers.remove(x, y, amount.to_f32());
+        }
+    }
+
+    pub fn add_sediment(&mut self, x: usize, y: usize, amount: P) {
+        self.data[x][y] += amount;
+        if let Some(layers) = &mut self.layers {
+            layers.sediment[x][y] += amount.to_f32();
+        }
+    }
+
+    pub fn hardness_at(&self, x: usize, y: usize) -> HeightmapPrecision {
+        self.hardness
+            .as_ref()
+            .map(|hardness| hardness[x][y])
+            .unwrap_or(0.0)
+    }
+
+    pub fn vegetation_at(&self, x: usize, y: usize) -> HeightmapPrecision {
+        self.vegetation
+            .as_ref()
+            .map(|vegetation| vegetation[x][y])
+            .unwrap_or(0.0)
+    }
+}
+
+impl Heightmap {
+    pub fn new(
+        data: HeightmapData,
+        width: usize,
+        height: usize,
+        depth: HeightmapPrecision,
+        original_depth: HeightmapPrecision,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Heightmap {
+        Heightmap::with_precision(data, width, height, depth, original_depth, metadata)
+    }
+
+    /// Splits the heightmap into material layers unless it already carries them.
+    pub fn with_layers(mut self, params: &LayerParameters) -> Self {
+        if !params.enabled {
+            self.layers = None;
+            return self;
+        }
+        let erodibility = [
+            params.bedrock_erodibility,
+            params.regolith_erodibility,
+            params.sediment_erodibility,
+        ];
+        if let Some(layers) = &mut self.layers {
+            layers.erodibility = erodibility;
+            return self;
+        }
+        let regolith: HeightmapData = self
+            .data
+            .iter()
+            .map(|col| {
+                col.iter()
+                    .map(|h| h.max(0.0).min(params.regolith_depth))
+                    .collect()
+            })
+            .collect();
+        let bedrock: HeightmapData = self
+            .data
+            .iter()
+            .zip(regolith.iter())
+            .map(|(col, reg)| col.iter().zip(reg.iter()).map(|(h, r)| h - r).collect())
+            .collect();
+        self.layers = Some(MaterialLayers {
+            bedrock,
+            regolith,
+            sediment: vec![vec![0.0; self.height]; self.width],
+            erodibility,
+        });
+        self
+    }
+
+    pub fn layer(&self, layer: MaterialLayer) -> Option<Heightmap> {
+        let layers = self.layers.as_ref()?;
+        let data = match layer {
+            MaterialLayer::Bedrock => &layers.bedrock,
+            MaterialLayer::Regolith => &layers.regolith,
+            MaterialLayer::Sediment => &layers.sediment,
+        };
+        Some(Heightmap::new(
+            data.clone(),
+            self.width,
+            self.height,
+            self.depth,
+            self.original_depth,
+            None,
+        ))
+    }
+
+    /// Brings the layers back in line with the surface after operations that only touch
+    /// `data`, such as blending partitions. Gains become sediment, losses are removed top down.
+    pub fn reconcile_layers(&mut self) {
+        if let Some(layers) = &mut self.layers {
+            for x in 0..self.width {
+                for y in 0..self.height {
+                    let difference = self.data[x][y]
+                        - (layers.bedrock[x][y] + layers.regolith[x][y] + layers.sediment[x][y]);
+                    if difference > 0.0 {
+                        layers.sediment[x][y] += difference;
+                    } else if difference < 0.0 {
+                        layers.remove(x, y, -difference);
+                    }
+                }
+            }
         }
     }
 
+    /// Attaches a rock hardness layer in [0, 1] which scales how easily each cell erodes.
+    pub fn with_hardness(mut self, hardness: Option<&Heightmap>) -> Self {
+        self.hardness = hardness
+            .filter(|h| h.width == self.width && h.height == self.height)
+            .map(|h| h.data.clone());
+        self
+    }
+
+    pub fn hardness_layer(&self) -> Option<Heightmap> {
+        self.hardness.as_ref().map(|hardness| {
+            Heightmap::new(hardness.clone(), self.width, self.height, 1.0, 1.0, None)
+        })
+    }
+
+    /// Attaches a vegetation cover in [0, 1], how much the plants at each cell hold the soil.
+    pub fn with_vegetation(mut self, vegetation: Option<&Heightmap>) -> Self {
+        self.vegetation = vegetation
+            .filter(|v| v.width == self.width && v.height == self.height)
+            .map(|v| v.data.clone());
+        self
+    }
+
+    pub fn vegetation_layer(&self) -> Option<Heightmap> {
+        self.vegetation.as_ref().map(|vegetation| {
+            Heightmap::new(vegetation.clone(), self.width, self.height, 1.0, 1.0, None)
+        })
+    }
+
     pub fn new_empty(
         width: usize,
         height: usize,
@@ -69,7 +685,7 @@ impl Heightmap {
 
     pub fn from_u8(data: &Vec<u8>, width: usize, height: usize) -> Self {
         let mut data_f32 = vec![vec![0.0; height]; width];
-        let data: Vec<&[u8]> = data.chunks(height).collect();
+        let data: Vec<&[u8]> = data.chunks(width).collect();
 
         data_f32.par_iter_mut().enumerate().for_each(|(i, col)| {
             for j in 0..height {
@@ -87,18 +703,11 @@ impl Heightmap {
         ImageBuffer::from_vec(width?, height?, self.to_u8())
     }
 
-    pub fn with_margin(&self, margin: (usize, usize, usize, usize)) -> PartialHeightmap {
-        let (margin_r, margin_t, margin_l, margin_b) = margin;
+    pub fn with_margin(&self, margin: Margins) -> PartialHeightmap {
         PartialHeightmap::from(
             self,
-            &UVector2 {
-                x: margin_l,
-                y: margin_t,
-            },
-            &UVector2 {
-                x: self.width - margin_l - margin_r,
-                y: self.height - margin_t - margin_b,
-            },
+            &margin.anchor(),
+            &margin.interior(self.extent()).into(),
         )
     }
 
@@ -111,25 +720,263 @@ impl Heightmap {
         Some(blurred_heightmap)
     }
 
+    /// Replaces every cell with `f(x, y, height)`, the columns are mapped in parallel.
+    pub fn map_inplace<F>(&mut self, f: F)
+    where
+        F: Fn(usize, usize, HeightmapPrecision) -> HeightmapPrecision + Sync,
+    {
+        self.data.par_iter_mut().enumerate().for_each(|(x, col)| {
+            for (y, value) in col.iter_mut().enumerate() {
+                *value = f(x, y, *value);
+            }
+        });
+        self.total_height = None;
+    }
+
+    /// A heightmap of `f(a, b)` for every cell of `self` and `other`, with the depth of `self`
+    /// and without metadata. The columns are mapped in parallel.
+    pub fn zip_map<F>(&self, other: &Heightmap, f: F) -> Result<Heightmap, HeightmapError>
+    where
+        F: Fn(HeightmapPrecision, HeightmapPrecision) -> HeightmapPrecision + Sync,
+    {
+        if self.width != other.width || self.height != other.height {
+            return Err(HeightmapError::MismatchingSize);
+        }
+        let data = self
+            .data
+            .par_iter()
+            .zip(other.data.par_iter())
+            .map(|(a, b)| a.iter().zip(b.iter()).map(|(&a, &b)| f(a, b)).collect())
+            .collect();
+        Ok(Heightmap::new(
+            data,
+            self.width,
+            self.height,
+            self.depth,
+            self.original_depth,
+            None,
+        ))
+    }
+
+    /// Sets every cell of `self` to `f(a, b)` with the cell of `other`, in parallel like `zip_map`.
+    pub fn zip_map_inplace<F>(&mut self, other: &Heightmap, f: F) -> Result<(), HeightmapError>
+    where
+        F: Fn(HeightmapPrecision, HeightmapPrecision) -> HeightmapPrecision + Sync,
+    {
+        if self.width != other.width || self.height != other.height {
+            return Err(HeightmapError::MismatchingSize);
+        }
+        self.data
+            .par_iter_mut()
+            .zip(other.data.par_iter())
+            .for_each(|(a, b)| a.iter_mut().zip(b.iter()).for_each(|(a, &b)| *a = f(*a, b)));
+        Ok(())
+    }
+
+    pub fn add(&self, other: &Heightmap) -> Result<Heightmap, HeightmapError> {
+        self.zip_map(other, |a, b| a + b)
+    }
+
+    pub fn add_inplace(&mut self, other: &Heightmap) -> Result<(), HeightmapError> {
+        self.zip_map_inplace(other, |a, b| a + b)
+    }
+
+    pub fn multiply(&self, other: &Heightmap) -> Result<Heightmap, HeightmapError> {
+        self.zip_map(other, |a, b| a * b)
+    }
+
+    pub fn multiply_inplace(&mut self, other: &Heightmap) -> Result<(), HeightmapError> {
+        self.zip_map_inplace(other, |a, b| a * b)
+    }
+
+    /// `self` at `t` = 0 and `other` at `t` = 1, linearly interpolated in between.
+    pub fn lerp(
+        &self,
+        other: &Heightmap,
+        t: HeightmapPrecision,
+    ) -> Result<Heightmap, HeightmapError> {
+        self.zip_map(other, |a, b| a + (b - a) * t)
+    }
+
+    pub fn lerp_inplace(
+        &mut self,
+        other: &Heightmap,
+        t: HeightmapPrecision,
+    ) -> Result<(), HeightmapError> {
+        self.zip_map_inplace(other, |a, b| a + (b - a) * t)
+    }
+
+    pub fn min(&self, other: &Heightmap) -> Result<Heightmap, HeightmapError> {
+        self.zip_map(other, HeightmapPrecision::min)
+    }
+
+    pub fn min_inplace(&mut self, other: &Heightmap) -> Result<(), HeightmapError> {
+        self.zip_map_inplace(other, HeightmapPrecision::min)
+    }
+
+    pub fn max(&self, other: &Heightmap) -> Result<Heightmap, HeightmapError> {
+        self.zip_map(other, HeightmapPrecision::max)
+    }
+
+    pub fn max_inplace(&mut self, other: &Heightmap) -> Result<(), HeightmapError> {
+        self.zip_map_inplace(other, HeightmapPrecision::max)
+    }
+
+    /// The heightmap scaled to `width` x `height` cells with the cell centres of both spread
+    /// evenly over the same extent. Hardness, vegetation and material layers are not carried over.
+    pub fn resample(&self, width: usize, height: usize, filter: ResampleFilter) -> Heightmap {
+        let scale_x = self.width as HeightmapPrecision / width as HeightmapPrecision;
+        let scale_y = self.height as HeightmapPrecision / height as HeightmapPrecision;
+        let data = (0..width)
+            .into_par_iter()
+            .map(|x| {
+                let sx = (x as HeightmapPrecision + 0.5) * scale_x - 0.5;
+                (0..height)
+                    .map(|y| {
+                        let sy = (y as HeightmapPrecision + 0.5) * scale_y - 0.5;
+                        self.sample(sx, sy, filter)
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut resampled = Heightmap::new(
+            data,
+            width,
+            height,
+            self.depth,
+            self.original_depth,
+            self.metadata.clone(),
+        );
+        resampled.wrap = self.wrap;
+        resampled.metadata_add("RESAMPLED_FROM", format!("{}x{}", self.width, self.height));
+        resampled
+    }
+
+    /// The cells of `extent` from `anchor` on, along with their hardness, vegetation and layers.
+    pub fn crop(&self, anchor: UVector2, extent: Extent) -> Result<Heightmap, HeightmapError> {
+        if extent.area() == 0
+            || anchor.x + extent.width > self.width
+            || anchor.y + extent.height > self.height
+        {
+            return Err(HeightmapError::OutOfBounds);
+        }
+        let mut cropped = PartialHeightmap::from(self, &anchor, &extent.into()).heightmap;
+        cropped.metadata_add(
+            "CROPPED_FROM",
+            format!(
+                "{}x{} at ({}, {})",
+                self.width, self.height, anchor.x, anchor.y
+            ),
+        );
+        Ok(cropped)
+    }
+
+    /// The heightmap with `margins` cells added to its sides, filled by `fill`. Hardness,
+    /// vegetation and material layers are not carried over.
+    pub fn extend(&self, margins: Margins, fill: ExtendFill) -> Heightmap {
+        let extent = Extent::new(
+            self.width + margins.horizontal(),
+            self.height + margins.vertical(),
+        );
+        // Cell of the heightmap an added cell takes its height from, `None` for a constant
+        let source = |v: usize, before: usize, size: usize| {
+            let (v, size) = (v as i64 - before as i64, size as i64);
+            match fill {
+                _ if (0..size).contains(&v) => Some(v as usize),
+                ExtendFill::Height(_) => None,
+                ExtendFill::Edge => Some(v.clamp(0, size - 1) as usize),
+                ExtendFill::Mirror => {
+                    let v = v.rem_euclid(2 * size);
+                    Some(if v < size { v } else { 2 * size - 1 - v } as usize)
+                }
+                ExtendFill::Wrap => Some(v.rem_euclid(size) as usize),
+            }
+        };
+        let constant = match fill {
+            ExtendFill::Height(height) => height,
+            _ => 0.0,
+        };
+        let data = (0..extent.width)
+            .map(|x| {
+                (0..extent.height)
+                    .map(|y| {
+                        match (
+                            source(x, margins.left, self.width),
+                            source(y, margins.top, self.height),
+                        ) {
+                            (Some(sx), Some(sy)) => self.data[sx][sy],
+                            _ => constant,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut extended = Heightmap::new(
+            data,
+            extent.width,
+            extent.height,
+            self.depth,
+            self.original_depth,
+            self.metadata.clone(),
+        );
+        extended.metadata_add("EXTENDED_FROM", format!("{}x{}", self.width, self.height));
+        extended
+    }
+
+    /// Height at a point between the cell centres, clamped or wrapped like `get_clamped`.
+    pub fn sample(
+        &self,
+        x: HeightmapPrecision,
+        y: HeightmapPrecision,
+        filter: ResampleFilter,
+    ) -> HeightmapPrecision {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let at = |dx: i32, dy: i32| self.get_clamped(x0 + dx, y0 + dy);
+        match filter {
+            ResampleFilter::Nearest => {
+                self.get_clamped((x + 0.5).floor() as i32, (y + 0.5).floor() as i32)
+            }
+            ResampleFilter::Bilinear => {
+                let top = at(0, 0) * (1.0 - tx) + at(1, 0) * tx;
+                let bottom = at(0, 1) * (1.0 - tx) + at(1, 1) * tx;
+                top * (1.0 - ty) + bottom * ty
+            }
+            ResampleFilter::Bicubic => {
+                // Clamped to the heights around so the overshoot of the curve adds no new peaks
+                let (mut low, mut high) = (HeightmapPrecision::MAX, HeightmapPrecision::MIN);
+                let mut rows = [0.0; 4];
+                for (j, row) in rows.iter_mut().enumerate() {
+                    let mut points = [0.0; 4];
+                    for (i, point) in points.iter_mut().enumerate() {
+                        *point = at(i as i32 - 1, j as i32 - 1);
+                        low = low.min(*point);
+                        high = high.max(*point);
+                    }
+                    *row = catmull_rom(points, tx);
+                }
+                catmull_rom(rows, ty).clamp(low, high)
+            }
+        }
+    }
+
     pub fn boolean(mut self, threshold: HeightmapPrecision, round_up: bool, invert: bool) -> Self {
         let one = if invert { 0.0 } else { 1.0 };
         let zero = 1.0 - one;
-        for x in 0..self.width {
-            for y in 0..self.height {
-                let d = self.data[x][y];
-                self.data[x][y] = if d == threshold {
-                    if round_up {
-                        one
-                    } else {
-                        zero
-                    }
-                } else if d < threshold {
-                    zero
-                } else {
+        self.map_inplace(|_, _, d| {
+            if d == threshold {
+                if round_up {
                     one
+                } else {
+                    zero
                 }
+            } else if d < threshold {
+                zero
+            } else {
+                one
             }
-        }
+        });
         self
     }
 
@@ -164,12 +1011,7 @@ impl Heightmap {
     pub fn normalize(mut self) -> Self {
         let (min, max) = self.get_range();
         let range = max - min;
-        for i in 0..self.width {
-            for j in 0..self.height {
-                let value = self.data[i][j];
-                self.data[i][j] = (value - min) / range;
-            }
-        }
+        self.map_inplace(|_, _, value| (value - min) / range);
         self.depth = 1.0;
         self
     }
@@ -202,6 +1044,104 @@ impl Heightmap {
         Some(self.total_height? / (self.width * self.height) as f32)
     }
 
+    /// Mean slope between neighbouring cells, 0 for flat terrain.
+    pub fn roughness(&self) -> HeightmapPrecision {
+        if self.width == 0 || self.height == 0 {
+            return 0.0;
+        }
+        let mut total = 0.0;
+        for x in 0..self.width {
+            for y in 0..self.height {
+                total += self.gradient(x, y).map_or(0.0, |g| g.magnitude());
+            }
+        }
+        total / (self.width * self.height) as HeightmapPrecision
+    }
+
+    /// Change of height per cell along x and y by central differences, one sided at the edges
+    /// unless the heightmap wraps.
+    pub fn central_difference(
+        &self,
+        x: usize,
+        y: usize,
+    ) -> (HeightmapPrecision, HeightmapPrecision) {
+        // Neighbours before and after along an axis and the cells between them
+        let step = |v: usize, size: usize| {
+            if self.wrap {
+                ((v + size - 1) % size, (v + 1) % size, 2)
+            } else {
+                let (before, after) = (v.saturating_sub(1), (v + 1).min(size - 1));
+                (before, after, (after - before).max(1))
+            }
+        };
+        let (left, right, width) = step(x, self.width);
+        let (up, down, height) = step(y, self.height);
+        let dx = (self.data[right][y] - self.data[left][y]) / width as HeightmapPrecision;
+        let dy = (self.data[x][down] - self.data[x][up]) / height as HeightmapPrecision;
+        (dx, dy)
+    }
+
+    /// A derived heightmap of the same size with `value` of every cell, with the given depth or
+    /// the largest absolute value for layers without a fixed range.
+    fn derived(
+        &self,
+        layer: DerivedLayer,
+        depth: Option<HeightmapPrecision>,
+        value: impl Fn(usize, usize) -> HeightmapPrecision,
+    ) -> Heightmap {
+        let data: HeightmapData = (0..self.width)
+            .map(|x| (0..self.height).map(|y| value(x, y)).collect())
+            .collect();
+        let depth = depth.unwrap_or_else(|| {
+            data.iter()
+                .flatten()
+                .fold(HeightmapPrecision::EPSILON, |max, v| max.max(v.abs()))
+        });
+        let mut derived = Heightmap::new(data, self.width, self.height, depth, depth, None);
+        derived.wrap = self.wrap;
+        derived.metadata_add("DERIVED_LAYER", layer.to_string());
+        derived
+    }
+
+    /// Steepness in degrees, with the map spanning one unit along its longest side and the depth
+    /// one unit high like the exported meshes.
+    pub fn slope_map(&self) -> Heightmap {
+        let scale = self.width.max(self.height) as HeightmapPrecision / self.depth;
+        self.derived(DerivedLayer::Slope, Some(90.0), |x, y| {
+            let (dx, dy) = self.central_difference(x, y);
+            ((dx * dx + dy * dy).sqrt() * scale).atan().to_degrees()
+        })
+    }
+
+    /// Direction the terrain faces in degrees clockwise from north, the top of the map, 0 where
+    /// it is flat.
+    pub fn aspect_map(&self) -> Heightmap {
+        self.derived(DerivedLayer::Aspect, Some(360.0), |x, y| {
+            let (dx, dy) = self.central_difference(x, y);
+            if dx == 0.0 && dy == 0.0 {
+                return 0.0;
+            }
+            // Facing downhill, against the gradient, with north being decreasing y
+            (-dx).atan2(dy).to_degrees().rem_euclid(360.0)
+        })
+    }
+
+    pub fn derived_layer(&self, layer: DerivedLayer) -> Heightmap {
+        match layer {
+            DerivedLayer::Slope => self.slope_map(),
+            DerivedLayer::Aspect => self.aspect_map(),
+            DerivedLayer::ProfileCurvature => {
+                self.derived(layer, None, |x, y| analysis::profile_curvature(self, x, y))
+            }
+            DerivedLayer::PlanCurvature => {
+                self.derived(layer, None, |x, y| analysis::plan_curvature(self, x, y))
+            }
+            DerivedLayer::Roughness => {
+                self.derived(layer, None, |x, y| analysis::roughness(self, x, y))
+            }
+        }
+    }
+
     pub fn set_range(&mut self, min: HeightmapPrecision, max: HeightmapPrecision) {
         let (old_min, old_max) = self.get_range();
         let old_range = old_max - old_min;
@@ -214,121 +1154,45 @@ impl Heightmap {
         }
     }
 
-    pub fn to_u8_rgba(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = Vec::new();
-        let mut errors: Vec<i32> = Vec::new();
-
+    /// Pixels of the heightmap row by row, `color` maps heights in [0, depth] scaled to [0, 1].
+    /// Heights outside of [0, depth] are clamped.
+    pub fn to_u8_rgba(&self, color: impl Fn(HeightmapPrecision) -> [u8; 3]) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(self.width * self.height * 4);
         for j in 0..self.height {
             for i in 0..self.width {
-                let mut value = self.data[i][j];
-                let u8_max: HeightmapPrecision = 255.0;
-                value = value / (self.depth / u8_max);
-                value = value.round();
-                let value = value as i32;
-
-                if let Some(value) = value.try_into().ok() {
-                    buffer.push(value);
-                    buffer.push(value);
-                    buffer.push(value);
-                } else {
-                    errors.push(value);
-                    buffer.push(if value < 0 { 0 } else { 255 });
-                    buffer.push(if value < 0 { 0 } else { 255 });
-                    buffer.push(if value < 0 { 0 } else { 255 });
-                }
-                buffer.push(255);
-            }
-        }
-        if errors.len() > 0 && errors.len() < 256 {
-            eprintln!(
-                "heightmap.rs: Could not convert {} / {} ({:.5}%) values to u8 ({:?})",
-                errors.len(),
-                buffer.len(),
-                errors.len() as f32 / buffer.len() as f32,
-                errors
-            );
-        } else if errors.len() > 0 {
-            eprintln!(
-                "heightmap.rs: Could not convert {} / {} ({:.5}%) values to u8.)",
-                errors.len(),
-                buffer.len(),
-                errors.len() as f32 / buffer.len() as f32
-            );
+                let value = self.data[i][j] / self.depth;
+                let [r, g, b] = color(value.clamp(0.0, 1.0));
+                buffer.extend_from_slice(&[r, g, b, 255]);
+            }
         }
-
         buffer
     }
 
+    /// Heights row by row scaled from [0, depth] to [0, 255], heights outside of it saturate.
     pub fn to_u8(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = Vec::new();
-        let mut errors: Vec<i32> = Vec::new();
-
+        let u8_max: HeightmapPrecision = 255.0;
+        let mut buffer: Vec<u8> = Vec::with_capacity(self.width * self.height);
         for j in 0..self.height {
             for i in 0..self.width {
-                let mut value = self.data[i][j];
-                let u8_max: HeightmapPrecision = 255.0;
-                value = value / (self.depth / u8_max);
-                value = value.round();
-                let value = value as i32;
-
-                if let Some(value) = value.try_into().ok() {
-                    buffer.push(value);
-                } else {
-                    errors.push(value);
-                    buffer.push(if value < 0 { 0 } else { 255 });
-                }
+                let value = (self.data[i][j] / (self.depth / u8_max)).round();
+                buffer.push(value.clamp(0.0, u8_max) as u8);
             }
         }
-        if errors.len() > 0 && errors.len() < 256 {
-            eprintln!(
-                "heightmap.rs: Could not convert {} / {} ({:.5}%) values to u8 ({:?})",
-                errors.len(),
-                buffer.len(),
-                errors.len() as f32 / buffer.len() as f32,
-                errors
-            );
-        } else if errors.len() > 0 {
-            eprintln!(
-                "heightmap.rs: Could not convert {} / {} ({:.5}%) values to u8.)",
-                errors.len(),
-                buffer.len(),
-                errors.len() as f32 / buffer.len() as f32
-            );
-        }
-
         buffer
     }
 
     pub fn subtract(&self, heightmap: &Heightmap) -> Result<Heightmap, HeightmapError> {
-        let mut data: HeightmapData = Vec::new();
-
-        let depth = if self.depth > heightmap.depth {
-            self.depth
-        } else {
-            heightmap.depth
-        };
-
-        if !(self.width == heightmap.width && self.height == heightmap.height) {
-            return Err(HeightmapError::MismatchingSize);
-        }
-
-        for i in 0..self.width {
-            let mut row = Vec::new();
-            for j in 0..self.height {
-                let value = (self.data[i][j] - heightmap.data[i][j]).abs();
-                row.push(value);
-            }
-            data.push(row);
-        }
+        let mut diff = self.zip_map(heightmap, |a, b| (a - b).abs())?;
+        diff.depth = self.depth.max(heightmap.depth);
+        diff.original_depth = heightmap.original_depth;
+        Ok(diff)
+    }
 
-        let diff = Heightmap::new(
-            data,
-            self.width,
-            self.height,
-            depth,
-            heightmap.original_depth,
-            None,
-        );
+    /// `self - heightmap` keeping the sign, negative where `self` is lower.
+    pub fn subtract_signed(&self, heightmap: &Heightmap) -> Result<Heightmap, HeightmapError> {
+        let mut diff = self.zip_map(heightmap, |a, b| a - b)?;
+        diff.depth = self.depth.max(heightmap.depth);
+        diff.original_depth = heightmap.original_depth;
         Ok(diff)
     }
 
@@ -351,7 +1215,13 @@ impl Heightmap {
         self.get(x_usize, y_usize)
     }
 
+    /// Height at a cell, clamped to the edges or wrapped around them for tileable heightmaps.
     pub fn get_clamped(&self, x: i32, y: i32) -> HeightmapPrecision {
+        if self.wrap {
+            let x = x.rem_euclid(self.width as i32);
+            let y = y.rem_euclid(self.height as i32);
+            return self.data[x as usize][y as usize];
+        }
         let mut x = x;
         let mut y = y;
 
@@ -439,14 +1309,11 @@ impl Heightmap {
         {
             return Err(HeightmapError::MismatchingSize);
         }
-        for x in 0..self.width {
-            for y in 0..self.height {
-                let v0 = self.data[x][y];
-                let v1 = overlay.data[x][y];
-                let m = mask.data[x][y];
-                self.data[x][y] = v1 * m + v0 * (1.0 - m);
-            }
-        }
+        self.map_inplace(|x, y, v0| {
+            let v1 = overlay.data[x][y];
+            let m = mask.data[x][y];
+            v1 * m + v0 * (1.0 - m)
+        });
         Ok(())
     }
 
@@ -490,7 +1357,7 @@ impl Heightmap {
             }
         };
 
-        create_heightmap_from_closure(self.width, 1.0, &func)
+        create_heightmap_from_closure(self.width, self.height, 1.0, &func)
     }
 
     pub fn get_flood_points(&self, isoline: &Self, inside: bool) -> Vec<UVector2> {
@@ -669,66 +1536,219 @@ impl Heightmap {
         (heightmap, flooded)
     }
 
-    pub fn metadata_add(&mut self, key: &str, value: String) {
-        if let Some(hashmap) = &mut self.metadata {
-            hashmap.insert(key.to_string(), value);
-        } else {
-            let mut hashmap = HashMap::new();
-            hashmap.insert(key.to_string(), value);
-            self.metadata = Some(hashmap);
-        }
+    /// Hashes the dimensions and height values, ignoring metadata. The hash is written to archives
+    /// and exports, so it is spelled out here instead of relying on `DefaultHasher`, whose
+    /// algorithm may change between Rust releases.
+    pub fn content_hash(&self) -> HeightmapHash {
+        let words = [
+            self.width as u64,
+            self.height as u64,
+            self.depth.to_bits() as u64,
+        ];
+        let values = self
+            .data
+            .iter()
+            .flatten()
+            .map(|value| value.to_bits() as u64);
+        words
+            .into_iter()
+            .chain(values)
+            .fold(CONTENT_HASH_SEED, |hash, word| {
+                (hash.rotate_left(5) ^ word).wrapping_mul(CONTENT_HASH_MULTIPLIER)
+            })
+    }
+
+    /// Whether the heights, dimensions and metadata are the same, as bits so that NaN equals NaN.
+    pub fn same_content(&self, other: &Heightmap) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.depth.to_bits() == other.depth.to_bits()
+            && self.metadata == other.metadata
+            && self
+                .data
+                .iter()
+                .flatten()
+                .zip(other.data.iter().flatten())
+                .all(|(a, b)| a.to_bits() == b.to_bits())
     }
 }
 
-impl PartialHeightmap {
-    pub fn from(heightmap: &Heightmap, anchor: &UVector2, size: &UVector2) -> Self {
-        let mut data: Vec<Vec<HeightmapPrecision>> = vec![vec![0.0; size.y]; size.x];
+fn slice_data(
+    data: Option<&HeightmapData>,
+    anchor: &UVector2,
+    size: &UVector2,
+) -> Option<HeightmapData> {
+    data.map(|data| {
+        (0..size.x)
+            .map(|x| data[x + anchor.x][anchor.y..anchor.y + size.y].to_vec())
+            .collect()
+    })
+}
+
+impl<P: Precision> PartialHeightmap<P> {
+    pub fn from(heightmap: &Heightmap<P>, anchor: &UVector2, size: &UVector2) -> Self {
+        let mut data: Vec<Vec<P>> = vec![vec![P::default(); size.y]; size.x];
         for x in 0..size.x {
             for y in 0..size.y {
                 data[x][y] = heightmap.data[x + anchor.x][y + anchor.y];
             }
         }
+        let mut partial = Heightmap::with_precision(
+            data,
+            size.x,
+            size.y,
+            heightmap.depth,
+            heightmap.original_depth,
+            heightmap.metadata.clone(),
+        );
+        partial.hardness = slice_data(heightmap.hardness.as_ref(), anchor, size);
+        partial.vegetation = slice_data(heightmap.vegetation.as_ref(), anchor, size);
+        partial.layers = heightmap
+            .layers
+            .as_ref()
+            .map(|layers| layers.slice(anchor, size));
+        // Only a copy of the whole heightmap still tiles
+        partial.wrap = heightmap.wrap && size.x == heightmap.width && size.y == heightmap.height;
         PartialHeightmap {
             anchor: anchor.clone(),
-            heightmap: Heightmap::new(
-                data,
-                size.x,
-                size.y,
-                heightmap.depth,
-                heightmap.original_depth,
-                heightmap.metadata.clone(),
-            ),
+            heightmap: partial,
+            halo: Margins::default(),
         }
     }
 
+    /// The `size` area at `anchor` with up to `halo` cells of the heightmap around it, fewer
+    /// where it meets the edges. Droplets near the edges of the partition then follow the slopes
+    /// of its neighbours instead of running into the edge, while `apply_to` only writes back the
+    /// area itself.
+    pub fn with_halo(
+        heightmap: &Heightmap<P>,
+        anchor: &UVector2,
+        size: &UVector2,
+        halo: usize,
+    ) -> Self {
+        let halo = Margins::new(
+            halo.min(heightmap.width - anchor.x - size.x),
+            halo.min(anchor.y),
+            halo.min(anchor.x),
+            halo.min(heightmap.height - anchor.y - size.y),
+        );
+        let mut partial = Self::from(
+            heightmap,
+            &(*anchor - halo.anchor()),
+            &UVector2::new(size.x + halo.horizontal(), size.y + halo.vertical()),
+        );
+        partial.halo = halo;
+        partial
+    }
+
+    /// Size of the area written back, the partition without its halo.
+    pub fn interior(&self) -> UVector2 {
+        self.halo.interior(self.heightmap.extent()).into()
+    }
+
     pub fn nest(&self, anchor: &UVector2, size: &UVector2) -> Self {
-        let mut data: Vec<Vec<HeightmapPrecision>> = vec![vec![0.0; size.y]; size.x];
+        let mut data: Vec<Vec<P>> = vec![vec![P::default(); size.y]; size.x];
         for x in 0..size.x {
             for y in 0..size.y {
                 data[x][y] = self.heightmap.data[x + anchor.x][y + anchor.y];
             }
         }
+        let mut partial = Heightmap::with_precision(
+            data,
+            size.x,
+            size.y,
+            self.heightmap.depth,
+            self.heightmap.original_depth,
+            self.heightmap.metadata.clone(),
+        );
+        partial.hardness = slice_data(self.heightmap.hardness.as_ref(), anchor, size);
+        partial.vegetation = slice_data(self.heightmap.vegetation.as_ref(), anchor, size);
+        partial.layers = self
+            .heightmap
+            .layers
+            .as_ref()
+            .map(|layers| layers.slice(anchor, size));
         PartialHeightmap {
             anchor: self.anchor + *anchor,
-            heightmap: Heightmap::new(
-                data,
-                size.x,
-                size.y,
-                self.heightmap.depth,
-                self.heightmap.original_depth,
-                self.heightmap.metadata.clone(),
-            ),
+            heightmap: partial,
+            halo: Margins::default(),
+        }
+    }
+
+    /// Writes the partition back into the heightmap it was cut out of, without its halo.
+    pub fn apply_to(&self, heightmap: &mut Heightmap<P>) {
+        let interior = self.interior();
+        let (left, top) = self.halo.anchor().to_tuple();
+        for x in left..left + interior.x {
+            for y in top..top + interior.y {
+                heightmap.data[x + self.anchor.x][y + self.anchor.y] = self.heightmap.data[x][y];
+            }
+        }
+        if let (Some(partial), Some(layers)) = (&self.heightmap.layers, &mut heightmap.layers) {
+            if self.halo.is_zero() {
+                partial.copy_to(layers, &self.anchor);
+            } else {
+                partial
+                    .slice(&self.halo.anchor(), &interior)
+                    .copy_to(layers, &(self.anchor + self.halo.anchor()));
+            }
+        }
+    }
+}
+
+/// How quickly the overlapping grid takes over from the offset grid towards the edges of its
+/// cells, see `PartialHeightmap::blend_apply_to`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum BlendCurve {
+    Linear,
+    Smoothstep,
+    Cosine,
+    /// Exponent, the lower the wider the seams are blended.
+    Power(f32),
+}
+
+pub const DEFAULT_BLEND_EXPONENT: f32 = 1.5;
+
+impl Default for BlendCurve {
+    fn default() -> Self {
+        BlendCurve::Power(DEFAULT_BLEND_EXPONENT)
+    }
+}
+
+impl Display for BlendCurve {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlendCurve::Linear => f.write_str("Linear"),
+            BlendCurve::Smoothstep => f.write_str("Smoothstep"),
+            BlendCurve::Cosine => f.write_str("Cosine"),
+            BlendCurve::Power(exponent) => write!(f, "Power {}", exponent),
         }
     }
+}
 
-    pub fn apply_to(&self, heightmap: &mut Heightmap) {
-        for x in 0..self.heightmap.width {
-            for y in 0..self.heightmap.height {
-                heightmap.data[x + self.anchor.x][y + self.anchor.y] = self.heightmap.data[x][y];
-            }
+impl BlendCurve {
+    pub fn list() -> [BlendCurve; 4] {
+        [
+            BlendCurve::Linear,
+            BlendCurve::Smoothstep,
+            BlendCurve::Cosine,
+            BlendCurve::default(),
+        ]
+    }
+
+    /// Share of the overlapping grid at `t` from the centre to the edge of a cell, both in [0, 1].
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            BlendCurve::Linear => t,
+            BlendCurve::Smoothstep => t * t * (3.0 - 2.0 * t),
+            BlendCurve::Cosine => (1.0 - (t * std::f32::consts::PI).cos()) / 2.0,
+            BlendCurve::Power(exponent) => t.powf(exponent.max(0.0)),
         }
     }
+}
 
+impl PartialHeightmap {
     pub fn apply_to_additive(&self, heightmap: &mut Heightmap, cap: HeightmapPrecision) {
         for x in 0..self.heightmap.width {
             for y in 0..self.heightmap.height {
@@ -739,7 +1759,9 @@ impl PartialHeightmap {
         }
     }
 
-    pub fn blend_apply_to(&self, other: &mut PartialHeightmap) {
+    /// Blends this partition into the overlapping `other`, taking more of this one towards the
+    /// edges of `other` by `curve`.
+    pub fn blend_apply_to(&self, other: &mut PartialHeightmap, curve: BlendCurve) {
         let rect_min = UVector2::new(
             self.anchor.x.max(other.anchor.x),
             self.anchor.y.max(other.anchor.y),
@@ -761,8 +1783,8 @@ impl PartialHeightmap {
                 let black_sample = self.heightmap.data[black_x][black_y];
                 let blue_sample = other.heightmap.data[blue_x][blue_y];
 
-                let mask_x = (blue_x as f32 / w * 2.0 - 1.0).abs().powf(1.5);
-                let mask_y = (blue_y as f32 / h * 2.0 - 1.0).abs().powf(1.5);
+                let mask_x = curve.apply((blue_x as f32 / w * 2.0 - 1.0).abs());
+                let mask_y = curve.apply((blue_y as f32 / h * 2.0 - 1.0).abs());
                 let mask = (mask_x + mask_y) / 2.0;
 
                 let height = mask * black_sample + (1.0 - mask) * blue_sample;
@@ -779,13 +1801,92 @@ impl PartialHeightmap {
     }
 }
 
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum NoiseTypeWrapper {
+    Value,
+    ValueFractal,
+    Perlin,
+    PerlinFractal,
+    Simplex,
+    SimplexFractal,
+    Cellular,
+    WhiteNoise,
+    Cubic,
+    CubicFractal,
+}
+
+impl From<NoiseType> for NoiseTypeWrapper {
+    fn from(item: NoiseType) -> Self {
+        match item {
+            NoiseType::Value => NoiseTypeWrapper::Value,
+            NoiseType::ValueFractal => NoiseTypeWrapper::ValueFractal,
+            NoiseType::Perlin => NoiseTypeWrapper::Perlin,
+            NoiseType::PerlinFractal => NoiseTypeWrapper::PerlinFractal,
+            NoiseType::Simplex => NoiseTypeWrapper::Simplex,
+            NoiseType::SimplexFractal => NoiseTypeWrapper::SimplexFractal,
+            NoiseType::Cellular => NoiseTypeWrapper::Cellular,
+            NoiseType::WhiteNoise => NoiseTypeWrapper::WhiteNoise,
+            NoiseType::Cubic => NoiseTypeWrapper::Cubic,
+            NoiseType::CubicFractal => NoiseTypeWrapper::CubicFractal,
+        }
+    }
+}
+
+impl From<NoiseTypeWrapper> for NoiseType {
+    fn from(item: NoiseTypeWrapper) -> Self {
+        match item {
+            NoiseTypeWrapper::Value => NoiseType::Value,
+            NoiseTypeWrapper::ValueFractal => NoiseType::ValueFractal,
+            NoiseTypeWrapper::Perlin => NoiseType::Perlin,
+            NoiseTypeWrapper::PerlinFractal => NoiseType::PerlinFractal,
+            NoiseTypeWrapper::Simplex => NoiseType::Simplex,
+            NoiseTypeWrapper::SimplexFractal => NoiseType::SimplexFractal,
+            NoiseTypeWrapper::Cellular => NoiseType::Cellular,
+            NoiseTypeWrapper::WhiteNoise => NoiseType::WhiteNoise,
+            NoiseTypeWrapper::Cubic => NoiseType::Cubic,
+            NoiseTypeWrapper::CubicFractal => NoiseType::CubicFractal,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum FractalTypeWrapper {
+    FBM,
+    Billow,
+    RigidMulti,
+}
+
+impl From<FractalType> for FractalTypeWrapper {
+    fn from(value: FractalType) -> Self {
+        match value {
+            FractalType::FBM => FractalTypeWrapper::FBM,
+            FractalType::Billow => FractalTypeWrapper::Billow,
+            FractalType::RigidMulti => FractalTypeWrapper::RigidMulti,
+        }
+    }
+}
+
+impl From<FractalTypeWrapper> for FractalType {
+    fn from(value: FractalTypeWrapper) -> Self {
+        match value {
+            FractalTypeWrapper::FBM => FractalType::FBM,
+            FractalTypeWrapper::Billow => FractalType::Billow,
+            FractalTypeWrapper::RigidMulti => FractalType::RigidMulti,
+        }
+    }
+}
+
 const DEFAULT_HEIGHTMAP_PARAMETERS: HeightmapParameters = HeightmapParameters {
     size: crate::PRESET_HEIGHTMAP_SIZE,
+    height: None,
 };
 
-#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HeightmapParameters {
     pub size: usize,
+    // Square heightmaps of `size` when unset, otherwise `size` is the width
+    #[serde(default)]
+    pub height: Option<usize>,
 }
 
 impl HeightmapParameters {
@@ -793,6 +1894,22 @@ impl HeightmapParameters {
         DEFAULT_HEIGHTMAP_PARAMETERS
     }
 
+    /// Parameters matching the dimensions of an existing heightmap.
+    pub fn of(heightmap: &Heightmap) -> Self {
+        HeightmapParameters {
+            size: heightmap.width,
+            height: Some(heightmap.height),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.size
+    }
+
+    pub fn height(&self) -> usize {
+        self.height.unwrap_or(self.size)
+    }
+
     pub fn reset(&mut self) {
         *self = Self::default();
     }
@@ -804,7 +1921,7 @@ impl Default for HeightmapParameters {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum HeightmapType {
     Procedural(HeightmapParameters, ProceduralHeightmapSettings),
     XGradient(HeightmapParameters),
@@ -813,6 +1930,8 @@ pub enum HeightmapType {
     XHyperbolaGradient(HeightmapParameters),
     CenteredHillGradient(HeightmapParameters, f32),
     XSinWave(HeightmapParameters, f32),
+    // A grayscale image, e.g. a real-world elevation model, see `io::import_image`
+    FromFile(HeightmapParameters, PathBuf),
 }
 
 impl HeightmapType {
@@ -825,6 +1944,7 @@ impl HeightmapType {
             HeightmapType::XHyperbolaGradient(params) => params,
             HeightmapType::CenteredHillGradient(params, _) => params,
             HeightmapType::XSinWave(params, _) => params,
+            HeightmapType::FromFile(params, _) => params,
         }
     }
 
@@ -837,6 +1957,7 @@ impl HeightmapType {
             HeightmapType::XHyperbolaGradient(params) => params,
             HeightmapType::CenteredHillGradient(params, _) => params,
             HeightmapType::XSinWave(params, _) => params,
+            HeightmapType::FromFile(params, _) => params,
         }
     }
 }
@@ -862,6 +1983,7 @@ impl Display for HeightmapType {
             HeightmapType::XHyperbolaGradient(_) => f.collect_str("Hyperbola Gradient"),
             HeightmapType::CenteredHillGradient(_, _) => f.collect_str("Centered Hill"),
             HeightmapType::XSinWave(_, _) => f.collect_str("Sin Wave"),
+            HeightmapType::FromFile(_, _) => f.collect_str("From File"),
         }
     }
 }
@@ -887,166 +2009,781 @@ impl HeightmapType {
             HeightmapType::CenteredHillGradient(HeightmapParameters::static_default(), 0.75),
             HeightmapType::XSinWave(HeightmapParameters::static_default(), 8.0),
         ];
-        TYPES.iter().copied()
+        TYPES.iter().cloned()
+    }
+}
+
+/// Heightmap of `preset`, a `FromFile` preset that can't be imported gives an empty heightmap.
+pub fn create_heightmap_from_preset(preset: &HeightmapType) -> Heightmap {
+    match preset {
+        HeightmapType::Procedural(params, settings) => create_perlin_heightmap(&params, &settings),
+        HeightmapType::XGradient(params) => create_heightmap_from_closure(
+            params.width(),
+            params.height(),
+            1.0,
+            &|x: usize, _: usize| x as HeightmapPrecision / params.width() as HeightmapPrecision,
+        ),
+        HeightmapType::XGradientRepeating(params, repetitions) => create_heightmap_from_closure(
+            params.width(),
+            params.height(),
+            1.0,
+            &|x: usize, _: usize| {
+                (repetitions * x as HeightmapPrecision / params.width() as HeightmapPrecision)
+                    .fract()
+            },
+        ),
+        HeightmapType::XGradientRepeatingAlternating(params, repetitions) => {
+            create_heightmap_from_closure(
+                params.width(),
+                params.height(),
+                1.0,
+                &|x: usize, _: usize| {
+                    let v = repetitions * x as HeightmapPrecision
+                        / params.width() as HeightmapPrecision;
+                    if v.trunc() % 2.0 == 0.0 {
+                        v.fract()
+                    } else {
+                        1.0 - v.fract()
+                    }
+                },
+            )
+        }
+        HeightmapType::XHyperbolaGradient(params) => create_heightmap_from_closure(
+            params.width(),
+            params.height(),
+            1.0,
+            &|x: usize, _: usize| {
+                let gradient = x as HeightmapPrecision / params.width() as HeightmapPrecision;
+                gradient.powi(2)
+            },
+        ),
+        HeightmapType::CenteredHillGradient(params, hill_radius) => create_heightmap_from_closure(
+            params.width(),
+            params.height(),
+            1.0,
+            &|x: usize, y: usize| {
+                let center_x = params.width() as HeightmapPrecision / 2.0;
+                let center_y = params.height() as HeightmapPrecision / 2.0;
+                let radius = center_x.min(center_y);
+                let x = x as HeightmapPrecision;
+                let y = y as HeightmapPrecision;
+                let distance = ((x - center_x).powf(2.0) + (y - center_y).powf(2.0)).sqrt();
+
+                if distance < radius * hill_radius {
+                    let to = radius * hill_radius;
+                    let from = 0.0;
+                    let gradient = (distance - from) / (to - from);
+                    ((std::f32::consts::PI * gradient).cos() + 1.0) / 2.0
+                } else {
+                    0.0
+                }
+            },
+        ),
+        HeightmapType::XSinWave(params, inverse_frequency) => {
+            create_heightmap_from_closure(params.width(), params.height(), 1.0, &|x: usize, _| {
+                let t = x as HeightmapPrecision / params.width() as HeightmapPrecision;
+                ((t * PI * inverse_frequency + PI).cos() + 1.0) / 2.0
+            })
+        }
+        HeightmapType::FromFile(params, path) => import_preset_file(path)
+            .unwrap_or_else(|_| Heightmap::new_empty(params.width(), params.height(), 1.0, 1.0)),
+    }
+}
+
+/// Heightmap of `preset`, failing instead of falling back to an empty heightmap when the file of
+/// a `FromFile` preset can't be imported.
+pub fn try_create_heightmap_from_preset(
+    preset: &HeightmapType,
+) -> Result<Heightmap, PresetImportError> {
+    match preset {
+        HeightmapType::FromFile(_, path) => import_preset_file(path),
+        _ => Ok(create_heightmap_from_preset(preset)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresetImportError {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl Display for PresetImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to import {}! Reason: {}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+#[cfg(feature = "export")]
+fn import_preset_file(path: &std::path::Path) -> Result<Heightmap, PresetImportError> {
+    io::import_image(path).map_err(|err| PresetImportError {
+        path: path.to_path_buf(),
+        reason: format!("{:?}", err),
+    })
+}
+
+#[cfg(not(feature = "export"))]
+fn import_preset_file(path: &std::path::Path) -> Result<Heightmap, PresetImportError> {
+    Err(PresetImportError {
+        path: path.to_path_buf(),
+        reason: "images are only read with the export feature".to_string(),
+    })
+}
+
+pub fn create_heightmap_from_closure(
+    width: usize,
+    height: usize,
+    original_depth: f32,
+    closure: &dyn Fn(usize, usize) -> HeightmapPrecision,
+) -> Heightmap {
+    let mut data: Vec<Vec<HeightmapPrecision>> = Vec::new();
+    for i in 0..width {
+        let mut row = Vec::new();
+        for j in 0..height {
+            row.push(closure(i, j));
+        }
+        data.push(row);
+    }
+
+    Heightmap::new(data, width, height, 1.0, original_depth, None)
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ProceduralHeightmapSettings {
+    pub seed: u64,
+    pub noise_type: NoiseTypeWrapper,
+    pub fractal_type: FractalTypeWrapper,
+    pub fractal_octaves: i32,
+    pub fractal_gain: f32,
+    pub fractal_lacunarity: f32,
+    pub frequency: f32,
+}
+
+const DEFAULT_PROCEDURAL_HEIGHTMAP_SETTINGS: ProceduralHeightmapSettings =
+    ProceduralHeightmapSettings {
+        seed: 1337,
+        noise_type: NoiseTypeWrapper::Perlin,
+        fractal_type: FractalTypeWrapper::FBM,
+        fractal_octaves: 5,
+        fractal_gain: 0.6,
+        fractal_lacunarity: 2.0,
+        frequency: 0.5,
+    };
+
+impl ProceduralHeightmapSettings {
+    const fn static_default() -> Self {
+        DEFAULT_PROCEDURAL_HEIGHTMAP_SETTINGS
+    }
+
+    pub fn reset(&mut self) {
+        *self = ProceduralHeightmapSettings::default()
+    }
+}
+
+impl Default for ProceduralHeightmapSettings {
+    fn default() -> Self {
+        DEFAULT_PROCEDURAL_HEIGHTMAP_SETTINGS
+    }
+}
+
+pub fn create_perlin_heightmap(
+    params: &HeightmapParameters,
+    settings: &ProceduralHeightmapSettings,
+) -> Heightmap {
+    let mut noise = FastNoise::seeded(settings.seed);
+    noise.set_noise_type(settings.noise_type.into());
+    noise.set_fractal_type(settings.fractal_type.into());
+    noise.set_fractal_octaves(settings.fractal_octaves);
+    noise.set_fractal_gain(settings.fractal_gain);
+    noise.set_fractal_lacunarity(settings.fractal_lacunarity);
+    noise.set_frequency(settings.frequency);
+
+    // Both axes share the scale of the width so rectangular maps are not stretched
+    let denominator = params.width() as f32 / 5.0;
+
+    let mut data: HeightmapData = Vec::new();
+
+    let mut min = noise.get_noise(0.0, 0.0);
+    let mut max = min.clone();
+
+    for x in 0..params.width() {
+        data.push(vec![]);
+        for y in 0..params.height() {
+            let n = noise.get_noise(x as f32 / denominator, y as f32 / denominator);
+            if n < min {
+                min = n;
+            }
+            if n > max {
+                max = n;
+            }
+            data.last_mut().unwrap().push(n);
+        }
+    }
+
+    Heightmap::new(
+        data,
+        params.width(),
+        params.height(),
+        max - min,
+        max - min,
+        None,
+    )
+    .normalize()
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum HardnessType {
+    Noise(ProceduralHeightmapSettings),
+    Strata(f32, f32), // layers [1, 32], 8; softness [0, 1], 0.5
+}
+
+impl Default for HardnessType {
+    fn default() -> Self {
+        HardnessType::Noise(ProceduralHeightmapSettings::default())
+    }
+}
+
+impl Display for HardnessType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HardnessType::Noise(_) => f.collect_str("Noise"),
+            HardnessType::Strata(_, _) => f.collect_str("Rock Strata"),
+        }
+    }
+}
+
+impl HardnessType {
+    pub fn list() -> [HardnessType; 2] {
+        [
+            HardnessType::Noise(ProceduralHeightmapSettings::default()),
+            HardnessType::Strata(8.0, 0.5),
+        ]
+    }
+
+    pub fn matches(&self, other: &Self) -> bool {
+        match self {
+            HardnessType::Noise(_) => matches!(other, HardnessType::Noise(_)),
+            HardnessType::Strata(_, _) => matches!(other, HardnessType::Strata(_, _)),
+        }
+    }
+}
+
+/// Generates a hardness layer in [0, 1] matching the size of `heightmap`.
+pub fn create_hardness_from_preset(heightmap: &Heightmap, preset: &HardnessType) -> Heightmap {
+    match preset {
+        HardnessType::Noise(settings) => {
+            create_perlin_heightmap(&HeightmapParameters::of(heightmap), settings)
+        }
+        HardnessType::Strata(layers, softness) => {
+            // Horizontal bands of alternating hard and soft rock following the terrain height
+            create_heightmap_from_closure(
+                heightmap.width,
+                heightmap.height,
+                1.0,
+                &|x: usize, y: usize| {
+                    let band = ((heightmap.data[x][y] * layers * PI).sin() + 1.0) / 2.0;
+                    band.powf(1.0 + softness * 4.0)
+                },
+            )
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum PrecipitationType {
+    Noise(ProceduralHeightmapSettings),
+    Altitude(f32), // contrast [0, 4], 1
+}
+
+impl Default for PrecipitationType {
+    fn default() -> Self {
+        PrecipitationType::Altitude(1.0)
+    }
+}
+
+impl Display for PrecipitationType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrecipitationType::Noise(_) => f.collect_str("Noise"),
+            PrecipitationType::Altitude(_) => f.collect_str("Altitude"),
+        }
+    }
+}
+
+impl PrecipitationType {
+    pub fn list() -> [PrecipitationType; 2] {
+        [
+            PrecipitationType::Altitude(1.0),
+            PrecipitationType::Noise(ProceduralHeightmapSettings::default()),
+        ]
+    }
+
+    pub fn matches(&self, other: &Self) -> bool {
+        match self {
+            PrecipitationType::Noise(_) => matches!(other, PrecipitationType::Noise(_)),
+            PrecipitationType::Altitude(_) => matches!(other, PrecipitationType::Altitude(_)),
+        }
+    }
+}
+
+/// Generates relative rainfall in [0, 1] matching the size of `heightmap`.
+pub fn create_precipitation_from_preset(
+    heightmap: &Heightmap,
+    preset: &PrecipitationType,
+) -> Heightmap {
+    match preset {
+        PrecipitationType::Noise(settings) => {
+            create_perlin_heightmap(&HeightmapParameters::of(heightmap), settings)
+        }
+        PrecipitationType::Altitude(contrast) => {
+            // Orographic rain, air cools as it is forced up the terrain and rains out on highlands
+            let (min, max) = heightmap.get_range();
+            let range = (max - min).max(HeightmapPrecision::EPSILON);
+            create_heightmap_from_closure(
+                heightmap.width,
+                heightmap.height,
+                1.0,
+                &|x: usize, y: usize| ((heightmap.data[x][y] - min) / range).powf(*contrast),
+            )
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct VegetationParameters {
+    pub max_slope: f32,  // [0, 0.05], 0.01 (steeper cells stay bare)
+    pub tree_line: f32,  // [0, 1], 0.7 (relative height above which nothing grows)
+    pub moisture: f32,   // [0, 1], 0.5 (how much growth depends on precipitation)
+    pub protection: f32, // [0, 1], 0.8 (erosion prevented under full cover)
+}
+
+impl Default for VegetationParameters {
+    fn default() -> Self {
+        VegetationParameters {
+            max_slope: 0.01,
+            tree_line: 0.7,
+            moisture: 0.5,
+            protection: 0.8,
+        }
+    }
+}
+
+/// Generates a vegetation cover in [0, 1] matching the size of `heightmap`. Plants grow on gentle
+/// slopes below the tree line and where it rains, see `Heightmap::vegetation_at`.
+pub fn create_vegetation(
+    heightmap: &Heightmap,
+    precipitation: Option<&Heightmap>,
+    params: &VegetationParameters,
+) -> Heightmap {
+    // Plants thin out over the last tenth below the tree line
+    const TREE_LINE_FADE: f32 = 0.1;
+    let (min, max) = heightmap.get_range();
+    let range = (max - min).max(HeightmapPrecision::EPSILON);
+    let precipitation =
+        precipitation.filter(|p| p.width == heightmap.width && p.height == heightmap.height);
+    create_heightmap_from_closure(
+        heightmap.width,
+        heightmap.height,
+        1.0,
+        &|x: usize, y: usize| {
+            let slope = heightmap
+                .gradient(x, y)
+                .map(|gradient| gradient.magnitude())
+                .unwrap_or(0.0);
+            let flatness = if params.max_slope > 0.0 {
+                (1.0 - slope / params.max_slope).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let altitude = (heightmap.data[x][y] - min) / range;
+            let climate = ((params.tree_line - altitude) / TREE_LINE_FADE).clamp(0.0, 1.0);
+            let rain = precipitation.map(|p| p.data[x][y]).unwrap_or(1.0);
+            let moisture = 1.0 - params.moisture + params.moisture * rain;
+            flatness * climate * moisture * params.protection
+        },
+    )
+}
+
+#[cfg(feature = "export")]
+pub mod io {
+    use crate::heightmap::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+    use std::io::BufWriter;
+    use std::path::Path;
+
+    #[derive(Debug)]
+    pub enum HeightmapIOError {
+        FileExportError,
+        FileImportError,
+        ImageImportError(image::ImageError),
+        ExrError(exr::error::Error),
+    }
+
+    /// Raw data formats for post-processing the layers outside of the application, every format
+    /// stores one row per y so `array[y][x]` matches the png. Unlike the 8 bit png written with
+    /// every export these keep the heights without visible terracing, `Png16` maps [0, depth] onto
+    /// the full 16 bit range and the others store the heights as they are.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+    pub enum DataFormat {
+        Npy,
+        Csv,
+        CsvGz,
+        Png16,
+        TiffF32,
+        /// Headerless little endian float32, read by most terrain tools given the size.
+        R32,
+        /// OpenEXR float32 with the depth and metadata as attributes, read back by `import_exr`.
+        Exr,
+    }
+
+    impl Display for DataFormat {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                DataFormat::Npy => f.write_str("NumPy"),
+                DataFormat::Csv => f.write_str("CSV"),
+                DataFormat::CsvGz => f.write_str("CSV (gzip)"),
+                DataFormat::Png16 => f.write_str("PNG (16 bit)"),
+                DataFormat::TiffF32 => f.write_str("TIFF (32 bit float)"),
+                DataFormat::R32 => f.write_str("Raw (32 bit float)"),
+                DataFormat::Exr => f.write_str("OpenEXR"),
+            }
+        }
     }
-}
 
-pub fn create_heightmap_from_preset(preset: &HeightmapType) -> Heightmap {
-    match preset {
-        HeightmapType::Procedural(params, settings) => create_perlin_heightmap(&params, &settings),
-        HeightmapType::XGradient(params) => {
-            create_heightmap_from_closure(params.size, 1.0, &|x: usize, _: usize| {
-                x as HeightmapPrecision / params.size as HeightmapPrecision
-            })
+    impl DataFormat {
+        pub fn list() -> [DataFormat; 7] {
+            [
+                DataFormat::Npy,
+                DataFormat::Csv,
+                DataFormat::CsvGz,
+                DataFormat::Png16,
+                DataFormat::TiffF32,
+                DataFormat::R32,
+                DataFormat::Exr,
+            ]
         }
-        HeightmapType::XGradientRepeating(params, repetitions) => {
-            create_heightmap_from_closure(params.size, 1.0, &|x: usize, _: usize| {
-                (repetitions * x as HeightmapPrecision / params.size as HeightmapPrecision).fract()
-            })
+
+        pub fn extension(&self) -> &'static str {
+            match self {
+                DataFormat::Npy => "npy",
+                DataFormat::Csv => "csv",
+                DataFormat::CsvGz => "csv.gz",
+                // Kept apart from the 8 bit png of the same layer
+                DataFormat::Png16 => "16.png",
+                DataFormat::TiffF32 => "tif",
+                DataFormat::R32 => "r32",
+                DataFormat::Exr => "exr",
+            }
         }
-        HeightmapType::XGradientRepeatingAlternating(params, repetitions) => {
-            create_heightmap_from_closure(params.size, 1.0, &|x: usize, _: usize| {
-                let v = repetitions * x as HeightmapPrecision / params.size as HeightmapPrecision;
-                if v.trunc() % 2.0 == 0.0 {
-                    v.fract()
-                } else {
-                    1.0 - v.fract()
+
+        /// Format of a file named `path`, a plain png is written with 16 bits like `Png16`.
+        pub fn from_path(path: &Path) -> Option<DataFormat> {
+            let name = path.file_name()?.to_str()?.to_lowercase();
+            let format = if name.ends_with(".csv.gz") {
+                DataFormat::CsvGz
+            } else {
+                match path.extension()?.to_str()?.to_lowercase().as_str() {
+                    "npy" => DataFormat::Npy,
+                    "csv" => DataFormat::Csv,
+                    "png" => DataFormat::Png16,
+                    "tif" | "tiff" => DataFormat::TiffF32,
+                    "r32" => DataFormat::R32,
+                    "exr" => DataFormat::Exr,
+                    _ => return None,
                 }
-            })
+            };
+            Some(format)
         }
-        HeightmapType::XHyperbolaGradient(params) => {
-            create_heightmap_from_closure(params.size, 1.0, &|x: usize, _: usize| {
-                let gradient = x as HeightmapPrecision / params.size as HeightmapPrecision;
-                gradient.powi(2)
-            })
+    }
+
+    /// Writes a version 1.0 npy file holding a little endian float32 array of shape (height, width).
+    fn write_npy<P: Precision>(
+        heightmap: &Heightmap<P>,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()> {
+        const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}",
+            P::NPY_DESCR,
+            heightmap.height,
+            heightmap.width
+        );
+        // The header is padded with spaces and ends in a newline so the data is 64 byte aligned
+        let unpadded = MAGIC.len() + 2 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+        header.push('\n');
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        for y in 0..heightmap.height {
+            for x in 0..heightmap.width {
+                heightmap.data[x][y].write_le(writer)?;
+            }
         }
-        HeightmapType::CenteredHillGradient(params, hill_radius) => {
-            create_heightmap_from_closure(params.size, 1.0, &|x: usize, y: usize| {
-                let radius = params.size as HeightmapPrecision / 2.0;
-                let x = x as HeightmapPrecision;
-                let y = y as HeightmapPrecision;
-                let distance = ((x - radius).powf(2.0) + (y - radius).powf(2.0)).sqrt();
+        Ok(())
+    }
 
-                if distance < radius * hill_radius {
-                    let to = radius * hill_radius;
-                    let from = 0.0;
-                    let gradient = (distance - from) / (to - from);
-                    ((std::f32::consts::PI * gradient).cos() + 1.0) / 2.0
-                } else {
-                    0.0
+    fn write_csv<P: Precision>(
+        heightmap: &Heightmap<P>,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()> {
+        for y in 0..heightmap.height {
+            for x in 0..heightmap.width {
+                if x > 0 {
+                    writer.write_all(b",")?;
                 }
-            })
-        }
-        HeightmapType::XSinWave(params, inverse_frequency) => {
-            create_heightmap_from_closure(params.size, 1.0, &|x: usize, _| {
-                let t = x as HeightmapPrecision / params.size as HeightmapPrecision;
-                ((t * PI * inverse_frequency + PI).cos() + 1.0) / 2.0
-            })
+                write!(writer, "{}", heightmap.data[x][y])?;
+            }
+            writer.write_all(b"\n")?;
         }
+        Ok(())
     }
-}
 
-pub fn create_heightmap_from_closure(
-    size: usize,
-    original_depth: f32,
-    closure: &dyn Fn(usize, usize) -> HeightmapPrecision,
-) -> Heightmap {
-    let mut data: Vec<Vec<HeightmapPrecision>> = Vec::new();
-    for i in 0..size {
-        let mut row = Vec::new();
-        for j in 0..size {
-            row.push(closure(i, j));
+    /// Also writes 64 bit floats for heightmaps of double precision, read as raw 64 bit data.
+    fn write_r32<P: Precision>(
+        heightmap: &Heightmap<P>,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()> {
+        for y in 0..heightmap.height {
+            for x in 0..heightmap.width {
+                heightmap.data[x][y].write_le(writer)?;
+            }
         }
-        data.push(row);
+        Ok(())
     }
 
-    Heightmap::new(data, size, size, 1.0, original_depth, None)
-}
-
-#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
-pub struct ProceduralHeightmapSettings {
-    pub seed: u64,
-    pub noise_type: NoiseTypeWrapper,
-    pub fractal_type: FractalTypeWrapper,
-    pub fractal_octaves: i32,
-    pub fractal_gain: f32,
-    pub fractal_lacunarity: f32,
-    pub frequency: f32,
-}
-
-const DEFAULT_PROCEDURAL_HEIGHTMAP_SETTINGS: ProceduralHeightmapSettings =
-    ProceduralHeightmapSettings {
-        seed: 1337,
-        noise_type: NoiseTypeWrapper::Perlin,
-        fractal_type: FractalTypeWrapper::FBM,
-        fractal_octaves: 5,
-        fractal_gain: 0.6,
-        fractal_lacunarity: 2.0,
-        frequency: 0.5,
-    };
-
-impl ProceduralHeightmapSettings {
-    const fn static_default() -> Self {
-        DEFAULT_PROCEDURAL_HEIGHTMAP_SETTINGS
+    fn write_png16<P: Precision>(
+        heightmap: &Heightmap<P>,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()> {
+        let mut encoder =
+            png::Encoder::new(writer, heightmap.width as u32, heightmap.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut data = Vec::with_capacity(heightmap.width * heightmap.height * 2);
+        for y in 0..heightmap.height {
+            for x in 0..heightmap.width {
+                let value = (heightmap.data[x][y] / heightmap.depth)
+                    .to_f32()
+                    .clamp(0.0, 1.0);
+                data.extend_from_slice(&((value * u16::MAX as f32).round() as u16).to_be_bytes());
+            }
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(())
     }
 
-    pub fn reset(&mut self) {
-        *self = ProceduralHeightmapSettings::default()
+    fn write_tiff_f32<P: Precision>(
+        heightmap: &Heightmap<P>,
+        writer: &mut (impl Write + Seek),
+    ) -> std::io::Result<()> {
+        let data: Vec<f32> = (0..heightmap.height)
+            .flat_map(|y| (0..heightmap.width).map(move |x| heightmap.data[x][y].to_f32()))
+            .collect();
+        tiff::encoder::TiffEncoder::new(writer)
+            .and_then(|mut encoder| {
+                encoder.write_image::<tiff::encoder::colortype::Gray32Float>(
+                    heightmap.width as u32,
+                    heightmap.height as u32,
+                    &data,
+                )
+            })
+            .map_err(std::io::Error::other)
     }
-}
 
-impl Default for ProceduralHeightmapSettings {
-    fn default() -> Self {
-        DEFAULT_PROCEDURAL_HEIGHTMAP_SETTINGS
+    /// Part of the map written when exporting layers of a state eroded with margins. Eroded
+    /// layers only cover the interior left after the margins were cut off while the base covers
+    /// the whole map, so without a common crop the layers of one export are off by the margin.
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+    pub enum ExportCrop {
+        /// The whole map, interior layers are put back into the frame of the base.
+        #[default]
+        Full,
+        Interior,
+        /// The whole map with the interior cleared to 0.
+        Frame,
     }
-}
-
-pub fn create_perlin_heightmap(
-    params: &HeightmapParameters,
-    settings: &ProceduralHeightmapSettings,
-) -> Heightmap {
-    let mut noise = FastNoise::seeded(settings.seed);
-    noise.set_noise_type(settings.noise_type.into());
-    noise.set_fractal_type(settings.fractal_type.into());
-    noise.set_fractal_octaves(settings.fractal_octaves);
-    noise.set_fractal_gain(settings.fractal_gain);
-    noise.set_fractal_lacunarity(settings.fractal_lacunarity);
-    noise.set_frequency(settings.frequency);
 
-    let denominator = params.size as f32 / 5.0;
+    impl Display for ExportCrop {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ExportCrop::Full => f.write_str("Full Map"),
+                ExportCrop::Interior => f.write_str("Interior"),
+                ExportCrop::Frame => f.write_str("Margin Frame"),
+            }
+        }
+    }
 
-    let mut data: HeightmapData = Vec::new();
+    impl ExportCrop {
+        pub fn list() -> [ExportCrop; 3] {
+            [ExportCrop::Full, ExportCrop::Interior, ExportCrop::Frame]
+        }
+    }
 
-    let mut min = noise.get_noise(0.0, 0.0);
-    let mut max = min.clone();
+    /// `heightmap` cropped to `crop` of a map of `extent` with `margin` around its interior, with
+    /// the crop recorded in the metadata. Heightmaps covering only the interior are framed by the
+    /// cells of `fill`, or 0 without it. Heightmaps of any other size are returned as they are.
+    pub fn crop_export(
+        heightmap: &Heightmap,
+        extent: Extent,
+        margin: Margins,
+        crop: ExportCrop,
+        fill: Option<&Heightmap>,
+    ) -> Heightmap {
+        let inside = |x: usize, y: usize| margin.in_interior(extent, UVector2::new(x, y));
+        let full: HeightmapData = if heightmap.extent() == extent {
+            heightmap.data.clone()
+        } else if heightmap.extent() == margin.interior(extent) {
+            (0..extent.width)
+                .map(|x| {
+                    (0..extent.height)
+                        .map(|y| {
+                            if inside(x, y) {
+                                heightmap.data[x - margin.left][y - margin.top]
+                            } else {
+                                fill.map(|fill| fill.data[x][y]).unwrap_or(0.0)
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        } else {
+            return heightmap.clone();
+        };
+        let mut cropped = Heightmap::new(
+            full,
+            extent.width,
+            extent.height,
+            heightmap.depth,
+            heightmap.original_depth,
+            heightmap.metadata.clone(),
+        );
+        match crop {
+            ExportCrop::Full => {}
+            ExportCrop::Interior => cropped = cropped.with_margin(margin).heightmap,
+            ExportCrop::Frame => {
+                for (x, col) in cropped.data.iter_mut().enumerate() {
+                    for (y, value) in col.iter_mut().enumerate() {
+                        if inside(x, y) {
+                            *value = 0.0;
+                        }
+                    }
+                }
+            }
+        }
+        if heightmap.total_height.is_some() {
+            cropped.calculate_total_height();
+        }
+        cropped.metadata_add("EXPORT_CROP", crop.to_string());
+        cropped.metadata_add("EXPORT_MARGIN", margin.to_string());
+        cropped
+    }
 
-    for x in 0..params.size {
-        data.push(vec![]);
-        for y in 0..params.size {
-            let n = noise.get_noise(x as f32 / denominator, y as f32 / denominator);
-            if n < min {
-                min = n;
+    // Attribute names in the exr header, metadata entries are stored as `EXR_METADATA` + key
+    const EXR_CHANNEL: &str = "Y";
+    const EXR_DEPTH: &str = "erosion.depth";
+    const EXR_ORIGINAL_DEPTH: &str = "erosion.original_depth";
+    const EXR_WRAP: &str = "erosion.wrap";
+    const EXR_METADATA: &str = "erosion.meta.";
+
+    fn write_exr<P: Precision>(
+        heightmap: &Heightmap<P>,
+        writer: &mut (impl Write + Seek),
+    ) -> std::io::Result<()> {
+        use exr::prelude::*;
+        let data: Vec<f32> = (0..heightmap.height)
+            .flat_map(|y| (0..heightmap.width).map(move |x| heightmap.data[x][y].to_f32()))
+            .collect();
+        let channel = AnyChannel::new(EXR_CHANNEL, FlatSamples::F32(data));
+        let mut attributes = LayerAttributes::named("height");
+        let mut attribute = |name: String, value: AttributeValue| {
+            // Names and values outside of latin-1 can not be stored and are left out
+            if let Some(name) = Text::new_or_none(name) {
+                attributes.other.insert(name, value);
             }
-            if n > max {
-                max = n;
+        };
+        attribute(
+            EXR_DEPTH.to_string(),
+            AttributeValue::F32(heightmap.depth.to_f32()),
+        );
+        attribute(
+            EXR_ORIGINAL_DEPTH.to_string(),
+            AttributeValue::F32(heightmap.original_depth.to_f32()),
+        );
+        attribute(
+            EXR_WRAP.to_string(),
+            AttributeValue::I32(heightmap.wrap as i32),
+        );
+        for (key, value) in heightmap.metadata.iter().flatten() {
+            if let Some(value) = Text::new_or_none(value) {
+                attribute(
+                    format!("{}{}", EXR_METADATA, key),
+                    AttributeValue::Text(value),
+                );
             }
-            data.last_mut().unwrap().push(n);
         }
+        let layer = Layer::new(
+            (heightmap.width, heightmap.height),
+            attributes,
+            Encoding::SMALL_LOSSLESS,
+            AnyChannels::sort(SmallVec::from_vec(vec![channel])),
+        );
+        Image::from_layer(layer)
+            .write()
+            .to_buffered(writer)
+            .map_err(std::io::Error::other)
     }
 
-    Heightmap::new(data, params.size, params.size, max - min, max - min, None).normalize()
-}
+    /// Writes the heightmap to `filename` with the extension of `format` appended. NumPy, CSV and
+    /// raw data keep the precision of the heights, the other formats store 32 bit or less.
+    pub fn export_data<P: Precision>(
+        heightmap: &Heightmap<P>,
+        filename: &str,
+        format: DataFormat,
+    ) -> Result<(), HeightmapIOError> {
+        let path = format!("{}.{}", filename, format.extension());
+        export_data_to(heightmap, Path::new(&path), format)
+    }
 
-#[cfg(feature = "export")]
-pub mod io {
-    use crate::heightmap::*;
-    use std::fs::{self, File};
-    use std::io::prelude::*;
+    /// Writes the heights as `format` to exactly `path`, unlike `export_data` which names the file.
+    pub fn export_data_to<P: Precision>(
+        heightmap: &Heightmap<P>,
+        path: &Path,
+        format: DataFormat,
+    ) -> Result<(), HeightmapIOError> {
+        fn _export<P: Precision>(
+            heightmap: &Heightmap<P>,
+            path: &Path,
+            format: DataFormat,
+        ) -> std::io::Result<()> {
+            let file = File::create(path)?;
+            let mut writer = BufWriter::new(file);
+            match format {
+                DataFormat::Npy => write_npy(heightmap, &mut writer)?,
+                DataFormat::Csv => write_csv(heightmap, &mut writer)?,
+                DataFormat::CsvGz => {
+                    let mut encoder = GzEncoder::new(writer, Compression::default());
+                    write_csv(heightmap, &mut encoder)?;
+                    writer = encoder.finish()?;
+                }
+                DataFormat::Png16 => write_png16(heightmap, &mut writer)?,
+                DataFormat::TiffF32 => write_tiff_f32(heightmap, &mut writer)?,
+                DataFormat::R32 => write_r32(heightmap, &mut writer)?,
+                DataFormat::Exr => write_exr(heightmap, &mut writer)?,
+            }
+            writer.flush()
+        }
 
-    #[derive(Debug)]
-    pub enum HeightmapIOError {
-        FileExportError,
-        FileImportError,
+        _export(heightmap, path, format).map_err(|_| HeightmapIOError::FileExportError)
     }
 
     pub fn export(
@@ -1056,7 +2793,8 @@ pub mod io {
     ) -> Result<(), HeightmapIOError> {
         fn _export(heightmap: &Heightmap, path: &str, filename: &str) -> std::io::Result<()> {
             fs::create_dir_all(path)?;
-            let data = serde_json::to_string(&heightmap).unwrap();
+            let data = schema::to_json(heightmap)
+                .map_err(|err| std::io::Error::other(format!("{:?}", err)))?;
             let mut file = File::create(format!("{}.json", filename))?;
             file.write_all(data.as_bytes())?;
             Ok(())
@@ -1076,9 +2814,7 @@ pub mod io {
                 file.read_to_string(&mut data)?;
             }
 
-            let heightmap: Heightmap = serde_json::from_str(&data).unwrap();
-
-            Ok(heightmap)
+            schema::from_json(&data).map_err(|err| std::io::Error::other(format!("{:?}", err)))
         }
         match _import(filename) {
             Ok(heightmap) => Ok(heightmap),
@@ -1086,6 +2822,123 @@ pub mod io {
         }
     }
 
+    pub const IMAGE_EXTENSIONS: [&str; 4] = [".png", ".tif", ".tiff", ".exr"];
+
+    /// Reads a grayscale png or tiff as heights in [0, 1], 8 and 16 bit images keep their full
+    /// precision and colour images are converted to grayscale. OpenEXR is read by `import_exr`.
+    pub fn import_image(path: &Path) -> Result<Heightmap, HeightmapIOError> {
+        if path
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("exr"))
+            .unwrap_or(false)
+        {
+            return import_exr(path);
+        }
+        let image = image::open(path).map_err(HeightmapIOError::ImageImportError)?;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let (bits, heights): (u32, Vec<HeightmapPrecision>) = match image {
+            DynamicImage::ImageLuma8(image) => {
+                (8, image.pixels().map(|p| p.0[0] as f32 / 255.0).collect())
+            }
+            DynamicImage::ImageLumaA8(image) => {
+                (8, image.pixels().map(|p| p.0[0] as f32 / 255.0).collect())
+            }
+            image => (
+                16,
+                image
+                    .to_luma16()
+                    .pixels()
+                    .map(|p| p.0[0] as f32 / u16::MAX as f32)
+                    .collect(),
+            ),
+        };
+        // Pixels are stored row by row
+        let data = (0..width)
+            .map(|x| (0..height).map(|y| heights[y * width + x]).collect())
+            .collect();
+        let mut heightmap = Heightmap::new(data, width, height, 1.0, 1.0, None);
+        heightmap.metadata_add("SOURCE", path.display().to_string());
+        heightmap.metadata_add("SOURCE_BIT_DEPTH", bits.to_string());
+        Ok(heightmap)
+    }
+
+    /// Reads the heights of an OpenEXR as they are, restoring the depth and metadata written by
+    /// `DataFormat::Exr`. Files from other tools are read from their `Y` channel, or their first
+    /// channel when they have none, with the depth taken from the highest height.
+    pub fn import_exr(path: &Path) -> Result<Heightmap, HeightmapIOError> {
+        use exr::prelude::*;
+        let image = read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+            .from_file(path)
+            .map_err(HeightmapIOError::ExrError)?;
+        let layer = &image.layer_data;
+        let (width, height) = (layer.size.width(), layer.size.height());
+        let channel = layer
+            .channel_data
+            .list
+            .iter()
+            .find(|channel| channel.name == *EXR_CHANNEL)
+            .or_else(|| layer.channel_data.list.first())
+            .ok_or(HeightmapIOError::FileImportError)?;
+        let heights: Vec<HeightmapPrecision> = channel.sample_data.values_as_f32().collect();
+        // Pixels are stored row by row
+        let data = (0..width)
+            .map(|x| (0..height).map(|y| heights[y * width + x]).collect())
+            .collect();
+        let attributes = image.attributes.other.iter().chain(&layer.attributes.other);
+        let (mut depth, mut original_depth, mut wrap) = (None, None, false);
+        let mut metadata = HashMap::new();
+        for (name, value) in attributes {
+            let name = name.to_string();
+            match (name.as_str(), value) {
+                (EXR_DEPTH, AttributeValue::F32(value)) => depth = Some(*value),
+                (EXR_ORIGINAL_DEPTH, AttributeValue::F32(value)) => original_depth = Some(*value),
+                (EXR_WRAP, AttributeValue::I32(value)) => wrap = *value != 0,
+                (_, AttributeValue::Text(value)) if name.starts_with(EXR_METADATA) => {
+                    metadata.insert(name[EXR_METADATA.len()..].to_string(), value.to_string());
+                }
+                _ => {}
+            }
+        }
+        let depth = depth.unwrap_or_else(|| heights.iter().fold(f32::EPSILON, |a, &b| a.max(b)));
+        let mut heightmap = Heightmap::new(
+            data,
+            width,
+            height,
+            depth,
+            original_depth.unwrap_or(depth),
+            None,
+        );
+        heightmap.wrap = wrap;
+        if metadata.is_empty() {
+            heightmap.metadata_add("SOURCE", path.display().to_string());
+        } else {
+            heightmap.metadata = Some(metadata);
+        }
+        Ok(heightmap)
+    }
+
+    /// Names of the images in `path` that `import_image` can read, sorted.
+    pub fn list_images(path: &str) -> std::io::Result<Vec<String>> {
+        let mut images: Vec<String> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| {
+                let name = name.to_lowercase();
+                IMAGE_EXTENSIONS
+                    .iter()
+                    .any(|extension| name.ends_with(extension))
+            })
+            .collect();
+        images.sort();
+        Ok(images)
+    }
+
     pub fn save_heightmap_as_image(
         heightmap: &Heightmap,
         filename: &str,
@@ -1114,17 +2967,319 @@ pub mod io {
         .unwrap()
     }
 
-    pub fn export_heightmaps(heightmaps: Vec<&Heightmap>, path: &str, filenames: Vec<&str>) {
-        println!("Exporting heightmaps...");
+    /// Exports every heightmap as json and png, plus each of the raw data `formats`. Returns what
+    /// could not be saved, exporting carries on past every failure.
+    pub fn export_heightmaps(
+        heightmaps: Vec<&Heightmap>,
+        path: &str,
+        filenames: Vec<&str>,
+        formats: &[DataFormat],
+    ) -> Vec<String> {
+        let mut failures = Vec::new();
         for (heightmap, filename) in heightmaps.iter().zip(filenames.iter()) {
-            io::export(heightmap, path, filename).unwrap();
+            if export(heightmap, path, filename).is_err() {
+                failures.push(format!("Failed to save {} as json!", filename));
+            }
             if let Err(e) = save_heightmap_as_image(heightmap, filename) {
-                println!(
-                    "Failed to save {}! Make sure the output folder exists.",
-                    filename
-                );
-                println!("Given Reason: {}", e);
+                failures.push(format!(
+                    "Failed to save {}! Make sure the output folder exists. Given Reason: {}",
+                    filename, e
+                ));
+            }
+            for &format in formats {
+                if export_data(heightmap, filename, format).is_err() {
+                    failures.push(format!("Failed to save {} as {}!", filename, format));
+                }
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        check_heightmap, temp_path, tiny_heightmap, tiny_rectangular_heightmap, Check, SIZE,
+    };
+    use crate::heightmap;
+
+    #[test]
+    fn heightmap_combinators() -> Check {
+        let heightmap = tiny_rectangular_heightmap();
+        let cells: Vec<_> = heightmap.iter_cells().collect();
+        if cells.len() != SIZE * SIZE * 2 || cells[1] != (0, 1, heightmap.data[0][1]) {
+            return Err("cells are not iterated column by column".to_string());
+        }
+        let mut mapped = heightmap.clone();
+        mapped.map_inplace(|x, y, v| v + (x * 1000 + y) as f32);
+        if mapped.data[3][5] != heightmap.data[3][5] + 3005.0 {
+            return Err("map_inplace passes the wrong cell".to_string());
+        }
+        let difference = mapped
+            .zip_map(&heightmap, |a, b| a - b)
+            .map_err(|err| format!("{:?}", err))?;
+        if (difference.data[7][2] - 7002.0).abs() > 0.01 {
+            return Err("zip_map combines the wrong cells".to_string());
+        }
+        if mapped.zip_map(&tiny_heightmap(), |a, _| a).is_ok() {
+            return Err("zip_map accepts heightmaps of different sizes".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn missing_preset_file() -> Check {
+        let path = temp_path("missing.png");
+        let params = heightmap::HeightmapParameters {
+            size: SIZE,
+            height: None,
+        };
+        let preset = HeightmapType::FromFile(params, path.clone());
+        match heightmap::try_create_heightmap_from_preset(&preset) {
+            Ok(_) => return Err(format!("imported {}", path.display())),
+            Err(err) if err.path != path => return Err(format!("reported {}", err.path.display())),
+            Err(_) => {}
+        }
+        // The infallible variant still gives an empty heightmap the size of the preset
+        check_heightmap(
+            &heightmap::create_heightmap_from_preset(&preset),
+            SIZE,
+            SIZE,
+        )
+    }
+
+    #[test]
+    fn slope_and_aspect() -> Check {
+        let plane = |height: fn(usize, usize) -> f32| {
+            let mut plane = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+            plane.map_inplace(|x, y, _| height(x, y));
+            plane
+        };
+        // Rising towards the east, so facing west, and towards the north, facing south
+        let east = plane(|x, _| x as f32 * 0.01);
+        let north = plane(|_, y| (SIZE - y) as f32 * 0.01);
+        let slope = east.slope_map();
+        let expected = (0.01 * SIZE as f32).atan().to_degrees();
+        for (x, y) in [(0, 0), (5, 9), (SIZE - 1, SIZE - 1)] {
+            if (slope.data[x][y] - expected).abs() > 1e-3 {
+                return Err(format!("slope {} at {}, {}", slope.data[x][y], x, y));
+            }
+        }
+        let (west, south) = (east.aspect_map(), north.aspect_map());
+        if (west.data[5][9] - 270.0).abs() > 1e-3 || (south.data[5][9] - 180.0).abs() > 1e-3 {
+            return Err(format!(
+                "aspects {} and {}",
+                west.data[5][9], south.data[5][9]
+            ));
+        }
+        let flat = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        if flat.slope_map().get_range() != (0.0, 0.0) || flat.aspect_map().get_range() != (0.0, 0.0)
+        {
+            return Err("flat terrain has a slope or aspect".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn layer_math() -> Check {
+        let mut base = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        base.map_inplace(|x, _, _| x as f32 / SIZE as f32);
+        let mut eroded = base.clone();
+        eroded.map_inplace(|_, y, h| h * 0.5 + y as f32 * 0.01);
+
+        let blend = HeightmapOperation::Lerp
+            .apply(&eroded, &base, 0.5)
+            .map_err(|e| format!("{:?}", e))?;
+        let mut inplace = eroded.clone();
+        inplace
+            .lerp_inplace(&base, 0.5)
+            .map_err(|e| format!("{:?}", e))?;
+        for (x, y, h) in blend.iter_cells() {
+            let expected = (eroded.data[x][y] + base.data[x][y]) / 2.0;
+            if (h - expected).abs() > 1e-6 || inplace.data[x][y] != h {
+                return Err(format!(
+                    "blend {} at ({}, {}), expected {}",
+                    h, x, y, expected
+                ));
+            }
+        }
+
+        for operation in HeightmapOperation::list() {
+            let expected = |a: f32, b: f32| match operation {
+                HeightmapOperation::Add => a + b,
+                HeightmapOperation::Multiply => a * b,
+                HeightmapOperation::Lerp => a,
+                HeightmapOperation::Min => a.min(b),
+                HeightmapOperation::Max => a.max(b),
+            };
+            let result = operation
+                .apply(&base, &eroded, 0.0)
+                .map_err(|e| format!("{}: {:?}", operation, e))?;
+            if result
+                .iter_cells()
+                .any(|(x, y, h)| h != expected(base.data[x][y], eroded.data[x][y]))
+            {
+                return Err(format!("{} differs from its cell-wise result", operation));
+            }
+        }
+
+        let mut sum = base.clone();
+        sum.add_inplace(&eroded).map_err(|e| format!("{:?}", e))?;
+        if sum.data != base.add(&eroded).map_err(|e| format!("{:?}", e))?.data {
+            return Err("add_inplace differs from add".to_string());
+        }
+        let small = Heightmap::new_empty(SIZE / 2, SIZE, 1.0, 1.0);
+        match (base.max(&small), sum.min_inplace(&small)) {
+            (Err(HeightmapError::MismatchingSize), Err(HeightmapError::MismatchingSize)) => Ok(()),
+            _ => Err("mismatching sizes were combined".to_string()),
+        }
+    }
+
+    #[test]
+    fn resample() -> Check {
+        let mut ramp = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        ramp.map_inplace(|x, y, _| x as f32 * 0.01 + y as f32 * 0.02);
+        for filter in ResampleFilter::list() {
+            let same = ramp.resample(SIZE, SIZE, filter);
+            if same.data != ramp.data {
+                return Err(format!("{} changed the heights at the same size", filter));
+            }
+            let half = ramp.resample(SIZE / 2, SIZE / 4, filter);
+            if (half.width, half.height) != (SIZE / 2, SIZE / 4) {
+                return Err(format!("{} made {}x{}", filter, half.width, half.height));
+            }
+        }
+
+        // Both interpolations are exact on a plane, away from the clamped edges
+        let source = |v: usize| (v as f32 + 0.5) / 2.0 - 0.5;
+        for filter in [ResampleFilter::Bilinear, ResampleFilter::Bicubic] {
+            let double = ramp.resample(SIZE * 2, SIZE * 2, filter);
+            for x in 4..SIZE * 2 - 4 {
+                for y in 4..SIZE * 2 - 4 {
+                    let expected = source(x) * 0.01 + source(y) * 0.02;
+                    if (double.data[x][y] - expected).abs() > 1e-5 {
+                        return Err(format!(
+                            "{} gave {} at ({}, {}), expected {}",
+                            filter, double.data[x][y], x, y, expected
+                        ));
+                    }
+                }
+            }
+        }
+        let nearest = ramp.resample(SIZE * 2, SIZE * 2, ResampleFilter::Nearest);
+        if nearest
+            .iter_cells()
+            .any(|(x, y, h)| h != ramp.data[x / 2][y / 2])
+        {
+            return Err("nearest did not repeat the cells".to_string());
+        }
+
+        let mut peak = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        peak.data[SIZE / 2][SIZE / 2] = 1.0;
+        let smooth = peak.resample(SIZE * 3, SIZE * 3, ResampleFilter::Bicubic);
+        let (low, high) = smooth.get_range();
+        if low < 0.0 || high > 1.0 {
+            return Err(format!("bicubic overshot to [{}, {}]", low, high));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn crop_and_extend() -> Check {
+        use crate::math::{Extent, Margins, UVector2};
+        let mut ramp = Heightmap::new_empty(SIZE, SIZE / 2, 1.0, 1.0);
+        ramp.map_inplace(|x, y, _| x as f32 * 0.01 + y as f32 * 0.001);
+
+        let cropped = ramp
+            .crop(UVector2::new(3, 5), Extent::new(10, 4))
+            .map_err(|e| format!("{:?}", e))?;
+        if cropped.extent() != Extent::new(10, 4) || cropped.data[0][0] != ramp.data[3][5] {
+            return Err(format!(
+                "cropped to {}x{} starting at {}",
+                cropped.width, cropped.height, cropped.data[0][0]
+            ));
+        }
+        if !matches!(
+            ramp.crop(UVector2::new(SIZE - 4, 0), Extent::new(8, 1)),
+            Err(HeightmapError::OutOfBounds)
+        ) {
+            return Err("cropped past the edge".to_string());
+        }
+
+        let margins = Margins::new(2, 1, 3, 4);
+        let inner = |extended: &Heightmap| {
+            ramp.iter_cells()
+                .all(|(x, y, h)| extended.data[x + margins.left][y + margins.top] == h)
+        };
+        for fill in ExtendFill::list() {
+            let extended = ramp.extend(margins, fill);
+            if extended.extent() != Extent::new(SIZE + 5, SIZE / 2 + 5) || !inner(&extended) {
+                return Err(format!("{} moved the heightmap", fill));
             }
         }
+        let (last_x, last_y) = (SIZE - 1, SIZE / 2 - 1);
+        let corner = |fill: ExtendFill| {
+            let extended = ramp.extend(margins, fill);
+            (extended.data[0][0], extended.data[SIZE + 4][SIZE / 2 + 4])
+        };
+        let expected = [
+            (ExtendFill::Height(0.25), (0.25, 0.25)),
+            (
+                ExtendFill::Edge,
+                (ramp.data[0][0], ramp.data[last_x][last_y]),
+            ),
+            (
+                ExtendFill::Mirror,
+                (ramp.data[2][0], ramp.data[last_x - 1][last_y - 3]),
+            ),
+            (
+                ExtendFill::Wrap,
+                (ramp.data[SIZE - 3][SIZE / 2 - 1], ramp.data[1][3]),
+            ),
+        ];
+        for (fill, corners) in expected {
+            if corner(fill) != corners {
+                return Err(format!(
+                    "{} corners {:?}, expected {:?}",
+                    fill,
+                    corner(fill),
+                    corners
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "export")]
+    fn export_crop() -> Check {
+        use crate::math::Margins;
+        use io::{crop_export, ExportCrop};
+        let heightmap = tiny_rectangular_heightmap();
+        let size = heightmap.extent();
+        let margin = Margins::new(1, 2, 3, 4);
+        let interior = heightmap.with_margin(margin).heightmap;
+        let full = crop_export(&interior, size, margin, ExportCrop::Full, Some(&heightmap));
+        if full.data != heightmap.data {
+            return Err("interior was not framed by the fill".to_string());
+        }
+        let cropped = crop_export(&heightmap, size, margin, ExportCrop::Interior, None);
+        if cropped.data != interior.data {
+            return Err("full map was not cropped to the interior".to_string());
+        }
+        let frame = crop_export(&interior, size, margin, ExportCrop::Frame, None);
+        if frame.data[0][0] != 0.0 || frame.data[3][2] != 0.0 {
+            return Err("frame of an interior without fill is not 0".to_string());
+        }
+        let frame = crop_export(&heightmap, size, margin, ExportCrop::Frame, None);
+        if frame.data[2][1] != heightmap.data[2][1] || frame.data[3][2] != 0.0 {
+            return Err("frame does not keep only the margins".to_string());
+        }
+        let recorded = frame.metadata.as_ref().and_then(|m| m.get("EXPORT_CROP"));
+        if recorded != Some(&ExportCrop::Frame.to_string()) {
+            return Err("crop is not recorded in the metadata".to_string());
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,235 @@
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use serde::{Deserialize, Serialize};
+
+/*
+Local shape of the terrain for comparing results quantitatively, such as how much the seams of a
+partitioning method roughen the terrain. Derivatives are taken from the 3x3 window around every
+cell with the map spanning one unit along its longest side and the depth one unit high, the same
+units as the slope, and the window is clamped at the edges unless the heightmap wraps.
+
+Profile curvature is the curvature along the slope, how fast it steepens or flattens going
+downhill, and plan curvature is the curvature across it, how the contour lines bend. Both are
+positive where the terrain is convex, on ridges and shoulders, and negative where it is concave, in
+valleys and at the feet of slopes, and both are 0 on flat terrain. Roughness is the standard
+deviation of the heights in the window, in the same units.
+
+Seams of partitioned erosion show as kinks, where the slope on one side of a boundary does not
+carry on into the other. The gradient discontinuity of a cell is the sum of its squared second
+derivatives along both axes, and the seam energy is its mean over the cells along the boundaries
+between partitions. Compared to the mean over the rest of the map, it tells how much the seams
+stand out from the terrain around them whatever the terrain, 1 where they cannot be told apart.
+ */
+
+/// The heights in the 3x3 window around a cell, `window[dx + 1][dy + 1]`, relative to the depth.
+fn window(heightmap: &Heightmap, x: usize, y: usize) -> [[HeightmapPrecision; 3]; 3] {
+    let index = |v: usize, d: i32, size: usize| {
+        let v = v as i32 + d;
+        if heightmap.wrap {
+            v.rem_euclid(size as i32) as usize
+        } else {
+            v.clamp(0, size as i32 - 1) as usize
+        }
+    };
+    let mut window = [[0.0; 3]; 3];
+    for (i, column) in window.iter_mut().enumerate() {
+        for (j, height) in column.iter_mut().enumerate() {
+            let nx = index(x, i as i32 - 1, heightmap.width);
+            let ny = index(y, j as i32 - 1, heightmap.height);
+            *height = heightmap.data[nx][ny] / heightmap.depth;
+        }
+    }
+    window
+}
+
+/// First and second derivatives of a cell, (zx, zy, zxx, zyy, zxy).
+fn derivatives(heightmap: &Heightmap, x: usize, y: usize) -> [HeightmapPrecision; 5] {
+    let z = window(heightmap, x, y);
+    let spacing = 1.0 / heightmap.width.max(heightmap.height) as HeightmapPrecision;
+    let zx = (z[2][1] - z[0][1]) / (2.0 * spacing);
+    let zy = (z[1][2] - z[1][0]) / (2.0 * spacing);
+    let zxx = (z[2][1] - 2.0 * z[1][1] + z[0][1]) / (spacing * spacing);
+    let zyy = (z[1][2] - 2.0 * z[1][1] + z[1][0]) / (spacing * spacing);
+    let zxy = (z[2][2] - z[2][0] - z[0][2] + z[0][0]) / (4.0 * spacing * spacing);
+    [zx, zy, zxx, zyy, zxy]
+}
+
+pub fn profile_curvature(heightmap: &Heightmap, x: usize, y: usize) -> HeightmapPrecision {
+    let [p, q, r, t, s] = derivatives(heightmap, x, y);
+    let gradient = p * p + q * q;
+    if gradient <= HeightmapPrecision::EPSILON {
+        return 0.0;
+    }
+    -(p * p * r + 2.0 * p * q * s + q * q * t) / (gradient * (1.0 + gradient).powf(1.5))
+}
+
+pub fn plan_curvature(heightmap: &Heightmap, x: usize, y: usize) -> HeightmapPrecision {
+    let [p, q, r, t, s] = derivatives(heightmap, x, y);
+    let gradient = p * p + q * q;
+    if gradient <= HeightmapPrecision::EPSILON {
+        return 0.0;
+    }
+    -(q * q * r - 2.0 * p * q * s + p * p * t) / gradient.powf(1.5)
+}
+
+pub fn roughness(heightmap: &Heightmap, x: usize, y: usize) -> HeightmapPrecision {
+    let z = window(heightmap, x, y);
+    let mean = z.iter().flatten().sum::<HeightmapPrecision>() / 9.0;
+    let variance = z
+        .iter()
+        .flatten()
+        .map(|h| (h - mean).powi(2))
+        .sum::<HeightmapPrecision>()
+        / 9.0;
+    variance.sqrt()
+}
+
+/// How sharply the slope changes at a cell, see the seam energy above.
+pub fn gradient_discontinuity(heightmap: &Heightmap, x: usize, y: usize) -> HeightmapPrecision {
+    let [_, _, zxx, zyy, _] = derivatives(heightmap, x, y);
+    zxx * zxx + zyy * zyy
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeamMetrics {
+    /// Mean gradient discontinuity of the cells along the seams.
+    pub seam_energy: HeightmapPrecision,
+    /// Mean gradient discontinuity of the other cells.
+    pub background_energy: HeightmapPrecision,
+    pub seam_cells: usize,
+}
+
+impl SeamMetrics {
+    /// Seam over background energy, 1 where the seams do not stand out.
+    pub fn ratio(&self) -> HeightmapPrecision {
+        if self.background_energy > 0.0 {
+            self.seam_energy / self.background_energy
+        } else {
+            1.0
+        }
+    }
+}
+
+/// `seams[x][y]` marks the cells along the boundaries between partitions, `None` if none or every
+/// cell is marked.
+pub fn seam_metrics(heightmap: &Heightmap, seams: &[Vec<bool>]) -> Option<SeamMetrics> {
+    let (mut seam, mut background) = ((0.0, 0), (0.0, 0));
+    let (width, height) = (heightmap.width, heightmap.height);
+    for (x, y, _) in heightmap.iter_cells() {
+        // The clamped window turns the slope at the edges into a kink of its own
+        if !heightmap.wrap && (x == 0 || y == 0 || x + 1 == width || y + 1 == height) {
+            continue;
+        }
+        let energy = gradient_discontinuity(heightmap, x, y);
+        let sum = if seams[x][y] {
+            &mut seam
+        } else {
+            &mut background
+        };
+        sum.0 += energy;
+        sum.1 += 1;
+    }
+    if seam.1 == 0 || background.1 == 0 {
+        return None;
+    }
+    Some(SeamMetrics {
+        seam_energy: seam.0 / seam.1 as HeightmapPrecision,
+        background_energy: background.0 / background.1 as HeightmapPrecision,
+        seam_cells: seam.1,
+    })
+}
+
+/// Averages of the terrain shape over a whole heightmap.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct TerrainMetrics {
+    /// In degrees.
+    pub mean_slope: HeightmapPrecision,
+    pub mean_abs_profile_curvature: HeightmapPrecision,
+    pub mean_abs_plan_curvature: HeightmapPrecision,
+    pub mean_roughness: HeightmapPrecision,
+}
+
+pub fn measure(heightmap: &Heightmap) -> TerrainMetrics {
+    let cells = (heightmap.width * heightmap.height).max(1) as HeightmapPrecision;
+    let slope = heightmap.slope_map();
+    let mut metrics = TerrainMetrics {
+        mean_slope: 0.0,
+        mean_abs_profile_curvature: 0.0,
+        mean_abs_plan_curvature: 0.0,
+        mean_roughness: 0.0,
+    };
+    for (x, y, _) in heightmap.iter_cells() {
+        metrics.mean_slope += slope.data[x][y];
+        metrics.mean_abs_profile_curvature += profile_curvature(heightmap, x, y).abs();
+        metrics.mean_abs_plan_curvature += plan_curvature(heightmap, x, y).abs();
+        metrics.mean_roughness += roughness(heightmap, x, y);
+    }
+    metrics.mean_slope /= cells;
+    metrics.mean_abs_profile_curvature /= cells;
+    metrics.mean_abs_plan_curvature /= cells;
+    metrics.mean_roughness /= cells;
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Check, SIZE};
+    use crate::heightmap;
+
+    #[test]
+    fn terrain_shape() -> Check {
+        use heightmap::{analysis, DerivedLayer};
+        let centre = SIZE as f32 / 2.0;
+        let mut dome = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        dome.map_inplace(|x, y, _| {
+            let (dx, dy) = (x as f32 - centre, y as f32 - centre);
+            0.5 - (dx * dx + dy * dy) * 0.0002
+        });
+        let mut bowl = dome.clone();
+        bowl.map_inplace(|_, _, h| 1.0 - h);
+        let cell = (centre as usize + 5, centre as usize + 3);
+        let curvatures = |heightmap: &Heightmap| {
+            (
+                analysis::profile_curvature(heightmap, cell.0, cell.1),
+                analysis::plan_curvature(heightmap, cell.0, cell.1),
+            )
+        };
+        let (dome_profile, dome_plan) = curvatures(&dome);
+        let (bowl_profile, bowl_plan) = curvatures(&bowl);
+        if dome_profile <= 0.0 || dome_plan <= 0.0 || bowl_profile >= 0.0 || bowl_plan >= 0.0 {
+            return Err(format!(
+                "dome {} {}, bowl {} {}",
+                dome_profile, dome_plan, bowl_profile, bowl_plan
+            ));
+        }
+
+        let mut plane = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        plane.map_inplace(|x, _, _| x as f32 * 0.01);
+        let (profile, plan) = (
+            analysis::profile_curvature(&plane, 9, 9),
+            analysis::plan_curvature(&plane, 9, 9),
+        );
+        let roughness = analysis::roughness(&plane, 9, 9);
+        let expected = (0.0002f32 * 3.0 / 9.0).sqrt();
+        if profile.abs() > 1e-2 || plan.abs() > 1e-2 || (roughness - expected).abs() > 1e-5 {
+            return Err(format!(
+                "plane curvatures {} {}, roughness {}",
+                profile, plan, roughness
+            ));
+        }
+
+        let layer = bowl.derived_layer(DerivedLayer::ProfileCurvature);
+        let (min, max) = layer.get_range();
+        if min >= 0.0 || layer.depth < min.abs().max(max.abs()) {
+            return Err(format!(
+                "profile curvature layer {} to {}, depth {}",
+                min, max, layer.depth
+            ));
+        }
+        let metrics = analysis::measure(&dome);
+        if metrics.mean_slope <= 0.0 || metrics.mean_abs_plan_curvature <= 0.0 {
+            return Err(format!("{:?}", metrics));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Write};
+use std::fs;
+
+/*
+Contour lines traced with marching squares, drawn over the isoline view and exported as vectors
+for CAD and GIS tools, which can't use the raster isoline. Exports place cell centres at .5 so the
+lines fall on the pixels of the exported pngs, making the SVG as many units wide as the map is
+cells, with rows going down. GeoJSON has y going up like maps do, so rows are flipped, and no
+coordinate reference system since the heightmap has no place on Earth. Every line carries its
+height, in the `data-height` of its group in SVG and in the properties of its feature in GeoJSON,
+along with whether it is an index contour.
+ */
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContourFormat {
+    #[default]
+    Svg,
+    GeoJson,
+}
+
+impl Display for ContourFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContourFormat::Svg => f.write_str("SVG"),
+            ContourFormat::GeoJson => f.write_str("GeoJSON"),
+        }
+    }
+}
+
+impl ContourFormat {
+    pub fn list() -> [ContourFormat; 2] {
+        [ContourFormat::Svg, ContourFormat::GeoJson]
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContourFormat::Svg => "svg",
+            ContourFormat::GeoJson => "geojson",
+        }
+    }
+}
+
+/// The contour lines at one height, `index` for the thicker lines of a topographic map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContourLevel {
+    pub height: HeightmapPrecision,
+    pub index: bool,
+    pub contours: Vec<Contour>,
+}
+
+/// A traced contour in cell coordinates, closed contours end where they start.
+pub type Contour = Vec<(f32, f32)>;
+
+/// A cell edge given by its first corner and whether it runs vertically from there.
+type Edge = (usize, usize, bool);
+
+/// Where the contour at `level` crosses `edge`, linearly interpolated between its corners.
+fn crossing(heightmap: &Heightmap, level: HeightmapPrecision, edge: Edge) -> (f32, f32) {
+    let (x, y, vertical) = edge;
+    let (end_x, end_y) = if vertical { (x, y + 1) } else { (x + 1, y) };
+    let a = heightmap.data[x][y];
+    let b = heightmap.data[end_x][end_y];
+    let t = if (b - a).abs() > HeightmapPrecision::EPSILON {
+        ((level - a) / (b - a)).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    (
+        x as f32 + t * (end_x - x) as f32,
+        y as f32 + t * (end_y - y) as f32,
+    )
+}
+
+/// Marching squares segments, saddles are resolved by the average of the four corners.
+fn segments(heightmap: &Heightmap, level: HeightmapPrecision) -> Vec<(Edge, Edge)> {
+    let mut segments = Vec::new();
+    let above = |x: usize, y: usize| heightmap.data[x][y] >= level;
+    for x in 0..heightmap.width.saturating_sub(1) {
+        for y in 0..heightmap.height.saturating_sub(1) {
+            let top = (x, y, false);
+            let bottom = (x, y + 1, false);
+            let left = (x, y, true);
+            let right = (x + 1, y, true);
+            let case = above(x, y) as u8
+                | (above(x + 1, y) as u8) << 1
+                | (above(x + 1, y + 1) as u8) << 2
+                | (above(x, y + 1) as u8) << 3;
+            match case {
+                1 | 14 => segments.push((left, top)),
+                2 | 13 => segments.push((top, right)),
+                3 | 12 => segments.push((left, right)),
+                4 | 11 => segments.push((right, bottom)),
+                6 | 9 => segments.push((top, bottom)),
+                7 | 8 => segments.push((left, bottom)),
+                5 | 10 => {
+                    let centre = (heightmap.data[x][y]
+                        + heightmap.data[x + 1][y]
+                        + heightmap.data[x + 1][y + 1]
+                        + heightmap.data[x][y + 1])
+                        / 4.0;
+                    if (case == 5) != (centre >= level) {
+                        segments.push((left, top));
+                        segments.push((right, bottom));
+                    } else {
+                        segments.push((top, right));
+                        segments.push((left, bottom));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    segments
+}
+
+/// Traces the contour lines of `heightmap` at `level` and joins them into polylines.
+pub fn trace(heightmap: &Heightmap, level: HeightmapPrecision) -> Vec<Contour> {
+    let segments = segments(heightmap, level);
+    let mut ends: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (i, (a, b)) in segments.iter().enumerate() {
+        ends.entry(*a).or_default().push(i);
+        ends.entry(*b).or_default().push(i);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let follow = |from: Edge, used: &mut [bool]| {
+        let mut path = Vec::new();
+        let mut current = from;
+        while let Some(&next) = ends[&current].iter().find(|&&s| !used[s]) {
+            used[next] = true;
+            let (a, b) = segments[next];
+            current = if a == current { b } else { a };
+            path.push(current);
+        }
+        path
+    };
+
+    let mut contours = Vec::new();
+    for start in 0..segments.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let (a, b) = segments[start];
+        let forward = follow(b, &mut used);
+        let backward = follow(a, &mut used);
+        let edges = backward.into_iter().rev().chain([a, b]).chain(forward);
+        contours.push(edges.map(|edge| crossing(heightmap, level, edge)).collect());
+    }
+    contours
+}
+
+/// Levels every `interval` of `depth`, from the first above 0 up to the last below `depth`.
+pub fn levels(depth: HeightmapPrecision, interval: HeightmapPrecision) -> Vec<HeightmapPrecision> {
+    if interval <= 0.0 || depth <= 0.0 {
+        return Vec::new();
+    }
+    let step = interval * depth;
+    (1..)
+        .map(|i| i as HeightmapPrecision * step)
+        .take_while(|level| *level < depth - HeightmapPrecision::EPSILON)
+        .collect()
+}
+
+/// Traces contour lines every `interval` of the depth, every `index_every`-th one an index
+/// contour, none when it is 0.
+pub fn trace_levels(
+    heightmap: &Heightmap,
+    interval: HeightmapPrecision,
+    index_every: usize,
+) -> Vec<ContourLevel> {
+    levels(heightmap.depth, interval)
+        .into_iter()
+        .enumerate()
+        .map(|(i, height)| ContourLevel {
+            height,
+            index: index_every > 0 && (i + 1).is_multiple_of(index_every),
+            contours: trace(heightmap, height),
+        })
+        .collect()
+}
+
+pub fn to_svg(heightmap: &Heightmap, levels: &[ContourLevel]) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = heightmap.width,
+        h = heightmap.height
+    );
+    for level in levels {
+        let _ = writeln!(
+            svg,
+            "<g data-height=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"{}\">",
+            level.height,
+            if level.index { 0.5 } else { 0.25 }
+        );
+        for contour in &level.contours {
+            let points: Vec<String> = contour
+                .iter()
+                .map(|(x, y)| format!("{},{}", x + 0.5, y + 0.5))
+                .collect();
+            let _ = writeln!(svg, "<polyline points=\"{}\"/>", points.join(" "));
+        }
+        svg.push_str("</g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn to_geojson(heightmap: &Heightmap, levels: &[ContourLevel]) -> Value {
+    let top = heightmap.height as f32 - 0.5;
+    let features: Vec<Value> = levels
+        .iter()
+        .flat_map(|level| {
+            level.contours.iter().map(move |contour| {
+                let coordinates: Vec<[f32; 2]> =
+                    contour.iter().map(|(x, y)| [x + 0.5, top - y]).collect();
+                json!({
+                    "type": "Feature",
+                    "properties": { "height": level.height, "index": level.index },
+                    "geometry": { "type": "LineString", "coordinates": coordinates },
+                })
+            })
+        })
+        .collect();
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Writes the contour lines to `filename` with the extension of the format appended.
+pub fn export(
+    heightmap: &Heightmap,
+    levels: &[ContourLevel],
+    filename: &str,
+    format: ContourFormat,
+) -> std::io::Result<()> {
+    let text = match format {
+        ContourFormat::Svg => to_svg(heightmap, levels),
+        ContourFormat::GeoJson => to_geojson(heightmap, levels).to_string(),
+    };
+    fs::write(format!("{}.{}", filename, format.extension()), text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_path, Check, SIZE};
+    use crate::heightmap;
+
+    #[test]
+    fn contour_export() -> Check {
+        use heightmap::contours::{self, ContourFormat};
+        let mut ramp = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                ramp.data[x][y] = y as f32 / (SIZE - 1) as f32;
+            }
+        }
+        let levels = contours::trace_levels(&ramp, 0.25, 2);
+        let heights: Vec<(f32, bool)> = levels.iter().map(|l| (l.height, l.index)).collect();
+        if heights != [(0.25, false), (0.5, true), (0.75, false)] {
+            return Err(format!("levels {:?}", heights));
+        }
+        let svg = contours::to_svg(&ramp, &levels);
+        if svg.matches("<polyline").count() != 3 || !svg.contains("data-height=\"0.5\"") {
+            return Err(format!("svg {}", svg));
+        }
+        let geojson = contours::to_geojson(&ramp, &levels);
+        let features = geojson["features"].as_array().cloned().unwrap_or_default();
+        if features.len() != 3 || features[1]["properties"]["index"] != true {
+            return Err(format!("geojson {}", geojson));
+        }
+        // Rows go up in GeoJSON, so the lowest contour is near the top of the map
+        let y = features[0]["geometry"]["coordinates"][0][1]
+            .as_f64()
+            .unwrap_or(0.0);
+        if (y - SIZE as f64 * 0.75).abs() > 1.0 {
+            return Err(format!("lowest contour at y = {}", y));
+        }
+        for format in ContourFormat::list() {
+            let path = temp_path("contours");
+            let filename = path.to_string_lossy().to_string();
+            contours::export(&ramp, &levels, &filename, format)
+                .map_err(|err| format!("{:?}", err))?;
+            let written = format!("{}.{}", filename, format.extension());
+            let size = fs::metadata(&written).map(|m| m.len()).unwrap_or(0);
+            let _ = fs::remove_file(&written);
+            if size == 0 {
+                return Err(format!("nothing written to {}", written));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+use crate::heightmap::segments::{self, SegmentMethod, Segmentation};
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/*
+Drainage of the terrain by the D8 flow model, water on every cell runs on to the one of its eight
+neighbours with the steepest drop, diagonal drops being divided by their longer distance. Cells
+without a lower neighbour are pits where the water stays, so every path of flow ends in a pit or on
+a flat patch of pits, and the cells draining to the same patch make up a basin. Basins are labelled
+like the other segmentations, in the order their pits are first met going column by column.
+
+Flow accumulation counts the cells draining through every cell, itself included, so the rivers of
+the terrain stand out as the lines of high accumulation. Flat areas and small dips turn into pits
+of their own, which splits the basins on noisy or unfinished terrain. Wrapping heightmaps drain
+across their edges.
+
+Depressions are filled by priority flood: water is let in from the edges, or from the lowest cell
+of a wrapping heightmap which has none, and always spreads over the lowest cell it has reached, so
+every cell is raised to the lowest level water can leave it at. Cells raised by the fill are lakes,
+the water bodies the terrain holds, each lake filled up to its spill elevation where it overflows.
+ */
+
+/// Offsets of the eight neighbours, starting east and going clockwise with rows going down.
+pub const D8: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// Smallest rise of the terrain counted as lake water.
+pub const LAKE_DEPTH_MIN: HeightmapPrecision = 1e-6;
+
+#[derive(Debug, Clone)]
+pub struct Drainage {
+    pub width: usize,
+    pub height: usize,
+    /// Index into `D8` of where every cell drains, `None` for pits, `directions[x][y]`.
+    pub directions: Vec<Vec<Option<u8>>>,
+    /// Cells draining through every cell, itself included.
+    pub accumulation: Vec<Vec<usize>>,
+    pub basins: Segmentation,
+}
+
+/// The neighbour of a cell in the direction of `D8[direction]`, wrapping on wrapping heightmaps.
+pub fn neighbour(
+    heightmap: &Heightmap,
+    x: usize,
+    y: usize,
+    direction: u8,
+) -> Option<(usize, usize)> {
+    let (dx, dy) = D8[direction as usize];
+    let step = |v: usize, d: i32, size: usize| {
+        let v = v as i32 + d;
+        if (0..size as i32).contains(&v) {
+            Some(v as usize)
+        } else if heightmap.wrap {
+            Some(v.rem_euclid(size as i32) as usize)
+        } else {
+            None
+        }
+    };
+    Some((
+        step(x, dx, heightmap.width)?,
+        step(y, dy, heightmap.height)?,
+    ))
+}
+
+/// The direction of the steepest drop from every cell, `None` where no neighbour is lower.
+pub fn flow_directions(heightmap: &Heightmap) -> Vec<Vec<Option<u8>>> {
+    let mut directions = vec![vec![None; heightmap.height]; heightmap.width];
+    for (x, y, h) in heightmap.iter_cells() {
+        let mut steepest = 0.0;
+        for direction in 0..D8.len() as u8 {
+            let (nx, ny) = match neighbour(heightmap, x, y, direction) {
+                Some(cell) => cell,
+                None => continue,
+            };
+            let distance = if direction & 1 == 0 {
+                1.0
+            } else {
+                std::f32::consts::SQRT_2
+            };
+            let drop = (h - heightmap.data[nx][ny]) / distance;
+            if drop > steepest {
+                steepest = drop;
+                directions[x][y] = Some(direction);
+            }
+        }
+    }
+    directions
+}
+
+/// Cells sorted from the highest to the lowest, water only ever flows on to later cells.
+fn highest_first(heightmap: &Heightmap) -> Vec<(usize, usize)> {
+    let mut cells: Vec<(usize, usize, HeightmapPrecision)> = heightmap.iter_cells().collect();
+    cells.sort_by(|a, b| b.2.total_cmp(&a.2));
+    cells.into_iter().map(|(x, y, _)| (x, y)).collect()
+}
+
+pub fn drainage(heightmap: &Heightmap) -> Drainage {
+    let directions = flow_directions(heightmap);
+    let order = highest_first(heightmap);
+    let downstream = |x: usize, y: usize| {
+        directions[x][y].and_then(|direction| neighbour(heightmap, x, y, direction))
+    };
+
+    let mut accumulation = vec![vec![1; heightmap.height]; heightmap.width];
+    for &(x, y) in order.iter() {
+        if let Some((nx, ny)) = downstream(x, y) {
+            accumulation[nx][ny] += accumulation[x][y];
+        }
+    }
+
+    // Flat patches of pits are one basin, labelled before the cells draining to them
+    let (plateaus, plateau_count) = segments::label_components(heightmap, |a, b| a == b);
+    let mut basin_of_plateau = vec![usize::MAX; plateau_count];
+    let mut labels = vec![vec![usize::MAX; heightmap.height]; heightmap.width];
+    let mut count = 0;
+    for x in 0..heightmap.width {
+        for y in 0..heightmap.height {
+            if directions[x][y].is_some() {
+                continue;
+            }
+            let plateau = plateaus[x][y];
+            if basin_of_plateau[plateau] == usize::MAX {
+                basin_of_plateau[plateau] = count;
+                count += 1;
+            }
+            labels[x][y] = basin_of_plateau[plateau];
+        }
+    }
+    for &(x, y) in order.iter().rev() {
+        if let Some((nx, ny)) = downstream(x, y) {
+            labels[x][y] = labels[nx][ny];
+        }
+    }
+
+    Drainage {
+        width: heightmap.width,
+        height: heightmap.height,
+        directions,
+        accumulation,
+        basins: Segmentation::new(heightmap, SegmentMethod::Drainage, labels, count),
+    }
+}
+
+impl Drainage {
+    pub fn max_accumulation(&self) -> usize {
+        self.accumulation
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The accumulation on a log scale in [0, 1], so the smaller streams show next to the rivers.
+    pub fn accumulation_map(&self) -> Heightmap {
+        let max = (self.max_accumulation().max(2) as f32).ln();
+        let data = self
+            .accumulation
+            .iter()
+            .map(|column| column.iter().map(|&a| (a as f32).ln() / max).collect())
+            .collect();
+        let mut heightmap = Heightmap::new(data, self.width, self.height, 1.0, 1.0, None);
+        heightmap.metadata_add("FLOW_ACCUMULATION_MAX", self.max_accumulation().to_string());
+        heightmap
+    }
+}
+
+/// A cell reached by the water, ordered lowest first and then first come.
+#[derive(Debug, PartialEq)]
+struct Spill {
+    height: HeightmapPrecision,
+    order: usize,
+    cell: (usize, usize),
+}
+
+impl Eq for Spill {}
+
+impl PartialOrd for Spill {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Spill {
+    // Reversed since `BinaryHeap` pops the greatest
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .height
+            .total_cmp(&self.height)
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+impl Heightmap {
+    /// The heightmap with every depression filled up to the level it spills over at.
+    pub fn fill_depressions(&self) -> Heightmap {
+        let mut filled = self.clone();
+        let mut reached = vec![vec![false; self.height]; self.width];
+        let mut queue = BinaryHeap::new();
+        let mut order = 0;
+        let mut push = |queue: &mut BinaryHeap<Spill>, cell: (usize, usize), height| {
+            queue.push(Spill {
+                height,
+                order,
+                cell,
+            });
+            order += 1;
+        };
+        if self.wrap {
+            if let Some((x, y, h)) = self.iter_cells().min_by(|a, b| a.2.total_cmp(&b.2)) {
+                reached[x][y] = true;
+                push(&mut queue, (x, y), h);
+            }
+        } else {
+            for (x, y, h) in self.iter_cells() {
+                if x == 0 || y == 0 || x + 1 == self.width || y + 1 == self.height {
+                    reached[x][y] = true;
+                    push(&mut queue, (x, y), h);
+                }
+            }
+        }
+        while let Some(Spill {
+            height,
+            cell: (x, y),
+            ..
+        }) = queue.pop()
+        {
+            for direction in 0..D8.len() as u8 {
+                let (nx, ny) = match neighbour(self, x, y, direction) {
+                    Some(cell) if !reached[cell.0][cell.1] => cell,
+                    _ => continue,
+                };
+                reached[nx][ny] = true;
+                let level = filled.data[nx][ny].max(height);
+                filled.data[nx][ny] = level;
+                push(&mut queue, (nx, ny), level);
+            }
+        }
+        filled
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lake {
+    pub label: usize,
+    /// In cells.
+    pub area: usize,
+    /// Height of the water, where the lake overflows.
+    pub spill: HeightmapPrecision,
+    pub max_depth: HeightmapPrecision,
+    /// Summed depth of the cells.
+    pub volume: HeightmapPrecision,
+    /// The deepest cell.
+    pub deepest: (usize, usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Lakes {
+    /// The terrain with the lakes filled.
+    pub filled: Heightmap,
+    /// Lake of every cell, `None` on dry land, `labels[x][y]`.
+    pub labels: Vec<Vec<Option<usize>>>,
+    /// Indexed by label.
+    pub lakes: Vec<Lake>,
+}
+
+impl Lakes {
+    /// Lakes from the largest to the smallest.
+    pub fn by_area(&self) -> Vec<&Lake> {
+        let mut lakes: Vec<&Lake> = self.lakes.iter().collect();
+        lakes.sort_by(|a, b| b.area.cmp(&a.area).then(a.label.cmp(&b.label)));
+        lakes
+    }
+
+    pub fn depth_at(&self, heightmap: &Heightmap, x: usize, y: usize) -> HeightmapPrecision {
+        self.filled.data[x][y] - heightmap.data[x][y]
+    }
+}
+
+/// The closed basins of the terrain as lakes, each 4-connected patch of filled cells one lake.
+pub fn lakes(heightmap: &Heightmap) -> Lakes {
+    let filled = heightmap.fill_depressions();
+    let wet = |x: usize, y: usize| filled.data[x][y] - heightmap.data[x][y] > LAKE_DEPTH_MIN;
+    let mut depths = heightmap.clone();
+    for (x, column) in depths.data.iter_mut().enumerate() {
+        for (y, depth) in column.iter_mut().enumerate() {
+            *depth = if wet(x, y) { 1.0 } else { 0.0 };
+        }
+    }
+    let (components, count) = segments::label_components(&depths, |a, b| a == b);
+
+    let mut label_of_component = vec![None; count];
+    let mut labels = vec![vec![None; heightmap.height]; heightmap.width];
+    let mut lakes: Vec<Lake> = Vec::new();
+    for (x, y, h) in heightmap.iter_cells() {
+        if !wet(x, y) {
+            continue;
+        }
+        let component = components[x][y];
+        let label = *label_of_component[component].get_or_insert_with(|| {
+            lakes.push(Lake {
+                label: lakes.len(),
+                area: 0,
+                spill: filled.data[x][y],
+                max_depth: 0.0,
+                volume: 0.0,
+                deepest: (x, y),
+            });
+            lakes.len() - 1
+        });
+        labels[x][y] = Some(label);
+        let depth = filled.data[x][y] - h;
+        let lake = &mut lakes[label];
+        lake.area += 1;
+        lake.volume += depth;
+        lake.spill = lake.spill.max(filled.data[x][y]);
+        if depth > lake.max_depth {
+            lake.max_depth = depth;
+            lake.deepest = (x, y);
+        }
+    }
+    Lakes {
+        filled,
+        labels,
+        lakes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Check, SIZE};
+    use crate::heightmap;
+
+    #[test]
+    fn drainage() -> Check {
+        use heightmap::hydrology;
+        // Two valleys along y, both falling towards y = 0
+        let mut valleys = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                let across = (x as f32 - 8.0).abs().min((x as f32 - 24.0).abs());
+                valleys.data[x][y] = across * 0.02 + y as f32 * 0.001;
+            }
+        }
+        let drainage = hydrology::drainage(&valleys);
+        if drainage.directions[0][5] != Some(0) || drainage.directions[8][5] != Some(6) {
+            return Err(format!(
+                "directions {:?} and {:?}",
+                drainage.directions[0][5], drainage.directions[8][5]
+            ));
+        }
+        let basins = &drainage.basins;
+        let outlets: Vec<(usize, usize)> = basins.regions.iter().map(|b| b.lowest).collect();
+        if outlets != [(8, 0), (24, 0)] {
+            return Err(format!("basins drain to {:?}", outlets));
+        }
+        let area: usize = basins.regions.iter().map(|b| b.area).sum();
+        let drained = drainage.accumulation[8][0] + drainage.accumulation[24][0];
+        if area != SIZE * SIZE || drained != SIZE * SIZE {
+            return Err(format!("{} cells in basins, {} drained", area, drained));
+        }
+        let scaled = drainage.accumulation_map();
+        if scaled.data[8][0].max(scaled.data[24][0]) != 1.0 {
+            return Err("the largest accumulation is not at the top of the scale".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lakes() -> Check {
+        use heightmap::hydrology;
+        let mut terrain = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        terrain.map_inplace(|_, _, _| 0.5);
+        // A closed dip in the middle and one open to the edge, which drains
+        for x in 10..14 {
+            for y in 10..14 {
+                terrain.data[x][y] = 0.2;
+            }
+        }
+        for x in 0..3 {
+            for y in 20..23 {
+                terrain.data[x][y] = 0.1;
+            }
+        }
+        terrain.data[11][11] = 0.1;
+        let filled = terrain.fill_depressions();
+        if filled.data[11][11] != 0.5 || filled.data[0][20] != 0.1 || filled.data[1][21] != 0.1 {
+            return Err(format!(
+                "filled to {}, {} and {}",
+                filled.data[11][11], filled.data[0][20], filled.data[1][21]
+            ));
+        }
+        let found = hydrology::lakes(&terrain);
+        let lake = match found.lakes.as_slice() {
+            [lake] => lake,
+            lakes => return Err(format!("expected one lake, got {:?}", lakes)),
+        };
+        let volume = 15.0 * 0.3 + 0.4;
+        if lake.area != 16
+            || lake.spill != 0.5
+            || lake.deepest != (11, 11)
+            || (lake.volume - volume).abs() > 1e-4
+        {
+            return Err(format!("lake {:?}", lake));
+        }
+        if found.labels[11][11] != Some(0) || found.labels[0][20].is_some() {
+            return Err("cells labelled outside their lake".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/*
+Masks and other categorical layers exported as palette based pngs. Every cell holds the index of
+a category, the value of the layer rounded to the nearest integer, so binary masks in [0, 1] come
+out as two categories. The palette is embedded in the png and a json legend next to it names the
+categories, e.g. `sea_mask.png` and
+
+    {
+        "format": "erosion-rs/indexed",
+        "version": 1,
+        "image": "sea_mask.png",
+        "width": 512,
+        "height": 512,
+        "categories": [
+            { "index": 0, "name": "Land", "color": "#000000" },
+            { "index": 1, "name": "Sea", "color": "#ffffff" }
+        ]
+    }
+
+The smallest bit depth that fits the categories is used, so a binary mask takes one bit per cell.
+ */
+
+pub const FORMAT: &str = "erosion-rs/indexed";
+pub const VERSION: u32 = 1;
+pub const MAX_CATEGORIES: usize = 256;
+
+#[derive(Debug)]
+pub enum IndexedError {
+    TooManyCategories(usize),
+    NoCategories,
+    Io(std::io::Error),
+    Encoding(png::EncodingError),
+}
+
+impl From<std::io::Error> for IndexedError {
+    fn from(err: std::io::Error) -> Self {
+        IndexedError::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for IndexedError {
+    fn from(err: png::EncodingError) -> Self {
+        IndexedError::Encoding(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Category {
+    pub name: String,
+    pub color: [u8; 3],
+}
+
+impl Category {
+    pub fn new(name: &str, color: [u8; 3]) -> Self {
+        Category {
+            name: name.to_string(),
+            color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedPalette {
+    pub categories: Vec<Category>,
+}
+
+impl IndexedPalette {
+    pub fn new(categories: Vec<Category>) -> Self {
+        IndexedPalette { categories }
+    }
+
+    /// Black where the mask is 0 and white where it is 1.
+    pub fn binary(off: &str, on: &str) -> Self {
+        IndexedPalette::new(vec![
+            Category::new(off, [0, 0, 0]),
+            Category::new(on, [255, 255, 255]),
+        ])
+    }
+
+    /// The category of a cell, values outside the categories go to the nearest one.
+    pub fn index(&self, value: HeightmapPrecision) -> u8 {
+        let last = self.categories.len().saturating_sub(1);
+        (value.round().max(0.0) as usize).min(last) as u8
+    }
+
+    fn bit_depth(&self) -> png::BitDepth {
+        match self.categories.len() {
+            0..=2 => png::BitDepth::One,
+            3..=4 => png::BitDepth::Two,
+            5..=16 => png::BitDepth::Four,
+            _ => png::BitDepth::Eight,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LegendEntry<'a> {
+    index: usize,
+    name: &'a str,
+    color: String,
+}
+
+#[derive(Serialize)]
+struct Legend<'a> {
+    format: &'a str,
+    version: u32,
+    image: String,
+    width: usize,
+    height: usize,
+    categories: Vec<LegendEntry<'a>>,
+}
+
+/// Rows of category indices packed at the bit depth of the palette, most significant bits first.
+fn pack_rows(heightmap: &Heightmap, palette: &IndexedPalette) -> Vec<u8> {
+    let bits = palette.bit_depth() as usize;
+    let per_byte = 8 / bits;
+    let row_bytes = heightmap.width.div_ceil(per_byte);
+    let mut data = vec![0; row_bytes * heightmap.height];
+    for y in 0..heightmap.height {
+        let row = &mut data[y * row_bytes..(y + 1) * row_bytes];
+        for x in 0..heightmap.width {
+            let index = palette.index(heightmap.data[x][y]);
+            let shift = 8 - bits * (x % per_byte + 1);
+            row[x / per_byte] |= index << shift;
+        }
+    }
+    data
+}
+
+/// Writes `filename.png` with the palette embedded and the legend as `filename.legend.json`.
+pub fn export(
+    heightmap: &Heightmap,
+    filename: &str,
+    palette: &IndexedPalette,
+) -> Result<(), IndexedError> {
+    match palette.categories.len() {
+        0 => return Err(IndexedError::NoCategories),
+        n if n > MAX_CATEGORIES => return Err(IndexedError::TooManyCategories(n)),
+        _ => {}
+    }
+
+    let image = format!("{}.png", filename);
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(&image)?),
+        heightmap.width as u32,
+        heightmap.height as u32,
+    );
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(palette.bit_depth());
+    encoder.set_palette(
+        palette
+            .categories
+            .iter()
+            .flat_map(|category| category.color)
+            .collect::<Vec<u8>>(),
+    );
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pack_rows(heightmap, palette))?;
+    writer.finish()?;
+
+    let legend = Legend {
+        format: FORMAT,
+        version: VERSION,
+        // Relative so the two files can be moved together
+        image: image.rsplit('/').next().unwrap_or(&image).to_string(),
+        width: heightmap.width,
+        height: heightmap.height,
+        categories: palette
+            .categories
+            .iter()
+            .enumerate()
+            .map(|(index, category)| LegendEntry {
+                index,
+                name: &category.name,
+                color: format!(
+                    "#{:02x}{:02x}{:02x}",
+                    category.color[0], category.color[1], category.color[2]
+                ),
+            })
+            .collect(),
+    };
+    let mut file = BufWriter::new(File::create(format!("{}.legend.json", filename))?);
+    file.write_all(
+        serde_json::to_string_pretty(&legend)
+            .map_err(std::io::Error::other)?
+            .as_bytes(),
+    )?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_path, Check, SIZE};
+    use std::fs;
+
+    #[test]
+    fn indexed_export() -> Check {
+        use crate::heightmap::indexed::{export, Category, IndexedPalette};
+        // Odd width so the rows do not fill up the last byte
+        let width = SIZE + 3;
+        let mut labels = Heightmap::new_empty(width, SIZE, 1.0, 1.0);
+        for x in 0..width {
+            for y in 0..SIZE {
+                labels.data[x][y] = ((x + y) % 5) as f32;
+            }
+        }
+        let palette = IndexedPalette::new(
+            (0..5)
+                .map(|i| Category::new(&format!("Class {}", i), [i * 50, 0, 255 - i * 50]))
+                .collect(),
+        );
+        let path = temp_path("labels");
+        let path = path.to_string_lossy();
+        let legend = format!("{}.legend.json", path);
+        let result = export(&labels, &path, &palette)
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|_| image::open(format!("{}.png", path)).map_err(|err| format!("{:?}", err)))
+            .and_then(|image| {
+                let json = fs::read_to_string(&legend).map_err(|err| format!("{:?}", err))?;
+                let value: serde_json::Value =
+                    serde_json::from_str(&json).map_err(|err| format!("{:?}", err))?;
+                Ok((image.to_rgb8(), value))
+            });
+        let _ = fs::remove_file(format!("{}.png", path));
+        let _ = fs::remove_file(&legend);
+        let (image, legend) = result?;
+        if image.width() as usize != width || image.height() as usize != SIZE {
+            return Err("png does not match the layer size".to_string());
+        }
+        for (x, y) in [(0, 0), (1, 0), (width - 1, 2), (3, SIZE - 1)] {
+            let expected = palette.categories[(x + y) % 5].color;
+            if image.get_pixel(x as u32, y as u32).0 != expected {
+                return Err(format!("wrong color at ({}, {})", x, y));
+            }
+        }
+        if legend["categories"].as_array().map(|c| c.len()) != Some(5) {
+            return Err("legend does not list every category".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use crate::heightmap::Heightmap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/*
+The terrain as a triangle mesh, for taking a result straight into Blender or a game engine. Every
+`decimation`-th cell along each axis becomes a vertex, always including the last row and column so
+the mesh covers the whole map, and every quad of vertices is split into two triangles. The mesh is
+y up with the longest side of the map spanning one unit from the origin along x and z, rows of the
+heightmap going along z. Heights are relative to the depth, so a cell at the full depth stands
+`vertical_scale` units high. Vertices have normals and texture coordinates in [0, 1] matching the
+exported pngs, so a layer can be draped over the mesh as a texture.
+
+OBJ is written as text with the heightmap metadata as comments. glTF is written as a single binary
+`.glb` with the metadata in the `extras` of the asset.
+ */
+
+#[derive(Debug)]
+pub enum MeshError {
+    TooSmall,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for MeshError {
+    fn from(err: std::io::Error) -> Self {
+        MeshError::Io(err)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeshFormat {
+    Obj,
+    Gltf,
+}
+
+impl Display for MeshFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshFormat::Obj => f.write_str("OBJ"),
+            MeshFormat::Gltf => f.write_str("glTF (binary)"),
+        }
+    }
+}
+
+impl MeshFormat {
+    pub fn list() -> [MeshFormat; 2] {
+        [MeshFormat::Obj, MeshFormat::Gltf]
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "obj",
+            MeshFormat::Gltf => "glb",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeshSettings {
+    pub format: MeshFormat,
+    pub vertical_scale: f32, // [0.01, 2], 0.25 (units at full depth)
+    pub decimation: usize,   // [1, 64], 1 (cells per vertex)
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        MeshSettings {
+            format: MeshFormat::Obj,
+            vertical_scale: 0.25,
+            decimation: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    /// Counter-clockwise seen from above.
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// Cells along an axis of `size` cells that get a vertex, every `step`-th and the last.
+fn samples(size: usize, step: usize) -> Vec<usize> {
+    let mut samples: Vec<usize> = (0..size).step_by(step.max(1)).collect();
+    if samples.last() != Some(&(size - 1)) {
+        samples.push(size - 1);
+    }
+    samples
+}
+
+impl Mesh {
+    pub fn new(heightmap: &Heightmap, settings: &MeshSettings) -> Result<Self, MeshError> {
+        if heightmap.width < 2 || heightmap.height < 2 {
+            return Err(MeshError::TooSmall);
+        }
+        let xs = samples(heightmap.width, settings.decimation);
+        let ys = samples(heightmap.height, settings.decimation);
+        let unit = 1.0 / (heightmap.width.max(heightmap.height) - 1) as f32;
+        let scale = settings.vertical_scale / heightmap.depth;
+        let elevation = |x: usize, y: usize| heightmap.data[x][y] * scale;
+
+        let mut mesh = Mesh::default();
+        for &y in ys.iter() {
+            for &x in xs.iter() {
+                mesh.positions
+                    .push([x as f32 * unit, elevation(x, y), y as f32 * unit]);
+                // Central differences over the full resolution keep decimated normals smooth
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(heightmap.width - 1));
+                let (y0, y1) = (y.saturating_sub(1), (y + 1).min(heightmap.height - 1));
+                let slope_x = (elevation(x1, y) - elevation(x0, y)) / ((x1 - x0) as f32 * unit);
+                let slope_z = (elevation(x, y1) - elevation(x, y0)) / ((y1 - y0) as f32 * unit);
+                let length = (slope_x * slope_x + slope_z * slope_z + 1.0).sqrt();
+                mesh.normals
+                    .push([-slope_x / length, 1.0 / length, -slope_z / length]);
+                mesh.uvs.push([
+                    (x as f32 + 0.5) / heightmap.width as f32,
+                    (y as f32 + 0.5) / heightmap.height as f32,
+                ]);
+            }
+        }
+        let columns = xs.len() as u32;
+        for row in 0..ys.len() as u32 - 1 {
+            for column in 0..columns - 1 {
+                let top_left = row * columns + column;
+                let bottom_left = top_left + columns;
+                mesh.triangles.push([top_left, bottom_left, top_left + 1]);
+                mesh.triangles
+                    .push([bottom_left, bottom_left + 1, top_left + 1]);
+            }
+        }
+        Ok(mesh)
+    }
+
+    pub fn write_obj(&self, heightmap: &Heightmap, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "# erosion-rs terrain mesh")?;
+        for (key, value) in sorted_metadata(heightmap) {
+            writeln!(writer, "# {}: {}", key, value)?;
+        }
+        writeln!(writer, "o terrain")?;
+        for [x, y, z] in self.positions.iter() {
+            writeln!(writer, "v {} {} {}", x, y, z)?;
+        }
+        for [x, y, z] in self.normals.iter() {
+            writeln!(writer, "vn {} {} {}", x, y, z)?;
+        }
+        for [u, v] in self.uvs.iter() {
+            // OBJ texture coordinates start at the bottom of the image
+            writeln!(writer, "vt {} {}", u, 1.0 - v)?;
+        }
+        for triangle in self.triangles.iter() {
+            let [a, b, c] = triangle.map(|i| i + 1);
+            writeln!(writer, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a binary glTF, a json chunk describing the mesh and a chunk with its buffers.
+    pub fn write_glb(&self, heightmap: &Heightmap, writer: &mut impl Write) -> std::io::Result<()> {
+        const ARRAY_BUFFER: u32 = 34962;
+        const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+        const FLOAT: u32 = 5126;
+        const UNSIGNED_INT: u32 = 5125;
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut views = Vec::new();
+        let mut push = |bytes: Vec<u8>, target: u32| {
+            views.push(json!({
+                "buffer": 0,
+                "byteOffset": buffer.len(),
+                "byteLength": bytes.len(),
+                "target": target,
+            }));
+            buffer.extend(bytes);
+        };
+        push(float_bytes(&self.positions), ARRAY_BUFFER);
+        push(float_bytes(&self.normals), ARRAY_BUFFER);
+        push(float_bytes(&self.uvs), ARRAY_BUFFER);
+        push(
+            self.triangles
+                .iter()
+                .flatten()
+                .flat_map(|i| i.to_le_bytes())
+                .collect(),
+            ELEMENT_ARRAY_BUFFER,
+        );
+
+        let (min, max) = self.positions.iter().fold(
+            ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]),
+            |(min, max), p| {
+                (
+                    [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])],
+                    [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])],
+                )
+            },
+        );
+        let vertices = self.positions.len();
+        let extras: serde_json::Map<String, serde_json::Value> = sorted_metadata(heightmap)
+            .into_iter()
+            .map(|(key, value)| (key.clone(), json!(value)))
+            .collect();
+        let document = json!({
+            "asset": { "version": "2.0", "generator": "erosion-rs", "extras": extras },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "name": "terrain", "mesh": 0 }],
+            "meshes": [{
+                "name": "terrain",
+                "primitives": [{
+                    "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                    "indices": 3,
+                }],
+            }],
+            "accessors": [
+                {
+                    "bufferView": 0, "componentType": FLOAT, "count": vertices, "type": "VEC3",
+                    "min": min, "max": max,
+                },
+                { "bufferView": 1, "componentType": FLOAT, "count": vertices, "type": "VEC3" },
+                { "bufferView": 2, "componentType": FLOAT, "count": vertices, "type": "VEC2" },
+                {
+                    "bufferView": 3, "componentType": UNSIGNED_INT,
+                    "count": self.triangles.len() * 3, "type": "SCALAR",
+                },
+            ],
+            "bufferViews": views,
+            "buffers": [{ "byteLength": buffer.len() }],
+        });
+
+        // Chunks are padded to 4 bytes, json with spaces and binary data with zeros
+        let mut document = serde_json::to_vec(&document).map_err(std::io::Error::other)?;
+        document.resize(document.len().next_multiple_of(4), b' ');
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        let length = 12 + 8 + document.len() + 8 + buffer.len();
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(length as u32).to_le_bytes())?;
+        writer.write_all(&(document.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(&document)?;
+        writer.write_all(&(buffer.len() as u32).to_le_bytes())?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+fn float_bytes<const N: usize>(values: &[[f32; N]]) -> Vec<u8> {
+    values
+        .iter()
+        .flatten()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+fn sorted_metadata(heightmap: &Heightmap) -> Vec<(&String, &String)> {
+    let mut metadata: Vec<(&String, &String)> = heightmap.metadata.iter().flatten().collect();
+    metadata.sort();
+    metadata
+}
+
+/// Writes the heightmap as a mesh to `filename` with the extension of the format appended.
+pub fn export(
+    heightmap: &Heightmap,
+    filename: &str,
+    settings: &MeshSettings,
+) -> Result<(), MeshError> {
+    let mesh = Mesh::new(heightmap, settings)?;
+    let file = File::create(format!("{}.{}", filename, settings.format.extension()))?;
+    let mut writer = BufWriter::new(file);
+    match settings.format {
+        MeshFormat::Obj => mesh.write_obj(heightmap, &mut writer)?,
+        MeshFormat::Gltf => mesh.write_glb(heightmap, &mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_path, tiny_rectangular_heightmap, Check, SIZE};
+    use std::fs;
+
+    #[test]
+    fn mesh_export() -> Check {
+        let heightmap = tiny_rectangular_heightmap();
+        let settings = MeshSettings {
+            decimation: 3,
+            ..MeshSettings::default()
+        };
+        let mesh = Mesh::new(&heightmap, &settings).map_err(|err| format!("{:?}", err))?;
+        // Every third cell plus the last row and column
+        let count = |size: usize| (size - 1).div_ceil(3) + 1;
+        let (columns, rows) = (count(SIZE), count(SIZE * 2));
+        if mesh.positions.len() != columns * rows
+            || mesh.triangles.len() != (columns - 1) * (rows - 1) * 2
+        {
+            return Err("decimated mesh has the wrong number of vertices".to_string());
+        }
+        for [a, b, c] in mesh.triangles.iter() {
+            let [a, b, c] = [a, b, c].map(|&i| mesh.positions[i as usize]);
+            let (u, v) = ([b[0] - a[0], b[2] - a[2]], [c[0] - a[0], c[2] - a[2]]);
+            if u[1] * v[0] - u[0] * v[1] <= 0.0 {
+                return Err("triangle does not face up".to_string());
+            }
+        }
+
+        for format in MeshFormat::list() {
+            let path = temp_path("mesh");
+            let path = path.to_string_lossy();
+            let file = format!("{}.{}", path, format.extension());
+            let settings = MeshSettings { format, ..settings };
+            let result = export(&heightmap, &path, &settings)
+                .map_err(|err| format!("{:?}", err))
+                .and_then(|_| fs::read(&file).map_err(|err| format!("{:?}", err)));
+            let _ = fs::remove_file(&file);
+            let bytes = result?;
+            match format {
+                MeshFormat::Obj => {
+                    let text = String::from_utf8(bytes).map_err(|err| format!("{:?}", err))?;
+                    let faces = text.lines().filter(|line| line.starts_with("f ")).count();
+                    if faces != mesh.triangles.len() {
+                        return Err("obj has the wrong number of faces".to_string());
+                    }
+                }
+                MeshFormat::Gltf => {
+                    let length = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+                    if !bytes.starts_with(b"glTF") || length as usize != bytes.len() {
+                        return Err("glb header is malformed".to_string());
+                    }
+                    let chunk = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+                    let document: serde_json::Value =
+                        serde_json::from_slice(&bytes[20..20 + chunk as usize])
+                            .map_err(|err| format!("{:?}", err))?;
+                    if document["accessors"][0]["count"] != mesh.positions.len() {
+                        return Err("glb does not describe the mesh".to_string());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
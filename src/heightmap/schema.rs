@@ -0,0 +1,136 @@
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/*
+The json format heightmaps are exported in, meant to be read by other tools. A document looks like
+
+    {
+        "format": "erosion-rs/heightmap",
+        "version": 1,
+        "width": 3,
+        "height": 2,
+        "depth": 1.0,
+        "original_depth": 1.0,
+        "wrap": false,
+        "data": [[0.0, 0.1, 0.2], [0.3, 0.4, 0.5]],
+        "metadata": { "BACKEND": "lague" }
+    }
+
+where `data` holds `height` rows of `width` heights each, so `data[y][x]` is the cell at (x, y)
+like in the exported pngs. Heights are normally in [0, depth]. `original_depth`, `wrap` and
+`metadata` may be left out. Readers must ignore fields they do not know, new fields are added
+without changing the version, which only goes up when existing fields change meaning. Files
+without a `version` are the raw heightmaps written before the format had one and are still read.
+A json schema of the format is kept in `assets/heightmap.schema.json`.
+ */
+
+pub const FORMAT: &str = "erosion-rs/heightmap";
+pub const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SchemaError {
+    InvalidJson(serde_json::Error),
+    UnknownFormat(String),
+    UnsupportedVersion(u32),
+    MismatchingSize,
+}
+
+impl From<serde_json::Error> for SchemaError {
+    fn from(err: serde_json::Error) -> Self {
+        SchemaError::InvalidJson(err)
+    }
+}
+
+fn default_depth() -> HeightmapPrecision {
+    1.0
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Document {
+    format: String,
+    version: u32,
+    width: usize,
+    height: usize,
+    #[serde(default = "default_depth")]
+    depth: HeightmapPrecision,
+    #[serde(default)]
+    original_depth: Option<HeightmapPrecision>,
+    #[serde(default)]
+    wrap: bool,
+    data: Vec<Vec<HeightmapPrecision>>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+pub fn to_json(heightmap: &Heightmap) -> Result<String, SchemaError> {
+    let document = Document {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        width: heightmap.width,
+        height: heightmap.height,
+        depth: heightmap.depth,
+        original_depth: Some(heightmap.original_depth),
+        wrap: heightmap.wrap,
+        data: (0..heightmap.height)
+            .map(|y| (0..heightmap.width).map(|x| heightmap.data[x][y]).collect())
+            .collect(),
+        metadata: heightmap.metadata.clone().unwrap_or_default(),
+    };
+    Ok(serde_json::to_string(&document)?)
+}
+
+pub fn from_json(json: &str) -> Result<Heightmap, SchemaError> {
+    let value: Value = serde_json::from_str(json)?;
+    if value.get("version").is_none() {
+        return Ok(serde_json::from_value(value)?);
+    }
+    let document: Document = serde_json::from_value(value)?;
+    if document.format != FORMAT {
+        return Err(SchemaError::UnknownFormat(document.format));
+    }
+    if document.version > VERSION {
+        return Err(SchemaError::UnsupportedVersion(document.version));
+    }
+    let (width, height) = (document.width, document.height);
+    if document.data.len() != height || document.data.iter().any(|row| row.len() != width) {
+        return Err(SchemaError::MismatchingSize);
+    }
+    let data = (0..width)
+        .map(|x| document.data.iter().map(|row| row[x]).collect())
+        .collect();
+    let metadata = (!document.metadata.is_empty()).then_some(document.metadata);
+    let original_depth = document.original_depth.unwrap_or(document.depth);
+    let mut heightmap = Heightmap::new(
+        data,
+        width,
+        height,
+        document.depth,
+        original_depth,
+        metadata,
+    );
+    heightmap.wrap = document.wrap;
+    Ok(heightmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{check_round_trip, tiny_rectangular_heightmap, Check};
+
+    #[test]
+    fn json_schema() -> Check {
+        let heightmap = tiny_rectangular_heightmap();
+        let json = to_json(&heightmap).map_err(|err| format!("{:?}", err))?;
+        let mut document: serde_json::Value =
+            serde_json::from_str(&json).map_err(|err| format!("{:?}", err))?;
+        document["added_by_a_newer_version"] = serde_json::Value::Bool(true);
+        let read = from_json(&document.to_string()).map_err(|err| format!("{:?}", err))?;
+        check_round_trip(&heightmap, &read)?;
+        // Heightmaps exported before the format was versioned are raw serializations
+        let legacy = serde_json::to_string(&heightmap).map_err(|err| format!("{:?}", err))?;
+        let read = from_json(&legacy).map_err(|err| format!("legacy: {:?}", err))?;
+        check_round_trip(&heightmap, &read)
+    }
+}
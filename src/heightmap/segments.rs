@@ -0,0 +1,477 @@
+use crate::heightmap::{hydrology, Heightmap, HeightmapPrecision};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/*
+The terrain split into connected regions, every cell labelled with the region it belongs to.
+Height bands split [0, depth] into equally tall bands and label every 4-connected patch of cells in
+the same band. Watershed labels the drainage basins instead, every regional minimum (a cell or a
+flat patch without lower neighbours) is flooded from and the basins grow over the lowest cells
+first until they meet. Drainage labels the basins by where their D8 flow ends, see `hydrology`.
+All of them respect wrapping heightmaps.
+
+Labels start at 0 in the order the regions are first met going column by column. The label map is
+exported as a 16 bit grayscale png holding the labels as they are, next to a json file with the
+area and heights of every region, e.g. `segments.png` and
+
+    {
+        "format": "erosion-rs/segments",
+        "version": 1,
+        "image": "segments.png",
+        "method": "Watershed",
+        "width": 512,
+        "height": 512,
+        "regions": [
+            { "label": 0, "area": 1834, "mean_height": 0.21, "min_height": 0.12, ... },
+            ...
+        ]
+    }
+ */
+
+pub const FORMAT: &str = "erosion-rs/segments";
+pub const VERSION: u32 = 1;
+pub const MAX_REGIONS: usize = u16::MAX as usize + 1;
+
+#[derive(Debug)]
+pub enum SegmentError {
+    TooManyRegions(usize),
+    Io(std::io::Error),
+    Encoding(png::EncodingError),
+}
+
+impl From<std::io::Error> for SegmentError {
+    fn from(err: std::io::Error) -> Self {
+        SegmentError::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for SegmentError {
+    fn from(err: png::EncodingError) -> Self {
+        SegmentError::Encoding(err)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SegmentMethod {
+    HeightBands,
+    Watershed,
+    Drainage,
+}
+
+impl Display for SegmentMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SegmentMethod::HeightBands => f.write_str("Height Bands"),
+            SegmentMethod::Watershed => f.write_str("Watershed"),
+            SegmentMethod::Drainage => f.write_str("Drainage (D8)"),
+        }
+    }
+}
+
+impl SegmentMethod {
+    pub fn list() -> [SegmentMethod; 3] {
+        [
+            SegmentMethod::HeightBands,
+            SegmentMethod::Watershed,
+            SegmentMethod::Drainage,
+        ]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentSettings {
+    pub method: SegmentMethod,
+    pub bands: usize, // [2, 64], 8
+}
+
+impl Default for SegmentSettings {
+    fn default() -> Self {
+        SegmentSettings {
+            method: SegmentMethod::HeightBands,
+            bands: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Region {
+    pub label: usize,
+    /// In cells.
+    pub area: usize,
+    pub mean_height: HeightmapPrecision,
+    pub min_height: HeightmapPrecision,
+    pub max_height: HeightmapPrecision,
+    /// The lowest cell of the region, the outlet of a basin.
+    pub lowest: (usize, usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Segmentation {
+    pub method: SegmentMethod,
+    pub width: usize,
+    pub height: usize,
+    /// Label of every cell, `labels[x][y]` like the data of a heightmap.
+    pub labels: Vec<Vec<usize>>,
+    /// Indexed by label.
+    pub regions: Vec<Region>,
+}
+
+/// The 4-connected neighbours of a cell, wrapping around the edges of wrapping heightmaps.
+fn neighbours(heightmap: &Heightmap, x: usize, y: usize) -> [Option<(usize, usize)>; 4] {
+    let (width, height) = (heightmap.width, heightmap.height);
+    let step = |v: usize, size: usize, forward: bool| match (forward, heightmap.wrap) {
+        (true, _) if v + 1 < size => Some(v + 1),
+        (true, true) => Some(0),
+        (false, _) if v > 0 => Some(v - 1),
+        (false, true) => Some(size - 1),
+        _ => None,
+    };
+    [
+        step(x, width, false).map(|x| (x, y)),
+        step(x, width, true).map(|x| (x, y)),
+        step(y, height, false).map(|y| (x, y)),
+        step(y, height, true).map(|y| (x, y)),
+    ]
+}
+
+/// Labels the 4-connected components of cells where `connected` holds between neighbouring
+/// heights, returns the labels and the number of components.
+pub fn label_components(
+    heightmap: &Heightmap,
+    connected: impl Fn(HeightmapPrecision, HeightmapPrecision) -> bool,
+) -> (Vec<Vec<usize>>, usize) {
+    let mut labels = vec![vec![usize::MAX; heightmap.height]; heightmap.width];
+    let mut count = 0;
+    let mut queue = VecDeque::new();
+    for x0 in 0..heightmap.width {
+        for y0 in 0..heightmap.height {
+            if labels[x0][y0] != usize::MAX {
+                continue;
+            }
+            labels[x0][y0] = count;
+            queue.push_back((x0, y0));
+            while let Some((x, y)) = queue.pop_front() {
+                for (nx, ny) in neighbours(heightmap, x, y).into_iter().flatten() {
+                    if labels[nx][ny] == usize::MAX
+                        && connected(heightmap.data[x][y], heightmap.data[nx][ny])
+                    {
+                        labels[nx][ny] = count;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+            count += 1;
+        }
+    }
+    (labels, count)
+}
+
+/// Regions of connected cells within the same of `bands` equally tall bands of [0, depth].
+pub fn height_bands(heightmap: &Heightmap, bands: usize) -> Segmentation {
+    let bands = bands.max(1);
+    let band = |h: HeightmapPrecision| {
+        ((h / heightmap.depth * bands as HeightmapPrecision)
+            .floor()
+            .max(0.0) as usize)
+            .min(bands - 1)
+    };
+    let (labels, count) = label_components(heightmap, |a, b| band(a) == band(b));
+    Segmentation::new(heightmap, SegmentMethod::HeightBands, labels, count)
+}
+
+/// A cell waiting to be flooded, ordered lowest first and then first come.
+#[derive(Debug, PartialEq)]
+struct Flood {
+    height: HeightmapPrecision,
+    order: usize,
+    cell: (usize, usize),
+    label: usize,
+}
+
+impl Eq for Flood {}
+
+impl PartialOrd for Flood {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Flood {
+    // Reversed since `BinaryHeap` pops the greatest
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .height
+            .total_cmp(&self.height)
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+/// Drainage basins, grown from the regional minima over the lowest cells first.
+pub fn watershed(heightmap: &Heightmap) -> Segmentation {
+    let (plateaus, plateau_count) = label_components(heightmap, |a, b| a == b);
+    let mut minimum = vec![true; plateau_count];
+    for x in 0..heightmap.width {
+        for y in 0..heightmap.height {
+            let h = heightmap.data[x][y];
+            if neighbours(heightmap, x, y)
+                .into_iter()
+                .flatten()
+                .any(|(nx, ny)| heightmap.data[nx][ny] < h)
+            {
+                minimum[plateaus[x][y]] = false;
+            }
+        }
+    }
+
+    let mut basin_of_plateau = vec![usize::MAX; plateau_count];
+    let mut labels = vec![vec![usize::MAX; heightmap.height]; heightmap.width];
+    let mut count = 0;
+    for x in 0..heightmap.width {
+        for y in 0..heightmap.height {
+            let plateau = plateaus[x][y];
+            if !minimum[plateau] {
+                continue;
+            }
+            if basin_of_plateau[plateau] == usize::MAX {
+                basin_of_plateau[plateau] = count;
+                count += 1;
+            }
+            labels[x][y] = basin_of_plateau[plateau];
+        }
+    }
+
+    let mut queue = BinaryHeap::new();
+    let mut order = 0;
+    let mut push = |queue: &mut BinaryHeap<Flood>, cell: (usize, usize), label: usize| {
+        queue.push(Flood {
+            height: heightmap.data[cell.0][cell.1],
+            order,
+            cell,
+            label,
+        });
+        order += 1;
+    };
+    for x in 0..heightmap.width {
+        for y in 0..heightmap.height {
+            if labels[x][y] == usize::MAX {
+                continue;
+            }
+            for (nx, ny) in neighbours(heightmap, x, y).into_iter().flatten() {
+                if labels[nx][ny] == usize::MAX {
+                    push(&mut queue, (nx, ny), labels[x][y]);
+                }
+            }
+        }
+    }
+    while let Some(Flood {
+        cell: (x, y),
+        label,
+        ..
+    }) = queue.pop()
+    {
+        if labels[x][y] != usize::MAX {
+            continue;
+        }
+        labels[x][y] = label;
+        for (nx, ny) in neighbours(heightmap, x, y).into_iter().flatten() {
+            if labels[nx][ny] == usize::MAX {
+                push(&mut queue, (nx, ny), label);
+            }
+        }
+    }
+    Segmentation::new(heightmap, SegmentMethod::Watershed, labels, count)
+}
+
+pub fn segment(heightmap: &Heightmap, settings: &SegmentSettings) -> Segmentation {
+    match settings.method {
+        SegmentMethod::HeightBands => height_bands(heightmap, settings.bands),
+        SegmentMethod::Watershed => watershed(heightmap),
+        SegmentMethod::Drainage => hydrology::drainage(heightmap).basins,
+    }
+}
+
+impl Segmentation {
+    pub fn new(
+        heightmap: &Heightmap,
+        method: SegmentMethod,
+        labels: Vec<Vec<usize>>,
+        count: usize,
+    ) -> Self {
+        let mut regions: Vec<Region> = (0..count)
+            .map(|label| Region {
+                label,
+                area: 0,
+                mean_height: 0.0,
+                min_height: HeightmapPrecision::INFINITY,
+                max_height: HeightmapPrecision::NEG_INFINITY,
+                lowest: (0, 0),
+            })
+            .collect();
+        for (x, y, h) in heightmap.iter_cells() {
+            let region = &mut regions[labels[x][y]];
+            region.area += 1;
+            // Summed here and divided by the area below
+            region.mean_height += h;
+            if h < region.min_height {
+                region.min_height = h;
+                region.lowest = (x, y);
+            }
+            region.max_height = region.max_height.max(h);
+        }
+        for region in regions.iter_mut() {
+            region.mean_height /= region.area.max(1) as HeightmapPrecision;
+        }
+        Segmentation {
+            method,
+            width: heightmap.width,
+            height: heightmap.height,
+            labels,
+            regions,
+        }
+    }
+
+    pub fn label_at(&self, x: usize, y: usize) -> Option<usize> {
+        self.labels.get(x).and_then(|column| column.get(y)).copied()
+    }
+
+    /// Regions from the largest to the smallest.
+    pub fn by_area(&self) -> Vec<&Region> {
+        let mut regions: Vec<&Region> = self.regions.iter().collect();
+        regions.sort_by(|a, b| b.area.cmp(&a.area).then(a.label.cmp(&b.label)));
+        regions
+    }
+
+    /// The labels as heights, the depth is the highest label.
+    pub fn label_map(&self) -> Heightmap {
+        let data = self
+            .labels
+            .iter()
+            .map(|column| column.iter().map(|&label| label as f32).collect())
+            .collect();
+        let depth = self.regions.len().saturating_sub(1).max(1) as f32;
+        let mut heightmap = Heightmap::new(data, self.width, self.height, depth, depth, None);
+        heightmap.metadata_add("SEGMENTATION", self.method.to_string());
+        heightmap.metadata_add("REGIONS", self.regions.len().to_string());
+        heightmap
+    }
+}
+
+#[derive(Serialize)]
+struct Legend<'a> {
+    format: &'a str,
+    version: u32,
+    image: String,
+    method: String,
+    width: usize,
+    height: usize,
+    regions: &'a [Region],
+}
+
+/// Writes the labels as `filename.png` and the regions as `filename.regions.json`.
+pub fn export(segmentation: &Segmentation, filename: &str) -> Result<(), SegmentError> {
+    if segmentation.regions.len() > MAX_REGIONS {
+        return Err(SegmentError::TooManyRegions(segmentation.regions.len()));
+    }
+
+    let image = format!("{}.png", filename);
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(&image)?),
+        segmentation.width as u32,
+        segmentation.height as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header()?;
+    let data: Vec<u8> = (0..segmentation.height)
+        .flat_map(|y| {
+            (0..segmentation.width)
+                .flat_map(move |x| (segmentation.labels[x][y] as u16).to_be_bytes())
+        })
+        .collect();
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+
+    let legend = Legend {
+        format: FORMAT,
+        version: VERSION,
+        // Relative so the two files can be moved together
+        image: image.rsplit('/').next().unwrap_or(&image).to_string(),
+        method: segmentation.method.to_string(),
+        width: segmentation.width,
+        height: segmentation.height,
+        regions: &segmentation.regions,
+    };
+    let mut file = BufWriter::new(File::create(format!("{}.regions.json", filename))?);
+    file.write_all(
+        serde_json::to_string_pretty(&legend)
+            .map_err(std::io::Error::other)?
+            .as_bytes(),
+    )?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_path, Check, SIZE};
+    use std::fs;
+
+    #[test]
+    fn segmentation() -> Check {
+        use crate::heightmap::segments::{export, height_bands, watershed};
+        // Two valleys along x = 8 and x = 24 with a ridge between them
+        let mut valleys = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                let to_valley = (x as f32 - 8.0).abs().min((x as f32 - 24.0).abs());
+                valleys.data[x][y] = to_valley / 8.0 + y as f32 * 0.001;
+            }
+        }
+        let basins = watershed(&valleys);
+        if basins.regions.len() != 2 || basins.label_at(8, 0) == basins.label_at(24, 0) {
+            return Err(format!("expected 2 basins, got {}", basins.regions.len()));
+        }
+        if basins.regions.iter().map(|r| r.area).sum::<usize>() != SIZE * SIZE {
+            return Err("basins do not cover the map".to_string());
+        }
+        if basins.regions[basins.labels[8][5]].lowest != (8, 0) {
+            return Err("basin outlet is not its lowest cell".to_string());
+        }
+        // Below 0.5 the valleys are apart, above it the ridge and the edges at x = 0 and x = 31
+        let bands = height_bands(&valleys, 2);
+        if bands.regions.len() != 5 {
+            return Err(format!(
+                "expected 5 band regions, got {}",
+                bands.regions.len()
+            ));
+        }
+
+        let path = temp_path("segments");
+        let path = path.to_string_lossy();
+        let json = format!("{}.regions.json", path);
+        let result = export(&basins, &path)
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|_| image::open(format!("{}.png", path)).map_err(|err| format!("{:?}", err)))
+            .and_then(|image| {
+                let json = fs::read_to_string(&json).map_err(|err| format!("{:?}", err))?;
+                let value: serde_json::Value =
+                    serde_json::from_str(&json).map_err(|err| format!("{:?}", err))?;
+                Ok((image.to_luma16(), value))
+            });
+        let _ = fs::remove_file(format!("{}.png", path));
+        let _ = fs::remove_file(&json);
+        let (image, legend) = result?;
+        if image.get_pixel(24, 3).0[0] as usize != basins.labels[24][3] {
+            return Err("label map does not hold the labels".to_string());
+        }
+        if legend["regions"].as_array().map(|r| r.len()) != Some(2) {
+            return Err("region table does not list every region".to_string());
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,11 @@
-use crate::heightmap::io::heightmap_to_image;
+use crate::heightmap::io::{heightmap_to_image, save_heightmap_as_image};
 use crate::visualize::app_state::AppState;
 use crate::visualize::ui::UiState;
-use crate::visualize::wrappers::HeightmapTexture;
+use crate::visualize::{channel_names, Composite, LayerMixMethod};
 use crate::State;
 use image::imageops::FilterType;
 use image::ImageError;
-use std::cell::RefCell;
-use std::rc::Rc;
+use serde::Serialize;
 use std::{fs, io};
 
 const STATE_FILE_EXT: &'static str = "ers";
@@ -57,6 +56,63 @@ pub fn export_icon(state: &State, filename: &str) -> Result<(), StateIoError> {
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+struct CompositeLayerManifest {
+    file: String,
+    depth: f32,
+    channels: String,
+    strength: f32,
+    layer_mix_method: LayerMixMethod,
+    inverted: bool,
+    modifies_alpha: bool,
+}
+
+/// Describes how to blend the exported layer images back into the composite,
+/// layer pixel values map linearly from [0, 255] to [0, depth].
+#[derive(Debug, Serialize)]
+struct CompositeManifest {
+    width: usize,
+    height: usize,
+    normalize_on_overflow: bool,
+    max_height: f32,
+    layers: Vec<CompositeLayerManifest>,
+}
+
+/// Writes every layer of a composite as a grayscale png next to a json manifest of the blend modes.
+pub fn export_composite(composite: &Composite, filename: &str) -> Result<(), StateIoError> {
+    let mut layers = Vec::new();
+    for (i, layer) in composite.layers.iter().enumerate() {
+        let layer_filename = format!("{}-layer-{}", filename, i);
+        save_heightmap_as_image(&layer.heightmap, &layer_filename)?;
+        layers.push(CompositeLayerManifest {
+            file: format!("{}.png", layer_filename),
+            depth: layer.heightmap.depth,
+            channels: channel_names(layer.channel),
+            strength: layer.strength,
+            layer_mix_method: layer.layer_mix_method,
+            inverted: layer.inverted,
+            modifies_alpha: layer.modifies_alpha,
+        });
+    }
+    let (width, height) = composite
+        .layers
+        .first()
+        .map(|layer| (layer.heightmap.width, layer.heightmap.height))
+        .unwrap_or((0, 0));
+    let manifest = CompositeManifest {
+        width,
+        height,
+        normalize_on_overflow: composite.normalize_on_overflow,
+        max_height: composite.max_height,
+        layers,
+    };
+    fs::write(
+        format!("{}.json", filename),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(())
+}
+
 pub fn export_json(state: &State, filename: &str) -> Result<(), StateIoError> {
     fs::create_dir_all(OUTPUT_DIRECTORY)?;
     let result = serde_json::to_string(state)?;
@@ -77,6 +133,22 @@ pub fn export_binary(state: &State, filename: &str) -> Result<(), StateIoError>
     Ok(())
 }
 
+/// Deletes the binary and json state files and the icon exported under `file_name`.
+#[cfg(feature = "gui")]
+pub fn remove(file_name: &str) -> Result<(), StateIoError> {
+    for path in [
+        format!("{}/{}.{}", OUTPUT_DIRECTORY, file_name, STATE_FILE_EXT),
+        format!("{}/{}.{}.json", OUTPUT_DIRECTORY, file_name, STATE_FILE_EXT),
+        format!("{}/{}.{}", OUTPUT_DIRECTORY, file_name, ICON_FILE_EXT),
+    ] {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
 pub fn import(file_name: &str) -> Result<State, StateIoError> {
     let binary_result = import_binary(file_name);
     let result = if let Err(_) = binary_result {
@@ -114,35 +186,7 @@ fn repair_ui_state(ui_state: &mut UiState) {
 }
 
 fn repair_app_state(app_state: &mut AppState) {
-    for ref mut state in &mut app_state.simulation_states {
-        let active_hm = &state.base().heightmap_active.heightmap;
-        let active = HeightmapTexture::from(active_hm);
-        state.base_mut().heightmap_active = Rc::new(active);
-
-        let base_hm = &state.base().heightmap_base.heightmap;
-        let base = HeightmapTexture::from(base_hm);
-        state.base_mut().heightmap_base = Rc::new(base);
-
-        if let Some(eroded_state) = state.eroded_mut() {
-            let eroded_hm = &eroded_state.heightmap_eroded.heightmap;
-            let eroded = HeightmapTexture::from(eroded_hm);
-            eroded_state.heightmap_eroded = Rc::new(eroded);
-
-            let mut diffs = Vec::new();
-            for diff in eroded_state.heightmap_difference.borrow().iter() {
-                let diff_fixed = HeightmapTexture::from(&diff.heightmap);
-                diffs.push(Rc::new(diff_fixed));
-            }
-            eroded_state.heightmap_difference = Rc::new(RefCell::new(diffs));
-
-            let mut diffs = Vec::new();
-            for diff in eroded_state.heightmap_difference_normalized.borrow().iter() {
-                let diff_fixed = HeightmapTexture::from(&diff.heightmap);
-                diffs.push(Rc::new(diff_fixed));
-            }
-            eroded_state.heightmap_difference_normalized = Rc::new(RefCell::new(diffs));
-        }
-    }
+    app_state.regenerate_textures(true);
 }
 
 pub type StateFile = (String, Option<String>);
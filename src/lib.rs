@@ -0,0 +1,200 @@
+/*
+The erosion core and the application built on it. Heightmaps, the erosion models, partitioning and
+the shared math build without any gui dependencies when the `gui` feature is off, so other projects
+can depend on the core alone. The engine, the application state and the tools built on it only need
+the `export` feature, the window, the ui and the self-test need `gui` as well, which the `erosion`
+binary is built with.
+
+The engine runs its scripts on `State`, the application state with its ui settings and queued ui
+events, which holds no textures or other gpu resources. Drawing the state, the textures and the
+windowed instructions of the engine, in `engine::window`, are all behind `gui`. Without it every
+engine runs headless. What a script prints goes to the log of the engine, see
+`engine::progress::Output`, which prints it on the terminal unless given another one.
+ */
+
+#[cfg(feature = "export")]
+pub mod bench;
+#[cfg(feature = "export")]
+pub mod cli;
+#[cfg(feature = "export")]
+pub mod compare;
+#[cfg(feature = "export")]
+pub mod engine;
+pub mod erode;
+#[cfg(any(test, feature = "gui"))]
+mod fixtures;
+#[cfg(feature = "export")]
+pub mod generate_tests;
+pub mod heightmap;
+#[cfg(feature = "export")]
+mod io;
+pub mod math;
+pub mod partitioning;
+#[cfg(feature = "gui")]
+pub mod self_test;
+#[cfg(feature = "export")]
+pub mod visualize;
+
+#[cfg(feature = "export")]
+use crate::erode::Parameters;
+#[cfg(feature = "export")]
+use crate::heightmap::io::ExportCrop;
+#[cfg(feature = "export")]
+use crate::heightmap::mesh::MeshSettings;
+#[cfg(feature = "export")]
+use crate::heightmap::segments::SegmentSettings;
+#[cfg(feature = "export")]
+use crate::heightmap::HeightmapType;
+#[cfg(feature = "export")]
+use crate::visualize::{
+    app_state::{AppParameters, AppState, SimulationState},
+    events::UiEvent,
+    flood::FloodAnimation,
+    heat::HeatOverlay,
+    hillshade::Hillshade,
+    history::History,
+    overlay::VectorOverlay,
+    palette::{Colormap, Palette},
+    region::RegionOfInterest,
+    sculpt::SculptBrush,
+    session::SessionStats,
+    ui::{
+        ContourLines, CropTool, ErosionBrush, HardnessBrush, IsolineProperties, LayerMath,
+        Resample, SnapshotBrowser, UiState,
+    },
+    view::{Navigation, View},
+    workspace::Workspaces,
+};
+#[cfg(feature = "gui")]
+use crate::visualize::{hud::Hud, preview::PartitionPreview, textures::TextureCache};
+#[cfg(feature = "export")]
+use serde::{Deserialize, Serialize};
+
+pub const PRESET_GRID_SIZE: usize = 6;
+pub const PRESET_HEIGHTMAP_SIZE: usize = 512;
+
+#[cfg(feature = "export")]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct State {
+    pub state_name: Option<String>,
+    pub app_state: AppState,
+    pub ui_state: UiState,
+    /// Set when the session changed since it was last saved or loaded.
+    #[serde(skip)]
+    pub dirty: bool,
+}
+
+#[cfg(feature = "export")]
+impl Default for State {
+    fn default() -> Self {
+        Self::new(&HeightmapType::default())
+    }
+}
+
+#[cfg(feature = "export")]
+impl State {
+    pub fn new(heightmap_type: &HeightmapType) -> Self {
+        Self {
+            state_name: None,
+            app_state: AppState {
+                simulation_states: vec![SimulationState::get_new_base(
+                    0,
+                    heightmap_type,
+                    &Parameters::default(),
+                )],
+                simulation_base_indices: vec![0],
+                parameters: AppParameters {
+                    heightmap_type: heightmap_type.clone(),
+                    ..Default::default()
+                },
+                notes: String::new(),
+                bookmarks: Vec::new(),
+                simulation_job: None,
+                incremental_simulation: None,
+                session: SessionStats::default(),
+                history: History::default(),
+            },
+            ui_state: UiState {
+                show_ui_all: true,
+                show_ui_keybinds: false,
+                show_ui_control_panel: true,
+                show_ui_metadata: false,
+                show_ui_metrics: false,
+                show_ui_snapshots: false,
+                show_ui_timeline: false,
+                show_ui_session: false,
+                show_ui_hud: false,
+                #[cfg(feature = "gui")]
+                hud: Hud::default(),
+                show_ui_region: false,
+                region: RegionOfInterest::default(),
+                session_log: false,
+                timeline_frame: 0,
+                show_ui_presentation_mode: true,
+                show_grid: false,
+                show_partition_preview: false,
+                #[cfg(feature = "gui")]
+                partition_preview: PartitionPreview::default(),
+                #[cfg(feature = "gui")]
+                textures: TextureCache::default(),
+                simulation_clear: true,
+                simulation_regenerate: false,
+                application_quit: false,
+                ui_events: Vec::<UiEvent>::new(),
+                ui_events_previous: Vec::<UiEvent>::new(),
+                frame_slots: None,
+                blur_sigma: 5.0,
+                canny_edge: (2.5, 50.0),
+                isoline: IsolineProperties {
+                    height: 0.2,
+                    error: 0.01,
+                    flood_lower: false,
+                    should_flood: true,
+                    flooded_areas_lower: None,
+                    flooded_areas_higher: None,
+                    blur_augmentation: (false, 1.0, 5, 5),
+                    advanced_texture: true,
+                    flooded_errors: None,
+                    flooded_share: None,
+                    contour_lines: ContourLines::default(),
+                },
+                hardness_brush: HardnessBrush::default(),
+                drop_zone_brush: HardnessBrush::default(),
+                erosion_brush: ErosionBrush::default(),
+                sculpt_brush: SculptBrush::default(),
+                hillshade: Hillshade::default(),
+                flood_animation: FloodAnimation::default(),
+                workspaces: Workspaces::load(),
+                heat_overlay: HeatOverlay::default(),
+                droplet_bin: 8,
+                heightmap_file: String::new(),
+                palette: Palette::default(),
+                colormap: Colormap::default(),
+                segments: SegmentSettings::default(),
+                segmentation: None,
+                drainage: None,
+                lakes: None,
+                terrain_metrics: Vec::new(),
+                layer_math: LayerMath::default(),
+                resample: Resample::default(),
+                crop: CropTool::default(),
+                show_legend: false,
+                vector_overlay: VectorOverlay::default(),
+                view: View::default(),
+                navigation: Navigation::default(),
+                snapshot_browser: SnapshotBrowser::default(),
+                #[cfg(feature = "export")]
+                saves: io::list_state_files()
+                    .ok()
+                    .or_else(|| Some(Vec::new()))
+                    .expect("Failed to access saved states."),
+                screenshots: 0,
+                export_formats: Vec::new(),
+                export_crop: ExportCrop::default(),
+                mesh: MeshSettings::default(),
+                quit_prompt: false,
+            },
+            dirty: false,
+        }
+    }
+}
@@ -1,36 +1,17 @@
-use crate::erode::Parameters;
-use crate::generate_tests::generate_all_permutations;
-use crate::heightmap::HeightmapType;
-use crate::visualize::app_state::{AppParameters, AppState, SimulationState};
-use crate::visualize::events::UiEvent;
-use crate::visualize::ui::{IsolineProperties, UiState};
+use erosion_rs::engine::checkpoint::{self, Checkpoints, CHECKPOINT_FILE, CHECKPOINT_INTERVAL};
+use erosion_rs::engine::format::ScriptFormat;
+use erosion_rs::engine::progress::Progress;
+use erosion_rs::generate_tests::generate_all_permutations;
+use erosion_rs::visualize::{HEIGHT, WIDTH};
+#[cfg(feature = "export")]
+use erosion_rs::{bench, cli, compare};
+use erosion_rs::{engine, erode, generate_tests, self_test, visualize};
 use image::io::Reader as ImageReader;
 use macroquad::miniquad::conf::Icon;
 use macroquad::prelude::*;
-use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
-pub mod engine;
-pub mod erode;
-pub mod generate_tests;
-pub mod heightmap;
-#[cfg(feature = "export")]
-mod io;
-pub mod math;
-pub mod partitioning;
-pub mod visualize;
-
-const WIDTH: u32 = 1107;
-const HEIGHT: u32 = 800;
-const PRESET_GRID_SIZE: usize = 6;
-const PRESET_HEIGHTMAP_SIZE: usize = 512;
-const GRID_SIZE_RANGE_MIN: usize = 2;
-const GRID_SIZE_RANGE_MAX: usize = 32;
-const GAUSSIAN_BLUR_SIGMA_RANGE_MIN: f32 = 0.0;
-const GAUSSIAN_BLUR_SIGMA_RANGE_MAX: f32 = 20.0;
-const GAUSSIAN_BLUR_BOUNDARY_THICKNESS_MIN: u16 = 0;
-const GAUSSIAN_BLUR_BOUNDARY_THICKNESS_MAX: u16 = 10;
-
 fn window_conf() -> Conf {
     fn icons() -> Option<Icon> {
         let icon_small_img = ImageReader::open("assets/icon16x16.png")
@@ -96,87 +77,126 @@ fn window_conf() -> Conf {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct State {
-    pub state_name: Option<String>,
-    pub app_state: AppState,
-    pub ui_state: UiState,
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+enum Command {
+    Engine,
+    Resume,
+    EngineWatch,
+    GenerateExample,
+    ScriptDocs,
+    GenerateScript,
+    GenerateSweep,
+    SelfTest,
+    BenchDroplets,
 }
 
-impl State {
-    pub fn default() -> Self {
-        Self::new(&HeightmapType::default())
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    // Subcommands run from the terminal without opening a window
+    if args.get(1).map(String::as_str) == Some("erode") {
+        #[cfg(feature = "export")]
+        std::process::exit(if cli::run(&args[2..]) { 0 } else { 1 });
+        #[cfg(not(feature = "export"))]
+        {
+            println!("Eroding from the command line needs the export feature");
+            std::process::exit(1);
+        }
+    }
+    if args.get(1).map(String::as_str) == Some("--check-script") {
+        std::process::exit(if check_scripts(&args[2..]) { 0 } else { 1 });
+    }
+    if args.get(1).map(String::as_str) == Some("--snapshot-records") {
+        std::process::exit(if snapshot_records(&args[2..]) { 0 } else { 1 });
+    }
+    if args.get(1).map(String::as_str) == Some("--compare") {
+        #[cfg(feature = "export")]
+        std::process::exit(if compare::run(&args[2..]) { 0 } else { 1 });
+        #[cfg(not(feature = "export"))]
+        {
+            println!("Comparing heightmaps needs the export feature");
+            std::process::exit(1);
+        }
     }
+    if args.get(1).map(String::as_str) == Some("--benchmark") {
+        #[cfg(feature = "export")]
+        std::process::exit(if bench::run(&args[2..]) { 0 } else { 1 });
+        #[cfg(not(feature = "export"))]
+        {
+            println!("Benchmarking compares heightmaps, which needs the export feature");
+            std::process::exit(1);
+        }
+    }
+    macroquad::Window::from_config(window_conf(), run(args));
+}
 
-    pub fn new(heightmap_type: &HeightmapType) -> Self {
-        Self {
-            state_name: None,
-            app_state: AppState {
-                simulation_states: vec![SimulationState::get_new_base(
-                    0,
-                    &heightmap_type,
-                    &Parameters::default(),
-                )],
-                simulation_base_indices: vec![0],
-                parameters: AppParameters {
-                    heightmap_type: *heightmap_type,
-                    ..Default::default()
-                },
-            },
-            ui_state: UiState {
-                show_ui_all: true,
-                show_ui_keybinds: false,
-                show_ui_control_panel: true,
-                show_ui_metadata: false,
-                show_ui_metrics: false,
-                show_ui_presentation_mode: true,
-                show_grid: false,
-                simulation_clear: true,
-                simulation_regenerate: false,
-                application_quit: false,
-                ui_events: Vec::<UiEvent>::new(),
-                ui_events_previous: Vec::<UiEvent>::new(),
-                frame_slots: None,
-                blur_sigma: 5.0,
-                canny_edge: (2.5, 50.0),
-                isoline: IsolineProperties {
-                    height: 0.2,
-                    error: 0.01,
-                    flood_lower: false,
-                    should_flood: true,
-                    flooded_areas_lower: None,
-                    flooded_areas_higher: None,
-                    blur_augmentation: (false, 1.0, 5, 5),
-                    advanced_texture: true,
-                    flooded_errors: None,
-                },
-                #[cfg(feature = "export")]
-                saves: io::list_state_files()
-                    .ok()
-                    .or_else(|| Some(Vec::new()))
-                    .expect("Failed to access saved states."),
-                screenshots: 0,
-            },
+/// Reads every script and checks its calls and first state, false when any of them failed.
+fn check_scripts(paths: &[String]) -> bool {
+    if paths.is_empty() {
+        println!("Usage: --check-script <script>...");
+        return false;
+    }
+    let mut valid = true;
+    for path in paths {
+        match engine::format::read(path).and_then(|script| engine::check(&script)) {
+            Ok(instructions) => println!("{}: ok, {} instructions", path, instructions.len()),
+            Err(engine::EngineError::ScriptError(err)) => {
+                println!("{}: {}", path, err);
+                valid = false;
+            }
+            Err(err) => {
+                println!("{}: {:?}", path, err);
+                valid = false;
+            }
         }
     }
+    valid
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
-enum Command {
-    Engine,
-    GenerateExample,
-    GenerateScript,
+/// Writes the records of a snapshot archive as csv or json, false when it failed.
+fn snapshot_records(args: &[String]) -> bool {
+    let [archive, output] = args else {
+        println!("Usage: --snapshot-records <archive.ersnap> <records.csv|records.json>");
+        return false;
+    };
+    let result = engine::archive::SnapshotArchive::open(archive)
+        .and_then(|mut archive| archive.read_all())
+        .and_then(|snapshots| engine::record::records(&snapshots))
+        .and_then(|records| {
+            engine::record::write(&records, output)?;
+            Ok(records.len())
+        });
+    match result {
+        Ok(count) => {
+            println!("Wrote {} records of {} to {}", count, archive, output);
+            true
+        }
+        Err(err) => {
+            println!("Failed to export {}. Reason: {:?}", archive, err);
+            false
+        }
+    }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let args: Vec<String> = env::args().collect();
+/// The file after `--progress`, where a running script writes its progress as json.
+fn progress_file(args: &[String]) -> Option<PathBuf> {
+    args.iter()
+        .position(|arg| arg == "--progress")
+        .and_then(|i| args.get(i + 1))
+        .map(PathBuf::from)
+}
 
+async fn run(args: Vec<String>) {
     let command_bindings: &[(String, Command)] = &[
         ("--engine".to_string(), Command::Engine),
         ("-e".to_string(), Command::Engine),
+        ("--resume".to_string(), Command::Resume),
+        ("--engine-watch".to_string(), Command::EngineWatch),
         ("--generate-example".to_string(), Command::GenerateExample),
+        ("--script-docs".to_string(), Command::ScriptDocs),
         ("--generate-script".to_string(), Command::GenerateScript),
+        ("--generate-sweep".to_string(), Command::GenerateSweep),
+        ("--self-test".to_string(), Command::SelfTest),
+        ("--bench-droplets".to_string(), Command::BenchDroplets),
     ];
 
     let mut commands: Vec<Command> = args
@@ -204,24 +224,99 @@ async fn main() {
                 // } else {
                 //     engine::scripts::default()
                 // };
-                let script = generate_all_permutations();
+                // A script after the flag is run as a test, failing the process when it fails
+                let path = args
+                    .iter()
+                    .position(|arg| arg == "--engine" || arg == "-e")
+                    .and_then(|i| args.get(i + 1))
+                    .filter(|arg| !arg.starts_with('-'));
+                let script = match path.map(|path| engine::format::read(path)) {
+                    Some(Ok(script)) => script,
+                    Some(Err(err)) => {
+                        println!("Failed to load the script. Reason: {:?}", err);
+                        std::process::exit(1);
+                    }
+                    None => generate_all_permutations(),
+                };
 
-                let engine_result = engine::launch(script).await;
+                prevent_quit();
+                let engine_result = match engine::start(script) {
+                    Ok(mut engine) => {
+                        engine.checkpoints =
+                            Some(Checkpoints::new(CHECKPOINT_FILE, CHECKPOINT_INTERVAL));
+                        engine.progress = Some(Progress::new(&engine, progress_file(&args)));
+                        engine::turn(engine).await
+                    }
+                    Err(err) => Err(err),
+                };
                 if let Ok(_state) = engine_result {
                 } else if let Err(err) = engine_result {
                     println!("Engine died. Reason: {:?}", err);
+                    println!("Continue from the last checkpoint with --resume");
+                    if path.is_some() {
+                        std::process::exit(1);
+                    }
                 };
             }
+            Command::Resume => {
+                let path = args
+                    .iter()
+                    .position(|arg| arg == "--resume")
+                    .and_then(|i| args.get(i + 1))
+                    .filter(|arg| !arg.starts_with('-'))
+                    .map(String::as_str)
+                    .unwrap_or(CHECKPOINT_FILE);
+                let checkpoint = match checkpoint::read(Path::new(path)) {
+                    Ok(checkpoint) => checkpoint,
+                    Err(err) => {
+                        println!("Failed to read the checkpoint {}. Reason: {:?}", path, err);
+                        std::process::exit(1);
+                    }
+                };
+                println!(
+                    "Resuming after {} instructions, {} left to run",
+                    checkpoint.completed,
+                    checkpoint.main.len()
+                );
+                prevent_quit();
+                let mut engine = checkpoint.resume();
+                engine.checkpoints = Some(Checkpoints::new(path, CHECKPOINT_INTERVAL));
+                engine.progress = Some(Progress::new(&engine, progress_file(&args)));
+                if let Err(err) = engine::turn(engine).await {
+                    println!("Engine died. Reason: {:?}", err);
+                    std::process::exit(1);
+                }
+            }
+            Command::EngineWatch => {
+                let path = args
+                    .iter()
+                    .position(|arg| arg == "--engine-watch")
+                    .and_then(|i| args.get(i + 1));
+                if let Some(path) = path {
+                    if let Err(err) = engine::watch::watch(path, engine::progress::terminal()).await
+                    {
+                        println!("Engine died. Reason: {:?}", err);
+                    }
+                } else {
+                    println!("Usage: --engine-watch <script>");
+                }
+            }
             Command::GenerateExample => {
-                let result = serde_json::to_string(&engine::scripts::default());
-                if let Ok(example) = result {
-                    let result = fs::write("script.example.erss", example);
-                    if let Ok(()) = result {
-                    } else {
-                        panic!("Example can't be converted to json!");
+                for format in ScriptFormat::list() {
+                    let path = format!("script.example.{}", format.extension());
+                    if let Err(err) = engine::format::write(&path, &engine::scripts::default()) {
+                        panic!("Failed to write {}: {:?}", path, err);
                     }
                 }
             }
+            Command::ScriptDocs => {
+                let schema = serde_json::to_string_pretty(&engine::docs::schema());
+                let result = schema.map(|schema| fs::write(engine::docs::SCHEMA_FILE, schema));
+                match result {
+                    Ok(Ok(())) => println!("Wrote {}", engine::docs::SCHEMA_FILE),
+                    _ => panic!("Failed to write {}!", engine::docs::SCHEMA_FILE),
+                }
+            }
             Command::GenerateScript => {
                 let result = serde_json::to_string(&generate_tests::generate_test());
                 if let Ok(example) = result {
@@ -232,6 +327,24 @@ async fn main() {
                     }
                 }
             }
+            Command::GenerateSweep => {
+                let sweep = generate_tests::generate_parameter_sweep(32, 1337);
+                let result = serde_json::to_string(&sweep)
+                    .map(|sweep| fs::write("sweep.erss", sweep).is_ok());
+                if result.ok() != Some(true) {
+                    panic!("Failed to serialize sweep script!");
+                }
+            }
+            Command::SelfTest => {
+                if !self_test::run() {
+                    std::process::exit(1);
+                }
+            }
+            Command::BenchDroplets => {
+                for run in erode::lague::benchmark(1024) {
+                    println!("{}", run);
+                }
+            }
         }
     }
 
@@ -1,5 +1,15 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::ops::{Add, Mul, Sub};
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/*
+Small vector and rectangle types shared by the simulation and the visualisation. `Vector2` is in
+continuous heightmap or screen space, `IVector2` and `UVector2` are cells, `Extent` is a size in
+cells and `Margins` are the cells cut off each side of a heightmap. The vectors have the usual
+operators, component-wise with another vector and uniform with a scalar, and convert to and from
+the egui and macroquad types so positions can be handed between the ui and the heightmaps.
+ */
 
 #[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct Vector2 {
@@ -46,6 +56,10 @@ impl Vector2 {
         (self.x, self.y)
     }
 
+    pub fn dot(&self, other: &Vector2) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
     pub fn interpolate(&self, other: &Vector2, t: f32) -> Vector2 {
         *self * (1.0 - t) + *other * t
     }
@@ -55,41 +69,8 @@ impl Vector2 {
         if magnitude <= 0.0 {
             panic!("Trying to normalize a zero length vector!");
         }
-        self.x = self.x / magnitude;
-        self.y = self.y / magnitude;
-    }
-}
-
-impl Sub for Vector2 {
-    type Output = Vector2;
-
-    fn sub(self, other: Vector2) -> Vector2 {
-        Vector2 {
-            x: self.x - other.x,
-            y: self.y - other.y,
-        }
-    }
-}
-
-impl Add for Vector2 {
-    type Output = Vector2;
-
-    fn add(self, other: Vector2) -> Vector2 {
-        Vector2 {
-            x: self.x + other.x,
-            y: self.y + other.y,
-        }
-    }
-}
-
-impl Mul<f32> for Vector2 {
-    type Output = Vector2;
-
-    fn mul(self, other: f32) -> Vector2 {
-        Vector2 {
-            x: self.x * other,
-            y: self.y * other,
-        }
+        self.x /= magnitude;
+        self.y /= magnitude;
     }
 }
 
@@ -145,40 +126,7 @@ impl IVector2 {
     }
 }
 
-impl Sub for IVector2 {
-    type Output = IVector2;
-
-    fn sub(self, other: IVector2) -> IVector2 {
-        IVector2 {
-            x: self.x - other.x,
-            y: self.y - other.y,
-        }
-    }
-}
-
-impl Add for IVector2 {
-    type Output = IVector2;
-
-    fn add(self, other: IVector2) -> IVector2 {
-        IVector2 {
-            x: self.x + other.x,
-            y: self.y + other.y,
-        }
-    }
-}
-
-impl Mul<i32> for IVector2 {
-    type Output = IVector2;
-
-    fn mul(self, other: i32) -> IVector2 {
-        IVector2 {
-            x: self.x * other,
-            y: self.y * other,
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct UVector2 {
     pub x: usize,
     pub y: usize,
@@ -219,35 +167,375 @@ impl UVector2 {
     }
 }
 
-impl Sub for UVector2 {
-    type Output = UVector2;
+/// Component-wise operators with another vector and uniform operators with a scalar.
+macro_rules! vector_ops {
+    ($vector:ident, $scalar:ty) => {
+        vector_ops!(@op $vector, $scalar, Add, add, AddAssign, add_assign, +);
+        vector_ops!(@op $vector, $scalar, Sub, sub, SubAssign, sub_assign, -);
+        vector_ops!(@op $vector, $scalar, Mul, mul, MulAssign, mul_assign, *);
+        vector_ops!(@op $vector, $scalar, Div, div, DivAssign, div_assign, /);
+    };
+    (@op $vector:ident, $scalar:ty, $op:ident, $fn:ident, $assign:ident, $assign_fn:ident, $sym:tt) => {
+        impl $op for $vector {
+            type Output = $vector;
+
+            fn $fn(self, other: $vector) -> $vector {
+                $vector {
+                    x: self.x $sym other.x,
+                    y: self.y $sym other.y,
+                }
+            }
+        }
+
+        impl $op<$scalar> for $vector {
+            type Output = $vector;
+
+            fn $fn(self, other: $scalar) -> $vector {
+                $vector {
+                    x: self.x $sym other,
+                    y: self.y $sym other,
+                }
+            }
+        }
+
+        impl $assign for $vector {
+            fn $assign_fn(&mut self, other: $vector) {
+                *self = *self $sym other;
+            }
+        }
+
+        impl $assign<$scalar> for $vector {
+            fn $assign_fn(&mut self, other: $scalar) {
+                *self = *self $sym other;
+            }
+        }
+    };
+}
+
+vector_ops!(Vector2, f32);
+vector_ops!(IVector2, i32);
+vector_ops!(UVector2, usize);
+
+impl Neg for Vector2 {
+    type Output = Vector2;
+
+    fn neg(self) -> Vector2 {
+        Vector2 {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
+impl Neg for IVector2 {
+    type Output = IVector2;
 
-    fn sub(self, other: UVector2) -> UVector2 {
-        UVector2 {
-            x: self.x - other.x,
-            y: self.y - other.y,
+    fn neg(self) -> IVector2 {
+        IVector2 {
+            x: -self.x,
+            y: -self.y,
         }
     }
 }
 
-impl Add for UVector2 {
-    type Output = UVector2;
+impl From<UVector2> for Vector2 {
+    fn from(vector: UVector2) -> Self {
+        Vector2::new(vector.x as f32, vector.y as f32)
+    }
+}
+
+impl From<IVector2> for Vector2 {
+    fn from(vector: IVector2) -> Self {
+        Vector2::new(vector.x as f32, vector.y as f32)
+    }
+}
+
+impl From<UVector2> for IVector2 {
+    fn from(vector: UVector2) -> Self {
+        IVector2::new(vector.x as i32, vector.y as i32)
+    }
+}
+
+impl From<(f32, f32)> for Vector2 {
+    fn from((x, y): (f32, f32)) -> Self {
+        Vector2::new(x, y)
+    }
+}
+
+impl From<(usize, usize)> for UVector2 {
+    fn from((x, y): (usize, usize)) -> Self {
+        UVector2::new(x, y)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<egui::Vec2> for Vector2 {
+    fn from(vector: egui::Vec2) -> Self {
+        Vector2::new(vector.x, vector.y)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Vector2> for egui::Vec2 {
+    fn from(vector: Vector2) -> Self {
+        egui::vec2(vector.x, vector.y)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<egui::Pos2> for Vector2 {
+    fn from(position: egui::Pos2) -> Self {
+        Vector2::new(position.x, position.y)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Vector2> for egui::Pos2 {
+    fn from(vector: Vector2) -> Self {
+        egui::pos2(vector.x, vector.y)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<macroquad::math::Vec2> for Vector2 {
+    fn from(vector: macroquad::math::Vec2) -> Self {
+        Vector2::new(vector.x, vector.y)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Vector2> for macroquad::math::Vec2 {
+    fn from(vector: Vector2) -> Self {
+        macroquad::math::vec2(vector.x, vector.y)
+    }
+}
+
+/// Axis aligned rectangle from `min` to `max`, y down.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Rect {
+    pub min: Vector2,
+    pub max: Vector2,
+}
+
+impl Rect {
+    pub fn new(min: Vector2, max: Vector2) -> Rect {
+        Rect { min, max }
+    }
+
+    pub fn from_min_size(min: Vector2, size: Vector2) -> Rect {
+        Rect {
+            min,
+            max: min + size,
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max.y - self.min.y
+    }
+
+    pub fn size(&self) -> Vector2 {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Vector2 {
+        self.min.interpolate(&self.max, 0.5)
+    }
+
+    pub fn contains(&self, point: Vector2) -> bool {
+        (self.min.x..=self.max.x).contains(&point.x) && (self.min.y..=self.max.y).contains(&point.y)
+    }
 
-    fn add(self, other: UVector2) -> UVector2 {
-        UVector2 {
-            x: self.x + other.x,
-            y: self.y + other.y,
+    /// The overlap of the two rectangles, `None` if they do not overlap.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let min = Vector2::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y));
+        let max = Vector2::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y));
+        if min.x > max.x || min.y > max.y {
+            None
+        } else {
+            Some(Rect { min, max })
         }
     }
+
+    /// Position of `point` relative to the rectangle, [0, 1] inside it.
+    pub fn normalize(&self, point: Vector2) -> Vector2 {
+        (point - self.min) / self.size()
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<egui::Rect> for Rect {
+    fn from(rect: egui::Rect) -> Self {
+        Rect::new(rect.min.into(), rect.max.into())
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Rect> for egui::Rect {
+    fn from(rect: Rect) -> Self {
+        egui::Rect::from_min_max(rect.min.into(), rect.max.into())
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<macroquad::math::Rect> for Rect {
+    fn from(rect: macroquad::math::Rect) -> Self {
+        Rect::from_min_size(Vector2::new(rect.x, rect.y), Vector2::new(rect.w, rect.h))
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Rect> for macroquad::math::Rect {
+    fn from(rect: Rect) -> Self {
+        macroquad::math::Rect::new(rect.min.x, rect.min.y, rect.width(), rect.height())
+    }
+}
+
+/// Size of a heightmap or part of one, in cells.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct Extent {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Extent {
+    pub fn new(width: usize, height: usize) -> Extent {
+        Extent { width, height }
+    }
+
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn contains(&self, cell: UVector2) -> bool {
+        cell.x < self.width && cell.y < self.height
+    }
+}
+
+impl From<UVector2> for Extent {
+    fn from(vector: UVector2) -> Self {
+        Extent::new(vector.x, vector.y)
+    }
 }
 
-impl Mul<usize> for UVector2 {
-    type Output = UVector2;
+impl From<Extent> for UVector2 {
+    fn from(extent: Extent) -> Self {
+        UVector2::new(extent.width, extent.height)
+    }
+}
+
+/// Cells cut off each side of a heightmap.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct Margins {
+    pub right: usize,
+    pub top: usize,
+    pub left: usize,
+    pub bottom: usize,
+}
 
-    fn mul(self, other: usize) -> UVector2 {
-        UVector2 {
-            x: self.x * other,
-            y: self.y * other,
+impl Margins {
+    pub fn new(right: usize, top: usize, left: usize, bottom: usize) -> Margins {
+        Margins {
+            right,
+            top,
+            left,
+            bottom,
         }
     }
+
+    /// `horizontal` cells on the left and right and `vertical` on the top and bottom.
+    pub fn symmetric(horizontal: usize, vertical: usize) -> Margins {
+        Margins::new(horizontal, vertical, horizontal, vertical)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Margins::default()
+    }
+
+    pub fn horizontal(&self) -> usize {
+        self.left + self.right
+    }
+
+    pub fn vertical(&self) -> usize {
+        self.top + self.bottom
+    }
+
+    /// Top left cell of the interior.
+    pub fn anchor(&self) -> UVector2 {
+        UVector2::new(self.left, self.top)
+    }
+
+    /// Size of the interior left of `extent` with the margins cut off.
+    pub fn interior(&self, extent: Extent) -> Extent {
+        Extent::new(
+            extent.width - self.horizontal(),
+            extent.height - self.vertical(),
+        )
+    }
+
+    /// Whether `cell` of a heightmap of `extent` lies inside the margins.
+    pub fn in_interior(&self, extent: Extent, cell: UVector2) -> bool {
+        (self.left..extent.width - self.right).contains(&cell.x)
+            && (self.top..extent.height - self.bottom).contains(&cell.y)
+    }
+
+    /// The larger margin of the two on each side.
+    pub fn max(&self, other: &Margins) -> Margins {
+        Margins::new(
+            self.right.max(other.right),
+            self.top.max(other.top),
+            self.left.max(other.left),
+            self.bottom.max(other.bottom),
+        )
+    }
+
+    /// The smaller margin of the two on each side.
+    pub fn min(&self, other: &Margins) -> Margins {
+        Margins::new(
+            self.right.min(other.right),
+            self.top.min(other.top),
+            self.left.min(other.left),
+            self.bottom.min(other.bottom),
+        )
+    }
+}
+
+impl Add for Margins {
+    type Output = Margins;
+
+    fn add(self, other: Margins) -> Margins {
+        Margins::new(
+            self.right + other.right,
+            self.top + other.top,
+            self.left + other.left,
+            self.bottom + other.bottom,
+        )
+    }
+}
+
+impl Sub for Margins {
+    type Output = Margins;
+
+    fn sub(self, other: Margins) -> Margins {
+        Margins::new(
+            self.right - other.right,
+            self.top - other.top,
+            self.left - other.left,
+            self.bottom - other.bottom,
+        )
+    }
+}
+
+impl Display for Margins {
+    /// Right, top, left and bottom separated by spaces, the order they are recorded in metadata.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.right, self.top, self.left, self.bottom
+        )
+    }
 }
@@ -1,23 +1,123 @@
+pub mod voronoi;
+
 use crate::erode;
-use crate::erode::{DropZone, Parameters};
+use crate::erode::{DropZone, ErosionOutputs, ErosionPipeline, Model, PartitionReport, Progress};
 use crate::heightmap;
-use crate::heightmap::{Heightmap, HeightmapPrecision};
-use crate::math::UVector2;
+use crate::heightmap::{BlendCurve, Heightmap, HeightmapPrecision};
+use crate::math::{Extent, Margins, UVector2};
 use rayon::prelude::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
+use std::fmt::{Display, Formatter};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 pub const GAUSSIAN_DEFAULT_SIGMA: f32 = 2.0;
 pub const GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS: u16 = 2;
 
-#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum Method {
     Default,
     Subdivision(usize),
     SubdivisionBlurBoundary((usize, (f32, u16))),
     // SubdivisionOverlap(usize),
-    GridOverlapBlend(usize),
+    GridOverlapBlend((usize, BlendCurve)),
+    /// Four passes over the grid, shifted by half a cell along either axis or both.
+    Checkerboard(usize),
+    /// Cell count and seed of irregular cells, see `voronoi`.
+    Voronoi(usize, u64),
+}
+
+/// Cells cut off the sides of the map around the partitions, see `Method::margins`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum MarginMode {
+    /// The whole map is eroded and kept.
+    None,
+    /// The largest margin of any method, so the results of every method cover the same cells.
+    #[default]
+    Automatic,
+    /// Exactly these cells, of which the method may erode as much as it would otherwise.
+    Custom(Margins),
+}
+
+/// The partitions of `Method::get_grid_layers` over the map before its margins are cut off, each
+/// layer 1 where it applies and 0 elsewhere.
+#[derive(Debug, Clone)]
+pub struct GridLayers {
+    /// The boundaries painted by `get_grid`.
+    pub boundaries: Heightmap,
+    /// Cells eroded by more than one partition, or seen or blurred across the seams.
+    pub overlap: Heightmap,
+    /// Cells cut off by the margins.
+    pub margin: Heightmap,
+}
+
+impl MarginMode {
+    /// The mode of the "Use Margin" toggle and optional per-side margins of the parameters.
+    pub fn new(use_margin: bool, custom: Option<Margins>) -> MarginMode {
+        match (use_margin, custom) {
+            (false, _) => MarginMode::None,
+            (true, None) => MarginMode::Automatic,
+            (true, Some(margins)) => MarginMode::Custom(margins),
+        }
+    }
+
+    pub fn is_used(&self) -> bool {
+        !matches!(self, MarginMode::None)
+    }
+
+    pub fn custom(&self) -> Option<Margins> {
+        match self {
+            MarginMode::Custom(margins) => Some(*margins),
+            _ => None,
+        }
+    }
+
+    /// Reads `none`, `auto`, the same number of cells on every side or `right,top,left,bottom`.
+    pub fn parse(text: &str) -> Result<MarginMode, String> {
+        let cells = |part: &str| {
+            part.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid margin {}", text))
+        };
+        match text {
+            "none" => Ok(MarginMode::None),
+            "auto" | "automatic" => Ok(MarginMode::Automatic),
+            _ => {
+                let sides = text
+                    .split(',')
+                    .map(cells)
+                    .collect::<Result<Vec<usize>, String>>()?;
+                match sides[..] {
+                    [all] => Ok(MarginMode::Custom(Margins::symmetric(all, all))),
+                    [right, top, left, bottom] => {
+                        Ok(MarginMode::Custom(Margins::new(right, top, left, bottom)))
+                    }
+                    _ => Err(format!("a margin takes 1 or 4 numbers, not {}", text)),
+                }
+            }
+        }
+    }
+
+    /// The mode written the way `parse` reads it.
+    pub fn spec(&self) -> String {
+        match self {
+            MarginMode::None => "none".to_string(),
+            MarginMode::Automatic => "auto".to_string(),
+            MarginMode::Custom(m) => format!("{},{},{},{}", m.right, m.top, m.left, m.bottom),
+        }
+    }
+}
+
+impl Display for MarginMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarginMode::None => f.write_str("false"),
+            MarginMode::Automatic => f.write_str("true"),
+            MarginMode::Custom(margins) => write!(f, "{}", margins),
+        }
+    }
 }
 
 impl Method {
@@ -28,6 +128,67 @@ impl Method {
             Method::SubdivisionBlurBoundary(_) => String::from("Naive Tiling with Blur"),
             // Method::SubdivisionOverlap(_) => String::from("SubdivisionOverlap"),
             Method::GridOverlapBlend(_) => String::from("Overlapping Grids"),
+            Method::Checkerboard(_) => String::from("Checkerboard Passes"),
+            Method::Voronoi(..) => String::from("Voronoi Cells"),
+        }
+    }
+
+    /// Reads a method written as `name[:grid size]`, such as `grid-overlap:8`, where the names are
+    /// `default`, `subdivision`, `subdivision-blur`, `grid-overlap`, `checkerboard` and
+    /// `voronoi`. Voronoi takes a cell count instead of a grid size and an optional seed after it.
+    pub fn parse(spec: &str) -> Result<Method, String> {
+        let mut parts = spec.split(':');
+        let name = parts.next().unwrap_or_default().to_lowercase();
+        let numbers = parts
+            .map(|part| {
+                part.parse::<u64>()
+                    .map_err(|_| format!("{} is not a whole number in {}", part, spec))
+            })
+            .collect::<Result<Vec<u64>, String>>()?;
+        let grid_size = numbers
+            .first()
+            .map(|&size| size as usize)
+            .unwrap_or(crate::PRESET_GRID_SIZE);
+        if grid_size == 0 {
+            return Err(format!("{} needs at least one partition", spec));
+        }
+        let arguments = if name == "voronoi" { 2 } else { 1 };
+        if numbers.len() > arguments || (name == "default" && !numbers.is_empty()) {
+            return Err(format!("{} has too many numbers", spec));
+        }
+        match name.as_str() {
+            "default" | "none" => Ok(Method::Default),
+            "subdivision" => Ok(Method::Subdivision(grid_size)),
+            "subdivision-blur" => Ok(Method::SubdivisionBlurBoundary((
+                grid_size,
+                (GAUSSIAN_DEFAULT_SIGMA, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS),
+            ))),
+            "grid-overlap" => Ok(Method::GridOverlapBlend((grid_size, BlendCurve::default()))),
+            "checkerboard" => Ok(Method::Checkerboard(grid_size)),
+            "voronoi" => Ok(Method::Voronoi(
+                numbers
+                    .first()
+                    .map(|&cells| cells as usize)
+                    .unwrap_or(crate::PRESET_GRID_SIZE * crate::PRESET_GRID_SIZE),
+                numbers.get(1).copied().unwrap_or(voronoi::DEFAULT_SEED),
+            )),
+            _ => Err(format!(
+                "unknown method {}, expected default, subdivision, subdivision-blur, \
+                 grid-overlap, checkerboard or voronoi",
+                name
+            )),
+        }
+    }
+
+    /// The method written the way `parse` reads it, the blur and blend settings are left out.
+    pub fn spec(&self) -> String {
+        match self {
+            Method::Default => "default".to_string(),
+            Method::Subdivision(size) => format!("subdivision:{}", size),
+            Method::SubdivisionBlurBoundary((size, _)) => format!("subdivision-blur:{}", size),
+            Method::GridOverlapBlend((size, _)) => format!("grid-overlap:{}", size),
+            Method::Checkerboard(size) => format!("checkerboard:{}", size),
+            Method::Voronoi(cells, seed) => format!("voronoi:{}:{}", cells, seed),
         }
     }
 
@@ -37,7 +198,10 @@ impl Method {
             Method::Subdivision(size) |
             Method::SubdivisionBlurBoundary((size, _)) |
             // Method::SubdivisionOverlap(size) |
-            Method::GridOverlapBlend(size) => *size,
+            Method::GridOverlapBlend((size, _)) |
+            Method::Checkerboard(size) => *size,
+            // The grid with about as many cells
+            Method::Voronoi(cells, _) => ((*cells as f32).sqrt().round() as usize).max(1),
         }
     }
 
@@ -48,9 +212,15 @@ impl Method {
                 grid_size,
                 (GAUSSIAN_DEFAULT_SIGMA, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS),
             )),
-            Method::SubdivisionBlurBoundary((grid_size, _)) => Method::GridOverlapBlend(grid_size),
+            Method::SubdivisionBlurBoundary((grid_size, _)) => {
+                Method::GridOverlapBlend((grid_size, BlendCurve::default()))
+            }
             // Method::SubdivisionOverlap(_) => Method::GridOverlapBlend(crate::PRESET_GRID_SIZE),
-            Method::GridOverlapBlend(_) => Method::Default,
+            Method::GridOverlapBlend((grid_size, _)) => Method::Checkerboard(grid_size),
+            Method::Checkerboard(grid_size) => {
+                Method::Voronoi(grid_size * grid_size, voronoi::DEFAULT_SEED)
+            }
+            Method::Voronoi(..) => Method::Default,
         }
     }
 
@@ -58,11 +228,18 @@ impl Method {
         match self {
             Method::Subdivision(_) => Method::Default,
             Method::SubdivisionBlurBoundary((grid_size, _)) => Method::Subdivision(grid_size),
-            Method::GridOverlapBlend(grid_size) => Method::SubdivisionBlurBoundary((
+            Method::GridOverlapBlend((grid_size, _)) => Method::SubdivisionBlurBoundary((
                 grid_size,
                 (GAUSSIAN_DEFAULT_SIGMA, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS),
             )),
-            Method::Default => Method::GridOverlapBlend(crate::PRESET_GRID_SIZE),
+            Method::Checkerboard(grid_size) => {
+                Method::GridOverlapBlend((grid_size, BlendCurve::default()))
+            }
+            Method::Voronoi(..) => Method::Checkerboard(self.get_grid_size()),
+            Method::Default => Method::Voronoi(
+                crate::PRESET_GRID_SIZE * crate::PRESET_GRID_SIZE,
+                voronoi::DEFAULT_SEED,
+            ),
             // Method::SubdivisionOverlap(grid_size) => Method::SubdivisionBlurBoundary((
             //     grid_size,
             //     (GAUSSIAN_DEFAULT_SIGMA, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS),
@@ -80,11 +257,13 @@ impl Method {
             }
             // Method::SubdivisionOverlap(_) => matches!(other, Method::SubdivisionOverlap(_)),
             Method::GridOverlapBlend(_) => matches!(other, Method::GridOverlapBlend(_)),
+            Method::Checkerboard(_) => matches!(other, Method::Checkerboard(_)),
+            Method::Voronoi(..) => matches!(other, Method::Voronoi(..)),
         }
     }
 
-    pub fn list(grid_size: usize) -> [Method; 4] {
-        let erosion_methods: [Method; 4] = [
+    pub fn list(grid_size: usize) -> [Method; 6] {
+        let erosion_methods: [Method; 6] = [
             Method::Default,
             Method::Subdivision(grid_size),
             Method::SubdivisionBlurBoundary((
@@ -92,7 +271,9 @@ impl Method {
                 (GAUSSIAN_DEFAULT_SIGMA, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS),
             )),
             // Method::SubdivisionOverlap(grid_size),
-            Method::GridOverlapBlend(grid_size),
+            Method::GridOverlapBlend((grid_size, BlendCurve::default())),
+            Method::Checkerboard(grid_size),
+            Method::Voronoi(grid_size * grid_size, voronoi::DEFAULT_SEED),
         ];
         erosion_methods
     }
@@ -107,25 +288,19 @@ impl Method {
             Method::SubdivisionBlurBoundary((ref mut grid_size, _)) => {
                 *grid_size = value;
             }
-            Method::GridOverlapBlend(ref mut grid_size) => {
+            Method::GridOverlapBlend((ref mut grid_size, _))
+            | Method::Checkerboard(ref mut grid_size) => {
                 *grid_size = value;
             }
+            Method::Voronoi(ref mut cells, _) => {
+                *cells = value * value;
+            }
         };
     }
 
-    pub fn get_grid(&self, size: usize, use_margin: bool) -> Heightmap {
-        let grid_size = self.get_grid_size();
-        let heightmap = Heightmap::new_empty(size, size, 1.0, 1.0);
-        let (local_margin, margin) = if use_margin {
-            let max_margin = Self::max_margin(size, grid_size);
-            let local_margin = self.margin_size(size);
-            let (mr, mt, ml, mb) = max_margin;
-            let (lr, lt, ll, lb) = local_margin;
-            let margin = (mr - lr, mt - lt, ml - ll, mb - lb);
-            (local_margin, margin)
-        } else {
-            ((0, 0, 0, 0), (0, 0, 0, 0))
-        };
+    pub fn get_grid(&self, width: usize, height: usize, margin: MarginMode) -> Heightmap {
+        let heightmap = Heightmap::new_empty(width, height, 1.0, 1.0);
+        let (local_margin, margin) = self.margins(width, height, margin);
         let mut partition = heightmap.with_margin(margin);
         match self {
             Method::Default => {
@@ -140,87 +315,490 @@ impl Method {
             // Method::SubdivisionOverlap(grid_size) => {
             //     subdivision_overlap_grid(&mut partition.heightmap, *grid_size);
             // }
-            Method::GridOverlapBlend(grid_size) => {
+            Method::GridOverlapBlend((grid_size, _)) => {
                 grid_overlap_blend_grid(&mut partition.heightmap, *grid_size, *grid_size);
             }
+            Method::Checkerboard(grid_size) => {
+                checkerboard_grid(&mut partition.heightmap, *grid_size);
+            }
+            Method::Voronoi(cells, seed) => {
+                voronoi::paint_grid(&mut partition.heightmap, *cells, *seed);
+            }
         }
         partition.heightmap.with_margin(local_margin).heightmap
     }
 
-    pub fn erode_with_margin(
+    /// `get_grid` together with the overlap and margins of the partitions, in the space of the
+    /// `width` x `height` map rather than the one left after eroding. `halo` is the halo of the
+    /// partitions read around them, see `uses_halo`.
+    pub fn get_grid_layers(
         &self,
-        use_margin: bool,
-        heightmap: &Heightmap,
-        parameters: &Parameters,
-        drop_zone: &DropZone,
-    ) -> Heightmap {
-        print!("Eroding using ");
-        let grid_size = self.get_grid_size();
-        let heightmap_size = heightmap.width;
-        let (local_margin, margin) = if use_margin {
-            let max_margin = Self::max_margin(heightmap_size, grid_size);
-            let local_margin = self.margin_size(heightmap_size);
-            let (mr, mt, ml, mb) = max_margin;
-            let (lr, lt, ll, lb) = local_margin;
-            let margin = (mr - lr, mt - lt, ml - ll, mb - lb);
-            (local_margin, margin)
-        } else {
-            ((0, 0, 0, 0), (0, 0, 0, 0))
+        width: usize,
+        height: usize,
+        margin: MarginMode,
+        halo: usize,
+    ) -> GridLayers {
+        let cut = self.cut_margin(width, height, margin);
+        let (_, extra) = self.margins(width, height, margin);
+        let empty = Heightmap::new_empty(width, height, 1.0, 1.0);
+        let mut layers = GridLayers {
+            boundaries: empty.clone(),
+            overlap: empty.clone(),
+            margin: empty,
         };
-        let mut partition = heightmap.with_margin(margin);
+        let kept =
+            |x: usize, y: usize| cut.in_interior(Extent::new(width, height), UVector2::new(x, y));
+        for (x, column) in layers.margin.data.iter_mut().enumerate() {
+            for (y, cell) in column.iter_mut().enumerate() {
+                if !kept(x, y) {
+                    *cell = 1.0;
+                }
+            }
+        }
+        for (x, y, boundary) in self.get_grid(width, height, margin).iter_cells() {
+            layers.boundaries.data[x + cut.left][y + cut.top] = boundary;
+        }
+
+        // The overlapping grids cover the cells between the seams of the other grid twice
+        if let Method::GridOverlapBlend(_) = self {
+            let whole = extra.interior(Extent::new(width, height));
+            let mut coverage = vec![vec![0; whole.height]; whole.width];
+            for (anchor, cell) in self.partition_cells(&whole.into()) {
+                for column in coverage.iter_mut().skip(anchor.x).take(cell.x) {
+                    for count in column.iter_mut().skip(anchor.y).take(cell.y) {
+                        *count += 1;
+                    }
+                }
+            }
+            for (x, column) in coverage.iter().enumerate() {
+                for (y, &count) in column.iter().enumerate() {
+                    let (x, y) = (x + extra.left, y + extra.top);
+                    if count > 1 && kept(x, y) {
+                        layers.overlap.data[x][y] = 1.0;
+                    }
+                }
+            }
+        }
+        let radius = self.overlap_radius(halo);
+        if let Some(seams) = self.seams(width, height, margin).filter(|_| radius > 0) {
+            for (x, column) in dilate(&seams, radius).iter().enumerate() {
+                for (y, &overlap) in column.iter().enumerate() {
+                    if overlap {
+                        layers.overlap.data[x + cut.left][y + cut.top] = 1.0;
+                    }
+                }
+            }
+        }
+        layers
+    }
+
+    /// How far from the seams the partitions overlap, the halo they read around them and the
+    /// boundary blurred after eroding.
+    fn overlap_radius(&self, halo: usize) -> usize {
+        let halo = if self.uses_halo() { halo } else { 0 };
         match self {
-            Method::Default => {
-                println!("{} method (no partitioning)", Method::Default.to_string());
-                default_erode(&mut partition.heightmap, &parameters, &drop_zone);
+            Method::SubdivisionBlurBoundary((_, (_, thickness))) => halo.max(*thickness as usize),
+            _ => halo,
+        }
+    }
+
+    /// Cells along the boundaries between partitions of the heightmap returned by
+    /// `erode_with_margin`, `seams[x][y]`, `None` without partitions. These are the boundaries
+    /// painted by `get_grid` without the edges of the map.
+    pub fn seams(&self, width: usize, height: usize, margin: MarginMode) -> Option<Vec<Vec<bool>>> {
+        if let Method::Default = self {
+            return None;
+        }
+        let grid = self.get_grid(width, height, margin);
+        let mut edges = Heightmap::new_empty(grid.width, grid.height, 1.0, 1.0);
+        default_grid(&mut edges);
+        Some(
+            grid.data
+                .iter()
+                .zip(edges.data.iter())
+                .map(|(grid, edges)| {
+                    grid.iter()
+                        .zip(edges)
+                        .map(|(&boundary, &edge)| boundary > 0.0 && edge <= 0.0)
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// Outlines of the partitions as closed polylines in [0, 1] space of the heightmap returned
+    /// by `erode_with_margin`, the vector counterpart of `get_grid`.
+    pub fn grid_lines(
+        &self,
+        width: usize,
+        height: usize,
+        margin: MarginMode,
+    ) -> Vec<Vec<(f32, f32)>> {
+        let (local_margin, margin) = self.margins(width, height, margin);
+        let whole = UVector2 {
+            x: width - margin.horizontal(),
+            y: height - margin.vertical(),
+        };
+        let cells = match self {
+            // Without margins of its own, so only the whole map needs scaling
+            Method::Voronoi(cells, seed) => {
+                let sites = voronoi::sites(whole.x, whole.y, *cells, *seed);
+                return (0..sites.len())
+                    .map(|site| {
+                        voronoi::cell_polygon(whole.x, whole.y, &sites, site)
+                            .into_iter()
+                            .map(|(x, y)| (x / whole.x as f32, y / whole.y as f32))
+                            .collect()
+                    })
+                    .collect();
             }
+            _ => self.partition_cells(&whole),
+        };
+
+        let width = (whole.x - local_margin.horizontal()) as f32;
+        let height = (whole.y - local_margin.vertical()) as f32;
+        let point = |x: usize, y: usize| {
+            (
+                (x as f32 - local_margin.left as f32) / width,
+                (y as f32 - local_margin.top as f32) / height,
+            )
+        };
+        cells
+            .iter()
+            .map(|(anchor, cell)| {
+                let (x0, y0) = (anchor.x, anchor.y);
+                let (x1, y1) = (anchor.x + cell.x, anchor.y + cell.y);
+                vec![
+                    point(x0, y0),
+                    point(x1, y0),
+                    point(x1, y1),
+                    point(x0, y1),
+                    point(x0, y0),
+                ]
+            })
+            .collect()
+    }
+
+    /// Rectangles of the partitions, anchor and size, of a map of `whole` cells without the extra
+    /// margins, see `grid_lines`. Voronoi cells are not rectangles and give none.
+    fn partition_cells(&self, whole: &UVector2) -> Vec<(UVector2, UVector2)> {
+        let whole = *whole;
+        match self {
+            Method::Default => vec![(UVector2 { x: 0, y: 0 }, whole)],
+            Method::Subdivision(grid_size) | Method::SubdivisionBlurBoundary((grid_size, _)) => {
+                let cell = UVector2 {
+                    x: whole.x / grid_size,
+                    y: whole.y / grid_size,
+                };
+                let anchors = grid_anchors(
+                    &UVector2 { x: 0, y: 0 },
+                    &whole,
+                    &cell,
+                    &UVector2 {
+                        x: *grid_size,
+                        y: *grid_size,
+                    },
+                );
+                anchors.into_iter().flatten().map(|a| (a, cell)).collect()
+            }
+            Method::GridOverlapBlend((grid_size, _)) => {
+                let slices = grid_size + 1;
+                let cell = UVector2 {
+                    x: whole.x / slices,
+                    y: whole.y / slices,
+                };
+                let grid = grid_anchors(
+                    &UVector2 { x: 0, y: 0 },
+                    &whole,
+                    &cell,
+                    &UVector2 {
+                        x: slices,
+                        y: slices,
+                    },
+                );
+                let offset_grid = grid_anchors(
+                    &UVector2 {
+                        x: cell.x / 2,
+                        y: cell.y / 2,
+                    },
+                    &UVector2 {
+                        x: whole.x - cell.x / 2,
+                        y: whole.y - cell.y / 2,
+                    },
+                    &cell,
+                    &UVector2 {
+                        x: slices - 1,
+                        y: slices - 1,
+                    },
+                );
+                grid.into_iter()
+                    .chain(offset_grid)
+                    .flatten()
+                    .map(|a| (a, cell))
+                    .collect()
+            }
+            // The passes along one axis only repeat lines of these two
+            Method::Checkerboard(grid_size) => [(false, false), (true, true)]
+                .into_iter()
+                .flat_map(|shifted| checkerboard_cells(&whole, *grid_size, shifted))
+                .collect(),
+            Method::Voronoi(..) => Vec::new(),
+        }
+    }
+
+    /// Outline of the cells left inside the margins cut off by `erode_with_margin`, in [0, 1]
+    /// space of the map before they are cut off. Empty without margins.
+    pub fn margin_lines(
+        &self,
+        width: usize,
+        height: usize,
+        margin: MarginMode,
+    ) -> Vec<Vec<(f32, f32)>> {
+        let cut = self.cut_margin(width, height, margin);
+        if cut.is_zero() {
+            return Vec::new();
+        }
+        let (x0, y0) = (
+            cut.left as f32 / width as f32,
+            cut.top as f32 / height as f32,
+        );
+        let x1 = (width - cut.right) as f32 / width as f32;
+        let y1 = (height - cut.bottom) as f32 / height as f32;
+        vec![vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1), (x0, y0)]]
+    }
+
+    /// Whether the partitions can read a halo of their neighbours, see `DropZone::with_halo`. The
+    /// overlapping grids blend their seams instead.
+    pub fn uses_halo(&self) -> bool {
+        matches!(
+            self,
+            Method::Subdivision(_)
+                | Method::SubdivisionBlurBoundary(_)
+                | Method::Checkerboard(_)
+                | Method::Voronoi(..)
+        )
+    }
+
+    /// Records the partitioning method and its settings in the heightmap metadata.
+    pub fn add_metadata(&self, heightmap: &mut Heightmap, margin: MarginMode, adaptive: bool) {
+        heightmap.metadata_add("PARTITIONING_METHOD", self.to_string());
+        heightmap.metadata_add("PARTITIONING_GRID_SIZE", self.get_grid_size().to_string());
+        heightmap.metadata_add("PARTITIONING_MARGIN", margin.to_string());
+        heightmap.metadata_add("PARTITIONING_ADAPTIVE", adaptive.to_string());
+        if let Method::SubdivisionBlurBoundary((_, (sigma, thickness))) = self {
+            heightmap.metadata_add("PARTITIONING_BLUR_SIGMA", sigma.to_string());
+            heightmap.metadata_add("PARTITIONING_BLUR_THICKNESS", thickness.to_string());
+        }
+        if let Method::GridOverlapBlend((_, curve)) = self {
+            heightmap.metadata_add("PARTITIONING_BLEND_CURVE", curve.to_string());
+        }
+        if let Method::Voronoi(cells, seed) = self {
+            heightmap.metadata_add("PARTITIONING_VORONOI_CELLS", cells.to_string());
+            heightmap.metadata_add("PARTITIONING_VORONOI_SEED", seed.to_string());
+        }
+    }
+
+    /// Erodes `heightmap` in place with this partitioning, without any margins. With `adaptive`
+    /// the droplets are shared out by the roughness of each partition, see `partition_models`.
+    pub fn erode(
+        &self,
+        heightmap: &mut Heightmap,
+        model: &Model,
+        adaptive: bool,
+        drop_zone: &DropZone,
+        progress: &Progress,
+    ) -> ErosionOutputs {
+        // The whole map tiles when eroded with wrapping, the partitions below never wrap
+        heightmap.wrap = model.wraps();
+        let mut outputs = match self {
+            Method::Default => default_erode(heightmap, model, drop_zone, progress),
             Method::Subdivision(grid_size) => {
-                println!("{} method", Method::Subdivision(*grid_size).to_string());
-                subdivision_erode(&mut partition.heightmap, &parameters, *grid_size);
+                subdivision_erode(heightmap, model, adaptive, *grid_size, drop_zone, progress)
             }
             Method::SubdivisionBlurBoundary((grid_size, (sigma, thickness))) => {
-                println!(
-                    "{} method",
-                    Method::SubdivisionBlurBoundary((
-                        *grid_size,
-                        (GAUSSIAN_DEFAULT_SIGMA, GAUSSIAN_DEFAULT_BOUNDARY_THICKNESS)
-                    ))
-                    .to_string()
-                );
                 subdivision_blur_boundary_erode(
-                    &mut partition.heightmap,
-                    &parameters,
-                    *grid_size,
-                    *sigma,
-                    *thickness,
-                );
+                    heightmap, model, adaptive, *grid_size, *sigma, *thickness, drop_zone, progress,
+                )
             }
             // Method::SubdivisionOverlap(grid_size) => {
-            //     println!(
-            //         "{} method",
-            //         Method::SubdivisionOverlap(*grid_size).to_string()
-            //     );
-            //     subdivision_overlap_erode(&mut partition.heightmap, &parameters, *grid_size);
+            //     subdivision_overlap_erode(heightmap, &parameters, *grid_size);
             // }
-            Method::GridOverlapBlend(grid_size) => {
-                println!(
-                    "{} method",
-                    Method::GridOverlapBlend(*grid_size).to_string()
-                );
-                grid_overlap_blend_erode(
+            Method::GridOverlapBlend((grid_size, curve)) => grid_overlap_blend_erode(
+                heightmap, model, adaptive, *grid_size, *grid_size, *curve, drop_zone, progress,
+            ),
+            Method::Checkerboard(grid_size) => {
+                checkerboard_erode(heightmap, model, adaptive, *grid_size, drop_zone, progress)
+            }
+            Method::Voronoi(cells, seed) => voronoi::erode(
+                heightmap, model, adaptive, *cells, *seed, drop_zone, progress,
+            ),
+        };
+        if self.uses_halo() && drop_zone.get_halo() > 0 {
+            heightmap.metadata_add("PARTITIONING_HALO", drop_zone.get_halo().to_string());
+        }
+        // Waves work along the whole coast, so they run after the partitions are put together
+        if let Some(sea) = drop_zone.get_sea() {
+            let steps = sea.wave_steps(model.num_iterations());
+            let waves = erode::coastal::erode(heightmap, sea, steps, progress);
+            outputs.merge(&waves, &UVector2 { x: 0, y: 0 });
+        }
+        outputs
+    }
+
+    pub fn erode_with_margin(
+        &self,
+        margin: MarginMode,
+        adaptive: bool,
+        heightmap: &Heightmap,
+        model: &Model,
+        drop_zone: &DropZone,
+        progress: &Progress,
+    ) -> (Heightmap, ErosionOutputs) {
+        let (heightmap, outputs, _) =
+            self.erode_with_snapshots(margin, adaptive, heightmap, model, drop_zone, progress, 0);
+        (heightmap, outputs)
+    }
+
+    /// Like `erode_with_margin` but also returns the heightmap after every `interval`
+    /// iterations together with the iteration it was taken at, 0 disables snapshots.
+    #[allow(clippy::too_many_arguments)]
+    pub fn erode_with_snapshots(
+        &self,
+        margin_mode: MarginMode,
+        adaptive: bool,
+        heightmap: &Heightmap,
+        model: &Model,
+        drop_zone: &DropZone,
+        progress: &Progress,
+        interval: usize,
+    ) -> (Heightmap, ErosionOutputs, Vec<(usize, Heightmap)>) {
+        let (local_margin, margin) = self.margins(heightmap.width, heightmap.height, margin_mode);
+        let mut partition = heightmap.with_margin(margin);
+        let drop_zone = &drop_zone.crop(
+            &margin.anchor(),
+            partition.heightmap.width,
+            partition.heightmap.height,
+        );
+        let total = model.num_iterations();
+        let mut snapshots = Vec::new();
+        let outputs = if interval == 0 || interval >= total {
+            self.erode(
+                &mut partition.heightmap,
+                model,
+                adaptive,
+                drop_zone,
+                progress,
+            )
+        } else {
+            progress.add_total(total);
+            let chunk_progress = progress.with_own_total();
+            let mut outputs = ErosionOutputs::empty(&partition.heightmap);
+            let mut done = 0;
+            while done < total && !progress.is_cancelled() {
+                let n = interval.min(total - done);
+                let chunk = model.with_iterations(n);
+                let chunk_outputs = self.erode(
                     &mut partition.heightmap,
-                    &parameters,
-                    *grid_size,
-                    *grid_size,
+                    &chunk,
+                    adaptive,
+                    drop_zone,
+                    &chunk_progress,
                 );
+                outputs.merge(&chunk_outputs, &UVector2 { x: 0, y: 0 });
+                done += n;
+                if done < total {
+                    let mut snapshot = partition.heightmap.with_margin(local_margin).heightmap;
+                    snapshot.reconcile_layers();
+                    snapshots.push((done, snapshot));
+                }
+            }
+            outputs
+        };
+        // Partitions only record metadata on their own copies, so record it once for the whole map
+        model.add_metadata(&mut partition.heightmap);
+        self.add_metadata(&mut partition.heightmap, margin_mode, adaptive);
+        partition.heightmap.reconcile_layers();
+        (
+            partition.heightmap.with_margin(local_margin).heightmap,
+            outputs.with_margin(local_margin),
+            snapshots,
+        )
+    }
+
+    /// Runs every pass of `pipeline` in turn on the same heightmap, otherwise like
+    /// `erode_with_margin`. The margins are only cropped once, after the last pass.
+    pub fn erode_pipeline(
+        &self,
+        margin_mode: MarginMode,
+        adaptive: bool,
+        heightmap: &Heightmap,
+        pipeline: &ErosionPipeline,
+        drop_zone: &DropZone,
+        progress: &Progress,
+    ) -> (Heightmap, ErosionOutputs) {
+        let (local_margin, margin) = self.margins(heightmap.width, heightmap.height, margin_mode);
+        let mut partition = heightmap.with_margin(margin);
+        let drop_zone = &drop_zone.crop(
+            &margin.anchor(),
+            partition.heightmap.width,
+            partition.heightmap.height,
+        );
+        let mut outputs = ErosionOutputs::empty(&partition.heightmap);
+        for model in pipeline.passes.iter() {
+            if progress.is_cancelled() {
+                break;
+            }
+            let pass = self.erode(
+                &mut partition.heightmap,
+                model,
+                adaptive,
+                drop_zone,
+                progress,
+            );
+            outputs.merge(&pass, &UVector2 { x: 0, y: 0 });
+        }
+        pipeline.add_metadata(&mut partition.heightmap);
+        self.add_metadata(&mut partition.heightmap, margin_mode, adaptive);
+        partition.heightmap.reconcile_layers();
+        (
+            partition.heightmap.with_margin(local_margin).heightmap,
+            outputs.with_margin(local_margin),
+        )
+    }
+
+    /// Margins of this method and the extra margins that make up the ones cut off in total, see
+    /// `cut_margin`. The method erodes its own margins and they are cropped afterwards, the extra
+    /// ones are cropped before eroding.
+    fn margins(&self, width: usize, height: usize, margin: MarginMode) -> (Margins, Margins) {
+        let cut = self.cut_margin(width, height, margin);
+        let local_margin = self.margin_size(width, height).min(&cut);
+        (local_margin, cut - local_margin)
+    }
+
+    /// Cells cut off each side of a map of `width` x `height` by `erode_with_margin`. Custom
+    /// margins leave at least one cell along either axis.
+    pub fn cut_margin(&self, width: usize, height: usize, margin: MarginMode) -> Margins {
+        match margin {
+            MarginMode::None => Margins::default(),
+            MarginMode::Automatic => Self::max_margin(width, height, self.get_grid_size()),
+            MarginMode::Custom(margins) => {
+                let fit = |low: usize, high: usize, size: usize| {
+                    let low = low.min(size.saturating_sub(1));
+                    (low, high.min(size.saturating_sub(1) - low))
+                };
+                let (left, right) = fit(margins.left, margins.right, width);
+                let (top, bottom) = fit(margins.top, margins.bottom, height);
+                Margins::new(right, top, left, bottom)
             }
         }
-        partition.heightmap.with_margin(local_margin).heightmap
     }
 
-    pub fn margin_size(&self, heightmap_size: usize) -> (usize, usize, usize, usize) {
+    /// Margins along one axis of `heightmap_size` cells, the same on both sides.
+    fn axis_margin(&self, heightmap_size: usize) -> usize {
         let grid_size = self.get_grid_size();
-        let margins = match self {
-            Method::Default => (0, 0, 0, 0),
+        match self {
+            // Shifted passes and Voronoi cells cover the whole map
+            Method::Default | Method::Checkerboard(_) | Method::Voronoi(..) => 0,
             Method::Subdivision(_) | Method::SubdivisionBlurBoundary(_) => {
                 let grid_cell_size = heightmap_size / grid_size;
                 let rect_min = grid_cell_size / 2;
@@ -228,43 +806,51 @@ impl Method {
 
                 let total_size = grid_cell_size * (grid_size - 1);
                 let desired_size = rect_max - rect_min;
-                let align = (desired_size - total_size) / 2;
-
-                (align, align, align, align)
+                (desired_size - total_size) / 2
             }
             // Method::SubdivisionOverlap(_) |
             Method::GridOverlapBlend(_) => {
                 let grid_size = grid_size + 1;
                 let grid_cell_size = heightmap_size / grid_size;
                 let total_size = grid_cell_size * (grid_size - 1);
-                let align = (heightmap_size - total_size) / 2;
-
-                (align, align, align, align)
+                (heightmap_size - total_size) / 2
             }
-        };
-        let (right, top, left, bottom) = margins;
-        (right, top, left, bottom)
-    }
-
-    pub fn max_margin(heightmap_size: usize, grid_size: usize) -> (usize, usize, usize, usize) {
-        let mut largest_margin_r = 0;
-        let mut largest_margin_t = 0;
-        let mut largest_margin_l = 0;
-        let mut largest_margin_b = 0;
-        for &m in Self::list(grid_size).iter() {
-            let (r, t, l, b) = m.margin_size(heightmap_size);
-            largest_margin_r = largest_margin_r.max(r);
-            largest_margin_t = largest_margin_t.max(t);
-            largest_margin_l = largest_margin_l.max(l);
-            largest_margin_b = largest_margin_b.max(b);
         }
-        (
-            largest_margin_r,
-            largest_margin_t,
-            largest_margin_l,
-            largest_margin_b,
-        )
     }
+
+    pub fn margin_size(&self, width: usize, height: usize) -> Margins {
+        Margins::symmetric(self.axis_margin(width), self.axis_margin(height))
+    }
+
+    /// The largest margin of any method on each side.
+    pub fn max_margin(width: usize, height: usize, grid_size: usize) -> Margins {
+        Self::list(grid_size)
+            .iter()
+            .fold(Margins::default(), |largest, m| {
+                largest.max(&m.margin_size(width, height))
+            })
+    }
+}
+
+/// `cells[x][y]` grown by `radius` cells along both axes.
+fn dilate(cells: &[Vec<bool>], radius: usize) -> Vec<Vec<bool>> {
+    let width = cells.len();
+    let height = cells.first().map_or(0, |column| column.len());
+    let window = |i: usize, size: usize| i.saturating_sub(radius)..(i + radius + 1).min(size);
+    let rows: Vec<Vec<bool>> = (0..width)
+        .map(|x| {
+            (0..height)
+                .map(|y| window(x, width).any(|wx| cells[wx][y]))
+                .collect()
+        })
+        .collect();
+    rows.iter()
+        .map(|column| {
+            (0..height)
+                .map(|y| window(y, height).any(|wy| column[wy]))
+                .collect()
+        })
+        .collect()
 }
 
 fn default_grid(heightmap: &mut Heightmap) {
@@ -365,9 +951,71 @@ fn grid_overlap_blend_grid(heightmap: &mut Heightmap, grid_size_x: usize, grid_s
     paint_grid_border(&subgrid, heightmap);
 }
 
+/// Start and length of the slices of an axis of `size` cells, `shifted` by half a slice so there
+/// is one more with halves at the ends. The last slice takes up what is left over.
+fn axis_slices(size: usize, slices: usize, shifted: bool) -> Vec<(usize, usize)> {
+    let slice = (size / slices.max(1)).max(1);
+    let mut starts: Vec<usize> = (0..slices)
+        .map(|i| i * slice + if shifted { slice / 2 } else { 0 })
+        .collect();
+    if shifted {
+        starts.insert(0, 0);
+    }
+    starts.retain(|&start| start < size);
+    starts.dedup();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| (start, starts.get(i + 1).copied().unwrap_or(size) - start))
+        .collect()
+}
+
+/// Anchors and sizes of the partitions of one checkerboard pass over a map of `size`.
+fn checkerboard_cells(
+    size: &UVector2,
+    grid_size: usize,
+    shifted: (bool, bool),
+) -> Vec<(UVector2, UVector2)> {
+    let columns = axis_slices(size.x, grid_size, shifted.0);
+    let rows = axis_slices(size.y, grid_size, shifted.1);
+    columns
+        .iter()
+        .flat_map(|&(x, width)| {
+            rows.iter().map(move |&(y, height)| {
+                (
+                    UVector2 { x, y },
+                    UVector2 {
+                        x: width,
+                        y: height,
+                    },
+                )
+            })
+        })
+        .collect()
+}
+
+fn checkerboard_grid(heightmap: &mut Heightmap, grid_size: usize) {
+    let size = UVector2 {
+        x: heightmap.width,
+        y: heightmap.height,
+    };
+    for shifted in [(false, false), (true, true)] {
+        let grid = vec![checkerboard_cells(&size, grid_size, shifted)
+            .iter()
+            .map(|(anchor, cell)| {
+                Arc::new(Mutex::new(heightmap::PartialHeightmap::from(
+                    heightmap, anchor, cell,
+                )))
+            })
+            .collect()];
+        paint_grid_border(&grid, heightmap);
+    }
+}
+
 fn subdivide(
     heightmap: &heightmap::Heightmap,
     grid_size: usize,
+    halo: usize,
 ) -> Vec<Arc<Mutex<heightmap::PartialHeightmap>>> {
     let slice_amount = grid_size;
     let slices = UVector2 {
@@ -385,8 +1033,8 @@ fn subdivide(
                 x: x * size.x,
                 y: y * size.y,
             };
-            let partition = Arc::new(Mutex::new(heightmap::PartialHeightmap::from(
-                &heightmap, &anchor, &size,
+            let partition = Arc::new(Mutex::new(heightmap::PartialHeightmap::with_halo(
+                heightmap, &anchor, &size, halo,
             )));
             partitions.push(partition);
         }
@@ -421,60 +1069,206 @@ fn subdivide(
 //     partitions
 // }
 
+/// One model per partition with the budget of `model` shared out between them. Rough partitions
+/// get more droplets than flat ones when `adaptive`, otherwise every partition gets the same.
+pub fn partition_models(
+    model: &Model,
+    roughness: &[HeightmapPrecision],
+    adaptive: bool,
+) -> Vec<Model> {
+    let even = model.divide_iterations(roughness.len());
+    let total: HeightmapPrecision = roughness.iter().sum();
+    if !adaptive || !model.splits_iterations() || total <= 0.0 {
+        return vec![even; roughness.len()];
+    }
+    let budget = even.num_iterations() * roughness.len();
+    // Rounding the running sum instead of every share keeps the budget exact
+    let mut assigned = 0;
+    let mut cumulative = 0.0;
+    roughness
+        .iter()
+        .map(|r| {
+            cumulative += r;
+            let end = ((cumulative / total * budget as f32).round() as usize).min(budget);
+            let iterations = end.saturating_sub(assigned);
+            assigned = end;
+            model.with_iterations(iterations)
+        })
+        .collect()
+}
+
+fn roughness_of(partitions: &[Arc<Mutex<heightmap::PartialHeightmap>>]) -> Vec<HeightmapPrecision> {
+    partitions
+        .par_iter()
+        .map(|partition| partition.lock().unwrap().heightmap.roughness())
+        .collect()
+}
+
+fn total_iterations(models: &[Model]) -> usize {
+    models.iter().map(|model| model.num_iterations()).sum()
+}
+
+/// Erodes a single partition, or the whole map, and records how long it took in the outputs.
+fn erode_partition(
+    model: &Model,
+    heightmap: &mut heightmap::Heightmap,
+    drop_zone: &erode::DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let time = Instant::now();
+    let mut outputs = model.erode(heightmap, drop_zone, progress);
+    outputs.partitions = vec![PartitionReport {
+        anchor: UVector2::new(0, 0),
+        size: UVector2::new(heightmap.width, heightmap.height),
+        iterations: model.num_iterations(),
+        seconds: time.elapsed().as_secs_f32(),
+    }];
+    outputs
+}
+
 fn erode_multiple(
     heightmaps: &Vec<Arc<Mutex<heightmap::PartialHeightmap>>>,
-    params: erode::Parameters,
+    models: &[Model],
     heightmap: &mut heightmap::Heightmap,
-) {
-    heightmaps.par_iter().for_each(|partition| {
-        let heightmap = &mut partition.lock().unwrap().heightmap;
-        let drop_zone = erode::DropZone::default(heightmap);
-        erode::erode(heightmap, &params, &drop_zone);
-    });
+    drop_zone: &erode::DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let partial_outputs: Vec<(UVector2, ErosionOutputs)> = heightmaps
+        .par_iter()
+        .zip(models.par_iter())
+        .map(|(partition, model)| {
+            let mut partition = partition.lock().unwrap();
+            let (anchor, halo) = (partition.anchor, partition.halo);
+            let heightmap = &mut partition.heightmap;
+            let drop_zone = drop_zone
+                .crop(&anchor, heightmap.width, heightmap.height)
+                .with_border(halo);
+            let outputs = erode_partition(model, heightmap, &drop_zone, progress);
+            // The halo is not written back, so neither is what happened there
+            (anchor + halo.anchor(), outputs.with_margin(halo))
+        })
+        .collect();
 
     for partition in heightmaps {
         partition.lock().unwrap().apply_to(heightmap);
     }
+
+    let mut outputs = ErosionOutputs::empty(heightmap);
+    for (anchor, partial) in partial_outputs.iter() {
+        outputs.merge(partial, anchor);
+    }
+    outputs
 }
 
 pub fn default_erode(
     heightmap: &mut heightmap::Heightmap,
-    params: &erode::Parameters,
+    model: &erode::Model,
     drop_zone: &erode::DropZone,
-) {
-    erode::erode(heightmap, &params, drop_zone);
+    progress: &Progress,
+) -> ErosionOutputs {
+    progress.add_total(model.num_iterations());
+    erode_partition(model, heightmap, drop_zone, progress)
 }
 
 pub fn subdivision_erode(
     heightmap: &mut heightmap::Heightmap,
-    params: &erode::Parameters,
+    model: &erode::Model,
+    adaptive: bool,
     grid_size: usize,
-) {
-    let partitions = subdivide(heightmap, grid_size);
+    drop_zone: &erode::DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let partitions = subdivide(heightmap, grid_size, drop_zone.get_halo());
+
+    let models = partition_models(model, &roughness_of(&partitions), adaptive);
+    progress.add_total(total_iterations(&models));
+
+    erode_multiple(&partitions, &models, heightmap, drop_zone, progress)
+}
 
-    let mut params = params.clone();
-    params.num_iterations /= partitions.len();
+/// Erodes the grid four times with a quarter of the droplets each, the second and third pass
+/// shifted by half a cell along one axis and the last along both. Every seam of a pass runs
+/// through the middle of the partitions of another, which erodes it away without any blurring.
+pub fn checkerboard_erode(
+    heightmap: &mut heightmap::Heightmap,
+    model: &erode::Model,
+    adaptive: bool,
+    grid_size: usize,
+    drop_zone: &erode::DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let passes = [(false, false), (true, false), (false, true), (true, true)];
+    let pass_model = model.divide_iterations(passes.len());
+    let size = UVector2 {
+        x: heightmap.width,
+        y: heightmap.height,
+    };
+    // Every pass is counted up front so the progress does not jump back between them
+    for shifted in passes {
+        let cells = checkerboard_cells(&size, grid_size, shifted).len();
+        progress.add_total(pass_model.divide_iterations(cells).num_iterations() * cells);
+    }
 
-    erode_multiple(&partitions, params, heightmap);
+    let mut outputs = ErosionOutputs::empty(heightmap);
+    for shifted in passes {
+        let partitions: Vec<_> = checkerboard_cells(&size, grid_size, shifted)
+            .iter()
+            .map(|(anchor, cell)| {
+                Arc::new(Mutex::new(heightmap::PartialHeightmap::with_halo(
+                    heightmap,
+                    anchor,
+                    cell,
+                    drop_zone.get_halo(),
+                )))
+            })
+            .collect();
+        // The halves at the edges get half the droplets of a whole partition
+        let weights: Vec<HeightmapPrecision> = partitions
+            .iter()
+            .zip(roughness_of(&partitions))
+            .map(|(partition, roughness)| {
+                let interior = partition.lock().unwrap().interior();
+                let area = (interior.x * interior.y) as HeightmapPrecision;
+                if adaptive {
+                    area * roughness
+                } else {
+                    area
+                }
+            })
+            .collect();
+        let models = partition_models(&pass_model, &weights, true);
+        let pass = erode_multiple(&partitions, &models, heightmap, drop_zone, progress);
+        outputs.merge(&pass, &UVector2 { x: 0, y: 0 });
+    }
+    outputs
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn subdivision_blur_boundary_erode(
     heightmap: &mut heightmap::Heightmap,
-    params: &erode::Parameters,
+    model: &erode::Model,
+    adaptive: bool,
     grid_size: usize,
     sigma: f32,
     thickness: u16,
-) {
-    subdivision_erode(heightmap, params, grid_size);
+    drop_zone: &erode::DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let outputs = subdivision_erode(heightmap, model, adaptive, grid_size, drop_zone, progress);
     let blurred = heightmap.blur(sigma).unwrap();
-    let size = heightmap.width;
+    let chunk_width = (heightmap.width / grid_size) as i32;
+    let chunk_height = (heightmap.height / grid_size) as i32;
     let mask = heightmap::create_heightmap_from_closure(
         heightmap.width,
+        heightmap.height,
         1.0,
         &|x, y| -> HeightmapPrecision {
-            let chunk = (size / grid_size) as i32;
-            let dx = (chunk - x as i32 % chunk).abs().min(x as i32 % chunk);
-            let dy = (chunk - y as i32 % chunk).abs().min(y as i32 % chunk);
+            let dx = (chunk_width - x as i32 % chunk_width)
+                .abs()
+                .min(x as i32 % chunk_width);
+            let dy = (chunk_height - y as i32 % chunk_height)
+                .abs()
+                .min(y as i32 % chunk_height);
             let d = dx.min(dy);
             if d >= thickness as i32 {
                 0.0
@@ -486,6 +1280,7 @@ pub fn subdivision_blur_boundary_erode(
     heightmap
         .overlay(&blurred, &mask)
         .expect("Subdivision Blur Boundary Erode failed.");
+    outputs
 }
 
 // pub fn subdivision_overlap_erode(
@@ -528,7 +1323,27 @@ fn get_grid(
     grid_size: &UVector2,
     grid_cells: &UVector2,
 ) -> Vec<Vec<Arc<Mutex<heightmap::PartialHeightmap>>>> {
-    let mut grid = Vec::new();
+    grid_anchors(rect_min, rect_max, grid_size, grid_cells)
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|anchor| {
+                    Arc::new(Mutex::new(heightmap::PartialHeightmap::from(
+                        &heightmap, anchor, grid_size,
+                    )))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Top left corners of `grid_cells` cells of `grid_size`, centred inside the rect.
+fn grid_anchors(
+    rect_min: &UVector2,
+    rect_max: &UVector2,
+    grid_size: &UVector2,
+    grid_cells: &UVector2,
+) -> Vec<Vec<UVector2>> {
     let slice_width = grid_size.x;
     let slice_height = grid_size.y;
 
@@ -539,44 +1354,53 @@ fn get_grid(
     let x_align = (desired_width - total_width) / 2;
     let y_align = (desired_height - total_height) / 2;
 
-    for x in 0..grid_cells.x {
-        let mut row = Vec::new();
-        for y in 0..grid_cells.y {
-            let anchor = UVector2 {
-                x: x * slice_width + rect_min.x + x_align,
-                y: y * slice_height + rect_min.y + y_align,
-            };
-            let size = UVector2 {
-                x: slice_width,
-                y: slice_height,
-            };
-            let partition = Arc::new(Mutex::new(heightmap::PartialHeightmap::from(
-                &heightmap, &anchor, &size,
-            )));
-            row.push(partition);
-        }
-        grid.push(row);
-    }
-    grid
+    (0..grid_cells.x)
+        .map(|x| {
+            (0..grid_cells.y)
+                .map(|y| UVector2 {
+                    x: x * slice_width + rect_min.x + x_align,
+                    y: y * slice_height + rect_min.y + y_align,
+                })
+                .collect()
+        })
+        .collect()
 }
 
 fn erode_grid(
     grid: &Vec<Vec<Arc<Mutex<heightmap::PartialHeightmap>>>>,
-    params: &erode::Parameters,
-) {
-    let mut params = params.clone();
+    model: &erode::Model,
+    adaptive: bool,
+    drop_zone: &erode::DropZone,
+    progress: &Progress,
+) -> Vec<(UVector2, ErosionOutputs)> {
     let grid_width = grid.len();
     let grid_height = grid[0].len();
-    params.num_iterations /= grid_width * grid_height;
-
-    (0..grid_width).for_each(|x| {
-        (0..grid_height).into_par_iter().for_each(|y| {
-            let partition = Arc::clone(&grid[x][y]);
-            let heightmap = &mut partition.lock().unwrap().heightmap;
-            let drop_zone = erode::DropZone::default(heightmap);
-            erode::erode(heightmap, &params, &drop_zone);
-        });
-    });
+    let roughness: Vec<HeightmapPrecision> = grid
+        .iter()
+        .flat_map(|column| roughness_of(column))
+        .collect();
+    let models = partition_models(model, &roughness, adaptive);
+
+    (0..grid_width)
+        .flat_map(|x| {
+            let models = &models;
+            (0..grid_height)
+                .into_par_iter()
+                .map(move |y| {
+                    let model = models[x * grid_height + y];
+                    let partition = Arc::clone(&grid[x][y]);
+                    let mut partition = partition.lock().unwrap();
+                    let anchor = partition.anchor;
+                    let heightmap = &mut partition.heightmap;
+                    let drop_zone = drop_zone.crop(&anchor, heightmap.width, heightmap.height);
+                    (
+                        anchor,
+                        erode_partition(&model, heightmap, &drop_zone, progress),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
 }
 
 fn blend_cells(
@@ -585,6 +1409,7 @@ fn blend_cells(
     tr: Arc<Mutex<heightmap::PartialHeightmap>>,
     bl: Arc<Mutex<heightmap::PartialHeightmap>>,
     br: Arc<Mutex<heightmap::PartialHeightmap>>,
+    curve: BlendCurve,
 ) {
     let mut center = center.lock().unwrap();
     let tl = tl.lock().unwrap();
@@ -592,18 +1417,23 @@ fn blend_cells(
     let bl = bl.lock().unwrap();
     let br = br.lock().unwrap();
 
-    tl.blend_apply_to(&mut center);
-    tr.blend_apply_to(&mut center);
-    bl.blend_apply_to(&mut center);
-    br.blend_apply_to(&mut center);
+    tl.blend_apply_to(&mut center, curve);
+    tr.blend_apply_to(&mut center, curve);
+    bl.blend_apply_to(&mut center, curve);
+    br.blend_apply_to(&mut center, curve);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn grid_overlap_blend_erode(
     heightmap: &mut heightmap::Heightmap,
-    params: &erode::Parameters,
+    model: &erode::Model,
+    adaptive: bool,
     grid_x_slices: usize,
     grid_y_slices: usize,
-) {
+    curve: BlendCurve,
+    drop_zone: &erode::DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
     let grid_x_slices = grid_x_slices + 1;
     let grid_y_slices = grid_y_slices + 1;
 
@@ -646,8 +1476,20 @@ pub fn grid_overlap_blend_erode(
         },
     );
 
-    erode_grid(&grid, params);
-    erode_grid(&offset_grid, params);
+    // Both grids are counted up front so the progress does not jump back between them
+    for cells in [
+        grid_x_slices * grid_y_slices,
+        (grid_x_slices - 1) * (grid_y_slices - 1),
+    ] {
+        progress.add_total(model.divide_iterations(cells).num_iterations() * cells);
+    }
+    let mut outputs = ErosionOutputs::empty(heightmap);
+    for (anchor, partial) in erode_grid(&grid, model, adaptive, drop_zone, progress)
+        .iter()
+        .chain(erode_grid(&offset_grid, model, adaptive, drop_zone, progress).iter())
+    {
+        outputs.merge(partial, anchor);
+    }
 
     for i in 0..=1 {
         for j in 0..=1 {
@@ -661,7 +1503,7 @@ pub fn grid_overlap_blend_erode(
                         let tr = Arc::clone(&grid[x + 1][y]);
                         let bl = Arc::clone(&grid[x][y + 1]);
                         let br = Arc::clone(&grid[x + 1][y + 1]);
-                        blend_cells(center, tl, tr, bl, br);
+                        blend_cells(center, tl, tr, bl, br, curve);
                     });
             });
         }
@@ -672,4 +1514,377 @@ pub fn grid_overlap_blend_erode(
             partition.lock().unwrap().apply_to(heightmap);
         }
     }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        check_heightmap, tiny_heightmap, tiny_model, tiny_rectangular_heightmap, Check, GRID_SIZE,
+        ITERATIONS, SIZE,
+    };
+
+    #[test]
+    fn adaptive_droplets() -> Check {
+        let model = Model::Lague(crate::erode::Parameters {
+            num_iterations: ITERATIONS,
+            ..Default::default()
+        });
+        let models = partition_models(&model, &[0.0, 0.1, 0.3], true);
+        let iterations: Vec<usize> = models.iter().map(|m| m.num_iterations()).collect();
+        if iterations.iter().sum::<usize>() != model.divide_iterations(3).num_iterations() * 3 {
+            return Err(format!("{:?} does not add up to the budget", iterations));
+        }
+        if iterations[0] != 0 || iterations[1] >= iterations[2] {
+            return Err(format!("{:?} does not follow the roughness", iterations));
+        }
+        let heightmap = tiny_rectangular_heightmap();
+        let (eroded, _) = Method::Subdivision(GRID_SIZE).erode_with_margin(
+            MarginMode::None,
+            true,
+            &heightmap,
+            &model,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE, SIZE * 2)
+    }
+
+    #[test]
+    fn voronoi_cells() -> Check {
+        let (width, height) = (SIZE, SIZE * 2);
+        let cells = 7;
+        let sites = voronoi::sites(width, height, cells, 3);
+        if sites.len() != cells {
+            return Err(format!("{} sites for {} cells", sites.len(), cells));
+        }
+        if sites != voronoi::sites(width, height, cells, 3) {
+            return Err("the same seed gave other sites".to_string());
+        }
+        if sites == voronoi::sites(width, height, cells, 4) {
+            return Err("another seed gave the same sites".to_string());
+        }
+
+        // The cells tile the map, so their areas add up to it
+        let area: f32 = (0..cells)
+            .map(|site| {
+                let polygon = voronoi::cell_polygon(width, height, &sites, site);
+                polygon
+                    .windows(2)
+                    .map(|edge| edge[0].0 * edge[1].1 - edge[1].0 * edge[0].1)
+                    .sum::<f32>()
+                    / 2.0
+            })
+            .sum();
+        if (area - (width * height) as f32).abs() > 0.01 {
+            return Err(format!(
+                "cells cover {} of the {} map",
+                area,
+                width * height
+            ));
+        }
+
+        let method = Method::Voronoi(cells, 3);
+        if method.grid_lines(width, height, MarginMode::None).len() != cells {
+            return Err("not every cell is outlined".to_string());
+        }
+        let grid = method.get_grid(width, height, MarginMode::None);
+        let painted = grid.data.iter().flatten().filter(|&&v| v > 0.0).count();
+        if painted == 0 || painted == width * height {
+            return Err("cell boundaries were not painted".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn checkerboard_passes() -> Check {
+        let heightmap = tiny_heightmap();
+        let model = tiny_model();
+        let progress = Progress::default();
+        let mut eroded = heightmap.clone();
+        let outputs = Method::Checkerboard(GRID_SIZE).erode(
+            &mut eroded,
+            &model,
+            false,
+            &DropZone::default(&heightmap),
+            &progress,
+        );
+        check_heightmap(&eroded, SIZE, SIZE)?;
+        // The shifted passes have one more partition along the shifted axis
+        let unshifted = GRID_SIZE;
+        let shifted = GRID_SIZE + 1;
+        let expected = unshifted * unshifted + 2 * shifted * unshifted + shifted * shifted;
+        if outputs.partitions.len() != expected {
+            return Err(format!(
+                "eroded {} partitions instead of {}",
+                outputs.partitions.len(),
+                expected
+            ));
+        }
+        if progress.fraction() != 1.0 {
+            return Err(format!(
+                "progress ended at {} instead of 1",
+                progress.fraction()
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn blend_curves() -> Check {
+        for curve in BlendCurve::list()
+            .into_iter()
+            .chain([BlendCurve::Power(0.5), BlendCurve::Power(6.5)])
+        {
+            if curve.apply(0.0) != 0.0 || (curve.apply(1.0) - 1.0).abs() > 1e-6 {
+                return Err(format!("{} does not go from 0 to 1", curve));
+            }
+            let samples: Vec<f32> = (0..=20).map(|i| curve.apply(i as f32 / 20.0)).collect();
+            if samples.windows(2).any(|pair| pair[1] < pair[0]) {
+                return Err(format!("{} is not increasing", curve));
+            }
+        }
+        // The default keeps the falloff the overlapping grids always had
+        let t = 0.3f32;
+        if BlendCurve::default().apply(t) != t.powf(1.5) {
+            return Err("the default curve changed".to_string());
+        }
+
+        let heightmap = tiny_heightmap();
+        let model = tiny_model();
+        for curve in BlendCurve::list() {
+            let mut eroded = heightmap.clone();
+            Method::GridOverlapBlend((GRID_SIZE, curve)).erode(
+                &mut eroded,
+                &model,
+                false,
+                &DropZone::default(&heightmap),
+                &Progress::default(),
+            );
+            check_heightmap(&eroded, SIZE, SIZE).map_err(|e| format!("{}: {}", curve, e))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn partition_budgets() -> Check {
+        let heightmap = tiny_heightmap();
+        let model = tiny_model();
+        let (eroded, outputs) = Method::Subdivision(GRID_SIZE).erode_with_margin(
+            MarginMode::Automatic,
+            false,
+            &heightmap,
+            &model,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        let partitions = &outputs.partitions;
+        if partitions.len() != GRID_SIZE * GRID_SIZE {
+            return Err(format!("{} partitions were reported", partitions.len()));
+        }
+        let iterations: usize = partitions.iter().map(|p| p.iterations).sum();
+        if iterations != model.num_iterations() {
+            return Err(format!(
+                "partitions got {} of {} iterations",
+                iterations,
+                model.num_iterations()
+            ));
+        }
+        // Cropped to the map without its margin, which the partitions still cover whole
+        let area: usize = partitions.iter().map(|p| p.size.x * p.size.y).sum();
+        if area != eroded.width * eroded.height {
+            return Err(format!(
+                "partitions cover {} of the {} cells",
+                area,
+                eroded.width * eroded.height
+            ));
+        }
+        if partitions
+            .iter()
+            .any(|p| p.anchor.x + p.size.x > eroded.width || p.anchor.y + p.size.y > eroded.height)
+        {
+            return Err("a partition reaches past the map".to_string());
+        }
+
+        // The same partitions eroded twice are reported once
+        let twice: Vec<PartitionReport> = partitions.iter().chain(partitions).copied().collect();
+        let combined = PartitionReport::combine(&twice);
+        if combined.len() != partitions.len()
+            || combined[0].iterations != 2 * partitions[0].iterations
+        {
+            return Err("repeated partitions were not added up".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn partition_halo() -> Check {
+        use crate::math::{Margins, UVector2, Vector2};
+        let heightmap = tiny_heightmap();
+        let (anchor, size) = (
+            UVector2::new(SIZE / 2, 0),
+            UVector2::new(SIZE / 2, SIZE / 2),
+        );
+        let mut partition = heightmap::PartialHeightmap::with_halo(&heightmap, &anchor, &size, 4);
+        // Cut off by the map on the right and top
+        if partition.halo != Margins::new(0, 0, 4, 4) || partition.interior() != size {
+            return Err(format!(
+                "halo {:?} around {:?}",
+                partition.halo,
+                partition.interior()
+            ));
+        }
+        for column in partition.heightmap.data.iter_mut() {
+            column.fill(0.5);
+        }
+        let mut applied = heightmap.clone();
+        partition.apply_to(&mut applied);
+        for (x, y, h) in applied.iter_cells() {
+            let inside = (anchor.x..anchor.x + size.x).contains(&x) && y < size.y;
+            let expected = if inside { 0.5 } else { heightmap.data[x][y] };
+            if h != expected {
+                return Err(format!("cell {}, {} was written back wrongly", x, y));
+            }
+        }
+
+        let drop_zone = DropZone::default(&heightmap).with_border(Margins::new(0, 0, 4, 4));
+        let rng = &mut rand::thread_rng();
+        if drop_zone.validate(&heightmap, &Vector2::new(2.0, 2.0), rng)
+            || !drop_zone.validate(&heightmap, &Vector2::new(6.0, 2.0), rng)
+        {
+            return Err("drops spawn in the halo".to_string());
+        }
+
+        // Every droplet spawns inside its partition, so none are lost with the halo
+        let model = tiny_model();
+        let mut eroded = heightmap.clone();
+        let outputs = Method::Subdivision(GRID_SIZE).erode(
+            &mut eroded,
+            &model,
+            false,
+            &DropZone::default(&heightmap).with_halo(4),
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE, SIZE)?;
+        let spawns: f32 = outputs
+            .spawn_map()
+            .map(|map| map.data.iter().flatten().sum())
+            .unwrap_or(0.0);
+        if spawns != model.num_iterations() as f32 {
+            return Err(format!(
+                "{} of {} droplets spawned inside the partitions",
+                spawns,
+                model.num_iterations()
+            ));
+        }
+        let area: usize = outputs.partitions.iter().map(|p| p.size.x * p.size.y).sum();
+        if area != SIZE * SIZE {
+            return Err(format!("partitions cover {} cells", area));
+        }
+        let recorded = eroded
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("PARTITIONING_HALO").cloned());
+        if recorded.as_deref() != Some("4") {
+            return Err(format!("halo recorded as {:?}", recorded));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn custom_margins() -> Check {
+        use crate::math::Margins;
+        let heightmap = tiny_rectangular_heightmap();
+        let model = tiny_model();
+        let method = Method::Subdivision(GRID_SIZE);
+        let margins = Margins::new(0, 1, 3, 2);
+        let (eroded, _) = method.erode_with_margin(
+            MarginMode::Custom(margins),
+            false,
+            &heightmap,
+            &model,
+            &DropZone::default(&heightmap),
+            &Progress::default(),
+        );
+        check_heightmap(&eroded, SIZE - 3, SIZE * 2 - 3)?;
+        let recorded = eroded
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("PARTITIONING_MARGIN").cloned());
+        if recorded != Some(margins.to_string()) {
+            return Err(format!("recorded margins {:?}", recorded));
+        }
+        let automatic = Method::max_margin(SIZE, SIZE * 2, GRID_SIZE);
+        if method.cut_margin(SIZE, SIZE * 2, MarginMode::Automatic) != automatic
+            || !method
+                .cut_margin(SIZE, SIZE * 2, MarginMode::None)
+                .is_zero()
+        {
+            return Err("cut other margins than before".to_string());
+        }
+        let cut = method.cut_margin(SIZE, SIZE, MarginMode::Custom(Margins::symmetric(SIZE, 0)));
+        if cut.horizontal() != SIZE - 1 {
+            return Err(format!("margins of {} leave no cells", cut));
+        }
+
+        // The outline of the margins starts at the top left cell that is kept
+        let lines = method.margin_lines(SIZE, SIZE * 2, MarginMode::Custom(margins));
+        let expected = (3.0 / SIZE as f32, 1.0 / (SIZE * 2) as f32);
+        if lines.len() != 1 || lines[0].first() != Some(&expected) {
+            return Err(format!("outlined the margins as {:?}", lines));
+        }
+        if !method.margin_lines(SIZE, SIZE, MarginMode::None).is_empty() {
+            return Err("outlined margins that are not used".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn seam_energy() -> Check {
+        use crate::heightmap::analysis;
+        if Method::Default
+            .seams(SIZE, SIZE, MarginMode::None)
+            .is_some()
+        {
+            return Err("found seams without partitions".to_string());
+        }
+        let method = Method::Subdivision(GRID_SIZE);
+        let seams = match method.seams(SIZE, SIZE, MarginMode::None) {
+            Some(seams) => seams,
+            None => return Err("found no seams between the partitions".to_string()),
+        };
+        let half = SIZE / 2;
+        if !seams[half][half / 2] || seams[0][half / 2] {
+            return Err("seams do not run between the partitions".to_string());
+        }
+
+        // A bowl, creased where the partitions meet in the second map
+        let bowl = |x: usize, y: usize| {
+            0.3 + 0.0002 * ((x as f32 - 10.0).powi(2) + (y as f32 - 12.0).powi(2))
+        };
+        let mut smooth = tiny_heightmap();
+        let mut creased = tiny_heightmap();
+        for (x, y, _) in tiny_heightmap().iter_cells() {
+            smooth.data[x][y] = bowl(x, y);
+            creased.data[x][y] = bowl(x, y) + 0.01 * (x.abs_diff(half) + y.abs_diff(half)) as f32;
+        }
+        let measure = |heightmap: &Heightmap| {
+            analysis::seam_metrics(heightmap, &seams).ok_or("nothing was measured".to_string())
+        };
+        let (smooth, creased) = (measure(&smooth)?, measure(&creased)?);
+        if (smooth.ratio() - 1.0).abs() > 0.01 {
+            return Err(format!(
+                "seams of a smooth map stand out by {}",
+                smooth.ratio()
+            ));
+        }
+        if creased.ratio() < 10.0 {
+            return Err(format!(
+                "a crease along the seams only stands out by {}",
+                creased.ratio()
+            ));
+        }
+        Ok(())
+    }
 }
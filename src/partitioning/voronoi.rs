@@ -0,0 +1,258 @@
+use crate::erode::{DropZone, ErosionOutputs, Model, Progress};
+use crate::heightmap::{Heightmap, HeightmapPrecision, PartialHeightmap};
+use crate::math::{UVector2, Vector2};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::sync::{Arc, Mutex};
+
+/*
+Voronoi partitioning cuts the map into irregular cells around jittered sites, one partition per
+cell, so the seams between partitions wander instead of running along the axes where they catch
+the eye. The sites are spread over rows, each with a random offset in its slot of the row, which
+keeps the cells about the same size, and the same seed always gives the same cells.
+
+Every cell is eroded on its own as the bounding box of the cell and a band around it, then every
+cell of the map takes the height of the partition of its nearest site. Within the band the heights
+of the two nearest sites are blended by the distance to the boundary between them, so the seams
+fade out instead of stepping.
+ */
+
+pub const DEFAULT_SEED: u64 = 1337;
+
+/// Sites of `cell_count` cells spread over a `width` x `height` map, in cells of the map.
+pub fn sites(width: usize, height: usize, cell_count: usize, seed: u64) -> Vec<Vector2> {
+    let cell_count = cell_count.max(1);
+    let mut rng = StdRng::seed_from_u64(seed);
+    // About as many rows as the slots in them are wide
+    let rows = ((cell_count as f32 * height as f32 / width as f32)
+        .sqrt()
+        .round() as usize)
+        .clamp(1, cell_count);
+    let slot_height = height as f32 / rows as f32;
+    let mut sites = Vec::with_capacity(cell_count);
+    for row in 0..rows {
+        let in_row = cell_count / rows + usize::from(row < cell_count % rows);
+        let slot_width = width as f32 / in_row as f32;
+        for slot in 0..in_row {
+            sites.push(Vector2::new(
+                (slot as f32 + rng.gen_range(0.15..0.85)) * slot_width,
+                (row as f32 + rng.gen_range(0.15..0.85)) * slot_height,
+            ));
+        }
+    }
+    sites
+}
+
+/// Half the width of the band where neighbouring cells are blended, in cells of the map.
+fn blend_distance(width: usize, height: usize, cell_count: usize) -> f32 {
+    (((width * height) as f32 / cell_count.max(1) as f32).sqrt() / 8.0).max(2.0)
+}
+
+/// The sites a cell of the map is made of.
+#[derive(Debug, Copy, Clone)]
+struct Owner {
+    nearest: usize,
+    second: usize,
+    /// Share of the nearest site in [0.5, 1], 1 outside the band around the boundary.
+    weight: f32,
+}
+
+fn owners(width: usize, height: usize, sites: &[Vector2], blend: f32) -> Vec<Vec<Owner>> {
+    (0..width)
+        .into_par_iter()
+        .map(|x| {
+            (0..height)
+                .map(|y| {
+                    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                    let (mut nearest, mut second) = ((0, f32::MAX), (0, f32::MAX));
+                    for (i, site) in sites.iter().enumerate() {
+                        let distance = (site.x - px).powi(2) + (site.y - py).powi(2);
+                        if distance < nearest.1 {
+                            second = nearest;
+                            nearest = (i, distance);
+                        } else if distance < second.1 {
+                            second = (i, distance);
+                        }
+                    }
+                    if sites.len() < 2 {
+                        return Owner {
+                            nearest: nearest.0,
+                            second: nearest.0,
+                            weight: 1.0,
+                        };
+                    }
+                    let (a, b) = (sites[nearest.0], sites[second.0]);
+                    let spacing = ((a.x - b.x).powi(2) + (a.y - b.y).powi(2))
+                        .sqrt()
+                        .max(f32::EPSILON);
+                    // Distance to the bisector of the two sites
+                    let distance = (second.1 - nearest.1) / (2.0 * spacing);
+                    let t = (distance / blend.max(f32::EPSILON)).min(1.0);
+                    Owner {
+                        nearest: nearest.0,
+                        second: second.0,
+                        weight: 0.5 + 0.5 * t * t * (3.0 - 2.0 * t),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Anchor and size of the area every site needs, `None` for sites without any cell of the map.
+fn bounding_boxes(owners: &[Vec<Owner>], site_count: usize) -> Vec<Option<(UVector2, UVector2)>> {
+    let mut boxes: Vec<Option<(UVector2, UVector2)>> = vec![None; site_count];
+    let mut include = |site: usize, x: usize, y: usize| {
+        let (min, max) = boxes[site].get_or_insert((UVector2::new(x, y), UVector2::new(x, y)));
+        *min = UVector2::new(min.x.min(x), min.y.min(y));
+        *max = UVector2::new(max.x.max(x), max.y.max(y));
+    };
+    for (x, column) in owners.iter().enumerate() {
+        for (y, owner) in column.iter().enumerate() {
+            include(owner.nearest, x, y);
+            if owner.weight < 1.0 {
+                include(owner.second, x, y);
+            }
+        }
+    }
+    boxes
+        .into_iter()
+        .map(|bounds| {
+            bounds.map(|(min, max)| (min, UVector2::new(max.x - min.x + 1, max.y - min.y + 1)))
+        })
+        .collect()
+}
+
+/// The cell of `sites[site]` as a closed polygon in cells of the map, the map cut by the
+/// bisectors with every other site.
+pub fn cell_polygon(
+    width: usize,
+    height: usize,
+    sites: &[Vector2],
+    site: usize,
+) -> Vec<(f32, f32)> {
+    let (width, height) = (width as f32, height as f32);
+    let a = sites[site];
+    let mut polygon = vec![(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+    for (i, b) in sites.iter().enumerate() {
+        if i == site || polygon.is_empty() {
+            continue;
+        }
+        // Negative on the side of `a`
+        let side = |p: (f32, f32)| {
+            (p.0 - (a.x + b.x) / 2.0) * (b.x - a.x) + (p.1 - (a.y + b.y) / 2.0) * (b.y - a.y)
+        };
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (j, &p) in polygon.iter().enumerate() {
+            let q = polygon[(j + 1) % polygon.len()];
+            let (side_p, side_q) = (side(p), side(q));
+            if side_p <= 0.0 {
+                clipped.push(p);
+            }
+            if (side_p <= 0.0) != (side_q <= 0.0) {
+                let t = side_p / (side_p - side_q);
+                clipped.push((p.0 + (q.0 - p.0) * t, p.1 + (q.1 - p.1) * t));
+            }
+        }
+        polygon = clipped;
+    }
+    if let Some(&first) = polygon.first() {
+        polygon.push(first);
+    }
+    polygon
+}
+
+/// Paints the boundaries between the cells and around the map, see `Method::get_grid`.
+pub fn paint_grid(heightmap: &mut Heightmap, cell_count: usize, seed: u64) {
+    let (width, height) = (heightmap.width, heightmap.height);
+    let sites = sites(width, height, cell_count, seed);
+    let owners = owners(width, height, &sites, 0.0);
+    super::default_grid(heightmap);
+    for x in 0..width {
+        for y in 0..height {
+            let nearest = owners[x][y].nearest;
+            if x + 1 < width && owners[x + 1][y].nearest != nearest {
+                heightmap.data[x][y] = 1.0;
+                heightmap.data[x + 1][y] = 1.0;
+            }
+            if y + 1 < height && owners[x][y + 1].nearest != nearest {
+                heightmap.data[x][y] = 1.0;
+                heightmap.data[x][y + 1] = 1.0;
+            }
+        }
+    }
+}
+
+pub fn erode(
+    heightmap: &mut Heightmap,
+    model: &Model,
+    adaptive: bool,
+    cell_count: usize,
+    seed: u64,
+    drop_zone: &DropZone,
+    progress: &Progress,
+) -> ErosionOutputs {
+    let (width, height) = (heightmap.width, heightmap.height);
+    let sites = sites(width, height, cell_count, seed);
+    let owners = owners(
+        width,
+        height,
+        &sites,
+        blend_distance(width, height, cell_count),
+    );
+
+    let mut partition_of_site = vec![usize::MAX; sites.len()];
+    let mut partitions = Vec::new();
+    for (site, bounds) in bounding_boxes(&owners, sites.len()).iter().enumerate() {
+        if let Some((anchor, size)) = bounds {
+            partition_of_site[site] = partitions.len();
+            partitions.push(Arc::new(Mutex::new(PartialHeightmap::with_halo(
+                heightmap,
+                anchor,
+                size,
+                drop_zone.get_halo(),
+            ))));
+        }
+    }
+
+    // The boxes overlap, so droplets are shared out by area for every part to be eroded as much
+    let roughness = super::roughness_of(&partitions);
+    let weights: Vec<HeightmapPrecision> = partitions
+        .iter()
+        .zip(roughness)
+        .map(|(partition, roughness)| {
+            let interior = partition.lock().unwrap().interior();
+            let area = (interior.x * interior.y) as HeightmapPrecision;
+            if adaptive {
+                area * roughness
+            } else {
+                area
+            }
+        })
+        .collect();
+    let models = super::partition_models(model, &weights, true);
+    progress.add_total(super::total_iterations(&models));
+    let outputs = super::erode_multiple(&partitions, &models, heightmap, drop_zone, progress);
+
+    // The partitions were copied back whole on top of each other, every cell is taken again
+    let partitions: Vec<_> = partitions
+        .iter()
+        .map(|partition| partition.lock().unwrap())
+        .collect();
+    let sample = |site: usize, x: usize, y: usize| {
+        let partition = &partitions[partition_of_site[site]];
+        partition.heightmap.data[x - partition.anchor.x][y - partition.anchor.y]
+    };
+    for (x, column) in owners.iter().enumerate() {
+        for (y, owner) in column.iter().enumerate() {
+            let nearest = sample(owner.nearest, x, y);
+            heightmap.data[x][y] = if owner.weight < 1.0 {
+                owner.weight * nearest + (1.0 - owner.weight) * sample(owner.second, x, y)
+            } else {
+                nearest
+            };
+        }
+    }
+    outputs
+}
@@ -0,0 +1,290 @@
+use crate::erode::{Backend, DropZone, Model, Progress};
+use crate::fixtures::*;
+use crate::heightmap::{self, HeightmapType};
+use crate::partitioning::{MarginMode, Method};
+use crate::visualize::app_state::AppParameters;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+/*
+`--self-test` runs a quick battery over the parts of a build that depend on the platform, so a
+release binary can be checked where it is installed: every heightmap preset is generated, a tiny
+map is eroded with every backend and partitioning method, which exercises the threads, and every
+file format is written and read back. The logic itself is covered by the unit tests of each module,
+run with `cargo test`. Nothing is kept on disk afterwards.
+ */
+
+fn generate(heightmap_type: HeightmapType, height: Option<usize>) -> Check {
+    let mut heightmap_type = heightmap_type;
+    heightmap_type.params_mut().size = SIZE;
+    heightmap_type.params_mut().height = height;
+    check_heightmap(
+        &heightmap::create_heightmap_from_preset(&heightmap_type),
+        SIZE,
+        height.unwrap_or(SIZE),
+    )
+}
+
+fn erode(method: &Method, model: &Model) -> Check {
+    // Rectangular so the backends can not mix up the two dimensions
+    let heightmap = tiny_rectangular_heightmap();
+    let (eroded, _) = method.erode_with_margin(
+        MarginMode::None,
+        false,
+        &heightmap,
+        model,
+        &DropZone::default(&heightmap),
+        &Progress::default(),
+    );
+    check_heightmap(&eroded, SIZE, SIZE * 2)?;
+    if eroded.content_hash() == heightmap.content_hash() {
+        return Err("terrain was not changed".to_string());
+    }
+    Ok(())
+}
+
+fn data_export() -> Check {
+    use crate::heightmap::io::{export_data, DataFormat};
+    let heightmap = tiny_rectangular_heightmap();
+    let path = temp_path("data");
+    let path = path.to_string_lossy();
+    let read = |format: DataFormat| {
+        let file = format!("{}.{}", path, format.extension());
+        let result = export_data(&heightmap, &path, format)
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|_| fs::read(&file).map_err(|err| format!("{:?}", err)));
+        let _ = fs::remove_file(file);
+        result
+    };
+
+    let npy = read(DataFormat::Npy)?;
+    let header = 10 + u16::from_le_bytes([npy[8], npy[9]]) as usize;
+    if !npy.starts_with(b"\x93NUMPY") || !header.is_multiple_of(64) {
+        return Err("npy header is malformed".to_string());
+    }
+    let values: Vec<f32> = npy[header..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    // Rows are y, so the second value is the next cell along x
+    if values.len() != SIZE * SIZE * 2 || values[1] != heightmap.data[1][0] {
+        return Err("npy data does not match the heightmap".to_string());
+    }
+
+    let csv = String::from_utf8(read(DataFormat::Csv)?).map_err(|err| format!("{:?}", err))?;
+    let rows: Vec<&str> = csv.lines().collect();
+    if rows.len() != SIZE * 2 || rows.iter().any(|row| row.split(',').count() != SIZE) {
+        return Err("csv does not have one row per y".to_string());
+    }
+    read(DataFormat::CsvGz)?;
+
+    let r32 = read(DataFormat::R32)?;
+    let values: Vec<f32> = r32
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    if values.len() != SIZE * SIZE * 2 || values[SIZE + 1] != heightmap.data[1][1] {
+        return Err("r32 data does not match the heightmap".to_string());
+    }
+
+    let png = image::load_from_memory(&read(DataFormat::Png16)?)
+        .map_err(|err| format!("{:?}", err))?
+        .into_luma16();
+    let expected = (heightmap.data[1][2] / heightmap.depth * u16::MAX as f32).round() as u16;
+    if png.dimensions() != (SIZE as u32, SIZE as u32 * 2) || png.get_pixel(1, 2).0[0] != expected {
+        return Err("16 bit png does not match the heightmap".to_string());
+    }
+
+    let tiff = read(DataFormat::TiffF32)?;
+    let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(tiff))
+        .map_err(|err| format!("{:?}", err))?;
+    match decoder.read_image().map_err(|err| format!("{:?}", err))? {
+        tiff::decoder::DecodingResult::F32(values) if values[SIZE + 1] == heightmap.data[1][1] => {
+            Ok(())
+        }
+        _ => Err("float tiff does not match the heightmap".to_string()),
+    }
+}
+
+fn image_import() -> Check {
+    use crate::heightmap::io::import_image;
+    use image::ColorType;
+    let (width, height) = (SIZE + 1, SIZE);
+    let at = |x: usize, y: usize| (x * 7 + y * 3) as f32 / (width * 7 + height * 3) as f32;
+    let luma8: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (at(x, y) * 255.0).round() as u8))
+        .collect();
+    let luma16: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (at(x, y) * 65535.0).round() as u16))
+        .flat_map(|v| v.to_ne_bytes())
+        .collect();
+    for (bits, data, color, tolerance) in [
+        (8, luma8, ColorType::L8, 0.5 / 255.0),
+        (16, luma16, ColorType::L16, 0.5 / 65535.0),
+    ] {
+        let path = temp_path(&format!("import-{}.png", bits));
+        let result = image::save_buffer(&path, &data, width as u32, height as u32, color)
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|_| import_image(&path).map_err(|err| format!("{:?}", err)));
+        let _ = fs::remove_file(&path);
+        let heightmap = result?;
+        if heightmap.width != width || heightmap.height != height {
+            return Err(format!("{} bit image size does not match", bits));
+        }
+        for (x, y) in [
+            (0, 0),
+            (width - 1, 0),
+            (2, height - 1),
+            (width - 1, height - 1),
+        ] {
+            if (heightmap.data[x][y] - at(x, y)).abs() > tolerance + f32::EPSILON {
+                return Err(format!("{} bit image wrong at ({}, {})", bits, x, y));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn exr_round_trip() -> Check {
+    use crate::heightmap::io::{export_data, import_image, DataFormat};
+    let mut heightmap = tiny_rectangular_heightmap();
+    heightmap.depth = 2.5;
+    heightmap.wrap = true;
+    heightmap.metadata_add("EROSION_ITERATIONS", ITERATIONS.to_string());
+    let path = temp_path("exr");
+    let file = path.with_extension(DataFormat::Exr.extension());
+    let result = export_data(&heightmap, &path.to_string_lossy(), DataFormat::Exr)
+        .map_err(|err| format!("{:?}", err))
+        .and_then(|_| import_image(&file).map_err(|err| format!("{:?}", err)));
+    let _ = fs::remove_file(&file);
+    let imported = result?;
+    if imported.width != heightmap.width || imported.height != heightmap.height {
+        return Err("exr size does not match".to_string());
+    }
+    if imported.data != heightmap.data {
+        return Err("exr heights are not lossless".to_string());
+    }
+    if imported.depth != heightmap.depth
+        || imported.original_depth != heightmap.original_depth
+        || !imported.wrap
+    {
+        return Err("exr depth or wrapping was not restored".to_string());
+    }
+    if imported.metadata != heightmap.metadata {
+        return Err("exr metadata was not restored".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "export")]
+fn json_round_trip() -> Check {
+    let heightmap = tiny_heightmap();
+    let path = temp_path("heightmap");
+    let path = path.to_string_lossy();
+    let result = heightmap::io::export(&heightmap, &env::temp_dir().to_string_lossy(), &path)
+        .and_then(|_| heightmap::io::import(&format!("{}.json", path)))
+        .map_err(|err| format!("{:?}", err));
+    let _ = fs::remove_file(format!("{}.json", path));
+    check_round_trip(&heightmap, &result?)
+}
+
+#[cfg(feature = "export")]
+fn png_round_trip() -> Check {
+    let heightmap = tiny_heightmap();
+    let path = temp_path("heightmap");
+    let path = path.to_string_lossy();
+    let result = heightmap::io::save_heightmap_as_image(&heightmap, &path)
+        .and_then(|_| image::open(format!("{}.png", path)))
+        .map_err(|err| format!("{:?}", err));
+    let _ = fs::remove_file(format!("{}.png", path));
+    // Pngs only hold 8 bits per cell, so the quantized heights are compared
+    if result?.to_luma8().into_raw() == heightmap.to_u8() {
+        Ok(())
+    } else {
+        Err("pixels differ from the heightmap".to_string())
+    }
+}
+
+#[cfg(feature = "export")]
+fn state_round_trip(binary: bool) -> Check {
+    let mut heightmap_type = HeightmapType::default();
+    heightmap_type.params_mut().size = SIZE;
+    let state = crate::State::new(&heightmap_type);
+    let result = if binary {
+        crate::io::export_binary(&state, NAME).and_then(|_| crate::io::import_binary(NAME))
+    } else {
+        crate::io::export_json(&state, NAME).and_then(|_| crate::io::import_json(NAME))
+    }
+    .map_err(|err| format!("{:?}", err));
+    let _ = crate::io::remove(NAME);
+    check_round_trip(
+        &state.app_state.simulation_state().get_heightmap(),
+        &result?.app_state.simulation_state().get_heightmap(),
+    )
+}
+
+/// Runs every check and prints one line per check, returns whether all of them passed.
+pub fn run() -> bool {
+    let mut checks: Vec<(String, Box<dyn Fn() -> Check>)> = Vec::new();
+    for heightmap_type in HeightmapType::iterator() {
+        let rectangular = heightmap_type.clone();
+        checks.push((
+            format!("Generate {}", heightmap_type),
+            Box::new(move || generate(heightmap_type.clone(), None)),
+        ));
+        checks.push((
+            format!("Generate {} (rectangular)", rectangular),
+            Box::new(move || generate(rectangular.clone(), Some(SIZE * 2))),
+        ));
+    }
+    let mut parameters = AppParameters::default();
+    for backend in Backend::list() {
+        parameters.backend = backend;
+        let model = parameters.model().with_iterations(ITERATIONS);
+        for method in Method::list(GRID_SIZE) {
+            checks.push((
+                format!("Erode {} with {}", backend, method.to_string()),
+                Box::new(move || erode(&method, &model)),
+            ));
+        }
+    }
+    checks.push(("Data export".to_string(), Box::new(data_export)));
+    checks.push(("EXR round trip".to_string(), Box::new(exr_round_trip)));
+    checks.push(("Image import".to_string(), Box::new(image_import)));
+    #[cfg(feature = "export")]
+    {
+        checks.push(("Heightmap json".to_string(), Box::new(json_round_trip)));
+        checks.push(("Heightmap png".to_string(), Box::new(png_round_trip)));
+        checks.push((
+            "State json".to_string(),
+            Box::new(|| state_round_trip(false)),
+        ));
+        checks.push((
+            "State binary".to_string(),
+            Box::new(|| state_round_trip(true)),
+        ));
+    }
+
+    let mut failed = 0;
+    for (name, check) in checks.iter() {
+        let time = Instant::now();
+        // A panicking check fails on its own instead of ending the battery
+        let result = panic::catch_unwind(AssertUnwindSafe(check))
+            .unwrap_or_else(|_| Err("panicked".to_string()));
+        match result {
+            Ok(()) => println!("[PASS] {} ({:.2} s)", name, time.elapsed().as_secs_f32()),
+            Err(reason) => {
+                println!("[FAIL] {}: {}", name, reason);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "Self-test finished, {} of {} checks passed.",
+        checks.len() - failed,
+        checks.len()
+    );
+    failed == 0
+}
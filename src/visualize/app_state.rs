@@ -1,22 +1,47 @@
-use macroquad::texture::{Image, Texture2D};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
-use crate::erode::{DropZone, Parameters};
-use crate::heightmap::{self, Heightmap, HeightmapType};
-use crate::partitioning::Method;
-use crate::visualize::wrappers::HeightmapTexture;
-use crate::visualize::{
-    layered_heightmaps_to_texture, rgba_color_channel, HeightmapLayer, LayerMixMethod,
+use crate::erode::{
+    beyer, coastal, fluvial, glacial, pipes, wind, Backend, DropZone, ErosionOutputs,
+    ErosionPipeline, Model, Parameters, PartitionReport, Progress,
+};
+use crate::heightmap::analysis::{self, SeamMetrics};
+use crate::heightmap::{
+    self, DerivedLayer, HardnessType, Heightmap, HeightmapType, LayerParameters, MaterialLayer,
+    PrecipitationType, VegetationParameters,
 };
+use crate::math::{Margins, UVector2};
+use crate::partitioning::{MarginMode, Method};
+use crate::visualize::droplets::DropletEnds;
+use crate::visualize::heat::{Heat, HeatOverlay};
+use crate::visualize::history::History;
+use crate::visualize::palette::{active_colormap, Legend};
+use crate::visualize::session::SessionStats;
+use crate::visualize::view::Bookmark;
+use crate::visualize::wrappers::HeightmapTexture;
+use crate::visualize::{heightmap_to_image_rgb, LayerImage};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppState {
     pub simulation_states: Vec<SimulationState>,
     pub simulation_base_indices: Vec<usize>,
     pub parameters: AppParameters,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+    #[serde(skip)]
+    pub simulation_job: Option<Rc<SimulationJob>>,
+    #[serde(skip)]
+    pub incremental_simulation: Option<Rc<RefCell<(BaseState, IncrementalSimulation)>>>,
+    #[serde(skip)]
+    pub session: SessionStats,
+    #[serde(skip)]
+    pub history: History,
 }
 
 impl AppState {
@@ -27,23 +52,356 @@ impl AppState {
     pub fn simulation_state_mut(&mut self) -> &mut SimulationState {
         &mut self.simulation_states[*self.simulation_base_indices.last().unwrap()]
     }
+
+    /// Records the simulation states before changing them so the change can be undone.
+    pub fn record_history(&mut self) {
+        self.history
+            .record(&self.simulation_states, &self.simulation_base_indices);
+    }
+
+    /// Returns whether there was anything to undo.
+    pub fn undo(&mut self) -> bool {
+        let current = (
+            self.simulation_states.clone(),
+            self.simulation_base_indices.clone(),
+        );
+        match self.history.undo(current) {
+            Some((states, indices)) => {
+                self.simulation_states = states;
+                self.simulation_base_indices = indices;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether there was anything to redo.
+    pub fn redo(&mut self) -> bool {
+        let current = (
+            self.simulation_states.clone(),
+            self.simulation_base_indices.clone(),
+        );
+        match self.history.redo(current) {
+            Some((states, indices)) => {
+                self.simulation_states = states;
+                self.simulation_base_indices = indices;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Rebuilds the textures of every state from their heightmaps, after loading states or
+    /// changing the colormap. The shown layer is only rebuilt with `rebuild_active` or when it is
+    /// the base or eroded heightmap, other layers such as a hillshade keep their own colours.
+    pub fn regenerate_textures(&mut self, rebuild_active: bool) {
+        for state in self.simulation_states.iter_mut() {
+            let active = state.base().heightmap_active.clone();
+            let mut showing_base = Rc::ptr_eq(&active, &state.base().heightmap_base);
+            let mut showing_eroded = false;
+
+            let base = HeightmapTexture::from(&state.base().heightmap_base.heightmap);
+            state.base_mut().heightmap_base = Rc::new(base);
+
+            if let Some(eroded_state) = state.eroded_mut() {
+                showing_eroded = Rc::ptr_eq(&active, &eroded_state.heightmap_eroded);
+                let eroded = HeightmapTexture::from(&eroded_state.heightmap_eroded.heightmap);
+                eroded_state.heightmap_eroded = Rc::new(eroded);
+
+                if let Some(flow_map) = &eroded_state.flow_map {
+                    eroded_state.flow_map =
+                        Some(Rc::new(HeightmapTexture::from(&flow_map.heightmap)));
+                }
+
+                if let Some(erosion_map) = &eroded_state.erosion_map {
+                    eroded_state.erosion_map = Some(Rc::new(material_layer_texture(
+                        (*erosion_map.heightmap).clone(),
+                    )));
+                }
+                if let Some(deposition_map) = &eroded_state.deposition_map {
+                    eroded_state.deposition_map = Some(Rc::new(material_layer_texture(
+                        (*deposition_map.heightmap).clone(),
+                    )));
+                }
+
+                for (_, layer) in eroded_state.material_layers.iter_mut() {
+                    *layer = Rc::new(material_layer_texture((*layer.heightmap).clone()));
+                }
+
+                let mut diffs = Vec::new();
+                for diff in eroded_state.heightmap_difference.borrow().iter() {
+                    diffs.push(Rc::new(HeightmapTexture::from(&diff.heightmap)));
+                }
+                eroded_state.heightmap_difference = Rc::new(RefCell::new(diffs));
+
+                let mut diffs = Vec::new();
+                for diff in eroded_state.heightmap_difference_normalized.borrow().iter() {
+                    diffs.push(Rc::new(HeightmapTexture::from(&diff.heightmap)));
+                }
+                eroded_state.heightmap_difference_normalized = Rc::new(RefCell::new(diffs));
+
+                for (_, snapshot) in eroded_state.timeline.iter_mut() {
+                    *snapshot = Rc::new(HeightmapTexture::from(&snapshot.heightmap));
+                }
+            }
+
+            if showing_eroded {
+                showing_base = false;
+                let eroded = state.eroded().map(|eroded| eroded.heightmap_eroded.clone());
+                if let Some(eroded) = eroded {
+                    state.set_active(eroded);
+                }
+            } else if showing_base {
+                let base = state.base().heightmap_base.clone();
+                state.set_active(base);
+            }
+            if rebuild_active && !showing_base && !showing_eroded {
+                state.set_active(Rc::new(HeightmapTexture::from(&active.heightmap)));
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppParameters {
     pub erosion_params: Parameters,
+    #[serde(default)]
+    pub beyer_params: beyer::Parameters,
+    #[serde(default)]
+    pub pipes_params: pipes::Parameters,
+    #[serde(default)]
+    pub fluvial_params: fluvial::Parameters,
+    pub wind_params: wind::Parameters,
+    #[serde(default)]
+    pub glacial_params: glacial::Parameters,
+    #[serde(default)]
+    pub coastal_params: coastal::Parameters,
+    pub backend: Backend,
     pub heightmap_type: HeightmapType,
+    pub hardness_type: HardnessType,
+    #[serde(default)]
+    pub precipitation_type: PrecipitationType,
+    #[serde(default)]
+    pub vegetation_params: VegetationParameters,
+    pub layer_params: LayerParameters,
     pub auto_apply: bool,
     pub margin: bool,
+    /// Cells cut off each side instead of the largest margin of any method, see `MarginMode`.
+    #[serde(default)]
+    pub custom_margins: Option<Margins>,
+    #[serde(default)]
+    pub snapshot_interval: usize, // [0, num_iterations], 0 disables the timeline
+    #[serde(default)]
+    pub pipeline: ErosionPipeline,
 }
 
 impl Default for AppParameters {
     fn default() -> Self {
         AppParameters {
             erosion_params: Parameters::default(),
+            beyer_params: beyer::Parameters::default(),
+            pipes_params: pipes::Parameters::default(),
+            fluvial_params: fluvial::Parameters::default(),
+            wind_params: wind::Parameters::default(),
+            glacial_params: glacial::Parameters::default(),
+            coastal_params: coastal::Parameters::default(),
+            backend: Backend::Lague,
             heightmap_type: HeightmapType::default(),
+            hardness_type: HardnessType::default(),
+            precipitation_type: PrecipitationType::default(),
+            vegetation_params: VegetationParameters::default(),
+            layer_params: LayerParameters::default(),
             auto_apply: true,
             margin: true,
+            custom_margins: None,
+            snapshot_interval: 0,
+            pipeline: ErosionPipeline::default(),
+        }
+    }
+}
+
+impl AppParameters {
+    pub fn margin_mode(&self) -> MarginMode {
+        MarginMode::new(self.margin, self.custom_margins)
+    }
+
+    pub fn set_margin_mode(&mut self, margin: MarginMode) {
+        self.margin = margin.is_used();
+        self.custom_margins = margin.custom();
+    }
+
+    pub fn model(&self) -> Model {
+        match self.backend {
+            Backend::Lague => Model::Lague(self.erosion_params),
+            Backend::Beyer => Model::Beyer(self.beyer_params),
+            Backend::Pipes => Model::Pipes(self.pipes_params),
+            Backend::Fluvial => Model::Fluvial(self.fluvial_params),
+            Backend::Wind => Model::Wind(self.wind_params),
+            Backend::Glacial => Model::Glacial(self.glacial_params),
+        }
+    }
+
+    /// Selects the backend of `model` and takes over its parameters, the inverse of `model`.
+    pub fn set_model(&mut self, model: Model) {
+        self.backend = model.backend();
+        match model {
+            Model::Lague(params) => self.erosion_params = params,
+            Model::Beyer(params) => self.beyer_params = params,
+            Model::Pipes(params) => self.pipes_params = params,
+            Model::Fluvial(params) => self.fluvial_params = params,
+            Model::Wind(params) => self.wind_params = params,
+            Model::Glacial(params) => self.glacial_params = params,
+        }
+    }
+
+    /// The metadata a simulation with these parameters and `method` would record.
+    pub fn expected_metadata(&self, method: &Method, adaptive: bool) -> HashMap<String, String> {
+        let mut heightmap = Heightmap::new(Vec::new(), 0, 0, 0.0, 0.0, None);
+        self.model().add_metadata(&mut heightmap);
+        if let Some(sea) = self.coastal_params.sea() {
+            coastal::add_metadata(&sea, &mut heightmap);
+        }
+        method.add_metadata(&mut heightmap, self.margin_mode(), adaptive);
+        let mut metadata = heightmap.metadata.unwrap_or_default();
+        // The hardness and vegetation maps belong to the heightmap rather than the parameters
+        metadata.remove("HARDNESS_MAP");
+        metadata.remove("VEGETATION_MAP");
+        if let Some(layers) = metadata.get_mut("MATERIAL_LAYERS") {
+            *layers = self.layer_params.enabled.to_string();
+        }
+        metadata
+    }
+
+    /// Cross-checks recorded metadata against these parameters, mismatches come first.
+    pub fn audit_metadata(
+        &self,
+        method: &Method,
+        adaptive: bool,
+        metadata: Option<&HashMap<String, String>>,
+    ) -> Vec<MetadataAudit> {
+        let mut audit: Vec<MetadataAudit> = self
+            .expected_metadata(method, adaptive)
+            .into_iter()
+            .map(|(key, expected)| MetadataAudit {
+                recorded: metadata.and_then(|metadata| metadata.get(&key).cloned()),
+                key,
+                expected,
+            })
+            .collect();
+        audit.sort_by(|a, b| a.matches().cmp(&b.matches()).then(a.key.cmp(&b.key)));
+        audit
+    }
+}
+
+/// A setting recorded in the heightmap metadata next to its current value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataAudit {
+    pub key: String,
+    pub expected: String,
+    pub recorded: Option<String>,
+}
+
+impl MetadataAudit {
+    pub fn matches(&self) -> bool {
+        self.recorded.as_ref() == Some(&self.expected)
+    }
+}
+
+/// Roughly how many frames an incremental simulation is spread over by default.
+const INCREMENTAL_FRAMES: usize = 300;
+
+/// Erosion advanced a chunk of iterations at a time, see `BaseState::step_simulation`.
+#[derive(Debug)]
+pub struct IncrementalSimulation {
+    heightmap: Heightmap,
+    model: Model,
+    outputs: ErosionOutputs,
+    pub done: usize,
+    pub chunk: usize, // iterations per frame
+    elapsed: Duration,
+    /// Recent activity, tracked while the heat overlay is enabled.
+    heat: Option<Heat>,
+}
+
+impl IncrementalSimulation {
+    pub fn total(&self) -> usize {
+        self.model.num_iterations()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.total().saturating_sub(self.done)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Starts or stops tracking the heat as the overlay is toggled and cools it off by `seconds`.
+    pub fn update_heat(&mut self, overlay: &HeatOverlay, seconds: f32) {
+        match (overlay.enabled, &mut self.heat) {
+            (true, Some(heat)) => heat.decay(seconds, overlay.half_life),
+            (true, None) => {
+                self.heat = Some(Heat::new(self.heightmap.width, self.heightmap.height));
+            }
+            (false, _) => self.heat = None,
+        }
+    }
+
+    /// The terrain as it looks after the iterations run so far, glowing where it recently changed
+    /// if the heat is tracked.
+    pub fn preview(&self) -> HeightmapTexture {
+        match &self.heat {
+            Some(heat) => {
+                let image = heat.image(&self.heightmap);
+                HeightmapTexture::new(Rc::new(self.heightmap.clone()), Some(Rc::new(image)))
+            }
+            None => self.heightmap.clone().into(),
+        }
+    }
+}
+
+/// A simulation running in the background, owned by the UI until it finishes or is cancelled.
+pub struct SimulationJob {
+    base: BaseState,
+    model: Model,
+    margin: MarginMode,
+    pub progress: Progress,
+    handle: RefCell<Option<JoinHandle<SimulationResult>>>,
+}
+
+impl std::fmt::Debug for SimulationJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimulationJob")
+            .field("base", &self.base.id)
+            .field("model", &self.model)
+            .field("progress", &self.progress.fraction())
+            .finish()
+    }
+}
+
+impl SimulationJob {
+    /// Returns the new state once the simulation thread is done, `None` while it is still running.
+    /// Cancelled simulations finish without a state.
+    pub fn poll(&self, new_id: usize) -> Option<Option<SimulationState>> {
+        let mut handle = self.handle.borrow_mut();
+        if !handle.as_ref()?.is_finished() {
+            return None;
+        }
+        let result = handle.take()?.join();
+        if self.progress.is_cancelled() {
+            return Some(None);
+        }
+        match result {
+            Ok(result) => {
+                let eroded = self
+                    .base
+                    .finish_simulation(new_id, &self.model, self.margin, result);
+                Some(Some(SimulationState::Eroded((self.base.clone(), eroded))))
+            }
+            Err(_) => {
+                eprintln!("Simulation thread panicked.");
+                Some(None)
+            }
         }
     }
 }
@@ -57,12 +415,45 @@ pub struct ErodedState {
     pub heightmap_eroded: Rc<HeightmapTexture>,
     pub heightmap_difference: Rc<RefCell<Vec<Rc<HeightmapTexture>>>>,
     pub heightmap_difference_normalized: Rc<RefCell<Vec<Rc<HeightmapTexture>>>>,
+    /// Eroded minus the compared state, without textures since it is coloured when shown.
+    #[serde(default)]
+    pub heightmap_difference_signed: Rc<RefCell<Vec<Rc<Heightmap>>>>,
     pub erosion_method: Rc<Method>,
+    pub erosion_model: Rc<Model>,
+    #[serde(default)]
+    pub material_layers: Vec<(MaterialLayer, Rc<HeightmapTexture>)>,
+    #[serde(default)]
+    pub flow_map: Option<Rc<HeightmapTexture>>,
+    #[serde(default)]
+    pub erosion_map: Option<Rc<HeightmapTexture>>,
+    #[serde(default)]
+    pub deposition_map: Option<Rc<HeightmapTexture>>,
+    #[serde(default)]
+    pub droplet_ends: Option<Rc<DropletEnds>>,
+    /// Iterations and wall time of every partition, see `ErosionOutputs::partitions`.
+    #[serde(default)]
+    pub partitions: Vec<PartitionReport>,
+    /// How much the seams between partitions stand out, `None` without partitions.
+    #[serde(default)]
+    pub seams: Option<SeamMetrics>,
     pub margin_removed: bool,
+    /// The margins cut off if they were not the automatic ones, see `margin_mode`.
+    #[serde(default)]
+    pub custom_margins: Option<Margins>,
     pub simulation_time: Duration,
+    #[serde(default)]
+    pub notes: String,
+    /// Intermediate heightmaps and the iteration they were taken at, see `snapshot_interval`.
+    #[serde(default)]
+    pub timeline: Vec<(usize, Rc<HeightmapTexture>)>,
 }
 
 impl ErodedState {
+    /// How the margins were cut off when this state was eroded.
+    pub fn margin_mode(&self) -> MarginMode {
+        MarginMode::new(self.margin_removed, self.custom_margins)
+    }
+
     pub fn diff_index_of(&self, diff_id: &usize) -> Option<usize> {
         for (i, d) in self.diffs.borrow().iter().enumerate() {
             if *diff_id == *d {
@@ -71,51 +462,280 @@ impl ErodedState {
         }
         None
     }
+
+    pub fn material_layer(&self, layer: MaterialLayer) -> Option<Rc<HeightmapTexture>> {
+        self.material_layers
+            .iter()
+            .find(|(l, _)| *l == layer)
+            .map(|(_, texture)| Rc::clone(texture))
+    }
+}
+
+/// Layer thicknesses are tiny compared to the terrain, so they are displayed normalized.
+pub fn material_layer_texture(heightmap: Heightmap) -> HeightmapTexture {
+    let (min, max) = heightmap.get_range();
+    let (image, legend) = if max > min {
+        (
+            heightmap_to_image_rgb(&heightmap.clone().normalize()),
+            Legend {
+                colormap: active_colormap(),
+                min,
+                max,
+            },
+        )
+    } else {
+        (
+            heightmap_to_image_rgb(&heightmap),
+            Legend::heights(&heightmap),
+        )
+    };
+    HeightmapTexture::new(Rc::new(heightmap), Some(Rc::new(image))).with_legend(legend)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BaseState {
     pub id: usize,
     pub erosion_method: Method,
+    /// Share droplets between partitions by their roughness instead of evenly.
+    #[serde(default)]
+    pub adaptive_iterations: bool,
+    /// Cells of their neighbours the partitions read around them, 0 for none.
+    #[serde(default)]
+    pub partition_halo: usize,
     pub params: Parameters,
     pub drop_zone: DropZone,
     pub heightmap_base: Rc<HeightmapTexture>,
     pub heightmap_active: Rc<HeightmapTexture>,
+    #[serde(default)]
+    pub hardness: Option<Rc<Heightmap>>,
+    #[serde(default)]
+    pub vegetation: Option<Rc<Heightmap>>,
+    #[serde(default)]
+    pub notes: String,
 }
 
+pub type SimulationResult = (Heightmap, ErosionOutputs, Duration, Vec<(usize, Heightmap)>);
+
 impl BaseState {
-    pub fn run_simulation(&self, id: usize, parameters: &Parameters, margin: bool) -> ErodedState {
+    pub fn run_simulation(
+        &self,
+        id: usize,
+        model: &Model,
+        layers: &LayerParameters,
+        margin: MarginMode,
+        snapshot_interval: usize,
+    ) -> ErodedState {
+        let result = self
+            .start_simulation(
+                model,
+                layers,
+                margin,
+                snapshot_interval,
+                Progress::default(),
+            )
+            .join()
+            .expect("Simulation thread panicked.");
+        self.finish_simulation(id, model, margin, result)
+    }
+
+    /// Runs every pass of `pipeline` as one simulation, `None` for an empty pipeline.
+    pub fn run_pipeline(
+        &self,
+        id: usize,
+        pipeline: &ErosionPipeline,
+        layers: &LayerParameters,
+        margin: MarginMode,
+    ) -> Option<ErodedState> {
+        let model = *pipeline.passes.last()?;
+        let base = self.simulation_heightmap(layers);
+        println!(
+            "Eroding using {} method with pipeline {}",
+            self.erosion_method.to_string(),
+            pipeline.describe()
+        );
         let time = std::time::Instant::now();
-        let mut heightmap: Heightmap = self.erosion_method.erode_with_margin(
+        let (mut heightmap, outputs) = self.erosion_method.erode_pipeline(
             margin,
-            &self.heightmap_base.heightmap,
-            parameters,
+            self.adaptive_iterations,
+            &base,
+            pipeline,
             &self.drop_zone,
+            &Progress::default(),
         );
         let elapsed = time.elapsed();
         heightmap.metadata_add("simulation_time", format!("{}", elapsed.as_secs_f32()));
-        let new_margin = if margin {
-            Method::max_margin(
-                self.heightmap_base.heightmap.width,
-                self.erosion_method.get_grid_size(),
-            )
+        Some(self.finish_simulation(
+            id,
+            &model,
+            margin,
+            (heightmap, outputs, elapsed, Vec::new()),
+        ))
+    }
+
+    /// A copy of the base heightmap carrying the hardness map, vegetation and material layers.
+    fn simulation_heightmap(&self, layers: &LayerParameters) -> Heightmap {
+        if self.hardness.is_some() || self.vegetation.is_some() || layers.enabled {
+            (*self.heightmap_base.heightmap)
+                .clone()
+                .with_hardness(self.hardness.as_deref())
+                .with_vegetation(self.vegetation.as_deref())
+                .with_layers(layers)
         } else {
-            (0, 0, 0, 0)
-        };
-        let mut heightmap_diff = heightmap
-            .subtract(
-                &self
-                    .heightmap_base
-                    .heightmap
-                    .with_margin(new_margin)
-                    .heightmap,
-            )
-            .unwrap();
+            (*self.heightmap_base.heightmap).clone()
+        }
+    }
+
+    /// Erodes a copy of the base heightmap on a separate thread so the UI stays responsive.
+    pub fn start_simulation(
+        &self,
+        model: &Model,
+        layers: &LayerParameters,
+        margin: MarginMode,
+        snapshot_interval: usize,
+        progress: Progress,
+    ) -> JoinHandle<SimulationResult> {
+        let base = self.simulation_heightmap(layers);
+        let method = self.erosion_method;
+        let adaptive = self.adaptive_iterations;
+        let model = *model;
+        let drop_zone = self.drop_zone.clone();
+        match method {
+            Method::Default => println!(
+                "Eroding using {} method (no partitioning)",
+                method.to_string()
+            ),
+            _ => println!("Eroding using {} method", method.to_string()),
+        }
+        thread::spawn(move || {
+            let time = std::time::Instant::now();
+            let (mut heightmap, outputs, snapshots) = method.erode_with_snapshots(
+                margin,
+                adaptive,
+                &base,
+                &model,
+                &drop_zone,
+                &progress,
+                snapshot_interval,
+            );
+            let elapsed = time.elapsed();
+            heightmap.metadata_add("simulation_time", format!("{}", elapsed.as_secs_f32()));
+            (heightmap, outputs, elapsed, snapshots)
+        })
+    }
+
+    /// Prepares a simulation that is advanced with `step_simulation` instead of all at once.
+    pub fn start_incremental(
+        &self,
+        model: &Model,
+        layers: &LayerParameters,
+    ) -> IncrementalSimulation {
+        let heightmap = self.simulation_heightmap(layers);
+        let outputs = ErosionOutputs::empty(&heightmap);
+        IncrementalSimulation {
+            heightmap,
+            model: *model,
+            outputs,
+            done: 0,
+            chunk: (model.num_iterations() / INCREMENTAL_FRAMES).max(1),
+            elapsed: Duration::ZERO,
+            heat: None,
+        }
+    }
+
+    /// Runs the next `n` iterations of `simulation`, keeping the intermediate terrain.
+    /// Margins are not used since they would crop the heightmap on every step.
+    pub fn step_simulation(&self, simulation: &mut IncrementalSimulation, n: usize) {
+        let n = n.min(simulation.remaining());
+        if n == 0 {
+            return;
+        }
+        let before = simulation
+            .heat
+            .as_ref()
+            .map(|_| simulation.heightmap.clone());
+        let time = std::time::Instant::now();
+        let outputs = self.erosion_method.erode(
+            &mut simulation.heightmap,
+            &simulation.model.with_iterations(n),
+            self.adaptive_iterations,
+            &self.drop_zone,
+            &Progress::default(),
+        );
+        if let (Some(heat), Some(before)) = (&mut simulation.heat, &before) {
+            heat.add(before, &simulation.heightmap);
+        }
+        simulation.outputs.merge(&outputs, &UVector2 { x: 0, y: 0 });
+        simulation.done += n;
+        simulation.elapsed += time.elapsed();
+    }
+
+    /// Turns a finished incremental simulation into an eroded state.
+    pub fn finish_incremental(&self, id: usize, simulation: IncrementalSimulation) -> ErodedState {
+        let IncrementalSimulation {
+            mut heightmap,
+            model,
+            outputs,
+            done,
+            elapsed,
+            ..
+        } = simulation;
+        heightmap.reconcile_layers();
+        model.with_iterations(done).add_metadata(&mut heightmap);
+        self.erosion_method.add_metadata(
+            &mut heightmap,
+            MarginMode::None,
+            self.adaptive_iterations,
+        );
+        heightmap.metadata_add("simulation_time", format!("{}", elapsed.as_secs_f32()));
+        self.finish_simulation(
+            id,
+            &model,
+            MarginMode::None,
+            (heightmap, outputs, elapsed, Vec::new()),
+        )
+    }
+
+    /// Builds the eroded state and its textures, which has to happen on the main thread.
+    pub fn finish_simulation(
+        &self,
+        id: usize,
+        model: &Model,
+        margin: MarginMode,
+        (mut heightmap, outputs, elapsed, snapshots): SimulationResult,
+    ) -> ErodedState {
+        let new_margin = self.erosion_method.cut_margin(
+            self.heightmap_base.heightmap.width,
+            self.heightmap_base.heightmap.height,
+            margin,
+        );
+        let base = self
+            .heightmap_base
+            .heightmap
+            .with_margin(new_margin)
+            .heightmap;
+        let mut heightmap_diff = heightmap.subtract(&base).unwrap();
+        let heightmap_diff_signed = heightmap.subtract_signed(&base).unwrap();
         let heightmap_diff_normalized = heightmap_diff.clone().normalize();
         println!("Done!");
 
         heightmap.calculate_total_height();
         heightmap_diff.calculate_total_height();
+        let seams = self
+            .erosion_method
+            .seams(
+                self.heightmap_base.heightmap.width,
+                self.heightmap_base.heightmap.height,
+                margin,
+            )
+            .filter(|seams| seams.len() == heightmap.width)
+            .and_then(|seams| analysis::seam_metrics(&heightmap, &seams));
+        let material_layers = MaterialLayer::list()
+            .iter()
+            .filter_map(|&layer| {
+                let texture = material_layer_texture(heightmap.layer(layer)?);
+                Some((layer, Rc::new(texture)))
+            })
+            .collect();
         ErodedState {
             id,
             base_id: self.id,
@@ -126,9 +746,33 @@ impl BaseState {
             heightmap_difference_normalized: Rc::new(RefCell::new(vec![Rc::new(
                 heightmap_diff_normalized.into(),
             )])),
+            heightmap_difference_signed: Rc::new(RefCell::new(vec![Rc::new(
+                heightmap_diff_signed,
+            )])),
             erosion_method: Rc::new(self.erosion_method),
-            margin_removed: margin,
+            erosion_model: Rc::new(*model),
+            material_layers,
+            flow_map: outputs.flow_map().map(|flow| Rc::new(flow.into())),
+            erosion_map: outputs
+                .erosion_map()
+                .map(|eroded| Rc::new(material_layer_texture(eroded))),
+            deposition_map: outputs
+                .deposition_map()
+                .map(|deposited| Rc::new(material_layer_texture(deposited))),
+            droplet_ends: outputs
+                .spawn_map()
+                .zip(outputs.death_map())
+                .map(|(spawns, deaths)| Rc::new(DropletEnds { spawns, deaths })),
+            partitions: PartitionReport::combine(&outputs.partitions),
+            seams,
+            margin_removed: margin.is_used(),
+            custom_margins: margin.custom(),
             simulation_time: elapsed,
+            notes: String::new(),
+            timeline: snapshots
+                .into_iter()
+                .map(|(iteration, snapshot)| (iteration, Rc::new(snapshot.into())))
+                .collect(),
         }
     }
 
@@ -138,6 +782,7 @@ impl BaseState {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum SimulationState {
     Base(BaseState),
     Eroded((BaseState, ErodedState)),
@@ -149,20 +794,93 @@ impl SimulationState {
         heightmap_type: &HeightmapType,
         parameters: &Parameters,
     ) -> Self {
-        let mut heightmap = heightmap::create_heightmap_from_preset(heightmap_type);
+        let heightmap =
+            heightmap::try_create_heightmap_from_preset(heightmap_type).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                heightmap::create_heightmap_from_preset(heightmap_type)
+            });
+        Self::get_new_base_from_heightmap(new_id, heightmap, parameters)
+    }
+
+    pub fn get_new_base_from_heightmap(
+        new_id: usize,
+        mut heightmap: Heightmap,
+        parameters: &Parameters,
+    ) -> Self {
         heightmap.calculate_total_height();
         let heightmap = Rc::new(heightmap);
         SimulationState::Base(BaseState {
             id: new_id,
             erosion_method: Method::Default,
-            params: parameters.clone(),
+            adaptive_iterations: false,
+            partition_halo: 0,
+            params: *parameters,
             drop_zone: DropZone::default(&heightmap),
             heightmap_base: Rc::new((&heightmap).into()),
             heightmap_active: Rc::new((&heightmap).into()),
+            hardness: None,
+            vegetation: None,
+            notes: String::new(),
         })
     }
 
-    pub fn get_new_eroded(&self, new_id: usize, parameters: &Parameters, margin: bool) -> Self {
+    pub fn get_new_eroded(&self, new_id: usize, parameters: &AppParameters) -> Self {
+        let base = self.next_base(parameters);
+        let eroded = base.run_simulation(
+            new_id,
+            &parameters.model(),
+            &parameters.layer_params,
+            parameters.margin_mode(),
+            parameters.snapshot_interval,
+        );
+        SimulationState::Eroded((base, eroded))
+    }
+
+    /// Same as `get_new_eroded` but runs the passes of `parameters.pipeline` one after another.
+    pub fn get_new_pipeline(&self, new_id: usize, parameters: &AppParameters) -> Option<Self> {
+        let base = self.next_base(parameters);
+        let eroded = base.run_pipeline(
+            new_id,
+            &parameters.pipeline,
+            &parameters.layer_params,
+            parameters.margin_mode(),
+        )?;
+        Some(SimulationState::Eroded((base, eroded)))
+    }
+
+    /// Same as `get_new_eroded` but runs in the background, see `SimulationJob::poll`.
+    pub fn start_new_eroded(&self, parameters: &AppParameters) -> SimulationJob {
+        let base = self.next_base(parameters);
+        let model = parameters.model();
+        let progress = Progress::default();
+        let handle = base.start_simulation(
+            &model,
+            &parameters.layer_params,
+            parameters.margin_mode(),
+            parameters.snapshot_interval,
+            progress.clone(),
+        );
+        SimulationJob {
+            base,
+            model,
+            margin: parameters.margin_mode(),
+            progress,
+            handle: RefCell::new(Some(handle)),
+        }
+    }
+
+    /// Same as `get_new_eroded` but advanced a chunk at a time with `BaseState::step_simulation`.
+    pub fn start_new_incremental(
+        &self,
+        parameters: &AppParameters,
+    ) -> (BaseState, IncrementalSimulation) {
+        let base = self.next_base(parameters);
+        let simulation = base.start_incremental(&parameters.model(), &parameters.layer_params);
+        (base, simulation)
+    }
+
+    /// The base the next simulation starts from, eroded states continue from their result.
+    fn next_base(&self, parameters: &AppParameters) -> BaseState {
         let (mut base, eroded) = match self {
             SimulationState::Base(base) => (base.clone(), None),
             SimulationState::Eroded((base, eroded)) => (base.clone(), Some(eroded)),
@@ -172,15 +890,32 @@ impl SimulationState {
             base = BaseState {
                 id: eroded.id,
                 erosion_method: base.erosion_method,
-                params: parameters.clone(),
+                adaptive_iterations: base.adaptive_iterations,
+                partition_halo: base.partition_halo,
+                params: parameters.erosion_params,
                 drop_zone: base.drop_zone,
                 heightmap_base: Rc::clone(&eroded.heightmap_eroded),
                 heightmap_active: Rc::clone(&eroded.heightmap_eroded),
+                hardness: eroded
+                    .heightmap_eroded
+                    .heightmap
+                    .hardness_layer()
+                    .map(Rc::new)
+                    .or(base.hardness),
+                vegetation: eroded
+                    .heightmap_eroded
+                    .heightmap
+                    .vegetation_layer()
+                    .map(Rc::new)
+                    .or(base.vegetation),
+                notes: eroded.notes.clone(),
             };
         }
-
-        let eroded = base.run_simulation(new_id, parameters, margin);
-        SimulationState::Eroded((base, eroded))
+        base.drop_zone = base
+            .drop_zone
+            .with_sea(parameters.coastal_params.sea())
+            .with_halo(base.partition_halo);
+        base
     }
 
     pub fn base(&self) -> &BaseState {
@@ -211,6 +946,21 @@ impl SimulationState {
         }
     }
 
+    /// Free-text notes of the most recent result, the eroded state if there is one.
+    pub fn notes(&self) -> &str {
+        match self {
+            SimulationState::Base(base) => &base.notes,
+            SimulationState::Eroded((_, eroded)) => &eroded.notes,
+        }
+    }
+
+    pub fn notes_mut(&mut self) -> &mut String {
+        match self {
+            SimulationState::Base(base) => &mut base.notes,
+            SimulationState::Eroded((_, eroded)) => &mut eroded.notes,
+        }
+    }
+
     pub fn get_active_heightmap_texture(&self) -> Rc<HeightmapTexture> {
         Rc::clone(&self.base().heightmap_active)
     }
@@ -219,20 +969,21 @@ impl SimulationState {
         Rc::clone(&self.get_active_heightmap_texture().heightmap)
     }
 
-    pub fn get_active_texture(&self) -> Rc<Texture2D> {
-        if let Some(texture) = &self.get_active_heightmap_texture().texture {
-            Rc::clone(texture)
-        } else {
-            eprintln!("WARN: get_active_texture(&self) called without any active texture set!");
-            Rc::clone(&self.get_active_heightmap_texture().get_or_generate())
-        }
-    }
-
     pub fn set_active(&mut self, heightmap_texture: Rc<HeightmapTexture>) {
         self.base_mut().set_active(heightmap_texture);
     }
 
-    pub fn set_active_separate(&mut self, heightmap: Rc<Heightmap>, image: Rc<Image>) {
+    /// Replaces the heightmap returned by `get_heightmap` with an edited one and shows it.
+    pub fn set_heightmap(&mut self, heightmap: Heightmap) {
+        let texture: Rc<HeightmapTexture> = Rc::new(heightmap.into());
+        match self {
+            SimulationState::Base(base) => base.heightmap_base = Rc::clone(&texture),
+            SimulationState::Eroded((_, eroded)) => eroded.heightmap_eroded = Rc::clone(&texture),
+        }
+        self.set_active(texture);
+    }
+
+    pub fn set_active_separate(&mut self, heightmap: Rc<Heightmap>, image: Rc<LayerImage>) {
         self.set_active(Rc::new(HeightmapTexture::new(heightmap, Some(image))))
     }
 
@@ -243,6 +994,16 @@ impl SimulationState {
         }
     }
 
+    /// Short name for listing the states, such as "#3 Base".
+    pub fn name(&self) -> String {
+        match self {
+            SimulationState::Base(base) => format!("#{} Base", base.id),
+            SimulationState::Eroded((_, eroded)) => {
+                format!("#{} {}", eroded.id, eroded.erosion_method.to_string())
+            }
+        }
+    }
+
     pub fn get_heightmap(&self) -> Rc<Heightmap> {
         match self {
             SimulationState::Base(base) => Rc::clone(&base.heightmap_base.heightmap),
@@ -250,42 +1011,102 @@ impl SimulationState {
         }
     }
 
-    pub fn get_active_grid_texture(&self, app_parameters: &AppParameters) -> Texture2D {
-        let grid = if let Some(state) = self.eroded() {
-            state.erosion_method.get_grid(
+    /// A layer derived from the heights of the state, the eroded ones for eroded states.
+    pub fn derived_layer(&self, layer: DerivedLayer) -> Rc<Heightmap> {
+        Rc::new(self.get_heightmap().derived_layer(layer))
+    }
+
+    /// Cells cut off each side of the base heightmap by eroding with margins. For a base state
+    /// these are the margins the next erosion would cut off.
+    pub fn margin(&self, app_parameters: &AppParameters) -> Margins {
+        let base = self.base();
+        let (margin, method) = match self.eroded() {
+            Some(eroded) => (eroded.margin_mode(), *eroded.erosion_method),
+            None => (app_parameters.margin_mode(), base.erosion_method),
+        };
+        method.cut_margin(
+            base.heightmap_base.heightmap.width,
+            base.heightmap_base.heightmap.height,
+            margin,
+        )
+    }
+
+    /// Partition outlines matching the active heightmap, see `Method::grid_lines`.
+    /// The partitions of a base state are drawn inside the margins the next erosion cuts off.
+    pub fn get_active_grid_lines(&self, app_parameters: &AppParameters) -> Vec<Vec<(f32, f32)>> {
+        if let Some(state) = self.eroded() {
+            let margin = if state.margin_removed {
+                MarginMode::None
+            } else {
+                app_parameters.margin_mode()
+            };
+            state.erosion_method.grid_lines(
                 state.heightmap_eroded.heightmap.width,
-                !state.margin_removed && app_parameters.margin,
+                state.heightmap_eroded.heightmap.height,
+                margin,
             )
         } else {
             let state = self.base();
+            let heightmap = &state.heightmap_base.heightmap;
+            let (width, height) = (heightmap.width as f32, heightmap.height as f32);
+            let margin = self.margin(app_parameters);
+            let interior = margin.interior(heightmap.extent());
+            let point = |(x, y): (f32, f32)| {
+                (
+                    (margin.left as f32 + x * interior.width as f32) / width,
+                    (margin.top as f32 + y * interior.height as f32) / height,
+                )
+            };
             state
                 .erosion_method
-                .get_grid(state.heightmap_base.heightmap.width, app_parameters.margin)
-        };
-        let heightmap = self.get_active();
-        let grid_texture = layered_heightmaps_to_texture(
-            grid.width,
-            &vec![
-                &HeightmapLayer {
-                    heightmap: &heightmap,
-                    channel: rgba_color_channel::RGB,
-                    strength: 1.0,
-                    layer_mix_method: LayerMixMethod::Additive,
-                    inverted: false,
-                    modifies_alpha: false,
-                },
-                &HeightmapLayer {
-                    heightmap: &grid,
-                    channel: rgba_color_channel::RA,
-                    strength: 1.0,
-                    layer_mix_method: LayerMixMethod::Additive,
-                    inverted: false,
-                    modifies_alpha: false,
-                },
-            ],
-            false,
-            1.0,
+                .grid_lines(
+                    heightmap.width,
+                    heightmap.height,
+                    app_parameters.margin_mode(),
+                )
+                .into_iter()
+                .map(|line| line.into_iter().map(point).collect())
+                .collect()
+        }
+    }
+
+    /// Outline of the cells the next erosion keeps, see `Method::margin_lines`. Eroded states
+    /// have their margins cut off already.
+    pub fn get_active_margin_lines(&self, app_parameters: &AppParameters) -> Vec<Vec<(f32, f32)>> {
+        if self.eroded().is_some() {
+            return Vec::new();
+        }
+        let state = self.base();
+        state.erosion_method.margin_lines(
+            state.heightmap_base.heightmap.width,
+            state.heightmap_base.heightmap.height,
+            app_parameters.margin_mode(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{check_heightmap, tiny_heightmap, tiny_model, Check, SIZE};
+
+    #[test]
+    fn headless_simulation() -> Check {
+        let heightmap = tiny_heightmap();
+        let base = SimulationState::get_new_base_from_heightmap(0, heightmap, &Default::default());
+        let model = tiny_model();
+        let eroded = base.base().run_simulation(
+            1,
+            &model,
+            &heightmap::LayerParameters::default(),
+            MarginMode::None,
+            0,
         );
-        grid_texture
+        check_heightmap(&eroded.heightmap_eroded.heightmap, SIZE, SIZE)?;
+        // Only the images are built with the states, their textures wait for a frame to draw them
+        if eroded.heightmap_eroded.image.is_none() {
+            return Err("eroded heightmap has no image".to_string());
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::heightmap::Heightmap;
+use crate::visualize::{layer_image, LayerImage};
+
+/*
+Where the droplets of a run started and ended. Droplets dying in lines along the partition
+boundaries instead of in pits, basins and at the sea show that the partitioning cuts their paths
+short, which is easiest to see with the grid shown on top. The scatter marks every cell a droplet
+spawned or died in, the density histograms count them in square bins since single cells are too
+sparse for anything but the longest runs.
+ */
+
+const SPAWN_COLOR: [u8; 3] = [60, 220, 90];
+const DEATH_COLOR: [u8; 3] = [235, 50, 40];
+const BOTH_COLOR: [u8; 3] = [250, 220, 60];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum DropletEnd {
+    Spawn,
+    Death,
+}
+
+impl Display for DropletEnd {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DropletEnd::Spawn => f.write_str("Spawns"),
+            DropletEnd::Death => f.write_str("Deaths"),
+        }
+    }
+}
+
+/// Droplets spawned and died in each cell during a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DropletEnds {
+    pub spawns: Heightmap,
+    pub deaths: Heightmap,
+}
+
+impl DropletEnds {
+    pub fn get(&self, end: DropletEnd) -> &Heightmap {
+        match end {
+            DropletEnd::Spawn => &self.spawns,
+            DropletEnd::Death => &self.deaths,
+        }
+    }
+}
+
+/// The terrain dimmed with the cells droplets spawned in green, died in red and both in yellow.
+pub fn scatter_image(terrain: &Heightmap, ends: &DropletEnds) -> LayerImage {
+    let (spawns, deaths) = (&ends.spawns, &ends.deaths);
+    let mut bytes = Vec::with_capacity(terrain.width * terrain.height * 4);
+    for y in 0..terrain.height {
+        for x in 0..terrain.width {
+            let color = match (spawns.data[x][y] > 0.0, deaths.data[x][y] > 0.0) {
+                (true, true) => BOTH_COLOR,
+                (true, false) => SPAWN_COLOR,
+                (false, true) => DEATH_COLOR,
+                (false, false) => {
+                    let v = ((terrain.data[x][y] / terrain.depth).clamp(0.0, 1.0) * 127.0) as u8;
+                    [v, v, v]
+                }
+            };
+            bytes.extend_from_slice(&[color[0], color[1], color[2], 255]);
+        }
+    }
+    layer_image(terrain.width, terrain.height, bytes)
+}
+
+/// Counts summed over square bins of `bin` cells, every cell holding the count of its bin.
+pub fn density(counts: &Heightmap, bin: usize) -> Heightmap {
+    let bin = bin.max(1);
+    let bins_x = counts.width.div_ceil(bin);
+    let bins_y = counts.height.div_ceil(bin);
+    let mut totals = vec![vec![0.0; bins_y]; bins_x];
+    for x in 0..counts.width {
+        for y in 0..counts.height {
+            totals[x / bin][y / bin] += counts.data[x][y];
+        }
+    }
+    let data = (0..counts.width)
+        .map(|x| {
+            (0..counts.height)
+                .map(|y| totals[x / bin][y / bin])
+                .collect()
+        })
+        .collect();
+    let depth = totals
+        .iter()
+        .flat_map(|col| col.iter())
+        .fold(1.0f32, |max, &v| max.max(v));
+    Heightmap::new(data, counts.width, counts.height, depth, depth, None)
+}
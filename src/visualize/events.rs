@@ -1,22 +1,50 @@
-use crate::heightmap::{create_heightmap_from_closure, Heightmap};
+use crate::erode::{DropZone, Progress};
+use crate::heightmap::{
+    create_hardness_from_preset, create_heightmap_from_closure, create_precipitation_from_preset,
+    create_vegetation, DerivedLayer, ExtendFill, Heightmap, MaterialLayer,
+};
+#[cfg(feature = "gui")]
+use macroquad::prelude::{
+    get_frame_time, is_mouse_button_down, is_mouse_button_pressed, mouse_position, MouseButton,
+};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-#[cfg(feature = "export")]
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
 use std::mem;
 use std::rc::Rc;
 
 #[cfg(feature = "export")]
-use crate::heightmap::io::export_heightmaps;
-use crate::math::UVector2;
+use crate::heightmap::contours::{self, ContourLevel};
+#[cfg(feature = "export")]
+use crate::heightmap::indexed::{self, IndexedPalette};
+#[cfg(feature = "export")]
+use crate::heightmap::io::{crop_export, export_heightmaps};
+#[cfg(feature = "export")]
+use crate::heightmap::mesh;
+use crate::heightmap::{analysis, hydrology, segments};
+use crate::math::{Extent, Margins, UVector2, Vector2};
 
 use crate::partitioning;
-use crate::visualize::ui::{IsolineProperties, UiState};
+use crate::visualize::droplets;
+use crate::visualize::droplets::DropletEnd;
+use crate::visualize::flood::flooded_share;
+#[cfg(feature = "export")]
+use crate::visualize::flood::FloodCurve;
+#[cfg(feature = "gui")]
+use crate::visualize::heat::HeatOverlay;
+use crate::visualize::palette::{label_image, lakes_image, Palette};
+use crate::visualize::ui::{ErosionBrush, HardnessBrush, IsolineProperties, UiState};
+use crate::visualize::view::{Bookmark, View};
+use crate::visualize::workspace::Workspace;
 use crate::visualize::wrappers::HeightmapTexture;
 #[cfg(feature = "export")]
 use crate::State;
 
 use super::{
-    layered_heightmaps_to_image, mix_heightmap_to_image, rgba_color_channel, AppState,
-    HeightmapLayer, LayerMixMethod, SimulationState,
+    erosion_deposition_to_image, layered_heightmaps_to_image, mix_heightmap_to_image,
+    rgba_color_channel, sea_to_image, AppState, Composite, HeightmapLayer, LayerMixMethod,
+    SimulationState,
 };
 
 /*
@@ -45,40 +73,73 @@ ui.label("[J] Select Next Partitioning Method");
 ui.label("[K] Select Previous Partitioning Method");
  */
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum UiWindow {
     All,
     Keybinds,
     ControlPanel,
     Metadata,
     Metrics,
+    Snapshots,
+    Timeline,
+    Session,
+    Hud,
+    Region,
 }
 
-impl UiWindow {
-    pub fn to_string(self) -> String {
+impl Display for UiWindow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            UiWindow::All => "All UI".to_string(),
-            UiWindow::Keybinds => "Keybinds UI".to_string(),
-            UiWindow::ControlPanel => "Control Panel UI".to_string(),
-            UiWindow::Metadata => "Metadata UI".to_string(),
-            UiWindow::Metrics => "Metrics UI".to_string(),
+            UiWindow::All => f.write_str("All UI"),
+            UiWindow::Keybinds => f.write_str("Keybinds UI"),
+            UiWindow::ControlPanel => f.write_str("Control Panel UI"),
+            UiWindow::Metadata => f.write_str("Metadata UI"),
+            UiWindow::Metrics => f.write_str("Metrics UI"),
+            UiWindow::Snapshots => f.write_str("Snapshots UI"),
+            UiWindow::Timeline => f.write_str("Timeline UI"),
+            UiWindow::Session => f.write_str("Session UI"),
+            UiWindow::Hud => f.write_str("Performance HUD"),
+            UiWindow::Region => f.write_str("Region UI"),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum UiEvent {
     NewHeightmap,
     ReplaceHeightmap,
     Clear,
     #[cfg(feature = "export")]
     ExportHeightmap,
+    #[cfg(feature = "export")]
+    ExportMasks,
+    #[cfg(feature = "export")]
+    ExportSunSequence,
+    #[cfg(feature = "export")]
+    ExportMesh,
+    #[cfg(feature = "export")]
+    ExportSegments,
+    #[cfg(feature = "export")]
+    ExportContours,
+    #[cfg(feature = "export")]
+    ExportFloodSequence,
     RunSimulation,
+    RunPipeline,
+    UndoSculpt,
+    Undo,
+    Redo,
+    StartSimulation,
+    CancelSimulation,
+    AnimateSimulation,
     ToggleUi(UiWindow),
     Quit,
+    ForceQuit,
+    AppendSessionLog,
     ShowBaseLayer,
+    ShowTimelineFrame(usize),
     ShowDifference,
     ShowDifferenceNormalized,
+    ShowDifferenceSigned,
     NextPartitioningMethod,
     PreviousPartitioningMethod,
     SelectMethod(partitioning::Method),
@@ -92,6 +153,56 @@ pub enum UiEvent {
     EdgeDetect,
     BlurEdgeDetect,
     Isoline,
+    GenerateHardness,
+    ClearHardness,
+    ShowDropZone,
+    ClearDropZone,
+    GeneratePrecipitation,
+    ShowPrecipitation,
+    ClearPrecipitation,
+    ShowSea,
+    ShowHillshade,
+    Segment,
+    ShowBasins,
+    ShowFlowAccumulation,
+    FillDepressions,
+    ShowLakes,
+    LayerMath,
+    Resample,
+    Crop {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+    Extend(Margins, ExtendFill),
+    ShowDropletScatter,
+    ShowDropletDensity(DropletEnd),
+    SeaLevelFromIsoline,
+    ShowHardness,
+    GenerateVegetation,
+    ClearVegetation,
+    ShowVegetation,
+    ShowMaterialLayer(MaterialLayer),
+    ShowDerivedLayer(DerivedLayer),
+    MeasureTerrain,
+    ShowFlowMap,
+    ShowErosionMap,
+    ShowDepositionMap,
+    ShowErosionDeposition,
+    SelectPalette(Palette),
+    ResetView,
+    GotoCoordinate,
+    AddBookmark,
+    GotoBookmark(usize),
+    RemoveBookmark(usize),
+    LoadVectorOverlay,
+    ClearVectorOverlay,
+    OpenSnapshotArchive,
+    LoadSnapshotHeightmap(usize),
+    SelectWorkspace(usize),
+    SaveWorkspace,
+    DeleteWorkspace(usize),
     #[cfg(feature = "export")]
     ExportState,
     #[cfg(feature = "export")]
@@ -100,9 +211,111 @@ pub enum UiEvent {
     ExportStateAs,
     #[cfg(feature = "export")]
     ExportActiveHeightmap,
+    #[cfg(feature = "export")]
+    ExportCompositeLayers,
 }
 
 impl UiEvent {
+    /// Whether the event changes the simulation states, which are recorded before so it can be
+    /// undone.
+    pub fn is_undoable(self) -> bool {
+        matches!(
+            self,
+            UiEvent::NewHeightmap
+                | UiEvent::ReplaceHeightmap
+                | UiEvent::RunSimulation
+                | UiEvent::RunPipeline
+                | UiEvent::UndoSculpt
+                | UiEvent::NextPartitioningMethod
+                | UiEvent::PreviousPartitioningMethod
+                | UiEvent::SelectMethod(_)
+                | UiEvent::Blur
+                | UiEvent::EdgeDetect
+                | UiEvent::BlurEdgeDetect
+                | UiEvent::Isoline
+                | UiEvent::FillDepressions
+                | UiEvent::LayerMath
+                | UiEvent::Resample
+                | UiEvent::Crop { .. }
+                | UiEvent::Extend(..)
+                | UiEvent::GenerateHardness
+                | UiEvent::ClearHardness
+                | UiEvent::GenerateVegetation
+                | UiEvent::ClearVegetation
+                | UiEvent::ClearDropZone
+                | UiEvent::GeneratePrecipitation
+                | UiEvent::ClearPrecipitation
+                | UiEvent::LoadSnapshotHeightmap(_)
+        )
+    }
+
+    /// Whether the event changes the session in a way that is lost if it is not saved.
+    pub fn changes_session(self) -> bool {
+        matches!(
+            self,
+            UiEvent::NewHeightmap
+                | UiEvent::ReplaceHeightmap
+                | UiEvent::RunSimulation
+                | UiEvent::RunPipeline
+                | UiEvent::UndoSculpt
+                | UiEvent::Undo
+                | UiEvent::Redo
+                | UiEvent::StartSimulation
+                | UiEvent::AnimateSimulation
+                | UiEvent::NextPartitioningMethod
+                | UiEvent::PreviousPartitioningMethod
+                | UiEvent::SelectMethod(_)
+                | UiEvent::Blur
+                | UiEvent::EdgeDetect
+                | UiEvent::BlurEdgeDetect
+                | UiEvent::Isoline
+                | UiEvent::FillDepressions
+                | UiEvent::LayerMath
+                | UiEvent::Resample
+                | UiEvent::Crop { .. }
+                | UiEvent::Extend(..)
+                | UiEvent::GenerateHardness
+                | UiEvent::ClearHardness
+                | UiEvent::GenerateVegetation
+                | UiEvent::ClearVegetation
+                | UiEvent::ClearDropZone
+                | UiEvent::GeneratePrecipitation
+                | UiEvent::ClearPrecipitation
+                | UiEvent::SeaLevelFromIsoline
+                | UiEvent::AddBookmark
+                | UiEvent::RemoveBookmark(_)
+                | UiEvent::LoadSnapshotHeightmap(_)
+        )
+    }
+
+    /// Whether the event shows a layer, which a workspace shows again when switched to.
+    pub fn is_visualization(self) -> bool {
+        matches!(
+            self,
+            UiEvent::ShowErodedLayer
+                | UiEvent::ShowDifferenceNormalized
+                | UiEvent::ShowDifferenceSigned
+                | UiEvent::ShowDropZone
+                | UiEvent::ShowPrecipitation
+                | UiEvent::ShowSea
+                | UiEvent::ShowHillshade
+                | UiEvent::Segment
+                | UiEvent::ShowBasins
+                | UiEvent::ShowFlowAccumulation
+                | UiEvent::ShowLakes
+                | UiEvent::ShowDropletScatter
+                | UiEvent::ShowDropletDensity(_)
+                | UiEvent::ShowHardness
+                | UiEvent::ShowVegetation
+                | UiEvent::ShowMaterialLayer(_)
+                | UiEvent::ShowDerivedLayer(_)
+                | UiEvent::ShowFlowMap
+                | UiEvent::ShowErosionMap
+                | UiEvent::ShowDepositionMap
+                | UiEvent::ShowErosionDeposition
+        )
+    }
+
     pub fn info(self) -> String {
         match self {
             UiEvent::NewHeightmap => "Generate new heightmap".to_string(),
@@ -110,12 +323,35 @@ impl UiEvent {
             UiEvent::Clear => "Clear simulations".to_string(),
             #[cfg(feature = "export")]
             UiEvent::ExportHeightmap => "Export layers".to_string(),
+            #[cfg(feature = "export")]
+            UiEvent::ExportMasks => "Export masks as indexed pngs".to_string(),
+            #[cfg(feature = "export")]
+            UiEvent::ExportSunSequence => "Export a rotating sun image sequence".to_string(),
+            #[cfg(feature = "export")]
+            UiEvent::ExportMesh => "Export the selected heightmap as a mesh".to_string(),
+            #[cfg(feature = "export")]
+            UiEvent::ExportSegments => "Export the segmentation label map".to_string(),
+            #[cfg(feature = "export")]
+            UiEvent::ExportContours => "Export the contour lines as vectors".to_string(),
+            #[cfg(feature = "export")]
+            UiEvent::ExportFloodSequence => "Export a rising water image sequence".to_string(),
             UiEvent::RunSimulation => "Run simulation".to_string(),
-            UiEvent::ToggleUi(window) => format!("Toggles {}", window.to_string()).to_string(),
+            UiEvent::RunPipeline => "Run erosion pipeline".to_string(),
+            UiEvent::UndoSculpt => "Undo the last sculpting stroke".to_string(),
+            UiEvent::Undo => "Undo".to_string(),
+            UiEvent::Redo => "Redo".to_string(),
+            UiEvent::StartSimulation => "Run simulation in the background".to_string(),
+            UiEvent::CancelSimulation => "Cancel running simulation".to_string(),
+            UiEvent::AnimateSimulation => "Run simulation step by step".to_string(),
+            UiEvent::ToggleUi(window) => format!("Toggles {}", window).to_string(),
             UiEvent::Quit => "Quit".to_string(),
+            UiEvent::ForceQuit => "Quit without saving".to_string(),
+            UiEvent::AppendSessionLog => "Append Session Summary to Log".to_string(),
             UiEvent::ShowBaseLayer => "Show base layer".to_string(),
+            UiEvent::ShowTimelineFrame(_) => "Show erosion timeline frame".to_string(),
             UiEvent::ShowDifference => "Show difference".to_string(),
             UiEvent::ShowDifferenceNormalized => "Show difference normalized".to_string(),
+            UiEvent::ShowDifferenceSigned => "Show signed difference".to_string(),
             UiEvent::NextPartitioningMethod => "Select next partitioning method".to_string(),
             UiEvent::PreviousPartitioningMethod => {
                 "Select previous partitioning method".to_string()
@@ -135,6 +371,72 @@ impl UiEvent {
                 "Apply blur then canny edge detection to selected state".to_string()
             }
             UiEvent::Isoline => "Show isoline".to_string(),
+            UiEvent::GenerateHardness => "Generate rock hardness map".to_string(),
+            UiEvent::ClearHardness => "Clear rock hardness map".to_string(),
+            UiEvent::ShowDropZone => "Show drop zone mask".to_string(),
+            UiEvent::ClearDropZone => "Let drops spawn everywhere".to_string(),
+            UiEvent::GeneratePrecipitation => "Generate precipitation map".to_string(),
+            UiEvent::ShowPrecipitation => "Show precipitation map".to_string(),
+            UiEvent::ClearPrecipitation => "Clear precipitation map".to_string(),
+            UiEvent::ShowSea => "Show terrain below the sea level".to_string(),
+            UiEvent::ShowHillshade => "Show hillshade".to_string(),
+            UiEvent::Segment => "Segment the terrain into regions".to_string(),
+            UiEvent::ShowBasins => "Show the drainage basins".to_string(),
+            UiEvent::ShowFlowAccumulation => "Show the flow accumulation".to_string(),
+            UiEvent::FillDepressions => "Fill the depressions of selected state".to_string(),
+            UiEvent::ShowLakes => "Show the lakes the terrain holds".to_string(),
+            UiEvent::LayerMath => "Combine selected state with another state".to_string(),
+            UiEvent::Resample => "Resample selected state to another size".to_string(),
+            UiEvent::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => format!(
+                "Crop selected state to {}x{} at ({}, {})",
+                width, height, x, y
+            ),
+            UiEvent::Extend(margins, fill) => format!(
+                "Extend selected state by {}x{} cells ({})",
+                margins.horizontal(),
+                margins.vertical(),
+                fill
+            ),
+            UiEvent::ShowDropletScatter => "Show where droplets spawned and died".to_string(),
+            UiEvent::ShowDropletDensity(end) => {
+                format!("Show droplet {} density", end.to_string().to_lowercase())
+            }
+            UiEvent::SeaLevelFromIsoline => "Use isoline height as sea level".to_string(),
+            UiEvent::ShowHardness => "Show rock hardness map".to_string(),
+            UiEvent::GenerateVegetation => "Generate vegetation map".to_string(),
+            UiEvent::ClearVegetation => "Clear vegetation map".to_string(),
+            UiEvent::ShowVegetation => "Show vegetation map".to_string(),
+            UiEvent::ShowMaterialLayer(layer) => {
+                format!("Show {} layer", layer.to_string().to_lowercase())
+            }
+            UiEvent::ShowDerivedLayer(layer) => {
+                format!("Show {} layer", layer.to_string().to_lowercase())
+            }
+            UiEvent::MeasureTerrain => "Measure the shape of every state".to_string(),
+            UiEvent::ShowFlowMap => "Show water flow map".to_string(),
+            UiEvent::ShowErosionMap => "Show eroded material".to_string(),
+            UiEvent::ShowDepositionMap => "Show deposited material".to_string(),
+            UiEvent::ShowErosionDeposition => "Show erosion and deposition".to_string(),
+            UiEvent::SelectPalette(palette) => format!("Select {} palette", palette),
+            UiEvent::ResetView => "Reset zoom and position".to_string(),
+            UiEvent::GotoCoordinate => "Go to coordinate".to_string(),
+            UiEvent::AddBookmark => "Bookmark current view".to_string(),
+            UiEvent::GotoBookmark(index) => format!("Go to bookmark #{}", index),
+            UiEvent::RemoveBookmark(index) => format!("Remove bookmark #{}", index),
+            UiEvent::LoadVectorOverlay => "Load GeoJSON overlay".to_string(),
+            UiEvent::ClearVectorOverlay => "Clear GeoJSON overlay".to_string(),
+            UiEvent::OpenSnapshotArchive => "Open snapshot file".to_string(),
+            UiEvent::LoadSnapshotHeightmap(index) => {
+                format!("Load heightmap of snapshot #{}", index)
+            }
+            UiEvent::SelectWorkspace(index) => format!("Switch to workspace #{}", index),
+            UiEvent::SaveWorkspace => "Save the ui layout as a workspace".to_string(),
+            UiEvent::DeleteWorkspace(index) => format!("Delete workspace #{}", index),
             #[cfg(feature = "export")]
             UiEvent::ExportState => "Export State".to_string(),
             #[cfg(feature = "export")]
@@ -143,6 +445,8 @@ impl UiEvent {
             UiEvent::ExportStateAs => "Export State As".to_string(),
             #[cfg(feature = "export")]
             UiEvent::ExportActiveHeightmap => "Export Visible Image".to_string(),
+            #[cfg(feature = "export")]
+            UiEvent::ExportCompositeLayers => "Export Visible Composite Layers".to_string(),
         }
     }
 }
@@ -153,20 +457,18 @@ fn get_or_calculate_selected_diff_index(state: &AppState) -> Option<usize> {
             Some(i)
         } else {
             let heightmap = &eroded.heightmap_eroded.heightmap;
-            let mut heightmap_diff = heightmap
-                .subtract(
-                    if let Some(eroded) =
-                        state.simulation_states[*eroded.selected_diff.borrow()].eroded()
-                    {
-                        &eroded.heightmap_eroded.heightmap
-                    } else {
-                        &state.simulation_states[*eroded.selected_diff.borrow()]
-                            .base()
-                            .heightmap_base
-                            .heightmap
-                    },
-                )
-                .unwrap();
+            let compared = if let Some(eroded) =
+                state.simulation_states[*eroded.selected_diff.borrow()].eroded()
+            {
+                &eroded.heightmap_eroded.heightmap
+            } else {
+                &state.simulation_states[*eroded.selected_diff.borrow()]
+                    .base()
+                    .heightmap_base
+                    .heightmap
+            };
+            let mut heightmap_diff = heightmap.subtract(compared).unwrap();
+            let heightmap_diff_signed = heightmap.subtract_signed(compared).unwrap();
             heightmap_diff.calculate_total_height();
             let heightmap_diff_normalized = heightmap_diff.clone().normalize();
 
@@ -178,6 +480,10 @@ fn get_or_calculate_selected_diff_index(state: &AppState) -> Option<usize> {
                 .heightmap_difference_normalized
                 .borrow_mut()
                 .push(Rc::new(heightmap_diff_normalized.into()));
+            eroded
+                .heightmap_difference_signed
+                .borrow_mut()
+                .push(Rc::new(heightmap_diff_signed));
             eroded
                 .diffs
                 .borrow_mut()
@@ -203,6 +509,20 @@ fn push_base(app_state: &mut AppState) {
         .push(app_state.simulation_states.len() - 1);
 }
 
+/// Adds the heightmap as a new base state and selects it.
+fn push_base_heightmap(app_state: &mut AppState, heightmap: Heightmap) {
+    app_state
+        .simulation_states
+        .push(SimulationState::get_new_base_from_heightmap(
+            app_state.simulation_states.len(),
+            heightmap,
+            &app_state.parameters.erosion_params,
+        ));
+    app_state
+        .simulation_base_indices
+        .push(app_state.simulation_states.len() - 1);
+}
+
 fn try_set_eroded_layer_active(state: &mut AppState) {
     let texture = if let Some(eroded) = state.simulation_state().eroded() {
         Some(Rc::clone(&eroded.heightmap_eroded))
@@ -215,6 +535,230 @@ fn try_set_eroded_layer_active(state: &mut AppState) {
     }
 }
 
+/// Shows a data layer such as a difference or heatmap, coloured with the selected palette.
+/// The derived layers of `heightmap` with their file names, `suffix` appended. Signed layers are
+/// left out since the pngs can't hold negative values.
+#[cfg(feature = "export")]
+fn derived_layers(heightmap: &Heightmap, suffix: &str) -> Vec<(Heightmap, String)> {
+    DerivedLayer::list()
+        .into_iter()
+        .filter(|layer| !layer.is_signed())
+        .map(|layer| {
+            (
+                heightmap.derived_layer(layer),
+                format!("{}{}", layer.file_name(), suffix),
+            )
+        })
+        .collect()
+}
+
+fn show_data_layer(app_state: &mut AppState, palette: Palette, texture: Rc<HeightmapTexture>) {
+    let texture = if palette == Palette::Grayscale {
+        texture
+    } else {
+        Rc::new(HeightmapTexture::from_palette(
+            Rc::clone(&texture.heightmap),
+            palette,
+        ))
+    };
+    app_state.simulation_state_mut().set_active(texture);
+}
+
+fn show_drop_zone(app_state: &mut AppState, palette: Palette) {
+    if let Some(mask) = app_state.simulation_state().base().drop_zone.get_mask() {
+        let texture = Rc::new(mask.clone().into());
+        show_data_layer(app_state, palette, texture);
+    }
+}
+
+fn show_precipitation(app_state: &mut AppState, palette: Palette) {
+    if let Some(precipitation) = app_state
+        .simulation_state()
+        .base()
+        .drop_zone
+        .get_precipitation()
+    {
+        let texture = Rc::new(precipitation.clone().into());
+        show_data_layer(app_state, palette, texture);
+    }
+}
+
+fn show_hardness(app_state: &mut AppState, palette: Palette) {
+    if let Some(hardness) = app_state.simulation_state().base().hardness.clone() {
+        show_data_layer(app_state, palette, Rc::new((&hardness).into()));
+    }
+}
+
+fn show_vegetation(app_state: &mut AppState, palette: Palette) {
+    if let Some(vegetation) = app_state.simulation_state().base().vegetation.clone() {
+        show_data_layer(app_state, palette, Rc::new((&vegetation).into()));
+    }
+}
+
+/// Adds the result of a finished background simulation as the new selected state.
+/// An animated simulation is advanced by one chunk and its progress shown instead.
+#[cfg(feature = "gui")]
+pub fn poll_simulation_job(app_state: &mut AppState, heat_overlay: &HeatOverlay) {
+    if let Some(incremental) = app_state.incremental_simulation.clone() {
+        let finished = {
+            let (base, simulation) = &mut *incremental.borrow_mut();
+            simulation.update_heat(heat_overlay, get_frame_time());
+            let chunk = simulation.chunk;
+            base.step_simulation(simulation, chunk);
+            if !simulation.is_finished() {
+                let preview = Rc::new(simulation.preview());
+                app_state.simulation_state_mut().set_active(preview);
+            }
+            simulation.is_finished()
+        };
+        if finished {
+            app_state.incremental_simulation = None;
+            let (base, simulation) = Rc::try_unwrap(incremental)
+                .expect("incremental simulation is only shared with the app state")
+                .into_inner();
+            let eroded = base.finish_incremental(app_state.simulation_states.len(), simulation);
+            app_state.session.record_simulation(&eroded);
+            app_state.record_history();
+            app_state
+                .simulation_states
+                .push(SimulationState::Eroded((base, eroded)));
+            app_state
+                .simulation_base_indices
+                .push(app_state.simulation_states.len() - 1);
+            try_set_eroded_layer_active(app_state);
+        }
+        return;
+    }
+
+    let finished = match &app_state.simulation_job {
+        Some(job) => job.poll(app_state.simulation_states.len()),
+        None => return,
+    };
+    if let Some(simulation_state) = finished {
+        app_state.simulation_job = None;
+        if let Some(simulation_state) = simulation_state {
+            if let Some(eroded) = simulation_state.eroded() {
+                app_state.session.record_simulation(eroded);
+            }
+            app_state.record_history();
+            app_state.simulation_states.push(simulation_state);
+            app_state
+                .simulation_base_indices
+                .push(app_state.simulation_states.len() - 1);
+            try_set_eroded_layer_active(app_state);
+        }
+    }
+}
+
+/// Blends `brush.value` into `layer` around `center`, fading out towards the brush edge.
+fn paint(brush: &HardnessBrush, layer: &mut Heightmap, center: (f32, f32)) {
+    let (center_x, center_y) = center;
+    let min_x = (center_x - brush.radius).max(0.0) as usize;
+    let min_y = (center_y - brush.radius).max(0.0) as usize;
+    let max_x = ((center_x + brush.radius) as usize).min(layer.width - 1);
+    let max_y = ((center_y + brush.radius) as usize).min(layer.height - 1);
+    for x in min_x..=max_x {
+        for y in min_y..=max_y {
+            let distance = ((x as f32 - center_x).powi(2) + (y as f32 - center_y).powi(2)).sqrt();
+            if distance < brush.radius {
+                let weight = (1.0 - distance / brush.radius) * brush.strength;
+                let h = &mut layer.data[x][y];
+                *h += (brush.value - *h) * weight;
+            }
+        }
+    }
+}
+
+/// Runs `brush.droplets` iterations of the selected model on the selected state, with drops
+/// spawning only within the brush around `position` in [0, 1] heightmap space.
+fn erode_under_brush(brush: &ErosionBrush, app_state: &mut AppState, position: (f32, f32)) {
+    let mut heightmap = (*app_state.simulation_state().get_heightmap()).clone();
+    let center = Vector2::new(
+        position.0 * heightmap.width as f32,
+        position.1 * heightmap.height as f32,
+    );
+    let drop_zone = DropZone::local(&heightmap, center, brush.radius)
+        .with_sea(app_state.parameters.coastal_params.sea());
+    let model = app_state.parameters.model().with_iterations(brush.droplets);
+    model.erode(&mut heightmap, &drop_zone, &Progress::default());
+    app_state.simulation_state_mut().set_heightmap(heightmap);
+}
+
+/// Paints into the rock hardness map or drop zone mask of the selected base layer, or erodes
+/// under the cursor, while the left mouse button is held, returns whether anything was painted.
+#[cfg(feature = "gui")]
+pub fn poll_brushes(
+    ui_state: &UiState,
+    app_state: &mut AppState,
+    canvas_rect: &egui::Rect,
+) -> bool {
+    let pointer_captured = ui_state
+        .frame_slots
+        .as_ref()
+        .map(|slots| slots.pointer_captured)
+        .unwrap_or(false);
+    let painting = ui_state.hardness_brush.painting
+        || ui_state.drop_zone_brush.painting
+        || ui_state.erosion_brush.painting;
+    if !painting || pointer_captured || !is_mouse_button_down(MouseButton::Left) {
+        return false;
+    }
+
+    if is_mouse_button_pressed(MouseButton::Left) {
+        app_state.record_history();
+    }
+    match ui_state.view.to_heightmap(canvas_rect, mouse_position()) {
+        Some(position) => {
+            paint_brushes(ui_state, app_state, position);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Paints with the active brush at `position` in [0, 1] heightmap space, see `poll_brushes`.
+pub fn paint_brushes(ui_state: &UiState, app_state: &mut AppState, position: (f32, f32)) {
+    if ui_state.erosion_brush.painting {
+        erode_under_brush(&ui_state.erosion_brush, app_state, position);
+        return;
+    }
+    let base = app_state.simulation_state().base();
+    let heightmap = Rc::clone(&base.heightmap_base.heightmap);
+    let (width, height) = (heightmap.width, heightmap.height);
+    let center = (position.0 * width as f32, position.1 * height as f32);
+
+    if ui_state.hardness_brush.painting {
+        let mut hardness = base
+            .hardness
+            .as_ref()
+            .map(|hardness| (**hardness).clone())
+            .unwrap_or_else(|| Heightmap::new_empty(width, height, 1.0, 1.0));
+        paint(&ui_state.hardness_brush, &mut hardness, center);
+        app_state.simulation_state_mut().base_mut().hardness = Some(Rc::new(hardness));
+        show_hardness(app_state, ui_state.palette);
+    } else if ui_state.drop_zone_brush.painting {
+        let brush = ui_state.drop_zone_brush;
+        let mut mask = match app_state.simulation_state().base().drop_zone.get_mask() {
+            Some(mask) => mask.clone(),
+            // Painting drops in starts from nothing, erasing them starts from everywhere
+            None if brush.value > 0.5 => Heightmap::new_empty(width, height, 1.0, 1.0),
+            None => Heightmap::new(
+                vec![vec![1.0; height]; width],
+                width,
+                height,
+                1.0,
+                1.0,
+                None,
+            ),
+        };
+        paint(&brush, &mut mask, center);
+        let drop_zone = &mut app_state.simulation_state_mut().base_mut().drop_zone;
+        let precipitation = drop_zone.get_precipitation().cloned();
+        *drop_zone = DropZone::mask(&heightmap, mask).with_precipitation(precipitation);
+        show_drop_zone(app_state, ui_state.palette);
+    }
+}
+
 fn poll_ui_events_pre_check(ui_state: &mut UiState) {
     for event in ui_state.ui_events.clone() {
         match event {
@@ -238,6 +782,12 @@ pub fn poll_ui_events(
 
     let mut next_frame_events = Vec::new();
     for event in ui_state.ui_events.clone().iter() {
+        if event.is_undoable() {
+            app_state.record_history();
+        }
+        if event.is_visualization() {
+            ui_state.workspaces.visualization = Some(*event);
+        }
         match event {
             UiEvent::NewHeightmap => {
                 push_base(app_state);
@@ -252,71 +802,319 @@ pub fn poll_ui_events(
                 ui_state.simulation_clear = true;
             }
             #[cfg(feature = "export")]
-            UiEvent::ExportHeightmap => match app_state.simulation_state() {
-                SimulationState::Base(base) => {
-                    export_heightmaps(
-                        vec![&base.heightmap_base.heightmap],
-                        "output",
-                        vec!["heightmap"],
-                    );
-                }
-                SimulationState::Eroded((base, eroded)) => {
-                    let diff_index: usize =
-                        if let Some(i) = eroded.diff_index_of(&eroded.selected_diff.borrow()) {
-                            i
-                        } else {
-                            0
-                        };
-                    export_heightmaps(
-                        vec![
-                            &base.heightmap_base.heightmap,
+            UiEvent::ExportHeightmap => {
+                app_state.session.record_export();
+                let formats = &ui_state.export_formats;
+                let vegetation = app_state.simulation_state().base().vegetation.clone();
+                let base = Rc::clone(&app_state.simulation_state().base().heightmap_base.heightmap);
+                let margin = app_state.simulation_state().margin(&app_state.parameters);
+                let crop = ui_state.export_crop;
+                // Every layer is cropped against the base, the eroded heightmap is framed by the
+                // base heights it was eroded from and the other interior layers by 0
+                let export = |heightmaps: Vec<&Heightmap>, filenames: Vec<&str>| {
+                    let cropped: Vec<Heightmap> = heightmaps
+                        .iter()
+                        .zip(filenames.iter())
+                        .map(|(heightmap, &filename)| {
+                            let fill = match filename {
+                                "heightmap_eroded" => Some(base.as_ref()),
+                                _ => None,
+                            };
+                            crop_export(heightmap, base.extent(), margin, crop, fill)
+                        })
+                        .collect();
+                    println!("Exporting heightmaps...");
+                    for failure in
+                        export_heightmaps(cropped.iter().collect(), "output", filenames, formats)
+                    {
+                        println!("{}", failure);
+                    }
+                };
+                match app_state.simulation_state() {
+                    SimulationState::Base(base) => {
+                        let derived = derived_layers(&base.heightmap_base.heightmap, "");
+                        let mut heightmaps = vec![base.heightmap_base.heightmap.as_ref()];
+                        let mut filenames = vec!["heightmap"];
+                        if let Some(vegetation) = &vegetation {
+                            heightmaps.push(vegetation);
+                            filenames.push("vegetation");
+                        }
+                        for (layer, filename) in derived.iter() {
+                            heightmaps.push(layer);
+                            filenames.push(filename);
+                        }
+                        export(heightmaps, filenames);
+                    }
+                    SimulationState::Eroded((base, eroded)) => {
+                        let diff_index: usize =
+                            if let Some(i) = eroded.diff_index_of(&eroded.selected_diff.borrow()) {
+                                i
+                            } else {
+                                0
+                            };
+                        let difference = eroded.heightmap_difference.borrow();
+                        let difference_normalized = eroded.heightmap_difference_normalized.borrow();
+                        let mut heightmaps = vec![
+                            base.heightmap_base.heightmap.as_ref(),
                             &eroded.heightmap_eroded.heightmap,
-                            &eroded.heightmap_difference.borrow()[diff_index].heightmap,
-                            &eroded.heightmap_difference_normalized.borrow()[diff_index].heightmap,
-                        ],
-                        "output",
-                        vec![
+                            &difference[diff_index].heightmap,
+                            &difference_normalized[diff_index].heightmap,
+                        ];
+                        let mut filenames = vec![
                             "heightmap",
                             "heightmap_eroded",
                             "heightmap_diff",
                             "heightmap_diff_normalized",
-                        ],
-                    );
+                        ];
+                        if let Some(vegetation) = &vegetation {
+                            heightmaps.push(vegetation);
+                            filenames.push("vegetation");
+                        }
+                        let derived = derived_layers(&base.heightmap_base.heightmap, "");
+                        let derived_eroded =
+                            derived_layers(&eroded.heightmap_eroded.heightmap, "_eroded");
+                        for (layer, filename) in derived.iter().chain(derived_eroded.iter()) {
+                            heightmaps.push(layer);
+                            filenames.push(filename);
+                        }
+                        for (map, filename) in [
+                            (&eroded.flow_map, "flow"),
+                            (&eroded.erosion_map, "erosion"),
+                            (&eroded.deposition_map, "deposition"),
+                        ] {
+                            if let Some(map) = map {
+                                heightmaps.push(&map.heightmap);
+                                filenames.push(filename);
+                            }
+                        }
+                        export(heightmaps, filenames);
+                    }
                 }
-            },
-            UiEvent::ToggleUi(ui_window) => match ui_window {
-                UiWindow::All => {
-                    ui_state.show_ui_all = !ui_state.show_ui_all;
+            }
+            #[cfg(feature = "export")]
+            UiEvent::ExportMasks => {
+                app_state.session.record_export();
+                export_masks(app_state, ui_state);
+            }
+            #[cfg(feature = "export")]
+            UiEvent::ExportSunSequence => {
+                let suffix = ui_state.screenshots;
+                let name = state_name.as_deref().unwrap_or(crate::io::DEFAULT_NAME);
+                let heightmap = app_state.simulation_state().get_heightmap();
+                println!("Exporting sun sequence...");
+                for (frame, image) in ui_state.hillshade.sequence(&heightmap).iter().enumerate() {
+                    let path = format!("{}-sun-{}-{:03}.png", name, suffix, frame);
+                    if let Err(err) = image.save(&path) {
+                        eprintln!("Failed to export {}: {:?}", path, err);
+                    }
+                }
+                ui_state.screenshots += 1;
+                app_state.session.record_export();
+            }
+            #[cfg(feature = "export")]
+            UiEvent::ExportFloodSequence => {
+                let suffix = ui_state.screenshots;
+                let name = state_name.as_deref().unwrap_or(crate::io::DEFAULT_NAME);
+                let isoline = ui_state.isoline;
+                let mut curve = FloodCurve::default();
+                println!("Exporting flood sequence...");
+                for (frame, level) in ui_state.flood_animation.levels().into_iter().enumerate() {
+                    ui_state.isoline.height = level;
+                    let (flooded, heightmap, outside, flood_line, flood_line_blurred) =
+                        compute_isoline(app_state, ui_state);
+                    let texture = get_isoline_heightmap_texture(
+                        flooded,
+                        &heightmap,
+                        &outside,
+                        &flood_line,
+                        &flood_line_blurred,
+                        ui_state,
+                    );
+                    if let Some(image) = &texture.image {
+                        let path = format!("{}-flood-{}-{:03}.png", name, suffix, frame);
+                        if let Err(err) = image.save(&path) {
+                            eprintln!("Failed to export {}: {:?}", path, err);
+                        }
+                    }
+                    if let Some(share) = ui_state.isoline.flooded_share {
+                        curve.record(level, share);
+                        ui_state.flood_animation.curve.record(level, share);
+                    }
                 }
-                UiWindow::Keybinds => {
-                    ui_state.show_ui_keybinds = !ui_state.show_ui_keybinds;
+                ui_state.isoline = isoline;
+                let csv = format!("{}-flood-{}.csv", name, suffix);
+                if let Err(e) = std::fs::write(&csv, curve.to_csv()) {
+                    eprintln!("Failed to write flood curve {}: {:?}", csv, e);
                 }
-                UiWindow::ControlPanel => {
-                    ui_state.show_ui_control_panel = !ui_state.show_ui_control_panel;
+                ui_state.screenshots += 1;
+                app_state.session.record_export();
+            }
+            #[cfg(feature = "export")]
+            UiEvent::ExportSegments => {
+                if let Some(segmentation) = &ui_state.segmentation {
+                    let name = state_name.as_deref().unwrap_or(crate::io::DEFAULT_NAME);
+                    let filename = format!("{}-segments-{}", name, ui_state.screenshots);
+                    match segments::export(segmentation, &filename) {
+                        Ok(()) => {
+                            println!("Exported label map {}.png", filename);
+                            ui_state.screenshots += 1;
+                            app_state.session.record_export();
+                        }
+                        Err(e) => eprintln!("Failed to export label map {}: {:?}", filename, e),
+                    }
+                } else {
+                    eprintln!("Nothing has been segmented yet!");
                 }
-                UiWindow::Metadata => {
-                    ui_state.show_ui_metadata = !ui_state.show_ui_metadata;
+            }
+            #[cfg(feature = "export")]
+            UiEvent::ExportContours => {
+                let name = state_name.as_deref().unwrap_or(crate::io::DEFAULT_NAME);
+                let filename = format!("{}-contours-{}", name, ui_state.screenshots);
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let lines = ui_state.isoline.contour_lines;
+                let levels = if lines.enabled {
+                    contours::trace_levels(&heightmap, lines.interval, lines.index_every)
+                } else {
+                    vec![ContourLevel {
+                        height: ui_state.isoline.height,
+                        index: false,
+                        contours: contours::trace(&heightmap, ui_state.isoline.height),
+                    }]
+                };
+                match contours::export(&heightmap, &levels, &filename, lines.format) {
+                    Ok(()) => {
+                        println!(
+                            "Exported contours {}.{}",
+                            filename,
+                            lines.format.extension()
+                        );
+                        ui_state.screenshots += 1;
+                        app_state.session.record_export();
+                    }
+                    Err(e) => eprintln!("Failed to export contours {}: {:?}", filename, e),
                 }
-                UiWindow::Metrics => {
-                    ui_state.show_ui_metrics = !ui_state.show_ui_metrics;
+            }
+            #[cfg(feature = "export")]
+            UiEvent::ExportMesh => {
+                let name = state_name.as_deref().unwrap_or(crate::io::DEFAULT_NAME);
+                let filename = format!("{}-mesh-{}", name, ui_state.screenshots);
+                let heightmap = app_state.simulation_state().get_heightmap();
+                match mesh::export(&heightmap, &filename, &ui_state.mesh) {
+                    Ok(()) => {
+                        println!(
+                            "Exported mesh {}.{}",
+                            filename,
+                            ui_state.mesh.format.extension()
+                        );
+                        ui_state.screenshots += 1;
+                        app_state.session.record_export();
+                    }
+                    Err(e) => eprintln!("Failed to export mesh {}: {:?}", filename, e),
                 }
-            },
+            }
+            UiEvent::ToggleUi(ui_window) => {
+                let shown = ui_state.window_shown_mut(*ui_window);
+                *shown = !*shown;
+            }
             UiEvent::RunSimulation => {
-                let simulation_state = app_state.simulation_state().get_new_eroded(
-                    app_state.simulation_states.len(),
-                    &app_state.parameters.erosion_params,
-                    app_state.parameters.margin,
-                );
+                let simulation_state = app_state
+                    .simulation_state()
+                    .get_new_eroded(app_state.simulation_states.len(), &app_state.parameters);
+                if let Some(eroded) = simulation_state.eroded() {
+                    app_state.session.record_simulation(eroded);
+                }
                 app_state.simulation_states.push(simulation_state);
                 app_state
                     .simulation_base_indices
                     .push(app_state.simulation_states.len() - 1);
                 try_set_eroded_layer_active(app_state);
             }
-            UiEvent::Quit => {
+            UiEvent::RunPipeline => {
+                match app_state
+                    .simulation_state()
+                    .get_new_pipeline(app_state.simulation_states.len(), &app_state.parameters)
+                {
+                    Some(simulation_state) => {
+                        if let Some(eroded) = simulation_state.eroded() {
+                            app_state.session.record_simulation(eroded);
+                        }
+                        app_state.simulation_states.push(simulation_state);
+                        app_state
+                            .simulation_base_indices
+                            .push(app_state.simulation_states.len() - 1);
+                        try_set_eroded_layer_active(app_state);
+                    }
+                    None => eprintln!("The erosion pipeline has no passes."),
+                }
+            }
+            UiEvent::UndoSculpt => {
+                if !ui_state.sculpt_brush.undo(app_state) {
+                    eprintln!("No sculpting stroke to undo on this state.");
+                }
+            }
+            UiEvent::Undo => {
+                if !app_state.undo() {
+                    eprintln!("Nothing to undo.");
+                }
+            }
+            UiEvent::Redo => {
+                if !app_state.redo() {
+                    eprintln!("Nothing to redo.");
+                }
+            }
+            UiEvent::StartSimulation => {
+                if app_state.simulation_job.is_none() && app_state.incremental_simulation.is_none()
+                {
+                    let job = app_state
+                        .simulation_state()
+                        .start_new_eroded(&app_state.parameters);
+                    app_state.simulation_job = Some(Rc::new(job));
+                }
+            }
+            UiEvent::CancelSimulation => {
+                if let Some(job) = &app_state.simulation_job {
+                    job.progress.cancel();
+                }
+                if app_state.incremental_simulation.take().is_some() {
+                    let heightmap = Rc::clone(&app_state.simulation_state().base().heightmap_base);
+                    app_state.simulation_state_mut().set_active(heightmap);
+                }
+            }
+            UiEvent::AnimateSimulation => {
+                if app_state.simulation_job.is_none() && app_state.incremental_simulation.is_none()
+                {
+                    let simulation = app_state
+                        .simulation_state()
+                        .start_new_incremental(&app_state.parameters);
+                    app_state.incremental_simulation = Some(Rc::new(RefCell::new(simulation)));
+                }
+            }
+            UiEvent::Quit | UiEvent::ForceQuit => {
                 println!("Quitting...");
                 ui_state.application_quit = true;
             }
+            UiEvent::AppendSessionLog => {
+                if let Err(err) = app_state.session.append_to_log() {
+                    eprintln!("Failed to append to the session log: {:?}", err);
+                }
+            }
+            UiEvent::ShowTimelineFrame(frame) => {
+                // Frame 0 is the base heightmap and the last frame the eroded result
+                let texture = match app_state.simulation_state().eroded() {
+                    Some(_) if *frame == 0 => Some(Rc::clone(
+                        &app_state.simulation_state().base().heightmap_base,
+                    )),
+                    Some(eroded) => eroded
+                        .timeline
+                        .get(*frame - 1)
+                        .map(|(_, snapshot)| Rc::clone(snapshot))
+                        .or_else(|| Some(Rc::clone(&eroded.heightmap_eroded))),
+                    None => None,
+                };
+                if let Some(texture) = texture {
+                    app_state.simulation_state_mut().set_active(texture);
+                }
+            }
             UiEvent::ShowBaseLayer => {
                 let heightmap = Rc::clone(&app_state.simulation_state().base().heightmap_base);
                 app_state.simulation_state_mut().set_active(heightmap);
@@ -333,7 +1131,7 @@ pub fn poll_ui_events(
                 };
 
                 if let Some(heightmap) = texture {
-                    app_state.simulation_state_mut().set_active(heightmap);
+                    show_data_layer(app_state, ui_state.palette, heightmap);
                 }
             }
             UiEvent::ShowDifferenceNormalized => {
@@ -348,7 +1146,27 @@ pub fn poll_ui_events(
                 };
 
                 if let Some(heightmap) = texture {
-                    app_state.simulation_state_mut().set_active(heightmap);
+                    show_data_layer(app_state, ui_state.palette, heightmap);
+                }
+            }
+            UiEvent::ShowDifferenceSigned => {
+                let diff = app_state.simulation_state().eroded().and_then(|eroded| {
+                    let diff_index = get_or_calculate_selected_diff_index(app_state)?;
+                    let diffs = eroded.heightmap_difference_signed.borrow();
+                    diffs.get(diff_index).map(Rc::clone)
+                });
+                // Removed material shows in red and deposited in blue unless another diverging
+                // palette is selected
+                let palette = if ui_state.palette.is_diverging() {
+                    ui_state.palette
+                } else {
+                    Palette::RedBlue
+                };
+                match diff {
+                    Some(diff) => app_state
+                        .simulation_state_mut()
+                        .set_active(Rc::new(HeightmapTexture::from_palette(diff, palette))),
+                    None => eprintln!("No signed difference for this state, erode it again!"),
                 }
             }
             UiEvent::NextPartitioningMethod => {
@@ -478,6 +1296,10 @@ pub fn poll_ui_events(
                 ui_state.isoline.flood_lower = flood_lower;
                 let (flooded, heightmap, outside, flood_line, flood_line_blurred) =
                     compute_isoline(app_state, ui_state);
+                if let Some(share) = ui_state.isoline.flooded_share {
+                    let level = ui_state.isoline.height;
+                    ui_state.flood_animation.curve.record(level, share);
+                }
 
                 let heightmap_texture = get_isoline_heightmap_texture(
                     flooded,
@@ -485,13 +1307,395 @@ pub fn poll_ui_events(
                     &outside,
                     &flood_line,
                     &flood_line_blurred,
-                    &ui_state,
+                    ui_state,
                 );
 
                 app_state
                     .simulation_state_mut()
                     .set_active(Rc::new(heightmap_texture));
             }
+            UiEvent::GenerateHardness => {
+                let hardness = create_hardness_from_preset(
+                    &app_state.simulation_state().base().heightmap_base.heightmap,
+                    &app_state.parameters.hardness_type,
+                );
+                app_state.simulation_state_mut().base_mut().hardness = Some(Rc::new(hardness));
+                show_hardness(app_state, ui_state.palette);
+            }
+            UiEvent::ClearHardness => {
+                app_state.simulation_state_mut().base_mut().hardness = None;
+                let heightmap = Rc::clone(&app_state.simulation_state().base().heightmap_base);
+                app_state.simulation_state_mut().set_active(heightmap);
+            }
+            UiEvent::ShowHardness => {
+                show_hardness(app_state, ui_state.palette);
+            }
+            UiEvent::GenerateVegetation => {
+                let base = app_state.simulation_state().base();
+                let vegetation = create_vegetation(
+                    &base.heightmap_base.heightmap,
+                    base.drop_zone.get_precipitation(),
+                    &app_state.parameters.vegetation_params,
+                );
+                app_state.simulation_state_mut().base_mut().vegetation = Some(Rc::new(vegetation));
+                show_vegetation(app_state, ui_state.palette);
+            }
+            UiEvent::ClearVegetation => {
+                app_state.simulation_state_mut().base_mut().vegetation = None;
+                let heightmap = Rc::clone(&app_state.simulation_state().base().heightmap_base);
+                app_state.simulation_state_mut().set_active(heightmap);
+            }
+            UiEvent::ShowVegetation => {
+                show_vegetation(app_state, ui_state.palette);
+            }
+            UiEvent::ShowDropZone => {
+                show_drop_zone(app_state, ui_state.palette);
+            }
+            UiEvent::ClearDropZone => {
+                let heightmap = Rc::clone(&app_state.simulation_state().base().heightmap_base);
+                let drop_zone = &mut app_state.simulation_state_mut().base_mut().drop_zone;
+                let precipitation = drop_zone.get_precipitation().cloned();
+                *drop_zone =
+                    DropZone::default(&heightmap.heightmap).with_precipitation(precipitation);
+                app_state.simulation_state_mut().set_active(heightmap);
+            }
+            UiEvent::GeneratePrecipitation => {
+                let precipitation = create_precipitation_from_preset(
+                    &app_state.simulation_state().base().heightmap_base.heightmap,
+                    &app_state.parameters.precipitation_type,
+                );
+                let drop_zone = &mut app_state.simulation_state_mut().base_mut().drop_zone;
+                *drop_zone = drop_zone.clone().with_precipitation(Some(precipitation));
+                show_precipitation(app_state, ui_state.palette);
+            }
+            UiEvent::ShowPrecipitation => {
+                show_precipitation(app_state, ui_state.palette);
+            }
+            UiEvent::ClearPrecipitation => {
+                let drop_zone = &mut app_state.simulation_state_mut().base_mut().drop_zone;
+                *drop_zone = drop_zone.clone().with_precipitation(None);
+                let heightmap = Rc::clone(&app_state.simulation_state().base().heightmap_base);
+                app_state.simulation_state_mut().set_active(heightmap);
+            }
+            UiEvent::ShowSea => {
+                let sea_level = app_state.parameters.coastal_params.sea_level;
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let image = sea_to_image(&heightmap, sea_level, ui_state.palette);
+                app_state
+                    .simulation_state_mut()
+                    .set_active(Rc::new(HeightmapTexture::new(
+                        heightmap,
+                        Some(Rc::new(image)),
+                    )));
+            }
+            UiEvent::ShowHillshade => {
+                ui_state.hillshade.show(app_state);
+            }
+            UiEvent::Segment => {
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let segmentation = segments::segment(&heightmap, &ui_state.segments);
+                let image = label_image(&segmentation);
+                ui_state.segmentation = Some(Rc::new(segmentation));
+                app_state
+                    .simulation_state_mut()
+                    .set_active(Rc::new(HeightmapTexture::new(
+                        heightmap,
+                        Some(Rc::new(image)),
+                    )));
+            }
+            UiEvent::ShowBasins => {
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let drainage = hydrology::drainage(&heightmap);
+                let image = label_image(&drainage.basins);
+                ui_state.drainage = Some(Rc::new(drainage));
+                app_state
+                    .simulation_state_mut()
+                    .set_active(Rc::new(HeightmapTexture::new(
+                        heightmap,
+                        Some(Rc::new(image)),
+                    )));
+            }
+            UiEvent::ShowFlowAccumulation => {
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let drainage = hydrology::drainage(&heightmap);
+                let accumulation = drainage.accumulation_map();
+                ui_state.drainage = Some(Rc::new(drainage));
+                show_data_layer(app_state, ui_state.palette, Rc::new(accumulation.into()));
+            }
+            UiEvent::FillDepressions => {
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let heightmap_texture = Rc::new(heightmap.fill_depressions().into());
+                app_state
+                    .simulation_state_mut()
+                    .set_active(heightmap_texture);
+            }
+            UiEvent::ShowLakes => {
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let lakes = hydrology::lakes(&heightmap);
+                let image = lakes_image(&heightmap, &lakes);
+                ui_state.lakes = Some(Rc::new(lakes));
+                app_state
+                    .simulation_state_mut()
+                    .set_active(Rc::new(HeightmapTexture::new(
+                        heightmap,
+                        Some(Rc::new(image)),
+                    )));
+            }
+            UiEvent::LayerMath => {
+                let math = ui_state.layer_math;
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let combined = app_state
+                    .simulation_states
+                    .iter()
+                    .find(|state| state.id() == math.operand)
+                    .map(|operand| {
+                        math.operation
+                            .apply(&heightmap, &operand.get_heightmap(), math.amount)
+                    });
+                match combined {
+                    Some(Ok(combined)) => push_base_heightmap(app_state, combined),
+                    Some(Err(e)) => eprintln!("Failed to combine the states: {:?}", e),
+                    None => eprintln!("There is no state #{} to combine with.", math.operand),
+                }
+            }
+            UiEvent::Resample => {
+                let size = ui_state.resample;
+                let heightmap = app_state.simulation_state().get_heightmap();
+                let resampled = heightmap.resample(size.width, size.height, size.filter);
+                push_base_heightmap(app_state, resampled);
+            }
+            UiEvent::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let heightmap = app_state.simulation_state().get_heightmap();
+                match heightmap.crop(UVector2::new(*x, *y), Extent::new(*width, *height)) {
+                    Ok(cropped) => push_base_heightmap(app_state, cropped),
+                    Err(e) => eprintln!("Failed to crop the state: {:?}", e),
+                }
+                ui_state.crop.selection.clear();
+            }
+            UiEvent::Extend(margins, fill) => {
+                let heightmap = app_state.simulation_state().get_heightmap();
+                push_base_heightmap(app_state, heightmap.extend(*margins, *fill));
+            }
+            UiEvent::ShowDropletScatter => {
+                let texture = app_state.simulation_state().eroded().and_then(|eroded| {
+                    let terrain = Rc::clone(&eroded.heightmap_eroded.heightmap);
+                    let image = droplets::scatter_image(&terrain, eroded.droplet_ends.as_ref()?);
+                    Some(HeightmapTexture::new(terrain, Some(Rc::new(image))))
+                });
+                if let Some(texture) = texture {
+                    app_state
+                        .simulation_state_mut()
+                        .set_active(Rc::new(texture));
+                }
+            }
+            UiEvent::ShowDropletDensity(end) => {
+                let density = app_state.simulation_state().eroded().and_then(|eroded| {
+                    let ends = eroded.droplet_ends.as_ref()?;
+                    Some(droplets::density(ends.get(*end), ui_state.droplet_bin))
+                });
+                if let Some(density) = density {
+                    app_state.simulation_state_mut().set_active(Rc::new(
+                        HeightmapTexture::from_palette(Rc::new(density), ui_state.palette),
+                    ));
+                }
+            }
+            UiEvent::SeaLevelFromIsoline => {
+                app_state.parameters.coastal_params.sea_level = ui_state.isoline.height;
+            }
+            UiEvent::ShowFlowMap => {
+                let texture = app_state
+                    .simulation_state()
+                    .eroded()
+                    .and_then(|eroded| eroded.flow_map.clone());
+                if let Some(texture) = texture {
+                    show_data_layer(app_state, ui_state.palette, texture);
+                }
+            }
+            UiEvent::ShowErosionMap => {
+                let texture = app_state
+                    .simulation_state()
+                    .eroded()
+                    .and_then(|eroded| eroded.erosion_map.clone());
+                if let Some(texture) = texture {
+                    show_data_layer(app_state, ui_state.palette, texture);
+                }
+            }
+            UiEvent::ShowDepositionMap => {
+                let texture = app_state
+                    .simulation_state()
+                    .eroded()
+                    .and_then(|eroded| eroded.deposition_map.clone());
+                if let Some(texture) = texture {
+                    show_data_layer(app_state, ui_state.palette, texture);
+                }
+            }
+            UiEvent::ShowErosionDeposition => {
+                let texture = app_state.simulation_state().eroded().and_then(|eroded| {
+                    let eroded_map = eroded.erosion_map.as_ref()?;
+                    let deposited_map = eroded.deposition_map.as_ref()?;
+                    let heightmap = Rc::clone(&eroded.heightmap_eroded.heightmap);
+                    let (image, composite) = erosion_deposition_to_image(
+                        &heightmap,
+                        &eroded_map.heightmap,
+                        &deposited_map.heightmap,
+                    );
+                    Some(
+                        HeightmapTexture::new(heightmap, Some(Rc::new(image)))
+                            .with_composite(composite),
+                    )
+                });
+                if let Some(texture) = texture {
+                    app_state
+                        .simulation_state_mut()
+                        .set_active(Rc::new(texture));
+                }
+            }
+            UiEvent::SelectPalette(palette) => {
+                ui_state.palette = *palette;
+            }
+            UiEvent::ResetView => {
+                ui_state.view = View::default();
+            }
+            UiEvent::GotoCoordinate => {
+                let size = app_state
+                    .simulation_state()
+                    .base()
+                    .heightmap_base
+                    .heightmap
+                    .width;
+                let (x, y) = ui_state.navigation.goto;
+                ui_state.view = View {
+                    center: (
+                        (x as f32 + 0.5) / size as f32,
+                        (y as f32 + 0.5) / size as f32,
+                    ),
+                    ..ui_state.view
+                }
+                .clamped();
+            }
+            UiEvent::AddBookmark => {
+                let name = if ui_state.navigation.bookmark_name.is_empty() {
+                    format!("Bookmark {}", app_state.bookmarks.len() + 1)
+                } else {
+                    mem::take(&mut ui_state.navigation.bookmark_name)
+                };
+                app_state.bookmarks.push(Bookmark {
+                    name,
+                    view: ui_state.view,
+                });
+            }
+            UiEvent::GotoBookmark(index) => {
+                if let Some(bookmark) = app_state.bookmarks.get(*index) {
+                    ui_state.view = bookmark.view.clamped();
+                }
+            }
+            UiEvent::RemoveBookmark(index) => {
+                if *index < app_state.bookmarks.len() {
+                    app_state.bookmarks.remove(*index);
+                }
+            }
+            UiEvent::LoadVectorOverlay => {
+                if let Err(err) = ui_state.vector_overlay.load() {
+                    eprintln!(
+                        "Failed to load GeoJSON overlay {}: {:?}",
+                        ui_state.vector_overlay.path, err
+                    );
+                }
+            }
+            UiEvent::ClearVectorOverlay => {
+                ui_state.vector_overlay.clear();
+            }
+            UiEvent::OpenSnapshotArchive => {
+                ui_state.snapshot_browser.open();
+            }
+            UiEvent::LoadSnapshotHeightmap(index) => {
+                if let Some(heightmap) = ui_state.snapshot_browser.read_heightmap(*index) {
+                    push_base_heightmap(app_state, heightmap);
+                } else {
+                    eprintln!("Failed to load heightmap of snapshot #{}!", index);
+                }
+            }
+            UiEvent::ShowMaterialLayer(layer) => {
+                let texture = app_state
+                    .simulation_state()
+                    .eroded()
+                    .and_then(|eroded| eroded.material_layer(*layer));
+                if let Some(texture) = texture {
+                    show_data_layer(app_state, ui_state.palette, texture);
+                }
+            }
+            UiEvent::MeasureTerrain => {
+                ui_state.terrain_metrics = app_state
+                    .simulation_states
+                    .iter()
+                    .map(|state| (state.name(), analysis::measure(&state.get_heightmap())))
+                    .collect();
+            }
+            UiEvent::ShowDerivedLayer(layer) => {
+                let heightmap = app_state.simulation_state().derived_layer(*layer);
+                if layer.is_signed() {
+                    let palette = if ui_state.palette.is_diverging() {
+                        ui_state.palette
+                    } else {
+                        Palette::RedBlue
+                    };
+                    app_state
+                        .simulation_state_mut()
+                        .set_active(Rc::new(HeightmapTexture::from_palette(heightmap, palette)));
+                } else {
+                    let texture = Rc::new((*heightmap).clone().into());
+                    show_data_layer(app_state, ui_state.palette, texture);
+                }
+            }
+            UiEvent::SelectWorkspace(index) => {
+                if let Some(workspace) = ui_state.workspaces.list.get(*index) {
+                    let layout = workspace.layout.clone();
+                    if let Some(event) = ui_state.apply_layout(&layout) {
+                        next_frame_events.push(event);
+                    }
+                    ui_state.workspaces.active = Some(*index);
+                }
+            }
+            UiEvent::SaveWorkspace => {
+                let layout = ui_state.layout();
+                let workspaces = &mut ui_state.workspaces;
+                let name = workspaces.name.trim().to_string();
+                let existing = match (name.is_empty(), workspaces.active) {
+                    (true, active) => active,
+                    (false, _) => workspaces.list.iter().position(|w| w.name == name),
+                };
+                match existing {
+                    Some(index) => workspaces.list[index].layout = layout,
+                    None if !name.is_empty() => {
+                        workspaces.list.push(Workspace { name, layout });
+                        workspaces.active = Some(workspaces.list.len() - 1);
+                    }
+                    None => eprintln!("Name the workspace to save the layout as!"),
+                }
+                workspaces.name.clear();
+                if let Err(err) = workspaces.save() {
+                    eprintln!("Failed to save workspaces: {:?}", err);
+                }
+            }
+            UiEvent::DeleteWorkspace(index) => {
+                let workspaces = &mut ui_state.workspaces;
+                if *index < workspaces.list.len() {
+                    workspaces.list.remove(*index);
+                    workspaces.active = match workspaces.active {
+                        Some(active) if active == *index => None,
+                        Some(active) if active > *index => Some(active - 1),
+                        active => active,
+                    };
+                    if let Err(err) = workspaces.save() {
+                        eprintln!("Failed to save workspaces: {:?}", err);
+                    }
+                }
+            }
             #[cfg(feature = "export")]
             UiEvent::ExportState => {
                 let filename = if let Some(filename) = &state_name {
@@ -504,6 +1708,7 @@ pub fn poll_ui_events(
                         state_name: state_name.clone(),
                         app_state: app_state.clone(),
                         ui_state: ui_state.clone(),
+                        dirty: false,
                     },
                     filename,
                 )
@@ -513,6 +1718,7 @@ pub fn poll_ui_events(
                         state_name: state_name.clone(),
                         app_state: app_state.clone(),
                         ui_state: ui_state.clone(),
+                        dirty: false,
                     },
                     filename,
                 )
@@ -522,10 +1728,12 @@ pub fn poll_ui_events(
                         state_name: state_name.clone(),
                         app_state: app_state.clone(),
                         ui_state: ui_state.clone(),
+                        dirty: false,
                     },
                     filename,
                 )
                 .expect("Failed to export icon!");
+                app_state.session.record_export();
             }
             #[cfg(feature = "export")]
             UiEvent::ReadState(index) => {
@@ -538,9 +1746,12 @@ pub fn poll_ui_events(
                     state_name: ref mut state_name_,
                     app_state: ref mut app_state_,
                     ui_state: ref mut ui_state_,
+                    ..
                 }) = result
                 {
                     mem::swap(state_name, state_name_);
+                    mem::swap(&mut app_state.session, &mut app_state_.session);
+                    mem::swap(&mut ui_state.workspaces, &mut ui_state_.workspaces);
                     mem::swap(app_state, app_state_);
                     mem::swap(ui_state, ui_state_);
                 } else {
@@ -558,22 +1769,91 @@ pub fn poll_ui_events(
                     .as_ref()
                     .and_then(|s| Some(s.as_str()))
                     .unwrap_or(crate::io::DEFAULT_NAME);
-                if let Some(_) = app_state
-                    .simulation_state()
-                    .get_active_heightmap_texture()
-                    .export(&format!("{}-heightmap-{}", &name, suffix))
-                {
+                let active = app_state.simulation_state().get_active_heightmap_texture();
+                let export = match (ui_state.show_legend, &active.legend) {
+                    (true, Some(legend)) => {
+                        let image = active.image.clone().unwrap_or_else(|| {
+                            Rc::new(super::heightmap_to_image_rgb(&active.heightmap))
+                        });
+                        Rc::new(HeightmapTexture {
+                            image: Some(Rc::new(crate::visualize::palette::with_legend(
+                                &image, legend,
+                            ))),
+                            heightmap: Rc::clone(&active.heightmap),
+                            legend: Some(legend.clone()),
+                            composite: None,
+                            contours: None,
+                            index_contours: None,
+                        })
+                    }
+                    _ => active,
+                };
+                if let Some(_) = export.export(&format!("{}-heightmap-{}", &name, suffix)) {
                     ui_state.screenshots += 1;
+                    app_state.session.record_export();
                 } else {
                     eprintln!("Failed to export active heightmap!");
                 }
             }
+            #[cfg(feature = "export")]
+            UiEvent::ExportCompositeLayers => {
+                let suffix = ui_state.screenshots;
+                let name = state_name.as_deref().unwrap_or(crate::io::DEFAULT_NAME);
+                let active = app_state.simulation_state().get_active_heightmap_texture();
+                if let Some(composite) = &active.composite {
+                    match crate::io::export_composite(
+                        composite,
+                        &format!("{}-composite-{}", &name, suffix),
+                    ) {
+                        Ok(()) => {
+                            ui_state.screenshots += 1;
+                            app_state.session.record_export();
+                        }
+                        Err(err) => eprintln!("Failed to export composite layers: {:?}", err),
+                    }
+                } else {
+                    eprintln!("The visible image is not a composite of several layers!");
+                }
+            }
         };
     }
     ui_state.clear_events();
     ui_state.ui_events.append(&mut next_frame_events);
 }
 
+/// Writes the binary layers of the selected state as indexed pngs with a json legend each.
+#[cfg(feature = "export")]
+fn export_masks(app_state: &AppState, ui_state: &UiState) {
+    let heightmap = app_state.simulation_state().get_heightmap();
+    let mut masks = vec![(
+        (*heightmap)
+            .clone()
+            .boolean(ui_state.isoline.height, true, false),
+        "isoline_mask",
+        IndexedPalette::binary("Below", "Above"),
+    )];
+    if let Some(sea) = app_state.parameters.coastal_params.sea() {
+        masks.push((
+            (*heightmap).clone().boolean(sea.sea_level, true, true),
+            "sea_mask",
+            IndexedPalette::binary("Land", "Sea"),
+        ));
+    }
+    if let Some(mask) = app_state.simulation_state().base().drop_zone.get_mask() {
+        masks.push((
+            mask.clone(),
+            "drop_zone_mask",
+            IndexedPalette::binary("No drops", "Drops"),
+        ));
+    }
+    println!("Exporting masks...");
+    for (mask, filename, palette) in masks {
+        if let Err(e) = indexed::export(&mask, filename, &palette) {
+            println!("Failed to save {}! Given Reason: {:?}", filename, e);
+        }
+    }
+}
+
 fn compute_isoline(
     app_state: &mut AppState,
     ui_state: &mut UiState,
@@ -608,13 +1888,14 @@ fn compute_isoline(
     } else {
         (&flood_upper, &flood_lower)
     };
+    ui_state.isoline.flooded_share = None;
     let flooded = if props.should_flood {
         let flooded = get_flooded(ui_state, &isoline, flood, flood_inverse);
 
         let mut points = Vec::new();
         let errors = unsafe {
             let pts = &mut points as *mut Vec<UVector2>;
-            create_heightmap_from_closure(flooded.width, 1.0, &|x, y| {
+            create_heightmap_from_closure(flooded.width, flooded.height, 1.0, &|x, y| {
                 let error = flooded.data[x][y] * (1. - outside.data[x][y]) > 0.0;
                 if error {
                     (*pts).push(UVector2::new(x, y));
@@ -631,7 +1912,7 @@ fn compute_isoline(
     } else {
         Rc::new(isoline)
     };
-    let flood_line = Heightmap::from_points(heightmap.width, &flood, 1.0);
+    let flood_line = Heightmap::from_points(heightmap.width, flood, 1.0);
     let flood_line_blurred = flood_line.blur(1.0).unwrap().boolean(0.0, false, false);
 
     (flooded, heightmap, outside, flood_line, flood_line_blurred)
@@ -642,8 +1923,8 @@ fn get_flood_points(
     isoline: &Heightmap,
     props: &IsolineProperties,
 ) -> (Vec<UVector2>, Vec<UVector2>) {
-    let flood_lower = heightmap.get_flood_points(&isoline, true);
-    let flood_upper = heightmap.get_flood_points(&isoline, false);
+    let flood_lower = heightmap.get_flood_points(isoline, true);
+    let flood_upper = heightmap.get_flood_points(isoline, false);
     if props.blur_augmentation.0 {
         (
             Heightmap::filter_noise_points(
@@ -671,8 +1952,9 @@ fn get_flooded(
     flood_inverse: &Vec<UVector2>,
 ) -> Rc<Heightmap> {
     let flood_amount = 1f32.min(ui_state.isoline.height + (1.0 - ui_state.isoline.height) / 3.0);
-    let (flooded, areas) = isoline.flood_empty(flood_amount, &flood);
-    let (_inv_flood, unflooded_areas) = flooded.flood_empty(flood_amount, &flood_inverse);
+    let (flooded, areas) = isoline.flood_empty(flood_amount, flood);
+    ui_state.isoline.flooded_share = Some(flooded_share(&flooded, flood_amount));
+    let (_inv_flood, unflooded_areas) = flooded.flood_empty(flood_amount, flood_inverse);
     let (_lower, _higher) = if ui_state.isoline.flood_lower {
         let l = Some((areas, unflooded_areas));
         let h = None;
@@ -695,56 +1977,113 @@ fn get_isoline_heightmap_texture(
     flood_line_blurred: &Heightmap,
     ui_state: &UiState,
 ) -> HeightmapTexture {
-    let image = if ui_state.isoline.advanced_texture {
-        Rc::new(layered_heightmaps_to_image(
+    let texture = if ui_state.isoline.advanced_texture {
+        let layers = [
+            HeightmapLayer {
+                heightmap,
+                channel: rgba_color_channel::RGB,
+                strength: 1.0,
+                layer_mix_method: LayerMixMethod::Additive,
+                inverted: false,
+                modifies_alpha: false,
+            },
+            HeightmapLayer {
+                heightmap: &flooded,
+                channel: rgba_color_channel::RGB,
+                strength: 0.5,
+                layer_mix_method: LayerMixMethod::Multiply,
+                inverted: false,
+                modifies_alpha: false,
+            },
+            HeightmapLayer {
+                heightmap: outside,
+                channel: rgba_color_channel::R,
+                strength: 0.3,
+                layer_mix_method: LayerMixMethod::Multiply,
+                inverted: false,
+                modifies_alpha: false,
+            },
+            HeightmapLayer {
+                heightmap: flood_line_blurred,
+                channel: rgba_color_channel::B,
+                strength: 0.3,
+                layer_mix_method: LayerMixMethod::AdditiveClamp,
+                inverted: false,
+                modifies_alpha: false,
+            },
+            HeightmapLayer {
+                heightmap: flood_line,
+                channel: rgba_color_channel::B,
+                strength: 1.0,
+                layer_mix_method: LayerMixMethod::AdditiveClamp,
+                inverted: false,
+                modifies_alpha: false,
+            },
+        ];
+        let layers: Vec<&HeightmapLayer> = layers.iter().collect();
+        let image = Rc::new(layered_heightmaps_to_image(
             flooded.width,
-            &vec![
-                &HeightmapLayer {
-                    heightmap: &heightmap,
-                    channel: rgba_color_channel::RGB,
-                    strength: 1.0,
-                    layer_mix_method: LayerMixMethod::Additive,
-                    inverted: false,
-                    modifies_alpha: false,
-                },
-                &HeightmapLayer {
-                    heightmap: &flooded,
-                    channel: rgba_color_channel::RGB,
-                    strength: 0.5,
-                    layer_mix_method: LayerMixMethod::Multiply,
-                    inverted: false,
-                    modifies_alpha: false,
-                },
-                &HeightmapLayer {
-                    heightmap: &outside,
-                    channel: rgba_color_channel::R,
-                    strength: 0.3,
-                    layer_mix_method: LayerMixMethod::Multiply,
-                    inverted: false,
-                    modifies_alpha: false,
-                },
-                &HeightmapLayer {
-                    heightmap: &flood_line_blurred,
-                    channel: rgba_color_channel::B,
-                    strength: 0.3,
-                    layer_mix_method: LayerMixMethod::AdditiveClamp,
-                    inverted: false,
-                    modifies_alpha: false,
-                },
-                &HeightmapLayer {
-                    heightmap: &flood_line,
-                    channel: rgba_color_channel::B,
-                    strength: 1.0,
-                    layer_mix_method: LayerMixMethod::AdditiveClamp,
-                    inverted: false,
-                    modifies_alpha: false,
-                },
-            ],
+            &layers,
             true,
             1.0,
-        ))
+        ));
+        HeightmapTexture::new(Rc::clone(&flooded), Some(image))
+            .with_composite(Composite::from_layers(&layers, true, 1.0))
     } else {
-        Rc::new(mix_heightmap_to_image(&flooded, &outside, 0, false, false))
+        let image = Rc::new(mix_heightmap_to_image(&flooded, outside, 0, false, false));
+        HeightmapTexture::new(flooded, Some(image))
     };
-    HeightmapTexture::new(flooded, Some(image))
+    let lines = ui_state.isoline.contour_lines;
+    if lines.enabled {
+        texture.with_contour_lines(heightmap, lines.interval, lines.index_every)
+    } else {
+        texture.with_contours(heightmap, ui_state.isoline.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{check_heightmap, Check, SIZE};
+    use crate::heightmap::HeightmapType;
+
+    #[test]
+    fn rectangular_painting() -> Check {
+        let mut heightmap_type = HeightmapType::default();
+        heightmap_type.params_mut().size = SIZE;
+        heightmap_type.params_mut().height = Some(SIZE * 2);
+        let mut state = crate::State::new(&heightmap_type);
+        // Low in the map, a brush mixing up the axes would land outside the upper square
+        let (x, y) = (SIZE / 4, SIZE * 3 / 2);
+        let position = (x as f32 / SIZE as f32, y as f32 / (SIZE * 2) as f32);
+
+        state.ui_state.hardness_brush.painting = true;
+        paint_brushes(&state.ui_state, &mut state.app_state, position);
+        let base = state.app_state.simulation_state().base();
+        let hardness = base
+            .hardness
+            .as_ref()
+            .ok_or("painting made no hardness layer")?;
+        check_heightmap(hardness, SIZE, SIZE * 2)?;
+        if hardness.data[x][y] <= hardness.data[x][SIZE - y / 2] {
+            return Err("painted the hardness in the wrong place".to_string());
+        }
+        let heightmap = base.heightmap_base.heightmap.as_ref().clone();
+        if heightmap.with_hardness(Some(hardness)).hardness.is_none() {
+            return Err("the painted hardness does not fit the heightmap".to_string());
+        }
+
+        state.ui_state.hardness_brush.painting = false;
+        state.ui_state.drop_zone_brush.painting = true;
+        paint_brushes(&state.ui_state, &mut state.app_state, position);
+        let drop_zone = &state.app_state.simulation_state().base().drop_zone;
+        let mask = drop_zone
+            .get_mask()
+            .ok_or("painting made no drop zone mask")?;
+        check_heightmap(mask, SIZE, SIZE * 2)?;
+        if mask.data[x][y] <= 0.0 || mask.data[x][SIZE - y / 2] != 0.0 {
+            return Err("painted the drop zone in the wrong place".to_string());
+        }
+        Ok(())
+    }
 }
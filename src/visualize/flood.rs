@@ -0,0 +1,157 @@
+#[cfg(feature = "gui")]
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+#[cfg(feature = "gui")]
+use crate::visualize::events::UiEvent;
+#[cfg(feature = "gui")]
+use crate::visualize::ui::UiState;
+
+/*
+Rising water for the isoline flood tool. While playing the isoline value, the water level, rises
+from `start` to `end` and the flood is recomputed every frame, so sea-level rise can be watched
+spreading over the terrain. Every flood that is computed adds the share of the map it covers at its
+level to the flood curve, whether the level was reached by playing or by dragging the slider, so
+the curve shows at which levels the water breaks through into new areas. A sequence exports the
+flood at `frames` evenly spaced levels as pngs with the curve as csv next to them.
+ */
+
+// Levels closer than this replace each other in the curve
+const LEVEL_EPSILON: HeightmapPrecision = 1e-4;
+
+/// Share of the map flooded at every level a flood was computed at, ordered by level.
+#[derive(Debug, Clone, Default)]
+pub struct FloodCurve {
+    points: Vec<(HeightmapPrecision, f32)>,
+}
+
+impl FloodCurve {
+    pub fn record(&mut self, level: HeightmapPrecision, share: f32) {
+        let i = self
+            .points
+            .partition_point(|(l, _)| *l < level - LEVEL_EPSILON);
+        match self.points.get_mut(i) {
+            Some(point) if (point.0 - level).abs() <= LEVEL_EPSILON => *point = (level, share),
+            _ => self.points.insert(i, (level, share)),
+        }
+    }
+
+    pub fn points(&self) -> &[(HeightmapPrecision, f32)] {
+        &self.points
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("level,flooded\n");
+        for (level, share) in self.points.iter() {
+            csv.push_str(&format!("{},{}\n", level, share));
+        }
+        csv
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FloodAnimation {
+    pub playing: bool,
+    pub start: HeightmapPrecision, // [0, 1], 0
+    pub end: HeightmapPrecision,   // [0, 1], 1
+    pub speed: f32,                // [0.01, 1], 0.1 (levels per second)
+    pub frames: usize,             // [2, 360], 60
+    #[serde(skip)]
+    pub curve: FloodCurve,
+}
+
+impl Default for FloodAnimation {
+    fn default() -> Self {
+        FloodAnimation {
+            playing: false,
+            start: 0.0,
+            end: 1.0,
+            speed: 0.1,
+            frames: 60,
+            curve: FloodCurve::default(),
+        }
+    }
+}
+
+impl FloodAnimation {
+    /// Evenly spaced levels from `start` to `end` for the frames of a sequence.
+    pub fn levels(&self) -> Vec<HeightmapPrecision> {
+        let steps = self.frames.max(2) - 1;
+        (0..=steps)
+            .map(|frame| self.start + (self.end - self.start) * frame as f32 / steps as f32)
+            .collect()
+    }
+}
+
+/// Share of the cells a flood from `Heightmap::flood_empty` filled with `with`.
+pub fn flooded_share(flooded: &Heightmap, with: HeightmapPrecision) -> f32 {
+    let cells = flooded.iter_cells().filter(|(_, _, v)| *v == with).count();
+    cells as f32 / (flooded.width * flooded.height).max(1) as f32
+}
+
+/// Raises the water of a playing animation and floods the terrain again at the new level.
+#[cfg(feature = "gui")]
+pub fn poll_flood(ui_state: &mut UiState) {
+    let animation = &mut ui_state.flood_animation;
+    if !animation.playing {
+        return;
+    }
+    let rising = animation.end >= animation.start;
+    let step = animation.speed * get_frame_time();
+    let level = if rising {
+        (ui_state.isoline.height + step).min(animation.end)
+    } else {
+        (ui_state.isoline.height - step).max(animation.end)
+    };
+    if level == animation.end {
+        animation.playing = false;
+    }
+    ui_state.isoline.height = level;
+    ui_state.ui_events.push(UiEvent::Isoline);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Check, SIZE};
+
+    #[test]
+    fn rising_water() -> Check {
+        use crate::math::UVector2;
+        use crate::visualize::flood::{flooded_share, FloodAnimation, FloodCurve};
+        // A wall along x = 8 keeps the flood from the left edge out of the rest of the map
+        let mut wall = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        for y in 0..SIZE {
+            wall.data[8][y] = 1.0;
+        }
+        let (flooded, areas) = wall.flood_empty(0.5, &vec![UVector2::new(0, 0)]);
+        let share = flooded_share(&flooded, 0.5);
+        if areas != 1 || (share - 8.0 / SIZE as f32).abs() > 1e-6 {
+            return Err(format!("flood covers {} of the map", share));
+        }
+
+        let mut curve = FloodCurve::default();
+        for (level, share) in [(0.5, 0.4), (0.1, 0.0), (0.9, 1.0), (0.5, 0.5)] {
+            curve.record(level, share);
+        }
+        if curve.points() != [(0.1, 0.0), (0.5, 0.5), (0.9, 1.0)] {
+            return Err(format!("curve is {:?}", curve.points()));
+        }
+        let animation = FloodAnimation {
+            start: 0.2,
+            end: 0.8,
+            frames: 4,
+            ..FloodAnimation::default()
+        };
+        let levels = animation.levels();
+        if levels.len() != 4 || levels[0] != 0.2 || (levels[3] - 0.8).abs() > 1e-6 {
+            return Err(format!("sequence levels are {:?}", levels));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+use crate::heightmap::{Heightmap, HeightmapData, HeightmapPrecision};
+use crate::visualize::{layer_image, LayerImage};
+
+/*
+Recent droplet activity shown while an incremental simulation runs. Every cell the erosion changes
+heats up by the amount it changed and cools off over time, so the terrain glows where the
+droplets are working right now and fades where they have moved on. The glow is scaled by the
+hottest cell, which cools off slower than the cells do so the overlay does not flicker when the
+activity drops for a frame.
+ */
+
+const GLOW_COOL: [f32; 3] = [200.0, 30.0, 0.0];
+const GLOW_HOT: [f32; 3] = [255.0, 240.0, 140.0];
+// The scale halves this many times slower than the cells cool off
+const PEAK_HALF_LIFE_FACTOR: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HeatOverlay {
+    pub enabled: bool,
+    pub half_life: f32, // [0.1, 10], 1 (seconds)
+}
+
+impl Default for HeatOverlay {
+    fn default() -> Self {
+        HeatOverlay {
+            enabled: true,
+            half_life: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Heat {
+    data: HeightmapData,
+    peak: HeightmapPrecision,
+}
+
+impl Heat {
+    pub fn new(width: usize, height: usize) -> Self {
+        Heat {
+            data: vec![vec![0.0; height]; width],
+            peak: 0.0,
+        }
+    }
+
+    /// Cools every cell off by `seconds` with the given half-life.
+    pub fn decay(&mut self, seconds: f32, half_life: f32) {
+        let factor = 0.5f32.powf(seconds / half_life.max(f32::EPSILON));
+        for value in self.data.iter_mut().flat_map(|col| col.iter_mut()) {
+            *value *= factor;
+        }
+        self.peak *= factor.powf(1.0 / PEAK_HALF_LIFE_FACTOR);
+    }
+
+    /// Heats up the cells that differ between `before` and `after`.
+    pub fn add(&mut self, before: &Heightmap, after: &Heightmap) {
+        for (x, col) in self.data.iter_mut().enumerate() {
+            for (y, value) in col.iter_mut().enumerate() {
+                *value += (after.data[x][y] - before.data[x][y]).abs();
+                self.peak = self.peak.max(*value);
+            }
+        }
+    }
+
+    /// How much the cell glows in [0, 1].
+    pub fn intensity(&self, x: usize, y: usize) -> f32 {
+        if self.peak <= 0.0 {
+            return 0.0;
+        }
+        // The square root lifts faint activity so single droplet paths stay visible
+        (self.data[x][y] / self.peak).clamp(0.0, 1.0).sqrt()
+    }
+
+    /// The terrain in grayscale with the heat glowing on top, from dark red to pale yellow.
+    pub fn image(&self, heightmap: &Heightmap) -> LayerImage {
+        let mut bytes = Vec::with_capacity(heightmap.width * heightmap.height * 4);
+        for y in 0..heightmap.height {
+            for x in 0..heightmap.width {
+                let gray = (heightmap.data[x][y] / heightmap.depth).clamp(0.0, 1.0) * 255.0;
+                let t = self.intensity(x, y);
+                let mut pixel = [0u8, 0, 0, 255];
+                for c in 0..3 {
+                    let glow = GLOW_COOL[c] + (GLOW_HOT[c] - GLOW_COOL[c]) * t;
+                    pixel[c] = (gray + (glow - gray) * t).round() as u8;
+                }
+                bytes.extend_from_slice(&pixel);
+            }
+        }
+        layer_image(heightmap.width, heightmap.height, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Check, SIZE};
+
+    #[test]
+    fn heat_decay() -> Check {
+        let before = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        let mut after = before.clone();
+        after.data[3][4] = 0.2;
+        after.data[5][4] = 0.05;
+        let mut heat = Heat::new(SIZE, SIZE);
+        heat.add(&before, &after);
+        if heat.intensity(3, 4) != 1.0 || heat.intensity(0, 0) != 0.0 {
+            return Err("hottest cell does not glow fully".to_string());
+        }
+        if heat.intensity(5, 4) <= 0.0 || heat.intensity(5, 4) >= 1.0 {
+            return Err("faint activity is not partly lit".to_string());
+        }
+        let fresh = heat.intensity(3, 4);
+        heat.decay(1.0, 1.0);
+        if heat.intensity(3, 4) >= fresh {
+            return Err("heat does not fade relative to the scale".to_string());
+        }
+        Ok(())
+    }
+}
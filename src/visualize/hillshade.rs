@@ -0,0 +1,276 @@
+use std::rc::Rc;
+
+#[cfg(feature = "gui")]
+use macroquad::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use crate::visualize::app_state::AppState;
+use crate::visualize::wrappers::HeightmapTexture;
+use crate::visualize::{layer_image, LayerImage};
+
+/*
+The terrain lit by a sun low over the horizon, which brings out shallow erosion features such as
+rills and terraces that are hard to see in the plain grayscale heights. Slopes are shaded by the
+angle they face the sun at and, with shadows on, cells are darkened where terrain towards the sun
+rises above it. The sun is a disc rather than a point so the shadows get a soft edge, `softness`
+is its apparent size. Sweeping the azimuth, either animated or by dragging the slider, shows
+features that only catch the light from some directions. The azimuth is in degrees clockwise from
+the top of the heightmap.
+ */
+
+// Light reaching cells facing away from the sun or in shadow
+const AMBIENT: f32 = 0.15;
+// Farthest a cell looks towards the sun for terrain casting a shadow on it, in cells
+const MAX_SHADOW_DISTANCE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sun {
+    pub azimuth: f32,      // [0, 360), 315 (degrees)
+    pub altitude: f32,     // [1, 89], 30 (degrees)
+    pub exaggeration: f32, // [0.1, 10], 1
+    pub shadows: bool,
+    pub softness: f32, // [0, 20], 4 (degrees)
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Sun {
+            azimuth: 315.0,
+            altitude: 30.0,
+            exaggeration: 1.0,
+            shadows: true,
+            softness: 4.0,
+        }
+    }
+}
+
+impl Sun {
+    /// Unit vector towards the sun, x right, y down and z up.
+    fn direction(&self) -> (f32, f32, f32) {
+        let azimuth = self.azimuth.to_radians();
+        let altitude = self.altitude.to_radians();
+        (
+            altitude.cos() * azimuth.sin(),
+            -altitude.cos() * azimuth.cos(),
+            altitude.sin(),
+        )
+    }
+
+    /// The same sun at `azimuth`, wrapped into [0, 360).
+    pub fn at(&self, azimuth: f32) -> Self {
+        Sun {
+            azimuth: azimuth.rem_euclid(360.0),
+            ..*self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hillshade {
+    pub sun: Sun,
+    pub animate: bool,
+    pub speed: f32,    // [1, 180], 30 (degrees per second)
+    pub frames: usize, // [2, 360], 36
+    /// Image on screen and the sun it was lit by, while the hillshade is the active layer.
+    #[serde(skip)]
+    shown: Option<(Rc<LayerImage>, Sun)>,
+}
+
+impl Default for Hillshade {
+    fn default() -> Self {
+        Hillshade {
+            sun: Sun::default(),
+            animate: false,
+            speed: 30.0,
+            frames: 36,
+            shown: None,
+        }
+    }
+}
+
+impl Hillshade {
+    /// Shows the selected state lit by the sun, the layer follows the sun until another is shown.
+    pub fn show(&mut self, app_state: &mut AppState) {
+        let heightmap = app_state.simulation_state().get_heightmap();
+        let image = Rc::new(to_image(&shade(&heightmap, &self.sun)));
+        self.shown = Some((Rc::clone(&image), self.sun));
+        app_state
+            .simulation_state_mut()
+            .set_active(Rc::new(HeightmapTexture::new(heightmap, Some(image))));
+    }
+
+    /// Images of the sun going once around the terrain, starting at the current azimuth.
+    pub fn sequence(&self, heightmap: &Heightmap) -> Vec<LayerImage> {
+        let step = 360.0 / self.frames.max(1) as f32;
+        (0..self.frames)
+            .map(|frame| {
+                let sun = self.sun.at(self.sun.azimuth + step * frame as f32);
+                to_image(&shade(heightmap, &sun))
+            })
+            .collect()
+    }
+}
+
+/// Brightness of every cell in [0, 1] when lit by `sun`.
+pub fn shade(heightmap: &Heightmap, sun: &Sun) -> Heightmap {
+    let (light_x, light_y, light_z) = sun.direction();
+    let cell_size = 1.0 / heightmap.width as f32;
+    let highest = heightmap.get_range().1;
+    let columns = (0..heightmap.width)
+        .into_par_iter()
+        .map(|x| {
+            (0..heightmap.height)
+                .map(|y| {
+                    let (xi, yi) = (x as i32, y as i32);
+                    let slope_x = (heightmap.get_clamped(xi + 1, yi)
+                        - heightmap.get_clamped(xi - 1, yi))
+                        * sun.exaggeration
+                        / (2.0 * cell_size);
+                    let slope_y = (heightmap.get_clamped(xi, yi + 1)
+                        - heightmap.get_clamped(xi, yi - 1))
+                        * sun.exaggeration
+                        / (2.0 * cell_size);
+                    let length = (slope_x * slope_x + slope_y * slope_y + 1.0).sqrt();
+                    let facing =
+                        ((-slope_x * light_x - slope_y * light_y + light_z) / length).max(0.0);
+                    let lit = if sun.shadows && facing > 0.0 {
+                        sunlit(heightmap, sun, x, y, cell_size, highest)
+                    } else {
+                        1.0
+                    };
+                    AMBIENT + (1.0 - AMBIENT) * facing * lit
+                })
+                .collect()
+        })
+        .collect();
+    Heightmap::new(columns, heightmap.width, heightmap.height, 1.0, 1.0, None)
+}
+
+/// Share of the sun disc visible from the cell, 0 when the terrain towards it hides all of it.
+fn sunlit(
+    heightmap: &Heightmap,
+    sun: &Sun,
+    x: usize,
+    y: usize,
+    cell_size: f32,
+    highest: HeightmapPrecision,
+) -> f32 {
+    let azimuth = sun.azimuth.to_radians();
+    let (step_x, step_y) = (azimuth.sin(), -azimuth.cos());
+    let half_disc = sun.softness / 2.0;
+    let lowest_edge = (sun.altitude - half_disc).to_radians().tan();
+    let start = heightmap.data[x][y];
+    let mut horizon = f32::NEG_INFINITY;
+    for step in 1..=MAX_SHADOW_DISTANCE {
+        let distance = step as f32 * cell_size;
+        // Nothing farther away can rise high enough to reach the sun
+        if (highest - start) * sun.exaggeration / distance < lowest_edge.max(horizon) {
+            break;
+        }
+        let sample_x = (x as f32 + step_x * step as f32).round() as i32;
+        let sample_y = (y as f32 + step_y * step as f32).round() as i32;
+        if !heightmap.wrap
+            && (sample_x < 0
+                || sample_y < 0
+                || sample_x >= heightmap.width as i32
+                || sample_y >= heightmap.height as i32)
+        {
+            break;
+        }
+        let rise = heightmap.get_clamped(sample_x, sample_y) - start;
+        horizon = horizon.max(rise * sun.exaggeration / distance);
+    }
+    if horizon == f32::NEG_INFINITY {
+        return 1.0;
+    }
+    let horizon = horizon.atan().to_degrees();
+    if sun.softness <= 0.0 {
+        return if horizon < sun.altitude { 1.0 } else { 0.0 };
+    }
+    ((sun.altitude + half_disc - horizon) / sun.softness).clamp(0.0, 1.0)
+}
+
+fn to_image(shade: &Heightmap) -> LayerImage {
+    let mut bytes = Vec::with_capacity(shade.width * shade.height * 4);
+    for y in 0..shade.height {
+        for x in 0..shade.width {
+            let v = (shade.data[x][y].clamp(0.0, 1.0) * 255.0).round() as u8;
+            bytes.extend_from_slice(&[v, v, v, 255]);
+        }
+    }
+    layer_image(shade.width, shade.height, bytes)
+}
+
+/// Moves the sun of a shown hillshade and relights the terrain when the sun has changed.
+#[cfg(feature = "gui")]
+pub fn poll_sun(hillshade: &mut Hillshade, app_state: &mut AppState) {
+    let active = app_state.simulation_state().get_active_heightmap_texture();
+    let sun = match (&hillshade.shown, &active.image) {
+        (Some((shown, sun)), Some(image)) if Rc::ptr_eq(shown, image) => *sun,
+        _ => {
+            hillshade.shown = None;
+            return;
+        }
+    };
+    if hillshade.animate {
+        hillshade.sun = hillshade
+            .sun
+            .at(hillshade.sun.azimuth + hillshade.speed * get_frame_time());
+    }
+    if hillshade.sun == sun {
+        return;
+    }
+    let image = Rc::new(to_image(&shade(&active.heightmap, &hillshade.sun)));
+    let texture = HeightmapTexture {
+        image: Some(Rc::clone(&image)),
+        ..(*active).clone()
+    };
+    hillshade.shown = Some((image, hillshade.sun));
+    app_state
+        .simulation_state_mut()
+        .set_active(Rc::new(texture));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Check, SIZE};
+
+    #[test]
+    fn hillshade() -> Check {
+        use crate::visualize::hillshade::{shade, Sun};
+        // A wall across the middle of flat ground with the sun low in the west
+        let mut heightmap = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        let wall = SIZE / 2;
+        for y in 0..SIZE {
+            heightmap.data[wall][y] = 0.5;
+        }
+        let sun = Sun {
+            azimuth: 270.0,
+            altitude: 10.0,
+            ..Sun::default()
+        };
+        let lit = shade(&heightmap, &sun);
+        let (west, east) = (lit.data[wall - 1][SIZE / 2], lit.data[wall + 1][SIZE / 2]);
+        if west <= east {
+            return Err("slope facing the sun is not brighter".to_string());
+        }
+        let (open, behind) = (lit.data[2][SIZE / 2], lit.data[wall + 4][SIZE / 2]);
+        if open <= behind {
+            return Err("ground behind the wall is not in shadow".to_string());
+        }
+        let unshadowed = shade(
+            &heightmap,
+            &Sun {
+                shadows: false,
+                ..sun
+            },
+        );
+        if (unshadowed.data[wall + 4][SIZE / 2] - open).abs() > f32::EPSILON {
+            return Err("flat ground is not evenly lit without shadows".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use crate::visualize::app_state::SimulationState;
+
+/*
+Undo and redo of changes to the simulation states, such as new or replaced heightmaps, erosion
+runs, post-processing and painting. The states are recorded before every change, which is cheap
+since heightmaps and textures are shared behind `Rc`s between the recorded and the live states.
+Undoing restores the states and the selected state as they were, making any change after undoing
+drops what could be redone. Only the last `MAX_HISTORY` changes can be undone.
+ */
+
+pub const MAX_HISTORY: usize = 64;
+
+type Entry = (Vec<SimulationState>, Vec<usize>);
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+}
+
+impl History {
+    /// Records the states as they are before a change.
+    pub fn record(&mut self, states: &[SimulationState], indices: &[usize]) {
+        self.undo.push((states.to_vec(), indices.to_vec()));
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Swaps `current` for the states before the last change, `None` if there is nothing to undo.
+    pub fn undo(&mut self, current: Entry) -> Option<Entry> {
+        let previous = self.undo.pop()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Swaps `current` for the states of the last undone change.
+    pub fn redo(&mut self, current: Entry) -> Option<Entry> {
+        let next = self.redo.pop()?;
+        self.undo.push(current);
+        Some(next)
+    }
+}
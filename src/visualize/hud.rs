@@ -0,0 +1,98 @@
+use std::time::Instant;
+
+use macroquad::prelude::get_fps;
+
+use crate::visualize::app_state::AppState;
+
+/*
+Performance HUD, a small overlay in the corner of the canvas with the frame rate, the number of
+simulations running, how many droplets per second they get through and the memory held by the
+process. Throughput is sampled a few times a second from the progress counters of the running
+jobs, so the numbers settle quickly after changing settings like the batch size.
+ */
+
+const SAMPLE_INTERVAL: f32 = 0.5; // seconds
+
+#[derive(Debug, Clone)]
+pub struct Hud {
+    sampled_at: Instant,
+    sampled_done: usize,
+    droplets_per_second: f32,
+    memory: Option<usize>,
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Hud {
+            sampled_at: Instant::now(),
+            sampled_done: 0,
+            droplets_per_second: 0.0,
+            memory: None,
+        }
+    }
+}
+
+/// Jobs running in the background or animated frame by frame, with the droplets they finished.
+fn running_jobs(app_state: &AppState) -> (usize, usize) {
+    let mut jobs = 0;
+    let mut done = 0;
+    if let Some(job) = &app_state.simulation_job {
+        jobs += 1;
+        done += job.progress.done();
+    }
+    if let Some(incremental) = &app_state.incremental_simulation {
+        jobs += 1;
+        done += incremental.borrow().1.done;
+    }
+    (jobs, done)
+}
+
+/// Resident memory of the process in bytes, only known on Linux.
+fn resident_memory() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        const PAGE_SIZE: usize = 4096;
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+        Some(pages * PAGE_SIZE)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+impl Hud {
+    /// Samples throughput and memory once `SAMPLE_INTERVAL` has passed since the last sample.
+    pub fn update(&mut self, app_state: &AppState) {
+        let elapsed = self.sampled_at.elapsed().as_secs_f32();
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+        let (jobs, done) = running_jobs(app_state);
+        // A new job restarts its counter, skip the sample instead of going negative
+        self.droplets_per_second = if jobs > 0 && done >= self.sampled_done {
+            (done - self.sampled_done) as f32 / elapsed
+        } else {
+            0.0
+        };
+        self.sampled_done = done;
+        self.sampled_at = Instant::now();
+        self.memory = resident_memory();
+    }
+
+    pub fn text(&self, app_state: &AppState) -> String {
+        let (jobs, _) = running_jobs(app_state);
+        let memory = self
+            .memory
+            .map(|bytes| format!("{:.1} MiB", bytes as f32 / (1024.0 * 1024.0)))
+            .unwrap_or_else(|| "unknown".to_string());
+        format!(
+            "FPS: {}\nJobs: {}\nDroplets/s: {:.0}\nMemory: {}",
+            get_fps(),
+            jobs,
+            self.droplets_per_second,
+            memory
+        )
+    }
+}
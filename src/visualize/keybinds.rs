@@ -22,6 +22,11 @@ pub const KEYCODE_TOGGLE_CONTROL_PANEL_UI: KeyCode = KeyCode::F2;
 pub const KEYCODE_TOGGLE_KEYBINDS_UI: KeyCode = KeyCode::F3;
 pub const KEYCODE_TOGGLE_METADATA_UI: KeyCode = KeyCode::F4;
 pub const KEYCODE_TOGGLE_METRICS_UI: KeyCode = KeyCode::F5;
+pub const KEYCODE_TOGGLE_SNAPSHOTS_UI: KeyCode = KeyCode::F6;
+pub const KEYCODE_TOGGLE_TIMELINE_UI: KeyCode = KeyCode::F7;
+pub const KEYCODE_TOGGLE_SESSION_UI: KeyCode = KeyCode::F8;
+pub const KEYCODE_TOGGLE_HUD_UI: KeyCode = KeyCode::F9;
+pub const KEYCODE_TOGGLE_REGION_UI: KeyCode = KeyCode::F10;
 pub const KEYCODE_NEW_HEIGHTMAP: KeyCode = KeyCode::G;
 pub const KEYCODE_NEXT_PARTITIONING_METHOD: KeyCode = KeyCode::J;
 pub const KEYCODE_PREVIOUS_PARTITIONING_METHOD: KeyCode = KeyCode::K;
@@ -42,7 +47,15 @@ pub const KEYBINDS: &[UiKeybind] = &[
     UiKeybind::Pressed(UiKey::Single(KeyCode::R), UiEvent::Clear),
     #[cfg(feature = "export")]
     UiKeybind::Pressed(UiKey::Single(KeyCode::S), UiEvent::ExportHeightmap),
-    UiKeybind::Pressed(UiKey::Single(KeyCode::Tab), UiEvent::RunSimulation),
+    UiKeybind::Pressed(UiKey::Single(KeyCode::Tab), UiEvent::StartSimulation),
+    UiKeybind::Pressed(
+        UiKey::Double((KeyCode::LeftControl, KeyCode::Z)),
+        UiEvent::Undo,
+    ),
+    UiKeybind::Pressed(
+        UiKey::Double((KeyCode::LeftControl, KeyCode::Y)),
+        UiEvent::Redo,
+    ),
     UiKeybind::Pressed(UiKey::Single(KeyCode::Q), UiEvent::Quit),
     UiKeybind::Pressed(UiKey::Single(KeyCode::Escape), UiEvent::Quit),
     UiKeybind::Down(UiKey::Single(KeyCode::Space), UiEvent::ShowBaseLayer),
@@ -51,6 +64,10 @@ pub const KEYBINDS: &[UiKeybind] = &[
         UiKey::Double((KeyCode::LeftShift, KeyCode::D)),
         UiEvent::ShowDifferenceNormalized,
     ),
+    UiKeybind::Down(
+        UiKey::Double((KeyCode::LeftControl, KeyCode::D)),
+        UiEvent::ShowDifferenceSigned,
+    ),
     UiKeybind::Pressed(
         UiKey::Single(KEYCODE_NEXT_PARTITIONING_METHOD),
         UiEvent::NextPartitioningMethod,
@@ -71,8 +88,29 @@ pub const KEYBINDS: &[UiKeybind] = &[
         UiKey::Single(KEYCODE_TOGGLE_METRICS_UI),
         UiEvent::ToggleUi(UiWindow::Metrics),
     ),
+    UiKeybind::Pressed(
+        UiKey::Single(KEYCODE_TOGGLE_SNAPSHOTS_UI),
+        UiEvent::ToggleUi(UiWindow::Snapshots),
+    ),
+    UiKeybind::Pressed(
+        UiKey::Single(KEYCODE_TOGGLE_TIMELINE_UI),
+        UiEvent::ToggleUi(UiWindow::Timeline),
+    ),
+    UiKeybind::Pressed(
+        UiKey::Single(KEYCODE_TOGGLE_SESSION_UI),
+        UiEvent::ToggleUi(UiWindow::Session),
+    ),
+    UiKeybind::Pressed(
+        UiKey::Single(KEYCODE_TOGGLE_HUD_UI),
+        UiEvent::ToggleUi(UiWindow::Hud),
+    ),
+    UiKeybind::Pressed(
+        UiKey::Single(KEYCODE_TOGGLE_REGION_UI),
+        UiEvent::ToggleUi(UiWindow::Region),
+    ),
     UiKeybind::Pressed(UiKey::Single(KeyCode::V), UiEvent::ShowErodedLayer),
     UiKeybind::Pressed(UiKey::Single(KeyCode::B), UiEvent::Blur),
+    UiKeybind::Pressed(UiKey::Single(KeyCode::Home), UiEvent::ResetView),
     UiKeybind::Pressed(UiKey::Single(KeyCode::C), UiEvent::EdgeDetect),
     UiKeybind::Pressed(UiKey::Single(KeyCode::X), UiEvent::BlurEdgeDetect),
     UiKeybind::Pressed(UiKey::Single(KeyCode::I), UiEvent::Isoline),
@@ -86,8 +124,9 @@ pub fn poll_ui_keybinds(ui_state: &mut UiState) {
         match keybind {
             UiKeybind::Pressed(keybind, event) => match keybind {
                 UiKey::Single(_) => (),
+                // The first key is held like a modifier while the second is pressed
                 UiKey::Double(key_codes) => {
-                    if is_key_pressed(key_codes.0)
+                    if is_key_down(key_codes.0)
                         && is_key_pressed(key_codes.1)
                         && !consumed_keys.contains(&key_codes.1)
                     {
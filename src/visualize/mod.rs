@@ -1,27 +1,75 @@
 use crate::{heightmap, State};
 
-use egui::{Pos2, Rect};
+#[cfg(feature = "gui")]
+use emath::{Pos2, Rect};
+#[cfg(feature = "gui")]
 use macroquad::prelude::*;
 
 pub mod app_state;
+#[cfg(feature = "gui")]
 pub mod canvas;
+pub mod droplets;
 pub mod events;
+pub mod flood;
+pub mod heat;
+pub mod hillshade;
+pub mod history;
+#[cfg(feature = "gui")]
+pub mod hud;
+#[cfg(feature = "gui")]
 pub mod keybinds;
+pub mod overlay;
+pub mod palette;
+#[cfg(feature = "gui")]
 pub mod panels;
+#[cfg(feature = "gui")]
+pub mod preview;
+pub mod region;
+pub mod sculpt;
+pub mod session;
+#[cfg(feature = "gui")]
+pub mod textures;
 pub mod ui;
+pub mod view;
+#[cfg(feature = "gui")]
 pub mod widgets;
+pub mod workspace;
 pub mod wrappers;
 
+pub const WIDTH: u32 = 1107;
+pub const HEIGHT: u32 = 800;
+
 use crate::heightmap::Heightmap;
 use crate::visualize::app_state::{AppState, SimulationState};
-use crate::visualize::events::poll_ui_events;
+#[cfg(feature = "gui")]
+use crate::visualize::events::{poll_brushes, poll_simulation_job, poll_ui_events, UiEvent};
+#[cfg(feature = "gui")]
+use crate::visualize::flood::poll_flood;
+#[cfg(feature = "gui")]
+use crate::visualize::hillshade::poll_sun;
+#[cfg(feature = "gui")]
 use crate::visualize::keybinds::poll_ui_keybinds;
+#[cfg(feature = "gui")]
+use crate::visualize::palette::poll_colormap;
+use crate::visualize::palette::Palette;
+#[cfg(feature = "gui")]
+use crate::visualize::region::poll_region_input;
+#[cfg(feature = "gui")]
+use crate::visualize::sculpt::poll_sculpting;
+#[cfg(feature = "gui")]
 use crate::visualize::ui::*;
+#[cfg(feature = "gui")]
+use crate::visualize::view::{poll_view_input, View};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "gui")]
+use std::mem;
+use std::rc::Rc;
 
 pub fn generate_default_state() -> State {
     State::default()
 }
 
+#[cfg(feature = "gui")]
 pub async fn run() {
     prevent_quit();
 
@@ -62,7 +110,9 @@ pub async fn run() {
     while launching || state.ui_state.simulation_clear && !state.ui_state.application_quit {
         launching = false;
         if state.ui_state.simulation_clear {
+            let session = mem::take(&mut state.app_state.session);
             state = generate_default_state();
+            state.app_state.session = session;
         }
         state.ui_state.simulation_clear = false;
 
@@ -83,10 +133,7 @@ pub async fn run() {
         }
 
         // Update UI
-        while !is_quit_requested()
-            && !state.ui_state.simulation_clear
-            && !state.ui_state.application_quit
-        {
+        while !state.ui_state.simulation_clear && !state.ui_state.application_quit {
             clear_background(BLACK);
 
             let canvas_rect = state
@@ -105,26 +152,147 @@ pub async fn run() {
             if !corrected_size {
                 let fit = canvas_rect.width().min(canvas_rect.height());
                 request_new_screen_size(
-                    crate::WIDTH as f32 + canvas_rect.height() - fit,
-                    crate::HEIGHT as f32 + canvas_rect.width() - fit,
+                    WIDTH as f32 + canvas_rect.height() - fit,
+                    HEIGHT as f32 + canvas_rect.width() - fit,
                 );
                 corrected_size = true;
             }
-            draw_frame(
-                &canvas_rect,
-                &state.app_state.simulation_state().get_active_texture(),
+            let texture = state.ui_state.textures.get(
+                &state
+                    .app_state
+                    .simulation_state()
+                    .get_active_heightmap_texture(),
             );
+            draw_frame(&canvas_rect, &texture, &state.ui_state.view);
+            let active = state
+                .app_state
+                .simulation_state()
+                .get_active_heightmap_texture();
+            let thickness = state.ui_state.isoline.contour_lines.thickness;
+            if let Some(contours) = &active.contours {
+                overlay::draw_polylines(
+                    &canvas_rect,
+                    &state.ui_state.view,
+                    contours,
+                    thickness,
+                    YELLOW,
+                );
+            }
+            if let Some(contours) = &active.index_contours {
+                overlay::draw_polylines(
+                    &canvas_rect,
+                    &state.ui_state.view,
+                    contours,
+                    thickness * 2.0,
+                    YELLOW,
+                );
+            }
+            if state.ui_state.show_partition_preview {
+                let simulation_state = state.app_state.simulation_state();
+                let heightmap = simulation_state.get_heightmap();
+                let base = simulation_state.base();
+                state.ui_state.partition_preview.update(
+                    base.erosion_method,
+                    state.app_state.parameters.margin_mode(),
+                    base.partition_halo,
+                    heightmap.width,
+                    heightmap.height,
+                );
+                state
+                    .ui_state
+                    .partition_preview
+                    .draw(&canvas_rect, &state.ui_state.view);
+            }
             if state.ui_state.show_grid {
-                draw_frame(
+                overlay::draw_polylines(
                     &canvas_rect,
+                    &state.ui_state.view,
                     &state
                         .app_state
                         .simulation_state()
-                        .get_active_grid_texture(&state.app_state.parameters),
+                        .get_active_grid_lines(&state.app_state.parameters),
+                    1.5,
+                    RED,
+                );
+                overlay::draw_polylines(
+                    &canvas_rect,
+                    &state.ui_state.view,
+                    &state
+                        .app_state
+                        .simulation_state()
+                        .get_active_margin_lines(&state.app_state.parameters),
+                    1.5,
+                    MAGENTA,
                 );
             }
+            state
+                .ui_state
+                .vector_overlay
+                .draw(&canvas_rect, &state.ui_state.view);
+            state
+                .ui_state
+                .region
+                .draw(&canvas_rect, &state.ui_state.view, ORANGE);
+            state
+                .ui_state
+                .crop
+                .selection
+                .draw(&canvas_rect, &state.ui_state.view, SKYBLUE);
+            if state.ui_state.show_legend {
+                if let Some(legend) = &state
+                    .app_state
+                    .simulation_state()
+                    .get_active_heightmap_texture()
+                    .legend
+                {
+                    palette::draw_legend(&canvas_rect, legend);
+                }
+            }
 
             state.ui_state.frame_slots = ui_draw(&mut state);
+            let pointer_captured = state
+                .ui_state
+                .frame_slots
+                .as_ref()
+                .is_some_and(|slots| slots.pointer_captured);
+            let selecting = poll_region_input(
+                &mut state.ui_state.region,
+                &state.ui_state.view,
+                pointer_captured,
+                &canvas_rect,
+            ) || poll_region_input(
+                &mut state.ui_state.crop.selection,
+                &state.ui_state.view,
+                pointer_captured,
+                &canvas_rect,
+            );
+            let sculpted = !selecting
+                && poll_sculpting(
+                    &mut state.ui_state.sculpt_brush,
+                    &mut state.app_state,
+                    &state.ui_state.view,
+                    pointer_captured,
+                    &canvas_rect,
+                );
+            if sculpted
+                || !selecting && poll_brushes(&state.ui_state, &mut state.app_state, &canvas_rect)
+            {
+                state.dirty = true;
+            }
+            poll_view_input(&mut state.ui_state, &canvas_rect);
+            poll_simulation_job(&mut state.app_state, &state.ui_state.heat_overlay);
+            poll_colormap(&state.ui_state.colormap, &mut state.app_state);
+            poll_sun(&mut state.ui_state.hillshade, &mut state.app_state);
+            poll_flood(&mut state.ui_state);
+            poll_quit(&mut state);
+            let events = &state.ui_state.ui_events;
+            #[cfg(feature = "export")]
+            let saving = events
+                .iter()
+                .any(|e| matches!(e, UiEvent::ExportState | UiEvent::ReadState(_)));
+            #[cfg(not(feature = "export"))]
+            let saving = false;
+            let changing = events.iter().any(|e| e.changes_session());
 
             #[cfg(feature = "export")]
             let state_name = &mut state.state_name;
@@ -136,44 +304,84 @@ pub async fn run() {
                 ui_state,
                 app_state,
             );
+            if saving {
+                state.dirty = false;
+            } else if changing {
+                state.dirty = true;
+            }
             poll_ui_keybinds(&mut state.ui_state);
             next_frame().await;
         }
     }
+
+    println!("{}", state.app_state.session.summary());
+    if state.ui_state.session_log {
+        if let Err(err) = state.app_state.session.append_to_log() {
+            eprintln!("Failed to append to the session log: {:?}", err);
+        }
+    }
+}
+
+/// Asks to save first when quitting or closing the window with unsaved changes.
+#[cfg(feature = "gui")]
+fn poll_quit(state: &mut State) {
+    let ui_state = &mut state.ui_state;
+    if is_quit_requested() {
+        ui_state.ui_events.push(UiEvent::Quit);
+    }
+    if state.dirty && ui_state.ui_events.contains(&UiEvent::Quit) {
+        ui_state.cancel_events(&UiEvent::Quit);
+        ui_state.quit_prompt = true;
+        ui_state.show_ui_all = true;
+    }
 }
 
-pub fn draw_frame(rect: &Rect, texture: &Texture2D) {
-    let side = rect.width().min(rect.height());
-    let margin_left = (rect.width() - side) / 2.0;
-    let margin_top = (rect.height() - side) / 2.0;
+#[cfg(feature = "gui")]
+pub fn draw_frame(rect: &Rect, texture: &Texture2D, view: &View) {
+    let (left, top, side) = view::frame(rect);
+    let (min_u, min_v, extent) = view.visible();
     texture.set_filter(FilterMode::Nearest);
     draw_texture_ex(
         *texture,
-        rect.min.x + margin_left,
-        rect.min.y + margin_top,
+        left,
+        top,
         WHITE,
         DrawTextureParams {
             dest_size: Some(vec2(side, side)),
+            source: Some(macroquad::math::Rect::new(
+                min_u * texture.width(),
+                min_v * texture.height(),
+                extent * texture.width(),
+                extent * texture.height(),
+            )),
             ..Default::default()
         },
     );
 }
 
-fn heightmap_to_image_rgb(heightmap: &heightmap::Heightmap) -> Image {
-    let buffer = heightmap.to_u8_rgba();
+/// Colours of a layer row by row from the top left, held by the application state without a window
+/// and uploaded by `TextureCache` once the layer is drawn.
+pub type LayerImage = image::RgbaImage;
 
-    let image = Image {
-        bytes: buffer,
-        width: heightmap.width.try_into().unwrap(),
-        height: heightmap.height.try_into().unwrap(),
-    };
+/// A layer image of `width` x `height` pixels from their rgba bytes.
+pub fn layer_image(width: usize, height: usize, bytes: Vec<u8>) -> LayerImage {
+    LayerImage::from_raw(width as u32, height as u32, bytes)
+        .expect("Image bytes do not match its size.")
+}
 
-    image
+/// Uploads a layer image, see `TextureCache`.
+#[cfg(feature = "gui")]
+pub fn image_to_texture(image: &LayerImage) -> Texture2D {
+    Texture2D::from_rgba8(image.width() as u16, image.height() as u16, image.as_raw())
 }
 
-fn heightmap_to_texture(heightmap: &heightmap::Heightmap) -> Texture2D {
-    let image = heightmap_to_image_rgb(heightmap);
-    Texture2D::from_image(&image)
+fn heightmap_to_image_rgb(heightmap: &heightmap::Heightmap) -> LayerImage {
+    let colormap = palette::active_colormap();
+    let buffer = heightmap.to_u8_rgba(|t| colormap.color(t));
+
+    let image = layer_image(heightmap.width, heightmap.height, buffer);
+
+    image
 }
 
 fn mix_heightmap_to_image(
@@ -182,9 +390,10 @@ fn mix_heightmap_to_image(
     channel: u8,
     invert: bool,
     round: bool,
-) -> Image {
+) -> LayerImage {
     let overlay = overlay.to_u8();
-    let mut buffer = heightmap.to_u8_rgba();
+    let colormap = palette::active_colormap();
+    let mut buffer = heightmap.to_u8_rgba(|t| colormap.color(t));
 
     for i in (0..buffer.len()).step_by(4) {
         let mut overlay = overlay[i / 4] as f32 / 255.0;
@@ -206,15 +415,12 @@ fn mix_heightmap_to_image(
         }
     }
 
-    let image = Image {
-        bytes: buffer,
-        width: heightmap.width.try_into().unwrap(),
-        height: heightmap.height.try_into().unwrap(),
-    };
+    let image = layer_image(heightmap.width, heightmap.height, buffer);
 
     image
 }
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum LayerMixMethod {
     Additive,
     AdditiveClamp,
@@ -249,6 +455,136 @@ pub struct HeightmapLayer<'a> {
     pub modifies_alpha: bool,
 }
 
+/// Tints the terrain red where material was eroded and blue where it was deposited.
+/// Both amounts share one scale so their intensities can be compared.
+pub fn erosion_deposition_to_image(
+    heightmap: &Heightmap,
+    eroded: &Heightmap,
+    deposited: &Heightmap,
+) -> (LayerImage, Composite) {
+    let max = eroded.get_range().1.max(deposited.get_range().1);
+    let scale = |amounts: &Heightmap| {
+        let mut scaled = amounts.clone();
+        if max > 0.0 {
+            for value in scaled.data.iter_mut().flat_map(|col| col.iter_mut()) {
+                *value /= max;
+            }
+        }
+        scaled
+    };
+    let eroded = scale(eroded);
+    let deposited = scale(deposited);
+    let layers = [
+        HeightmapLayer {
+            heightmap,
+            channel: rgba_color_channel::RGB,
+            strength: 0.5,
+            layer_mix_method: LayerMixMethod::Additive,
+            inverted: false,
+            modifies_alpha: false,
+        },
+        HeightmapLayer {
+            heightmap: &eroded,
+            channel: rgba_color_channel::R,
+            strength: 1.0,
+            layer_mix_method: LayerMixMethod::AdditiveClamp,
+            inverted: false,
+            modifies_alpha: false,
+        },
+        HeightmapLayer {
+            heightmap: &deposited,
+            channel: rgba_color_channel::B,
+            strength: 1.0,
+            layer_mix_method: LayerMixMethod::AdditiveClamp,
+            inverted: false,
+            modifies_alpha: false,
+        },
+    ];
+    let layers: Vec<&HeightmapLayer> = layers.iter().collect();
+    (
+        layered_heightmaps_to_image(heightmap.width, &layers, false, 1.0),
+        Composite::from_layers(&layers, false, 1.0),
+    )
+}
+
+/// Colours the terrain with `palette` and paints submerged cells blue, darker with depth.
+pub fn sea_to_image(heightmap: &Heightmap, sea_level: f32, palette: Palette) -> LayerImage {
+    const SHALLOW: [f32; 3] = [70.0, 140.0, 210.0];
+    const DEEP: [f32; 3] = [10.0, 30.0, 90.0];
+    let (mut image, _) = palette.image(heightmap);
+    let depth_range = sea_level.max(f32::EPSILON);
+    for y in 0..heightmap.height {
+        for x in 0..heightmap.width {
+            let height = heightmap.data[x][y];
+            if height >= sea_level {
+                continue;
+            }
+            let depth = ((sea_level - height) / depth_range).min(1.0);
+            let i = (y * heightmap.width + x) * 4;
+            for c in 0..3 {
+                image.as_mut()[i + c] = (SHALLOW[c] + (DEEP[c] - SHALLOW[c]) * depth).round() as u8;
+            }
+        }
+    }
+    image
+}
+
+/// Owned copy of the layers a composite image was blended from, kept for exporting them separately.
+#[derive(Debug, Clone)]
+pub struct Composite {
+    pub layers: Vec<CompositeLayer>,
+    pub normalize_on_overflow: bool,
+    pub max_height: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompositeLayer {
+    pub heightmap: Rc<Heightmap>,
+    pub channel: rgba_color_channel::Channel,
+    pub strength: f32,
+    pub layer_mix_method: LayerMixMethod,
+    pub inverted: bool,
+    pub modifies_alpha: bool,
+}
+
+impl Composite {
+    pub fn from_layers(
+        layers: &[&HeightmapLayer],
+        normalize_on_overflow: bool,
+        max_height: f32,
+    ) -> Self {
+        Composite {
+            layers: layers
+                .iter()
+                .map(|layer| CompositeLayer {
+                    heightmap: Rc::new(layer.heightmap.clone()),
+                    channel: layer.channel,
+                    strength: layer.strength,
+                    layer_mix_method: layer.layer_mix_method,
+                    inverted: layer.inverted,
+                    modifies_alpha: layer.modifies_alpha,
+                })
+                .collect(),
+            normalize_on_overflow,
+            max_height,
+        }
+    }
+}
+
+pub fn channel_names(channel: rgba_color_channel::Channel) -> String {
+    [
+        (rgba_color_channel::R, 'R'),
+        (rgba_color_channel::G, 'G'),
+        (rgba_color_channel::B, 'B'),
+        (rgba_color_channel::A, 'A'),
+    ]
+    .iter()
+    .filter(|(bit, _)| channel & bit == *bit)
+    .map(|(_, name)| *name)
+    .collect()
+}
+
+#[cfg(feature = "gui")]
 pub fn layered_heightmaps_to_texture(
     size: usize,
     layers: &Vec<&HeightmapLayer>,
@@ -256,7 +592,7 @@ pub fn layered_heightmaps_to_texture(
     max_height: f32,
 ) -> Texture2D {
     let image = layered_heightmaps_to_image(size, layers, normalize_on_overflow, max_height);
-    Texture2D::from_image(&image)
+    image_to_texture(&image)
 }
 
 pub fn layered_heightmaps_to_image(
@@ -264,7 +600,7 @@ pub fn layered_heightmaps_to_image(
     layers: &Vec<&HeightmapLayer>,
     normalize_on_overflow: bool,
     max_height: f32,
-) -> Image {
+) -> LayerImage {
     let mut buffer: Vec<f32> = vec![0.0; 4 * size * size];
     let mut highest = 0f32;
 
@@ -343,21 +679,16 @@ pub fn layered_heightmaps_to_image(
         }
     }
 
-    let image = Image {
-        bytes: buffer
-            .iter()
-            .map(|&float| {
-                let value = if normalize_on_overflow && highest > max_height {
-                    float / (highest / max_height)
-                } else {
-                    float
-                };
-                (value / max_height * 255.0).trunc() as u8
-            })
-            .collect(),
-        width: size as u16,
-        height: size as u16,
-    };
-
-    image
+    let bytes = buffer
+        .iter()
+        .map(|&float| {
+            let value = if normalize_on_overflow && highest > max_height {
+                float / (highest / max_height)
+            } else {
+                float
+            };
+            (value / max_height * 255.0).trunc() as u8
+        })
+        .collect();
+    layer_image(size, size, bytes)
 }
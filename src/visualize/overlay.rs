@@ -0,0 +1,243 @@
+#[cfg(feature = "gui")]
+use crate::visualize::view::View;
+#[cfg(feature = "gui")]
+use emath::Rect;
+#[cfg(feature = "gui")]
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+
+pub type Polyline = Vec<(f64, f64)>;
+
+#[derive(Debug)]
+pub enum OverlayError {
+    RWError(std::io::Error),
+    InvalidJson(serde_json::Error),
+    InvalidGeometry(String),
+}
+
+impl From<std::io::Error> for OverlayError {
+    fn from(err: std::io::Error) -> Self {
+        OverlayError::RWError(err)
+    }
+}
+
+impl From<serde_json::Error> for OverlayError {
+    fn from(err: serde_json::Error) -> Self {
+        OverlayError::InvalidJson(err)
+    }
+}
+
+/// Line and polygon features from a GeoJSON file, drawn on top of the canvas.
+/// `bounds` ([min x, min y, max x, max y] in source coordinates) is stretched over the heightmap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorOverlay {
+    pub path: String,
+    pub visible: bool,
+    pub thickness: f32,
+    pub bounds: [f64; 4],
+    pub lines: Vec<Polyline>,
+}
+
+impl Default for VectorOverlay {
+    fn default() -> Self {
+        VectorOverlay {
+            path: String::new(),
+            visible: true,
+            thickness: 2.0,
+            bounds: [0.0, 0.0, 1.0, 1.0],
+            lines: Vec::new(),
+        }
+    }
+}
+
+impl VectorOverlay {
+    pub fn load(&mut self) -> Result<(), OverlayError> {
+        let json: Value = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+        let mut lines = Vec::new();
+        collect_lines(&json, &mut lines)?;
+        if lines.is_empty() {
+            return Err(OverlayError::InvalidGeometry(
+                "no line or polygon features found".to_string(),
+            ));
+        }
+        self.lines = lines;
+        self.bounds = match json.get("bbox").and_then(parse_bbox) {
+            Some(bounds) => bounds,
+            None => self.data_bounds(),
+        };
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Bounding box of all loaded coordinates.
+    pub fn data_bounds(&self) -> [f64; 4] {
+        let mut bounds = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+        for &(x, y) in self.lines.iter().flatten() {
+            bounds = [
+                bounds[0].min(x),
+                bounds[1].min(y),
+                bounds[2].max(x),
+                bounds[3].max(y),
+            ];
+        }
+        if bounds[0] > bounds[2] {
+            [0.0, 0.0, 1.0, 1.0]
+        } else {
+            bounds
+        }
+    }
+
+    /// Maps source coordinates to [0, 1] heightmap space, north is up.
+    pub fn normalized(&self, point: &(f64, f64)) -> (f32, f32) {
+        let [min_x, min_y, max_x, max_y] = self.bounds;
+        let width = if max_x > min_x { max_x - min_x } else { 1.0 };
+        let height = if max_y > min_y { max_y - min_y } else { 1.0 };
+        (
+            ((point.0 - min_x) / width) as f32,
+            ((max_y - point.1) / height) as f32,
+        )
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn draw(&self, rect: &Rect, view: &View) {
+        if !self.visible {
+            return;
+        }
+        let lines: Vec<Vec<(f32, f32)>> = self
+            .lines
+            .iter()
+            .map(|line| line.iter().map(|p| self.normalized(p)).collect())
+            .collect();
+        draw_polylines(rect, view, &lines, self.thickness, SKYBLUE);
+    }
+}
+
+/// Draws polylines given in [0, 1] heightmap space over the visible part of the heightmap frame.
+#[cfg(feature = "gui")]
+pub fn draw_polylines(
+    rect: &Rect,
+    view: &View,
+    lines: &[Vec<(f32, f32)>],
+    thickness: f32,
+    color: Color,
+) {
+    for line in lines {
+        for segment in line.windows(2) {
+            if let Some((a, b)) = view.clip(segment[0], segment[1]) {
+                let (x1, y1) = view.to_screen(rect, a);
+                let (x2, y2) = view.to_screen(rect, b);
+                draw_line(x1, y1, x2, y2, thickness, color);
+            }
+        }
+    }
+}
+
+fn parse_bbox(value: &Value) -> Option<[f64; 4]> {
+    let bbox = value.as_array()?;
+    // 3D bounding boxes list [min x, min y, min z, max x, max y, max z]
+    let (max_x, max_y) = if bbox.len() == 6 { (3, 4) } else { (2, 3) };
+    Some([
+        bbox.first()?.as_f64()?,
+        bbox.get(1)?.as_f64()?,
+        bbox.get(max_x)?.as_f64()?,
+        bbox.get(max_y)?.as_f64()?,
+    ])
+}
+
+fn parse_position(value: &Value) -> Result<(f64, f64), OverlayError> {
+    let position = value.as_array();
+    match position.map(|p| {
+        (
+            p.first().and_then(Value::as_f64),
+            p.get(1).and_then(Value::as_f64),
+        )
+    }) {
+        Some((Some(x), Some(y))) => Ok((x, y)),
+        _ => Err(OverlayError::InvalidGeometry(format!(
+            "invalid position {}",
+            value
+        ))),
+    }
+}
+
+fn parse_positions(value: &Value) -> Result<Polyline, OverlayError> {
+    value
+        .as_array()
+        .ok_or_else(|| OverlayError::InvalidGeometry("expected a list of positions".to_string()))?
+        .iter()
+        .map(parse_position)
+        .collect()
+}
+
+fn parse_list<T>(
+    value: &Value,
+    parse: fn(&Value) -> Result<T, OverlayError>,
+) -> Result<Vec<T>, OverlayError> {
+    value
+        .as_array()
+        .ok_or_else(|| OverlayError::InvalidGeometry("expected a list".to_string()))?
+        .iter()
+        .map(parse)
+        .collect()
+}
+
+/// Walks feature collections, features and geometry collections, keeping lines and polygon rings.
+/// Points are skipped since they have no extent to draw.
+fn collect_lines(value: &Value, lines: &mut Vec<Polyline>) -> Result<(), OverlayError> {
+    let coordinates = value.get("coordinates").unwrap_or(&Value::Null);
+    match value.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            for feature in value
+                .get("features")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                collect_lines(feature, lines)?;
+            }
+        }
+        Some("Feature") => {
+            if let Some(geometry) = value.get("geometry").filter(|g| !g.is_null()) {
+                collect_lines(geometry, lines)?;
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in value
+                .get("geometries")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                collect_lines(geometry, lines)?;
+            }
+        }
+        Some("LineString") => lines.push(parse_positions(coordinates)?),
+        Some("MultiLineString") | Some("Polygon") => {
+            lines.append(&mut parse_list(coordinates, parse_positions)?);
+        }
+        Some("MultiPolygon") => {
+            for mut polygon in parse_list(coordinates, |rings| parse_list(rings, parse_positions))?
+            {
+                lines.append(&mut polygon);
+            }
+        }
+        Some("Point") | Some("MultiPoint") => (),
+        Some(other) => {
+            return Err(OverlayError::InvalidGeometry(format!(
+                "unsupported type {}",
+                other
+            )))
+        }
+        None => {
+            return Err(OverlayError::InvalidGeometry(
+                "object without a type".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,669 @@
+use crate::heightmap::hydrology::Lakes;
+use crate::heightmap::segments::Segmentation;
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use crate::visualize::app_state::AppState;
+use crate::visualize::{layer_image, LayerImage};
+#[cfg(feature = "gui")]
+use emath::Rect;
+use image::Rgba;
+#[cfg(feature = "gui")]
+use macroquad::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+
+/*
+Colour stops are sampled from the published palettes:
+- Viridis, Cividis and Magma (matplotlib), perceptually uniform and readable with colour vision
+  deficiencies
+- Terrain (matplotlib), a hypsometric tint from sea blue over green lowlands to snowy peaks
+- PuOr (ColorBrewer), a diverging palette safe for deuteranopia and protanopia
+- RdBu (ColorBrewer), a diverging palette for signed differences, red for loss and blue for gain
+  like the erosion and deposition composite
+- Okabe & Ito, eight categorical colours distinguishable under all common colour vision deficiencies
+ */
+
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+const CIVIDIS: [[u8; 3]; 5] = [
+    [0, 34, 78],
+    [65, 77, 107],
+    [124, 123, 120],
+    [188, 175, 111],
+    [254, 232, 56],
+];
+
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+
+// Unevenly spaced, the stops are at their position in [0, 1]
+const TERRAIN: [(f32, [u8; 3]); 6] = [
+    (0.0, [51, 51, 153]),
+    (0.15, [0, 153, 255]),
+    (0.25, [0, 204, 102]),
+    (0.5, [255, 255, 153]),
+    (0.75, [128, 92, 84]),
+    (1.0, [255, 255, 255]),
+];
+
+const PURPLE_ORANGE: [[u8; 3]; 7] = [
+    [84, 39, 136],
+    [153, 142, 195],
+    [216, 218, 235],
+    [247, 247, 247],
+    [254, 224, 182],
+    [241, 163, 64],
+    [179, 88, 6],
+];
+
+const RED_BLUE: [[u8; 3]; 7] = [
+    [178, 24, 43],
+    [239, 138, 98],
+    [253, 219, 199],
+    [247, 247, 247],
+    [209, 229, 240],
+    [103, 169, 207],
+    [33, 102, 172],
+];
+
+const OKABE_ITO: [[u8; 3]; 8] = [
+    [0, 0, 0],
+    [230, 159, 0],
+    [86, 180, 233],
+    [0, 158, 115],
+    [240, 228, 66],
+    [0, 114, 178],
+    [213, 94, 0],
+    [204, 121, 167],
+];
+
+#[derive(Debug, Default, PartialEq, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Palette {
+    #[default]
+    Grayscale,
+    Viridis,
+    Cividis,
+    Magma,
+    Terrain,
+    PurpleOrange,
+    RedBlue,
+    OkabeIto,
+}
+
+impl Display for Palette {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Palette::Grayscale => f.write_str("Grayscale"),
+            Palette::Viridis => f.write_str("Viridis"),
+            Palette::Cividis => f.write_str("Cividis"),
+            Palette::Magma => f.write_str("Magma"),
+            Palette::Terrain => f.write_str("Terrain"),
+            Palette::PurpleOrange => f.write_str("Purple-Orange (diverging)"),
+            Palette::RedBlue => f.write_str("Red-Blue (diverging)"),
+            Palette::OkabeIto => f.write_str("Okabe-Ito (categorical)"),
+        }
+    }
+}
+
+impl Palette {
+    pub fn list() -> [Palette; 8] {
+        [
+            Palette::Grayscale,
+            Palette::Viridis,
+            Palette::Cividis,
+            Palette::Magma,
+            Palette::Terrain,
+            Palette::PurpleOrange,
+            Palette::RedBlue,
+            Palette::OkabeIto,
+        ]
+    }
+
+    /// Diverging palettes are centred on zero so that gains and losses get equal weight.
+    pub fn is_diverging(self) -> bool {
+        matches!(self, Palette::PurpleOrange | Palette::RedBlue)
+    }
+
+    /// Maps `t` in [0, 1] to a colour.
+    pub fn color(self, t: f32) -> [u8; 3] {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        match self {
+            Palette::Grayscale => {
+                let v = (t * 255.0).round() as u8;
+                [v, v, v]
+            }
+            Palette::Viridis => interpolate(&VIRIDIS, t),
+            Palette::Cividis => interpolate(&CIVIDIS, t),
+            Palette::Magma => interpolate(&MAGMA, t),
+            Palette::Terrain => interpolate_positioned(&TERRAIN, t),
+            Palette::PurpleOrange => interpolate(&PURPLE_ORANGE, t),
+            Palette::RedBlue => interpolate(&RED_BLUE, t),
+            Palette::OkabeIto => {
+                let class = (t * OKABE_ITO.len() as f32) as usize;
+                OKABE_ITO[class.min(OKABE_ITO.len() - 1)]
+            }
+        }
+    }
+
+    /// The value range mapped onto the palette for `heightmap`.
+    pub fn range(self, heightmap: &Heightmap) -> (HeightmapPrecision, HeightmapPrecision) {
+        let (min, max) = heightmap.get_range();
+        if self.is_diverging() {
+            let extent = min.abs().max(max.abs());
+            (-extent, extent)
+        } else {
+            (min, max)
+        }
+    }
+
+    pub fn image(self, heightmap: &Heightmap) -> (LayerImage, Legend) {
+        let (min, max) = self.range(heightmap);
+        let span = if max > min { max - min } else { 1.0 };
+        let mut bytes = Vec::with_capacity(heightmap.width * heightmap.height * 4);
+        for y in 0..heightmap.height {
+            for x in 0..heightmap.width {
+                let [r, g, b] = self.color((heightmap.data[x][y] - min) / span);
+                bytes.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        let image = layer_image(heightmap.width, heightmap.height, bytes);
+        (
+            image,
+            Legend {
+                colormap: self.into(),
+                min,
+                max,
+            },
+        )
+    }
+}
+
+fn interpolate(stops: &[[u8; 3]], t: f32) -> [u8; 3] {
+    let position = t * (stops.len() - 1) as f32;
+    let i = (position as usize).min(stops.len() - 2);
+    let k = position - i as f32;
+    let mut color = [0u8; 3];
+    for c in 0..3 {
+        let a = stops[i][c] as f32;
+        let b = stops[i + 1][c] as f32;
+        color[c] = (a + (b - a) * k).round() as u8;
+    }
+    color
+}
+
+/// Colours between stops at increasing positions in [0, 1].
+fn interpolate_positioned(stops: &[(f32, [u8; 3])], t: f32) -> [u8; 3] {
+    let next = stops
+        .iter()
+        .position(|(position, _)| *position > t)
+        .unwrap_or(stops.len() - 1)
+        .max(1);
+    let (p0, c0) = stops[next - 1];
+    let (p1, c1) = stops[next];
+    let k = if p1 > p0 {
+        ((t - p0) / (p1 - p0)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let mut color = [0u8; 3];
+    for c in 0..3 {
+        color[c] = (c0[c] as f32 + (c1[c] as f32 - c0[c] as f32) * k).round() as u8;
+    }
+    color
+}
+
+pub const MAX_GRADIENT_STOPS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    pub position: f32, // [0, 1]
+    pub color: [u8; 3],
+}
+
+/// A colour ramp made in the gradient editor, between two and `MAX_GRADIENT_STOPS` stops.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gradient {
+    pub stops: Vec<GradientStop>,
+}
+
+impl Default for Gradient {
+    /// Lowlands to snow, a starting point for a hypsometric tint.
+    fn default() -> Self {
+        Gradient {
+            stops: [
+                (0.0, [34, 102, 51]),
+                (0.35, [143, 179, 92]),
+                (0.6, [204, 179, 115]),
+                (0.85, [140, 107, 87]),
+                (1.0, [250, 250, 250]),
+            ]
+            .iter()
+            .map(|&(position, color)| GradientStop { position, color })
+            .collect(),
+        }
+    }
+}
+
+impl Gradient {
+    pub fn color(&self, t: f32) -> [u8; 3] {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let mut stops: Vec<(f32, [u8; 3])> = self
+            .stops
+            .iter()
+            .map(|stop| (stop.position, stop.color))
+            .collect();
+        match stops.len() {
+            0 => return [0, 0, 0],
+            1 => return stops[0].1,
+            _ => {}
+        }
+        // The editor lets stops pass each other
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        interpolate_positioned(&stops, t)
+    }
+
+    /// Adds a stop halfway into the widest gap, coloured as the gradient already is there.
+    pub fn split_widest(&mut self) {
+        if self.stops.len() >= MAX_GRADIENT_STOPS {
+            return;
+        }
+        let mut positions: Vec<f32> = self.stops.iter().map(|stop| stop.position).collect();
+        positions.extend([0.0, 1.0]);
+        positions.sort_by(|a, b| a.total_cmp(b));
+        let position = positions
+            .windows(2)
+            .max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+            .map(|gap| (gap[0] + gap[1]) / 2.0)
+            .unwrap_or(0.5);
+        let color = self.color(position);
+        self.stops.push(GradientStop { position, color });
+    }
+}
+
+/// Colours of plain heightmap textures, [0, depth] mapped onto a palette or the custom gradient.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Colormap {
+    pub palette: Palette,
+    pub custom: bool,
+    #[serde(default)]
+    pub gradient: Gradient,
+}
+
+impl From<Palette> for Colormap {
+    fn from(palette: Palette) -> Self {
+        Colormap {
+            palette,
+            ..Colormap::default()
+        }
+    }
+}
+
+impl Colormap {
+    /// Maps `t` in [0, 1] to a colour.
+    pub fn color(&self, t: f32) -> [u8; 3] {
+        if self.custom {
+            self.gradient.color(t)
+        } else {
+            self.palette.color(t)
+        }
+    }
+
+    pub fn name(&self) -> String {
+        if self.custom {
+            "Custom Gradient".to_string()
+        } else {
+            self.palette.to_string()
+        }
+    }
+}
+
+/*
+Heightmaps are turned into textures in many places that know nothing about the ui, so the colormap
+selected in the ui is kept here for them. It only lives on the main thread, where the textures are
+made.
+ */
+thread_local! {
+    static ACTIVE_COLORMAP: RefCell<Colormap> = RefCell::new(Colormap::default());
+}
+
+/// The colormap plain heightmap textures are currently made with.
+pub fn active_colormap() -> Colormap {
+    ACTIVE_COLORMAP.with(|colormap| colormap.borrow().clone())
+}
+
+pub fn set_active_colormap(colormap: &Colormap) {
+    ACTIVE_COLORMAP.with(|active| *active.borrow_mut() = colormap.clone());
+}
+
+/// Describes how the values of a displayed layer map to colours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Legend {
+    pub colormap: Colormap,
+    pub min: HeightmapPrecision,
+    pub max: HeightmapPrecision,
+}
+
+impl Legend {
+    /// Plain heightmap textures map [0, depth] onto the active colormap.
+    pub fn heights(heightmap: &Heightmap) -> Self {
+        Legend {
+            colormap: active_colormap(),
+            min: 0.0,
+            max: heightmap.depth,
+        }
+    }
+
+    pub fn labels(&self) -> (String, String) {
+        (format_value(self.min), format_value(self.max))
+    }
+}
+
+fn format_value(value: HeightmapPrecision) -> String {
+    if value.abs() >= 1000.0 {
+        format!("{:.0}", value)
+    } else if value.abs() >= 1.0 {
+        format!("{:.2}", value)
+    } else {
+        format!("{:.4}", value)
+    }
+}
+
+#[cfg(feature = "gui")]
+const LEGEND_STEPS: usize = 64;
+
+/// Draws a scale bar along the bottom of the heightmap frame inside `rect`.
+#[cfg(feature = "gui")]
+pub fn draw_legend(rect: &Rect, legend: &Legend) {
+    let side = rect.width().min(rect.height());
+    let left = rect.min.x + (rect.width() - side) / 2.0;
+    let bottom = rect.min.y + (rect.height() + side) / 2.0;
+    let padding = 8.0;
+    let bar_width = (side / 2.0).max(64.0);
+    let bar_height = 12.0;
+    let font_size = 16.0;
+    let x = left + padding;
+    let y = bottom - padding - bar_height - font_size;
+
+    draw_rectangle(
+        x - 4.0,
+        y - 4.0,
+        bar_width + 8.0,
+        bar_height + font_size + 8.0,
+        Color::new(0.0, 0.0, 0.0, 0.6),
+    );
+    let step_width = bar_width / LEGEND_STEPS as f32;
+    for i in 0..LEGEND_STEPS {
+        let [r, g, b] = legend
+            .colormap
+            .color((i as f32 + 0.5) / LEGEND_STEPS as f32);
+        draw_rectangle(
+            x + i as f32 * step_width,
+            y,
+            step_width + 0.5,
+            bar_height,
+            Color::from_rgba(r, g, b, 255),
+        );
+    }
+    let (min, max) = legend.labels();
+    let text_y = y + bar_height + font_size - 2.0;
+    draw_text(&min, x, text_y, font_size, WHITE);
+    let max_width = measure_text(&max, None, font_size as u16, 1.0).width;
+    draw_text(&max, x + bar_width - max_width, text_y, font_size, WHITE);
+}
+
+/*
+Baked legends cannot rely on a font being available, so labels are drawn with a 3x5 pixel font.
+Each glyph is five rows of three bits, most significant bit on the left.
+ */
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => return None,
+    })
+}
+
+fn fill(image: &mut LayerImage, x: usize, y: usize, w: usize, h: usize, color: [u8; 3]) {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            image.put_pixel(
+                px as u32,
+                py as u32,
+                Rgba([color[0], color[1], color[2], 255]),
+            );
+        }
+    }
+}
+
+fn text_width(text: &str, scale: usize) -> usize {
+    (text.chars().count() * 4).saturating_sub(1) * scale
+}
+
+fn draw_pixel_text(image: &mut LayerImage, text: &str, x: usize, y: usize, scale: usize) {
+    for (i, c) in text.chars().enumerate() {
+        if let Some(rows) = glyph(c) {
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        let px = x + (i * 4 + col) * scale;
+                        let py = y + row * scale;
+                        fill(image, px, py, scale, scale, [255, 255, 255]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns a copy of `image` with a strip holding the legend scale bar appended below it.
+pub fn with_legend(image: &LayerImage, legend: &Legend) -> LayerImage {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let scale = (width / 256).max(1);
+    let padding = 4 * scale;
+    let bar_height = 8 * scale;
+    let strip_height = padding * 3 + bar_height + 5 * scale;
+
+    let mut bytes = image.as_raw().clone();
+    bytes.resize((width * (height + strip_height)) * 4, 0);
+    let mut result = layer_image(width, height + strip_height, bytes);
+    fill(&mut result, 0, height, width, strip_height, [0, 0, 0]);
+
+    let bar_width = width.saturating_sub(padding * 2).max(1);
+    for i in 0..bar_width {
+        let color = legend.colormap.color((i as f32 + 0.5) / bar_width as f32);
+        fill(
+            &mut result,
+            padding + i,
+            height + padding,
+            1,
+            bar_height,
+            color,
+        );
+    }
+
+    let (min, max) = legend.labels();
+    let text_y = height + padding * 2 + bar_height;
+    draw_pixel_text(&mut result, &min, padding, text_y, scale);
+    let max_x = (padding + bar_width).saturating_sub(text_width(&max, scale));
+    draw_pixel_text(&mut result, &max, max_x, text_y, scale);
+    result
+}
+
+/// A colour for every label, neighbouring labels a golden angle apart in hue so they stand apart.
+pub fn label_color(label: usize) -> [u8; 3] {
+    let hue = (label as f32 * 137.508).rem_euclid(360.0) / 60.0;
+    // Alternating lightness keeps labels with close hues apart
+    let (saturation, value) = if label & 1 == 0 {
+        (0.65, 0.95)
+    } else {
+        (0.8, 0.7)
+    };
+    let chroma = value * saturation;
+    let second = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as usize {
+        0 => (chroma, second, 0.0),
+        1 => (second, chroma, 0.0),
+        2 => (0.0, chroma, second),
+        3 => (0.0, second, chroma),
+        4 => (second, 0.0, chroma),
+        _ => (chroma, 0.0, second),
+    };
+    let base = value - chroma;
+    [r, g, b].map(|c| ((c + base) * 255.0).round() as u8)
+}
+
+/// Every region of a segmentation in the colour of its label.
+pub fn label_image(segmentation: &Segmentation) -> LayerImage {
+    let mut bytes = Vec::with_capacity(segmentation.width * segmentation.height * 4);
+    for y in 0..segmentation.height {
+        for x in 0..segmentation.width {
+            let [r, g, b] = label_color(segmentation.labels[x][y]);
+            bytes.extend_from_slice(&[r, g, b, 255]);
+        }
+    }
+    layer_image(segmentation.width, segmentation.height, bytes)
+}
+
+/// The terrain in gray with the lakes in blue, darker where they are deeper.
+pub fn lakes_image(heightmap: &Heightmap, lakes: &Lakes) -> LayerImage {
+    const SHALLOW: [f32; 3] = [150.0, 200.0, 240.0];
+    const DEEP: [f32; 3] = [20.0, 60.0, 140.0];
+    let mut bytes = Vec::with_capacity(heightmap.width * heightmap.height * 4);
+    for y in 0..heightmap.height {
+        for x in 0..heightmap.width {
+            let rgb = match lakes.labels[x][y] {
+                Some(label) => {
+                    let t = lakes.depth_at(heightmap, x, y)
+                        / lakes.lakes[label]
+                            .max_depth
+                            .max(HeightmapPrecision::EPSILON);
+                    let t = t.clamp(0.0, 1.0);
+                    [0, 1, 2].map(|i| (SHALLOW[i] + (DEEP[i] - SHALLOW[i]) * t) as u8)
+                }
+                None => {
+                    let gray = (heightmap.data[x][y] / heightmap.depth).clamp(0.0, 1.0);
+                    [(gray * 255.0) as u8; 3]
+                }
+            };
+            bytes.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+        }
+    }
+    layer_image(heightmap.width, heightmap.height, bytes)
+}
+
+/// Recolours the textures of every state when the colormap selected in the ui has changed.
+pub fn poll_colormap(colormap: &Colormap, app_state: &mut AppState) {
+    if *colormap == active_colormap() {
+        return;
+    }
+    set_active_colormap(colormap);
+    app_state.regenerate_textures(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{tiny_heightmap, Check, SIZE};
+
+    #[test]
+    fn colormaps() -> Check {
+        use crate::visualize::palette::{Colormap, Gradient, Palette};
+        let heightmap = tiny_heightmap();
+        let gray = heightmap.to_u8_rgba(|t| Palette::Grayscale.color(t));
+        let expected = (heightmap.data[0][0] / heightmap.depth * 255.0).round() as u8;
+        if gray[0..4] != [expected, expected, expected, 255] {
+            return Err("grayscale pixels changed".to_string());
+        }
+        if Palette::Terrain.color(0.25) != [0, 204, 102]
+            || Palette::Magma.color(1.0) != [252, 253, 191]
+        {
+            return Err("palettes miss their stops".to_string());
+        }
+        let mut colormap = Colormap {
+            custom: true,
+            ..Colormap::default()
+        };
+        let gradient = Gradient::default();
+        if colormap.color(0.0) != gradient.stops[0].color
+            || colormap.color(1.0) != gradient.stops.last().unwrap().color
+        {
+            return Err("custom gradient does not end at its stops".to_string());
+        }
+        let middle = colormap.color(0.5);
+        colormap.gradient.split_widest();
+        if colormap.gradient.stops.len() != gradient.stops.len() + 1
+            || colormap.color(0.5) != middle
+        {
+            return Err("adding a stop changed the gradient".to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn signed_difference() -> Check {
+        let base = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        let mut eroded = base.clone();
+        eroded.data[1][2] -= 0.25;
+        eroded.data[3][4] += 0.125;
+        let diff = eroded
+            .subtract_signed(&base)
+            .map_err(|err| format!("{:?}", err))?;
+        if diff.data[1][2] != -0.25 || diff.data[3][4] != 0.125 || diff.data[0][0] != 0.0 {
+            return Err("differences lost their sign".to_string());
+        }
+        let (image, legend) = Palette::RedBlue.image(&diff);
+        if legend.min != -0.25 || legend.max != 0.25 {
+            return Err(format!("range {} to {}", legend.min, legend.max));
+        }
+        let pixel = |x: usize, y: usize| {
+            let i = (y * diff.width + x) * 4;
+            [
+                image.as_raw()[i],
+                image.as_raw()[i + 1],
+                image.as_raw()[i + 2],
+            ]
+        };
+        let (removed, deposited, unchanged) = (pixel(1, 2), pixel(3, 4), pixel(0, 0));
+        if removed[0] <= removed[2] || deposited[2] <= deposited[0] || unchanged != [247, 247, 247]
+        {
+            return Err(format!(
+                "removed {:?}, deposited {:?}, unchanged {:?}",
+                removed, deposited, unchanged
+            ));
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,29 @@
+use crate::erode::PartitionReport;
+use crate::heightmap::analysis::SeamMetrics;
+#[cfg(feature = "export")]
+use crate::heightmap::contours::ContourFormat;
+use crate::heightmap::hydrology::{Drainage, Lakes};
+#[cfg(feature = "export")]
+use crate::heightmap::io::{DataFormat, ExportCrop};
+#[cfg(feature = "export")]
+use crate::heightmap::mesh::MeshFormat;
+use crate::heightmap::{DerivedLayer, Heightmap};
+use crate::visualize::app_state::ErodedState;
+use crate::visualize::droplets::DropletEnd;
 use crate::visualize::events::{UiEvent, UiWindow};
 use crate::visualize::keybinds::{
     UiKey, UiKeybind, KEYBINDS, KEYCODE_TOGGLE_ALL_UI, KEYCODE_TOGGLE_CONTROL_PANEL_UI,
-    KEYCODE_TOGGLE_KEYBINDS_UI, KEYCODE_TOGGLE_METADATA_UI, KEYCODE_TOGGLE_METRICS_UI,
+    KEYCODE_TOGGLE_HUD_UI, KEYCODE_TOGGLE_KEYBINDS_UI, KEYCODE_TOGGLE_METADATA_UI,
+    KEYCODE_TOGGLE_METRICS_UI, KEYCODE_TOGGLE_REGION_UI, KEYCODE_TOGGLE_SESSION_UI,
+    KEYCODE_TOGGLE_SNAPSHOTS_UI, KEYCODE_TOGGLE_TIMELINE_UI,
 };
+use crate::visualize::palette::{active_colormap, label_color};
+use crate::visualize::session::SESSION_LOG;
 use crate::visualize::ui::UiState;
-use egui::Rect;
+use egui::{Color32, ColorImage, Rect, Stroke, TextureHandle, TextureOptions, Vec2};
+use std::rc::Rc;
+
+const SNAPSHOT_THUMBNAIL_SIZE: usize = 64;
 
 use super::{widgets::*, AppState};
 
@@ -37,9 +56,142 @@ pub fn ui_save_as(
     }
 }
 
+pub fn ui_session_window(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &AppState) {
+    if ui_state.show_ui_session {
+        let response = ui_state
+            .workspaces
+            .place(
+                UiWindow::Session,
+                egui::Window::new(format!("Session [{:?}]", KEYCODE_TOGGLE_SESSION_UI)),
+            )
+            .show(egui_ctx, |ui| {
+                ui.label(state.session.summary());
+                ui.separator();
+                ui.checkbox(&mut ui_state.session_log, "Append to log on quit");
+                if ui.button("Append to log now").clicked() {
+                    ui_state.ui_events.push(UiEvent::AppendSessionLog);
+                }
+                ui.label(format!("Log file: {}", SESSION_LOG));
+            });
+        ui_state.workspaces.track(UiWindow::Session, &response);
+    }
+}
+
+pub fn ui_hud(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &AppState) {
+    if ui_state.show_ui_hud {
+        ui_state.hud.update(state);
+        egui::Area::new("hud")
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 32.0))
+            .interactable(false)
+            .show(egui_ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.monospace(ui_state.hud.text(state));
+                });
+            });
+    }
+}
+
+pub fn ui_region_window(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &AppState) {
+    if !ui_state.show_ui_region {
+        return;
+    }
+    let response = ui_state
+        .workspaces
+        .place(
+            UiWindow::Region,
+            egui::Window::new(format!("Region [{:?}]", KEYCODE_TOGGLE_REGION_UI)),
+        )
+        .show(egui_ctx, |ui| {
+            let region = &mut ui_state.region;
+            ui.horizontal(|ui| {
+                ui.toggle_value(&mut region.selecting, "Select");
+                if ui.button("Clear").clicked() {
+                    region.clear();
+                }
+            });
+            if region.selecting {
+                ui.label("Drag a rectangle on the canvas with the left mouse button.");
+            }
+
+            let heightmap = state.simulation_state().get_heightmap();
+            let stats = match (
+                region.cells(heightmap.width, heightmap.height),
+                region.stats(state),
+            ) {
+                (Some(cells), Some(stats)) => (cells, stats),
+                _ => {
+                    ui.label("No region selected.");
+                    return;
+                }
+            };
+            let (((min_x, min_y), (max_x, max_y)), stats) = stats;
+            ui.label(format!(
+                "Cells ({}, {}) to ({}, {}), {} in total",
+                min_x,
+                min_y,
+                max_x - 1,
+                max_y - 1,
+                stats.cells
+            ));
+            ui.separator();
+            egui::Grid::new("region_stats").show(ui, |ui| {
+                let mut row = |name: &str, value: String| {
+                    ui.label(name);
+                    ui.monospace(value);
+                    ui.end_row();
+                };
+                row("Mean height", format!("{:.5}", stats.mean));
+                row("Min height", format!("{:.5}", stats.min));
+                row("Max height", format!("{:.5}", stats.max));
+                row("Mean slope", format!("{:.5}", stats.mean_slope));
+                row("Max slope", format!("{:.5}", stats.max_slope));
+                if let (Some(diff), Some(eroded), Some(deposited)) =
+                    (stats.mean_diff, stats.eroded, stats.deposited)
+                {
+                    row("Mean diff vs base", format!("{:+.6}", diff));
+                    row("Eroded volume", format!("{:.5}", eroded));
+                    row("Deposited volume", format!("{:.5}", deposited));
+                }
+            });
+            if stats.mean_diff.is_none() {
+                ui.label("Select an eroded state to compare against its base.");
+            }
+        });
+    ui_state.workspaces.track(UiWindow::Region, &response);
+}
+
+pub fn ui_quit_prompt(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &AppState) {
+    if ui_state.quit_prompt {
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .show(egui_ctx, |ui| {
+                ui.label(state.session.summary());
+                ui.separator();
+                ui.label("Save before quitting?");
+                ui.horizontal(|ui| {
+                    #[cfg(feature = "export")]
+                    if ui.button("Save").clicked() {
+                        ui_state.ui_events.push(UiEvent::ExportState);
+                        ui_state.ui_events.push(UiEvent::ForceQuit);
+                        ui_state.quit_prompt = false;
+                    }
+                    if ui.button("Discard").clicked() {
+                        ui_state.ui_events.push(UiEvent::ForceQuit);
+                        ui_state.quit_prompt = false;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        ui_state.quit_prompt = false;
+                    }
+                });
+            });
+    }
+}
+
 pub fn ui_top_panel(
     egui_ctx: &egui::Context,
     ui_state: &mut UiState,
+    app_state: &AppState,
     state_name: &mut Option<String>,
 ) {
     egui::TopBottomPanel::top("top_panel").show(egui_ctx, |ui| {
@@ -81,6 +233,67 @@ pub fn ui_top_panel(
                         ui_state.ui_events.push(UiEvent::ExportActiveHeightmap);
                         ui.close_menu();
                     }
+                    if ui.button("Export Composite Layers").clicked() {
+                        ui_state.ui_events.push(UiEvent::ExportCompositeLayers);
+                        ui.close_menu();
+                    }
+                    ui.menu_button("Export Layers", |ui| {
+                        ui.label("Json and png, plus:");
+                        for format in DataFormat::list() {
+                            let mut selected = ui_state.export_formats.contains(&format);
+                            if ui.checkbox(&mut selected, format.to_string()).changed() {
+                                ui_state.export_formats.retain(|f| *f != format);
+                                if selected {
+                                    ui_state.export_formats.push(format);
+                                }
+                            }
+                        }
+                        ui.separator();
+                        ui.label("Crop margins to:");
+                        for crop in ExportCrop::list() {
+                            ui.radio_value(&mut ui_state.export_crop, crop, crop.to_string());
+                        }
+                        if ui.button("Export").clicked() {
+                            ui_state.ui_events.push(UiEvent::ExportHeightmap);
+                            ui.close_menu();
+                        }
+                    });
+                    ui.menu_button("Export Mesh", |ui| {
+                        for format in MeshFormat::list() {
+                            ui.radio_value(&mut ui_state.mesh.format, format, format.to_string());
+                        }
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.mesh.vertical_scale, 0.01..=2.0)
+                                .text("Vertical Scale"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ui_state.mesh.decimation, 1..=64)
+                                .text("Cells per Vertex"),
+                        );
+                        if ui.button("Export").clicked() {
+                            ui_state.ui_events.push(UiEvent::ExportMesh);
+                            ui.close_menu();
+                        }
+                    });
+                    ui.menu_button("Export Contours", |ui| {
+                        let lines = &mut ui_state.isoline.contour_lines;
+                        for format in ContourFormat::list() {
+                            ui.radio_value(&mut lines.format, format, format.to_string());
+                        }
+                        ui.label(if lines.enabled {
+                            "Contour lines of the isoline settings"
+                        } else {
+                            "The isoline only, enable contour lines for more"
+                        });
+                        if ui.button("Export").clicked() {
+                            ui_state.ui_events.push(UiEvent::ExportContours);
+                            ui.close_menu();
+                        }
+                    });
+                    if ui.button("Export Masks").clicked() {
+                        ui_state.ui_events.push(UiEvent::ExportMasks);
+                        ui.close_menu();
+                    }
                     if ui
                         .button(if ui_state.show_ui_presentation_mode {
                             "Exit Presentation Mode"
@@ -100,6 +313,48 @@ pub fn ui_top_panel(
                 ui.separator();
                 ui_save_as(egui_ctx, ui_state, state_name);
             }
+            ui.menu_button("Edit", |ui| {
+                if ui
+                    .add_enabled(app_state.history.can_undo(), egui::Button::new("Undo"))
+                    .on_hover_text("Ctrl+Z")
+                    .clicked()
+                {
+                    ui_state.ui_events.push(UiEvent::Undo);
+                    ui.close_menu();
+                }
+                if ui
+                    .add_enabled(app_state.history.can_redo(), egui::Button::new("Redo"))
+                    .on_hover_text("Ctrl+Y")
+                    .clicked()
+                {
+                    ui_state.ui_events.push(UiEvent::Redo);
+                    ui.close_menu();
+                }
+            });
+            ui.menu_button("Workspace", |ui| {
+                let active = ui_state.workspaces.active;
+                for (i, workspace) in ui_state.workspaces.list.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .selectable_label(active == Some(i), &workspace.name)
+                            .clicked()
+                        {
+                            ui_state.ui_events.push(UiEvent::SelectWorkspace(i));
+                            ui.close_menu();
+                        }
+                        if ui.small_button("x").on_hover_text("Delete").clicked() {
+                            ui_state.ui_events.push(UiEvent::DeleteWorkspace(i));
+                        }
+                    });
+                }
+                ui.separator();
+                ui.text_edit_singleline(&mut ui_state.workspaces.name)
+                    .on_hover_text("Leave empty to overwrite the active workspace");
+                if ui.button("Save Current Layout").clicked() {
+                    ui_state.ui_events.push(UiEvent::SaveWorkspace);
+                    ui.close_menu();
+                }
+            });
             if ui
                 .button(format!(
                     "[{:?}] {} UI",
@@ -174,6 +429,78 @@ pub fn ui_top_panel(
                     .ui_events
                     .push(UiEvent::ToggleUi(UiWindow::Metrics));
             };
+            if ui
+                .button(format!(
+                    "[{:?}] {} Snapshots",
+                    KEYCODE_TOGGLE_SNAPSHOTS_UI,
+                    if ui_state.show_ui_snapshots {
+                        "Hide"
+                    } else {
+                        "Show"
+                    }
+                ))
+                .clicked()
+            {
+                ui_state
+                    .ui_events
+                    .push(UiEvent::ToggleUi(UiWindow::Snapshots));
+            };
+            if ui
+                .button(format!(
+                    "[{:?}] {} Timeline",
+                    KEYCODE_TOGGLE_TIMELINE_UI,
+                    if ui_state.show_ui_timeline {
+                        "Hide"
+                    } else {
+                        "Show"
+                    }
+                ))
+                .clicked()
+            {
+                ui_state
+                    .ui_events
+                    .push(UiEvent::ToggleUi(UiWindow::Timeline));
+            };
+            if ui
+                .button(format!(
+                    "[{:?}] {} Session",
+                    KEYCODE_TOGGLE_SESSION_UI,
+                    if ui_state.show_ui_session {
+                        "Hide"
+                    } else {
+                        "Show"
+                    }
+                ))
+                .clicked()
+            {
+                ui_state
+                    .ui_events
+                    .push(UiEvent::ToggleUi(UiWindow::Session));
+            };
+            if ui
+                .button(format!(
+                    "[{:?}] {} HUD",
+                    KEYCODE_TOGGLE_HUD_UI,
+                    if ui_state.show_ui_hud { "Hide" } else { "Show" }
+                ))
+                .clicked()
+            {
+                ui_state.ui_events.push(UiEvent::ToggleUi(UiWindow::Hud));
+            };
+            if ui
+                .button(format!(
+                    "[{:?}] {} Region",
+                    KEYCODE_TOGGLE_REGION_UI,
+                    if ui_state.show_ui_region {
+                        "Hide"
+                    } else {
+                        "Show"
+                    }
+                ))
+                .clicked()
+            {
+                ui_state.ui_events.push(UiEvent::ToggleUi(UiWindow::Region));
+            };
         });
     });
 }
@@ -188,8 +515,46 @@ pub fn ui_side_panel(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &m
                     .default_open(true)
                     .show(ui, |ui| {
                         // Erosion Method Selection
-                        if ui.button("Run Simulation").clicked() {
-                            ui_state.ui_events.push(UiEvent::RunSimulation);
+                        if let Some(job) = &state.simulation_job {
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::ProgressBar::new(job.progress.fraction())
+                                        .show_percentage(),
+                                );
+                                if ui.button("Cancel").clicked() {
+                                    ui_state.ui_events.push(UiEvent::CancelSimulation);
+                                }
+                            });
+                        } else if let Some(incremental) = &state.incremental_simulation {
+                            let (_, simulation) = &mut *incremental.borrow_mut();
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::ProgressBar::new(
+                                        simulation.done as f32 / simulation.total() as f32,
+                                    )
+                                    .show_percentage(),
+                                );
+                                if ui.button("Cancel").clicked() {
+                                    ui_state.ui_events.push(UiEvent::CancelSimulation);
+                                }
+                            });
+                            let total = simulation.total();
+                            ui.add(
+                                egui::Slider::new(&mut simulation.chunk, 1..=total)
+                                    .logarithmic(true)
+                                    .text("Iterations per frame"),
+                            );
+                            heat_overlay_settings(ui, &mut ui_state.heat_overlay);
+                        } else {
+                            ui.horizontal(|ui| {
+                                if ui.button("Run Simulation").clicked() {
+                                    ui_state.ui_events.push(UiEvent::StartSimulation);
+                                }
+                                if ui.button("Animate").clicked() {
+                                    ui_state.ui_events.push(UiEvent::AnimateSimulation);
+                                }
+                            });
+                            heat_overlay_settings(ui, &mut ui_state.heat_overlay);
                         }
                         if ui.button("Clear Simulations").clicked() {
                             ui_state.ui_events.push(UiEvent::Clear);
@@ -206,12 +571,84 @@ pub fn ui_side_panel(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &m
                         if ui.button("Show difference normalized").clicked() {
                             ui_state.ui_events.push(UiEvent::ShowDifferenceNormalized);
                         }
+                        if ui.button("Show signed difference").clicked() {
+                            ui_state.ui_events.push(UiEvent::ShowDifferenceSigned);
+                        }
+                        if let Some(eroded) = state.simulation_state().eroded() {
+                            if eroded.flow_map.is_some() && ui.button("Show flow map").clicked() {
+                                ui_state.ui_events.push(UiEvent::ShowFlowMap);
+                            }
+                            if eroded.erosion_map.is_some() && eroded.deposition_map.is_some() {
+                                if ui.button("Show erosion and deposition").clicked() {
+                                    ui_state.ui_events.push(UiEvent::ShowErosionDeposition);
+                                }
+                                ui.horizontal(|ui| {
+                                    if ui.button("Eroded").clicked() {
+                                        ui_state.ui_events.push(UiEvent::ShowErosionMap);
+                                    }
+                                    if ui.button("Deposited").clicked() {
+                                        ui_state.ui_events.push(UiEvent::ShowDepositionMap);
+                                    }
+                                });
+                            }
+                            if eroded.droplet_ends.is_some() {
+                                if ui.button("Show droplet spawns and deaths").clicked() {
+                                    ui_state.ui_events.push(UiEvent::ShowDropletScatter);
+                                }
+                                ui.horizontal(|ui| {
+                                    for end in [DropletEnd::Spawn, DropletEnd::Death] {
+                                        if ui.button(format!("{} Density", end)).clicked() {
+                                            ui_state
+                                                .ui_events
+                                                .push(UiEvent::ShowDropletDensity(end));
+                                        }
+                                    }
+                                });
+                                ui.add(
+                                    egui::Slider::new(&mut ui_state.droplet_bin, 1..=64)
+                                        .text("Density Bin Size"),
+                                );
+                            }
+                            ui.horizontal(|ui| {
+                                for (layer, _) in eroded.material_layers.iter() {
+                                    if ui.button(layer.to_string()).clicked() {
+                                        ui_state.ui_events.push(UiEvent::ShowMaterialLayer(*layer));
+                                    }
+                                }
+                            });
+                        }
+                        ui.horizontal_wrapped(|ui| {
+                            for layer in DerivedLayer::list() {
+                                if ui.button(layer.to_string()).clicked() {
+                                    ui_state.ui_events.push(UiEvent::ShowDerivedLayer(layer));
+                                }
+                            }
+                        });
                     });
                 erosion_method_selection(ui, ui_state, state);
                 erosion_parameter_selection(ui, state);
+                pipeline_settings(ui, ui_state, state);
+                sculpt_settings(ui, ui_state, state);
+                erosion_brush_settings(ui, ui_state, state);
+                hardness_settings(ui, ui_state, state);
+                vegetation_settings(ui, ui_state, state);
+                drop_zone_settings(ui, ui_state, state);
+                precipitation_settings(ui, ui_state, state);
+                sea_settings(ui, ui_state, state);
+                hillshade_settings(ui, ui_state);
+                segmentation_settings(ui, ui_state);
+                hydrology_settings(ui, ui_state);
+                layer_math_settings(ui, ui_state, state);
+                resample_settings(ui, ui_state, state);
+                crop_settings(ui, ui_state, state);
+                material_layer_settings(ui, state);
+                palette_settings(ui, ui_state);
+                vector_overlay_settings(ui, ui_state);
+                view_settings(ui, ui_state, state);
                 layer_selection(ui, state);
                 heightmap_generation_settings(ui, ui_state, state);
                 post_processing(ui, ui_state);
+                flood_animation_settings(ui, ui_state);
             });
         },
     );
@@ -219,9 +656,13 @@ pub fn ui_side_panel(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &m
 
 pub fn ui_keybinds_window(egui_ctx: &egui::Context, ui_state: &mut UiState) {
     if ui_state.show_ui_keybinds {
-        egui::Window::new(format!("Keybinds [{:?}]", KEYCODE_TOGGLE_KEYBINDS_UI)).show(
-            egui_ctx,
-            |ui| {
+        let response = ui_state
+            .workspaces
+            .place(
+                UiWindow::Keybinds,
+                egui::Window::new(format!("Keybinds [{:?}]", KEYCODE_TOGGLE_KEYBINDS_UI)),
+            )
+            .show(egui_ctx, |ui| {
                 for keybind in KEYBINDS {
                     match keybind {
                         UiKeybind::Pressed(keys, event) => {
@@ -256,104 +697,140 @@ pub fn ui_keybinds_window(egui_ctx: &egui::Context, ui_state: &mut UiState) {
                         }
                     }
                 }
-            },
-        );
+            });
+        ui_state.workspaces.track(UiWindow::Keybinds, &response);
     }
 }
 
 pub fn ui_metadata_window(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &mut AppState) {
     if ui_state.show_ui_metadata {
-        egui::Window::new(format!("Metadata")).show(egui_ctx, |ui| {
-            ui.heading("Base Heightmap");
-            ui.label(format!(
-                "Width x Height: {} x {}",
-                state
-                    .simulation_state()
-                    .base()
-                    .heightmap_base
-                    .heightmap
-                    .width,
-                state
-                    .simulation_state()
-                    .base()
-                    .heightmap_base
-                    .heightmap
-                    .height
-            ));
-            ui.label(format!(
-                "Depth: {}",
-                state
-                    .simulation_state()
-                    .base()
-                    .heightmap_base
-                    .heightmap
-                    .depth
-            ));
-            ui.label(format!(
-                "Original Depth: {}",
-                state
-                    .simulation_state()
-                    .base()
-                    .heightmap_base
-                    .heightmap
-                    .original_depth
-            ));
-            if let Some(height) = state
-                .simulation_state()
-                .get_heightmap()
-                .get_average_height()
-            {
-                ui.label(format!("Average Height: {}", height));
-            }
-            if let Some(height) = state
-                .simulation_state()
-                .base()
-                .heightmap_base
-                .heightmap
-                .total_height
-            {
-                ui.label(format!("Total Depth: {}", height));
-            }
-            if let Some(metadata) = state
-                .simulation_state()
-                .base()
-                .heightmap_base
-                .heightmap
-                .metadata
-                .clone()
-            {
-                for (k, v) in metadata.iter() {
-                    ui.label(format!("{}: {}", k, v));
-                }
-            }
-            if let Some(eroded) = state.simulation_state().eroded() {
-                ui.heading("Eroded Heightmap");
+        let response = ui_state
+            .workspaces
+            .place(UiWindow::Metadata, egui::Window::new(format!("Metadata")))
+            .show(egui_ctx, |ui| {
+                ui.heading("Session Notes");
+                ui.text_edit_multiline(&mut state.notes);
+                ui.heading(format!("Notes (State #{})", state.simulation_state().id()));
+                ui.text_edit_multiline(state.simulation_state_mut().notes_mut());
+                ui.heading("Base Heightmap");
                 ui.label(format!(
                     "Width x Height: {} x {}",
-                    eroded.heightmap_eroded.heightmap.width,
-                    eroded.heightmap_eroded.heightmap.height,
+                    state
+                        .simulation_state()
+                        .base()
+                        .heightmap_base
+                        .heightmap
+                        .width,
+                    state
+                        .simulation_state()
+                        .base()
+                        .heightmap_base
+                        .heightmap
+                        .height
                 ));
                 ui.label(format!(
                     "Depth: {}",
-                    eroded.heightmap_eroded.heightmap.depth
+                    state
+                        .simulation_state()
+                        .base()
+                        .heightmap_base
+                        .heightmap
+                        .depth
                 ));
                 ui.label(format!(
                     "Original Depth: {}",
-                    eroded.heightmap_eroded.heightmap.original_depth
+                    state
+                        .simulation_state()
+                        .base()
+                        .heightmap_base
+                        .heightmap
+                        .original_depth
                 ));
-                if let Some(height) = eroded.heightmap_eroded.heightmap.get_average_height() {
+                if let Some(height) = state
+                    .simulation_state()
+                    .get_heightmap()
+                    .get_average_height()
+                {
                     ui.label(format!("Average Height: {}", height));
                 }
-                if let Some(height) = eroded.heightmap_eroded.heightmap.total_height {
+                if let Some(height) = state
+                    .simulation_state()
+                    .base()
+                    .heightmap_base
+                    .heightmap
+                    .total_height
+                {
                     ui.label(format!("Total Depth: {}", height));
                 }
-                if let Some(metadata) = eroded.heightmap_eroded.heightmap.metadata.clone() {
+                if let Some(metadata) = state
+                    .simulation_state()
+                    .base()
+                    .heightmap_base
+                    .heightmap
+                    .metadata
+                    .clone()
+                {
                     for (k, v) in metadata.iter() {
                         ui.label(format!("{}: {}", k, v));
                     }
                 }
-            }
-        });
+                if let Some(eroded) = state.simulation_state().eroded() {
+                    ui.heading("Eroded Heightmap");
+                    ui.label(format!(
+                        "Width x Height: {} x {}",
+                        eroded.heightmap_eroded.heightmap.width,
+                        eroded.heightmap_eroded.heightmap.height,
+                    ));
+                    ui.label(format!(
+                        "Depth: {}",
+                        eroded.heightmap_eroded.heightmap.depth
+                    ));
+                    ui.label(format!(
+                        "Original Depth: {}",
+                        eroded.heightmap_eroded.heightmap.original_depth
+                    ));
+                    if let Some(height) = eroded.heightmap_eroded.heightmap.get_average_height() {
+                        ui.label(format!("Average Height: {}", height));
+                    }
+                    if let Some(height) = eroded.heightmap_eroded.heightmap.total_height {
+                        ui.label(format!("Total Depth: {}", height));
+                    }
+                    if let Some(metadata) = eroded.heightmap_eroded.heightmap.metadata.clone() {
+                        for (k, v) in metadata.iter() {
+                            ui.label(format!("{}: {}", k, v));
+                        }
+                    }
+
+                    ui.heading("Parameter Audit");
+                    let audit = state.parameters.audit_metadata(
+                        &state.simulation_state().base().erosion_method,
+                        state.simulation_state().base().adaptive_iterations,
+                        eroded.heightmap_eroded.heightmap.metadata.as_ref(),
+                    );
+                    let matching = audit.iter().filter(|entry| entry.matches()).count();
+                    ui.label(format!(
+                        "{} of {} settings match the current parameters",
+                        matching,
+                        audit.len()
+                    ));
+                    for entry in audit.iter().filter(|entry| !entry.matches()) {
+                        match &entry.recorded {
+                            Some(recorded) => ui.colored_label(
+                                Color32::RED,
+                                format!(
+                                    "{}: recorded {}, current {}",
+                                    entry.key, recorded, entry.expected
+                                ),
+                            ),
+                            None => ui.colored_label(
+                                Color32::GRAY,
+                                format!("{}: not recorded, current {}", entry.key, entry.expected),
+                            ),
+                        };
+                    }
+                }
+            });
+        ui_state.workspaces.track(UiWindow::Metadata, &response);
     }
 }
 
@@ -364,16 +841,412 @@ pub fn ui_metrics_window(
 ) -> Option<Rect> {
     let mut rect = None;
     if ui_state.show_ui_metrics {
-        rect = Some(
-            egui::Window::new(format!("Metrics [{:?}]", KEYCODE_TOGGLE_METRICS_UI))
-                .show(egui_ctx, |ui| {
-                    ui.heading("Average Height");
-                    plot_height(ui, state);
-                })
-                .unwrap()
-                .response
-                .rect,
-        );
+        let response = ui_state
+            .workspaces
+            .place(
+                UiWindow::Metrics,
+                egui::Window::new(format!("Metrics [{:?}]", KEYCODE_TOGGLE_METRICS_UI)),
+            )
+            .show(egui_ctx, |ui| {
+                ui.heading("Average Height");
+                plot_height(ui, state);
+                if let Some(drainage) = &ui_state.drainage {
+                    ui.separator();
+                    drainage_metrics(ui, drainage);
+                }
+                if let Some(lakes) = &ui_state.lakes {
+                    ui.separator();
+                    lake_metrics(ui, lakes);
+                }
+                if let Some(eroded) = state.simulation_state().eroded() {
+                    if !eroded.partitions.is_empty() {
+                        ui.separator();
+                        partition_metrics(ui, eroded);
+                    }
+                    if let Some(seams) = &eroded.seams {
+                        ui.separator();
+                        seam_metrics(ui, seams);
+                    }
+                }
+                ui.separator();
+                terrain_metrics(ui, ui_state);
+            });
+        ui_state.workspaces.track(UiWindow::Metrics, &response);
+        rect = Some(response.unwrap().response.rect);
     }
     rect
 }
+
+// Largest basins and lakes listed in the metrics window
+const LISTED_BASINS: usize = 8;
+
+fn drainage_metrics(ui: &mut egui::Ui, drainage: &Drainage) {
+    let basins = &drainage.basins;
+    let cells = (basins.width * basins.height) as f32;
+    ui.heading("Drainage");
+    ui.label(format!("{} basins", basins.regions.len()));
+    ui.label(format!(
+        "Largest accumulation: {} cells ({:.1}%)",
+        drainage.max_accumulation(),
+        drainage.max_accumulation() as f32 / cells * 100.0
+    ));
+    egui::Grid::new("drainage_basins")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Basin");
+            ui.label("Area");
+            ui.label("Cells");
+            ui.label("Outlet");
+            ui.end_row();
+            for basin in basins.by_area().into_iter().take(LISTED_BASINS) {
+                let [r, g, b] = label_color(basin.label);
+                ui.colored_label(Color32::from_rgb(r, g, b), format!("#{}", basin.label));
+                ui.label(format!("{:.1}%", basin.area as f32 / cells * 100.0));
+                ui.label(basin.area.to_string());
+                ui.label(format!("{}, {}", basin.lowest.0, basin.lowest.1));
+                ui.end_row();
+            }
+        });
+}
+
+// Longest side of the partition heatmap in the metrics window, in points
+const PARTITION_HEATMAP_SIZE: f32 = 160.0;
+
+/// Iterations and wall time of the partitions of the last simulation, the partitions are painted
+/// over the map by their time to show where the load was uneven.
+fn partition_metrics(ui: &mut egui::Ui, eroded: &ErodedState) {
+    let partitions = &eroded.partitions;
+    let heightmap = &eroded.heightmap_eroded.heightmap;
+    let slowest = partitions.iter().map(|p| p.seconds).fold(0.0, f32::max);
+    let mean = partitions.iter().map(|p| p.seconds).sum::<f32>() / partitions.len() as f32;
+    ui.heading("Partitions");
+    ui.label(format!(
+        "{} partitions, slowest {:.3} s, mean {:.3} s",
+        partitions.len(),
+        slowest,
+        mean
+    ));
+    if mean > 0.0 {
+        ui.label(format!("Slowest over mean: {:.2}", slowest / mean));
+    }
+
+    let scale = PARTITION_HEATMAP_SIZE / heightmap.width.max(heightmap.height).max(1) as f32;
+    let (rect, response) = ui.allocate_exact_size(
+        Vec2::new(heightmap.width as f32, heightmap.height as f32) * scale,
+        egui::Sense::hover(),
+    );
+    let bounds = |partition: &PartitionReport| {
+        Rect::from_min_size(
+            rect.min + Vec2::new(partition.anchor.x as f32, partition.anchor.y as f32) * scale,
+            Vec2::new(partition.size.x as f32, partition.size.y as f32) * scale,
+        )
+    };
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, Color32::BLACK);
+    let colormap = active_colormap();
+    for partition in partitions.iter() {
+        let [r, g, b] = colormap.color(partition.seconds / slowest.max(f32::EPSILON));
+        // Translucent since the partitions of some methods overlap
+        painter.rect(
+            bounds(partition),
+            0.0,
+            Color32::from_rgba_unmultiplied(r, g, b, 192),
+            Stroke::new(1.0, Color32::GRAY),
+        );
+    }
+    let hovered = response.hover_pos().and_then(|pos| {
+        partitions
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, partition)| bounds(partition).contains(pos))
+    });
+    if let Some((i, partition)) = hovered {
+        response.on_hover_text(format!(
+            "#{}: {} iterations in {:.3} s",
+            i, partition.iterations, partition.seconds
+        ));
+    }
+
+    egui::ScrollArea::vertical()
+        .id_source("partition_metrics")
+        .max_height(200.0)
+        .show(ui, |ui| {
+            egui::Grid::new("partition_metrics")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Partition");
+                    ui.label("Anchor");
+                    ui.label("Size");
+                    ui.label("Iterations");
+                    ui.label("Time");
+                    ui.label("Of Slowest");
+                    ui.end_row();
+                    for (i, partition) in partitions.iter().enumerate() {
+                        ui.label(format!("#{}", i));
+                        ui.label(format!("{}, {}", partition.anchor.x, partition.anchor.y));
+                        ui.label(format!("{}x{}", partition.size.x, partition.size.y));
+                        ui.label(partition.iterations.to_string());
+                        ui.label(format!("{:.3} s", partition.seconds));
+                        ui.label(format!(
+                            "{:.0}%",
+                            partition.seconds / slowest.max(f32::EPSILON) * 100.0
+                        ));
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+fn seam_metrics(ui: &mut egui::Ui, seams: &SeamMetrics) {
+    ui.heading("Seams");
+    egui::Grid::new("seam_metrics").show(ui, |ui| {
+        ui.label("Seam energy");
+        ui.label(format!("{:.3e}", seams.seam_energy));
+        ui.end_row();
+        ui.label("Elsewhere");
+        ui.label(format!("{:.3e}", seams.background_energy));
+        ui.end_row();
+        ui.label("Ratio");
+        ui.label(format!("{:.3}", seams.ratio()));
+        ui.end_row();
+    });
+    ui.label(format!(
+        "Gradient discontinuity over {} cells along the seams, a ratio of 1 hides them.",
+        seams.seam_cells
+    ));
+}
+
+fn terrain_metrics(ui: &mut egui::Ui, ui_state: &mut UiState) {
+    ui.heading("Terrain Shape");
+    if ui.button("Measure All States").clicked() {
+        ui_state.ui_events.push(UiEvent::MeasureTerrain);
+    }
+    if ui_state.terrain_metrics.is_empty() {
+        return;
+    }
+    egui::Grid::new("terrain_metrics")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("State");
+            ui.label("Slope");
+            ui.label("Profile");
+            ui.label("Plan");
+            ui.label("Roughness");
+            ui.end_row();
+            for (state, metrics) in ui_state.terrain_metrics.iter() {
+                ui.label(state);
+                ui.label(format!("{:.2}°", metrics.mean_slope));
+                ui.label(format!("{:.3}", metrics.mean_abs_profile_curvature));
+                ui.label(format!("{:.3}", metrics.mean_abs_plan_curvature));
+                ui.label(format!("{:.5}", metrics.mean_roughness));
+                ui.end_row();
+            }
+        });
+    ui.label("Means over the map, curvatures by magnitude.");
+}
+
+fn lake_metrics(ui: &mut egui::Ui, lakes: &Lakes) {
+    let cells = (lakes.filled.width * lakes.filled.height) as f32;
+    let area: usize = lakes.lakes.iter().map(|lake| lake.area).sum();
+    let volume: f32 = lakes.lakes.iter().map(|lake| lake.volume).sum();
+    ui.heading("Lakes");
+    ui.label(format!(
+        "{} lakes covering {:.1}%, volume {:.3}",
+        lakes.lakes.len(),
+        area as f32 / cells * 100.0,
+        volume
+    ));
+    egui::Grid::new("lakes").striped(true).show(ui, |ui| {
+        ui.label("Lake");
+        ui.label("Area");
+        ui.label("Spill");
+        ui.label("Depth");
+        ui.label("Volume");
+        ui.end_row();
+        for lake in lakes.by_area().into_iter().take(LISTED_BASINS) {
+            ui.label(format!("#{}", lake.label));
+            ui.label(format!("{:.1}%", lake.area as f32 / cells * 100.0));
+            ui.label(format!("{:.3}", lake.spill));
+            ui.label(format!("{:.3}", lake.max_depth));
+            ui.label(format!("{:.3}", lake.volume));
+            ui.end_row();
+        }
+    });
+}
+
+fn snapshot_thumbnail(
+    egui_ctx: &egui::Context,
+    name: String,
+    heightmap: &Heightmap,
+) -> TextureHandle {
+    let size = SNAPSHOT_THUMBNAIL_SIZE;
+    let mut pixels = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let value = heightmap.data[x * heightmap.width / size][y * heightmap.height / size];
+            pixels.push(Color32::from_gray((value.clamp(0.0, 1.0) * 255.0) as u8));
+        }
+    }
+    egui_ctx.load_texture(
+        name,
+        ColorImage {
+            size: [size, size],
+            pixels,
+        },
+        TextureOptions::NEAREST,
+    )
+}
+
+pub fn ui_timeline_window(egui_ctx: &egui::Context, ui_state: &mut UiState, state: &mut AppState) {
+    if ui_state.show_ui_timeline {
+        let response = ui_state
+            .workspaces
+            .place(
+                UiWindow::Timeline,
+                egui::Window::new(format!("Timeline [{:?}]", KEYCODE_TOGGLE_TIMELINE_UI)),
+            )
+            .show(egui_ctx, |ui| {
+                let num_iterations = state.parameters.model().num_iterations();
+                ui.add(
+                    egui::Slider::new(&mut state.parameters.snapshot_interval, 0..=num_iterations)
+                        .text("Snapshot interval"),
+                );
+                ui.label("Iterations between timeline snapshots, 0 records none.");
+                ui.separator();
+
+                let eroded = match state.simulation_state().eroded() {
+                    Some(eroded) if !eroded.timeline.is_empty() => eroded,
+                    _ => {
+                        ui.label("The selected state has no timeline.");
+                        return;
+                    }
+                };
+                let frames = eroded.timeline.len() + 1;
+                let total = eroded.erosion_model.num_iterations();
+                ui_state.timeline_frame = ui_state.timeline_frame.min(frames);
+                let iteration = match ui_state.timeline_frame {
+                    0 => 0,
+                    frame if frame == frames => total,
+                    frame => eroded.timeline[frame - 1].0,
+                };
+                if ui
+                    .add(egui::Slider::new(&mut ui_state.timeline_frame, 0..=frames).text("Frame"))
+                    .changed()
+                {
+                    ui_state
+                        .ui_events
+                        .push(UiEvent::ShowTimelineFrame(ui_state.timeline_frame));
+                }
+                ui.label(format!("Iteration {} of {}", iteration, total));
+            });
+        ui_state.workspaces.track(UiWindow::Timeline, &response);
+    }
+}
+
+pub fn ui_snapshots_window(egui_ctx: &egui::Context, ui_state: &mut UiState) {
+    if ui_state.show_ui_snapshots {
+        let response = ui_state
+            .workspaces
+            .place(
+                UiWindow::Snapshots,
+                egui::Window::new(format!("Snapshots [{:?}]", KEYCODE_TOGGLE_SNAPSHOTS_UI)),
+            )
+            .show(egui_ctx, |ui| {
+                let browser = &mut ui_state.snapshot_browser;
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut browser.path);
+                    if ui.button("Open").clicked() {
+                        ui_state.ui_events.push(UiEvent::OpenSnapshotArchive);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.menu_button("Recent", |ui| {
+                        for archive in browser.recent().to_vec() {
+                            if ui.button(&archive).clicked() {
+                                browser.path = archive;
+                                ui_state.ui_events.push(UiEvent::OpenSnapshotArchive);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    if ui.button("Refresh").clicked() {
+                        browser.refresh();
+                    }
+                });
+                if let Some(error) = &browser.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+                if browser.archive.is_none() {
+                    return;
+                }
+                ui.label(format!(
+                    "{} snapshots, {} unique heightmaps",
+                    browser.entries.len(),
+                    browser
+                        .archive
+                        .as_ref()
+                        .map(|a| a.borrow().index.heightmaps.len())
+                        .unwrap_or(0)
+                ));
+                ui.separator();
+
+                let entries = Rc::clone(&browser.entries);
+                egui::ScrollArea::vertical().max_height(400.0).show_rows(
+                    ui,
+                    SNAPSHOT_THUMBNAIL_SIZE as f32,
+                    entries.len(),
+                    |ui, rows| {
+                        for i in rows {
+                            let (tuning, measurements, hash) = &entries[i];
+                            if !browser.thumbnails.contains_key(hash) {
+                                if let Some(heightmap) = browser.read_heightmap(i) {
+                                    let texture = snapshot_thumbnail(
+                                        egui_ctx,
+                                        format!("snapshot-{}", hash),
+                                        &heightmap,
+                                    );
+                                    browser.thumbnails.insert(*hash, texture);
+                                }
+                            }
+                            ui.horizontal(|ui| {
+                                if let Some(texture) = browser.thumbnails.get(hash) {
+                                    ui.image(
+                                        texture,
+                                        [
+                                            SNAPSHOT_THUMBNAIL_SIZE as f32,
+                                            SNAPSHOT_THUMBNAIL_SIZE as f32,
+                                        ],
+                                    );
+                                }
+                                ui.vertical(|ui| {
+                                    ui.label(format!(
+                                        "#{} {} / {} / {}",
+                                        i,
+                                        tuning
+                                            .method
+                                            .map(|m| m.to_string())
+                                            .unwrap_or("Base".to_string()),
+                                        tuning.model.backend(),
+                                        tuning.map_type
+                                    ));
+                                    ui.label(format!(
+                                        "Flatness: {:.4}, Isoline: {} ± {}",
+                                        tuning.flatness, tuning.isoline_value, tuning.isoline_error
+                                    ));
+                                    ui.label(format!("{:?}", measurements));
+                                    if ui.button("Load as layer").clicked() {
+                                        ui_state.ui_events.push(UiEvent::LoadSnapshotHeightmap(i));
+                                    }
+                                });
+                            });
+                        }
+                    },
+                );
+            });
+        ui_state.workspaces.track(UiWindow::Snapshots, &response);
+    } else {
+        // Listed again once the window opens
+        ui_state.snapshot_browser.refresh();
+    }
+}
@@ -0,0 +1,141 @@
+use std::rc::Rc;
+
+use egui::Rect;
+use macroquad::prelude::*;
+
+use crate::partitioning::{GridLayers, MarginMode, Method};
+use crate::visualize::view::View;
+
+/*
+How the selected partitioning method would cut up the map, drawn over the heightmap without running
+a simulation. The boundaries of the partitions are drawn like the grid, the cells more than one
+partition works on, through overlapping grids, the halo or the blurred seams, are tinted yellow
+and the margins cut off the sides are tinted in the colour of their outline. The preview is only
+built again when the method, its settings, the margins, the halo or the size of the map change,
+so it follows the sliders from frame to frame.
+ */
+
+const BOUNDARY: [u8; 4] = [230, 41, 55, 255];
+const OVERLAP: [u8; 4] = [253, 249, 0, 110];
+const MARGIN: [u8; 4] = [255, 0, 255, 90];
+
+/// Everything the preview depends on, see `PartitionPreview::update`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PreviewKey {
+    method: Method,
+    margin: MarginMode,
+    halo: usize,
+    width: usize,
+    height: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PartitionPreview {
+    key: Option<PreviewKey>,
+    texture: Option<Rc<Texture2D>>,
+}
+
+impl PartitionPreview {
+    /// Builds the preview of `method` over a map of `width` x `height` unless it is up to date.
+    pub fn update(
+        &mut self,
+        method: Method,
+        margin: MarginMode,
+        halo: usize,
+        width: usize,
+        height: usize,
+    ) {
+        let key = PreviewKey {
+            method,
+            margin,
+            halo,
+            width,
+            height,
+        };
+        if self.key == Some(key) {
+            return;
+        }
+        let layers = method.get_grid_layers(width, height, margin, halo);
+        self.texture = Some(Rc::new(Texture2D::from_image(&image(&layers))));
+        self.key = Some(key);
+    }
+
+    pub fn draw(&self, rect: &Rect, view: &View) {
+        if let Some(texture) = &self.texture {
+            crate::visualize::draw_frame(rect, texture, view);
+        }
+    }
+}
+
+/// The layers coloured on a transparent image, boundaries on top of the overlap on the margins.
+pub fn image(layers: &GridLayers) -> Image {
+    let (width, height) = (layers.boundaries.width, layers.boundaries.height);
+    let mut bytes = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let color = if layers.boundaries.data[x][y] > 0.0 {
+                BOUNDARY
+            } else if layers.overlap.data[x][y] > 0.0 {
+                OVERLAP
+            } else if layers.margin.data[x][y] > 0.0 {
+                MARGIN
+            } else {
+                [0; 4]
+            };
+            bytes.extend_from_slice(&color);
+        }
+    }
+    Image {
+        bytes,
+        width: width as u16,
+        height: height as u16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{check_heightmap, Check, GRID_SIZE, SIZE};
+    use crate::heightmap::{BlendCurve, Heightmap};
+
+    #[test]
+    fn partition_preview() -> Check {
+        use crate::math::Margins;
+        let count = |heightmap: &Heightmap| {
+            heightmap
+                .data
+                .iter()
+                .flatten()
+                .filter(|&&v| v > 0.0)
+                .count()
+        };
+        let margins = Margins::new(0, 1, 3, 2);
+        let method = Method::Subdivision(GRID_SIZE);
+        let layers = method.get_grid_layers(SIZE, SIZE, MarginMode::Custom(margins), 0);
+        check_heightmap(&layers.margin, SIZE, SIZE)?;
+        let kept = (SIZE - 3) * (SIZE - 3);
+        if count(&layers.margin) != SIZE * SIZE - kept {
+            return Err(format!(
+                "{} cells are in the margins",
+                count(&layers.margin)
+            ));
+        }
+        if (0..SIZE).any(|y| layers.boundaries.data[0][y] > 0.0) || count(&layers.boundaries) == 0 {
+            return Err("boundaries are not drawn inside the margins".to_string());
+        }
+        if count(&layers.overlap) != 0 {
+            return Err("tiles without a halo overlap".to_string());
+        }
+        let halo = method.get_grid_layers(SIZE, SIZE, MarginMode::None, 2);
+        let overlapping = Method::GridOverlapBlend((GRID_SIZE, BlendCurve::default()));
+        let blended = overlapping.get_grid_layers(SIZE, SIZE, MarginMode::None, 0);
+        if count(&halo.overlap) == 0 || count(&blended.overlap) == 0 {
+            return Err("the halo or overlapping grids do not overlap".to_string());
+        }
+        let image = image(&layers);
+        if image.bytes.len() != SIZE * SIZE * 4 {
+            return Err(format!("preview of {} bytes", image.bytes.len()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use std::rc::Rc;
+
+#[cfg(feature = "gui")]
+use emath::Rect;
+#[cfg(feature = "gui")]
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightmap::Heightmap;
+use crate::visualize::app_state::AppState;
+#[cfg(feature = "gui")]
+use crate::visualize::overlay::draw_polylines;
+#[cfg(feature = "gui")]
+use crate::visualize::view::{frame, View};
+
+/*
+Region of interest, a rectangle dragged out on the canvas to get statistics for just that part of
+the heightmap. Useful for putting numbers on artifacts along partition seams or at the map edges,
+where averages over the whole map hide what is going on. The rectangle is stored in [0, 1]
+heightmap space so it stays in place when changing state or map size, and the statistics are
+recomputed whenever the selected state or the rectangle changes.
+ */
+
+/// Min and max corner of a rectangle in [0, 1] heightmap space.
+pub type Corners = ((f32, f32), (f32, f32));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegionStats {
+    pub cells: usize,
+    pub mean: f32,
+    pub min: f32,
+    pub max: f32,
+    pub mean_slope: f32,
+    pub max_slope: f32,
+    /// Only known for eroded states, compared against the base they were eroded from.
+    pub mean_diff: Option<f32>,
+    pub eroded: Option<f32>,
+    pub deposited: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegionOfInterest {
+    pub rect: Option<Corners>,
+    #[serde(skip)]
+    pub selecting: bool,
+    #[serde(skip)]
+    drag: Option<(f32, f32)>,
+    #[serde(skip)]
+    cached: Option<(usize, Corners, RegionStats)>,
+}
+
+impl RegionOfInterest {
+    pub fn clear(&mut self) {
+        self.rect = None;
+        self.drag = None;
+        self.cached = None;
+    }
+
+    /// First and one past the last cell covered by the region in a `width` x `height` heightmap.
+    pub fn cells(&self, width: usize, height: usize) -> Option<((usize, usize), (usize, usize))> {
+        let ((min_u, min_v), (max_u, max_v)) = self.rect?;
+        let to_cell = |t: f32, size: usize| ((t * size as f32) as usize).min(size);
+        let min = (to_cell(min_u, width), to_cell(min_v, height));
+        let max = (
+            to_cell(max_u, width).max(min.0 + 1).min(width),
+            to_cell(max_v, height).max(min.1 + 1).min(height),
+        );
+        if min.0 >= max.0 || min.1 >= max.1 {
+            return None;
+        }
+        Some((min, max))
+    }
+
+    /// Statistics for the selected state, reused until the state or the rectangle changes.
+    pub fn stats(&mut self, app_state: &AppState) -> Option<RegionStats> {
+        let rect = self.rect?;
+        let simulation_state = app_state.simulation_state();
+        let heightmap = simulation_state.get_heightmap();
+        let key = Rc::as_ptr(&heightmap) as usize;
+        if let Some((cached_key, cached_rect, stats)) = self.cached {
+            if cached_key == key && cached_rect == rect {
+                return Some(stats);
+            }
+        }
+        let base = simulation_state
+            .eroded()
+            .map(|_| Rc::clone(&simulation_state.base().heightmap_base.heightmap));
+        let stats = region_stats(&heightmap, base.as_deref(), self)?;
+        self.cached = Some((key, rect, stats));
+        Some(stats)
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn draw(&self, rect: &Rect, view: &View, color: Color) {
+        if let Some(((min_u, min_v), (max_u, max_v))) = self.rect {
+            let outline = vec![
+                (min_u, min_v),
+                (max_u, min_v),
+                (max_u, max_v),
+                (min_u, max_v),
+                (min_u, min_v),
+            ];
+            draw_polylines(rect, view, &[outline], 2.0, color);
+        }
+    }
+}
+
+/// Drags out the region with the left mouse button while selecting, returns if the click was used.
+#[cfg(feature = "gui")]
+pub fn poll_region_input(
+    region: &mut RegionOfInterest,
+    view: &View,
+    pointer_captured: bool,
+    canvas_rect: &Rect,
+) -> bool {
+    if !region.selecting {
+        return false;
+    }
+    if !is_mouse_button_down(MouseButton::Left) {
+        if region.drag.take().is_some() {
+            region.selecting = false;
+        }
+        return true;
+    }
+    let position = mouse_position();
+    match region.drag {
+        Some(start) => {
+            // Keep dragging past the frame edge by clamping instead of dropping the point
+            let (left, top, side) = frame(canvas_rect);
+            let clamped = (
+                position.0.clamp(left, left + side - 1.0),
+                position.1.clamp(top, top + side - 1.0),
+            );
+            if let Some(end) = view.to_heightmap(canvas_rect, clamped) {
+                region.rect = Some((
+                    (start.0.min(end.0), start.1.min(end.1)),
+                    (start.0.max(end.0), start.1.max(end.1)),
+                ));
+            }
+        }
+        None if !pointer_captured => {
+            region.drag = view.to_heightmap(canvas_rect, position);
+        }
+        None => {}
+    }
+    true
+}
+
+pub fn region_stats(
+    heightmap: &Heightmap,
+    base: Option<&Heightmap>,
+    region: &RegionOfInterest,
+) -> Option<RegionStats> {
+    let ((min_x, min_y), (max_x, max_y)) = region.cells(heightmap.width, heightmap.height)?;
+    let base = base.filter(|b| b.width == heightmap.width && b.height == heightmap.height);
+
+    let mut total = 0.0;
+    let mut min = f32::MAX;
+    let mut max = f32::MIN;
+    let mut total_slope = 0.0;
+    let mut max_slope: f32 = 0.0;
+    let mut total_diff = 0.0;
+    let mut eroded = 0.0;
+    let mut deposited = 0.0;
+    for x in min_x..max_x {
+        for y in min_y..max_y {
+            let height = heightmap.data[x][y];
+            total += height;
+            min = min.min(height);
+            max = max.max(height);
+            let slope = heightmap
+                .gradient(x, y)
+                .map(|gradient| gradient.magnitude())
+                .unwrap_or(0.0);
+            total_slope += slope;
+            max_slope = max_slope.max(slope);
+            if let Some(base) = base {
+                let diff = height - base.data[x][y];
+                total_diff += diff;
+                if diff < 0.0 {
+                    eroded -= diff;
+                } else {
+                    deposited += diff;
+                }
+            }
+        }
+    }
+
+    let cells = (max_x - min_x) * (max_y - min_y);
+    let n = cells as f32;
+    Some(RegionStats {
+        cells,
+        mean: total / n,
+        min,
+        max,
+        mean_slope: total_slope / n,
+        max_slope,
+        mean_diff: base.map(|_| total_diff / n),
+        eroded: base.map(|_| eroded),
+        deposited: base.map(|_| deposited),
+    })
+}
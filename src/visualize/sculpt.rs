@@ -0,0 +1,225 @@
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+#[cfg(feature = "gui")]
+use emath::Rect;
+#[cfg(feature = "gui")]
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use crate::visualize::app_state::AppState;
+#[cfg(feature = "gui")]
+use crate::visualize::view::View;
+
+/*
+Manual terrain editing, for tweaking a base by hand before eroding it. While the left mouse button
+is held the selected tool is applied to the heightmap of the selected state under the cursor,
+fading out towards the edge of the brush. Every stroke, from pressing the button until releasing
+it, can be undone on its own. Only the last `UNDO_STROKES` strokes are kept.
+ */
+
+#[cfg(feature = "gui")]
+const UNDO_STROKES: usize = 32;
+// Height raised or lowered per frame at the center of a full strength brush, relative to depth
+const RAISE_STEP: HeightmapPrecision = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SculptTool {
+    Raise,
+    Lower,
+    Smooth,
+    Flatten,
+}
+
+impl Display for SculptTool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SculptTool::Raise => f.write_str("Raise"),
+            SculptTool::Lower => f.write_str("Lower"),
+            SculptTool::Smooth => f.write_str("Smooth"),
+            SculptTool::Flatten => f.write_str("Flatten"),
+        }
+    }
+}
+
+impl SculptTool {
+    pub fn list() -> [SculptTool; 4] {
+        [
+            SculptTool::Raise,
+            SculptTool::Lower,
+            SculptTool::Smooth,
+            SculptTool::Flatten,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SculptBrush {
+    pub painting: bool,
+    pub tool: SculptTool,
+    pub radius: f32,   // [1, 64], 16 (cells)
+    pub strength: f32, // [0, 1], 0.2 (per frame)
+    /// Id of the state and its heightmap before each stroke, the most recent last.
+    #[serde(skip)]
+    history: Vec<(usize, Rc<Heightmap>)>,
+    /// Height flattened towards, taken under the cursor when the stroke started.
+    #[serde(skip)]
+    stroke: Option<HeightmapPrecision>,
+}
+
+impl Default for SculptBrush {
+    fn default() -> Self {
+        SculptBrush::new(SculptTool::Raise, 16.0, 0.2)
+    }
+}
+
+impl SculptBrush {
+    pub fn new(tool: SculptTool, radius: f32, strength: f32) -> Self {
+        SculptBrush {
+            painting: false,
+            tool,
+            radius,
+            strength,
+            history: Vec::new(),
+            stroke: None,
+        }
+    }
+
+    /// Whether the selected state has a stroke to undo.
+    pub fn can_undo(&self, app_state: &AppState) -> bool {
+        let id = app_state.simulation_state().id();
+        self.history.iter().any(|(state, _)| *state == id)
+    }
+
+    /// Restores the selected state to before its last stroke, returns if there was one.
+    pub fn undo(&mut self, app_state: &mut AppState) -> bool {
+        let id = app_state.simulation_state().id();
+        let index = match self.history.iter().rposition(|(state, _)| *state == id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let (_, heightmap) = self.history.remove(index);
+        app_state
+            .simulation_state_mut()
+            .set_heightmap((*heightmap).clone());
+        true
+    }
+
+    /// Applies the tool around `center` in cells, fading out towards the brush edge.
+    pub fn apply(&self, heightmap: &mut Heightmap, center: (f32, f32)) {
+        let (center_x, center_y) = center;
+        let min_x = (center_x - self.radius).max(0.0) as usize;
+        let min_y = (center_y - self.radius).max(0.0) as usize;
+        let max_x = ((center_x + self.radius) as usize).min(heightmap.width - 1);
+        let max_y = ((center_y + self.radius) as usize).min(heightmap.height - 1);
+        // Smoothing reads the neighbours from before this frame so the result does not depend on
+        // the order the cells are visited in
+        let before = match self.tool {
+            SculptTool::Smooth => Some(heightmap.clone()),
+            _ => None,
+        };
+        let step = RAISE_STEP * heightmap.depth;
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                let distance =
+                    ((x as f32 - center_x).powi(2) + (y as f32 - center_y).powi(2)).sqrt();
+                if distance >= self.radius {
+                    continue;
+                }
+                let weight = (1.0 - distance / self.radius) * self.strength;
+                let height = heightmap.data[x][y];
+                let target = match self.tool {
+                    SculptTool::Raise => height + step,
+                    SculptTool::Lower => height - step,
+                    SculptTool::Smooth => neighbourhood_mean(before.as_ref().unwrap(), x, y),
+                    SculptTool::Flatten => self.stroke.unwrap_or(height),
+                };
+                heightmap.data[x][y] =
+                    (height + (target - height) * weight).clamp(0.0, heightmap.depth);
+            }
+        }
+    }
+}
+
+fn neighbourhood_mean(heightmap: &Heightmap, x: usize, y: usize) -> HeightmapPrecision {
+    let mut total = 0.0;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            total += heightmap.get_clamped(x as i32 + dx, y as i32 + dy);
+        }
+    }
+    total / 9.0
+}
+
+/// Sculpts the selected state while the left mouse button is held, returns if anything changed.
+#[cfg(feature = "gui")]
+pub fn poll_sculpting(
+    brush: &mut SculptBrush,
+    app_state: &mut AppState,
+    view: &View,
+    pointer_captured: bool,
+    canvas_rect: &Rect,
+) -> bool {
+    if !brush.painting || !is_mouse_button_down(MouseButton::Left) {
+        brush.stroke = None;
+        return false;
+    }
+    let heightmap = app_state.simulation_state().get_heightmap();
+    let position = match view.to_heightmap(canvas_rect, mouse_position()) {
+        Some(position) if brush.stroke.is_some() || !pointer_captured => position,
+        _ => return false,
+    };
+    let center = (
+        position.0 * heightmap.width as f32,
+        position.1 * heightmap.height as f32,
+    );
+    if brush.stroke.is_none() {
+        app_state.record_history();
+        let id = app_state.simulation_state().id();
+        brush.history.push((id, Rc::clone(&heightmap)));
+        if brush.history.len() > UNDO_STROKES {
+            brush.history.remove(0);
+        }
+        let x = (center.0 as usize).min(heightmap.width - 1);
+        let y = (center.1 as usize).min(heightmap.height - 1);
+        brush.stroke = Some(heightmap.data[x][y]);
+    }
+    let mut heightmap = (*heightmap).clone();
+    brush.apply(&mut heightmap, center);
+    app_state.simulation_state_mut().set_heightmap(heightmap);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{check_heightmap, tiny_heightmap, Check, SIZE};
+
+    #[test]
+    fn sculpting() -> Check {
+        let heightmap = tiny_heightmap();
+        let center = (SIZE as f32 / 2.0, SIZE as f32 / 2.0);
+        let (x, y) = (SIZE / 2, SIZE / 2);
+        let sculpt = |tool| {
+            let brush = SculptBrush::new(tool, 8.0, 1.0);
+            let mut sculpted = heightmap.clone();
+            brush.apply(&mut sculpted, center);
+            sculpted
+        };
+        let raised = sculpt(SculptTool::Raise);
+        let lowered = sculpt(SculptTool::Lower);
+        if raised.data[x][y] <= heightmap.data[x][y] || lowered.data[x][y] >= heightmap.data[x][y] {
+            return Err("raising or lowering did not change the height".to_string());
+        }
+        if raised.data[0][0] != heightmap.data[0][0] {
+            return Err("sculpted outside the brush".to_string());
+        }
+        let smoothed = sculpt(SculptTool::Smooth);
+        check_heightmap(&smoothed, SIZE, SIZE)?;
+        if smoothed.roughness() > heightmap.roughness() {
+            return Err("smoothing made the terrain rougher".to_string());
+        }
+        Ok(())
+    }
+}
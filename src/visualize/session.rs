@@ -0,0 +1,104 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::visualize::app_state::ErodedState;
+
+pub const SESSION_LOG: &str = "session.log";
+
+/// Compute time and runs of one partitioning method and backend combination.
+#[derive(Debug, Clone)]
+pub struct MethodStats {
+    pub method: String,
+    pub simulations: usize,
+    pub time: Duration,
+}
+
+/// What was done since the application was started, kept across loaded states and restarts.
+#[derive(Debug, Clone)]
+pub struct SessionStats {
+    pub started: Instant,
+    pub started_at: SystemTime,
+    pub simulations: usize,
+    pub droplets: usize,
+    pub methods: Vec<MethodStats>,
+    pub exports: usize,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        SessionStats {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            simulations: 0,
+            droplets: 0,
+            methods: Vec::new(),
+            exports: 0,
+        }
+    }
+}
+
+impl SessionStats {
+    pub fn record_simulation(&mut self, eroded: &ErodedState) {
+        let method = format!(
+            "{} ({})",
+            eroded.erosion_method.to_string(),
+            eroded.erosion_model.backend()
+        );
+        self.simulations += 1;
+        self.droplets += eroded.erosion_model.num_iterations();
+        match self.methods.iter_mut().find(|stats| stats.method == method) {
+            Some(stats) => {
+                stats.simulations += 1;
+                stats.time += eroded.simulation_time;
+            }
+            None => self.methods.push(MethodStats {
+                method,
+                simulations: 1,
+                time: eroded.simulation_time,
+            }),
+        }
+    }
+
+    pub fn record_export(&mut self) {
+        self.exports += 1;
+    }
+
+    pub fn compute_time(&self) -> Duration {
+        self.methods.iter().map(|stats| stats.time).sum()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Session of {:.0} s\nSimulations run: {}\nDroplets simulated: {}\nCompute time: {:.2} s\nExports written: {}\n",
+            self.started.elapsed().as_secs_f32(),
+            self.simulations,
+            self.droplets,
+            self.compute_time().as_secs_f32(),
+            self.exports
+        );
+        for stats in self.methods.iter() {
+            summary += &format!(
+                "  {}: {} runs, {:.2} s\n",
+                stats.method,
+                stats.simulations,
+                stats.time.as_secs_f32()
+            );
+        }
+        summary
+    }
+
+    /// Appends the summary to `SESSION_LOG`, headed by the Unix time the session started at.
+    pub fn append_to_log(&self) -> std::io::Result<()> {
+        let started = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(SESSION_LOG)?;
+        writeln!(file, "[{}]\n{}", started, self.summary())
+    }
+}
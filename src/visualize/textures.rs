@@ -0,0 +1,87 @@
+use std::borrow::Cow;
+use std::rc::{Rc, Weak};
+
+use macroquad::texture::{Image, Texture2D};
+
+use crate::visualize::wrappers::HeightmapTexture;
+use crate::visualize::{heightmap_to_image_rgb, image_to_texture, LayerImage};
+
+/*
+Textures are only built by the frame that draws them, the application state holds the heightmaps
+and their coloured images and nothing on the gpu, so states can be built, eroded and saved without
+a window. The cache keeps the texture of every layer that was drawn for as long as the layer is
+alive, so switching between states does not upload them again. The texture of a dropped layer is
+kept for one more frame and rewritten with the next layer of the same size, so layers replaced
+every frame, such as an animated hillshade, do not create and delete a texture each time.
+ */
+
+#[derive(Debug, Default)]
+pub struct TextureCache {
+    textures: Vec<(Weak<HeightmapTexture>, Texture2D)>,
+    /// Textures of the layers dropped since the last frame, see `upload`.
+    spare: Vec<Texture2D>,
+}
+
+impl TextureCache {
+    /// Texture of `layer`, uploaded from its image or its heights the first time it is drawn.
+    pub fn get(&mut self, layer: &Rc<HeightmapTexture>) -> Texture2D {
+        self.prune();
+        // Dropped layers are pruned first, so a layer at the same address is the same layer
+        if let Some((_, texture)) = self
+            .textures
+            .iter()
+            .find(|(cached, _)| std::ptr::eq(cached.as_ptr(), Rc::as_ptr(layer)))
+        {
+            return *texture;
+        }
+        let image = match &layer.image {
+            Some(image) => Cow::Borrowed(image.as_ref()),
+            None => Cow::Owned(heightmap_to_image_rgb(&layer.heightmap)),
+        };
+        let texture = self.upload(&image);
+        self.textures.push((Rc::downgrade(layer), texture));
+        texture
+    }
+
+    /// Rewrites a spare texture of the same size with `image`, or creates one if there is none.
+    fn upload(&mut self, image: &LayerImage) -> Texture2D {
+        let (width, height) = (image.width(), image.height());
+        let spare = self.spare.iter().position(|texture| {
+            texture.width() == width as f32 && texture.height() == height as f32
+        });
+        match spare {
+            Some(i) => {
+                let texture = self.spare.swap_remove(i);
+                texture.update(&Image {
+                    bytes: image.as_raw().clone(),
+                    width: width as u16,
+                    height: height as u16,
+                });
+                texture
+            }
+            None => image_to_texture(image),
+        }
+    }
+
+    /// Deletes the spare textures no layer took and spares those of the layers dropped since.
+    fn prune(&mut self) {
+        for texture in self.spare.drain(..) {
+            texture.delete();
+        }
+        let spare = &mut self.spare;
+        self.textures.retain(|(layer, texture)| {
+            let alive = layer.strong_count() > 0;
+            if !alive {
+                spare.push(*texture);
+            }
+            alive
+        });
+    }
+}
+
+/// A copy starts empty, two caches deleting the same textures would leave one drawing nothing.
+impl Clone for TextureCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
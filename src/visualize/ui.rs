@@ -1,18 +1,55 @@
+use std::cell::RefCell;
+#[cfg(feature = "gui")]
+use std::collections::HashMap;
 use std::mem;
+use std::rc::Rc;
 
-use egui::{Color32, Rect};
-use macroquad::prelude::*;
+#[cfg(feature = "gui")]
+use egui::Color32;
+use emath::Rect;
 use serde::{Deserialize, Serialize};
 
-use crate::heightmap::HeightmapPrecision;
+use crate::engine::archive::{list_archives, SnapshotArchive};
+use crate::engine::Snapshot;
+use crate::heightmap::analysis::TerrainMetrics;
+use crate::heightmap::contours::ContourFormat;
+use crate::heightmap::hydrology::{Drainage, Lakes};
+use crate::heightmap::io::{DataFormat, ExportCrop};
+use crate::heightmap::mesh::MeshSettings;
+use crate::heightmap::segments::{SegmentSettings, Segmentation};
+#[cfg(feature = "gui")]
+use crate::heightmap::HeightmapHash;
+use crate::heightmap::{
+    ExtendFill, Heightmap, HeightmapOperation, HeightmapPrecision, ResampleFilter,
+};
+use crate::math::Margins;
 use crate::visualize::events::UiEvent;
+use crate::visualize::flood::FloodAnimation;
+use crate::visualize::heat::HeatOverlay;
+use crate::visualize::hillshade::Hillshade;
+#[cfg(feature = "gui")]
+use crate::visualize::hud::Hud;
+use crate::visualize::overlay::VectorOverlay;
+use crate::visualize::palette::{Colormap, Palette};
+#[cfg(feature = "gui")]
+use crate::visualize::preview::PartitionPreview;
+use crate::visualize::region::RegionOfInterest;
+use crate::visualize::sculpt::SculptBrush;
+#[cfg(feature = "gui")]
+use crate::visualize::textures::TextureCache;
+use crate::visualize::view::{Navigation, View};
+use crate::visualize::workspace::Workspaces;
+#[cfg(feature = "gui")]
 use crate::State;
 
 #[cfg(feature = "export")]
 use crate::io::StateFile;
 
+#[cfg(feature = "gui")]
 use super::panels::{
-    ui_keybinds_window, ui_metadata_window, ui_metrics_window, ui_side_panel, ui_top_panel,
+    ui_hud, ui_keybinds_window, ui_metadata_window, ui_metrics_window, ui_quit_prompt,
+    ui_region_window, ui_session_window, ui_side_panel, ui_snapshots_window, ui_timeline_window,
+    ui_top_panel,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -26,6 +63,185 @@ pub struct IsolineProperties {
     pub blur_augmentation: (bool, f32, usize, usize),
     pub advanced_texture: bool,
     pub flooded_errors: Option<usize>,
+    /// Share of the map the last flood covered.
+    #[serde(default)]
+    pub flooded_share: Option<f32>,
+    #[serde(default)]
+    pub contour_lines: ContourLines,
+}
+
+/// Evenly spaced contour lines drawn by the isoline view instead of the single isoline.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ContourLines {
+    pub enabled: bool,
+    pub interval: f32,      // [0.01, 0.5], 0.05 (of the depth)
+    pub thickness: f32,     // [0.5, 6], 1.5 (pixels)
+    pub index_every: usize, // [0, 20], 5 (every n-th line is drawn thicker, 0 for none)
+    #[serde(default)]
+    pub format: ContourFormat,
+}
+
+/// Combines the selected state with another into a new base state.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LayerMath {
+    pub operation: HeightmapOperation,
+    /// Id of the state the selected state is combined with.
+    pub operand: usize,
+    pub amount: f32, // [0, 1], 0.5 (share of the operand when blending)
+}
+
+impl Default for LayerMath {
+    fn default() -> Self {
+        LayerMath {
+            operation: HeightmapOperation::default(),
+            operand: 0,
+            amount: 0.5,
+        }
+    }
+}
+
+/// Size the selected state is resampled to as a new base state.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Resample {
+    pub width: usize,  // [2, 4096], 512 (cells)
+    pub height: usize, // [2, 4096], 512 (cells)
+    pub filter: ResampleFilter,
+}
+
+impl Default for Resample {
+    fn default() -> Self {
+        Resample {
+            width: 512,
+            height: 512,
+            filter: ResampleFilter::default(),
+        }
+    }
+}
+
+/// Crops the selected state to a rectangle dragged out on the canvas, or extends it on its sides.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CropTool {
+    #[serde(skip)]
+    pub selection: RegionOfInterest,
+    pub margins: Margins,
+    pub fill: ExtendFill,
+}
+
+impl Default for ContourLines {
+    fn default() -> Self {
+        ContourLines {
+            enabled: false,
+            interval: 0.05,
+            thickness: 1.5,
+            index_every: 5,
+            format: ContourFormat::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HardnessBrush {
+    pub painting: bool,
+    pub radius: f32,   // [1, 64], 12 (cells)
+    pub value: f32,    // [0, 1], 1
+    pub strength: f32, // [0, 1], 0.2 (per frame)
+}
+
+impl Default for HardnessBrush {
+    fn default() -> Self {
+        HardnessBrush {
+            painting: false,
+            radius: 12.0,
+            value: 1.0,
+            strength: 0.2,
+        }
+    }
+}
+
+/// Erodes the selected state with drops spawning only under the cursor while held down.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ErosionBrush {
+    pub painting: bool,
+    pub radius: f32,     // [1, 64], 12 (cells)
+    pub droplets: usize, // [1, 2000], 200 (iterations per frame)
+}
+
+impl Default for ErosionBrush {
+    fn default() -> Self {
+        ErosionBrush {
+            painting: false,
+            radius: 12.0,
+            droplets: 200,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SnapshotBrowser {
+    pub path: String,
+    pub archive: Option<Rc<RefCell<SnapshotArchive>>>,
+    pub entries: Rc<Vec<Snapshot>>,
+    #[cfg(feature = "gui")]
+    pub thumbnails: HashMap<HeightmapHash, egui::TextureHandle>,
+    pub error: Option<String>,
+    /// Archives in the working directory, listed when the window opens or is refreshed.
+    pub recent: Option<Vec<String>>,
+}
+
+impl std::fmt::Debug for SnapshotBrowser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("SnapshotBrowser");
+        debug
+            .field("path", &self.path)
+            .field("entries", &self.entries.len());
+        #[cfg(feature = "gui")]
+        debug.field("thumbnails", &self.thumbnails.len());
+        debug.field("error", &self.error).finish()
+    }
+}
+
+impl SnapshotBrowser {
+    /// The archives in the working directory, listed again after `refresh`.
+    pub fn recent(&mut self) -> &[String] {
+        self.recent
+            .get_or_insert_with(|| list_archives(".").unwrap_or_default())
+    }
+
+    pub fn refresh(&mut self) {
+        self.recent = None;
+    }
+
+    pub fn open(&mut self) {
+        let result = SnapshotArchive::open(&self.path).and_then(|mut archive| {
+            let entries = (0..archive.len())
+                .map(|i| archive.read_snapshot(i))
+                .collect::<Result<Vec<Snapshot>, _>>()?;
+            Ok((archive, entries))
+        });
+        #[cfg(feature = "gui")]
+        self.thumbnails.clear();
+        match result {
+            Ok((archive, entries)) => {
+                self.archive = Some(Rc::new(RefCell::new(archive)));
+                self.entries = Rc::new(entries);
+                self.error = None;
+            }
+            Err(err) => {
+                self.archive = None;
+                self.entries = Rc::new(Vec::new());
+                self.error = Some(format!("{:?}", err));
+            }
+        }
+    }
+
+    pub fn read_heightmap(&self, index: usize) -> Option<Heightmap> {
+        let (_, _, hash) = self.entries.get(index)?;
+        self.archive
+            .as_ref()?
+            .borrow_mut()
+            .read_heightmap(*hash)
+            .ok()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -35,8 +251,37 @@ pub struct UiState {
     pub show_ui_control_panel: bool,
     pub show_ui_metadata: bool,
     pub show_ui_metrics: bool,
+    #[serde(default)]
+    pub show_ui_snapshots: bool,
+    #[serde(default)]
+    pub show_ui_timeline: bool,
+    #[serde(skip)]
+    pub timeline_frame: usize,
+    #[serde(default)]
+    pub show_ui_session: bool,
+    #[serde(default)]
+    pub show_ui_hud: bool,
+    #[cfg(feature = "gui")]
+    #[serde(skip)]
+    pub hud: Hud,
+    #[serde(default)]
+    pub show_ui_region: bool,
+    #[serde(default)]
+    pub region: RegionOfInterest,
+    /// Append the session summary to the session log when quitting.
+    #[serde(default)]
+    pub session_log: bool,
     pub show_ui_presentation_mode: bool,
     pub show_grid: bool,
+    /// Draw the partitions of the selected method over the map, see `PartitionPreview`.
+    #[serde(default)]
+    pub show_partition_preview: bool,
+    #[cfg(feature = "gui")]
+    #[serde(skip)]
+    pub partition_preview: PartitionPreview,
+    #[cfg(feature = "gui")]
+    #[serde(skip)]
+    pub textures: TextureCache,
     pub simulation_clear: bool,
     pub simulation_regenerate: bool,
     pub application_quit: bool,
@@ -46,10 +291,80 @@ pub struct UiState {
     pub blur_sigma: f32,
     pub canny_edge: (f32, f32),
     pub isoline: IsolineProperties,
+    #[serde(default)]
+    pub hardness_brush: HardnessBrush,
+    #[serde(default)]
+    pub drop_zone_brush: HardnessBrush,
+    #[serde(default)]
+    pub erosion_brush: ErosionBrush,
+    #[serde(default)]
+    pub sculpt_brush: SculptBrush,
+    #[serde(default)]
+    pub hillshade: Hillshade,
+    #[serde(default)]
+    pub flood_animation: FloodAnimation,
+    #[serde(skip)]
+    pub workspaces: Workspaces,
+    #[serde(default)]
+    pub heat_overlay: HeatOverlay,
+    #[serde(default = "default_droplet_bin")]
+    pub droplet_bin: usize, // [1, 64], 8 (cells)
+    /// Path of the image imported with the "From File" heightmap type.
+    #[serde(default)]
+    pub heightmap_file: String,
+    #[serde(default)]
+    pub palette: Palette,
+    /// Colours of plain heightmap textures, `palette` colours the data layers.
+    #[serde(default)]
+    pub colormap: Colormap,
+    #[serde(default)]
+    pub segments: SegmentSettings,
+    #[serde(default)]
+    pub layer_math: LayerMath,
+    #[serde(default)]
+    pub resample: Resample,
+    #[serde(default)]
+    pub crop: CropTool,
+    /// The last segmentation made, kept for its statistics and export.
+    #[serde(skip)]
+    pub segmentation: Option<Rc<Segmentation>>,
+    /// The last drainage analysis, kept for its statistics in the metrics window.
+    #[serde(skip)]
+    pub drainage: Option<Rc<Drainage>>,
+    /// The last lakes found, kept for their statistics in the metrics window.
+    #[serde(skip)]
+    pub lakes: Option<Rc<Lakes>>,
+    /// Shape of every state by its name when last measured.
+    #[serde(skip)]
+    pub terrain_metrics: Vec<(String, TerrainMetrics)>,
+    #[serde(default)]
+    pub show_legend: bool,
+    #[serde(default)]
+    pub vector_overlay: VectorOverlay,
+    #[serde(default)]
+    pub view: View,
+    #[serde(skip)]
+    pub navigation: Navigation,
+    #[serde(skip)]
+    pub snapshot_browser: SnapshotBrowser,
     #[cfg(feature = "export")]
     #[serde(skip)]
     pub saves: Vec<StateFile>,
     pub screenshots: usize,
+    /// Raw data formats written next to the json and png when exporting layers.
+    #[serde(default)]
+    pub export_formats: Vec<DataFormat>,
+    /// Part of the map exported layers cover when eroded with margins.
+    #[serde(default)]
+    pub export_crop: ExportCrop,
+    #[serde(default)]
+    pub mesh: MeshSettings,
+    #[serde(skip)]
+    pub quit_prompt: bool,
+}
+
+fn default_droplet_bin() -> usize {
+    8
 }
 
 impl UiState {
@@ -66,17 +381,21 @@ impl UiState {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FrameSlots {
     pub canvas: Option<Rect>,
+    #[serde(default)]
+    pub pointer_captured: bool,
 }
 
+#[cfg(feature = "gui")]
 pub fn ui_draw(state: &mut State) -> Option<FrameSlots> {
     let ui_state = &mut state.ui_state;
     let app_state = &mut state.app_state;
     let state_name = &mut state.state_name;
     if ui_state.show_ui_all {
         let mut central_rect = None;
+        let mut pointer_captured = false;
         egui_macroquad::ui(|egui_ctx| {
             // Top Panel
-            ui_top_panel(egui_ctx, ui_state, state_name);
+            ui_top_panel(egui_ctx, ui_state, app_state, state_name);
 
             // Side Panel
             ui_side_panel(egui_ctx, ui_state, app_state);
@@ -96,11 +415,20 @@ pub fn ui_draw(state: &mut State) -> Option<FrameSlots> {
             ui_keybinds_window(egui_ctx, ui_state);
             ui_metadata_window(egui_ctx, ui_state, app_state);
             ui_metrics_window(egui_ctx, ui_state, app_state);
+            ui_snapshots_window(egui_ctx, ui_state);
+            ui_timeline_window(egui_ctx, ui_state, app_state);
+            ui_session_window(egui_ctx, ui_state, app_state);
+            ui_region_window(egui_ctx, ui_state, app_state);
+            ui_hud(egui_ctx, ui_state, app_state);
+            ui_quit_prompt(egui_ctx, ui_state, app_state);
+
+            pointer_captured = egui_ctx.wants_pointer_input() || egui_ctx.is_pointer_over_area();
         });
 
         egui_macroquad::draw();
         Some(FrameSlots {
             canvas: central_rect,
+            pointer_captured,
         })
     } else {
         None
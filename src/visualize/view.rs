@@ -0,0 +1,183 @@
+use emath::Rect;
+#[cfg(feature = "gui")]
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "gui")]
+use crate::visualize::ui::UiState;
+
+pub const MAX_ZOOM: f32 = 64.0;
+#[cfg(feature = "gui")]
+const ZOOM_STEP: f32 = 1.25;
+
+/// The part of the heightmap shown on the canvas, `center` is given in [0, 1] heightmap space.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct View {
+    pub center: (f32, f32),
+    pub zoom: f32, // [1, 64], 1
+}
+
+impl Default for View {
+    fn default() -> Self {
+        View {
+            center: (0.5, 0.5),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl View {
+    /// Keeps the visible window inside the heightmap.
+    pub fn clamped(self) -> Self {
+        let zoom = self.zoom.clamp(1.0, MAX_ZOOM);
+        let half = 0.5 / zoom;
+        View {
+            center: (
+                self.center.0.clamp(half, 1.0 - half),
+                self.center.1.clamp(half, 1.0 - half),
+            ),
+            zoom,
+        }
+    }
+
+    /// Top left corner and side length of the visible window in heightmap space.
+    pub fn visible(&self) -> (f32, f32, f32) {
+        let extent = 1.0 / self.zoom;
+        (
+            self.center.0 - extent / 2.0,
+            self.center.1 - extent / 2.0,
+            extent,
+        )
+    }
+
+    pub fn to_screen(&self, rect: &Rect, point: (f32, f32)) -> (f32, f32) {
+        let (left, top, side) = frame(rect);
+        let (min_u, min_v, extent) = self.visible();
+        (
+            left + (point.0 - min_u) / extent * side,
+            top + (point.1 - min_v) / extent * side,
+        )
+    }
+
+    /// Maps a screen position to heightmap space, `None` outside of the heightmap frame.
+    pub fn to_heightmap(&self, rect: &Rect, position: (f32, f32)) -> Option<(f32, f32)> {
+        let (left, top, side) = frame(rect);
+        let x = (position.0 - left) / side;
+        let y = (position.1 - top) / side;
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+            return None;
+        }
+        let (min_u, min_v, extent) = self.visible();
+        Some((min_u + x * extent, min_v + y * extent))
+    }
+
+    /// Zooms by `factor` while keeping `point` at the same place on screen.
+    pub fn zoom_at(&mut self, point: (f32, f32), factor: f32) {
+        let zoom = (self.zoom * factor).clamp(1.0, MAX_ZOOM);
+        let scale = self.zoom / zoom;
+        self.center = (
+            point.0 + (self.center.0 - point.0) * scale,
+            point.1 + (self.center.1 - point.1) * scale,
+        );
+        self.zoom = zoom;
+        *self = self.clamped();
+    }
+
+    /// Clips a line segment in heightmap space to the visible window (Liang-Barsky).
+    pub fn clip(&self, a: (f32, f32), b: (f32, f32)) -> Option<((f32, f32), (f32, f32))> {
+        let (min_u, min_v, extent) = self.visible();
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let mut t0: f32 = 0.0;
+        let mut t1: f32 = 1.0;
+        for (p, q) in [
+            (-dx, a.0 - min_u),
+            (dx, min_u + extent - a.0),
+            (-dy, a.1 - min_v),
+            (dy, min_v + extent - a.1),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else {
+                let t = q / p;
+                if p < 0.0 {
+                    t0 = t0.max(t);
+                } else {
+                    t1 = t1.min(t);
+                }
+            }
+        }
+        if t0 > t1 {
+            return None;
+        }
+        Some((
+            (a.0 + t0 * dx, a.1 + t0 * dy),
+            (a.0 + t1 * dx, a.1 + t1 * dy),
+        ))
+    }
+}
+
+/// Left, top and side length of the square heightmap frame inside `rect`.
+pub fn frame(rect: &Rect) -> (f32, f32, f32) {
+    let side = rect.width().min(rect.height());
+    (
+        rect.min.x + (rect.width() - side) / 2.0,
+        rect.min.y + (rect.height() - side) / 2.0,
+        side,
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub view: View,
+}
+
+/// Transient input of the view controls.
+#[derive(Debug, Clone, Default)]
+pub struct Navigation {
+    pub drag: Option<(f32, f32)>,
+    pub goto: (usize, usize),
+    pub bookmark_name: String,
+}
+
+/// Zooms with the mouse wheel and pans while the right mouse button is held over the canvas.
+#[cfg(feature = "gui")]
+pub fn poll_view_input(ui_state: &mut UiState, canvas_rect: &Rect) {
+    let pointer_captured = ui_state
+        .frame_slots
+        .as_ref()
+        .map(|slots| slots.pointer_captured)
+        .unwrap_or(false);
+    let position = mouse_position();
+
+    if !is_mouse_button_down(MouseButton::Right) {
+        ui_state.navigation.drag = None;
+    } else if let Some(previous) = ui_state.navigation.drag {
+        let (_, _, side) = frame(canvas_rect);
+        let extent = ui_state.view.visible().2;
+        let view = &mut ui_state.view;
+        view.center.0 -= (position.0 - previous.0) / side * extent;
+        view.center.1 -= (position.1 - previous.1) / side * extent;
+        *view = view.clamped();
+        ui_state.navigation.drag = Some(position);
+    } else if !pointer_captured && ui_state.view.to_heightmap(canvas_rect, position).is_some() {
+        ui_state.navigation.drag = Some(position);
+    }
+
+    if pointer_captured {
+        return;
+    }
+    let (_, wheel) = mouse_wheel();
+    if wheel != 0.0 {
+        if let Some(point) = ui_state.view.to_heightmap(canvas_rect, position) {
+            let factor = if wheel > 0.0 {
+                ZOOM_STEP
+            } else {
+                1.0 / ZOOM_STEP
+            };
+            ui_state.view.zoom_at(point, factor);
+        }
+    }
+}
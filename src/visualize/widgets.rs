@@ -1,22 +1,41 @@
 use bracket_noise::prelude::NoiseType;
 use egui::{Color32, Pos2, Rect, Vec2};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use std::path::PathBuf;
 
-use crate::heightmap::{HeightmapParameters, HeightmapType};
+use crate::heightmap::segments::SegmentMethod;
+use crate::heightmap::{
+    io::list_images, BlendCurve, ExtendFill, HardnessType, HeightmapOperation, HeightmapParameters,
+    HeightmapType, LayerParameters, PrecipitationType, ResampleFilter,
+};
 use crate::visualize::events::UiEvent;
+use crate::visualize::heat::HeatOverlay;
+use crate::visualize::hillshade::Hillshade;
 use crate::visualize::keybinds::{
     KEYCODE_NEW_HEIGHTMAP, KEYCODE_NEXT_PARTITIONING_METHOD, KEYCODE_PREVIOUS_PARTITIONING_METHOD,
 };
+use crate::visualize::palette::{label_color, GradientStop, Palette, MAX_GRADIENT_STOPS};
+use crate::visualize::sculpt::SculptTool;
 use crate::visualize::ui::UiState;
+use crate::visualize::view::MAX_ZOOM;
 use crate::{
-    erode::Parameters, heightmap::ProceduralHeightmapSettings, partitioning,
-    GAUSSIAN_BLUR_BOUNDARY_THICKNESS_MAX, GAUSSIAN_BLUR_BOUNDARY_THICKNESS_MIN,
-    GAUSSIAN_BLUR_SIGMA_RANGE_MAX, GAUSSIAN_BLUR_SIGMA_RANGE_MIN, GRID_SIZE_RANGE_MAX,
-    GRID_SIZE_RANGE_MIN,
+    erode::{
+        beyer, coastal, fluvial, glacial, lague::BrushKernel, pipes, wind, Backend, Parameters,
+    },
+    heightmap::ProceduralHeightmapSettings,
+    partitioning,
 };
 
 use super::{canvas::Canvas, AppState, SimulationState};
 
+const GRID_SIZE_RANGE_MIN: usize = 2;
+const GRID_SIZE_RANGE_MAX: usize = 32;
+const GAUSSIAN_BLUR_SIGMA_RANGE_MIN: f32 = 0.0;
+const GAUSSIAN_BLUR_SIGMA_RANGE_MAX: f32 = 20.0;
+const GAUSSIAN_BLUR_BOUNDARY_THICKNESS_MIN: u16 = 0;
+const GAUSSIAN_BLUR_BOUNDARY_THICKNESS_MAX: u16 = 10;
+const PARTITION_HALO_MAX: usize = 32;
+
 pub fn plot_height(ui: &mut egui::Ui, state: &mut AppState) {
     let width = 800.0;
     let height = 800.0;
@@ -141,6 +160,32 @@ pub fn post_processing(ui: &mut egui::Ui, ui_state: &mut UiState) {
                 updated = true;
             }
 
+            let lines = &mut props.contour_lines;
+            updated = updated || ui.checkbox(&mut lines.enabled, "Contour lines").changed();
+            if lines.enabled {
+                updated = updated
+                    || ui
+                        .add(
+                            egui::Slider::new(&mut lines.interval, 0.01..=0.5)
+                                .text("Contour interval"),
+                        )
+                        .changed();
+                updated = updated
+                    || ui
+                        .add(
+                            egui::Slider::new(&mut lines.thickness, 0.5..=6.0)
+                                .text("Contour thickness"),
+                        )
+                        .changed();
+                updated = updated
+                    || ui
+                        .add(
+                            egui::Slider::new(&mut lines.index_every, 0..=20)
+                                .text("Index contour every"),
+                        )
+                        .changed();
+            }
+
             let should_flood_inside_ = props.flood_lower.clone();
             updated = updated
                 || ui
@@ -263,6 +308,79 @@ pub fn post_processing(ui: &mut egui::Ui, ui_state: &mut UiState) {
     ui.separator();
 }
 
+pub fn flood_animation_settings(ui: &mut egui::Ui, ui_state: &mut UiState) {
+    egui::CollapsingHeader::new("Rising Water")
+        .default_open(false)
+        .show(ui, |ui| {
+            let animation = &mut ui_state.flood_animation;
+            ui.add(egui::Slider::new(&mut animation.start, 0.0..=1.0).text("Start Level"));
+            ui.add(egui::Slider::new(&mut animation.end, 0.0..=1.0).text("End Level"));
+            ui.add(
+                egui::Slider::new(&mut animation.speed, 0.01..=1.0)
+                    .logarithmic(true)
+                    .text("Levels per Second"),
+            );
+            ui.horizontal(|ui| {
+                let label = if animation.playing { "Pause" } else { "Play" };
+                if ui.button(label).clicked() {
+                    animation.playing = !animation.playing;
+                    let (low, high) = if animation.start <= animation.end {
+                        (animation.start, animation.end)
+                    } else {
+                        (animation.end, animation.start)
+                    };
+                    let level = ui_state.isoline.height;
+                    // Starts over when the water has reached the end or is outside the range
+                    if animation.playing && (level == animation.end || level < low || level > high)
+                    {
+                        ui_state.isoline.height = animation.start;
+                    }
+                }
+                if ui.button("Clear Curve").clicked() {
+                    animation.curve.clear();
+                }
+            });
+            ui.label(format!(
+                "Water at {:.3}, {:.1}% flooded",
+                ui_state.isoline.height,
+                ui_state.isoline.flooded_share.unwrap_or(0.0) * 100.0
+            ));
+            flood_curve(ui, ui_state);
+            #[cfg(feature = "export")]
+            {
+                let animation = &mut ui_state.flood_animation;
+                ui.add(egui::Slider::new(&mut animation.frames, 2..=360).text("Frames"));
+                if ui.button("Export Sequence").clicked() {
+                    ui_state.ui_events.push(UiEvent::ExportFloodSequence);
+                }
+            }
+        });
+
+    ui.separator();
+}
+
+/// Flooded share over the water level, the current level marked by a vertical line.
+fn flood_curve(ui: &mut egui::Ui, ui_state: &UiState) {
+    let (width, height) = (240.0, 120.0);
+    let mut canvas = Canvas::new(
+        Vec2::new(width, height),
+        egui::Stroke::new(1.0, Color32::DARK_GRAY),
+    );
+    canvas.draw(ui);
+    canvas.draw_rectangle_lines(ui, Rect::from_two_pos(Pos2::ZERO, canvas.size.to_pos2()));
+
+    let level = ui_state.isoline.height * width;
+    canvas.draw_line(ui, Vec2::new(level, 0.0), Vec2::new(level, height));
+
+    canvas.stroke = egui::Stroke::new(2.0, Color32::LIGHT_BLUE);
+    let points = ui_state.flood_animation.curve.points();
+    for pair in points.windows(2) {
+        let start = Vec2::new(pair[0].0 * width, pair[0].1 * height);
+        let end = Vec2::new(pair[1].0 * width, pair[1].1 * height);
+        canvas.draw_line(ui, start, end);
+    }
+}
+
 pub fn erosion_method_selection(ui: &mut egui::Ui, ui_state: &mut UiState, state: &mut AppState) {
     egui::CollapsingHeader::new("Erosion Method Selection")
         .default_open(true)
@@ -300,6 +418,7 @@ pub fn erosion_method_selection(ui: &mut egui::Ui, ui_state: &mut UiState, state
                     match state.simulation_state_mut().base_mut().erosion_method {
                         partitioning::Method::Default => (), // TODO: Fix default always using default grid size, this breaks margin calculations
                         partitioning::Method::Subdivision(ref mut grid_size)
+                        | partitioning::Method::Checkerboard(ref mut grid_size)
                         // | partitioning::Method::SubdivisionOverlap(ref mut grid_size)
                             => {
                             ui.add(
@@ -337,7 +456,10 @@ pub fn erosion_method_selection(ui: &mut egui::Ui, ui_state: &mut UiState, state
                                 .text("Gaussian Blur Boundary Thickness"),
                             );
                         }
-                        partitioning::Method::GridOverlapBlend(ref mut grid_size) => {
+                        partitioning::Method::GridOverlapBlend((
+                            ref mut grid_size,
+                            ref mut curve,
+                        )) => {
                             ui.add(
                                 egui::Slider::new(
                                     grid_size,
@@ -345,11 +467,74 @@ pub fn erosion_method_selection(ui: &mut egui::Ui, ui_state: &mut UiState, state
                                 )
                                 .text("Grid Size"),
                             );
+                            egui::ComboBox::from_label("Blend Curve")
+                                .selected_text(curve.to_string())
+                                .show_ui(ui, |ui| {
+                                    for option in BlendCurve::list() {
+                                        let selected = std::mem::discriminant(curve)
+                                            == std::mem::discriminant(&option);
+                                        // Picking the power again keeps its exponent
+                                        if ui.selectable_label(selected, option.to_string()).clicked()
+                                            && !selected
+                                        {
+                                            *curve = option;
+                                        }
+                                    }
+                                })
+                                .response
+                                .on_hover_text("How the overlapping grids fade into each other at the seams");
+                            if let BlendCurve::Power(ref mut exponent) = curve {
+                                ui.add(
+                                    egui::Slider::new(exponent, 0.1..=10.0)
+                                        .logarithmic(true)
+                                        .text("Blend Exponent"),
+                                );
+                            }
+                        }
+                        partitioning::Method::Voronoi(ref mut cells, ref mut seed) => {
+                            ui.add(
+                                egui::Slider::new(
+                                    cells,
+                                    GRID_SIZE_RANGE_MIN..=GRID_SIZE_RANGE_MAX * GRID_SIZE_RANGE_MAX,
+                                )
+                                .logarithmic(true)
+                                .text("Cells"),
+                            );
+                            ui.add(egui::DragValue::new(seed).prefix("Seed: "));
                         }
                     };
                     if !ui_state.show_ui_presentation_mode {
                         ui.toggle_value(&mut state.parameters.margin, "Use Margin");
+                        if state.parameters.margin {
+                            margin_selection(ui, state);
+                        }
+                        ui.toggle_value(
+                            &mut state.simulation_state_mut().base_mut().adaptive_iterations,
+                            "Adaptive Droplets",
+                        )
+                        .on_hover_text("Spend more droplets on rough partitions than flat ones");
+                        if state.simulation_state().base().erosion_method.uses_halo() {
+                            ui.add(
+                                egui::Slider::new(
+                                    &mut state.simulation_state_mut().base_mut().partition_halo,
+                                    0..=PARTITION_HALO_MAX,
+                                )
+                                .text("Halo"),
+                            )
+                            .on_hover_text(
+                                "Cells of the neighbouring partitions droplets see around each \
+                                partition, they are eroded over but not kept",
+                            );
+                        }
                         ui.toggle_value(&mut ui_state.show_grid, "Show Grid");
+                        ui.toggle_value(
+                            &mut ui_state.show_partition_preview,
+                            "Preview Partitions",
+                        )
+                        .on_hover_text(
+                            "Boundaries, overlap in yellow and margins in magenta of the next \
+                            erosion, without running it",
+                        );
                     }
                 });
         });
@@ -357,201 +542,1336 @@ pub fn erosion_method_selection(ui: &mut egui::Ui, ui_state: &mut UiState, state
     ui.separator();
 }
 
+/// Per-side margins instead of the largest margin of any method, starting from the current ones.
+fn margin_selection(ui: &mut egui::Ui, state: &mut AppState) {
+    let mut per_side = state.parameters.custom_margins.is_some();
+    if ui
+        .toggle_value(&mut per_side, "Margin per Side")
+        .on_hover_text("Cells cut off each side of the map, shown on the grid")
+        .changed()
+    {
+        state.parameters.custom_margins = None;
+        if per_side {
+            state.parameters.custom_margins =
+                Some(state.simulation_state().margin(&state.parameters));
+        }
+    }
+    let heightmap = &state.simulation_state().base().heightmap_base.heightmap;
+    let (width, height) = (heightmap.width / 2, heightmap.height / 2);
+    if let Some(margins) = &mut state.parameters.custom_margins {
+        egui::Grid::new("partitioning_margins").show(ui, |ui| {
+            ui.add(egui::DragValue::new(&mut margins.left).clamp_range(0..=width));
+            ui.label("Left");
+            ui.add(egui::DragValue::new(&mut margins.right).clamp_range(0..=width));
+            ui.label("Right");
+            ui.end_row();
+            ui.add(egui::DragValue::new(&mut margins.top).clamp_range(0..=height));
+            ui.label("Top");
+            ui.add(egui::DragValue::new(&mut margins.bottom).clamp_range(0..=height));
+            ui.label("Bottom");
+            ui.end_row();
+        });
+    }
+}
+
 pub fn erosion_parameter_selection(ui: &mut egui::Ui, state: &mut AppState) {
     egui::CollapsingHeader::new("Erosion Parameters")
         .default_open(true)
         .show(ui, |ui| {
-            egui::CollapsingHeader::new("Advanced")
-                .default_open(false)
-                .show(ui, |ui| {
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.erosion_radius,
-                            0..=5,
-                        )
-                        .text("Erosion Radius"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(&mut state.parameters.erosion_params.inertia, 0.0..=5.5)
-                            .text("Inertia"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.sediment_capacity_factor,
-                            0.0..=5.5,
-                        )
-                        .text("Sediment Capacity Factor"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.min_sediment_capacity,
-                            0.0..=5.5,
-                        )
-                        .text("Min Sediment Capacity"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.erode_speed,
-                            0.0..=5.5,
-                        )
-                        .text("Erode Speed"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.deposit_speed,
-                            0.0..=5.5,
-                        )
-                        .text("Deposit Speed"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.evaporate_speed,
-                            0.0..=5.5,
-                        )
-                        .text("Evaporate Speed"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(&mut state.parameters.erosion_params.gravity, 0.0..=5.5)
-                            .text("Gravity"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.max_droplet_lifetime,
-                            0..=5,
-                        )
-                        .text("Max Droplet Lifetime"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.initial_water_volume,
-                            0.0..=5.5,
-                        )
-                        .text("Initial Water Volume"),
-                    )
-                    .changed();
-                    ui.add(
-                        egui::Slider::new(
-                            &mut state.parameters.erosion_params.initial_speed,
-                            0.0..=5.5,
-                        )
-                        .text("Initial Speed"),
-                    )
-                    .changed();
+            egui::ComboBox::from_label("Erosion Model")
+                .selected_text(state.parameters.backend.to_string())
+                .show_ui(ui, |ui| {
+                    for backend in Backend::list() {
+                        ui.selectable_value(
+                            &mut state.parameters.backend,
+                            backend,
+                            backend.to_string(),
+                        );
+                    }
                 });
-            ui.add(
-                egui::Slider::new(
-                    &mut state.parameters.erosion_params.num_iterations,
-                    0..=20_000_000,
-                )
-                .text("Num Iterations"),
-            )
-            .changed();
-
-            if ui.button("Reset").clicked() {
-                state.parameters.erosion_params = Parameters::default();
+            match state.parameters.backend {
+                Backend::Lague => {
+                    lague_parameter_selection(ui, &mut state.parameters.erosion_params)
+                }
+                Backend::Beyer => beyer_parameter_selection(ui, &mut state.parameters.beyer_params),
+                Backend::Pipes => pipes_parameter_selection(ui, &mut state.parameters.pipes_params),
+                Backend::Fluvial => {
+                    fluvial_parameter_selection(ui, &mut state.parameters.fluvial_params)
+                }
+                Backend::Wind => wind_parameter_selection(ui, &mut state.parameters.wind_params),
+                Backend::Glacial => {
+                    glacial_parameter_selection(ui, &mut state.parameters.glacial_params)
+                }
             }
         });
 
     ui.separator();
 }
-pub fn layer_selection(ui: &mut egui::Ui, state: &AppState) {
-    egui::CollapsingHeader::new("Layers")
-        .default_open(true)
+
+fn lague_parameter_selection(ui: &mut egui::Ui, params: &mut Parameters) {
+    let presets = Parameters::presets();
+    let selected = presets
+        .iter()
+        .find(|preset| preset.parameters == *params)
+        .map(|preset| preset.name.clone())
+        .unwrap_or_else(|| "custom".to_string());
+    egui::ComboBox::from_label("Preset")
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            for preset in presets {
+                if ui
+                    .selectable_label(preset.parameters == *params, &preset.name)
+                    .clicked()
+                {
+                    *params = preset.parameters;
+                }
+            }
+        });
+    egui::CollapsingHeader::new("Advanced")
+        .default_open(false)
         .show(ui, |ui| {
-            let selected_diff: Option<usize> =
-                if let Some(eroded) = state.simulation_state().eroded() {
-                    Some((*eroded.selected_diff.borrow()).clone())
-                } else {
-                    None
-                };
-            // Image Layers
-            ui.heading("Image Layers");
-            for simulation in state.simulation_states.iter() {
-                ui.horizontal(|ui| {
-                    if *state.simulation_base_indices.last().unwrap() == simulation.id() {
-                        ui.label("-> ");
-                    }
-                    match simulation {
-                        SimulationState::Base(base) => {
-                            ui.label(format!("{}: [Base Layer]", base.id));
-                        }
-                        SimulationState::Eroded((_, eroded)) => {
-                            ui.label(format!(
-                                "{}: {} eroded from #{}",
-                                eroded.id,
-                                eroded.erosion_method.to_string(),
-                                eroded.base_id
-                            ));
-                        }
-                    }
-                    if let Some(selected_diff) = selected_diff {
-                        if simulation.id() == selected_diff {
-                            ui.label(" <-- diff");
-                        }
+            ui.add(egui::Slider::new(&mut params.erosion_radius, 0..=5).text("Erosion Radius"))
+                .changed();
+            egui::ComboBox::from_label("Brush Kernel")
+                .selected_text(params.brush_kernel.to_string())
+                .show_ui(ui, |ui| {
+                    for kernel in BrushKernel::list() {
+                        ui.selectable_value(&mut params.brush_kernel, kernel, kernel.to_string());
                     }
                 });
-            }
+            ui.add(egui::Slider::new(&mut params.inertia, 0.0..=5.5).text("Inertia"))
+                .changed();
+            ui.add(
+                egui::Slider::new(&mut params.sediment_capacity_factor, 0.0..=5.5)
+                    .text("Sediment Capacity Factor"),
+            )
+            .changed();
+            ui.add(
+                egui::Slider::new(&mut params.min_sediment_capacity, 0.0..=5.5)
+                    .text("Min Sediment Capacity"),
+            )
+            .changed();
+            ui.add(egui::Slider::new(&mut params.erode_speed, 0.0..=5.5).text("Erode Speed"))
+                .changed();
+            ui.add(egui::Slider::new(&mut params.deposit_speed, 0.0..=5.5).text("Deposit Speed"))
+                .changed();
+            ui.add(
+                egui::Slider::new(&mut params.evaporate_speed, 0.0..=5.5).text("Evaporate Speed"),
+            )
+            .changed();
+            ui.add(egui::Slider::new(&mut params.gravity, 0.0..=5.5).text("Gravity"))
+                .changed();
+            ui.add(
+                egui::Slider::new(&mut params.max_droplet_lifetime, 0..=5)
+                    .text("Max Droplet Lifetime"),
+            )
+            .changed();
+            ui.add(
+                egui::Slider::new(&mut params.initial_water_volume, 0.0..=5.5)
+                    .text("Initial Water Volume"),
+            )
+            .changed();
+            ui.add(egui::Slider::new(&mut params.initial_speed, 0.0..=5.5).text("Initial Speed"))
+                .changed();
+            ui.add(egui::Slider::new(&mut params.min_water, 0.0..=1.0).text("Min Water"));
+            ui.add(egui::Slider::new(&mut params.min_speed, 0.0..=1.0).text("Min Speed"));
+            ui.add(egui::Slider::new(&mut params.batch_size, 0..=1024).text("Batch Size"))
+                .on_hover_text(
+                    "Droplets simulated side by side, 0 runs them one by one or in batches of 256 when parallel",
+                );
+            ui.checkbox(&mut params.parallel, "Parallel Droplets")
+                .on_hover_text(
+                    "Simulates the droplets of a batch on all cores, best with large batches",
+                );
+            ui.add(egui::Checkbox::new(
+                &mut params.record_flow,
+                "Record Flow Map",
+            ));
+            ui.checkbox(&mut params.double_precision, "Double Precision")
+                .on_hover_text(
+                    "Erodes the heights as 64 bit floats within a pass, slower but without drift",
+                );
         });
+    ui.add(egui::Slider::new(&mut params.num_iterations, 0..=20_000_000).text("Num Iterations"))
+        .changed();
+    ui.checkbox(&mut params.wrap, "Wrap Around")
+        .on_hover_text("Tileable result, only wraps without partitioning");
 
-    ui.separator();
+    if ui.button("Reset").clicked() {
+        *params = Parameters::default();
+    }
 }
 
-fn heightmap_parameters(
-    params: &mut HeightmapParameters,
-    ui: &mut egui::Ui,
-    ui_state: &mut UiState,
-    state: &mut AppState,
-) {
-    let mut size = params.size;
-    let mut updated = ui
-        .add(egui::Slider::new(&mut size, 2usize.pow(6)..=2usize.pow(12)).text("Resolution"))
-        .changed();
-    params.size = size;
+fn beyer_parameter_selection(ui: &mut egui::Ui, params: &mut beyer::Parameters) {
+    egui::CollapsingHeader::new("Advanced")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.add(egui::Slider::new(&mut params.inertia, 0.0..=1.0).text("Inertia"));
+            ui.add(egui::Slider::new(&mut params.capacity, 0.0..=32.0).text("Capacity"));
+            ui.add(egui::Slider::new(&mut params.deposition, 0.0..=1.0).text("Deposition"));
+            ui.add(egui::Slider::new(&mut params.erosion, 0.0..=1.0).text("Erosion"));
+            ui.add(egui::Slider::new(&mut params.evaporation, 0.0..=1.0).text("Evaporation"));
+            ui.add(egui::Slider::new(&mut params.radius, 1.0..=8.0).text("Radius"));
+            ui.add(egui::Slider::new(&mut params.min_slope, 0.0..=0.1).text("Min Slope"));
+            ui.add(egui::Slider::new(&mut params.gravity, 0.0..=20.0).text("Gravity"));
+            ui.add(egui::Slider::new(&mut params.max_path, 1..=256).text("Max Path"));
+            ui.add(egui::Slider::new(&mut params.initial_water, 0.0..=5.0).text("Initial Water"));
+            ui.add(egui::Slider::new(&mut params.initial_speed, 0.0..=5.0).text("Initial Speed"));
+            ui.add(egui::Slider::new(&mut params.min_water, 0.0..=1.0).text("Min Water"));
+            ui.add(egui::Slider::new(&mut params.min_speed, 0.0..=1.0).text("Min Speed"));
+        });
+    ui.add(egui::Slider::new(&mut params.num_iterations, 0..=20_000_000).text("Num Iterations"));
 
-    ui.add(egui::Checkbox::new(
-        &mut state.parameters.auto_apply,
-        "Auto Apply",
-    ));
+    if ui.button("Reset").clicked() {
+        *params = beyer::Parameters::default();
+    }
+}
+
+fn pipes_parameter_selection(ui: &mut egui::Ui, params: &mut pipes::Parameters) {
+    egui::CollapsingHeader::new("Advanced")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.add(egui::Slider::new(&mut params.time_step, 0.001..=0.2).text("Time Step"));
+            ui.add(egui::Slider::new(&mut params.rain_rate, 0.0..=0.1).text("Rain Rate"));
+            ui.add(egui::Slider::new(&mut params.pipe_area, 0.1..=20.0).text("Pipe Area"));
+            ui.add(egui::Slider::new(&mut params.gravity, 0.0..=20.0).text("Gravity"));
+            ui.add(egui::Slider::new(&mut params.height_scale, 1.0..=512.0).text("Height Scale"));
+            ui.add(
+                egui::Slider::new(&mut params.sediment_capacity, 0.0..=4.0)
+                    .text("Sediment Capacity"),
+            );
+            ui.add(egui::Slider::new(&mut params.dissolving, 0.0..=2.0).text("Dissolving"));
+            ui.add(egui::Slider::new(&mut params.deposition, 0.0..=2.0).text("Deposition"));
+            ui.add(egui::Slider::new(&mut params.evaporation, 0.0..=1.0).text("Evaporation"));
+            ui.add(egui::Slider::new(&mut params.min_tilt, 0.0..=1.0).text("Min Tilt"));
+            ui.add(egui::Checkbox::new(
+                &mut params.record_flow,
+                "Record Flow Map",
+            ));
+        });
+    ui.add(egui::Slider::new(&mut params.num_iterations, 1..=10_000).text("Time Steps"));
 
     if ui.button("Reset").clicked() {
-        params.reset();
-        updated = true;
+        *params = pipes::Parameters::default();
     }
+}
 
-    let mut apply = false;
-    if !state.parameters.auto_apply {
-        apply = ui.button("Apply").clicked();
+fn fluvial_parameter_selection(ui: &mut egui::Ui, params: &mut fluvial::Parameters) {
+    egui::CollapsingHeader::new("Advanced")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.add(
+                egui::Slider::new(&mut params.erodibility, 0.0..=0.01)
+                    .logarithmic(true)
+                    .text("Erodibility (K)"),
+            );
+            ui.add(
+                egui::Slider::new(&mut params.area_exponent, 0.0..=1.0).text("Area Exponent (m)"),
+            );
+            ui.add(
+                egui::Slider::new(&mut params.slope_exponent, 0.5..=2.0).text("Slope Exponent (n)"),
+            );
+            ui.add(egui::Slider::new(&mut params.time_step, 0.1..=10.0).text("Time Step"));
+            ui.add(egui::Slider::new(&mut params.height_scale, 1.0..=512.0).text("Height Scale"));
+            ui.add(egui::Checkbox::new(
+                &mut params.record_flow,
+                "Record Flow Map",
+            ));
+        });
+    ui.add(egui::Slider::new(&mut params.num_iterations, 1..=1000).text("Time Steps"));
+
+    if ui.button("Reset").clicked() {
+        *params = fluvial::Parameters::default();
     }
+}
 
-    let update = (state.parameters.auto_apply && updated) || apply;
-    if update {
-        ui_state.ui_events.push(UiEvent::ReplaceHeightmap);
+fn glacial_parameter_selection(ui: &mut egui::Ui, params: &mut glacial::Parameters) {
+    egui::CollapsingHeader::new("Advanced")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.add(
+                egui::Slider::new(&mut params.mass_balance, 0.0..=0.01)
+                    .logarithmic(true)
+                    .text("Mass Balance"),
+            );
+            ui.add(egui::Slider::new(&mut params.flow_rate, 0.0..=1.0).text("Flow Rate"));
+            ui.add(egui::Slider::new(&mut params.height_scale, 1.0..=512.0).text("Height Scale"));
+            ui.add(egui::Checkbox::new(
+                &mut params.record_flow,
+                "Record Ice Flow Map",
+            ));
+        });
+    ui.add(egui::Slider::new(&mut params.snowline, 0.0..=1.0).text("Snowline"));
+    ui.add(egui::Slider::new(&mut params.abrasion, 0.0..=1.0).text("Abrasion"));
+    ui.add(egui::Slider::new(&mut params.num_iterations, 1..=2000).text("Time Steps"));
+    ui.label("Run a hydraulic model on the result for alpine terrain.");
+
+    if ui.button("Reset").clicked() {
+        *params = glacial::Parameters::default();
     }
 }
 
-fn procedural_generation_settings(
-    settings: &mut ProceduralHeightmapSettings,
-    ui: &mut egui::Ui,
-    ui_state: &mut UiState,
-    state: &mut AppState,
-) {
-    let mut updated = false;
+fn wind_parameter_selection(ui: &mut egui::Ui, params: &mut wind::Parameters) {
+    egui::CollapsingHeader::new("Advanced")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.add(egui::Slider::new(&mut params.turbulence, 0.0..=1.0).text("Turbulence"));
+            ui.add(
+                egui::Slider::new(&mut params.turbulence_scale, 0.0..=8.0).text("Turbulence Scale"),
+            );
+            ui.add(egui::Slider::new(&mut params.slab_height, 0.0..=0.02).text("Slab Height"));
+            ui.add(egui::Slider::new(&mut params.hop_length, 1.0..=16.0).text("Hop Length"));
+            ui.add(egui::Slider::new(&mut params.deposit_chance, 0.0..=1.0).text("Deposit Chance"));
+            ui.add(egui::Slider::new(&mut params.suspension, 0.0..=1.0).text("Suspension"));
+            ui.add(
+                egui::Slider::new(&mut params.suspension_length, 1.0..=64.0)
+                    .text("Suspension Length"),
+            );
+            ui.add(egui::Slider::new(&mut params.shadow_angle, 0.0..=45.0).text("Shadow Angle"));
+            ui.add(egui::Slider::new(&mut params.repose_angle, 0.0..=90.0).text("Repose Angle"));
+            ui.add(egui::Slider::new(&mut params.max_hops, 1..=64).text("Max Hops"));
+            ui.add(egui::Slider::new(&mut params.seed, 0..=10000000000).text("Seed"));
+        });
+    ui.add(egui::Slider::new(&mut params.direction, 0.0..=360.0).text("Wind Direction"));
+    ui.add(egui::Slider::new(&mut params.strength, 0.0..=4.0).text("Wind Strength"));
+    ui.add(egui::Slider::new(&mut params.num_iterations, 0..=20_000_000).text("Num Iterations"));
 
-    updated = updated
-        || ui
-            .add(egui::Slider::new(&mut settings.seed, 0..=10000000000).text("Seed"))
-            .changed();
+    if ui.button("Reset").clicked() {
+        *params = wind::Parameters::default();
+    }
+}
 
-    let noise_type = settings.noise_type;
+pub fn pipeline_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &mut AppState) {
+    egui::CollapsingHeader::new("Erosion Pipeline")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.label("Passes run one after another as a single simulation.");
+            let parameters = &mut state.parameters;
+            let mut load = None;
+            let mut replace = None;
+            let mut remove = None;
+            let mut up = None;
+            let mut down = None;
+            for (i, model) in parameters.pipeline.passes.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{}. {} x{}",
+                        i + 1,
+                        model.backend(),
+                        model.num_iterations()
+                    ));
+                    if ui
+                        .small_button("Edit")
+                        .on_hover_text("Load into the erosion parameters")
+                        .clicked()
+                    {
+                        load = Some(*model);
+                    }
+                    if ui
+                        .small_button("Set")
+                        .on_hover_text("Replace with the erosion parameters")
+                        .clicked()
+                    {
+                        replace = Some(i);
+                    }
+                    if ui.small_button("^").clicked() {
+                        up = Some(i);
+                    }
+                    if ui.small_button("v").clicked() {
+                        down = Some(i);
+                    }
+                    if ui.small_button("x").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(model) = load {
+                parameters.set_model(model);
+            }
+            if let Some(i) = replace {
+                parameters.pipeline.passes[i] = parameters.model();
+            }
+            if let Some(i) = up {
+                parameters.pipeline.move_up(i);
+            }
+            if let Some(i) = down {
+                parameters.pipeline.move_down(i);
+            }
+            if let Some(i) = remove {
+                parameters.pipeline.passes.remove(i);
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Add Pass")
+                    .on_hover_text("Append the erosion parameters as a pass")
+                    .clicked()
+                {
+                    parameters.pipeline.passes.push(parameters.model());
+                }
+                if ui.button("Clear").clicked() {
+                    parameters.pipeline.passes.clear();
+                }
+            });
+            if !parameters.pipeline.is_empty() {
+                ui.label(format!(
+                    "{} iterations in total",
+                    parameters.pipeline.num_iterations()
+                ));
+                if ui.button("Run Pipeline").clicked() {
+                    ui_state.ui_events.push(UiEvent::RunPipeline);
+                }
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn sea_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &mut AppState) {
+    egui::CollapsingHeader::new("Sea Level")
+        .default_open(false)
+        .show(ui, |ui| {
+            let params = &mut state.parameters.coastal_params;
+            ui.checkbox(&mut params.enabled, "Sea");
+            ui.add(egui::Slider::new(&mut params.sea_level, 0.0..=1.0).text("Sea Level"));
+            ui.add(egui::Slider::new(&mut params.wave_strength, 0.0..=0.01).text("Wave Strength"));
+            ui.add(egui::Slider::new(&mut params.wave_reach, 0.0..=0.1).text("Wave Reach"));
+            ui.add(
+                egui::Slider::new(&mut params.wave_interval, 1..=100_000)
+                    .logarithmic(true)
+                    .text("Iterations per Wave"),
+            );
+            ui.horizontal(|ui| {
+                if ui.button("Show").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowSea);
+                }
+                if ui.button("Use Isoline Height").clicked() {
+                    ui_state.ui_events.push(UiEvent::SeaLevelFromIsoline);
+                }
+                if ui.button("Reset").clicked() {
+                    *params = coastal::Parameters::default();
+                }
+            });
+            if params.enabled {
+                ui.label("Droplets dissolve in the sea and waves cut the coast.");
+            }
+        });
+
+    ui.separator();
+}
+
+/// Shown with the controls of animated simulations, where the overlay applies.
+pub fn heat_overlay_settings(ui: &mut egui::Ui, overlay: &mut HeatOverlay) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut overlay.enabled, "Heat Overlay");
+        if overlay.enabled {
+            ui.add(
+                egui::Slider::new(&mut overlay.half_life, 0.1..=10.0)
+                    .logarithmic(true)
+                    .text("Half-life (s)"),
+            );
+        }
+    });
+}
+
+pub fn hillshade_settings(ui: &mut egui::Ui, ui_state: &mut UiState) {
+    egui::CollapsingHeader::new("Hillshade")
+        .default_open(false)
+        .show(ui, |ui| {
+            let hillshade = &mut ui_state.hillshade;
+            let sun = &mut hillshade.sun;
+            ui.add(egui::Slider::new(&mut sun.azimuth, 0.0..=360.0).text("Sun Azimuth"));
+            ui.add(egui::Slider::new(&mut sun.altitude, 1.0..=89.0).text("Sun Altitude"));
+            ui.add(
+                egui::Slider::new(&mut sun.exaggeration, 0.1..=10.0)
+                    .logarithmic(true)
+                    .text("Vertical Exaggeration"),
+            );
+            ui.checkbox(&mut sun.shadows, "Cast Shadows");
+            if sun.shadows {
+                ui.add(egui::Slider::new(&mut sun.softness, 0.0..=20.0).text("Shadow Softness"));
+            }
+            sun.azimuth = sun.azimuth.rem_euclid(360.0);
+            ui.checkbox(&mut hillshade.animate, "Animate Sun");
+            if hillshade.animate {
+                ui.add(
+                    egui::Slider::new(&mut hillshade.speed, 1.0..=180.0).text("Degrees per Second"),
+                );
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Show").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowHillshade);
+                }
+                if ui.button("Reset").clicked() {
+                    *hillshade = Hillshade::default();
+                }
+            });
+            #[cfg(feature = "export")]
+            {
+                ui.add(egui::Slider::new(&mut hillshade.frames, 2..=360).text("Frames"));
+                if ui.button("Export Sequence").clicked() {
+                    ui_state.ui_events.push(UiEvent::ExportSunSequence);
+                }
+            }
+        });
+
+    ui.separator();
+}
+
+// Largest regions listed under the segmentation settings
+const LISTED_REGIONS: usize = 8;
+
+pub fn segmentation_settings(ui: &mut egui::Ui, ui_state: &mut UiState) {
+    egui::CollapsingHeader::new("Segmentation")
+        .default_open(false)
+        .show(ui, |ui| {
+            let settings = &mut ui_state.segments;
+            egui::ComboBox::from_label("Segment By")
+                .selected_text(settings.method.to_string())
+                .show_ui(ui, |ui| {
+                    for method in SegmentMethod::list() {
+                        ui.selectable_value(&mut settings.method, method, method.to_string());
+                    }
+                });
+            if settings.method == SegmentMethod::HeightBands {
+                ui.add(egui::Slider::new(&mut settings.bands, 2..=64).text("Bands"));
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Segment").clicked() {
+                    ui_state.ui_events.push(UiEvent::Segment);
+                }
+                #[cfg(feature = "export")]
+                if ui
+                    .add_enabled(
+                        ui_state.segmentation.is_some(),
+                        egui::Button::new("Export Label Map"),
+                    )
+                    .clicked()
+                {
+                    ui_state.ui_events.push(UiEvent::ExportSegments);
+                }
+            });
+
+            if let Some(segmentation) = &ui_state.segmentation {
+                let cells = (segmentation.width * segmentation.height) as f32;
+                ui.label(format!(
+                    "{} regions by {}",
+                    segmentation.regions.len(),
+                    segmentation.method
+                ));
+                egui::Grid::new("segmentation_regions")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Region");
+                        ui.label("Area");
+                        ui.label("Mean Height");
+                        ui.end_row();
+                        for region in segmentation.by_area().into_iter().take(LISTED_REGIONS) {
+                            let [r, g, b] = label_color(region.label);
+                            ui.colored_label(
+                                Color32::from_rgb(r, g, b),
+                                format!("#{}", region.label),
+                            );
+                            ui.label(format!("{:.1}%", region.area as f32 / cells * 100.0));
+                            ui.label(format!("{:.3}", region.mean_height));
+                            ui.end_row();
+                        }
+                    });
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn hydrology_settings(ui: &mut egui::Ui, ui_state: &mut UiState) {
+    egui::CollapsingHeader::new("Hydrology")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Show Basins").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowBasins);
+                }
+                if ui.button("Show Flow Accumulation").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowFlowAccumulation);
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Show Lakes").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowLakes);
+                }
+                if ui.button("Fill Depressions").clicked() {
+                    ui_state.ui_events.push(UiEvent::FillDepressions);
+                }
+            });
+            ui.label("Basin and lake statistics are listed in the metrics window.");
+        });
+
+    ui.separator();
+}
+
+pub fn layer_math_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &AppState) {
+    egui::CollapsingHeader::new("Layer Math")
+        .default_open(false)
+        .show(ui, |ui| {
+            let math = &mut ui_state.layer_math;
+            egui::ComboBox::from_label("Operation")
+                .selected_text(format!("{}", math.operation))
+                .show_ui(ui, |ui| {
+                    for operation in HeightmapOperation::list() {
+                        ui.selectable_value(
+                            &mut math.operation,
+                            operation,
+                            format!("{}", operation),
+                        );
+                    }
+                });
+            let operand = state
+                .simulation_states
+                .iter()
+                .find(|simulation| simulation.id() == math.operand)
+                .map(|simulation| simulation.name())
+                .unwrap_or_else(|| "None".to_string());
+            egui::ComboBox::from_label("With State")
+                .selected_text(&operand)
+                .show_ui(ui, |ui| {
+                    for simulation in state.simulation_states.iter() {
+                        ui.selectable_value(&mut math.operand, simulation.id(), simulation.name());
+                    }
+                });
+            if math.operation == HeightmapOperation::Lerp {
+                ui.add(egui::Slider::new(&mut math.amount, 0.0..=1.0).text("Amount"));
+            }
+            ui.label(format!(
+                "{} with {} into a new base state.",
+                math.operation, operand
+            ));
+            if ui.button("Combine Into New State").clicked() {
+                ui_state.ui_events.push(UiEvent::LayerMath);
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn resample_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &AppState) {
+    egui::CollapsingHeader::new("Resample")
+        .default_open(false)
+        .show(ui, |ui| {
+            let heightmap = state.simulation_state().get_heightmap();
+            ui.label(format!(
+                "Selected state is {}x{} cells.",
+                heightmap.width, heightmap.height
+            ));
+            let resample = &mut ui_state.resample;
+            ui.add(egui::Slider::new(&mut resample.width, 2..=4096).text("Width"));
+            ui.add(egui::Slider::new(&mut resample.height, 2..=4096).text("Height"));
+            ui.horizontal(|ui| {
+                if ui.button("Half").clicked() {
+                    resample.width = (heightmap.width / 2).max(2);
+                    resample.height = (heightmap.height / 2).max(2);
+                }
+                if ui.button("Same").clicked() {
+                    resample.width = heightmap.width;
+                    resample.height = heightmap.height;
+                }
+                if ui.button("Double").clicked() {
+                    resample.width = (heightmap.width * 2).min(4096);
+                    resample.height = (heightmap.height * 2).min(4096);
+                }
+            });
+            egui::ComboBox::from_label("Filter")
+                .selected_text(format!("{}", resample.filter))
+                .show_ui(ui, |ui| {
+                    for filter in ResampleFilter::list() {
+                        ui.selectable_value(&mut resample.filter, filter, format!("{}", filter));
+                    }
+                });
+            if ui.button("Resample Into New State").clicked() {
+                ui_state.ui_events.push(UiEvent::Resample);
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn crop_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &AppState) {
+    egui::CollapsingHeader::new("Crop and Extend")
+        .default_open(false)
+        .show(ui, |ui| {
+            let heightmap = state.simulation_state().get_heightmap();
+            let crop = &mut ui_state.crop;
+            ui.horizontal(|ui| {
+                ui.toggle_value(&mut crop.selection.selecting, "Select");
+                if ui.button("Clear").clicked() {
+                    crop.selection.clear();
+                }
+            });
+            if crop.selection.selecting {
+                ui.label("Drag the area to keep on the canvas with the left mouse button.");
+            }
+            match crop.selection.cells(heightmap.width, heightmap.height) {
+                Some(((min_x, min_y), (max_x, max_y))) => {
+                    let (width, height) = (max_x - min_x, max_y - min_y);
+                    ui.label(format!(
+                        "Keeps {}x{} cells from ({}, {}).",
+                        width, height, min_x, min_y
+                    ));
+                    if ui.button("Crop Into New State").clicked() {
+                        ui_state.ui_events.push(UiEvent::Crop {
+                            x: min_x,
+                            y: min_y,
+                            width,
+                            height,
+                        });
+                    }
+                }
+                None => {
+                    ui.label("No area selected.");
+                }
+            }
+
+            ui.separator();
+            let margins = &mut crop.margins;
+            egui::Grid::new("extend_margins").show(ui, |ui| {
+                ui.add(egui::DragValue::new(&mut margins.left).clamp_range(0..=1024));
+                ui.label("Left");
+                ui.add(egui::DragValue::new(&mut margins.right).clamp_range(0..=1024));
+                ui.label("Right");
+                ui.end_row();
+                ui.add(egui::DragValue::new(&mut margins.top).clamp_range(0..=1024));
+                ui.label("Top");
+                ui.add(egui::DragValue::new(&mut margins.bottom).clamp_range(0..=1024));
+                ui.label("Bottom");
+                ui.end_row();
+            });
+            egui::ComboBox::from_label("Fill")
+                .selected_text(format!("{}", crop.fill))
+                .show_ui(ui, |ui| {
+                    for fill in ExtendFill::list() {
+                        let selected =
+                            std::mem::discriminant(&crop.fill) == std::mem::discriminant(&fill);
+                        if ui.selectable_label(selected, format!("{}", fill)).clicked() && !selected
+                        {
+                            crop.fill = fill;
+                        }
+                    }
+                });
+            if let ExtendFill::Height(height) = &mut crop.fill {
+                ui.add(egui::Slider::new(height, 0.0..=heightmap.depth).text("Height"));
+            }
+            ui.label(format!(
+                "Makes {}x{} cells.",
+                heightmap.width + crop.margins.horizontal(),
+                heightmap.height + crop.margins.vertical()
+            ));
+            if ui.button("Extend Into New State").clicked() {
+                ui_state
+                    .ui_events
+                    .push(UiEvent::Extend(crop.margins, crop.fill));
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn hardness_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &mut AppState) {
+    egui::CollapsingHeader::new("Rock Hardness")
+        .default_open(false)
+        .show(ui, |ui| {
+            let hardness_type = &mut state.parameters.hardness_type;
+            egui::ComboBox::from_label("Hardness Source")
+                .selected_text(format!("{}", hardness_type))
+                .show_ui(ui, |ui| {
+                    for t in HardnessType::list() {
+                        if ui
+                            .selectable_label(hardness_type.matches(&t), format!("{}", t))
+                            .clicked()
+                            && !hardness_type.matches(&t)
+                        {
+                            *hardness_type = t;
+                        }
+                    }
+                });
+            match hardness_type {
+                HardnessType::Noise(ref mut settings) => {
+                    ui.add(egui::Slider::new(&mut settings.seed, 0..=10000000000).text("Seed"));
+                    ui.add(
+                        egui::Slider::new(&mut settings.fractal_octaves, 0..=28)
+                            .text("Fractal Octaves"),
+                    );
+                    ui.add(egui::Slider::new(&mut settings.frequency, 0.0..=5.0).text("Frequency"));
+                }
+                HardnessType::Strata(ref mut layers, ref mut softness) => {
+                    ui.add(egui::Slider::new(layers, 1.0..=32.0).text("Layers"));
+                    ui.add(egui::Slider::new(softness, 0.0..=1.0).text("Softness"));
+                }
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Generate").clicked() {
+                    ui_state.ui_events.push(UiEvent::GenerateHardness);
+                }
+                if ui.button("Show").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowHardness);
+                }
+                if ui.button("Clear").clicked() {
+                    ui_state.ui_events.push(UiEvent::ClearHardness);
+                }
+            });
+
+            let brush = &mut ui_state.hardness_brush;
+            if ui
+                .add(egui::Checkbox::new(&mut brush.painting, "Paint Hardness"))
+                .changed()
+                && brush.painting
+            {
+                ui_state.drop_zone_brush.painting = false;
+                ui_state.erosion_brush.painting = false;
+                ui_state.sculpt_brush.painting = false;
+            }
+            let brush = &mut ui_state.hardness_brush;
+            if brush.painting {
+                ui.add(egui::Slider::new(&mut brush.radius, 1.0..=64.0).text("Brush Radius"));
+                ui.add(egui::Slider::new(&mut brush.value, 0.0..=1.0).text("Brush Hardness"));
+                ui.add(egui::Slider::new(&mut brush.strength, 0.0..=1.0).text("Brush Strength"));
+            }
+            if state.simulation_state().base().hardness.is_none() {
+                ui.label("No hardness map, all rock erodes uniformly.");
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn sculpt_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &AppState) {
+    egui::CollapsingHeader::new("Sculpt")
+        .default_open(false)
+        .show(ui, |ui| {
+            let brush = &mut ui_state.sculpt_brush;
+            if ui
+                .add(egui::Checkbox::new(&mut brush.painting, "Sculpt Terrain"))
+                .changed()
+                && brush.painting
+            {
+                ui_state.hardness_brush.painting = false;
+                ui_state.drop_zone_brush.painting = false;
+                ui_state.erosion_brush.painting = false;
+            }
+            let brush = &mut ui_state.sculpt_brush;
+            if brush.painting {
+                ui.horizontal(|ui| {
+                    for tool in SculptTool::list() {
+                        ui.selectable_value(&mut brush.tool, tool, tool.to_string());
+                    }
+                });
+                ui.add(egui::Slider::new(&mut brush.radius, 1.0..=64.0).text("Brush Radius"));
+                ui.add(egui::Slider::new(&mut brush.strength, 0.0..=1.0).text("Brush Strength"));
+            }
+            let can_undo = ui_state.sculpt_brush.can_undo(state);
+            if ui
+                .add_enabled(can_undo, egui::Button::new("Undo Stroke"))
+                .clicked()
+            {
+                ui_state.ui_events.push(UiEvent::UndoSculpt);
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn erosion_brush_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &AppState) {
+    egui::CollapsingHeader::new("Erosion Brush")
+        .default_open(false)
+        .show(ui, |ui| {
+            let brush = &mut ui_state.erosion_brush;
+            if ui
+                .add(egui::Checkbox::new(
+                    &mut brush.painting,
+                    "Erode Under Cursor",
+                ))
+                .changed()
+                && brush.painting
+            {
+                ui_state.hardness_brush.painting = false;
+                ui_state.drop_zone_brush.painting = false;
+                ui_state.sculpt_brush.painting = false;
+            }
+            let brush = &mut ui_state.erosion_brush;
+            if brush.painting {
+                ui.add(egui::Slider::new(&mut brush.radius, 1.0..=64.0).text("Brush Radius"));
+                ui.add(
+                    egui::Slider::new(&mut brush.droplets, 1..=2000).text("Iterations per Frame"),
+                );
+                ui.label(format!(
+                    "Hold the left mouse button to erode with {}.",
+                    state.parameters.backend
+                ));
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn vegetation_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &mut AppState) {
+    egui::CollapsingHeader::new("Vegetation")
+        .default_open(false)
+        .show(ui, |ui| {
+            let params = &mut state.parameters.vegetation_params;
+            ui.add(egui::Slider::new(&mut params.max_slope, 0.0..=0.05).text("Max Slope"));
+            ui.add(egui::Slider::new(&mut params.tree_line, 0.0..=1.0).text("Tree Line"));
+            ui.add(egui::Slider::new(&mut params.moisture, 0.0..=1.0).text("Moisture"))
+                .on_hover_text("How much growth follows the precipitation map");
+            ui.add(egui::Slider::new(&mut params.protection, 0.0..=1.0).text("Protection"))
+                .on_hover_text("Erosion prevented under full cover");
+            ui.horizontal(|ui| {
+                if ui.button("Generate").clicked() {
+                    ui_state.ui_events.push(UiEvent::GenerateVegetation);
+                }
+                if ui.button("Show").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowVegetation);
+                }
+                if ui.button("Clear").clicked() {
+                    ui_state.ui_events.push(UiEvent::ClearVegetation);
+                }
+            });
+            if state.simulation_state().base().vegetation.is_none() {
+                ui.label("No vegetation, the terrain is bare.");
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn drop_zone_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &mut AppState) {
+    egui::CollapsingHeader::new("Drop Zone")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Show").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowDropZone);
+                }
+                if ui.button("Clear").clicked() {
+                    ui_state.ui_events.push(UiEvent::ClearDropZone);
+                }
+            });
+
+            let brush = &mut ui_state.drop_zone_brush;
+            if ui
+                .add(egui::Checkbox::new(&mut brush.painting, "Paint Drop Zone"))
+                .changed()
+                && brush.painting
+            {
+                ui_state.hardness_brush.painting = false;
+                ui_state.erosion_brush.painting = false;
+                ui_state.sculpt_brush.painting = false;
+            }
+            let brush = &mut ui_state.drop_zone_brush;
+            if brush.painting {
+                ui.add(egui::Slider::new(&mut brush.radius, 1.0..=64.0).text("Brush Radius"));
+                ui.add(egui::Slider::new(&mut brush.value, 0.0..=1.0).text("Brush Spawn Chance"));
+                ui.add(egui::Slider::new(&mut brush.strength, 0.0..=1.0).text("Brush Strength"));
+            }
+            if state
+                .simulation_state()
+                .base()
+                .drop_zone
+                .get_mask()
+                .is_none()
+            {
+                ui.label("No mask, drops spawn everywhere.");
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn precipitation_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &mut AppState) {
+    egui::CollapsingHeader::new("Precipitation")
+        .default_open(false)
+        .show(ui, |ui| {
+            let precipitation_type = &mut state.parameters.precipitation_type;
+            egui::ComboBox::from_label("Precipitation Source")
+                .selected_text(format!("{}", precipitation_type))
+                .show_ui(ui, |ui| {
+                    for t in PrecipitationType::list() {
+                        if ui
+                            .selectable_label(precipitation_type.matches(&t), format!("{}", t))
+                            .clicked()
+                            && !precipitation_type.matches(&t)
+                        {
+                            *precipitation_type = t;
+                        }
+                    }
+                });
+            match precipitation_type {
+                PrecipitationType::Noise(ref mut settings) => {
+                    ui.add(egui::Slider::new(&mut settings.seed, 0..=10000000000).text("Seed"));
+                    ui.add(
+                        egui::Slider::new(&mut settings.fractal_octaves, 0..=28)
+                            .text("Fractal Octaves"),
+                    );
+                    ui.add(egui::Slider::new(&mut settings.frequency, 0.0..=5.0).text("Frequency"));
+                }
+                PrecipitationType::Altitude(ref mut contrast) => {
+                    ui.add(egui::Slider::new(contrast, 0.0..=4.0).text("Contrast"));
+                }
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Generate").clicked() {
+                    ui_state.ui_events.push(UiEvent::GeneratePrecipitation);
+                }
+                if ui.button("Show").clicked() {
+                    ui_state.ui_events.push(UiEvent::ShowPrecipitation);
+                }
+                if ui.button("Clear").clicked() {
+                    ui_state.ui_events.push(UiEvent::ClearPrecipitation);
+                }
+            });
+            if state
+                .simulation_state()
+                .base()
+                .drop_zone
+                .get_precipitation()
+                .is_none()
+            {
+                ui.label("No precipitation map, rain falls evenly.");
+            } else {
+                ui.label(format!(
+                    "Droplets spawn where it rains more, used by {}.",
+                    Backend::Lague
+                ));
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn palette_settings(ui: &mut egui::Ui, ui_state: &mut UiState) {
+    egui::CollapsingHeader::new("Palette")
+        .default_open(false)
+        .show(ui, |ui| {
+            let selected = ui_state.palette;
+            egui::ComboBox::from_label("Data Layer Palette")
+                .selected_text(selected.to_string())
+                .show_ui(ui, |ui| {
+                    for palette in Palette::list() {
+                        if ui
+                            .selectable_label(selected == palette, palette.to_string())
+                            .clicked()
+                            && selected != palette
+                        {
+                            ui_state.ui_events.push(UiEvent::SelectPalette(palette));
+                        }
+                    }
+                });
+            ui.label("Applies to differences, heatmaps and material layers when shown.");
+
+            let colormap = &mut ui_state.colormap;
+            egui::ComboBox::from_label("Heightmap Colormap")
+                .selected_text(colormap.name())
+                .show_ui(ui, |ui| {
+                    for palette in Palette::list() {
+                        let selected = !colormap.custom && colormap.palette == palette;
+                        if ui.selectable_label(selected, palette.to_string()).clicked() {
+                            colormap.palette = palette;
+                            colormap.custom = false;
+                        }
+                    }
+                    if ui
+                        .selectable_label(colormap.custom, "Custom Gradient")
+                        .clicked()
+                    {
+                        colormap.custom = true;
+                    }
+                });
+            ui.label("Colours heights from zero to the depth of the heightmap.");
+            if colormap.custom {
+                gradient_editor(ui, &mut colormap.gradient.stops);
+                if ui
+                    .add_enabled(
+                        colormap.gradient.stops.len() < MAX_GRADIENT_STOPS,
+                        egui::Button::new("Add Stop"),
+                    )
+                    .clicked()
+                {
+                    colormap.gradient.split_widest();
+                }
+            }
+
+            ui.add(egui::Checkbox::new(
+                &mut ui_state.show_legend,
+                "Show Legend",
+            ));
+        });
+
+    ui.separator();
+}
+
+fn gradient_editor(ui: &mut egui::Ui, stops: &mut Vec<GradientStop>) {
+    let mut remove = None;
+    let removable = stops.len() > 2;
+    for (i, stop) in stops.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            egui::color_picker::color_edit_button_srgb(ui, &mut stop.color);
+            ui.add(egui::Slider::new(&mut stop.position, 0.0..=1.0).text("Position"));
+            if ui
+                .add_enabled(removable, egui::Button::new("Remove"))
+                .clicked()
+            {
+                remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = remove {
+        stops.remove(i);
+    }
+}
+
+pub fn vector_overlay_settings(ui: &mut egui::Ui, ui_state: &mut UiState) {
+    egui::CollapsingHeader::new("Vector Overlay")
+        .default_open(false)
+        .show(ui, |ui| {
+            let overlay = &mut ui_state.vector_overlay;
+            ui.horizontal(|ui| {
+                ui.label("GeoJSON File");
+                ui.text_edit_singleline(&mut overlay.path);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Load").clicked() {
+                    ui_state.ui_events.push(UiEvent::LoadVectorOverlay);
+                }
+                if ui.button("Clear").clicked() {
+                    ui_state.ui_events.push(UiEvent::ClearVectorOverlay);
+                }
+            });
+            let overlay = &mut ui_state.vector_overlay;
+            if overlay.lines.is_empty() {
+                ui.label("No features loaded.");
+                return;
+            }
+            ui.label(format!("{} lines", overlay.lines.len()));
+            ui.add(egui::Checkbox::new(&mut overlay.visible, "Show Overlay"));
+            ui.add(egui::Slider::new(&mut overlay.thickness, 0.5..=8.0).text("Line Thickness"));
+            ui.label("Heightmap Bounds (min x, min y, max x, max y)");
+            ui.horizontal(|ui| {
+                for bound in overlay.bounds.iter_mut() {
+                    ui.add(egui::DragValue::new(bound).speed(0.001));
+                }
+            });
+            if ui.button("Fit to Features").clicked() {
+                overlay.bounds = overlay.data_bounds();
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn view_settings(ui: &mut egui::Ui, ui_state: &mut UiState, state: &AppState) {
+    egui::CollapsingHeader::new("View")
+        .default_open(false)
+        .show(ui, |ui| {
+            ui.label("Scroll to zoom, drag with the right mouse button to pan.");
+            let view = &mut ui_state.view;
+            if ui
+                .add(egui::Slider::new(&mut view.zoom, 1.0..=MAX_ZOOM).text("Zoom"))
+                .changed()
+            {
+                *view = view.clamped();
+            }
+            let size = state
+                .simulation_state()
+                .base()
+                .heightmap_base
+                .heightmap
+                .width;
+            ui.horizontal(|ui| {
+                let (x, y) = &mut ui_state.navigation.goto;
+                ui.label("x");
+                ui.add(egui::DragValue::new(x).clamp_range(0..=size - 1));
+                ui.label("y");
+                ui.add(egui::DragValue::new(y).clamp_range(0..=size - 1));
+                if ui.button("Go to").clicked() {
+                    ui_state.ui_events.push(UiEvent::GotoCoordinate);
+                }
+            });
+            if ui.button("Reset View").clicked() {
+                ui_state.ui_events.push(UiEvent::ResetView);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut ui_state.navigation.bookmark_name);
+                if ui.button("Add Bookmark").clicked() {
+                    ui_state.ui_events.push(UiEvent::AddBookmark);
+                }
+            });
+            for (i, bookmark) in state.bookmarks.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let (x, y) = bookmark.view.center;
+                    if ui
+                        .button(&bookmark.name)
+                        .on_hover_text(format!(
+                            "({}, {}) at {:.1}x",
+                            (x * size as f32) as usize,
+                            (y * size as f32) as usize,
+                            bookmark.view.zoom
+                        ))
+                        .clicked()
+                    {
+                        ui_state.ui_events.push(UiEvent::GotoBookmark(i));
+                    }
+                    if ui.button("Remove").clicked() {
+                        ui_state.ui_events.push(UiEvent::RemoveBookmark(i));
+                    }
+                });
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn material_layer_settings(ui: &mut egui::Ui, state: &mut AppState) {
+    egui::CollapsingHeader::new("Material Layers")
+        .default_open(false)
+        .show(ui, |ui| {
+            let params = &mut state.parameters.layer_params;
+            ui.add(egui::Checkbox::new(
+                &mut params.enabled,
+                "Bedrock, Regolith & Sediment",
+            ));
+            if params.enabled {
+                ui.add(
+                    egui::Slider::new(&mut params.regolith_depth, 0.0..=0.2).text("Regolith Depth"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut params.bedrock_erodibility, 0.0..=1.0)
+                        .text("Bedrock Erodibility"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut params.regolith_erodibility, 0.0..=1.0)
+                        .text("Regolith Erodibility"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut params.sediment_erodibility, 0.0..=1.0)
+                        .text("Sediment Erodibility"),
+                );
+                if ui.button("Reset").clicked() {
+                    *params = LayerParameters {
+                        enabled: true,
+                        ..Default::default()
+                    };
+                }
+            }
+        });
+
+    ui.separator();
+}
+
+pub fn layer_selection(ui: &mut egui::Ui, state: &AppState) {
+    egui::CollapsingHeader::new("Layers")
+        .default_open(true)
+        .show(ui, |ui| {
+            let selected_diff: Option<usize> =
+                if let Some(eroded) = state.simulation_state().eroded() {
+                    Some((*eroded.selected_diff.borrow()).clone())
+                } else {
+                    None
+                };
+            // Image Layers
+            ui.heading("Image Layers");
+            for simulation in state.simulation_states.iter() {
+                ui.horizontal(|ui| {
+                    if *state.simulation_base_indices.last().unwrap() == simulation.id() {
+                        ui.label("-> ");
+                    }
+                    match simulation {
+                        SimulationState::Base(base) => {
+                            ui.label(format!("{}: [Base Layer]", base.id));
+                        }
+                        SimulationState::Eroded((_, eroded)) => {
+                            ui.label(format!(
+                                "{}: {} {} eroded from #{}",
+                                eroded.id,
+                                eroded.erosion_method.to_string(),
+                                eroded.erosion_model.backend(),
+                                eroded.base_id
+                            ));
+                        }
+                    }
+                    if let Some(selected_diff) = selected_diff {
+                        if simulation.id() == selected_diff {
+                            ui.label(" <-- diff");
+                        }
+                    }
+                });
+            }
+        });
+
+    ui.separator();
+}
+
+fn heightmap_parameters(
+    params: &mut HeightmapParameters,
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    state: &mut AppState,
+) {
+    let resolutions = 2usize.pow(6)..=2usize.pow(12);
+    let mut square = params.height.is_none();
+    let mut size = params.size;
+    let mut updated = ui
+        .add(
+            egui::Slider::new(&mut size, resolutions.clone()).text(if square {
+                "Resolution"
+            } else {
+                "Width"
+            }),
+        )
+        .changed();
+    params.size = size;
+    if ui.checkbox(&mut square, "Square").changed() {
+        params.height = if square { None } else { Some(params.size) };
+        updated = true;
+    }
+    if let Some(height) = params.height.as_mut() {
+        updated |= ui
+            .add(egui::Slider::new(height, resolutions).text("Height"))
+            .changed();
+    }
+
+    ui.add(egui::Checkbox::new(
+        &mut state.parameters.auto_apply,
+        "Auto Apply",
+    ));
+
+    if ui.button("Reset").clicked() {
+        params.reset();
+        updated = true;
+    }
+
+    let mut apply = false;
+    if !state.parameters.auto_apply {
+        apply = ui.button("Apply").clicked();
+    }
+
+    let update = (state.parameters.auto_apply && updated) || apply;
+    if update {
+        ui_state.ui_events.push(UiEvent::ReplaceHeightmap);
+    }
+}
+
+fn procedural_generation_settings(
+    settings: &mut ProceduralHeightmapSettings,
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    state: &mut AppState,
+) {
+    let mut updated = false;
+
+    updated = updated
+        || ui
+            .add(egui::Slider::new(&mut settings.seed, 0..=10000000000).text("Seed"))
+            .changed();
+
+    let noise_type = settings.noise_type;
     egui::ComboBox::from_label("Noise Type")
         .selected_text(format!("{:?}", settings.noise_type))
         .show_ui(ui, |ui| {
@@ -636,6 +1956,35 @@ fn procedural_generation_settings(
         ui_state.ui_events.push(UiEvent::ReplaceHeightmap);
     }
 }
+fn heightmap_file_picker(
+    heightmap_type: &mut HeightmapType,
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+) {
+    let mut load = false;
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut ui_state.heightmap_file);
+        load = ui.button("Load Image").clicked();
+    });
+    if let Ok(images) = list_images(".") {
+        ui.menu_button("Images", |ui| {
+            for image in images {
+                if ui.button(&image).clicked() {
+                    ui_state.heightmap_file = image;
+                    load = true;
+                    ui.close_menu();
+                }
+            }
+        });
+    }
+    if load && !ui_state.heightmap_file.is_empty() {
+        *heightmap_type = HeightmapType::FromFile(
+            *heightmap_type.params(),
+            PathBuf::from(&ui_state.heightmap_file),
+        );
+    }
+}
+
 pub fn heightmap_generation_settings(
     ui: &mut egui::Ui,
     ui_state: &mut UiState,
@@ -647,14 +1996,16 @@ pub fn heightmap_generation_settings(
             if state.simulation_state().eroded().is_none()
                 && state.simulation_state().id() == state.simulation_base_indices.len() - 1
             {
-                let mut heightmap_type = state.parameters.heightmap_type;
+                let mut heightmap_type = state.parameters.heightmap_type.clone();
                 egui::ComboBox::from_label("Heightmap Type")
                     .selected_text(format!("{}", heightmap_type))
                     .show_ui(ui, |ui| {
-                        for ref mut t in HeightmapType::iterator() {
-                            ui.selectable_value(&mut heightmap_type, *t, format!("{}", t));
+                        for t in HeightmapType::iterator() {
+                            let label = format!("{}", t);
+                            ui.selectable_value(&mut heightmap_type, t, label);
                         }
                     });
+                heightmap_file_picker(&mut heightmap_type, ui, ui_state);
 
                 let type_changed = heightmap_type != state.parameters.heightmap_type;
 
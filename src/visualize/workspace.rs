@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+#[cfg(feature = "gui")]
+use egui::Pos2;
+use serde::{Deserialize, Serialize};
+
+use crate::visualize::events::{UiEvent, UiWindow};
+use crate::visualize::palette::Palette;
+use crate::visualize::ui::UiState;
+
+/*
+Named ui layouts for switching between ways of working, such as comparing partitioning methods
+with the metrics and timeline open or authoring terrain with only the control panel and a
+hillshade. A layout holds which windows are open and where, the presentation mode, grid, legend
+and data layer palette and the kind of layer that was shown, which is shown again when switching
+to the workspace. Workspaces belong to the user rather than to a terrain, so they are kept in
+`WORKSPACES_FILE` instead of the saved states and survive loading a state.
+ */
+
+pub const WORKSPACES_FILE: &str = "workspaces.json";
+
+const WINDOWS: [UiWindow; 9] = [
+    UiWindow::Keybinds,
+    UiWindow::ControlPanel,
+    UiWindow::Metadata,
+    UiWindow::Metrics,
+    UiWindow::Snapshots,
+    UiWindow::Timeline,
+    UiWindow::Session,
+    UiWindow::Hud,
+    UiWindow::Region,
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub open: Vec<UiWindow>,
+    /// Top left corner of the windows last seen on screen.
+    pub positions: Vec<(UiWindow, [f32; 2])>,
+    pub presentation_mode: bool,
+    pub show_grid: bool,
+    pub show_legend: bool,
+    pub palette: Palette,
+    /// Event that showed the layer on screen, sent again when the layout is applied.
+    pub visualization: Option<UiEvent>,
+}
+
+impl Layout {
+    fn new(open: &[UiWindow], visualization: Option<UiEvent>) -> Self {
+        Layout {
+            open: open.to_vec(),
+            positions: Vec::new(),
+            presentation_mode: false,
+            show_grid: false,
+            show_legend: false,
+            palette: Palette::default(),
+            visualization,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Workspace {
+    pub name: String,
+    pub layout: Layout,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Workspaces {
+    pub list: Vec<Workspace>,
+    pub active: Option<usize>,
+    /// Name typed for the next workspace saved.
+    pub name: String,
+    /// Where the windows were drawn last frame.
+    positions: HashMap<UiWindow, [f32; 2]>,
+    /// Where windows go the next time they are drawn.
+    pending: HashMap<UiWindow, [f32; 2]>,
+    /// Event of the layer last shown.
+    pub visualization: Option<UiEvent>,
+}
+
+impl Workspaces {
+    /// The workspaces in `WORKSPACES_FILE`, or a starting set when there is none yet.
+    pub fn load() -> Self {
+        let list = match fs::read_to_string(WORKSPACES_FILE) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                eprintln!("Failed to read {}: {:?}", WORKSPACES_FILE, err);
+                Vec::new()
+            }),
+            Err(_) => Workspaces::defaults(),
+        };
+        Workspaces {
+            list,
+            ..Workspaces::default()
+        }
+    }
+
+    pub fn defaults() -> Vec<Workspace> {
+        vec![
+            Workspace {
+                name: "Method Comparison".to_string(),
+                layout: Layout::new(
+                    &[
+                        UiWindow::ControlPanel,
+                        UiWindow::Metadata,
+                        UiWindow::Metrics,
+                        UiWindow::Timeline,
+                    ],
+                    Some(UiEvent::ShowErodedLayer),
+                ),
+            },
+            Workspace {
+                name: "Terrain Authoring".to_string(),
+                layout: Layout::new(&[UiWindow::ControlPanel], Some(UiEvent::ShowHillshade)),
+            },
+        ]
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.list).map_err(io::Error::other)?;
+        fs::write(WORKSPACES_FILE, json)
+    }
+
+    /// Moves a window to where the workspace being applied has it.
+    #[cfg(feature = "gui")]
+    pub fn place<'a>(
+        &mut self,
+        window: UiWindow,
+        egui_window: egui::Window<'a>,
+    ) -> egui::Window<'a> {
+        match self.pending.remove(&window) {
+            Some([x, y]) => egui_window.current_pos(Pos2::new(x, y)),
+            None => egui_window,
+        }
+    }
+
+    /// Remembers where a window was drawn, `None` when it is closed.
+    #[cfg(feature = "gui")]
+    pub fn track<R>(&mut self, window: UiWindow, response: &Option<egui::InnerResponse<R>>) {
+        if let Some(response) = response {
+            let min = response.response.rect.min;
+            self.positions.insert(window, [min.x, min.y]);
+        }
+    }
+}
+
+impl UiState {
+    pub fn window_shown(&self, window: UiWindow) -> bool {
+        match window {
+            UiWindow::All => self.show_ui_all,
+            UiWindow::Keybinds => self.show_ui_keybinds,
+            UiWindow::ControlPanel => self.show_ui_control_panel,
+            UiWindow::Metadata => self.show_ui_metadata,
+            UiWindow::Metrics => self.show_ui_metrics,
+            UiWindow::Snapshots => self.show_ui_snapshots,
+            UiWindow::Timeline => self.show_ui_timeline,
+            UiWindow::Session => self.show_ui_session,
+            UiWindow::Hud => self.show_ui_hud,
+            UiWindow::Region => self.show_ui_region,
+        }
+    }
+
+    pub fn window_shown_mut(&mut self, window: UiWindow) -> &mut bool {
+        match window {
+            UiWindow::All => &mut self.show_ui_all,
+            UiWindow::Keybinds => &mut self.show_ui_keybinds,
+            UiWindow::ControlPanel => &mut self.show_ui_control_panel,
+            UiWindow::Metadata => &mut self.show_ui_metadata,
+            UiWindow::Metrics => &mut self.show_ui_metrics,
+            UiWindow::Snapshots => &mut self.show_ui_snapshots,
+            UiWindow::Timeline => &mut self.show_ui_timeline,
+            UiWindow::Session => &mut self.show_ui_session,
+            UiWindow::Hud => &mut self.show_ui_hud,
+            UiWindow::Region => &mut self.show_ui_region,
+        }
+    }
+
+    /// The layout currently on screen.
+    pub fn layout(&self) -> Layout {
+        let positions = WINDOWS
+            .iter()
+            .filter_map(|window| Some((*window, *self.workspaces.positions.get(window)?)))
+            .collect();
+        Layout {
+            open: WINDOWS
+                .into_iter()
+                .filter(|window| self.window_shown(*window))
+                .collect(),
+            positions,
+            presentation_mode: self.show_ui_presentation_mode,
+            show_grid: self.show_grid,
+            show_legend: self.show_legend,
+            palette: self.palette,
+            visualization: self.workspaces.visualization,
+        }
+    }
+
+    /// Opens and moves the windows of the layout, returns the event showing its layer.
+    pub fn apply_layout(&mut self, layout: &Layout) -> Option<UiEvent> {
+        for window in WINDOWS {
+            *self.window_shown_mut(window) = layout.open.contains(&window);
+        }
+        self.workspaces.pending = layout.positions.iter().copied().collect();
+        self.show_ui_all = true;
+        self.show_ui_presentation_mode = layout.presentation_mode;
+        self.show_grid = layout.show_grid;
+        self.show_legend = layout.show_legend;
+        self.palette = layout.palette;
+        layout.visualization
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Check;
+
+    #[test]
+    fn workspaces() -> Check {
+        use crate::visualize::events::UiWindow;
+        let mut list = Workspaces::defaults();
+        list[0]
+            .layout
+            .positions
+            .push((UiWindow::Metrics, [12.0, 480.5]));
+        let json = serde_json::to_string(&list).map_err(|err| format!("{:?}", err))?;
+        let read: Vec<Workspace> =
+            serde_json::from_str(&json).map_err(|err| format!("{:?}", err))?;
+        if read != list {
+            return Err("workspaces changed when read back".to_string());
+        }
+        match list.iter().find_map(|w| w.layout.visualization) {
+            Some(event) if event.is_visualization() => Ok(()),
+            event => Err(format!("{:?} does not show a layer", event)),
+        }
+    }
+}
@@ -1,126 +1,107 @@
+use crate::heightmap::contours::{self, Contour};
 use crate::heightmap::io::save_heightmap_as_image;
-use crate::heightmap::Heightmap;
-use crate::visualize::{heightmap_to_image_rgb, heightmap_to_texture};
-use bracket_noise::prelude::{FractalType, NoiseType};
-use macroquad::texture::{Image, Texture2D};
+use crate::heightmap::{Heightmap, HeightmapPrecision};
+use crate::visualize::palette::{Legend, Palette};
+use crate::visualize::{heightmap_to_image_rgb, Composite, LayerImage};
 use serde::{Deserialize, Serialize};
 use std::rc::Rc;
 
-#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
-pub enum NoiseTypeWrapper {
-    Value,
-    ValueFractal,
-    Perlin,
-    PerlinFractal,
-    Simplex,
-    SimplexFractal,
-    Cellular,
-    WhiteNoise,
-    Cubic,
-    CubicFractal,
+/// A heightmap with the image it is shown as, uploaded by `TextureCache` when it is drawn.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeightmapTexture {
+    #[serde(skip)]
+    pub image: Option<Rc<LayerImage>>,
+    pub heightmap: Rc<Heightmap>,
+    #[serde(skip)]
+    pub legend: Option<Legend>,
+    #[serde(skip)]
+    pub composite: Option<Rc<Composite>>,
+    /// Vector lines drawn on top of the texture, in [0, 1] heightmap space.
+    #[serde(skip)]
+    pub contours: Option<Rc<Vec<Contour>>>,
+    /// Every n-th contour line, drawn thicker like the index contours of a topographic map.
+    #[serde(skip)]
+    pub index_contours: Option<Rc<Vec<Contour>>>,
 }
 
-impl From<NoiseType> for NoiseTypeWrapper {
-    fn from(item: NoiseType) -> Self {
-        match item {
-            NoiseType::Value => NoiseTypeWrapper::Value,
-            NoiseType::ValueFractal => NoiseTypeWrapper::ValueFractal,
-            NoiseType::Perlin => NoiseTypeWrapper::Perlin,
-            NoiseType::PerlinFractal => NoiseTypeWrapper::PerlinFractal,
-            NoiseType::Simplex => NoiseTypeWrapper::Simplex,
-            NoiseType::SimplexFractal => NoiseTypeWrapper::SimplexFractal,
-            NoiseType::Cellular => NoiseTypeWrapper::Cellular,
-            NoiseType::WhiteNoise => NoiseTypeWrapper::WhiteNoise,
-            NoiseType::Cubic => NoiseTypeWrapper::Cubic,
-            NoiseType::CubicFractal => NoiseTypeWrapper::CubicFractal,
-        }
-    }
+/// Moves contours from cell coordinates to [0, 1] heightmap space through the cell centres.
+fn to_unit_space(heightmap: &Heightmap, contours: Vec<Contour>) -> Vec<Contour> {
+    let width = heightmap.width as f32;
+    let height = heightmap.height as f32;
+    contours
+        .into_iter()
+        .map(|contour| {
+            contour
+                .into_iter()
+                .map(|(x, y)| ((x + 0.5) / width, (y + 0.5) / height))
+                .collect()
+        })
+        .collect()
 }
 
-impl From<NoiseTypeWrapper> for NoiseType {
-    fn from(item: NoiseTypeWrapper) -> Self {
-        match item {
-            NoiseTypeWrapper::Value => NoiseType::Value,
-            NoiseTypeWrapper::ValueFractal => NoiseType::ValueFractal,
-            NoiseTypeWrapper::Perlin => NoiseType::Perlin,
-            NoiseTypeWrapper::PerlinFractal => NoiseType::PerlinFractal,
-            NoiseTypeWrapper::Simplex => NoiseType::Simplex,
-            NoiseTypeWrapper::SimplexFractal => NoiseType::SimplexFractal,
-            NoiseTypeWrapper::Cellular => NoiseType::Cellular,
-            NoiseTypeWrapper::WhiteNoise => NoiseType::WhiteNoise,
-            NoiseTypeWrapper::Cubic => NoiseType::Cubic,
-            NoiseTypeWrapper::CubicFractal => NoiseType::CubicFractal,
+impl HeightmapTexture {
+    pub fn new(heightmap: Rc<Heightmap>, image: Option<Rc<LayerImage>>) -> Self {
+        Self {
+            image,
+            heightmap,
+            legend: None,
+            composite: None,
+            contours: None,
+            index_contours: None,
         }
     }
-}
 
-#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
-pub enum FractalTypeWrapper {
-    FBM,
-    Billow,
-    RigidMulti,
-}
-
-impl From<FractalType> for FractalTypeWrapper {
-    fn from(value: FractalType) -> Self {
-        match value {
-            FractalType::FBM => FractalTypeWrapper::FBM,
-            FractalType::Billow => FractalTypeWrapper::Billow,
-            FractalType::RigidMulti => FractalTypeWrapper::RigidMulti,
-        }
+    pub fn with_legend(mut self, legend: Legend) -> Self {
+        self.legend = Some(legend);
+        self
     }
-}
 
-impl From<FractalTypeWrapper> for FractalType {
-    fn from(value: FractalTypeWrapper) -> Self {
-        match value {
-            FractalTypeWrapper::FBM => FractalType::FBM,
-            FractalTypeWrapper::Billow => FractalType::Billow,
-            FractalTypeWrapper::RigidMulti => FractalType::RigidMulti,
-        }
+    pub fn with_composite(mut self, composite: Composite) -> Self {
+        self.composite = Some(Rc::new(composite));
+        self
     }
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct HeightmapTexture {
-    #[serde(skip)]
-    pub image: Option<Rc<Image>>,
-    #[serde(skip)]
-    pub texture: Option<Rc<Texture2D>>,
-    pub heightmap: Rc<Heightmap>,
-}
 
-impl HeightmapTexture {
-    pub fn new(heightmap: Rc<Heightmap>, image: Option<Rc<Image>>) -> Self {
-        let texture = image
-            .as_ref()
-            .and_then(|img| Some(Rc::new(Texture2D::from_image(&img))));
-        Self {
-            image,
+    /// Traces the contour of `heightmap` at `level` so it can be drawn as resolution independent lines.
+    pub fn with_contours(mut self, heightmap: &Heightmap, level: HeightmapPrecision) -> Self {
+        self.contours = Some(Rc::new(to_unit_space(
             heightmap,
-            texture,
-        }
+            contours::trace(heightmap, level),
+        )));
+        self
     }
 
-    pub fn get_or_generate(&self) -> Rc<Texture2D> {
-        if let Some(texture) = &self.texture {
-            Rc::clone(texture)
-        } else {
-            Rc::new(heightmap_to_texture(&self.heightmap))
+    /// Traces contour lines every `interval` of the depth, with every `index_every`-th one kept
+    /// apart as an index contour, none when it is 0.
+    pub fn with_contour_lines(
+        mut self,
+        heightmap: &Heightmap,
+        interval: HeightmapPrecision,
+        index_every: usize,
+    ) -> Self {
+        let mut lines = Vec::new();
+        let mut index_lines = Vec::new();
+        for level in contours::trace_levels(heightmap, interval, index_every) {
+            if level.index {
+                index_lines.extend(level.contours);
+            } else {
+                lines.extend(level.contours);
+            }
         }
+        self.contours = Some(Rc::new(to_unit_space(heightmap, lines)));
+        self.index_contours = Some(Rc::new(to_unit_space(heightmap, index_lines)));
+        self
     }
 
-    pub fn get_and_generate_cache(&mut self) -> Rc<Texture2D> {
-        let texture = self.get_or_generate();
-        self.texture = Some(Rc::clone(&texture));
-        texture
+    /// Colours the heightmap with `palette`, keeping the legend that describes the mapping.
+    pub fn from_palette(heightmap: Rc<Heightmap>, palette: Palette) -> Self {
+        let (image, legend) = palette.image(&heightmap);
+        Self::new(heightmap, Some(Rc::new(image))).with_legend(legend)
     }
 
     #[cfg(feature = "export")]
     pub fn export_image(&self, filename: &str) -> Option<()> {
         if let Some(ref image) = self.image {
-            image.export_png(&format!("{}.png", filename));
-            Some(())
+            image.save(format!("{}.png", filename)).ok()
         } else {
             None
         }
@@ -145,11 +126,13 @@ impl HeightmapTexture {
 impl From<&Rc<Heightmap>> for HeightmapTexture {
     fn from(value: &Rc<Heightmap>) -> Self {
         let image = heightmap_to_image_rgb(value);
-        let texture = Texture2D::from_image(&image);
         Self {
             image: Some(Rc::new(image)),
-            texture: Some(Rc::new(texture)),
             heightmap: Rc::clone(value),
+            legend: Some(Legend::heights(value)),
+            composite: None,
+            contours: None,
+            index_contours: None,
         }
     }
 }
@@ -157,21 +140,55 @@ impl From<&Rc<Heightmap>> for HeightmapTexture {
 impl From<Heightmap> for HeightmapTexture {
     fn from(value: Heightmap) -> Self {
         let image = heightmap_to_image_rgb(&value);
-        let texture = Texture2D::from_image(&image);
         Self {
             image: Some(Rc::new(image)),
-            texture: Some(Rc::new(texture)),
+            legend: Some(Legend::heights(&value)),
+            composite: None,
+            contours: None,
+            index_contours: None,
             heightmap: Rc::new(value),
         }
     }
 }
 
-impl From<HeightmapTexture> for Rc<Texture2D> {
-    fn from(value: HeightmapTexture) -> Self {
-        if let Some(texture) = value.texture {
-            texture
-        } else {
-            value.get_or_generate()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Check, SIZE};
+    use crate::heightmap;
+
+    #[test]
+    fn contour_lines() -> Check {
+        let mut ramp = Heightmap::new_empty(SIZE, SIZE, 1.0, 1.0);
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                ramp.data[x][y] = x as f32 / (SIZE - 1) as f32;
+            }
+        }
+        let levels = heightmap::contours::levels(ramp.depth, 0.1);
+        if levels.len() != 9 {
+            return Err(format!("expected 9 levels, got {:?}", levels));
+        }
+        let texture =
+            HeightmapTexture::new(Rc::new(ramp.clone()), None).with_contour_lines(&ramp, 0.1, 5);
+        let count = |contours: &Option<Rc<Vec<heightmap::contours::Contour>>>| {
+            contours
+                .as_ref()
+                .map(|contours| contours.len())
+                .unwrap_or(0)
+        };
+        let (lines, index_lines) = (count(&texture.contours), count(&texture.index_contours));
+        if lines != 8 || index_lines != 1 {
+            return Err(format!(
+                "expected 8 lines and 1 index line, got {} and {}",
+                lines, index_lines
+            ));
+        }
+        // The index line is the fifth level, halfway up the ramp
+        let x = texture.index_contours.as_ref().unwrap()[0][0].0;
+        if (x - 0.5).abs() > 0.5 / SIZE as f32 {
+            return Err(format!("index line at x = {}", x));
         }
+        Ok(())
     }
 }